@@ -5,15 +5,41 @@ fn main() {
     // Tell cargo to rerun this script if libxsmm changes
     println!("cargo:rerun-if-changed=build.rs");
     
+    // `cfg!(target_*)` would be the host's; cargo passes the target's
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+
+    // libxsmm is only used on x86_64; elsewhere `use-libxsmm` scores on the native kernel
+    let libxsmm = cfg!(feature = "use-libxsmm") && target_arch == "x86_64";
+    let native = cfg!(feature = "backend-native") || (cfg!(feature = "use-libxsmm") && !libxsmm);
+    println!("cargo:rustc-check-cfg=cfg(libxsmm)");
+    println!("cargo:rustc-check-cfg=cfg(native_gemm)");
+    if libxsmm {
+        println!("cargo:rustc-cfg=libxsmm");
+    }
+    if native {
+        println!("cargo:rustc-cfg=native_gemm");
+    }
+
+    // The system BLAS is linked for `sgemm` unless the native kernel replaces it, and for `cblas_sgemm`
+    let system_blas = !native || cfg!(feature = "backend-cblas");
+    println!("cargo:rustc-check-cfg=cfg(system_blas)");
+    if system_blas {
+        println!("cargo:rustc-cfg=system_blas");
+    }
+    // Which BLAS, e.g. mkl_rt to compare against MKL
+    println!("cargo:rerun-if-env-changed=MAXSIM_BLAS_LIB");
+    let blas_lib = env::var("MAXSIM_BLAS_LIB").unwrap_or_else(|_| "openblas".to_string());
+    let link_libxsmm = libxsmm && !cfg!(feature = "libxsmm-dlopen");
+
     // Platform-specific linking
-    #[cfg(all(target_os = "linux", not(feature = "use-libxsmm")))]
-    {
-        // On Linux without libxsmm, we need to explicitly link system OpenBLAS
-        println!("cargo:rustc-link-lib=openblas");
+    if target_os == "linux" && system_blas && !link_libxsmm {
+        // On Linux without libxsmm, we need to explicitly link the system BLAS
+        println!("cargo:rustc-link-lib={}", blas_lib);
     }
     
-    // Only link libxsmm if the feature is enabled
-    if cfg!(feature = "use-libxsmm") {
+    // Only link libxsmm if it is used, and not when it is loaded at runtime
+    if link_libxsmm {
         // Look for LIBXSMM_DIR or LIBXSMM_LIB_DIR
         let libxsmm_dir = env::var("LIBXSMM_DIR")
             .or_else(|_| env::var("LIBXSMM_LIB_DIR").map(|lib_dir| {
@@ -33,7 +59,7 @@ fn main() {
         // Don't use xsmmnoblas - we want the BLAS version!
         
         // Link against BLAS (OpenBLAS or system BLAS)
-        println!("cargo:rustc-link-lib=openblas");
+        println!("cargo:rustc-link-lib={}", blas_lib);
         
         // Link with standard libraries that libxsmm needs
         println!("cargo:rustc-link-lib=dl");
@@ -0,0 +1,53 @@
+//! Fused-path scoring of a large synthetic store with tile prefetching off
+//! and at distances 1 and 2. Prints timings; asserts nothing.
+//!
+//! ```text
+//! cargo run --release --example prefetch -- [n_docs] [d_len] [dim]
+//! ```
+//!
+//! The defaults (1000 documents of 1024 tokens of 128 values, 512 MiB)
+//! keep the store well outside the last-level cache. Documents need more
+//! than `fused_threshold()` tokens to take the fused path.
+
+use maxsim_cpu::bench::bench_maxsim;
+use maxsim_cpu::score::{fused_threshold, prefetch_distance, set_prefetch_distance};
+use maxsim_cpu::ScorerConfig;
+
+fn main() {
+    let arg = |i: usize, default: usize| {
+        std::env::args()
+            .nth(i)
+            .map_or(default, |arg| arg.parse().expect("sizes are integers"))
+    };
+    let (n_docs, d_len, dim) = (arg(1, 1000), arg(2, 1024), arg(3, 128));
+    let q_len = 32;
+    if d_len <= fused_threshold() {
+        eprintln!(
+            "{} tokens per document stay on the whole-matrix path (threshold {})",
+            d_len,
+            fused_threshold()
+        );
+    }
+    println!(
+        "{} docs x {} tokens x {} dim, {} MiB",
+        n_docs,
+        d_len,
+        dim,
+        (n_docs * d_len * dim * size_of::<f32>()) >> 20
+    );
+
+    let default = prefetch_distance();
+    for distance in [0, 1, 2] {
+        set_prefetch_distance(distance);
+        let result = bench_maxsim(q_len, d_len, dim, n_docs, ScorerConfig::default())
+            .expect("f32 scoring of a well-formed store");
+        println!(
+            "prefetch distance {}: median {:.2} ms, p99 {:.2} ms, {:.1} GFLOP/s",
+            distance,
+            result.median_ns as f64 / 1e6,
+            result.p99_ns as f64 / 1e6,
+            result.gflops
+        );
+    }
+    set_prefetch_distance(default);
+}
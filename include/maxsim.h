@@ -0,0 +1,50 @@
+/* C interface of maxsim-cpu, built with the `capi` feature.
+ *
+ * Link against the crate's cdylib built with
+ *   cargo build --profile capi --no-default-features --features capi
+ * so panics are reported as MAXSIM_ERR_PANIC instead of aborting and the
+ * GEMMs run on the system BLAS. The profile is required: a `capi` build
+ * that aborts on panic, such as --release, fails. See src/capi.rs for the
+ * full contract of each function and tests/c/capi_test.c for a program
+ * using them.
+ */
+#ifndef MAXSIM_H
+#define MAXSIM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MAXSIM_OK 0
+#define MAXSIM_ERR_INVALID_ARGUMENT 1
+#define MAXSIM_ERR_STORE 2
+#define MAXSIM_ERR_SCORE 3
+#define MAXSIM_ERR_PANIC 4
+
+/* A loaded document store; safe to search from several threads. */
+typedef struct MaxsimStore maxsim_store;
+
+int32_t maxsim_store_open(const char *path, maxsim_store **out);
+void maxsim_store_free(maxsim_store *store);
+size_t maxsim_store_len(const maxsim_store *store);
+size_t maxsim_store_dim(const maxsim_store *store);
+
+/* `query` is [q_len, dim] row-major; `scores` holds n_scores >= len floats. */
+int32_t maxsim_score_batch(const maxsim_store *store, const float *query, size_t q_len,
+                           size_t dim, float *scores, size_t n_scores);
+
+/* `ids` and `scores` hold k values each; *n_out receives the hit count. */
+int32_t maxsim_top_k(const maxsim_store *store, const float *query, size_t q_len, size_t dim,
+                     size_t k, uint64_t *ids, float *scores, size_t *n_out);
+
+/* Last failure on the calling thread, or NULL. */
+const char *maxsim_last_error_message(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MAXSIM_H */
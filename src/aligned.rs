@@ -0,0 +1,439 @@
+//! 64-byte aligned growable buffers.
+//!
+//! AVX-512 and AMX kernels load full cache lines; a `Vec<f32>` only
+//! promises 4-byte alignment. `AlignedVec` keeps its first element on a
+//! `KERNEL_ALIGN` boundary through every reallocation, and an empty one
+//! still hands out an aligned (dangling) pointer. Scoring scratch uses it
+//! throughout, and collections copy embeddings into it at ingest unless
+//! they already arrive in one.
+//!
+//! Buffers spanning gigabytes can be put on huge pages (`AllocPolicy`) to
+//! cut TLB misses when scoring streams through them.
+
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// Alignment in bytes of `AlignedVec` data, one cache line.
+pub const KERNEL_ALIGN: usize = 64;
+
+/// Huge-page mappings are sized in multiples of this (the x86-64 2 MiB
+/// page).
+const HUGE_PAGE: usize = 2 << 20;
+
+/// Where an `AlignedVec` gets its memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AllocPolicy {
+    /// The global allocator.
+    #[default]
+    Heap,
+    /// Anonymous mappings in whole 2 MiB pages: `MAP_HUGETLB` pages when the
+    /// system has them reserved, else transparent huge pages requested
+    /// with `madvise(MADV_HUGEPAGE)`, else normal pages; off Linux, normal
+    /// pages. Worth it for large buffers only; every allocation takes at
+    /// least one huge page.
+    HugePages,
+}
+
+/// Pages an `AlignedVec` actually got.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PageBacking {
+    /// Normal pages (including every `AllocPolicy::Heap` buffer).
+    #[default]
+    Normal,
+    /// Normal pages advised for transparent huge pages. Only says that
+    /// `madvise` succeeded: the kernel promotes the mapping as it can, if
+    /// at all (e.g. not with THP set to `never`).
+    Advised,
+    /// Explicit `MAP_HUGETLB` huge pages.
+    Explicit,
+}
+
+/// Whether `ptr` sits on a `KERNEL_ALIGN` boundary.
+pub fn is_aligned_for_kernels<T>(ptr: *const T) -> bool {
+    (ptr as usize).is_multiple_of(KERNEL_ALIGN)
+}
+
+/// `Vec`-like buffer of `Copy` values aligned to `KERNEL_ALIGN` bytes.
+pub struct AlignedVec<T: Copy> {
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+    policy: AllocPolicy,
+    pages: PageBacking,
+}
+
+// Owns its elements like a `Vec`
+unsafe impl<T: Copy + Send> Send for AlignedVec<T> {}
+unsafe impl<T: Copy + Sync> Sync for AlignedVec<T> {}
+
+impl<T: Copy> AlignedVec<T> {
+    /// Empty, without allocating.
+    pub fn new() -> Self {
+        Self::new_in(AllocPolicy::Heap)
+    }
+
+    /// Empty, allocating per `policy` once it grows.
+    pub fn new_in(policy: AllocPolicy) -> Self {
+        Self {
+            ptr: Self::dangling(),
+            len: 0,
+            cap: if size_of::<T>() == 0 { usize::MAX } else { 0 },
+            policy,
+            pages: PageBacking::Normal,
+        }
+    }
+
+    /// Empty, with room for `capacity` values.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_in(capacity, AllocPolicy::Heap)
+    }
+
+    /// Empty, with room for `capacity` values allocated per `policy`.
+    pub fn with_capacity_in(capacity: usize, policy: AllocPolicy) -> Self {
+        let mut v = Self::new_in(policy);
+        v.reserve(capacity);
+        v
+    }
+
+    /// Aligned copy of `src`.
+    pub fn from_slice(src: &[T]) -> Self {
+        let mut v = Self::with_capacity(src.len());
+        v.extend_from_slice(src);
+        v
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub fn policy(&self) -> AllocPolicy {
+        self.policy
+    }
+
+    /// Pages the current allocation got; `Normal` before the first one.
+    pub fn page_backing(&self) -> PageBacking {
+        self.pages
+    }
+
+    /// Grow or shrink to `new_len`, filling new slots with `value`. Existing
+    /// values are kept across a reallocation.
+    pub fn resize(&mut self, new_len: usize, value: T) {
+        if new_len > self.len {
+            self.reserve(new_len - self.len);
+            for i in self.len..new_len {
+                // In capacity after `reserve`
+                unsafe { self.ptr.as_ptr().add(i).write(value) };
+            }
+        }
+        self.len = new_len;
+    }
+
+    pub fn extend_from_slice(&mut self, src: &[T]) {
+        self.reserve(src.len());
+        // In capacity after `reserve`, and `src` cannot alias the spare tail
+        unsafe {
+            let dst = self.ptr.as_ptr().add(self.len);
+            std::ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+        }
+        self.len += src.len();
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.reserve(1);
+        unsafe { self.ptr.as_ptr().add(self.len).write(value) };
+        self.len += 1;
+    }
+
+    /// Drop every value, keeping the allocation.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Room for at least `additional` more values; grows geometrically.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self
+            .len
+            .checked_add(additional)
+            .expect("aligned buffer capacity overflow");
+        if needed <= self.cap {
+            return;
+        }
+        let cap = needed
+            .max(self.cap.saturating_mul(2))
+            .max(KERNEL_ALIGN / size_of::<T>().max(1));
+        if self.policy == AllocPolicy::HugePages {
+            self.remap(cap);
+            return;
+        }
+        let layout = Self::layout(cap);
+        let ptr = unsafe {
+            if self.cap == 0 {
+                alloc::alloc(layout)
+            } else {
+                alloc::realloc(
+                    self.ptr.as_ptr() as *mut u8,
+                    Self::layout(self.cap),
+                    layout.size(),
+                )
+            }
+        };
+        self.ptr = match NonNull::new(ptr as *mut T) {
+            Some(ptr) => ptr,
+            None => alloc::handle_alloc_error(layout),
+        };
+        self.cap = cap;
+    }
+
+    /// Move into a fresh huge-page mapping of at least `cap` values.
+    fn remap(&mut self, cap: usize) {
+        let bytes = Self::mapping_len(cap);
+        let (ptr, pages) = map_huge(bytes).unwrap_or_else(|| {
+            alloc::handle_alloc_error(Layout::from_size_align(bytes, HUGE_PAGE).unwrap())
+        });
+        let ptr = ptr as *mut T;
+        // The new mapping is larger than `len` values and disjoint
+        unsafe { std::ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr, self.len) };
+        self.release();
+        self.ptr = NonNull::new(ptr).expect("mmap never returns null on success");
+        self.cap = bytes / size_of::<T>();
+        self.pages = pages;
+    }
+
+    /// Bytes of the huge-page mapping holding `cap` values.
+    fn mapping_len(cap: usize) -> usize {
+        cap.checked_mul(size_of::<T>())
+            .and_then(|bytes| bytes.checked_next_multiple_of(HUGE_PAGE))
+            .expect("aligned buffer capacity overflow")
+    }
+
+    /// Free the allocation, if any.
+    fn release(&mut self) {
+        if self.cap == 0 || size_of::<T>() == 0 {
+            return;
+        }
+        let ptr = self.ptr.as_ptr() as *mut u8;
+        match self.policy {
+            AllocPolicy::Heap => unsafe { alloc::dealloc(ptr, Self::layout(self.cap)) },
+            AllocPolicy::HugePages => unsafe {
+                libc::munmap(ptr as *mut libc::c_void, Self::mapping_len(self.cap));
+            },
+        }
+    }
+
+    fn layout(cap: usize) -> Layout {
+        let align = KERNEL_ALIGN.max(align_of::<T>());
+        Layout::array::<T>(cap)
+            .and_then(|array| array.align_to(align))
+            .expect("aligned buffer capacity overflow")
+    }
+
+    /// Non-null, aligned, never dereferenced: the pointer of an empty buffer.
+    fn dangling() -> NonNull<T> {
+        let align = KERNEL_ALIGN.max(align_of::<T>());
+        NonNull::new(std::ptr::without_provenance_mut(align)).expect("alignment is non-zero")
+    }
+}
+
+impl<T: Copy> Drop for AlignedVec<T> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// An anonymous read-write mapping of `bytes` (a multiple of `HUGE_PAGE`),
+/// on the best pages available; `None` when even normal pages fail.
+fn map_huge(bytes: usize) -> Option<(*mut u8, PageBacking)> {
+    let map = |flags| {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                bytes,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+                -1,
+                0,
+            )
+        };
+        (ptr != libc::MAP_FAILED).then_some(ptr)
+    };
+    #[cfg(target_os = "linux")]
+    if let Some(ptr) = map(libc::MAP_HUGETLB) {
+        return Some((ptr as *mut u8, PageBacking::Explicit));
+    }
+    let ptr = map(0)?;
+    #[cfg(target_os = "linux")]
+    let advised = unsafe { libc::madvise(ptr, bytes, libc::MADV_HUGEPAGE) } == 0;
+    #[cfg(not(target_os = "linux"))]
+    let advised = false;
+    let pages = if advised {
+        PageBacking::Advised
+    } else {
+        PageBacking::Normal
+    };
+    Some((ptr as *mut u8, pages))
+}
+
+impl<T: Copy> Deref for AlignedVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // `len` values initialized; aligned and non-null even when empty
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> DerefMut for AlignedVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> Default for AlignedVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Allocates the clone per the same policy.
+impl<T: Copy> Clone for AlignedVec<T> {
+    fn clone(&self) -> Self {
+        let mut v = Self::with_capacity_in(self.len, self.policy);
+        v.extend_from_slice(self);
+        v
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for AlignedVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Copy + PartialEq> PartialEq for AlignedVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: Copy> Extend<T> for AlignedVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<'a, T: Copy> IntoIterator for &'a AlignedVec<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T: Copy> IntoIterator for &'a mut AlignedVec<T> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T: Copy> From<&[T]> for AlignedVec<T> {
+    fn from(src: &[T]) -> Self {
+        Self::from_slice(src)
+    }
+}
+
+/// Copies: a `Vec` allocation cannot be re-aligned in place.
+impl<T: Copy> From<Vec<T>> for AlignedVec<T> {
+    fn from(src: Vec<T>) -> Self {
+        Self::from_slice(&src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn growth_keeps_values_and_alignment() {
+        let mut v = AlignedVec::new();
+        let mut moves = 0;
+        for i in 0..10_000u32 {
+            let before = v.as_ptr();
+            v.push(i);
+            moves += usize::from(v.as_ptr() != before);
+            assert!(is_aligned_for_kernels(v.as_ptr()), "after {i} pushes");
+        }
+        assert!(moves > 1, "{moves} reallocations");
+        assert!(v.iter().copied().eq(0..10_000));
+
+        let mut bytes = AlignedVec::<u8>::from_slice(&[1, 2, 3]);
+        bytes.resize(1 << 20, 7);
+        bytes.extend_from_slice(&[9; 100]);
+        assert!(is_aligned_for_kernels(bytes.as_ptr()));
+        assert_eq!(bytes[..4], [1, 2, 3, 7]);
+        assert_eq!(bytes.len(), (1 << 20) + 100);
+        assert_eq!(bytes[bytes.len() - 1], 9);
+        let copy = bytes.clone();
+        assert!(is_aligned_for_kernels(copy.as_ptr()));
+        assert_eq!(copy, bytes);
+    }
+
+    #[test]
+    fn empty_and_zero_sized_buffers_work() {
+        let empty = AlignedVec::<f32>::new();
+        assert!(empty.is_empty());
+        assert_eq!(empty.capacity(), 0);
+        assert!(is_aligned_for_kernels(empty.as_ptr()));
+        let copy = empty.clone();
+        assert!(copy.is_empty() && is_aligned_for_kernels(copy.as_ptr()));
+        let mut v = AlignedVec::<f32>::with_capacity(0);
+        v.extend_from_slice(&[]);
+        v.extend(std::iter::empty());
+        assert!(v.is_empty() && is_aligned_for_kernels(v.as_ptr()));
+        v.push(1.5);
+        assert_eq!(v[..], [1.5]);
+        v.clear();
+        assert!(v.is_empty());
+
+        let mut units = AlignedVec::<()>::new();
+        for _ in 0..1000 {
+            units.push(());
+        }
+        units.extend([(); 24]);
+        units.extend_from_slice(&[(); 1000]);
+        assert_eq!(units.len(), 2024);
+        assert_eq!(units.clone().len(), 2024);
+        assert!(is_aligned_for_kernels(units.as_ptr()));
+    }
+
+    #[test]
+    fn huge_page_requests_fall_back_to_whatever_pages_there_are() {
+        let mut v = AlignedVec::<f32>::new_in(AllocPolicy::HugePages);
+        assert_eq!(v.page_backing(), PageBacking::Normal);
+        assert!(v.is_empty() && is_aligned_for_kernels(v.as_ptr()));
+        // Without reserved huge pages (as on most test machines) the
+        // mapping still succeeds, on advised or normal pages
+        v.push(1.0);
+        assert_eq!(v.capacity() * size_of::<f32>() % HUGE_PAGE, 0);
+        v.resize(HUGE_PAGE, 2.0);
+        v.push(3.0);
+        assert!(is_aligned_for_kernels(v.as_ptr()));
+        assert_eq!((v[0], v[1], v[HUGE_PAGE]), (1.0, 2.0, 3.0));
+        let copy = v.clone();
+        assert_eq!(copy.policy(), AllocPolicy::HugePages);
+        assert_eq!(copy, v);
+    }
+}
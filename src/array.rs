@@ -0,0 +1,151 @@
+//! `ndarray` inputs and outputs for the scoring API (feature `ndarray`).
+//!
+//! Embeddings come in as `ArrayView2<f32>` of shape `[tokens, dim]`, so
+//! token counts and dim are read off the shape instead of passed next to a
+//! flat slice. A standard-layout view is used in place (or copied once into
+//! aligned storage); any other layout, e.g. a transposed or sliced view, is
+//! copied out in logical row-major order first. Score matrices come back
+//! as `Array2<f32>`.
+
+use std::borrow::Cow;
+
+use ndarray::{Array1, Array2, ArrayView2};
+
+use crate::collection::{DocBatch, DocCollection, DocId, Documents, QueryBatch, QueryEmbeddings};
+use crate::score::{
+    maxsim_per_token, maxsim_per_token_batch, maxsim_score, maxsim_score_matrix, ScoreError,
+};
+
+impl QueryEmbeddings {
+    /// Query from a `[tokens, dim]` view. All tokens are unmasked.
+    pub fn from_array(query: ArrayView2<'_, f32>) -> Self {
+        let (len, dim) = query.dim();
+        Self::new(&row_major(&query)[..], len, dim).expect("view holds tokens * dim values")
+    }
+}
+
+impl QueryBatch {
+    /// One query per `[tokens, dim]` view.
+    pub fn from_arrays(queries: &[ArrayView2<'_, f32>]) -> Self {
+        Self::new(
+            queries
+                .iter()
+                .map(|&q| QueryEmbeddings::from_array(q))
+                .collect(),
+        )
+    }
+}
+
+impl DocBatch {
+    /// Append one `[tokens, dim]` document, identified by its position.
+    /// Fails with `DimMismatch` if its dim is not the batch's.
+    pub fn push_array(&mut self, doc: ArrayView2<'_, f32>) -> Result<DocId, ScoreError> {
+        check_doc_dim(&doc, self.dim())?;
+        self.push(&row_major(&doc))
+    }
+}
+
+impl DocCollection {
+    /// Documents from `[tokens, dim]` views of one dim.
+    pub fn from_arrays(docs: &[ArrayView2<'_, f32>]) -> Result<Self, ScoreError> {
+        let dim = docs.first().map_or(0, |doc| doc.ncols());
+        let mut data = Vec::with_capacity(docs.iter().map(|doc| doc.len()).sum());
+        let mut lengths = Vec::with_capacity(docs.len());
+        for doc in docs {
+            check_doc_dim(doc, dim)?;
+            data.extend_from_slice(&row_major(doc));
+            lengths.push(doc.nrows());
+        }
+        Self::from_lengths(data, &lengths, dim)
+    }
+}
+
+/// `maxsim_score` of a `[q_len, dim]` query view against a `[d_len, dim]`
+/// document view.
+pub fn maxsim_score_array(
+    query: ArrayView2<'_, f32>,
+    doc: ArrayView2<'_, f32>,
+) -> Result<f32, ScoreError> {
+    let dim = check_dims(&query, &doc)?;
+    maxsim_score(
+        &row_major(&query),
+        query.nrows(),
+        &row_major(&doc),
+        doc.nrows(),
+        dim,
+    )
+}
+
+/// `maxsim_per_token` of a query view against a document view: each query
+/// token's best similarity.
+pub fn maxsim_per_token_array(
+    query: ArrayView2<'_, f32>,
+    doc: ArrayView2<'_, f32>,
+) -> Result<Array1<f32>, ScoreError> {
+    let dim = check_dims(&query, &doc)?;
+    let mut out = Array1::zeros(query.nrows());
+    maxsim_per_token(
+        &row_major(&query),
+        query.nrows(),
+        &row_major(&doc),
+        doc.nrows(),
+        dim,
+        out.as_slice_mut().expect("fresh array is contiguous"),
+    )?;
+    Ok(out)
+}
+
+/// `maxsim_per_token_batch` as a `[docs.len(), query.len()]` array.
+pub fn maxsim_per_token_batch_array<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+) -> Result<Array2<f32>, ScoreError> {
+    let mut out = Array2::zeros((docs.len(), query.len()));
+    maxsim_per_token_batch(
+        query,
+        docs,
+        out.as_slice_mut().expect("fresh array is contiguous"),
+    )?;
+    Ok(out)
+}
+
+/// `maxsim_score_matrix` as a `[n_queries, n_docs]` array.
+pub fn maxsim_score_matrix_array<D: Documents + ?Sized>(
+    queries: &QueryBatch,
+    docs: &D,
+) -> Result<Array2<f32>, ScoreError> {
+    let scores = maxsim_score_matrix(queries, docs)?;
+    Ok(Array2::from_shape_vec((queries.len(), docs.len()), scores)
+        .expect("score matrix is n_queries * n_docs"))
+}
+
+/// The values of `view` in row-major order, borrowed when its layout
+/// already is.
+fn row_major<'a>(view: &ArrayView2<'a, f32>) -> Cow<'a, [f32]> {
+    match view.to_slice() {
+        Some(values) => Cow::Borrowed(values),
+        None => Cow::Owned(view.iter().copied().collect()),
+    }
+}
+
+/// The shared dim of a query and a document view.
+fn check_dims(query: &ArrayView2<'_, f32>, doc: &ArrayView2<'_, f32>) -> Result<usize, ScoreError> {
+    if query.ncols() != doc.ncols() {
+        return Err(ScoreError::EmbeddingDim {
+            query: query.ncols(),
+            docs: doc.ncols(),
+        });
+    }
+    Ok(query.ncols())
+}
+
+fn check_doc_dim(doc: &ArrayView2<'_, f32>, dim: usize) -> Result<(), ScoreError> {
+    if doc.ncols() != dim {
+        return Err(ScoreError::DimMismatch {
+            operand: "doc",
+            expected: doc.nrows() * dim,
+            actual: doc.len(),
+        });
+    }
+    Ok(())
+}
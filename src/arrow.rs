@@ -0,0 +1,719 @@
+//! Arrow ingestion of document embeddings and export of search results
+//! (feature `arrow`).
+//!
+//! Batches come in through the Arrow C Data Interface, the ABI-stable
+//! struct pair every Arrow implementation exports (`arrow::ffi` in
+//! arrow-rs, `_export_to_c` in pyarrow), so no Arrow crate is linked. A
+//! record batch is exported as a struct array whose children are its
+//! columns.
+//!
+//! Each row is one token: a `FixedSizeList<Float32>[dim]` embedding, the
+//! `Int64` or `UInt64` id of its document, and a `Boolean` that is true on
+//! the first token of each document. A document's rows are consecutive.
+//! The float values are copied into the batch in one pass; nothing is
+//! allocated per row.
+//!
+//! Search results go the other way as `ExportedRecordBatch`es of one row
+//! per hit: `query_id: UInt64`, `rank: UInt32`, `doc_id: UInt64` and
+//! `score: Float32`, none nullable. Output past `arrow_batch_rows()` rows
+//! is split over several batches, and an empty result is one batch of
+//! no rows, so consumers always get the schema.
+
+use std::any::Any;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::aligned::AlignedVec;
+use crate::collection::DocBatch;
+use crate::score::ScoreError;
+use crate::topk::SearchResults;
+
+/// `struct ArrowSchema` of the C Data Interface.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowSchema {
+    format: *const c_char,
+    name: *const c_char,
+    metadata: *const c_char,
+    flags: i64,
+    n_children: i64,
+    children: *mut *mut ArrowSchema,
+    dictionary: *mut ArrowSchema,
+    release: Option<unsafe extern "C" fn(*mut ArrowSchema)>,
+    private_data: *mut c_void,
+}
+
+/// `struct ArrowArray` of the C Data Interface.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowArray {
+    length: i64,
+    null_count: i64,
+    offset: i64,
+    n_buffers: i64,
+    n_children: i64,
+    buffers: *mut *const c_void,
+    children: *mut *mut ArrowArray,
+    dictionary: *mut ArrowArray,
+    release: Option<unsafe extern "C" fn(*mut ArrowArray)>,
+    private_data: *mut c_void,
+}
+
+/// An exported record batch, borrowed for as long as it is read.
+#[derive(Clone, Copy, Debug)]
+pub struct ArrowRecordBatch<'a> {
+    array: &'a ArrowArray,
+    schema: &'a ArrowSchema,
+}
+
+impl<'a> ArrowRecordBatch<'a> {
+    /// Borrow an exported record batch.
+    ///
+    /// # Safety
+    ///
+    /// `array` and `schema` must point to a record batch exported through
+    /// the C Data Interface (e.g. arrow-rs `FFI_ArrowArray` and
+    /// `FFI_ArrowSchema` of a `StructArray`), not released for `'a`.
+    pub unsafe fn from_raw(array: *const ArrowArray, schema: *const ArrowSchema) -> Self {
+        Self {
+            array: &*array,
+            schema: &*schema,
+        }
+    }
+
+    /// Number of rows.
+    pub fn num_rows(&self) -> usize {
+        self.array.length as usize
+    }
+
+    /// Column `name` with its absolute row offset.
+    fn column(&self, name: &str) -> Result<Column<'a>, ArrowError> {
+        if format(self.schema) != "+s" || self.array.n_children != self.schema.n_children {
+            return Err(ArrowError::NotRecordBatch);
+        }
+        for i in 0..self.schema.n_children as usize {
+            // Children of a live export, one per column
+            let (schema, array) = unsafe {
+                (
+                    &**self.schema.children.add(i),
+                    &**self.array.children.add(i),
+                )
+            };
+            let field = unsafe { CStr::from_ptr(schema.name) };
+            if field.to_bytes() == name.as_bytes() {
+                return Ok(Column {
+                    name: name.to_string(),
+                    schema,
+                    array,
+                    base: (self.array.offset + array.offset) as usize,
+                });
+            }
+        }
+        Err(ArrowError::MissingColumn {
+            name: name.to_string(),
+        })
+    }
+}
+
+/// Why a record batch could not be ingested.
+#[derive(Debug)]
+pub enum ArrowError {
+    /// The batch is not an exported struct array.
+    NotRecordBatch,
+    MissingColumn {
+        name: String,
+    },
+    /// Column `name` has Arrow format string `format`, not `expected`.
+    ColumnType {
+        name: String,
+        format: String,
+        expected: &'static str,
+    },
+    /// Column `name` holds nulls.
+    Nulls {
+        name: String,
+    },
+    /// The embedding lists are empty or run past their float values.
+    ListSize {
+        name: String,
+        size: usize,
+    },
+    /// Column `name` holds fewer values than the batch has rows.
+    ColumnLength {
+        name: String,
+        length: usize,
+        rows: usize,
+    },
+    /// Row `row` carries a negative id, or another id than the first row
+    /// of its document.
+    InvalidId {
+        row: usize,
+    },
+    /// The first row does not start a document.
+    NoBoundary,
+    /// The embeddings were rejected by the batch, e.g. a repeated id.
+    Score(ScoreError),
+}
+
+impl std::fmt::Display for ArrowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArrowError::NotRecordBatch => write!(f, "arrow array is not a record batch"),
+            ArrowError::MissingColumn { name } => write!(f, "no column `{}` in batch", name),
+            ArrowError::ColumnType {
+                name,
+                format,
+                expected,
+            } => write!(
+                f,
+                "column `{}` has arrow format `{}`, expected {}",
+                name, format, expected
+            ),
+            ArrowError::Nulls { name } => write!(f, "column `{}` holds nulls", name),
+            ArrowError::ListSize { name, size } => {
+                write!(f, "column `{}` has invalid fixed list size {}", name, size)
+            }
+            ArrowError::ColumnLength { name, length, rows } => write!(
+                f,
+                "column `{}` has {} values for {} rows",
+                name, length, rows
+            ),
+            ArrowError::InvalidId { row } => {
+                write!(f, "row {} has an id its document does not start with", row)
+            }
+            ArrowError::NoBoundary => write!(f, "first row does not start a document"),
+            ArrowError::Score(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ArrowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArrowError::Score(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScoreError> for ArrowError {
+    fn from(err: ScoreError) -> Self {
+        ArrowError::Score(err)
+    }
+}
+
+impl DocBatch {
+    /// Documents from the token rows of `batch`: embeddings from
+    /// `embedding_col`, external ids from `id_col`, and document starts
+    /// from `doc_boundary_col` (see the module docs for the column types).
+    /// Every row of a document must carry the same id, and ids must be
+    /// unique across documents.
+    pub fn from_arrow(
+        batch: &ArrowRecordBatch<'_>,
+        embedding_col: &str,
+        id_col: &str,
+        doc_boundary_col: &str,
+    ) -> Result<Self, ArrowError> {
+        let rows = batch.num_rows();
+        let embeddings = batch.column(embedding_col)?;
+        let ids = batch.column(id_col)?;
+        let boundaries = batch.column(doc_boundary_col)?;
+        let (values, dim) = embeddings.float_lists(rows)?;
+        let id = ids.ids(rows)?;
+        let starts = boundaries.booleans(rows)?;
+
+        let mut offsets = Vec::new();
+        let mut doc_ids = Vec::new();
+        for row in 0..rows {
+            if starts(row) {
+                offsets.push(row * dim);
+                doc_ids.push(id(row).ok_or(ArrowError::InvalidId { row })?);
+            } else if row == 0 {
+                return Err(ArrowError::NoBoundary);
+            } else if id(row) != doc_ids.last().copied() {
+                return Err(ArrowError::InvalidId { row });
+            }
+        }
+        offsets.push(rows * dim);
+        let mut data = AlignedVec::with_capacity(values.len());
+        data.extend_from_slice(values);
+        Ok(Self::from_parts(data, offsets, dim)?.with_ids(doc_ids)?)
+    }
+}
+
+/// One column of a record batch.
+struct Column<'a> {
+    name: String,
+    schema: &'a ArrowSchema,
+    array: &'a ArrowArray,
+    /// Index of row 0 in the column's buffers.
+    base: usize,
+}
+
+impl<'a> Column<'a> {
+    fn type_error(&self, expected: &'static str) -> ArrowError {
+        ArrowError::ColumnType {
+            name: self.name.clone(),
+            format: format(self.schema).to_string(),
+            expected,
+        }
+    }
+
+    /// Fails unless the column's buffers hold `rows` values from `base`.
+    fn check_length(&self, rows: usize) -> Result<(), ArrowError> {
+        let (length, offset) = (self.array.length as usize, self.array.offset as usize);
+        if offset + length < self.base + rows {
+            return Err(ArrowError::ColumnLength {
+                name: self.name.clone(),
+                length: (offset + length).saturating_sub(self.base),
+                rows,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_valid(&self, array: &ArrowArray, start: usize, rows: usize) -> Result<(), ArrowError> {
+        if has_nulls(array, start, rows) {
+            return Err(ArrowError::Nulls {
+                name: self.name.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The floats of `rows` `FixedSizeList<Float32>` values, and the list
+    /// size.
+    fn float_lists(&self, rows: usize) -> Result<(&'a [f32], usize), ArrowError> {
+        const EXPECTED: &str = "FixedSizeList<Float32> (`+w:<dim>` of `f`)";
+        let dim = format(self.schema)
+            .strip_prefix("+w:")
+            .and_then(|size| size.parse::<usize>().ok())
+            .ok_or_else(|| self.type_error(EXPECTED))?;
+        if self.array.n_children != 1 || self.schema.n_children != 1 {
+            return Err(ArrowError::NotRecordBatch);
+        }
+        // The list's only child, of a live export
+        let (child_schema, child) = unsafe { (&**self.schema.children, &**self.array.children) };
+        if format(child_schema) != "f" {
+            return Err(self.type_error(EXPECTED));
+        }
+        let start = self.base * dim;
+        let len = rows * dim;
+        if dim == 0 || (child.length as usize) < start + len {
+            return Err(ArrowError::ListSize {
+                name: self.name.clone(),
+                size: dim,
+            });
+        }
+        let first = child.offset as usize + start;
+        self.check_valid(self.array, self.base, rows)?;
+        self.check_valid(child, first, len)?;
+        let values = buffer::<f32>(child, 1);
+        // `child.length` floats from `child.offset` live in the buffer
+        Ok((
+            unsafe { std::slice::from_raw_parts(values.add(first), len) },
+            dim,
+        ))
+    }
+
+    /// Id of each row, `None` when negative.
+    fn ids(&self, rows: usize) -> Result<impl Fn(usize) -> Option<u64> + 'a, ArrowError> {
+        let signed = match format(self.schema) {
+            "l" => true,
+            "L" => false,
+            _ => return Err(self.type_error("Int64 or UInt64")),
+        };
+        self.check_length(rows)?;
+        self.check_valid(self.array, self.base, rows)?;
+        let (values, base) = (buffer::<u64>(self.array, 1), self.base);
+        Ok(move |row: usize| {
+            // In bounds: `row` < the column length
+            let id = unsafe { *values.add(base + row) };
+            (!signed || (id as i64) >= 0).then_some(id)
+        })
+    }
+
+    /// Value of each row of a Boolean column.
+    fn booleans(&self, rows: usize) -> Result<impl Fn(usize) -> bool + 'a, ArrowError> {
+        if format(self.schema) != "b" {
+            return Err(self.type_error("Boolean"));
+        }
+        self.check_length(rows)?;
+        self.check_valid(self.array, self.base, rows)?;
+        let (bits, base) = (buffer::<u8>(self.array, 1), self.base);
+        // In bounds: `row` < the column length
+        Ok(move |row: usize| unsafe { bit(bits, base + row) })
+    }
+}
+
+/// Format string of `schema`.
+fn format(schema: &ArrowSchema) -> &str {
+    if schema.format.is_null() {
+        return "";
+    }
+    unsafe { CStr::from_ptr(schema.format) }
+        .to_str()
+        .unwrap_or("")
+}
+
+/// Buffer `i` of `array`, as `T`s.
+fn buffer<T>(array: &ArrowArray, i: usize) -> *const T {
+    unsafe { *array.buffers.add(i) as *const T }
+}
+
+/// Bit `i` of an LSB-first bitmap.
+unsafe fn bit(bits: *const u8, i: usize) -> bool {
+    *bits.add(i / 8) & (1 << (i % 8)) != 0
+}
+
+/// Whether any of buffer elements `start..start + len` of `array` is null.
+fn has_nulls(array: &ArrowArray, start: usize, len: usize) -> bool {
+    if array.null_count == 0 || array.n_buffers == 0 {
+        return false;
+    }
+    let validity = buffer::<u8>(array, 0);
+    if validity.is_null() {
+        return false;
+    }
+    (start..start + len).any(|i| !unsafe { bit(validity, i) })
+}
+
+/// Default for `set_arrow_batch_rows`: 1M rows, 24 MiB of columns.
+pub const DEFAULT_ARROW_BATCH_ROWS: usize = 1 << 20;
+
+static ARROW_BATCH_ROWS: AtomicUsize = AtomicUsize::new(DEFAULT_ARROW_BATCH_ROWS);
+
+/// Most rows `SearchResults::to_arrow` puts in one batch before starting
+/// another; at least 1.
+pub fn set_arrow_batch_rows(rows: usize) {
+    ARROW_BATCH_ROWS.store(rows.max(1), Ordering::Relaxed);
+}
+
+pub fn arrow_batch_rows() -> usize {
+    ARROW_BATCH_ROWS.load(Ordering::Relaxed)
+}
+
+/// A record batch this crate exported through the C Data Interface. It
+/// owns its buffers until `into_raw` hands them to a consumer, or releases
+/// them when dropped.
+#[derive(Debug)]
+pub struct ExportedRecordBatch {
+    array: ArrowArray,
+    schema: ArrowSchema,
+}
+
+// The exported structs and everything they point to are owned by the batch
+unsafe impl Send for ExportedRecordBatch {}
+
+impl ExportedRecordBatch {
+    pub fn num_rows(&self) -> usize {
+        self.array.length as usize
+    }
+
+    /// Borrow the batch, as an imported one is.
+    pub fn as_batch(&self) -> ArrowRecordBatch<'_> {
+        ArrowRecordBatch {
+            array: &self.array,
+            schema: &self.schema,
+        }
+    }
+
+    /// Move the batch into `array` and `schema`, e.g. arrow-rs
+    /// `FFI_ArrowArray::empty()` and `FFI_ArrowSchema::empty()`; whoever
+    /// holds them then releases it.
+    ///
+    /// # Safety
+    ///
+    /// `array` and `schema` must be valid for writes. Whatever they held is
+    /// overwritten without being released.
+    pub unsafe fn into_raw(self, array: *mut ArrowArray, schema: *mut ArrowSchema) {
+        let this = ManuallyDrop::new(self);
+        std::ptr::write(array, std::ptr::read(&this.array));
+        std::ptr::write(schema, std::ptr::read(&this.schema));
+    }
+}
+
+impl Drop for ExportedRecordBatch {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(release) = self.array.release {
+                release(&mut self.array);
+            }
+            if let Some(release) = self.schema.release {
+                release(&mut self.schema);
+            }
+        }
+    }
+}
+
+impl SearchResults {
+    /// These hits as record batches; see the module docs for the columns.
+    pub fn to_arrow(&self) -> Vec<ExportedRecordBatch> {
+        Self::batch_to_arrow(std::slice::from_ref(self))
+    }
+
+    /// The hits of every query in `results`, in order, as record batches of
+    /// at most `arrow_batch_rows()` rows. A query's hits may span two
+    /// batches.
+    pub fn batch_to_arrow(results: &[SearchResults]) -> Vec<ExportedRecordBatch> {
+        let total: usize = results.iter().map(SearchResults::len).sum();
+        let max_rows = arrow_batch_rows();
+        let mut rows = results
+            .iter()
+            .flat_map(|results| results.hits.iter().map(|hit| (results.query_id, hit)));
+        let mut batches = Vec::with_capacity(total.div_ceil(max_rows).max(1));
+        let mut done = 0;
+        loop {
+            let n = max_rows.min(total - done);
+            let mut query_id = Vec::with_capacity(n);
+            let mut rank = Vec::with_capacity(n);
+            let mut doc_id = Vec::with_capacity(n);
+            let mut score = Vec::with_capacity(n);
+            for (query, hit) in rows.by_ref().take(n) {
+                query_id.push(query);
+                rank.push(hit.rank);
+                doc_id.push(hit.id);
+                score.push(hit.score);
+            }
+            batches.push(export_batch(
+                n,
+                vec![
+                    export_column("query_id", "L", query_id),
+                    export_column("rank", "I", rank),
+                    export_column("doc_id", "L", doc_id),
+                    export_column("score", "f", score),
+                ],
+            ));
+            done += n;
+            if done == total {
+                return batches;
+            }
+        }
+    }
+}
+
+/// What an exported array's `private_data` owns.
+struct ArrayData {
+    buffers: Vec<*const c_void>,
+    children: Vec<*mut ArrowArray>,
+    _values: Option<Box<dyn Any>>,
+}
+
+/// What an exported schema's `private_data` owns.
+struct SchemaData {
+    format: CString,
+    name: CString,
+    children: Vec<*mut ArrowSchema>,
+}
+
+/// A struct array of `rows` rows over `columns`.
+fn export_batch(rows: usize, columns: Vec<(ArrowArray, ArrowSchema)>) -> ExportedRecordBatch {
+    let (arrays, schemas): (Vec<_>, Vec<_>) = columns
+        .into_iter()
+        .map(|(array, schema)| {
+            (
+                Box::into_raw(Box::new(array)),
+                Box::into_raw(Box::new(schema)),
+            )
+        })
+        .unzip();
+    ExportedRecordBatch {
+        array: export_array(rows, vec![std::ptr::null()], arrays, None),
+        schema: export_schema("+s", "", schemas),
+    }
+}
+
+/// A non-null primitive column `name` of Arrow format `format`.
+fn export_column<T: 'static>(
+    name: &str,
+    format: &str,
+    values: Vec<T>,
+) -> (ArrowArray, ArrowSchema) {
+    let buffers = vec![std::ptr::null(), values.as_ptr() as *const c_void];
+    (
+        export_array(values.len(), buffers, Vec::new(), Some(Box::new(values))),
+        export_schema(format, name, Vec::new()),
+    )
+}
+
+fn export_array(
+    length: usize,
+    buffers: Vec<*const c_void>,
+    children: Vec<*mut ArrowArray>,
+    values: Option<Box<dyn Any>>,
+) -> ArrowArray {
+    let mut data = Box::new(ArrayData {
+        buffers,
+        children,
+        _values: values,
+    });
+    ArrowArray {
+        length: length as i64,
+        null_count: 0,
+        offset: 0,
+        n_buffers: data.buffers.len() as i64,
+        n_children: data.children.len() as i64,
+        buffers: data.buffers.as_mut_ptr(),
+        children: data.children.as_mut_ptr(),
+        dictionary: std::ptr::null_mut(),
+        release: Some(release_array),
+        private_data: Box::into_raw(data) as *mut c_void,
+    }
+}
+
+fn export_schema(format: &str, name: &str, children: Vec<*mut ArrowSchema>) -> ArrowSchema {
+    let mut data = Box::new(SchemaData {
+        format: CString::new(format).expect("format strings have no NUL"),
+        name: CString::new(name).expect("column names have no NUL"),
+        children,
+    });
+    ArrowSchema {
+        format: data.format.as_ptr(),
+        name: data.name.as_ptr(),
+        metadata: std::ptr::null(),
+        flags: 0,
+        n_children: data.children.len() as i64,
+        children: data.children.as_mut_ptr(),
+        dictionary: std::ptr::null_mut(),
+        release: Some(release_schema),
+        private_data: Box::into_raw(data) as *mut c_void,
+    }
+}
+
+/// Release callback of exported arrays: releases the children a consumer
+/// has not moved out, then frees them and the buffers.
+unsafe extern "C" fn release_array(array: *mut ArrowArray) {
+    let array = &mut *array;
+    let data = Box::from_raw(array.private_data as *mut ArrayData);
+    for &child in &data.children {
+        if let Some(release) = (*child).release {
+            release(child);
+        }
+        drop(Box::from_raw(child));
+    }
+    array.release = None;
+}
+
+/// Release callback of exported schemas, as `release_array`.
+unsafe extern "C" fn release_schema(schema: *mut ArrowSchema) {
+    let schema = &mut *schema;
+    let data = Box::from_raw(schema.private_data as *mut SchemaData);
+    for &child in &data.children {
+        if let Some(release) = (*child).release {
+            release(child);
+        }
+        drop(Box::from_raw(child));
+    }
+    schema.release = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+    use crate::collection::QueryEmbeddings;
+    use crate::scorer::{Scorer, ScorerConfig};
+
+    const DIM: usize = 4;
+
+    /// Token rows of documents `(id, tokens)` as an exported batch with
+    /// columns `emb`, `id` and `start`, the id column cut to `id_rows`.
+    fn token_batch(docs: &[(u64, usize)], id_rows: usize) -> (ExportedRecordBatch, Vec<f32>) {
+        let rows: usize = docs.iter().map(|&(_, tokens)| tokens).sum();
+        let values = unit_rows(rows, DIM, 5);
+        let (mut ids, mut starts) = (Vec::new(), vec![0u8; rows.div_ceil(8)]);
+        for &(id, tokens) in docs {
+            starts[ids.len() / 8] |= 1 << (ids.len() % 8);
+            ids.extend(std::iter::repeat_n(id, tokens));
+        }
+        ids.truncate(id_rows);
+
+        let (floats, float_schema) = export_column("item", "f", values.clone());
+        let embeddings = (
+            export_array(
+                rows,
+                vec![std::ptr::null()],
+                vec![Box::into_raw(Box::new(floats))],
+                None,
+            ),
+            export_schema(
+                &format!("+w:{DIM}"),
+                "emb",
+                vec![Box::into_raw(Box::new(float_schema))],
+            ),
+        );
+        let (bits, bits_schema) = export_column("start", "b", starts);
+        let columns = vec![
+            embeddings,
+            export_column("id", "L", ids),
+            (
+                ArrowArray {
+                    length: rows as i64,
+                    ..bits
+                },
+                bits_schema,
+            ),
+        ];
+        (export_batch(rows, columns), values)
+    }
+
+    /// Column `name` of `batch` read as `T`s.
+    fn column<T: Copy>(batch: &ExportedRecordBatch, name: &str) -> Vec<T> {
+        let column = batch.as_batch().column(name).unwrap();
+        let values = buffer::<T>(column.array, 1);
+        (0..batch.num_rows())
+            .map(|row| unsafe { *values.add(column.base + row) })
+            .collect()
+    }
+
+    #[test]
+    fn ingested_ids_come_back_on_the_hits() {
+        let docs = [(900, 2), (7, 3), (31, 1), (u64::MAX >> 1, 4)];
+        let (exported, values) = token_batch(&docs, 10);
+        let batch = DocBatch::from_arrow(&exported.as_batch(), "emb", "id", "start").unwrap();
+        assert_eq!(batch.len(), docs.len());
+        let mut row = 0;
+        for (i, &(id, tokens)) in docs.iter().enumerate() {
+            assert_eq!(batch.id(i), id);
+            assert_eq!(batch.doc(i), &values[row * DIM..(row + tokens) * DIM]);
+            row += tokens;
+        }
+
+        let scorer = Scorer::new(ScorerConfig::default().with_num_threads(1)).unwrap();
+        let query = QueryEmbeddings::new(unit_rows(3, DIM, 6), 3, DIM).unwrap();
+        let results = scorer.search(17, &query, &batch, 3).unwrap();
+        let out = results.to_arrow();
+        assert_eq!(out.len(), 1);
+        let hits = &results.hits;
+        assert_eq!(column::<u64>(&out[0], "query_id"), vec![17; 3]);
+        assert_eq!(column::<u32>(&out[0], "rank"), vec![0, 1, 2]);
+        let ids = column::<u64>(&out[0], "doc_id");
+        assert_eq!(ids, hits.iter().map(|h| h.id).collect::<Vec<_>>());
+        assert!(ids.iter().all(|id| docs.iter().any(|&(d, _)| d == *id)));
+        let scores = column::<f32>(&out[0], "score");
+        assert_eq!(scores, hits.iter().map(|h| h.score).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn short_columns_are_errors() {
+        let (exported, _) = token_batch(&[(1, 2), (2, 3)], 4);
+        let err = DocBatch::from_arrow(&exported.as_batch(), "emb", "id", "start").unwrap_err();
+        assert!(
+            matches!(&err, ArrowError::ColumnLength { name, length: 4, rows: 5 } if name == "id"),
+            "{err}"
+        );
+
+        let (mut exported, _) = token_batch(&[(1, 2), (2, 3)], 5);
+        // Rows 1.. of a batch whose boundary column stops at row 3
+        exported.array.offset = 1;
+        exported.array.length = 4;
+        let starts = unsafe { &mut **exported.array.children.add(2) };
+        starts.length = 3;
+        let err = DocBatch::from_arrow(&exported.as_batch(), "emb", "id", "start").unwrap_err();
+        assert!(
+            matches!(&err, ArrowError::ColumnLength { name, length: 2, rows: 4 } if name == "start"),
+            "{err}"
+        );
+    }
+}
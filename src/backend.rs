@@ -0,0 +1,487 @@
+//! GEMM backend, chosen at compile time and switchable at runtime.
+//!
+//! The f32 similarity GEMMs run on libxsmm with `use-libxsmm` (or its
+//! alias `backend-libxsmm`), else on the system BLAS `sgemm`, or with
+//! `backend-native`, the default feature, on a blocked pure-Rust kernel
+//! that links neither. With both, libxsmm's JIT kernels are used. `libxsmm-dlopen` enables both and
+//! picks at runtime, libxsmm when its shared library loads. The bf16 and
+//! int8 kernels are libxsmm's alone; without it bf16 scores in f32 per the
+//! fallback policy and int8 runs its plain integer loop, on every backend
+//! alike.
+//!
+//! `backend-cblas` adds `cblas_sgemm` from the system BLAS (OpenBLAS, or
+//! the library `MAXSIM_BLAS_LIB` names at build time, e.g. `mkl_rt`),
+//! called with the same column-major operands, and makes it the default
+//! after libxsmm. `set_backend` moves the f32 GEMMs to any backend the
+//! build has, and `compare_backends` times each on a workload and checks
+//! their scores agree.
+//!
+//! libxsmm is only used on x86_64. Elsewhere `use-libxsmm` builds the
+//! native kernel instead, which runs its inner loop on NEON on aarch64,
+//! so the whole API works there with nothing to link. `build.rs` sets the
+//! `libxsmm` and `native_gemm` cfgs the code checks from the features and
+//! the target.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::collection::{Documents, QueryEmbeddings};
+use crate::score::{maxsim_score_batch, ScoreError};
+
+/// Where f32 GEMMs run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// libxsmm JIT kernels, BLAS-style `libxsmm_sgemm` for the rest.
+    Libxsmm,
+    /// The system BLAS `cblas_sgemm`.
+    Cblas,
+    /// The system BLAS `sgemm`.
+    Blas,
+    /// The crate's own blocked kernel.
+    Native,
+}
+
+/// `set_backend`'s choice as its index in `Backend::ALL` plus one; 0 for
+/// the build's default.
+static SELECTED: AtomicU8 = AtomicU8::new(0);
+
+impl Backend {
+    /// Every backend, in the order the build's default is picked.
+    const ALL: [Backend; 4] = [
+        Backend::Libxsmm,
+        Backend::Cblas,
+        Backend::Native,
+        Backend::Blas,
+    ];
+
+    /// The backend f32 GEMMs run on: `set_backend`'s choice, else the
+    /// first available of libxsmm, cblas with `backend-cblas`, native with
+    /// `backend-native`, and the system BLAS.
+    pub fn current() -> Self {
+        selected().unwrap_or_else(|| {
+            Self::ALL
+                .into_iter()
+                .find(|backend| match backend {
+                    Backend::Native => cfg!(native_gemm),
+                    _ => backend.is_available(),
+                })
+                .unwrap_or(Backend::Native)
+        })
+    }
+
+    /// Whether this build has the backend; libxsmm also needs its library
+    /// loaded.
+    pub fn is_available(self) -> bool {
+        match self {
+            #[cfg(libxsmm)]
+            Backend::Libxsmm => crate::libxsmm_link::loaded(),
+            #[cfg(not(libxsmm))]
+            Backend::Libxsmm => false,
+            Backend::Cblas => cfg!(feature = "backend-cblas"),
+            Backend::Blas => cfg!(system_blas),
+            Backend::Native => true,
+        }
+    }
+
+    /// The backends this build has, in `current`'s order of preference.
+    pub fn available() -> Vec<Self> {
+        Self::ALL
+            .into_iter()
+            .filter(|backend| backend.is_available())
+            .collect()
+    }
+}
+
+fn selected() -> Option<Backend> {
+    match SELECTED.load(Ordering::Relaxed) {
+        0 => None,
+        i => Some(Backend::ALL[i as usize - 1]),
+    }
+}
+
+fn select(backend: Option<Backend>) {
+    let i = backend.map_or(0, |backend| {
+        Backend::ALL.iter().position(|&b| b == backend).unwrap() + 1
+    });
+    SELECTED.store(i as u8, Ordering::Relaxed);
+}
+
+/// Run the f32 GEMMs on `backend` from now on, process-wide, or on the
+/// build's default again with `None`. Returns the previous choice. GEMMs
+/// already running finish where they started; the bf16 and int8 kernels
+/// are unaffected.
+pub fn set_backend(backend: Option<Backend>) -> Result<Option<Backend>, ScoreError> {
+    if let Some(backend) = backend.filter(|backend| !backend.is_available()) {
+        return Err(ScoreError::UnavailableBackend { backend });
+    }
+    let previous = selected();
+    select(backend);
+    Ok(previous)
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Libxsmm => "libxsmm",
+            Backend::Cblas => "cblas",
+            Backend::Blas => "blas",
+            Backend::Native => "native",
+        })
+    }
+}
+
+/// How the available backends did on one workload; see
+/// `compare_backends`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackendReport {
+    /// The backend selected when the comparison started, which the others'
+    /// scores are compared to.
+    pub reference: Backend,
+    /// One run per available backend, the reference's first.
+    pub runs: Vec<BackendRun>,
+}
+
+/// One backend's run in a `BackendReport`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackendRun {
+    pub backend: Backend,
+    /// Wall time of one `maxsim_score_batch` call, after a warm-up call.
+    pub time: Duration,
+    /// Largest absolute difference from the reference's scores.
+    pub max_delta: f32,
+}
+
+impl BackendReport {
+    /// Largest `max_delta` of any backend.
+    pub fn max_delta(&self) -> f32 {
+        self.runs
+            .iter()
+            .map(|run| run.max_delta)
+            .fold(0.0, f32::max)
+    }
+
+    /// The run with the shortest time.
+    pub fn fastest(&self) -> Option<&BackendRun> {
+        self.runs.iter().min_by_key(|run| run.time)
+    }
+}
+
+/// Score `query` against `docs` with `maxsim_score_batch` on every
+/// available backend, timing each and comparing its scores to the current
+/// backend's. The backends sum the same products in different orders, so
+/// the deltas are rounding error, a few ulps of the scores.
+///
+/// Switches the process-wide backend while it runs, so other scoring in
+/// the meantime lands on whichever is being measured, and restores the
+/// previous selection before returning.
+pub fn compare_backends<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+) -> Result<BackendReport, ScoreError> {
+    let previous = selected();
+    let reference = Backend::current();
+    let mut backends = Backend::available();
+    backends.retain(|&backend| backend != reference);
+    backends.insert(0, reference);
+
+    let mut runs = Vec::with_capacity(backends.len());
+    let mut reference_scores = Vec::new();
+    let mut run = |backend: Backend| -> Result<(), ScoreError> {
+        select(Some(backend));
+        maxsim_score_batch(query, docs)?;
+        let start = Instant::now();
+        let scores = maxsim_score_batch(query, docs)?;
+        let time = start.elapsed();
+        if runs.is_empty() {
+            reference_scores = scores.clone();
+        }
+        let max_delta = scores
+            .iter()
+            .zip(&reference_scores)
+            .map(|(s, r)| (s - r).abs())
+            .fold(0.0, f32::max);
+        runs.push(BackendRun {
+            backend,
+            time,
+            max_delta,
+        });
+        Ok(())
+    };
+    let result = backends.into_iter().try_for_each(&mut run);
+    select(previous);
+    result?;
+    Ok(BackendReport { reference, runs })
+}
+
+/// Column-major `C = alpha · op(A) · op(B) + beta · C` with the signature
+/// of `blas::sgemm`, on the current backend.
+///
+/// # Safety
+/// As `blas::sgemm`: the slices must hold the matrices their leading
+/// dimensions describe. The native kernel checks the bounds and panics.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn sgemm(
+    transa: u8,
+    transb: u8,
+    m: i32,
+    n: i32,
+    k: i32,
+    alpha: f32,
+    a: &[f32],
+    lda: i32,
+    b: &[f32],
+    ldb: i32,
+    beta: f32,
+    c: &mut [f32],
+    ldc: i32,
+) {
+    match Backend::current() {
+        #[cfg(libxsmm)]
+        Backend::Libxsmm => crate::libxsmm_bindings::xsmm_sgemm(
+            transa,
+            transb,
+            m,
+            n,
+            k,
+            alpha,
+            a.as_ptr(),
+            lda,
+            b.as_ptr(),
+            ldb,
+            beta,
+            c.as_mut_ptr(),
+            ldc,
+        ),
+        #[cfg(feature = "backend-cblas")]
+        Backend::Cblas => {
+            cblas::sgemm(transa, transb, m, n, k, alpha, a, lda, b, ldb, beta, c, ldc)
+        }
+        #[cfg(system_blas)]
+        Backend::Blas => blas::sgemm(transa, transb, m, n, k, alpha, a, lda, b, ldb, beta, c, ldc),
+        _ => native::sgemm(
+            (transa, transb),
+            (m as usize, n as usize, k as usize),
+            alpha,
+            (a, lda as usize),
+            (b, ldb as usize),
+            beta,
+            (c, ldc as usize),
+        ),
+    }
+}
+
+fn is_trans(flag: u8) -> bool {
+    matches!(flag, b'T' | b't' | b'C' | b'c')
+}
+
+#[cfg(feature = "backend-cblas")]
+mod cblas {
+    use libc::c_int;
+
+    const COL_MAJOR: c_int = 102;
+    const NO_TRANS: c_int = 111;
+    const TRANS: c_int = 112;
+
+    extern "C" {
+        fn cblas_sgemm(
+            order: c_int,
+            transa: c_int,
+            transb: c_int,
+            m: c_int,
+            n: c_int,
+            k: c_int,
+            alpha: f32,
+            a: *const f32,
+            lda: c_int,
+            b: *const f32,
+            ldb: c_int,
+            beta: f32,
+            c: *mut f32,
+            ldc: c_int,
+        );
+    }
+
+    /// `super::sgemm` through `cblas_sgemm`, column-major like the rest.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) unsafe fn sgemm(
+        transa: u8,
+        transb: u8,
+        m: i32,
+        n: i32,
+        k: i32,
+        alpha: f32,
+        a: &[f32],
+        lda: i32,
+        b: &[f32],
+        ldb: i32,
+        beta: f32,
+        c: &mut [f32],
+        ldc: i32,
+    ) {
+        let trans = |flag| match super::is_trans(flag) {
+            true => TRANS,
+            false => NO_TRANS,
+        };
+        cblas_sgemm(
+            COL_MAJOR,
+            trans(transa),
+            trans(transb),
+            m,
+            n,
+            k,
+            alpha,
+            a.as_ptr(),
+            lda,
+            b.as_ptr(),
+            ldb,
+            beta,
+            c.as_mut_ptr(),
+            ldc,
+        );
+    }
+}
+
+mod native {
+    use super::is_trans;
+    use std::borrow::Cow;
+
+    /// Rows of op(A) per register block.
+    const MR: usize = 4;
+    /// Columns of op(B) per register block.
+    const NR: usize = 2;
+    /// Accumulator width; the compiler maps it to SIMD lanes.
+    const LANES: usize = 8;
+
+    /// `super::sgemm`. Each entry of C is a dot product of a row of op(A)
+    /// and a column of op(B); both are read as `k` contiguous values,
+    /// copied out first when the transpose flags leave them strided, and
+    /// C is filled in `MR × NR` blocks that share every load.
+    pub(super) fn sgemm(
+        (transa, transb): (u8, u8),
+        (m, n, k): (usize, usize, usize),
+        alpha: f32,
+        (a, lda): (&[f32], usize),
+        (b, ldb): (&[f32], usize),
+        beta: f32,
+        (c, ldc): (&mut [f32], usize),
+    ) {
+        if m == 0 || n == 0 {
+            return;
+        }
+        // Row i of op(A) is column i of A when transposed
+        let (a, lda) = lines(a, lda, m, k, is_trans(transa));
+        // Column j of op(B) is column j of B unless transposed
+        let (b, ldb) = lines(b, ldb, n, k, !is_trans(transb));
+        let a_row = |i: usize| &a[i * lda..i * lda + k];
+        let b_col = |j: usize| &b[j * ldb..j * ldb + k];
+        let mut store = |i: usize, j: usize, dot: f32| {
+            let dst = &mut c[i + j * ldc];
+            // beta = 0 overwrites, NaNs in C included, as BLAS does
+            *dst = match beta {
+                0.0 => alpha * dot,
+                _ => alpha * dot + beta * *dst,
+            };
+        };
+
+        for j0 in (0..n).step_by(NR) {
+            for i0 in (0..m).step_by(MR) {
+                if i0 + MR <= m && j0 + NR <= n {
+                    let rows = std::array::from_fn(|r| a_row(i0 + r));
+                    let cols = std::array::from_fn(|s| b_col(j0 + s));
+                    let dots = block(rows, cols, k);
+                    for (r, row) in dots.iter().enumerate() {
+                        for (s, &dot) in row.iter().enumerate() {
+                            store(i0 + r, j0 + s, dot);
+                        }
+                    }
+                } else {
+                    for j in j0..(j0 + NR).min(n) {
+                        for i in i0..(i0 + MR).min(m) {
+                            store(i, j, dot(a_row(i), b_col(j)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// `count` lines of `k` values from the column-major `src` with leading
+    /// dimension `ld`: its columns when `columns`, else its rows, copied
+    /// out. Returns them with their stride.
+    fn lines(
+        src: &[f32],
+        ld: usize,
+        count: usize,
+        k: usize,
+        columns: bool,
+    ) -> (Cow<'_, [f32]>, usize) {
+        if columns {
+            return (Cow::Borrowed(src), ld);
+        }
+        let mut out = vec![0.0; count * k];
+        for (i, line) in out.chunks_exact_mut(k.max(1)).enumerate().take(count) {
+            for (p, v) in line.iter_mut().enumerate() {
+                *v = src[i + p * ld];
+            }
+        }
+        (Cow::Owned(out), k)
+    }
+
+    /// The `MR × NR` dot products of `rows` with `cols`.
+    fn block(rows: [&[f32]; MR], cols: [&[f32]; NR], k: usize) -> [[f32; NR]; MR] {
+        let mut acc = [[[0.0f32; LANES]; NR]; MR];
+        let full = k - k % LANES;
+        for p in (0..full).step_by(LANES) {
+            let b: [&[f32]; NR] = std::array::from_fn(|s| &cols[s][p..p + LANES]);
+            for (acc, row) in acc.iter_mut().zip(rows) {
+                let a = &row[p..p + LANES];
+                for (acc, b) in acc.iter_mut().zip(b) {
+                    fma_lanes(acc, a, b);
+                }
+            }
+        }
+        std::array::from_fn(|r| {
+            std::array::from_fn(|s| {
+                let tail = dot(&rows[r][full..k], &cols[s][full..k]);
+                acc[r][s].iter().sum::<f32>() + tail
+            })
+        })
+    }
+
+    fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let mut acc = [0.0f32; LANES];
+        let (a_full, a_tail) = a.split_at(a.len() - a.len() % LANES);
+        let b_tail = &b[a_full.len()..a.len()];
+        for (a, b) in a_full.chunks_exact(LANES).zip(b.chunks_exact(LANES)) {
+            fma_lanes(&mut acc, a, b);
+        }
+        let tail: f32 = a_tail.iter().zip(b_tail).map(|(a, b)| a * b).sum();
+        acc.iter().sum::<f32>() + tail
+    }
+
+    /// `acc[l] += a[l] · b[l]` for the first `LANES` values of `a` and `b`.
+    #[inline(always)]
+    fn fma_lanes(acc: &mut [f32; LANES], a: &[f32], b: &[f32]) {
+        let (a, b) = (&a[..LANES], &b[..LANES]);
+        #[cfg(target_arch = "aarch64")]
+        // NEON is part of the aarch64 baseline: 4-lane fused multiply-adds
+        unsafe {
+            use std::arch::aarch64::{vfmaq_f32, vld1q_f32, vst1q_f32};
+
+            let (acc, a, b) = (acc.as_mut_ptr(), a.as_ptr(), b.as_ptr());
+            for half in (0..LANES).step_by(4) {
+                let sum = vfmaq_f32(
+                    vld1q_f32(acc.add(half)),
+                    vld1q_f32(a.add(half)),
+                    vld1q_f32(b.add(half)),
+                );
+                vst1q_f32(acc.add(half), sum);
+            }
+        }
+        #[cfg(not(target_arch = "aarch64"))]
+        for l in 0..LANES {
+            acc[l] += a[l] * b[l];
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! Micro-benchmarks of the scoring kernels, returned as numbers rather
+//! than printed so regression tests can bound them.
+//!
+//! Each benchmark sets up its kernels (JIT dispatch included) and runs
+//! `WARMUP` untimed iterations before sampling, so code generation, page
+//! faults and cold caches stay out of the timings. Samples come from
+//! `Instant`, which is monotonic, and every input and output goes through
+//! `black_box`, so no iteration can be optimized away. Inputs are unit-norm
+//! rows of fixed pseudo-random values, the same on every run.
+//!
+//! Times still move with load and frequency scaling: compare them to a
+//! baseline from the same machine, with generous relative bounds.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use crate::backend::Backend;
+use crate::collection::{Bf16DocCollection, DocCollection, QueryEmbeddings};
+use crate::norm::normalize_rows_inplace;
+use crate::score::{ScoreError, SimilarityGemm};
+use crate::scorer::{LoopOrder, Precision, Scorer, ScorerConfig};
+
+/// Untimed iterations before sampling.
+pub const WARMUP: usize = 3;
+
+/// Timed `score_batch` calls `bench_maxsim` makes.
+pub const MAXSIM_ITERS: usize = 25;
+
+/// Back-to-back kernel calls per iteration of `bench_kernel_call`.
+pub const KERNEL_CALLS: usize = 1000;
+
+/// A similarity GEMM: `q_len` query tokens against `d_len` document
+/// tokens of `dim` values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GemmShape {
+    pub q_len: usize,
+    pub d_len: usize,
+    pub dim: usize,
+}
+
+impl GemmShape {
+    /// `2 · q_len · d_len · dim`.
+    pub fn flops(&self) -> u64 {
+        2 * (self.q_len * self.d_len * self.dim) as u64
+    }
+}
+
+/// Timings of one benchmark.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchResult {
+    /// Timed iterations.
+    pub iters: usize,
+    pub median_ns: u64,
+    /// 99th percentile by nearest rank: the slowest sample under 100.
+    pub p99_ns: u64,
+    /// Similarity GEMM FLOPs per iteration over `median_ns`, in 10^9 per
+    /// second; 0 when the median rounds to 0 ns.
+    pub gflops: f64,
+}
+
+/// Time the similarity GEMM of `shape` in `dtype` over `iters` iterations
+/// (at least one): the f32 one on the current `Backend`, the bf16 one on
+/// libxsmm's JIT kernel with the query and documents already converted.
+/// `ScoreError::Unsupported` for bf16 without native bf16 dot products,
+/// as `Fallback::Error` would report it.
+pub fn bench_gemm(
+    shape: GemmShape,
+    dtype: Precision,
+    iters: usize,
+) -> Result<BenchResult, ScoreError> {
+    let GemmShape { q_len, d_len, dim } = shape;
+    match dtype {
+        Precision::F32 => {
+            let query = unit_rows(q_len, dim, 1);
+            let doc = unit_rows(d_len, dim, 2);
+            let mut sims = vec![0.0f32; q_len * d_len];
+            let gemm = SimilarityGemm::new(q_len, d_len, dim);
+            Ok(measure(iters, shape.flops(), || {
+                gemm.run(black_box(&query), black_box(&doc), black_box(&mut sims));
+            }))
+        }
+        #[cfg(libxsmm)]
+        Precision::Bf16 => {
+            use crate::bf16::convert_f32_to_bf16;
+            use crate::libxsmm_bindings::{Bf16KernelConfig, JitKernel, LibxsmmContext};
+
+            let unsupported = ScoreError::Unsupported { precision: dtype };
+            crate::LIBXSMM_CTX.get_or_init(LibxsmmContext::acquire);
+            let config = Bf16KernelConfig::auto();
+            if !config.arch.supports_bf16_dot() {
+                return Err(unsupported);
+            }
+            // Documents as A, `[d_len, k]`, as the scorer runs them
+            let kernel =
+                JitKernel::bf16_gemm_with_config(d_len as i32, q_len as i32, dim as i32, &config)
+                    .map_err(|_| unsupported)?;
+            let k = config.padded_k(dim as i32) as usize;
+            let to_bf16 = |rows: Vec<f32>| {
+                let mut out = vec![0u16; rows.len()];
+                convert_f32_to_bf16(&rows, &mut out);
+                out
+            };
+            let doc = to_bf16(unit_rows(d_len, k, 2));
+            let query = to_bf16(unit_rows(q_len, k, 1));
+            let mut sims = vec![0.0f32; q_len * d_len];
+            Ok(measure(iters, shape.flops(), || {
+                kernel
+                    .call_bf16(black_box(&doc), black_box(&query), black_box(&mut sims))
+                    .expect("operands sized from the kernel's shape");
+            }))
+        }
+        #[cfg(not(libxsmm))]
+        Precision::Bf16 => Err(ScoreError::Unsupported { precision: dtype }),
+    }
+}
+
+/// Time `iters` (at least one) iterations of `KERNEL_CALLS` calls of the
+/// f32 JIT kernel of `shape`, as the fused path runs it (query as A, the
+/// document tile as B): each through `JitKernel::call`, which builds a
+/// fresh param block, or with `reuse` through one `KernelCall` that only
+/// rewrites the B and C pointers. The difference between the two is the
+/// per-call overhead. `ScoreError::UnavailableBackend` without libxsmm or
+/// when it cannot JIT the shape.
+pub fn bench_kernel_call(
+    shape: GemmShape,
+    reuse: bool,
+    iters: usize,
+) -> Result<BenchResult, ScoreError> {
+    let unavailable = ScoreError::UnavailableBackend {
+        backend: Backend::Libxsmm,
+    };
+    #[cfg(libxsmm)]
+    {
+        use libc::c_void;
+
+        use crate::libxsmm_bindings::{JitKernel, KernelCall, LibxsmmContext, Transpose};
+
+        let GemmShape { q_len, d_len, dim } = shape;
+        crate::LIBXSMM_CTX.get_or_init(LibxsmmContext::acquire);
+        let (m, n, k) = (q_len as i32, d_len as i32, dim as i32);
+        let kernel = JitKernel::f32_gemm(m, n, k, Transpose::A).map_err(|_| unavailable)?;
+        let query = unit_rows(q_len, dim, 1);
+        let doc = unit_rows(d_len, dim, 2);
+        let mut sims = vec![0.0f32; q_len * d_len];
+        kernel
+            .call_f32(&query, &doc, &mut sims)
+            .expect("operands sized from the kernel's shape");
+        let (a, b) = (
+            query.as_ptr() as *const c_void,
+            doc.as_ptr() as *const c_void,
+        );
+        let c = sims.as_mut_ptr() as *mut c_void;
+        let flops = shape.flops() * KERNEL_CALLS as u64;
+        // Checked by the call above; the loops only repeat it
+        Ok(if reuse {
+            let mut call = KernelCall::new(&kernel);
+            call.set_a(a);
+            measure(iters, flops, || {
+                for _ in 0..KERNEL_CALLS {
+                    call.set_b(black_box(b));
+                    call.set_c(black_box(c));
+                    unsafe { call.fire() };
+                }
+            })
+        } else {
+            measure(iters, flops, || {
+                for _ in 0..KERNEL_CALLS {
+                    unsafe { kernel.call(black_box(a), black_box(b), black_box(c)) };
+                }
+            })
+        })
+    }
+    #[cfg(not(libxsmm))]
+    {
+        let _ = (shape, reuse, iters);
+        Err(unavailable)
+    }
+}
+
+/// Time `MAXSIM_ITERS` `Scorer::score_batch` calls of a `q_len`-token
+/// query against `n_docs` documents of `d_len` tokens, under `config`.
+/// Scorers that resolve to bf16 score a `Bf16DocCollection` of the same
+/// documents with `score_batch_bf16`. GFLOPs count every document's
+/// similarity GEMM.
+pub fn bench_maxsim(
+    q_len: usize,
+    d_len: usize,
+    dim: usize,
+    n_docs: usize,
+    config: ScorerConfig,
+) -> Result<BenchResult, ScoreError> {
+    let scorer = Scorer::new(config)?;
+    let query = QueryEmbeddings::new(unit_rows(q_len, dim, 1), q_len, dim)?;
+    let offsets = (0..=n_docs).map(|i| i * d_len).collect();
+    let docs = DocCollection::new(unit_rows(n_docs * d_len, dim, 2), offsets, dim)?;
+    let flops = GemmShape { q_len, d_len, dim }.flops() * n_docs as u64;
+    match scorer.precision() {
+        Precision::F32 => {
+            scorer.score_batch(&query, &docs)?;
+            Ok(measure(MAXSIM_ITERS, flops, || {
+                black_box(
+                    scorer
+                        .score_batch(black_box(&query), black_box(&docs))
+                        .expect("same call succeeded above"),
+                );
+            }))
+        }
+        Precision::Bf16 => {
+            let docs = Bf16DocCollection::from_documents(&docs);
+            scorer.score_batch_bf16(&query, &docs)?;
+            Ok(measure(MAXSIM_ITERS, flops, || {
+                black_box(
+                    scorer
+                        .score_batch_bf16(black_box(&query), black_box(&docs))
+                        .expect("same call succeeded above"),
+                );
+            }))
+        }
+    }
+}
+
+/// `bench_maxsim` of f32 documents with stacking (see
+/// `ScorerConfig::stack_threshold`) covering `d_len` if `stacked`, else
+/// off, for comparing stacked against per-document GEMMs on the same
+/// batch, e.g. 10k documents of 8 tokens.
+pub fn bench_stacked(
+    q_len: usize,
+    d_len: usize,
+    dim: usize,
+    n_docs: usize,
+    stacked: bool,
+) -> Result<BenchResult, ScoreError> {
+    let threshold = if stacked { d_len.max(1) } else { 0 };
+    let config = ScorerConfig::default().with_stack_threshold(threshold);
+    bench_maxsim(q_len, d_len, dim, n_docs, config)
+}
+
+/// Both `LoopOrder`s timed on one batch shape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoopOrderPoint {
+    pub d_len: usize,
+    pub n_docs: usize,
+    pub query_major: BenchResult,
+    pub doc_major: BenchResult,
+}
+
+/// `bench_maxsim` of f32 documents under `LoopOrder::QueryMajor` and
+/// `LoopOrder::DocMajor`, at each of `d_lens` with about `tokens` document
+/// tokens per batch (at least one document), so every point does the same
+/// work. The orders only differ for `q_len` over `QUERY_BLOCK`.
+/// `measured_crossover` reads off where they cross.
+pub fn bench_loop_order(
+    q_len: usize,
+    dim: usize,
+    tokens: usize,
+    d_lens: &[usize],
+) -> Result<Vec<LoopOrderPoint>, ScoreError> {
+    let bench = |d_len: usize, n_docs, order| {
+        let config = ScorerConfig::default().with_loop_order(order);
+        bench_maxsim(q_len, d_len, dim, n_docs, config)
+    };
+    d_lens
+        .iter()
+        .map(|&d_len| {
+            let n_docs = (tokens / d_len.max(1)).max(1);
+            Ok(LoopOrderPoint {
+                d_len,
+                n_docs,
+                query_major: bench(d_len, n_docs, LoopOrder::QueryMajor)?,
+                doc_major: bench(d_len, n_docs, LoopOrder::DocMajor)?,
+            })
+        })
+        .collect()
+}
+
+/// The longest `d_len` of `points` (ascending by `d_len`) that ran faster
+/// query-major before doc-major first won, as `set_loop_order_crossover`
+/// takes it; `None` if doc-major won from the first point.
+pub fn measured_crossover(points: &[LoopOrderPoint]) -> Option<usize> {
+    points
+        .iter()
+        .take_while(|p| p.query_major.median_ns < p.doc_major.median_ns)
+        .last()
+        .map(|p| p.d_len)
+}
+
+/// Warm up, then time `iters` (at least one) runs of `run`.
+fn measure(iters: usize, flops: u64, mut run: impl FnMut()) -> BenchResult {
+    for _ in 0..WARMUP {
+        run();
+    }
+    let iters = iters.max(1);
+    let mut samples: Vec<u64> = (0..iters)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed().as_nanos() as u64
+        })
+        .collect();
+    samples.sort_unstable();
+    let median_ns = samples[iters / 2];
+    let p99_ns = samples[(iters * 99).div_ceil(100) - 1];
+    let gflops = match median_ns {
+        0 => 0.0,
+        ns => flops as f64 / ns as f64,
+    };
+    BenchResult {
+        iters,
+        median_ns,
+        p99_ns,
+        gflops,
+    }
+}
+
+/// `rows` unit-norm rows of `dim` values, fixed by `seed`.
+pub(crate) fn unit_rows(rows: usize, dim: usize, seed: u64) -> Vec<f32> {
+    // splitmix64
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let mut data: Vec<f32> = (0..rows * dim)
+        .map(|_| {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            // Top 24 bits as a uniform value in [-1, 1)
+            (z >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+        })
+        .collect();
+    normalize_rows_inplace(&mut data, dim);
+    data
+}
+
+/// Unit-norm documents of the given token `lengths`, fixed by `seed`.
+#[cfg(test)]
+pub(crate) fn unit_docs(lengths: &[usize], dim: usize, seed: u64) -> DocCollection {
+    let tokens = lengths.iter().sum();
+    DocCollection::from_lengths(unit_rows(tokens, dim, seed), lengths, dim)
+        .expect("lengths are nonzero")
+}
+
+/// Unit-norm documents of the given token `lengths` whose tokens scatter
+/// around `clusters` centres, three centres per document, fixed by `seed`.
+#[cfg(test)]
+pub(crate) fn clustered_docs(
+    lengths: &[usize],
+    dim: usize,
+    clusters: usize,
+    seed: u64,
+) -> DocCollection {
+    let centres = unit_rows(clusters, dim, seed);
+    let tokens = lengths.iter().sum();
+    let mut data = unit_rows(tokens, dim, seed + 1);
+    let mut rows = data.chunks_exact_mut(dim);
+    for (i, &len) in lengths.iter().enumerate() {
+        for (t, row) in rows.by_ref().take(len).enumerate() {
+            let c = (3 * i + t % 3) % clusters;
+            for (x, &centre) in row.iter_mut().zip(&centres[c * dim..(c + 1) * dim]) {
+                *x = centre + 0.3 * *x;
+            }
+        }
+    }
+    normalize_rows_inplace(&mut data, dim);
+    DocCollection::from_lengths(data, lengths, dim).expect("lengths are nonzero")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_sane(result: BenchResult, iters: usize) {
+        assert_eq!(result.iters, iters);
+        assert!(result.median_ns > 0, "{result:?}");
+        assert!(result.p99_ns >= result.median_ns, "{result:?}");
+        assert!(
+            result.gflops.is_finite() && result.gflops > 0.0,
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn gemm_and_maxsim_report_positive_finite_timings() {
+        let shape = GemmShape {
+            q_len: 32,
+            d_len: 40,
+            dim: 64,
+        };
+        assert_sane(bench_gemm(shape, Precision::F32, 5).unwrap(), 5);
+        assert_sane(bench_gemm(shape, Precision::F32, 0).unwrap(), 1);
+        match bench_gemm(shape, Precision::Bf16, 5) {
+            Ok(result) => assert_sane(result, 5),
+            Err(e) => assert!(matches!(e, ScoreError::Unsupported { .. }), "{e:?}"),
+        }
+
+        let config = ScorerConfig::default();
+        assert_sane(bench_maxsim(32, 40, 32, 10, config).unwrap(), MAXSIM_ITERS);
+        for stacked in [false, true] {
+            assert_sane(bench_stacked(32, 8, 32, 30, stacked).unwrap(), MAXSIM_ITERS);
+        }
+    }
+
+    #[test]
+    fn loop_order_times_both_orders_at_every_length() {
+        let d_lens = [4, 16, 300];
+        let points = bench_loop_order(40, 16, 200, &d_lens).unwrap();
+        assert_eq!(points.len(), d_lens.len());
+        for (point, &d_len) in points.iter().zip(&d_lens) {
+            assert_eq!(point.d_len, d_len);
+            assert_eq!(point.n_docs, (200 / d_len).max(1));
+            assert_sane(point.query_major, MAXSIM_ITERS);
+            assert_sane(point.doc_major, MAXSIM_ITERS);
+        }
+        if let Some(crossover) = measured_crossover(&points) {
+            assert!(d_lens.contains(&crossover));
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Bulk f32 ↔ bf16 conversion.
+//!
+//! bf16 values are raw `u16` bit patterns (the upper half of an f32).
+//! f32 → bf16 rounds to nearest-even and quiets NaNs, matching libxsmm's
+//! `libxsmm_rne_convert_fp32_bf16`. bf16 → f32 is exact.
+//!
+//! Runtime dispatch picks AVX-512 BF16 (`VCVTNE2PS2BF16`), then AVX2, then a
+//! scalar loop. The AVX-512 BF16 instruction treats f32 subnormals as zero;
+//! the AVX2 and scalar paths round them like any other value.
+//!
+//! With the `half` feature the bulk conversions also take `half::bf16`
+//! slices, which share the bits. `half::bf16::from_f32` rounds and quiets
+//! NaNs as the scalar and AVX2 paths do, so it differs from
+//! `convert_f32_to_bf16` only on f32 subnormals under AVX-512 BF16.
+//! `half::bf16::to_f32` quiets signalling NaNs where `convert_bf16_to_f32`
+//! keeps the bits.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[cfg(feature = "half")]
+use half::slice::HalfFloatSliceExt;
+
+/// Round one f32 to bf16 (nearest-even, NaNs quieted).
+#[inline]
+pub fn f32_to_bf16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    if x.is_nan() {
+        return ((bits | 0x0040_0000) >> 16) as u16;
+    }
+    let lsb = (bits >> 16) & 1;
+    (bits.wrapping_add(0x7fff + lsb) >> 16) as u16
+}
+
+/// Widen one bf16 to f32 (exact).
+#[inline]
+pub fn bf16_to_f32(x: u16) -> f32 {
+    f32::from_bits((x as u32) << 16)
+}
+
+/// Convert `src` to bf16 into `dst`. Panics if the lengths differ.
+pub fn convert_f32_to_bf16(src: &[f32], dst: &mut [u16]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "f32→bf16: source and destination lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512bf16") && is_x86_feature_detected!("avx512f") {
+            return unsafe { f32_to_bf16_avx512(src, dst) };
+        }
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { f32_to_bf16_avx2(src, dst) };
+        }
+    }
+
+    f32_to_bf16_scalar(src, dst);
+}
+
+/// Convert bf16 `src` to f32 into `dst`. Panics if the lengths differ.
+pub fn convert_bf16_to_f32(src: &[u16], dst: &mut [f32]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "bf16→f32: source and destination lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512f") {
+            return unsafe { bf16_to_f32_avx512(src, dst) };
+        }
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { bf16_to_f32_avx2(src, dst) };
+        }
+    }
+
+    bf16_to_f32_scalar(src, dst);
+}
+
+/// `convert_f32_to_bf16` into `half::bf16` values.
+#[cfg(feature = "half")]
+pub fn convert_f32_to_half_bf16(src: &[f32], dst: &mut [half::bf16]) {
+    convert_f32_to_bf16(src, dst.reinterpret_cast_mut());
+}
+
+/// `convert_bf16_to_f32` from `half::bf16` values.
+#[cfg(feature = "half")]
+pub fn convert_half_bf16_to_f32(src: &[half::bf16], dst: &mut [f32]) {
+    convert_bf16_to_f32(src.reinterpret_cast(), dst);
+}
+
+fn f32_to_bf16_scalar(src: &[f32], dst: &mut [u16]) {
+    for (d, &s) in dst.iter_mut().zip(src) {
+        *d = f32_to_bf16(s);
+    }
+}
+
+fn bf16_to_f32_scalar(src: &[u16], dst: &mut [f32]) {
+    for (d, &s) in dst.iter_mut().zip(src) {
+        *d = bf16_to_f32(s);
+    }
+}
+
+/// AVX-512 BF16: 32 floats per iteration via VCVTNE2PS2BF16.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx512bf16")]
+unsafe fn f32_to_bf16_avx512(src: &[f32], dst: &mut [u16]) {
+    let n = src.len();
+    let mut i = 0;
+
+    while i + 32 <= n {
+        let lo = _mm512_loadu_ps(src.as_ptr().add(i));
+        let hi = _mm512_loadu_ps(src.as_ptr().add(i + 16));
+        // First operand fills the upper 16 lanes
+        let packed: __m512i = std::mem::transmute(_mm512_cvtne2ps_pbh(hi, lo));
+        _mm512_storeu_si512(dst.as_mut_ptr().add(i) as *mut _, packed);
+        i += 32;
+    }
+
+    f32_to_bf16_scalar(&src[i..], &mut dst[i..]);
+}
+
+/// AVX2: 16 floats per iteration, rounding done with integer adds.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn f32_to_bf16_avx2(src: &[f32], dst: &mut [u16]) {
+    #[inline(always)]
+    unsafe fn round8(x: __m256) -> __m256i {
+        let bits = _mm256_castps_si256(x);
+        let lsb = _mm256_and_si256(_mm256_srli_epi32(bits, 16), _mm256_set1_epi32(1));
+        let rounded = _mm256_add_epi32(bits, _mm256_add_epi32(lsb, _mm256_set1_epi32(0x7fff)));
+        let quiet = _mm256_or_si256(bits, _mm256_set1_epi32(0x0040_0000));
+        let nan = _mm256_castps_si256(_mm256_cmp_ps(x, x, _CMP_UNORD_Q));
+        _mm256_srli_epi32(_mm256_blendv_epi8(rounded, quiet, nan), 16)
+    }
+
+    let n = src.len();
+    let mut i = 0;
+
+    while i + 16 <= n {
+        let lo = round8(_mm256_loadu_ps(src.as_ptr().add(i)));
+        let hi = round8(_mm256_loadu_ps(src.as_ptr().add(i + 8)));
+        // packus interleaves 128-bit lanes: [lo0-3, hi0-3, lo4-7, hi4-7]
+        let packed = _mm256_permute4x64_epi64(_mm256_packus_epi32(lo, hi), 0b11_01_10_00);
+        _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, packed);
+        i += 16;
+    }
+
+    f32_to_bf16_scalar(&src[i..], &mut dst[i..]);
+}
+
+/// AVX-512F: 16 values per iteration (zero-extend, shift into the high half).
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+unsafe fn bf16_to_f32_avx512(src: &[u16], dst: &mut [f32]) {
+    let n = src.len();
+    let mut i = 0;
+
+    while i + 16 <= n {
+        let h = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+        let w = _mm512_slli_epi32(_mm512_cvtepu16_epi32(h), 16);
+        _mm512_storeu_ps(dst.as_mut_ptr().add(i), _mm512_castsi512_ps(w));
+        i += 16;
+    }
+
+    bf16_to_f32_scalar(&src[i..], &mut dst[i..]);
+}
+
+/// AVX2: 8 values per iteration.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn bf16_to_f32_avx2(src: &[u16], dst: &mut [f32]) {
+    let n = src.len();
+    let mut i = 0;
+
+    while i + 8 <= n {
+        let h = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+        let w = _mm256_slli_epi32(_mm256_cvtepu16_epi32(h), 16);
+        _mm256_storeu_ps(dst.as_mut_ptr().add(i), _mm256_castsi256_ps(w));
+        i += 8;
+    }
+
+    bf16_to_f32_scalar(&src[i..], &mut dst[i..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// f32 bit patterns covering ties, subnormals, infinities, NaNs and the
+    /// overflow boundary, padded past every vector width with a sweep.
+    fn inputs() -> Vec<f32> {
+        let special = [
+            0x3f80_8000, // 1 + 2^-8: tie, rounds down to even
+            0x3f81_8000, // tie, rounds up to even
+            0x3f80_8001, // just past the tie
+            0x0001_8000, // subnormal tie
+            0x0000_0001, // smallest subnormal
+            0x807f_ffff, // largest negative subnormal
+            0x7f7f_ffff, // f32::MAX rounds to infinity
+            0x7f80_0000,
+            0xff80_0000,
+            0x7f80_0001, // signalling NaN with only low payload bits
+            0xffc0_0000,
+            0x8000_0000,
+        ];
+        let sweep = (0..4000u32).map(|i| i.wrapping_mul(0x9e37_79b9));
+        special
+            .into_iter()
+            .chain(sweep)
+            .map(f32::from_bits)
+            .collect()
+    }
+
+    /// What a vector path must produce: the scalar rounding, except that
+    /// AVX-512 BF16 flushes f32 subnormals to zero.
+    fn expected(x: f32, flushes: bool) -> u16 {
+        if flushes && x.is_subnormal() {
+            return (x.to_bits() >> 16) as u16 & 0x8000;
+        }
+        f32_to_bf16(x)
+    }
+
+    /// Whether `convert_f32_to_bf16` runs the AVX-512 BF16 path here.
+    fn flushes_subnormals() -> bool {
+        #[cfg(target_arch = "x86_64")]
+        return is_x86_feature_detected!("avx512bf16") && is_x86_feature_detected!("avx512f");
+        #[cfg(not(target_arch = "x86_64"))]
+        false
+    }
+
+    #[test]
+    fn scalar_rounds_to_nearest_even() {
+        let round = |bits: u32| f32_to_bf16(f32::from_bits(bits));
+        assert_eq!(round(0x3f80_8000), 0x3f80);
+        assert_eq!(round(0x3f81_8000), 0x3f82);
+        assert_eq!(round(0x3f80_8001), 0x3f81);
+        assert_eq!(round(0x0001_8000), 0x0002);
+        assert_eq!(round(0x0000_0001), 0x0000);
+        assert_eq!(round(0x7f7f_ffff), 0x7f80);
+        assert_eq!(round(0xff80_0000), 0xff80);
+        // NaNs stay NaNs even when the payload sits below bit 16
+        assert!(bf16_to_f32(round(0x7f80_0001)).is_nan());
+        assert!(bf16_to_f32(round(0xffc0_0000)).is_nan());
+    }
+
+    #[test]
+    fn bulk_paths_match_scalar() {
+        let src = inputs();
+        let mut dst = vec![0u16; src.len()];
+        let flushes = flushes_subnormals();
+        convert_f32_to_bf16(&src, &mut dst);
+        for (&x, &y) in src.iter().zip(&dst) {
+            assert_eq!(y, expected(x, flushes), "{:#010x}", x.to_bits());
+        }
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            unsafe { f32_to_bf16_avx2(&src, &mut dst) };
+            for (&x, &y) in src.iter().zip(&dst) {
+                assert_eq!(y, f32_to_bf16(x), "avx2 {:#010x}", x.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn representable_values_round_trip_exactly() {
+        let all: Vec<u16> = (0..=u16::MAX).collect();
+        let mut widened = vec![0.0f32; all.len()];
+        convert_bf16_to_f32(&all, &mut widened);
+        let mut back = vec![0u16; all.len()];
+        convert_f32_to_bf16(&widened, &mut back);
+        let flushes = flushes_subnormals();
+        for ((&bits, &x), &y) in all.iter().zip(&widened).zip(&back) {
+            assert_eq!(x.to_bits(), bf16_to_f32(bits).to_bits());
+            if x.is_nan() {
+                assert_eq!(y, bits | 0x0040, "{bits:#06x}");
+            } else {
+                assert_eq!(y, expected(x, flushes), "{bits:#06x}");
+                assert!(flushes || y == bits, "{bits:#06x}");
+            }
+        }
+    }
+
+    #[cfg(feature = "half")]
+    #[test]
+    fn half_agrees_with_the_bulk_paths() {
+        let src = inputs();
+        let mut dst = vec![half::bf16::ZERO; src.len()];
+        let flushes = flushes_subnormals();
+        convert_f32_to_half_bf16(&src, &mut dst);
+        for (&x, &y) in src.iter().zip(&dst) {
+            let want = half::bf16::from_f32(x).to_bits();
+            if flushes && x.is_subnormal() {
+                assert_eq!(y.to_bits(), expected(x, flushes), "{:#010x}", x.to_bits());
+            } else {
+                assert_eq!(y.to_bits(), want, "{:#010x}", x.to_bits());
+            }
+        }
+        let all: Vec<half::bf16> = (0..=u16::MAX).map(half::bf16::from_bits).collect();
+        let mut widened = vec![0.0f32; all.len()];
+        convert_half_bf16_to_f32(&all, &mut widened);
+        for (&h, &x) in all.iter().zip(&widened) {
+            let quiet = if x.is_nan() { 0x0040_0000 } else { 0 };
+            assert_eq!(
+                x.to_bits() | quiet,
+                h.to_f32().to_bits(),
+                "{:#06x}",
+                h.to_bits()
+            );
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! C ABI for opening stores and scoring against them (feature `capi`).
+//!
+//! The declarations are in `include/maxsim.h`. A store is an opaque
+//! `maxsim_store *` from `maxsim_store_open`, released with
+//! `maxsim_store_free`; it holds a `DocStore` (any dtype `DocStore::save`
+//! writes) and a scorer for its similarity, and may be searched from
+//! several threads at once.
+//!
+//! Every fallible function returns a `MAXSIM_*` status code, 0 on
+//! success, and writes its results through out-pointers. On failure the
+//! calling thread's `maxsim_last_error_message` describes the error until
+//! its next failing call. A panic inside the library is caught at the
+//! boundary and reported as `MAXSIM_ERR_PANIC`; that needs unwinding, so
+//! build the C library with `--profile capi` rather than `--release`,
+//! whose `panic = "abort"` would end the process instead (such builds fail
+//! to compile), and with `--no-default-features` to run on the system BLAS
+//! rather than the native kernel.
+
+// Cargo hands build scripts the target's default panic strategy, not the
+// profile's, so the check lives here
+#[cfg(panic = "abort")]
+compile_error!(
+    "the capi feature needs panic = \"unwind\" to catch panics at the C boundary; \
+     build with `--profile capi` instead of `--release`"
+);
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+use crate::collection::QueryEmbeddings;
+use crate::docstore::DocStore;
+use crate::score::ScoreError;
+use crate::scorer::{Scorer, ScorerConfig};
+use crate::topk::TopK;
+
+/// Success.
+pub const MAXSIM_OK: i32 = 0;
+/// A null pointer, a buffer too small, or a bad path.
+pub const MAXSIM_ERR_INVALID_ARGUMENT: i32 = 1;
+/// The store file could not be read or is malformed.
+pub const MAXSIM_ERR_STORE: i32 = 2;
+/// Scoring rejected the query, e.g. a dim the store does not have.
+pub const MAXSIM_ERR_SCORE: i32 = 3;
+/// The library panicked; the message says where.
+pub const MAXSIM_ERR_PANIC: i32 = 4;
+
+/// `maxsim_store`: a loaded store and the scorer for it.
+pub struct MaxsimStore {
+    store: DocStore,
+    scorer: Scorer,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A failed call's status code and message.
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn invalid(message: &str) -> Self {
+        Failure {
+            code: MAXSIM_ERR_INVALID_ARGUMENT,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<ScoreError> for Failure {
+    fn from(err: ScoreError) -> Self {
+        Failure {
+            code: MAXSIM_ERR_SCORE,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Run `f`, turning its error or panic into a status code and the
+/// thread's last error message.
+fn guard(f: impl FnOnce() -> Result<(), Failure>) -> i32 {
+    let failure = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return MAXSIM_OK,
+        Ok(Err(failure)) => failure,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Failure {
+                code: MAXSIM_ERR_PANIC,
+                message: format!("panic: {}", message),
+            }
+        }
+    };
+    // Interior NULs cannot be represented; drop them
+    let message = CString::new(failure.message.replace('\0', "")).expect("NULs removed");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    failure.code
+}
+
+/// The query of `q_len` rows of `dim` floats at `query`, padded to the
+/// store's dim.
+///
+/// # Safety
+/// `query` must point to `q_len * dim` readable floats.
+unsafe fn read_query(
+    store: &MaxsimStore,
+    query: *const f32,
+    q_len: usize,
+    dim: usize,
+) -> Result<QueryEmbeddings, Failure> {
+    if query.is_null() {
+        return Err(Failure::invalid("query is null"));
+    }
+    let values = q_len
+        .checked_mul(dim)
+        .ok_or_else(|| Failure::invalid("query size overflows"))?;
+    let data = std::slice::from_raw_parts(query, values);
+    let query = QueryEmbeddings::new(data, q_len, dim)?;
+    Ok(store.store.pad_query(query)?)
+}
+
+/// Open the store `DocStore::save` wrote at `path` (a NUL-terminated UTF-8
+/// path) into `*out`.
+///
+/// # Safety
+/// `path` must be a valid C string and `out` writable.
+#[no_mangle]
+pub unsafe extern "C" fn maxsim_store_open(path: *const c_char, out: *mut *mut MaxsimStore) -> i32 {
+    guard(|| {
+        if path.is_null() || out.is_null() {
+            return Err(Failure::invalid("path or out is null"));
+        }
+        let path = CStr::from_ptr(path)
+            .to_str()
+            .map_err(|_| Failure::invalid("path is not UTF-8"))?;
+        let store = DocStore::load(Path::new(path)).map_err(|err| Failure {
+            code: MAXSIM_ERR_STORE,
+            message: err.to_string(),
+        })?;
+        let config = ScorerConfig::default().with_similarity(store.similarity());
+        let scorer = Scorer::new(config)?;
+        *out = Box::into_raw(Box::new(MaxsimStore { store, scorer }));
+        Ok(())
+    })
+}
+
+/// Release a store from `maxsim_store_open`; null is ignored. Dropping the
+/// scorer joins its worker pool; a panic doing so is caught and left in
+/// `maxsim_last_error_message`.
+///
+/// # Safety
+/// `store` must be null or an unreleased handle no other thread is using.
+#[no_mangle]
+pub unsafe extern "C" fn maxsim_store_free(store: *mut MaxsimStore) {
+    guard(|| {
+        if !store.is_null() {
+            drop(Box::from_raw(store));
+        }
+        Ok(())
+    });
+}
+
+/// Number of documents in `store`; 0 for null.
+///
+/// # Safety
+/// `store` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn maxsim_store_len(store: *const MaxsimStore) -> usize {
+    store.as_ref().map_or(0, |store| store.store.len())
+}
+
+/// Embedding dim queries are given in (before any padding); 0 for null.
+///
+/// # Safety
+/// `store` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn maxsim_store_dim(store: *const MaxsimStore) -> usize {
+    store.as_ref().map_or(0, |store| store.store.logical_dim())
+}
+
+/// Score the `[q_len, dim]` row-major f32 `query` against every document,
+/// writing `maxsim_store_len` scores in store order to `scores`, which
+/// holds `n_scores` floats.
+///
+/// # Safety
+/// `store` must be a live handle, `query` point to `q_len * dim` floats
+/// and `scores` to `n_scores` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn maxsim_score_batch(
+    store: *const MaxsimStore,
+    query: *const f32,
+    q_len: usize,
+    dim: usize,
+    scores: *mut f32,
+    n_scores: usize,
+) -> i32 {
+    guard(|| {
+        let store = store
+            .as_ref()
+            .ok_or_else(|| Failure::invalid("store is null"))?;
+        if scores.is_null() || n_scores < store.store.len() {
+            return Err(Failure::invalid("scores is null or shorter than the store"));
+        }
+        let query = read_query(store, query, q_len, dim)?;
+        let all = store.scorer.score_store(&query, &store.store)?;
+        std::slice::from_raw_parts_mut(scores, all.len()).copy_from_slice(&all);
+        Ok(())
+    })
+}
+
+/// The `k` best documents for the `[q_len, dim]` row-major f32 `query`:
+/// writes their positions to `ids` and scores to `scores` (each holding
+/// `k` values), best first, and their count (at most `k`) to `*n_out`.
+///
+/// # Safety
+/// `store` must be a live handle, `query` point to `q_len * dim` floats,
+/// `ids` and `scores` to `k` writable values each, and `n_out` be
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn maxsim_top_k(
+    store: *const MaxsimStore,
+    query: *const f32,
+    q_len: usize,
+    dim: usize,
+    k: usize,
+    ids: *mut u64,
+    scores: *mut f32,
+    n_out: *mut usize,
+) -> i32 {
+    guard(|| {
+        let store = store
+            .as_ref()
+            .ok_or_else(|| Failure::invalid("store is null"))?;
+        if ids.is_null() || scores.is_null() || n_out.is_null() {
+            return Err(Failure::invalid("ids, scores or n_out is null"));
+        }
+        let query = read_query(store, query, q_len, dim)?;
+        let mut top = TopK::new(k);
+        let all = store.scorer.score_store(&query, &store.store)?;
+        for (i, score) in all.into_iter().enumerate() {
+            top.push(i, score);
+        }
+        let hits = top.into_sorted_vec();
+        let ids = std::slice::from_raw_parts_mut(ids, hits.len());
+        let scores = std::slice::from_raw_parts_mut(scores, hits.len());
+        for (j, (i, score)) in hits.iter().enumerate() {
+            (ids[j], scores[j]) = (*i as u64, *score);
+        }
+        *n_out = hits.len();
+        Ok(())
+    })
+}
+
+/// Message of the calling thread's last failed call, or null if none has
+/// failed. Valid until that thread's next failing call.
+#[no_mangle]
+pub extern "C" fn maxsim_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
@@ -0,0 +1,144 @@
+//! Token-embedding centroids: nearest-centroid search and k-means training.
+//!
+//! "Nearest" is in L2, found as the largest `x·c - ‖c‖²/2` so that the
+//! distances come out of one similarity GEMM per block of rows.
+
+use crate::aligned::AlignedVec;
+use crate::collection::Documents;
+use crate::score::SimilarityGemm;
+
+/// Rows per centroid-similarity GEMM.
+const BLOCK: usize = 32;
+
+/// Tokens k-means trains on, at most.
+const TRAIN_SAMPLE: usize = 1 << 16;
+
+/// `[n_centroids, dim]` centroids with their `‖c‖²/2` precomputed.
+pub(crate) struct CentroidTable<'a> {
+    centroids: &'a [f32],
+    dim: usize,
+    half_norms: Vec<f32>,
+}
+
+impl<'a> CentroidTable<'a> {
+    /// `centroids` holds whole rows of `dim > 0` values.
+    pub(crate) fn new(centroids: &'a [f32], dim: usize) -> Self {
+        let half_norms = centroids
+            .chunks_exact(dim)
+            .map(|c| 0.5 * c.iter().map(|x| x * x).sum::<f32>())
+            .collect();
+        Self {
+            centroids,
+            dim,
+            half_norms,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.half_norms.len()
+    }
+
+    /// Calls `f(row, scores)` for every row of `rows` with its score
+    /// against each centroid; higher is nearer.
+    pub(crate) fn for_each_scored(&self, rows: &[f32], mut f: impl FnMut(usize, &[f32])) {
+        let n_centroids = self.len();
+        if n_centroids == 0 {
+            return;
+        }
+        let mut sims = AlignedVec::new();
+        sims.resize((rows.len() / self.dim).min(BLOCK) * n_centroids, 0.0);
+        for (b, block) in rows.chunks(BLOCK * self.dim).enumerate() {
+            let tokens = block.len() / self.dim;
+            let sims = &mut sims[..tokens * n_centroids];
+            SimilarityGemm::new(tokens, n_centroids, self.dim).run(block, self.centroids, sims);
+            for (r, row) in sims.chunks_exact_mut(n_centroids).enumerate() {
+                for (s, h) in row.iter_mut().zip(&self.half_norms) {
+                    *s -= h;
+                }
+                f(b * BLOCK + r, row);
+            }
+        }
+    }
+
+    /// Nearest centroid of every row of `rows` (the first on ties).
+    pub(crate) fn nearest(&self, rows: &[f32]) -> Vec<u32> {
+        let mut ids = Vec::with_capacity(rows.len() / self.dim);
+        self.for_each_scored(rows, |_, scores| {
+            let best = scores
+                .iter()
+                .enumerate()
+                .fold((0, f32::NEG_INFINITY), |best, (c, &v)| {
+                    if v > best.1 {
+                        (c, v)
+                    } else {
+                        best
+                    }
+                });
+            ids.push(best.0 as u32);
+        });
+        ids
+    }
+}
+
+/// `n_centroids` centroids for the tokens of `docs` by Lloyd's k-means
+/// over an evenly strided sample of up to 65536 tokens, seeded with
+/// evenly strided sample tokens. Fewer sample tokens than `n_centroids`
+/// give that many centroids. Centroids that lose all their tokens keep
+/// their previous position.
+pub fn train_centroids<D: Documents + ?Sized>(
+    docs: &D,
+    n_centroids: usize,
+    iterations: usize,
+) -> Vec<f32> {
+    let dim = docs.dim();
+    let total: usize = (0..docs.len()).map(|i| docs.doc_len(i)).sum();
+    if dim == 0 || total == 0 || n_centroids == 0 {
+        return Vec::new();
+    }
+    let step = total.div_ceil(TRAIN_SAMPLE);
+    let mut sample = Vec::with_capacity(total.div_ceil(step) * dim);
+    let mut t = 0;
+    for i in 0..docs.len() {
+        for row in docs.doc(i).chunks_exact(dim) {
+            if t % step == 0 {
+                sample.extend_from_slice(row);
+            }
+            t += 1;
+        }
+    }
+
+    let n_sample = sample.len() / dim;
+    let n_centroids = n_centroids.min(n_sample);
+    let mut centroids = Vec::with_capacity(n_centroids * dim);
+    for c in 0..n_centroids {
+        let row = c * n_sample / n_centroids;
+        centroids.extend_from_slice(&sample[row * dim..(row + 1) * dim]);
+    }
+
+    let mut sums = vec![0.0f32; n_centroids * dim];
+    let mut counts = vec![0usize; n_centroids];
+    for _ in 0..iterations {
+        let assigned = CentroidTable::new(&centroids, dim).nearest(&sample);
+        sums.fill(0.0);
+        counts.fill(0);
+        for (row, &c) in sample.chunks_exact(dim).zip(&assigned) {
+            let c = c as usize;
+            for (s, &x) in sums[c * dim..(c + 1) * dim].iter_mut().zip(row) {
+                *s += x;
+            }
+            counts[c] += 1;
+        }
+        for (c, &count) in counts.iter().enumerate() {
+            if count > 0 {
+                let inv = 1.0 / count as f32;
+                for (dst, &s) in centroids[c * dim..(c + 1) * dim]
+                    .iter_mut()
+                    .zip(&sums[c * dim..(c + 1) * dim])
+                {
+                    *dst = s * inv;
+                }
+            }
+        }
+    }
+    centroids
+}
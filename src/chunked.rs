@@ -0,0 +1,469 @@
+//! f32 document store scored chunk by chunk with bounded memory.
+//!
+//! A mapped store (`crate::store`) leaves paging to the OS, which under
+//! memory pressure evicts whatever it likes mid-batch. `ChunkedDocStore`
+//! reads the same f32 store file itself instead: the corpus is split into
+//! runs of consecutive documents of at most `chunk_bytes` each, and at
+//! most `max_resident` of them are held in memory, the least recently
+//! used evicted first. A chunk is at most `chunk_bytes` unless a single
+//! document is larger. Chunks are read outside the cache lock and a
+//! search keeps scoring a chunk evicted under it, so each search in
+//! flight may hold one chunk on top of the cached ones: resident memory
+//! is bounded by `(max_resident + searches) * chunk_bytes`.
+//!
+//! A search visits the resident chunks first, then the rest in file
+//! order, asking the OS to read ahead the next chunk while the current one
+//! is scored. Visiting in file order keeps LRU from discarding a chunk
+//! just before the scan reaches it.
+
+use std::fs::File;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::aligned::AlignedVec;
+use crate::collection::{DocId, Documents, QueryEmbeddings};
+use crate::score::{check_dim, top_k_heap, Reduction, ScoreError};
+use crate::stats::{timed, Phase};
+use crate::store::{read_extents, read_header, Mmap, StoreError, DTYPE_F32, HEADER_LEN, MAGIC};
+use crate::topk::TopK;
+
+/// How a `ChunkedDocStore` splits and caches its file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkConfig {
+    /// Byte budget of one chunk. Default 1 GiB.
+    pub chunk_bytes: usize,
+    /// Chunks held in memory at once, at least 1. Default 2.
+    pub max_resident: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            chunk_bytes: 1 << 30,
+            max_resident: 2,
+        }
+    }
+}
+
+impl ChunkConfig {
+    pub fn with_chunk_bytes(mut self, chunk_bytes: usize) -> Self {
+        self.chunk_bytes = chunk_bytes;
+        self
+    }
+
+    pub fn with_max_resident(mut self, max_resident: usize) -> Self {
+        self.max_resident = max_resident;
+        self
+    }
+}
+
+/// Cache counters of a `ChunkedDocStore` since it was opened or last
+/// reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ChunkStats {
+    /// Chunks read from disk.
+    pub loads: usize,
+    /// Chunks dropped to make room for another.
+    pub evictions: usize,
+    /// Chunk visits served from memory.
+    pub hits: usize,
+}
+
+/// Why a chunked search failed.
+#[derive(Debug)]
+pub enum ChunkedError {
+    /// A chunk could not be read.
+    Store(StoreError),
+    Score(ScoreError),
+}
+
+impl std::fmt::Display for ChunkedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkedError::Store(err) => err.fmt(f),
+            ChunkedError::Score(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ChunkedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChunkedError::Store(err) => Some(err),
+            ChunkedError::Score(err) => Some(err),
+        }
+    }
+}
+
+impl From<StoreError> for ChunkedError {
+    fn from(err: StoreError) -> Self {
+        ChunkedError::Store(err)
+    }
+}
+
+impl From<ScoreError> for ChunkedError {
+    fn from(err: ScoreError) -> Self {
+        ChunkedError::Score(err)
+    }
+}
+
+impl From<std::io::Error> for ChunkedError {
+    fn from(err: std::io::Error) -> Self {
+        ChunkedError::Store(StoreError::Io(err))
+    }
+}
+
+/// An f32 store file (`write_doc_store`) read in chunks on demand, with at
+/// most `max_resident` chunks in memory. See the module docs.
+#[derive(Debug)]
+pub struct ChunkedDocStore {
+    file: File,
+    dim: usize,
+    /// (byte start, token count) per document.
+    extents: Vec<(usize, usize)>,
+    /// Documents of each chunk, consecutive and ascending.
+    chunks: Vec<Range<DocId>>,
+    max_resident: usize,
+    /// Resident chunks, least recently used first.
+    resident: Mutex<Vec<(usize, Arc<Chunk>)>>,
+    loads: AtomicUsize,
+    evictions: AtomicUsize,
+    hits: AtomicUsize,
+}
+
+impl ChunkedDocStore {
+    /// Validate the store at `path` and split it per `config`. Only the
+    /// header and document table are read.
+    pub fn open(path: &Path, config: ChunkConfig) -> Result<Self, StoreError> {
+        let (dim, extents) = {
+            let map = Mmap::open(path)?;
+            let (dim, n_docs) = read_header(map.bytes(), MAGIC, DTYPE_F32)?;
+            let doc_bytes = |tokens: usize| tokens.checked_mul(dim)?.checked_mul(4);
+            (
+                dim,
+                read_extents(map.bytes(), HEADER_LEN, n_docs, doc_bytes)?,
+            )
+        };
+        // Chunks are read as one byte range from their first document
+        for pair in extents.windows(2) {
+            let (start, tokens) = pair[0];
+            if pair[1].0 < start + tokens * dim * 4 {
+                return Err(StoreError::Corrupt {
+                    reason: "documents overlap or are out of order",
+                });
+            }
+        }
+
+        let mut chunks = Vec::new();
+        let mut first = 0;
+        for i in 0..extents.len() {
+            let end = extents[i].0 + extents[i].1 * dim * 4;
+            if i > first && end - extents[first].0 > config.chunk_bytes {
+                chunks.push(first..i);
+                first = i;
+            }
+        }
+        if first < extents.len() {
+            chunks.push(first..extents.len());
+        }
+
+        Ok(Self {
+            file: File::open(path)?,
+            dim,
+            extents,
+            chunks,
+            max_resident: config.max_resident.max(1),
+            resident: Mutex::new(Vec::new()),
+            loads: AtomicUsize::new(0),
+            evictions: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+        })
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.extents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extents.is_empty()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        self.extents[i].1
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Documents of chunk `c`.
+    pub fn chunk_docs(&self, c: usize) -> Range<DocId> {
+        self.chunks[c].clone()
+    }
+
+    pub fn max_resident(&self) -> usize {
+        self.max_resident
+    }
+
+    /// Chunks currently held in memory.
+    pub fn resident_chunks(&self) -> usize {
+        self.resident.lock().expect("chunk cache poisoned").len()
+    }
+
+    pub fn stats(&self) -> ChunkStats {
+        ChunkStats {
+            loads: self.loads.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        self.loads.store(0, Ordering::Relaxed);
+        self.evictions.store(0, Ordering::Relaxed);
+        self.hits.store(0, Ordering::Relaxed);
+    }
+
+    /// The `k` best documents for `query`, ids being positions in the
+    /// store. Each chunk is scored in parallel like a `DocBatch`.
+    pub fn top_k(&self, query: &QueryEmbeddings, k: usize) -> Result<TopK, ChunkedError> {
+        check_dim(query, self.dim)?;
+        let order = self.visit_order();
+        let mut top = TopK::new(k);
+        for (j, &c) in order.iter().enumerate() {
+            if let Some(&next) = order.get(j + 1) {
+                self.prefetch(next);
+            }
+            let chunk = self.chunk(c)?;
+            let first = self.chunks[c].start;
+            let local = top_k_heap(query, &*chunk, k, Reduction::default())?;
+            for (i, score) in local.into_sorted_vec() {
+                top.push(first + i, score);
+            }
+        }
+        Ok(top)
+    }
+
+    /// Resident chunks, most recently used first, then the others in file
+    /// order.
+    fn visit_order(&self) -> Vec<usize> {
+        let resident = self.resident.lock().expect("chunk cache poisoned");
+        let mut order: Vec<usize> = resident.iter().rev().map(|&(c, _)| c).collect();
+        let cached = |c: &usize| resident.iter().any(|&(r, _)| r == *c);
+        order.extend((0..self.chunks.len()).filter(|c| !cached(c)));
+        order
+    }
+
+    /// Chunk `c`, from memory or read from disk, evicting the least
+    /// recently used chunk when the cache is full. The cache is not locked
+    /// during the read.
+    fn chunk(&self, c: usize) -> Result<Arc<Chunk>, ChunkedError> {
+        let cached = Self::touch(&mut self.resident.lock().expect("chunk cache poisoned"), c);
+        if let Some(chunk) = cached {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(chunk);
+        }
+        let chunk = Arc::new(timed(Phase::Io, || self.read_chunk(c))?);
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        let mut resident = self.resident.lock().expect("chunk cache poisoned");
+        // Another search may have cached it during the read
+        if let Some(cached) = Self::touch(&mut resident, c) {
+            return Ok(cached);
+        }
+        if resident.len() >= self.max_resident {
+            resident.remove(0);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        resident.push((c, chunk.clone()));
+        Ok(chunk)
+    }
+
+    /// Chunk `c` if cached, marked most recently used.
+    fn touch(resident: &mut Vec<(usize, Arc<Chunk>)>, c: usize) -> Option<Arc<Chunk>> {
+        let at = resident.iter().position(|&(r, _)| r == c)?;
+        let entry = resident.remove(at);
+        let chunk = entry.1.clone();
+        resident.push(entry);
+        Some(chunk)
+    }
+
+    /// Byte range of chunk `c` in the file.
+    fn byte_range(&self, c: usize) -> Range<usize> {
+        let docs = &self.chunks[c];
+        let (start, _) = self.extents[docs.start];
+        let (last, tokens) = self.extents[docs.end - 1];
+        start..last + tokens * self.dim * 4
+    }
+
+    /// Every document of chunk `c` in one read.
+    fn read_chunk(&self, c: usize) -> Result<Chunk, ChunkedError> {
+        let bytes = self.byte_range(c);
+        trace_span!(DEBUG, "maxsim.read_chunk", chunk = c, bytes = bytes.len());
+        let mut data = AlignedVec::new();
+        data.resize(bytes.len() / 4, 0.0f32);
+        // `data` holds exactly `bytes.len()` bytes; the file is little-endian
+        let raw =
+            unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, bytes.len()) };
+        self.file.read_exact_at(raw, bytes.start as u64)?;
+        let extents = self.extents[self.chunks[c].clone()]
+            .iter()
+            .map(|&(start, tokens)| ((start - bytes.start) / 4, tokens))
+            .collect();
+        Ok(Chunk {
+            data,
+            dim: self.dim,
+            extents,
+        })
+    }
+
+    /// Ask the OS to start reading chunk `c` unless it is resident.
+    fn prefetch(&self, c: usize) {
+        let resident = self.resident.lock().expect("chunk cache poisoned");
+        if resident.iter().any(|&(r, _)| r == c) {
+            return;
+        }
+        drop(resident);
+        // Advisory only, and Linux only; a failure just means no read-ahead
+        #[cfg(target_os = "linux")]
+        unsafe {
+            let bytes = self.byte_range(c);
+            libc::posix_fadvise(
+                self.file.as_raw_fd(),
+                bytes.start as libc::off_t,
+                bytes.len() as libc::off_t,
+                libc::POSIX_FADV_WILLNEED,
+            );
+        }
+    }
+}
+
+/// The documents of one chunk, read into memory.
+#[derive(Debug)]
+struct Chunk {
+    data: AlignedVec<f32>,
+    dim: usize,
+    /// (f32 index, token count) per document.
+    extents: Vec<(usize, usize)>,
+}
+
+impl Documents for Chunk {
+    fn len(&self) -> usize {
+        self.extents.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        self.extents[i].1
+    }
+
+    fn doc(&self, i: DocId) -> &[f32] {
+        let (start, tokens) = self.extents[i];
+        &self.data[start..start + tokens * self.dim]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{unit_docs, unit_rows};
+    use crate::store::{scratch_path, write_doc_store};
+
+    const DIM: usize = 16;
+    const LENS: [usize; 9] = [3, 9, 1, 4, 12, 2, 6, 5, 8];
+
+    /// A store file of `LENS` documents and the results a full scan gives.
+    fn store(name: &str) -> (std::path::PathBuf, QueryEmbeddings, Vec<(DocId, f32)>) {
+        let docs = unit_docs(&LENS, DIM, 8);
+        let path = scratch_path(name);
+        write_doc_store(&docs, &path).unwrap();
+        let query = QueryEmbeddings::new(unit_rows(3, DIM, 9), 3, DIM).unwrap();
+        let top = top_k_heap(&query, &docs, 4, Reduction::default()).unwrap();
+        (path, query, top.into_sorted_vec())
+    }
+
+    #[test]
+    fn chunked_search_equals_a_full_scan() {
+        let (path, query, expected) = store("chunked-scan");
+        for (chunk_bytes, max_resident) in [(1, 1), (1, 3), (600, 2), (2000, 1), (1 << 30, 2)] {
+            let config = ChunkConfig::default()
+                .with_chunk_bytes(chunk_bytes)
+                .with_max_resident(max_resident);
+            let store = ChunkedDocStore::open(&path, config).unwrap();
+            let chunks = store.num_chunks();
+            let covered: Vec<DocId> = (0..chunks).flat_map(|c| store.chunk_docs(c)).collect();
+            assert_eq!(covered, (0..LENS.len()).collect::<Vec<_>>());
+            for round in 0..2 {
+                let top = store.top_k(&query, 4).unwrap();
+                assert_eq!(
+                    top.into_sorted_vec(),
+                    expected,
+                    "{chunk_bytes} bytes, round {round}"
+                );
+                assert!(store.resident_chunks() <= max_resident);
+            }
+            let stats = store.stats();
+            assert_eq!(stats.loads + stats.hits, 2 * chunks);
+            assert_eq!(stats.evictions, stats.loads - store.resident_chunks());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn concurrent_searches_share_the_cache() {
+        let (path, query, expected) = store("chunked-concurrent");
+        let config = ChunkConfig::default()
+            .with_chunk_bytes(600)
+            .with_max_resident(2);
+        let store = ChunkedDocStore::open(&path, config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..3 {
+                        let top = store.top_k(&query, 4).unwrap();
+                        assert_eq!(top.into_sorted_vec(), expected);
+                    }
+                });
+            }
+        });
+        assert!(store.resident_chunks() <= 2);
+        let stats = store.stats();
+        assert_eq!(stats.loads + stats.hits, 8 * 3 * store.num_chunks());
+    }
+
+    #[test]
+    fn overlapping_documents_are_corrupt() {
+        let (path, _, _) = store("chunked-overlap");
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let entry = |i: usize| HEADER_LEN + i * 16;
+        let start = |i: usize| &bytes[entry(i)..entry(i) + 8];
+        let swapped = [(1, start(2)), (2, start(1))];
+        let overlapping = [(2, start(1))];
+        for (name, patch) in [("swapped", &swapped[..]), ("overlapping", &overlapping[..])] {
+            let mut bytes = bytes.clone();
+            for &(i, start) in patch {
+                bytes[entry(i)..entry(i) + 8].copy_from_slice(start);
+            }
+            let path = scratch_path(&format!("chunked-{name}"));
+            std::fs::write(&path, &bytes).unwrap();
+            let opened = ChunkedDocStore::open(&path, ChunkConfig::default());
+            std::fs::remove_file(&path).unwrap();
+            assert!(matches!(opened, Err(StoreError::Corrupt { .. })), "{name}");
+        }
+    }
+}
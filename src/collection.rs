@@ -0,0 +1,1092 @@
+//! Owned token-embedding containers for the scoring API.
+//!
+//! Embeddings are row-major `[tokens, dim]` f32. A collection stores all
+//! documents back to back in one buffer, with token offsets marking where
+//! each document starts. Buffers are `AlignedVec`s: embeddings passed in as
+//! a `Vec` are copied into aligned storage, an `AlignedVec` is adopted.
+
+use std::collections::HashMap;
+
+use crate::aligned::AlignedVec;
+use crate::bf16::convert_f32_to_bf16;
+use crate::f16::convert_f32_to_f16;
+use crate::norm::{max_row_norm, normalize_rows_inplace};
+use crate::quant::{max_abs_scale, quantize_i8, ScaleGranularity};
+use crate::score::{check_len, ScoreError};
+use crate::scorer::Similarity;
+#[cfg(feature = "half")]
+use crate::{bf16::convert_half_bf16_to_f32, f16::convert_half_f16_to_f32};
+#[cfg(feature = "half")]
+use half::slice::{HalfBitsSliceExt, HalfFloatSliceExt};
+
+/// Position of a document in its collection.
+pub type DocId = usize;
+
+/// A set of documents the batch scorers can read from.
+///
+/// Each document is a row-major `[doc_len(i), dim()]` slice.
+pub trait Documents: Sync {
+    /// Number of documents.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn dim(&self) -> usize;
+
+    /// Token count of document `i`.
+    fn doc_len(&self, i: DocId) -> usize;
+
+    /// Embeddings of document `i`.
+    fn doc(&self, i: DocId) -> &[f32];
+
+    /// Largest L2 norm among the tokens of document `i`, 0 when it has
+    /// none. Stores that keep it from ingest override this; the default
+    /// computes it from `doc(i)`.
+    fn max_token_norm(&self, i: DocId) -> f32 {
+        max_row_norm(self.doc(i), self.dim())
+    }
+
+    /// External id of document `i`, reported in `SearchHit`s. Its position
+    /// unless the collection stores ids.
+    fn id(&self, i: DocId) -> u64 {
+        i as u64
+    }
+
+    /// Trailing rows of document `i` that repeat its last token to fill a
+    /// length bucket (see `DocStoreBuilder::with_buckets`). They count in
+    /// `doc_len` but not as document tokens in the document-side
+    /// directions. 0 unless the collection pads.
+    fn padding(&self, _i: DocId) -> usize {
+        0
+    }
+}
+
+/// Token embeddings of one query.
+///
+/// Tokens can be masked out (e.g. the `[MASK]` padding of a fixed-length
+/// ColBERT query); masked tokens contribute nothing to the score. Tokens can
+/// also carry weights (e.g. IDF-like importances) that scale their maxima
+/// before aggregation.
+#[derive(Clone, Debug)]
+pub struct QueryEmbeddings {
+    data: AlignedVec<f32>,
+    len: usize,
+    dim: usize,
+    mask: TokenMask,
+    weights: Option<Vec<f32>>,
+}
+
+/// Which query tokens take part in scoring.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TokenMask {
+    /// The first `n` tokens; the rest are skipped before the GEMM.
+    Prefix(usize),
+    /// Arbitrary per-token mask; masked rows are computed but not summed.
+    Tokens(Vec<bool>),
+}
+
+impl TokenMask {
+    /// Collapse `mask` to a prefix when it is one (`true`s then `false`s).
+    pub(crate) fn from_bools(mask: &[bool]) -> Self {
+        let valid = mask.iter().take_while(|&&m| m).count();
+        if mask[valid..].iter().all(|&m| !m) {
+            TokenMask::Prefix(valid)
+        } else {
+            TokenMask::Tokens(mask.to_vec())
+        }
+    }
+}
+
+/// How the rows `QueryEmbeddings::active` returns count toward a score:
+/// rows whose `mask` entry is `false` are skipped, and each other row's
+/// maximum is scaled by its `weights` entry.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct TokenWeights<'a> {
+    pub(crate) mask: Option<&'a [bool]>,
+    pub(crate) weights: Option<&'a [f32]>,
+}
+
+impl TokenWeights<'_> {
+    /// Weight of row `qi`: 0 when masked, 1 without weights.
+    pub(crate) fn weight(&self, qi: usize) -> f32 {
+        if self.mask.is_some_and(|mask| !mask[qi]) {
+            0.0
+        } else {
+            self.weights.map_or(1.0, |weights| weights[qi])
+        }
+    }
+}
+
+impl QueryEmbeddings {
+    /// `data` is `[len, dim]` row-major. All tokens are unmasked.
+    pub fn new(
+        data: impl Into<AlignedVec<f32>>,
+        len: usize,
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        let data = data.into();
+        check_len("query", &data, len, dim)?;
+        Ok(Self {
+            data,
+            len,
+            dim,
+            mask: TokenMask::Prefix(len),
+            weights: None,
+        })
+    }
+
+    /// `new` from `[len, dim]` bf16 values, widened to f32.
+    #[cfg(feature = "half")]
+    pub fn from_bf16(data: &[half::bf16], len: usize, dim: usize) -> Result<Self, ScoreError> {
+        let mut widened = AlignedVec::new();
+        widened.resize(data.len(), 0.0);
+        convert_half_bf16_to_f32(data, &mut widened);
+        Self::new(widened, len, dim)
+    }
+
+    /// `new` from `[len, dim]` f16 values, widened to f32.
+    #[cfg(feature = "half")]
+    pub fn from_f16(data: &[half::f16], len: usize, dim: usize) -> Result<Self, ScoreError> {
+        let mut widened = AlignedVec::new();
+        widened.resize(data.len(), 0.0);
+        convert_half_f16_to_f32(data, &mut widened);
+        Self::new(widened, len, dim)
+    }
+
+    /// Keep only the first `valid_len` tokens.
+    pub fn with_valid_len(mut self, valid_len: usize) -> Result<Self, ScoreError> {
+        if valid_len > self.len {
+            return Err(ScoreError::MaskLength {
+                mask: valid_len,
+                tokens: self.len,
+            });
+        }
+        self.mask = TokenMask::Prefix(valid_len);
+        Ok(self)
+    }
+
+    /// Keep the tokens whose `mask` entry is `true`. `mask` has one entry
+    /// per token.
+    pub fn with_mask(mut self, mask: &[bool]) -> Result<Self, ScoreError> {
+        if mask.len() != self.len {
+            return Err(ScoreError::MaskLength {
+                mask: mask.len(),
+                tokens: self.len,
+            });
+        }
+        self.mask = TokenMask::from_bools(mask);
+        Ok(self)
+    }
+
+    /// Scale each token's best similarity by its entry in `weights` (one
+    /// per token) before aggregation, so the default sum becomes a weighted
+    /// sum. Masked tokens count with weight 0 whatever their entry.
+    pub fn with_weights(mut self, weights: &[f32]) -> Result<Self, ScoreError> {
+        if weights.len() != self.len {
+            return Err(ScoreError::WeightsLength {
+                weights: weights.len(),
+                tokens: self.len,
+            });
+        }
+        self.weights = Some(weights.to_vec());
+        Ok(self)
+    }
+
+    /// Every token row zero-padded to `dim` dims, mask and weights kept.
+    /// Dot products with rows zero-padded alike are unchanged.
+    pub fn padded_to(self, dim: usize) -> Result<Self, ScoreError> {
+        if dim < self.dim {
+            return Err(ScoreError::DimMismatch {
+                operand: "query",
+                expected: dim,
+                actual: self.dim,
+            });
+        }
+        let mut data = AlignedVec::with_capacity(self.len * dim);
+        for row in self.data.chunks_exact(self.dim.max(1)) {
+            data.extend_from_slice(row);
+            data.resize(data.len() + dim - self.dim, 0.0);
+        }
+        Ok(Self { data, dim, ..self })
+    }
+
+    /// L2-normalize every token row (masked ones included).
+    pub fn normalized(mut self) -> Self {
+        normalize_rows_inplace(&mut self.data, self.dim);
+        self
+    }
+
+    /// Number of unmasked tokens.
+    pub fn valid_len(&self) -> usize {
+        match &self.mask {
+            TokenMask::Prefix(n) => *n,
+            TokenMask::Tokens(mask) => mask.iter().filter(|&&m| m).count(),
+        }
+    }
+
+    /// Rows to feed the GEMM, their count, and their mask (when the
+    /// unmasked tokens are not a prefix) and weights.
+    pub(crate) fn active(&self) -> (&[f32], usize, TokenWeights<'_>) {
+        let (rows, mask) = match &self.mask {
+            TokenMask::Prefix(n) => (*n, None),
+            TokenMask::Tokens(mask) => (self.len, Some(&mask[..])),
+        };
+        let weights = self.weights.as_deref().map(|weights| &weights[..rows]);
+        (
+            &self.data[..rows * self.dim],
+            rows,
+            TokenWeights { mask, weights },
+        )
+    }
+
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    /// Per-token weights, if set with `with_weights`.
+    pub fn weights(&self) -> Option<&[f32]> {
+        self.weights.as_deref()
+    }
+
+    /// Number of query tokens.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+}
+
+/// Queries scored together against one document set, e.g. by
+/// `maxsim_score_matrix`. Each query keeps its own length and mask.
+#[derive(Clone, Debug, Default)]
+pub struct QueryBatch {
+    queries: Vec<QueryEmbeddings>,
+}
+
+impl QueryBatch {
+    pub fn new(queries: Vec<QueryEmbeddings>) -> Self {
+        Self { queries }
+    }
+
+    /// Append one query; returns its position in the batch.
+    pub fn push(&mut self, query: QueryEmbeddings) -> usize {
+        self.queries.push(query);
+        self.queries.len() - 1
+    }
+
+    /// Number of queries.
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    pub fn query(&self, i: usize) -> &QueryEmbeddings {
+        &self.queries[i]
+    }
+
+    pub fn queries(&self) -> &[QueryEmbeddings] {
+        &self.queries
+    }
+}
+
+/// Documents stored back to back in one `[total_tokens, dim]` buffer.
+///
+/// Document `i` spans tokens `offsets[i]..offsets[i + 1]`.
+#[derive(Clone, Debug)]
+pub struct DocCollection {
+    data: AlignedVec<f32>,
+    offsets: Vec<usize>,
+    dim: usize,
+    /// Largest token norm per document, for pruning.
+    max_norms: Vec<f32>,
+}
+
+impl DocCollection {
+    /// `offsets` has one entry per document plus a final end offset; it
+    /// starts at 0, never decreases between documents of at least one token,
+    /// and ends at the total token count of `data`.
+    pub fn new(
+        data: impl Into<AlignedVec<f32>>,
+        offsets: Vec<usize>,
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        let data = data.into();
+        let total = offsets.last().copied().unwrap_or(0);
+        check_len("docs", &data, total, dim)?;
+        if offsets.first().is_some_and(|&first| first != 0) {
+            return Err(ScoreError::InvalidOffsets { doc: 0 });
+        }
+        if let Some(doc) = offsets.windows(2).position(|w| w[1] < w[0]) {
+            return Err(ScoreError::InvalidOffsets { doc });
+        }
+        if offsets.windows(2).any(|w| w[1] == w[0]) {
+            return Err(ScoreError::EmptyDocument);
+        }
+        let mut docs = Self {
+            data,
+            offsets,
+            dim,
+            max_norms: Vec::new(),
+        };
+        docs.max_norms = (0..docs.len())
+            .map(|i| max_row_norm(docs.doc(i), dim))
+            .collect();
+        Ok(docs)
+    }
+
+    /// Build from per-document token counts instead of offsets.
+    pub fn from_lengths(
+        data: impl Into<AlignedVec<f32>>,
+        lengths: &[usize],
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        let mut offsets = Vec::with_capacity(lengths.len() + 1);
+        offsets.push(0);
+        let mut end = 0;
+        for &len in lengths {
+            end += len;
+            offsets.push(end);
+        }
+        Self::new(data, offsets, dim)
+    }
+
+    /// Prepare the stored rows for scoring under `similarity`: `Cosine`
+    /// L2-normalizes every token row, `Dot` keeps them as they are.
+    pub fn with_similarity(mut self, similarity: Similarity) -> Self {
+        if similarity == Similarity::Cosine {
+            normalize_rows_inplace(&mut self.data, self.dim);
+            self.max_norms = (0..self.len())
+                .map(|i| max_row_norm(self.doc(i), self.dim))
+                .collect();
+        }
+        self
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: usize) -> usize {
+        self.offsets[i + 1] - self.offsets[i]
+    }
+
+    /// Embeddings of document `i`, `[doc_len(i), dim]`.
+    pub fn doc(&self, i: usize) -> &[f32] {
+        &self.data[self.offsets[i] * self.dim..self.offsets[i + 1] * self.dim]
+    }
+
+    /// Largest token norm of document `i`, computed at ingest.
+    pub fn max_token_norm(&self, i: usize) -> f32 {
+        self.max_norms[i]
+    }
+
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+}
+
+impl Documents for DocCollection {
+    fn len(&self) -> usize {
+        DocCollection::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        DocCollection::doc_len(self, i)
+    }
+
+    fn doc(&self, i: DocId) -> &[f32] {
+        DocCollection::doc(self, i)
+    }
+
+    fn max_token_norm(&self, i: DocId) -> f32 {
+        DocCollection::max_token_norm(self, i)
+    }
+}
+
+/// Growable variable-length document batch in CSR layout.
+///
+/// Document `i` is `flat[offsets[i]..offsets[i + 1]]`; offsets count f32
+/// values, not tokens. Zero-length documents are allowed and score 0.
+///
+/// Every document carries a unique external `u64` id (its position unless
+/// pushed with `push_with_id`) and optionally a metadata blob.
+#[derive(Clone, Debug)]
+pub struct DocBatch {
+    flat: AlignedVec<f32>,
+    offsets: Vec<usize>,
+    dim: usize,
+    /// Rows are normalized on `push` under `Similarity::Cosine`.
+    similarity: Similarity,
+    /// Largest token norm per document, for pruning.
+    max_norms: Vec<f32>,
+    ids: Vec<u64>,
+    positions: HashMap<u64, DocId>,
+    metadata: Vec<Option<Vec<u8>>>,
+}
+
+impl DocBatch {
+    /// Empty batch of `dim`-dimensional token embeddings.
+    pub fn new(dim: usize) -> Self {
+        Self {
+            flat: AlignedVec::new(),
+            offsets: vec![0],
+            dim,
+            similarity: Similarity::Dot,
+            max_norms: Vec::new(),
+            ids: Vec::new(),
+            positions: HashMap::new(),
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Adopt an existing CSR layout. `offsets` starts at 0, never
+    /// decreases, ends at `flat.len()`, and every segment holds a whole
+    /// number of `dim`-value rows. Documents are identified by position.
+    pub fn from_parts(
+        flat: impl Into<AlignedVec<f32>>,
+        offsets: Vec<usize>,
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        let flat = flat.into();
+        if offsets.first() != Some(&0) {
+            return Err(ScoreError::InvalidOffsets { doc: 0 });
+        }
+        if offsets.last() != Some(&flat.len()) {
+            return Err(ScoreError::DimMismatch {
+                operand: "docs",
+                expected: offsets.last().copied().unwrap_or(0),
+                actual: flat.len(),
+            });
+        }
+        for (doc, w) in offsets.windows(2).enumerate() {
+            if w[1] < w[0] {
+                return Err(ScoreError::InvalidOffsets { doc });
+            }
+            let seg = w[1] - w[0];
+            if !seg.is_multiple_of(dim) {
+                return Err(ScoreError::DimMismatch {
+                    operand: "doc",
+                    expected: seg.checked_div(dim).unwrap_or(0) * dim,
+                    actual: seg,
+                });
+            }
+        }
+        let n_docs = offsets.len() - 1;
+        let mut docs = Self {
+            flat,
+            offsets,
+            dim,
+            similarity: Similarity::Dot,
+            max_norms: Vec::new(),
+            ids: (0..n_docs as u64).collect(),
+            positions: (0..n_docs).map(|i| (i as u64, i)).collect(),
+            metadata: vec![None; n_docs],
+        };
+        docs.update_max_norms();
+        Ok(docs)
+    }
+
+    /// Prepare the batch for scoring under `similarity`: with `Cosine`,
+    /// the documents already pushed and every later one are L2-normalized
+    /// per token row.
+    pub fn with_similarity(mut self, similarity: Similarity) -> Self {
+        if similarity == Similarity::Cosine && self.similarity != Similarity::Cosine {
+            normalize_rows_inplace(&mut self.flat, self.dim);
+            self.update_max_norms();
+        }
+        self.similarity = similarity;
+        self
+    }
+
+    /// Identify the documents by `ids`, one per document, instead of by
+    /// position. Fails with `IdsLength` unless there is one id per
+    /// document and with `DuplicateId` on a repeated id.
+    pub fn with_ids(mut self, ids: Vec<u64>) -> Result<Self, ScoreError> {
+        if ids.len() != self.len() {
+            return Err(ScoreError::IdsLength {
+                ids: ids.len(),
+                docs: self.len(),
+            });
+        }
+        let mut positions = HashMap::with_capacity(ids.len());
+        for (pos, &id) in ids.iter().enumerate() {
+            if positions.insert(id, pos).is_some() {
+                return Err(ScoreError::DuplicateId { id });
+            }
+        }
+        self.ids = ids;
+        self.positions = positions;
+        Ok(self)
+    }
+
+    fn update_max_norms(&mut self) {
+        self.max_norms = (0..self.len())
+            .map(|i| max_row_norm(self.doc(i), self.dim))
+            .collect();
+    }
+
+    pub fn similarity(&self) -> Similarity {
+        self.similarity
+    }
+
+    /// Append one `[tokens, dim]` document, identified by its position.
+    /// Fails with `DuplicateId` if `push_with_id` already took that id.
+    pub fn push(&mut self, doc: &[f32]) -> Result<DocId, ScoreError> {
+        self.push_with_id(self.len() as u64, doc, None)
+    }
+
+    /// Append one `[tokens, dim]` document under the external `id`, with
+    /// an optional metadata blob. Fails with `DuplicateId` if the batch
+    /// already holds `id`.
+    pub fn push_with_id(
+        &mut self,
+        id: u64,
+        doc: &[f32],
+        metadata: Option<Vec<u8>>,
+    ) -> Result<DocId, ScoreError> {
+        if self.positions.contains_key(&id) {
+            return Err(ScoreError::DuplicateId { id });
+        }
+        if !doc.len().is_multiple_of(self.dim) {
+            return Err(ScoreError::DimMismatch {
+                operand: "doc",
+                expected: doc.len().checked_div(self.dim).unwrap_or(0) * self.dim,
+                actual: doc.len(),
+            });
+        }
+        let start = self.flat.len();
+        self.flat.extend_from_slice(doc);
+        if self.similarity == Similarity::Cosine {
+            normalize_rows_inplace(&mut self.flat[start..], self.dim);
+        }
+        let max_norm = max_row_norm(&self.flat[start..], self.dim);
+        self.max_norms.push(max_norm);
+        self.offsets.push(self.flat.len());
+        let pos = self.offsets.len() - 2;
+        self.ids.push(id);
+        self.positions.insert(id, pos);
+        self.metadata.push(metadata);
+        Ok(pos)
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        (self.offsets[i + 1] - self.offsets[i])
+            .checked_div(self.dim)
+            .unwrap_or(0)
+    }
+
+    /// Embeddings of document `i`.
+    pub fn doc(&self, i: DocId) -> &[f32] {
+        &self.flat[self.offsets[i]..self.offsets[i + 1]]
+    }
+
+    /// Largest token norm of document `i`, computed at ingest.
+    pub fn max_token_norm(&self, i: DocId) -> f32 {
+        self.max_norms[i]
+    }
+
+    /// External id of document `i`.
+    pub fn id(&self, i: DocId) -> u64 {
+        self.ids[i]
+    }
+
+    /// Position of the document with external id `id`.
+    pub fn position(&self, id: u64) -> Option<DocId> {
+        self.positions.get(&id).copied()
+    }
+
+    /// Stop resolving `id` to its document, so `push_with_id` can take it
+    /// again. The document itself stays in place.
+    pub(crate) fn release_id(&mut self, id: u64) {
+        self.positions.remove(&id);
+    }
+
+    /// Metadata blob of document `i`, if it was pushed with one.
+    pub fn metadata(&self, i: DocId) -> Option<&[u8]> {
+        self.metadata[i].as_deref()
+    }
+
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    pub fn flat(&self) -> &[f32] {
+        &self.flat
+    }
+}
+
+impl Documents for DocBatch {
+    fn len(&self) -> usize {
+        DocBatch::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        DocBatch::doc_len(self, i)
+    }
+
+    fn doc(&self, i: DocId) -> &[f32] {
+        DocBatch::doc(self, i)
+    }
+
+    fn max_token_norm(&self, i: DocId) -> f32 {
+        DocBatch::max_token_norm(self, i)
+    }
+
+    fn id(&self, i: DocId) -> u64 {
+        DocBatch::id(self, i)
+    }
+}
+
+/// Documents stored as bf16 (raw `u16` bits) in one `[total_tokens, dim]`
+/// buffer, half the footprint of f32.
+///
+/// Document `i` spans tokens `offsets[i]..offsets[i + 1]`. Zero-length
+/// documents are allowed and score 0. Scored through `Scorer`, in bf16 or
+/// widened to f32 depending on its precision.
+#[derive(Clone, Debug)]
+pub struct Bf16DocCollection {
+    data: AlignedVec<u16>,
+    offsets: Vec<usize>,
+    dim: usize,
+}
+
+impl Bf16DocCollection {
+    /// `offsets` has one entry per document plus a final end offset; it
+    /// starts at 0, never decreases, and ends at the total token count of
+    /// `data`.
+    pub fn new(
+        data: impl Into<AlignedVec<u16>>,
+        offsets: Vec<usize>,
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        let data = data.into();
+        let total = offsets.last().copied().unwrap_or(0);
+        check_len("docs", &data, total, dim)?;
+        check_offsets(&offsets)?;
+        Ok(Self { data, offsets, dim })
+    }
+
+    /// Round every document of `docs` to bf16 (nearest-even).
+    pub fn from_documents<D: Documents + ?Sized>(docs: &D) -> Self {
+        let dim = docs.dim();
+        let mut offsets = Vec::with_capacity(docs.len() + 1);
+        offsets.push(0);
+        for i in 0..docs.len() {
+            offsets.push(offsets[i] + docs.doc_len(i));
+        }
+        let mut data = AlignedVec::new();
+        data.resize(offsets[docs.len()] * dim, 0u16);
+        for i in 0..docs.len() {
+            let dst = &mut data[offsets[i] * dim..offsets[i + 1] * dim];
+            convert_f32_to_bf16(docs.doc(i), dst);
+        }
+        Self { data, offsets, dim }
+    }
+
+    /// `new` from `half::bf16` values, copied.
+    #[cfg(feature = "half")]
+    pub fn from_half(
+        data: &[half::bf16],
+        offsets: Vec<usize>,
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        Self::new(data.reinterpret_cast(), offsets, dim)
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        self.offsets[i + 1] - self.offsets[i]
+    }
+
+    /// bf16 embeddings of document `i`, `[doc_len(i), dim]`.
+    pub fn doc(&self, i: DocId) -> &[u16] {
+        &self.data[self.offsets[i] * self.dim..self.offsets[i + 1] * self.dim]
+    }
+
+    /// `doc` as `half::bf16` values.
+    #[cfg(feature = "half")]
+    pub fn doc_half(&self, i: DocId) -> &[half::bf16] {
+        self.doc(i).reinterpret_cast()
+    }
+
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    pub fn data(&self) -> &[u16] {
+        &self.data
+    }
+
+    /// `data` as `half::bf16` values.
+    #[cfg(feature = "half")]
+    pub fn data_half(&self) -> &[half::bf16] {
+        self.data.reinterpret_cast()
+    }
+}
+
+/// Documents stored as IEEE half (raw `u16` bits) in one
+/// `[total_tokens, dim]` buffer, half the footprint of f32.
+///
+/// Document `i` spans tokens `offsets[i]..offsets[i + 1]`. Zero-length
+/// documents are allowed and score 0. Scored with
+/// `Scorer::score_batch_f16_collection`, widened to f32 tile by tile.
+#[derive(Clone, Debug)]
+pub struct F16DocCollection {
+    data: AlignedVec<u16>,
+    offsets: Vec<usize>,
+    dim: usize,
+}
+
+impl F16DocCollection {
+    /// `offsets` has one entry per document plus a final end offset; it
+    /// starts at 0, never decreases, and ends at the total token count of
+    /// `data`.
+    pub fn new(
+        data: impl Into<AlignedVec<u16>>,
+        offsets: Vec<usize>,
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        let data = data.into();
+        let total = offsets.last().copied().unwrap_or(0);
+        check_len("docs", &data, total, dim)?;
+        check_offsets(&offsets)?;
+        Ok(Self { data, offsets, dim })
+    }
+
+    /// Round every document of `docs` to f16 (nearest-even).
+    pub fn from_documents<D: Documents + ?Sized>(docs: &D) -> Self {
+        let dim = docs.dim();
+        let offsets = token_offsets(docs);
+        let mut data = AlignedVec::new();
+        data.resize(offsets[docs.len()] * dim, 0u16);
+        for i in 0..docs.len() {
+            let dst = &mut data[offsets[i] * dim..offsets[i + 1] * dim];
+            convert_f32_to_f16(docs.doc(i), dst);
+        }
+        Self { data, offsets, dim }
+    }
+
+    /// `new` from `half::f16` values, copied.
+    #[cfg(feature = "half")]
+    pub fn from_half(
+        data: &[half::f16],
+        offsets: Vec<usize>,
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        Self::new(data.reinterpret_cast(), offsets, dim)
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        self.offsets[i + 1] - self.offsets[i]
+    }
+
+    /// f16 embeddings of document `i`, `[doc_len(i), dim]`.
+    pub fn doc(&self, i: DocId) -> &[u16] {
+        &self.data[self.offsets[i] * self.dim..self.offsets[i + 1] * self.dim]
+    }
+
+    /// `doc` as `half::f16` values.
+    #[cfg(feature = "half")]
+    pub fn doc_half(&self, i: DocId) -> &[half::f16] {
+        self.doc(i).reinterpret_cast()
+    }
+
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    pub fn data(&self) -> &[u16] {
+        &self.data
+    }
+
+    /// `data` as `half::f16` values.
+    #[cfg(feature = "half")]
+    pub fn data_half(&self) -> &[half::f16] {
+        self.data.reinterpret_cast()
+    }
+}
+
+/// Token offsets of `docs`: 0, then each document's end.
+fn token_offsets<D: Documents + ?Sized>(docs: &D) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(docs.len() + 1);
+    offsets.push(0);
+    for i in 0..docs.len() {
+        offsets.push(offsets[i] + docs.doc_len(i));
+    }
+    offsets
+}
+
+/// `offsets` starts at 0 (or is empty) and never decreases.
+fn check_offsets(offsets: &[usize]) -> Result<(), ScoreError> {
+    if offsets.first().is_some_and(|&first| first != 0) {
+        return Err(ScoreError::InvalidOffsets { doc: 0 });
+    }
+    if let Some(doc) = offsets.windows(2).position(|w| w[1] < w[0]) {
+        return Err(ScoreError::InvalidOffsets { doc });
+    }
+    Ok(())
+}
+
+/// Documents quantized to int8 with f32 scales (see `crate::quant`), a
+/// quarter of the f32 footprint.
+///
+/// Document `i` spans tokens `offsets[i]..offsets[i + 1]`. Zero-length
+/// documents are allowed and score 0.
+#[derive(Clone, Debug)]
+pub struct Int8DocCollection {
+    data: AlignedVec<i8>,
+    /// One scale per token or per document, per `granularity`.
+    scales: Vec<f32>,
+    granularity: ScaleGranularity,
+    offsets: Vec<usize>,
+    dim: usize,
+}
+
+impl Int8DocCollection {
+    /// Adopt already quantized documents. `offsets` is as for
+    /// `Bf16DocCollection::new`; `scales` holds one entry per token or per
+    /// document, per `granularity`.
+    pub fn new(
+        data: impl Into<AlignedVec<i8>>,
+        scales: Vec<f32>,
+        granularity: ScaleGranularity,
+        offsets: Vec<usize>,
+        dim: usize,
+    ) -> Result<Self, ScoreError> {
+        let data = data.into();
+        let total = offsets.last().copied().unwrap_or(0);
+        check_len("docs", &data, total, dim)?;
+        check_offsets(&offsets)?;
+        let n_scales = match granularity {
+            ScaleGranularity::PerToken => total,
+            ScaleGranularity::PerDocument => offsets.len().saturating_sub(1),
+        };
+        check_len("scales", &scales, n_scales, 1)?;
+        Ok(Self {
+            data,
+            scales,
+            granularity,
+            offsets,
+            dim,
+        })
+    }
+
+    /// Quantize every document of `docs` symmetrically with max-abs scales.
+    pub fn from_documents<D: Documents + ?Sized>(docs: &D, granularity: ScaleGranularity) -> Self {
+        let dim = docs.dim();
+        let mut offsets = Vec::with_capacity(docs.len() + 1);
+        offsets.push(0);
+        for i in 0..docs.len() {
+            offsets.push(offsets[i] + docs.doc_len(i));
+        }
+        let mut data = AlignedVec::new();
+        data.resize(offsets[docs.len()] * dim, 0i8);
+        let mut scales = Vec::new();
+        for i in 0..docs.len() {
+            let (src, dst) = (
+                docs.doc(i),
+                &mut data[offsets[i] * dim..offsets[i + 1] * dim],
+            );
+            match granularity {
+                ScaleGranularity::PerToken if dim > 0 => {
+                    for (row, out) in src.chunks_exact(dim).zip(dst.chunks_exact_mut(dim)) {
+                        let scale = max_abs_scale(row);
+                        quantize_i8(row, scale, out);
+                        scales.push(scale);
+                    }
+                }
+                ScaleGranularity::PerToken => {
+                    scales.extend(std::iter::repeat_n(0.0, docs.doc_len(i)))
+                }
+                ScaleGranularity::PerDocument => {
+                    let scale = max_abs_scale(src);
+                    quantize_i8(src, scale, dst);
+                    scales.push(scale);
+                }
+            }
+        }
+        Self {
+            data,
+            scales,
+            granularity,
+            offsets,
+            dim,
+        }
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn granularity(&self) -> ScaleGranularity {
+        self.granularity
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        self.offsets[i + 1] - self.offsets[i]
+    }
+
+    /// int8 embeddings of document `i`, `[doc_len(i), dim]`.
+    pub fn doc(&self, i: DocId) -> &[i8] {
+        &self.data[self.offsets[i] * self.dim..self.offsets[i + 1] * self.dim]
+    }
+
+    /// Scales of document `i`: one per token, or a single one per document.
+    pub fn doc_scales(&self, i: DocId) -> &[f32] {
+        match self.granularity {
+            ScaleGranularity::PerToken => &self.scales[self.offsets[i]..self.offsets[i + 1]],
+            ScaleGranularity::PerDocument => std::slice::from_ref(&self.scales[i]),
+        }
+    }
+
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    pub fn data(&self) -> &[i8] {
+        &self.data
+    }
+
+    pub fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+    use crate::scorer::{Scorer, ScorerConfig};
+
+    const DIM: usize = 8;
+
+    fn batch(lens: &[usize]) -> DocBatch {
+        let mut offsets = vec![0];
+        for &len in lens {
+            offsets.push(offsets.last().unwrap() + len * DIM);
+        }
+        let data = unit_rows(lens.iter().sum(), DIM, 3);
+        DocBatch::from_parts(data, offsets, DIM).unwrap()
+    }
+
+    #[test]
+    fn ids_must_cover_every_document() {
+        for ids in [vec![], vec![5, 6], vec![5, 6, 7, 8]] {
+            let n = ids.len();
+            assert!(
+                matches!(
+                    batch(&[2, 3, 1]).with_ids(ids),
+                    Err(ScoreError::IdsLength { ids, docs: 3 }) if ids == n
+                ),
+                "{n} ids"
+            );
+        }
+        assert!(matches!(
+            batch(&[2, 3, 1]).with_ids(vec![5, 6, 5]),
+            Err(ScoreError::DuplicateId { id: 5 })
+        ));
+    }
+
+    #[test]
+    fn hits_carry_the_given_ids() {
+        let ids = vec![900, 7, 31, u64::MAX];
+        let docs = batch(&[2, 3, 1, 4]).with_ids(ids.clone()).unwrap();
+        for (i, &id) in ids.iter().enumerate() {
+            assert_eq!((docs.id(i), docs.position(id)), (id, Some(i)));
+        }
+        assert_eq!(docs.position(0), None);
+
+        let scorer = Scorer::new(ScorerConfig::default().with_num_threads(1)).unwrap();
+        let query = QueryEmbeddings::new(unit_rows(3, DIM, 4), 3, DIM).unwrap();
+        let scores = scorer.score_batch(&query, &docs).unwrap();
+        let results = scorer.search(1, &query, &docs, ids.len()).unwrap();
+        assert_eq!(results.hits.len(), ids.len());
+        for hit in &results.hits {
+            assert_eq!(hit.score, scores[docs.position(hit.id).unwrap()]);
+        }
+    }
+}
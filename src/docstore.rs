@@ -0,0 +1,1539 @@
+//! One save/load format for every in-memory document store.
+//!
+//! Building a store (quantizing, packing, normalizing) is far slower than
+//! reading it back, so `DocStore::save` writes the finished store and
+//! `DocStore::load` restores it as it was. The file is little-endian and
+//! extends the `crate::store` header:
+//!
+//! ```text
+//! 0    magic       b"MAXSIMST"
+//! 8    ...         version, dtype, dim, n_docs as in `crate::store`
+//! 32   similarity  u32 (0 = dot, 1 = cosine)
+//! 36   layout      u32 (bf16: as in `crate::packed`; else 0)
+//! 40   arch        u32 (bf16: `ArchFamily` packed for; else 0)
+//! 44   scales      u32 (int8: 0 = per token, 1 = per document; else 0)
+//! 48   logical_dim u64 (dim before zero padding, at most dim)
+//! 56   n_buckets   u64
+//! 64   buckets     n_buckets × (tokens: u64, docs: u64), ascending tokens
+//! ...  padding     n_docs × u64 (trailing rows of bucket padding)
+//! ...  table       n_docs × (start: u64, tokens: u64)
+//! ...  data        each document at its `start`
+//! ```
+//!
+//! Documents start on `STORE_ALIGN` boundaries as in `crate::store`. An
+//! int8 document is its f32 scales followed by its `[tokens, dim]` rows.
+//! The buckets are the documents' length histogram, i.e. the groups the
+//! batch scorers set up one GEMM for; `load` checks them against the table.
+//! A document's padding rows, counted in its tokens, repeat its last real
+//! row; the scorers leave them out of document-side reductions.
+//!
+//! `DocStoreBuilder` assembles a store document by document into
+//! pre-allocated storage, optionally padding documents to fixed buckets
+//! and token rows with zeros to a kernel-friendly dim. Zero columns add
+//! nothing to a dot product, so a zero-padded store scores the same as the
+//! unpadded one once queries are padded alike (`DocStore::pad_query`).
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::aligned::{AlignedVec, AllocPolicy, PageBacking};
+use crate::bf16::convert_f32_to_bf16;
+#[cfg(feature = "half")]
+use crate::bf16::convert_half_bf16_to_f32;
+use crate::collection::{
+    DocCollection, DocId, F16DocCollection, Int8DocCollection, QueryEmbeddings,
+};
+use crate::f16::convert_f32_to_f16;
+#[cfg(feature = "half")]
+use crate::f16::convert_half_f16_to_f32;
+use crate::norm::normalize_rows_inplace;
+use crate::packed::{
+    layout_id, pack_doc, packed_k, read_packing, ArchFamily, OnMismatch, Operand, PackedDocStore,
+};
+use crate::quant::{max_abs_scale, quantize_i8, ScaleGranularity};
+use crate::score::ScoreError;
+use crate::scorer::Similarity;
+use crate::store::{
+    dtype_error, read_extents, read_header_any, read_u32, read_u64, write_docs, write_header,
+    StoreDtype, StoreError, HEADER_LEN,
+};
+use crate::vnni::VnniLayout;
+
+const MAGIC: &[u8; 8] = b"MAXSIMST";
+const BUCKETS_START: usize = HEADER_LEN + 32;
+const BUCKET_LEN: usize = 16;
+
+/// The documents of a `DocStore`, in one of the stored encodings.
+#[derive(Debug)]
+pub enum StoreDocs {
+    F32(DocCollection),
+    F16(F16DocCollection),
+    /// bf16, flat or packed for the bf16 kernels.
+    Bf16(PackedDocStore),
+    /// int8 with per-token or per-document scales.
+    Int8(Int8DocCollection),
+}
+
+/// A document store with the similarity its rows were prepared for, saved
+/// and loaded as one file.
+#[derive(Debug)]
+pub struct DocStore {
+    docs: StoreDocs,
+    similarity: Similarity,
+    /// Embedding dim before zero padding.
+    logical_dim: usize,
+    /// Rows of bucket padding ending each document; empty for none.
+    padding: Vec<usize>,
+}
+
+impl DocStore {
+    /// `docs` as prepared for `similarity` (e.g. normalized rows for
+    /// `Cosine`). The similarity is recorded, not applied.
+    pub fn new(docs: StoreDocs, similarity: Similarity) -> Self {
+        let mut store = Self {
+            docs,
+            similarity,
+            logical_dim: 0,
+            padding: Vec::new(),
+        };
+        store.logical_dim = store.dim();
+        store
+    }
+
+    /// Record that the rows are `logical_dim`-dimensional embeddings
+    /// zero-padded to `dim()`.
+    pub(crate) fn with_logical_dim(mut self, logical_dim: usize) -> Self {
+        debug_assert!(logical_dim <= self.dim());
+        self.logical_dim = logical_dim;
+        self
+    }
+
+    /// Record that the last `padding[i]` rows of document `i` repeat its
+    /// last token to fill a bucket.
+    pub(crate) fn with_padding(mut self, padding: Vec<usize>) -> Self {
+        debug_assert!(padding.is_empty() || padding.len() == self.len());
+        self.padding = padding;
+        self
+    }
+
+    pub fn docs(&self) -> &StoreDocs {
+        &self.docs
+    }
+
+    pub fn into_docs(self) -> StoreDocs {
+        self.docs
+    }
+
+    pub fn similarity(&self) -> Similarity {
+        self.similarity
+    }
+
+    pub fn dtype(&self) -> StoreDtype {
+        match self.docs {
+            StoreDocs::F32(_) => StoreDtype::F32,
+            StoreDocs::F16(_) => StoreDtype::F16,
+            StoreDocs::Bf16(_) => StoreDtype::Bf16,
+            StoreDocs::Int8(_) => StoreDtype::Int8,
+        }
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        match &self.docs {
+            StoreDocs::F32(docs) => docs.len(),
+            StoreDocs::F16(docs) => docs.len(),
+            StoreDocs::Bf16(docs) => docs.len(),
+            StoreDocs::Int8(docs) => docs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Dim of the stored rows, zero padding included.
+    pub fn dim(&self) -> usize {
+        match &self.docs {
+            StoreDocs::F32(docs) => docs.dim(),
+            StoreDocs::F16(docs) => docs.dim(),
+            StoreDocs::Bf16(docs) => docs.dim(),
+            StoreDocs::Int8(docs) => docs.dim(),
+        }
+    }
+
+    /// Dim of the embeddings before zero padding.
+    pub fn logical_dim(&self) -> usize {
+        self.logical_dim
+    }
+
+    /// `query` padded with zeros to `dim()` to score against the stored
+    /// rows; unchanged when it already has `dim()`. Fails unless it has
+    /// `logical_dim()` or `dim()` dims.
+    pub fn pad_query(&self, query: QueryEmbeddings) -> Result<QueryEmbeddings, ScoreError> {
+        match query.dim() {
+            d if d == self.dim() => Ok(query),
+            d if d == self.logical_dim => query.padded_to(self.dim()),
+            d => Err(ScoreError::DimMismatch {
+                operand: "query",
+                expected: self.logical_dim,
+                actual: d,
+            }),
+        }
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        match &self.docs {
+            StoreDocs::F32(docs) => docs.doc_len(i),
+            StoreDocs::F16(docs) => docs.doc_len(i),
+            StoreDocs::Bf16(docs) => docs.doc_len(i),
+            StoreDocs::Int8(docs) => docs.doc_len(i),
+        }
+    }
+
+    /// Rows of bucket padding at the end of document `i`, included in
+    /// `doc_len`.
+    pub fn padding(&self, i: DocId) -> usize {
+        self.padding.get(i).copied().unwrap_or(0)
+    }
+
+    /// (token count, number of documents) per distinct document length,
+    /// ascending.
+    pub fn length_buckets(&self) -> Vec<(usize, usize)> {
+        length_histogram((0..self.len()).map(|i| self.doc_len(i)))
+    }
+
+    /// Bytes of embedding payload held: rows, plus scales for int8.
+    pub fn memory_bytes(&self) -> usize {
+        match &self.docs {
+            StoreDocs::F32(docs) => docs.data().len() * 4,
+            StoreDocs::F16(docs) => docs.data().len() * 2,
+            StoreDocs::Bf16(docs) => (0..docs.len()).map(|i| docs.doc(i).len() * 2).sum(),
+            StoreDocs::Int8(docs) => docs.data().len() + docs.scales().len() * 4,
+        }
+    }
+
+    /// Write the store to `path`.
+    pub fn save(&self, path: &Path) -> Result<(), StoreError> {
+        trace_span!(DEBUG, "maxsim.store_save", path = %path.display(), docs = self.len());
+        let dim = self.dim();
+        let (layout, operand, arch) = match &self.docs {
+            StoreDocs::Bf16(docs) => (docs.layout(), docs.operand(), docs.arch()),
+            _ => (VnniLayout::Flat, Operand::A, ArchFamily::Generic),
+        };
+        let granularity = match &self.docs {
+            StoreDocs::Int8(docs) => Some(docs.granularity()),
+            _ => None,
+        };
+        let payload = Payload::new(self.dtype(), dim, layout, granularity);
+
+        let mut out = BufWriter::new(File::create(path)?);
+        write_header(&mut out, MAGIC, self.dtype().id(), dim, self.len())?;
+        let fields = [
+            similarity_id(self.similarity),
+            layout_id(layout, operand),
+            arch.id(),
+            granularity.map_or(0, granularity_id),
+        ];
+        for field in fields {
+            out.write_all(&field.to_le_bytes())?;
+        }
+        out.write_all(&(self.logical_dim as u64).to_le_bytes())?;
+        let buckets = self.length_buckets();
+        out.write_all(&(buckets.len() as u64).to_le_bytes())?;
+        for &(tokens, docs) in &buckets {
+            out.write_all(&(tokens as u64).to_le_bytes())?;
+            out.write_all(&(docs as u64).to_le_bytes())?;
+        }
+        for i in 0..self.len() {
+            out.write_all(&(self.padding(i) as u64).to_le_bytes())?;
+        }
+
+        let lens: Vec<usize> = (0..self.len()).map(|i| self.doc_len(i)).collect();
+        let table_start = BUCKETS_START + buckets.len() * BUCKET_LEN + self.len() * 8;
+        let doc_bytes = |tokens| {
+            payload
+                .doc_bytes(tokens)
+                .expect("document sizes of an in-memory store fit in usize")
+        };
+        write_docs(&mut out, table_start, &lens, doc_bytes, |out, i| {
+            self.write_doc(out, i)
+        })?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Read a store written by `save`. A bf16 store packed for another CPU
+    /// family is repacked for this one, as `PackedDocStore::open` does by
+    /// default.
+    pub fn load(path: &Path) -> Result<Self, StoreError> {
+        Self::load_checked(path, None)
+    }
+
+    /// `load`, failing with `DtypeMismatch` unless the file holds `dtype`
+    /// embeddings.
+    pub fn load_as(path: &Path, dtype: StoreDtype) -> Result<Self, StoreError> {
+        Self::load_checked(path, Some(dtype))
+    }
+
+    fn load_checked(path: &Path, expected: Option<StoreDtype>) -> Result<Self, StoreError> {
+        trace_span!(DEBUG, "maxsim.store_load", path = %path.display());
+        let bytes = std::fs::read(path)?;
+        let (dtype_id, dim, n_docs) = read_header_any(&bytes, MAGIC)?;
+        if let Some(expected) = expected.filter(|e| e.id() != dtype_id) {
+            return Err(dtype_error(dtype_id, expected.id()));
+        }
+        let dtype = StoreDtype::from_id(dtype_id)
+            .ok_or(StoreError::UnsupportedDtype { dtype: dtype_id })?;
+        if bytes.len() < BUCKETS_START {
+            return Err(StoreError::Corrupt {
+                reason: "file is shorter than the header",
+            });
+        }
+        // No row may take more bytes than an allocation can hold.
+        if dim > isize::MAX as usize / 4 {
+            return Err(StoreError::Corrupt {
+                reason: "dim is too large",
+            });
+        }
+        let similarity = match read_u32(&bytes, HEADER_LEN) {
+            0 => Similarity::Dot,
+            1 => Similarity::Cosine,
+            _ => {
+                return Err(StoreError::Corrupt {
+                    reason: "unknown similarity",
+                })
+            }
+        };
+        let (layout, operand, arch) = match dtype {
+            StoreDtype::Bf16 => read_packing(&bytes, HEADER_LEN + 4)?,
+            _ => (VnniLayout::Flat, Operand::A, ArchFamily::Generic),
+        };
+        let granularity = match (dtype, read_u32(&bytes, HEADER_LEN + 12)) {
+            (StoreDtype::Int8, 0) => Some(ScaleGranularity::PerToken),
+            (StoreDtype::Int8, 1) => Some(ScaleGranularity::PerDocument),
+            (StoreDtype::Int8, _) => {
+                return Err(StoreError::Corrupt {
+                    reason: "unknown scale granularity",
+                })
+            }
+            _ => None,
+        };
+
+        let logical_dim = read_u64(&bytes, HEADER_LEN + 16) as usize;
+        if logical_dim > dim {
+            return Err(StoreError::Corrupt {
+                reason: "logical dim exceeds the stored dim",
+            });
+        }
+        let n_buckets = read_u64(&bytes, HEADER_LEN + 24) as usize;
+        let padding_start = n_buckets
+            .checked_mul(BUCKET_LEN)
+            .and_then(|len| len.checked_add(BUCKETS_START))
+            .filter(|&end| end <= bytes.len())
+            .ok_or(StoreError::Corrupt {
+                reason: "bucket table runs past the end of the file",
+            })?;
+        let table_start = n_docs
+            .checked_mul(8)
+            .and_then(|len| len.checked_add(padding_start))
+            .filter(|&end| end <= bytes.len())
+            .ok_or(StoreError::Corrupt {
+                reason: "padding table runs past the end of the file",
+            })?;
+        let buckets: Vec<(usize, usize)> = (BUCKETS_START..padding_start)
+            .step_by(BUCKET_LEN)
+            .map(|at| {
+                (
+                    read_u64(&bytes, at) as usize,
+                    read_u64(&bytes, at + 8) as usize,
+                )
+            })
+            .collect();
+        let payload = Payload::new(dtype, dim, layout, granularity);
+        let extents = read_extents(&bytes, table_start, n_docs, |t| payload.doc_bytes(t))?;
+        if length_histogram(extents.iter().map(|&(_, tokens)| tokens)) != buckets {
+            return Err(StoreError::Corrupt {
+                reason: "bucket table does not match the documents",
+            });
+        }
+        let padding: Vec<usize> = (padding_start..table_start)
+            .step_by(8)
+            .map(|at| read_u64(&bytes, at) as usize)
+            .collect();
+        // At least one real row must remain.
+        if (padding.iter().zip(&extents)).any(|(&rows, &(_, tokens))| rows > 0 && rows >= tokens) {
+            return Err(StoreError::Corrupt {
+                reason: "padding covers a whole document",
+            });
+        }
+
+        let mut offsets = Vec::with_capacity(n_docs + 1);
+        offsets.push(0usize);
+        for (i, &(_, tokens)) in extents.iter().enumerate() {
+            // Only zero-sized rows leave token counts unbounded by the file.
+            let end = offsets[i].checked_add(tokens).ok_or(StoreError::Corrupt {
+                reason: "token counts overflow",
+            })?;
+            offsets.push(end);
+        }
+        let invalid = |_| StoreError::Corrupt {
+            reason: "documents do not form a valid collection",
+        };
+        let docs = match dtype {
+            StoreDtype::F32 => {
+                let data = read_values(&bytes, &extents, dim * 4, f32::from_le_bytes);
+                StoreDocs::F32(DocCollection::new(data, offsets, dim).map_err(invalid)?)
+            }
+            StoreDtype::F16 => {
+                let data = read_values(&bytes, &extents, dim * 2, u16::from_le_bytes);
+                StoreDocs::F16(F16DocCollection::new(data, offsets, dim).map_err(invalid)?)
+            }
+            StoreDtype::Bf16 => {
+                let k = packed_k(layout, dim);
+                let data = read_values(&bytes, &extents, k * 2, u16::from_le_bytes);
+                let packed = extents
+                    .iter()
+                    .zip(&offsets)
+                    .map(|(&(_, tokens), &first)| (first * k, tokens))
+                    .collect();
+                let store = PackedDocStore::from_owned(
+                    data,
+                    dim,
+                    (layout, operand, arch),
+                    packed,
+                    OnMismatch::Repack,
+                )?;
+                StoreDocs::Bf16(store)
+            }
+            StoreDtype::Int8 => {
+                let granularity = granularity.expect("int8 stores record a granularity");
+                let mut data = AlignedVec::with_capacity(offsets[n_docs] * dim);
+                let mut scales = Vec::new();
+                for &(start, tokens) in &extents {
+                    let n_scales = scale_count(granularity, tokens);
+                    let rows = start + n_scales * 4;
+                    let raw = &bytes[start..rows];
+                    scales.extend(raw.chunks_exact(4).map(|b| f32::from_le_bytes(le(b))));
+                    data.extend(bytes[rows..rows + tokens * dim].iter().map(|&b| b as i8));
+                }
+                let docs = Int8DocCollection::new(data, scales, granularity, offsets, dim);
+                StoreDocs::Int8(docs.map_err(invalid)?)
+            }
+        };
+        Ok(Self {
+            docs,
+            similarity,
+            logical_dim,
+            padding,
+        })
+    }
+
+    /// Payload of document `i` in the file encoding.
+    fn write_doc<W: Write>(&self, out: &mut W, i: DocId) -> std::io::Result<()> {
+        match &self.docs {
+            StoreDocs::F32(docs) => {
+                let mut values = docs.doc(i).iter();
+                values.try_for_each(|x| out.write_all(&x.to_le_bytes()))
+            }
+            StoreDocs::F16(docs) => {
+                let mut values = docs.doc(i).iter();
+                values.try_for_each(|x| out.write_all(&x.to_le_bytes()))
+            }
+            StoreDocs::Bf16(docs) => {
+                let mut values = docs.doc(i).iter();
+                values.try_for_each(|x| out.write_all(&x.to_le_bytes()))
+            }
+            StoreDocs::Int8(docs) => {
+                let mut scales = docs.doc_scales(i).iter();
+                scales.try_for_each(|x| out.write_all(&x.to_le_bytes()))?;
+                let rows: Vec<u8> = docs.doc(i).iter().map(|&v| v as u8).collect();
+                out.write_all(&rows)
+            }
+        }
+    }
+}
+
+/// What `DocStoreBuilder::push` does with a document longer than the
+/// largest bucket.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OnOverflow {
+    /// Store it as consecutive documents of at most the largest bucket's
+    /// length.
+    #[default]
+    Split,
+    /// Fail with `ScoreError::DocumentTooLong`.
+    Error,
+}
+
+/// Sizes of what a `DocStoreBuilder` holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BuildStats {
+    /// Documents stored, split parts counted separately.
+    pub documents: usize,
+    /// Tokens stored, padding included.
+    pub tokens: usize,
+    /// Padding tokens added to fill buckets.
+    pub padding_tokens: usize,
+    /// Bytes of embedding payload stored, as `DocStore::memory_bytes`
+    /// counts them before bf16 packing.
+    pub bytes: usize,
+    /// Part of `bytes` that is zero padding of the token rows to the
+    /// padded dim.
+    pub dim_padding_bytes: usize,
+    /// Pages the rows buffer got under the builder's `AllocPolicy`.
+    pub pages: PageBacking,
+}
+
+impl BuildStats {
+    /// Padding as a percentage of the stored tokens.
+    pub fn padding_percent(&self) -> f64 {
+        if self.tokens == 0 {
+            return 0.0;
+        }
+        100.0 * self.padding_tokens as f64 / self.tokens as f64
+    }
+}
+
+/// Builds a `DocStore` one document at a time into pre-allocated storage,
+/// encoding each document to the target dtype as it is pushed.
+///
+/// With buckets configured every stored document has one of the bucket
+/// lengths, so a batch sets up one GEMM per bucket. A document is padded
+/// to the smallest bucket that fits by repeating its last token row: a
+/// repeated token never changes a query token's best match, and the store
+/// records the padding (`DocStore::padding`) so the document-side
+/// directions skip it. Scores are those of the unpadded documents.
+#[derive(Clone, Debug)]
+pub struct DocStoreBuilder {
+    dim: usize,
+    /// `dim` rounded up for the kernels; rows are stored this wide.
+    padded_dim: usize,
+    dtype: StoreDtype,
+    similarity: Similarity,
+    /// Ascending, distinct, non-zero.
+    buckets: Vec<usize>,
+    /// Document lengths are rounded up to a multiple of this, after buckets.
+    token_multiple: usize,
+    on_overflow: OnOverflow,
+    operand: Operand,
+    /// Typical query length to pick the operand from at `finish`.
+    auto_q_len: Option<usize>,
+    alloc: AllocPolicy,
+    /// (documents, tokens) to allocate for.
+    capacity: (usize, usize),
+    rows: Option<EncodedRows>,
+    offsets: Vec<usize>,
+    /// Rows of bucket padding per stored document.
+    padding: Vec<usize>,
+    padding_tokens: usize,
+    /// One (padded) document part as f32 rows.
+    part: Vec<f32>,
+}
+
+/// Token rows pushed so far, in the target encoding.
+#[derive(Clone, Debug)]
+enum EncodedRows {
+    F32(AlignedVec<f32>),
+    /// f16 or bf16 bit patterns.
+    Half(AlignedVec<u16>),
+    /// bf16 documents packed for this CPU's kernels as they are pushed,
+    /// with one document's worth of scratch (bf16 rows, packed rows).
+    Packed {
+        data: AlignedVec<u16>,
+        packing: (VnniLayout, Operand, ArchFamily),
+        scratch: (AlignedVec<u16>, AlignedVec<u16>),
+    },
+    /// Rows with one scale per token.
+    Int8(AlignedVec<i8>, Vec<f32>),
+}
+
+impl EncodedRows {
+    /// No rows of `dtype` yet, with room for `capacity` values. bf16 rows
+    /// are packed as `operand` when it is known up front, else kept flat.
+    fn new(
+        dtype: StoreDtype,
+        (capacity, dim): (usize, usize),
+        operand: Option<Operand>,
+        alloc: AllocPolicy,
+    ) -> Self {
+        match (dtype, operand) {
+            (StoreDtype::Bf16, Some(operand)) => {
+                let arch = ArchFamily::detect();
+                let capacity = capacity / dim.max(1) * packed_k(arch.layout(), dim);
+                EncodedRows::Packed {
+                    data: AlignedVec::with_capacity_in(capacity, alloc),
+                    packing: (arch.layout(), operand, arch),
+                    scratch: Default::default(),
+                }
+            }
+            (StoreDtype::F32, _) => EncodedRows::F32(AlignedVec::with_capacity_in(capacity, alloc)),
+            (StoreDtype::F16 | StoreDtype::Bf16, _) => {
+                EncodedRows::Half(AlignedVec::with_capacity_in(capacity, alloc))
+            }
+            (StoreDtype::Int8, _) => EncodedRows::Int8(
+                AlignedVec::with_capacity_in(capacity, alloc),
+                Vec::with_capacity(capacity / dim.max(1)),
+            ),
+        }
+    }
+
+    fn pages(&self) -> PageBacking {
+        match self {
+            EncodedRows::F32(data) => data.page_backing(),
+            EncodedRows::Half(data) | EncodedRows::Packed { data, .. } => data.page_backing(),
+            EncodedRows::Int8(data, _) => data.page_backing(),
+        }
+    }
+}
+
+impl DocStoreBuilder {
+    /// Empty f32 builder for `dim`-dimensional tokens, with no buckets.
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            padded_dim: dim,
+            dtype: StoreDtype::F32,
+            similarity: Similarity::Dot,
+            buckets: Vec::new(),
+            token_multiple: 1,
+            on_overflow: OnOverflow::default(),
+            operand: Operand::default(),
+            auto_q_len: None,
+            alloc: AllocPolicy::Heap,
+            capacity: (0, 0),
+            rows: None,
+            offsets: vec![0],
+            padding: Vec::new(),
+            padding_tokens: 0,
+            part: Vec::new(),
+        }
+    }
+
+    /// Allocate for `docs` documents of `total_tokens` tokens up front
+    /// (padding included), instead of growing while pushing.
+    pub fn with_capacity(mut self, docs: usize, total_tokens: usize) -> Self {
+        self.capacity = (docs, total_tokens);
+        self.offsets.reserve(docs);
+        self.padding.reserve(docs);
+        self
+    }
+
+    /// Pad every document to the smallest of `lengths` that fits it.
+    pub fn with_buckets(mut self, lengths: &[usize]) -> Self {
+        self.buckets = lengths.iter().copied().filter(|&len| len > 0).collect();
+        self.buckets.sort_unstable();
+        self.buckets.dedup();
+        self
+    }
+
+    /// Pad every document (or its bucket length) to the next multiple of
+    /// `multiple` tokens, as buckets are padded. bf16 documents packed as
+    /// A on an AMX CPU are always padded to whole tiles as well
+    /// (`ArchFamily::token_multiple`), so the AMX path reads them in place.
+    pub fn with_token_multiple(mut self, multiple: usize) -> Self {
+        self.token_multiple = multiple.max(1);
+        self
+    }
+
+    /// Zero-pad every token row to the next multiple of `multiple` dims
+    /// (e.g. 32 or 64 for the bf16 and AMX kernels). Scores are unchanged;
+    /// queries are padded with `DocStore::pad_query`.
+    pub fn with_dim_padding(mut self, multiple: usize) -> Self {
+        self.padded_dim = self.dim.next_multiple_of(multiple.max(1));
+        self
+    }
+
+    /// Store embeddings as `dtype`: f32, f16, bf16 (packed for this CPU's
+    /// kernels at `finish`), or int8 with per-token scales.
+    pub fn with_storage(mut self, dtype: StoreDtype) -> Self {
+        self.dtype = dtype;
+        self
+    }
+
+    /// Prepare rows for `similarity`: `Cosine` L2-normalizes each token as
+    /// it is pushed.
+    pub fn with_similarity(mut self, similarity: Similarity) -> Self {
+        self.similarity = similarity;
+        self
+    }
+
+    pub fn with_overflow(mut self, on_overflow: OnOverflow) -> Self {
+        self.on_overflow = on_overflow;
+        self
+    }
+
+    /// Pack bf16 documents as `operand` of the similarity GEMM. Ignored
+    /// for other dtypes.
+    pub fn with_operand(mut self, operand: Operand) -> Self {
+        (self.operand, self.auto_q_len) = (operand, None);
+        self
+    }
+
+    /// Allocate the stored rows per `policy`, e.g. on huge pages; see
+    /// `BuildStats::pages` for what was obtained.
+    pub fn with_alloc(mut self, policy: AllocPolicy) -> Self {
+        self.alloc = policy;
+        self
+    }
+
+    /// Pick the bf16 operand at `finish` with `Operand::auto`, from
+    /// `typical_q_len` and the mean stored document length.
+    pub fn with_auto_operand(mut self, typical_q_len: usize) -> Self {
+        self.auto_q_len = Some(typical_q_len);
+        self
+    }
+
+    /// Append one `[tokens, dim]` f32 document, converted to the storage
+    /// dtype as it goes in: rounded to f16 or bf16 (and packed for this
+    /// CPU's bf16 kernels unless the operand is picked at `finish`), or
+    /// quantized to int8 with a scale per token, by the same routines as
+    /// the standalone conversions. Only one document is held in f32.
+    /// Returns the positions it was stored at, more than one when it was
+    /// split.
+    pub fn push(&mut self, doc: &[f32]) -> Result<Range<DocId>, ScoreError> {
+        let (dim, padded_dim) = (self.dim, self.padded_dim);
+        if !doc.len().is_multiple_of(dim) {
+            return Err(ScoreError::DimMismatch {
+                operand: "doc",
+                expected: doc.len().checked_div(dim).unwrap_or(0) * dim,
+                actual: doc.len(),
+            });
+        }
+        let tokens = doc.len().checked_div(dim).unwrap_or(0);
+        if tokens == 0 {
+            return Err(ScoreError::EmptyDocument);
+        }
+        let max = self.buckets.last().copied().unwrap_or(usize::MAX);
+        if tokens > max && self.on_overflow == OnOverflow::Error {
+            return Err(ScoreError::DocumentTooLong { tokens, max });
+        }
+
+        let first = self.offsets.len() - 1;
+        let multiple = self.doc_multiple();
+        let mut rows = std::mem::take(&mut self.part);
+        for part in doc.chunks(max.saturating_mul(dim)) {
+            let part_tokens = part.len() / dim;
+            let bucket = self
+                .buckets
+                .iter()
+                .copied()
+                .find(|&len| len >= part_tokens)
+                .unwrap_or(part_tokens)
+                .next_multiple_of(multiple);
+            rows.clear();
+            for row in part.chunks_exact(dim) {
+                rows.extend_from_slice(row);
+                rows.resize(rows.len() + padded_dim - dim, 0.0);
+            }
+            if self.similarity == Similarity::Cosine {
+                normalize_rows_inplace(&mut rows, padded_dim);
+            }
+            let last = rows.len() - padded_dim;
+            for _ in part_tokens..bucket {
+                rows.extend_from_within(last..last + padded_dim);
+            }
+            self.append(&rows);
+            self.padding.push(bucket - part_tokens);
+            self.padding_tokens += bucket - part_tokens;
+            self.offsets
+                .push(self.offsets[self.offsets.len() - 1] + bucket);
+        }
+        self.part = rows;
+        Ok(first..self.offsets.len() - 1)
+    }
+
+    /// `push` for a bf16 document. Widening is exact, so a bf16 store
+    /// keeps the values as given.
+    #[cfg(feature = "half")]
+    pub fn push_bf16(&mut self, doc: &[half::bf16]) -> Result<Range<DocId>, ScoreError> {
+        let mut widened = vec![0.0; doc.len()];
+        convert_half_bf16_to_f32(doc, &mut widened);
+        self.push(&widened)
+    }
+
+    /// `push` for an f16 document. Widening is exact, so an f16 store
+    /// keeps the values as given.
+    #[cfg(feature = "half")]
+    pub fn push_f16(&mut self, doc: &[half::f16]) -> Result<Range<DocId>, ScoreError> {
+        let mut widened = vec![0.0; doc.len()];
+        convert_half_f16_to_f32(doc, &mut widened);
+        self.push(&widened)
+    }
+
+    /// Multiple of tokens stored documents are padded to: `token_multiple`,
+    /// and whole AMX tiles for bf16 rows packed as A on AMX CPUs.
+    fn doc_multiple(&self) -> usize {
+        let tile = match (self.dtype, self.packed_operand()) {
+            (StoreDtype::Bf16, Some(Operand::A)) => ArchFamily::detect().token_multiple(),
+            _ => 1,
+        };
+        // The least common multiple; `tile` is at most 32.
+        (1..=tile)
+            .map(|n| n * self.token_multiple)
+            .find(|m| m.is_multiple_of(tile))
+            .unwrap_or(self.token_multiple * tile)
+    }
+
+    /// Operand to pack bf16 rows as while pushing; `None` when it is only
+    /// known at `finish`.
+    fn packed_operand(&self) -> Option<Operand> {
+        self.auto_q_len.is_none().then_some(self.operand)
+    }
+
+    /// Encode `rows` onto the stored rows.
+    fn append(&mut self, rows: &[f32]) {
+        let dim = self.padded_dim;
+        let (dtype, capacity) = (self.dtype, self.capacity.1 * dim);
+        let (operand, alloc) = (self.packed_operand(), self.alloc);
+        let stored = self
+            .rows
+            .get_or_insert_with(|| EncodedRows::new(dtype, (capacity, dim), operand, alloc));
+        match stored {
+            EncodedRows::F32(data) => data.extend_from_slice(rows),
+            EncodedRows::Half(data) => {
+                let start = data.len();
+                data.resize(start + rows.len(), 0);
+                match dtype {
+                    StoreDtype::F16 => convert_f32_to_f16(rows, &mut data[start..]),
+                    _ => convert_f32_to_bf16(rows, &mut data[start..]),
+                }
+            }
+            EncodedRows::Packed {
+                data,
+                packing: (layout, operand, _),
+                scratch: (bf16, packed),
+            } => {
+                bf16.resize(rows.len(), 0);
+                convert_f32_to_bf16(rows, bf16);
+                pack_doc(bf16, rows.len() / dim, dim, (*layout, *operand), packed);
+                data.extend_from_slice(packed);
+            }
+            EncodedRows::Int8(data, scales) => {
+                let start = data.len();
+                data.resize(start + rows.len(), 0);
+                for (row, out) in rows
+                    .chunks_exact(dim)
+                    .zip(data[start..].chunks_exact_mut(dim))
+                {
+                    let scale = max_abs_scale(row);
+                    quantize_i8(row, scale, out);
+                    scales.push(scale);
+                }
+            }
+        }
+    }
+
+    /// Sizes of what has been pushed so far.
+    pub fn stats(&self) -> BuildStats {
+        let tokens = self.offsets[self.offsets.len() - 1];
+        let value_bytes = match self.dtype {
+            StoreDtype::F32 => 4,
+            StoreDtype::F16 | StoreDtype::Bf16 => 2,
+            StoreDtype::Int8 => 1,
+        };
+        let scale_bytes = match self.dtype {
+            StoreDtype::Int8 => tokens * 4,
+            _ => 0,
+        };
+        BuildStats {
+            documents: self.offsets.len() - 1,
+            tokens,
+            padding_tokens: self.padding_tokens,
+            bytes: tokens * self.padded_dim * value_bytes + scale_bytes,
+            dim_padding_bytes: tokens * (self.padded_dim - self.dim) * value_bytes,
+            pages: self
+                .rows
+                .as_ref()
+                .map_or(PageBacking::Normal, EncodedRows::pages),
+        }
+    }
+
+    /// The finished store.
+    pub fn finish(self) -> Result<DocStore, ScoreError> {
+        let (dim, offsets) = (self.padded_dim, self.offsets);
+        let n_docs = offsets.len() - 1;
+        let operand = match self.auto_q_len {
+            Some(q_len) => Operand::auto(q_len, offsets[n_docs] / n_docs.max(1)),
+            None => self.operand,
+        };
+        let rows = (self.rows)
+            .unwrap_or_else(|| EncodedRows::new(self.dtype, (0, dim), Some(operand), self.alloc));
+        let docs = match (rows, self.dtype) {
+            (EncodedRows::F32(data), _) => StoreDocs::F32(DocCollection::new(data, offsets, dim)?),
+            (EncodedRows::Half(data), StoreDtype::F16) => {
+                StoreDocs::F16(F16DocCollection::new(data, offsets, dim)?)
+            }
+            (EncodedRows::Half(data), _) => {
+                let extents = offsets.windows(2).map(|w| (w[0] * dim, w[1] - w[0]));
+                let store = PackedDocStore::from_owned(
+                    data,
+                    dim,
+                    (VnniLayout::Flat, operand, ArchFamily::Generic),
+                    extents.collect(),
+                    OnMismatch::Repack,
+                )
+                .expect("repacking never fails");
+                StoreDocs::Bf16(store)
+            }
+            (EncodedRows::Packed { data, packing, .. }, _) => {
+                let k = packed_k(packing.0, dim);
+                let extents = offsets.windows(2).map(|w| (w[0] * k, w[1] - w[0]));
+                let store = PackedDocStore::from_owned(
+                    data,
+                    dim,
+                    packing,
+                    extents.collect(),
+                    OnMismatch::Repack,
+                )
+                .expect("packed for this CPU");
+                StoreDocs::Bf16(store)
+            }
+            (EncodedRows::Int8(data, scales), _) => StoreDocs::Int8(Int8DocCollection::new(
+                data,
+                scales,
+                ScaleGranularity::PerToken,
+                offsets,
+                dim,
+            )?),
+        };
+        Ok(DocStore::new(docs, self.similarity)
+            .with_logical_dim(self.dim)
+            .with_padding(self.padding))
+    }
+}
+
+/// Size of one document's payload from its token count.
+struct Payload {
+    /// Bytes of one token's row.
+    row_bytes: usize,
+    /// int8 only: how many f32 scales precede the rows.
+    scales: Option<ScaleGranularity>,
+}
+
+impl Payload {
+    fn new(
+        dtype: StoreDtype,
+        dim: usize,
+        layout: VnniLayout,
+        scales: Option<ScaleGranularity>,
+    ) -> Self {
+        let row_bytes = match dtype {
+            StoreDtype::F32 => dim.saturating_mul(4),
+            StoreDtype::F16 => dim.saturating_mul(2),
+            StoreDtype::Bf16 => packed_k(layout, dim).saturating_mul(2),
+            StoreDtype::Int8 => dim,
+        };
+        Self { row_bytes, scales }
+    }
+
+    /// `None` on overflow.
+    fn doc_bytes(&self, tokens: usize) -> Option<usize> {
+        let scales = self.scales.map_or(0, |g| scale_count(g, tokens));
+        tokens
+            .checked_mul(self.row_bytes)?
+            .checked_add(scales.checked_mul(4)?)
+    }
+}
+
+/// f32 scales of an int8 document of `tokens` tokens.
+fn scale_count(granularity: ScaleGranularity, tokens: usize) -> usize {
+    match granularity {
+        ScaleGranularity::PerToken => tokens,
+        ScaleGranularity::PerDocument => 1,
+    }
+}
+
+/// Every document's `row_bytes`-per-token payload decoded with `decode`
+/// and concatenated.
+fn read_values<T: Copy, const N: usize>(
+    bytes: &[u8],
+    extents: &[(usize, usize)],
+    row_bytes: usize,
+    decode: fn([u8; N]) -> T,
+) -> AlignedVec<T> {
+    let total: usize = extents
+        .iter()
+        .map(|&(_, tokens)| tokens * row_bytes / N)
+        .sum();
+    let mut values = AlignedVec::with_capacity(total);
+    for &(start, tokens) in extents {
+        let raw = &bytes[start..start + tokens * row_bytes];
+        values.extend(raw.chunks_exact(N).map(|b| decode(le(b))));
+    }
+    values
+}
+
+/// A `chunks_exact(N)` chunk as an array.
+fn le<const N: usize>(chunk: &[u8]) -> [u8; N] {
+    chunk.try_into().expect("chunks_exact yields N bytes")
+}
+
+/// (length, count) per distinct value of `lens`, ascending.
+fn length_histogram(lens: impl Iterator<Item = usize>) -> Vec<(usize, usize)> {
+    let mut lens: Vec<usize> = lens.collect();
+    lens.sort_unstable();
+    lens.chunk_by(|a, b| a == b)
+        .map(|run| (run[0], run.len()))
+        .collect()
+}
+
+fn similarity_id(similarity: Similarity) -> u32 {
+    match similarity {
+        Similarity::Dot => 0,
+        Similarity::Cosine => 1,
+    }
+}
+
+fn granularity_id(granularity: ScaleGranularity) -> u32 {
+    match granularity {
+        ScaleGranularity::PerToken => 0,
+        ScaleGranularity::PerDocument => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+    use crate::collection::QueryEmbeddings;
+    use crate::scorer::{Aggregation, Direction, MaxSimScorer, ScorerConfig};
+    use crate::store::scratch_path;
+
+    const DIM: usize = 12;
+    const LENS: [usize; 6] = [3, 7, 1, 7, 12, 5];
+
+    fn build(builder: DocStoreBuilder) -> DocStore {
+        let mut builder = builder;
+        for (i, &len) in LENS.iter().enumerate() {
+            builder.push(&unit_rows(len, DIM, i as u64)).unwrap();
+        }
+        builder.finish().unwrap()
+    }
+
+    /// An int8 store with one scale per document, which the builder does
+    /// not produce.
+    fn per_document_int8() -> DocStore {
+        let (mut data, mut scales, mut offsets) = (Vec::new(), Vec::new(), vec![0]);
+        for (i, &len) in LENS.iter().enumerate() {
+            let rows = unit_rows(len, DIM, i as u64);
+            let scale = max_abs_scale(&rows);
+            let mut quantized = vec![0i8; rows.len()];
+            quantize_i8(&rows, scale, &mut quantized);
+            data.extend(quantized);
+            scales.push(scale);
+            offsets.push(offsets[i] + len);
+        }
+        let docs =
+            Int8DocCollection::new(data, scales, ScaleGranularity::PerDocument, offsets, DIM);
+        DocStore::new(StoreDocs::Int8(docs.unwrap()), Similarity::Dot)
+    }
+
+    fn stores() -> Vec<(&'static str, DocStore)> {
+        vec![
+            ("f32", build(DocStoreBuilder::new(DIM))),
+            (
+                "f32-padded",
+                build(
+                    DocStoreBuilder::new(DIM)
+                        .with_buckets(&[4, 8, 16])
+                        .with_dim_padding(16)
+                        .with_similarity(Similarity::Cosine),
+                ),
+            ),
+            (
+                "f16",
+                build(DocStoreBuilder::new(DIM).with_storage(StoreDtype::F16)),
+            ),
+            (
+                "bf16",
+                build(DocStoreBuilder::new(DIM).with_storage(StoreDtype::Bf16)),
+            ),
+            (
+                "bf16-b",
+                build(
+                    DocStoreBuilder::new(DIM)
+                        .with_storage(StoreDtype::Bf16)
+                        .with_operand(Operand::B),
+                ),
+            ),
+            (
+                "int8",
+                build(DocStoreBuilder::new(DIM).with_storage(StoreDtype::Int8)),
+            ),
+            ("int8-per-doc", per_document_int8()),
+        ]
+    }
+
+    /// Document `i` as `save` encodes it.
+    fn payload(store: &DocStore, i: DocId) -> Vec<u8> {
+        let mut out = Vec::new();
+        store.write_doc(&mut out, i).unwrap();
+        out
+    }
+
+    fn scores(store: &DocStore) -> Vec<f32> {
+        let config = ScorerConfig::default()
+            .with_num_threads(1)
+            .with_similarity(store.similarity());
+        let scorer = MaxSimScorer::from_config(config, store).unwrap();
+        let query = QueryEmbeddings::new(unit_rows(4, DIM, 99), 4, DIM).unwrap();
+        let query = store.pad_query(query).unwrap();
+        scorer.score_batch(&query).unwrap()
+    }
+
+    #[test]
+    fn every_dtype_round_trips() {
+        for (name, store) in stores() {
+            let path = scratch_path(&format!("round-trip-{name}"));
+            store.save(&path).unwrap();
+            let loaded = DocStore::load_as(&path, store.dtype());
+            std::fs::remove_file(&path).unwrap();
+            let loaded = loaded.unwrap();
+
+            assert_eq!(loaded.dtype(), store.dtype(), "{name}");
+            assert_eq!(loaded.len(), store.len(), "{name}");
+            assert_eq!(loaded.dim(), store.dim(), "{name}");
+            assert_eq!(loaded.logical_dim(), store.logical_dim(), "{name}");
+            assert_eq!(loaded.similarity(), store.similarity(), "{name}");
+            assert_eq!(loaded.length_buckets(), store.length_buckets(), "{name}");
+            assert_eq!(loaded.memory_bytes(), store.memory_bytes(), "{name}");
+            for i in 0..store.len() {
+                assert_eq!(loaded.doc_len(i), store.doc_len(i), "{name} doc {i}");
+                assert_eq!(payload(&loaded, i), payload(&store, i), "{name} doc {i}");
+            }
+            if let (StoreDocs::Bf16(a), StoreDocs::Bf16(b)) = (loaded.docs(), store.docs()) {
+                assert_eq!(
+                    (a.layout(), a.operand(), a.arch()),
+                    (b.layout(), b.operand(), b.arch())
+                );
+            }
+            if let (StoreDocs::Int8(a), StoreDocs::Int8(b)) = (loaded.docs(), store.docs()) {
+                assert_eq!(a.granularity(), b.granularity());
+            }
+            let (before, after) = (scores(&store), scores(&loaded));
+            let bits = |s: &[f32]| s.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(&after), bits(&before), "{name}");
+        }
+    }
+
+    #[test]
+    fn load_as_names_both_dtypes() {
+        let store = build(DocStoreBuilder::new(DIM).with_storage(StoreDtype::F16));
+        let path = scratch_path("load-as");
+        store.save(&path).unwrap();
+        let err = DocStore::load_as(&path, StoreDtype::F32).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            err,
+            StoreError::DtypeMismatch {
+                stored: StoreDtype::F16,
+                expected: StoreDtype::F32,
+            }
+        ));
+    }
+
+    /// `load` of `bytes` written to a scratch file.
+    fn load_bytes(name: &str, bytes: &[u8]) -> Result<DocStore, StoreError> {
+        let path = scratch_path(name);
+        std::fs::write(&path, bytes).unwrap();
+        let loaded = DocStore::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        for (name, store) in stores() {
+            let path = scratch_path(&format!("truncated-{name}"));
+            store.save(&path).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            for len in 0..bytes.len() {
+                let loaded = load_bytes(&format!("truncated-{name}-{len}"), &bytes[..len]);
+                assert!(
+                    matches!(loaded, Err(StoreError::Corrupt { .. })),
+                    "{name} cut to {len} bytes"
+                );
+            }
+        }
+    }
+
+    fn with_u32(bytes: &[u8], at: usize, value: u32) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    fn with_u64(bytes: &[u8], at: usize, value: u64) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn corrupt_headers_are_errors() {
+        let store = build(DocStoreBuilder::new(DIM).with_storage(StoreDtype::Int8));
+        let path = scratch_path("corrupt");
+        store.save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let table = BUCKETS_START + store.length_buckets().len() * BUCKET_LEN + store.len() * 8;
+
+        let mut magic = bytes.clone();
+        magic[0] ^= 0xff;
+        assert!(matches!(
+            load_bytes("magic", &magic),
+            Err(StoreError::BadMagic)
+        ));
+        assert!(matches!(
+            load_bytes("version", &with_u32(&bytes, 8, 99)),
+            Err(StoreError::UnsupportedVersion { version: 99 })
+        ));
+        assert!(matches!(
+            load_bytes("dtype", &with_u32(&bytes, 12, 99)),
+            Err(StoreError::UnsupportedDtype { dtype: 99 })
+        ));
+
+        let corrupt = [
+            ("dim", with_u64(&bytes, 16, DIM as u64 + 1)),
+            ("n-docs", with_u64(&bytes, 24, u64::MAX)),
+            ("similarity", with_u32(&bytes, HEADER_LEN, 2)),
+            ("scales", with_u32(&bytes, HEADER_LEN + 12, 2)),
+            (
+                "logical-dim",
+                with_u64(&bytes, HEADER_LEN + 16, DIM as u64 + 1),
+            ),
+            ("n-buckets", with_u64(&bytes, HEADER_LEN + 24, u64::MAX / 2)),
+            ("bucket", with_u64(&bytes, BUCKETS_START + 8, 2)),
+            ("start", with_u64(&bytes, table, 1)),
+            ("start-past-end", with_u64(&bytes, table, !63)),
+            ("tokens", with_u64(&bytes, table + 8, u64::MAX)),
+            (
+                "padding",
+                with_u64(&bytes, table - store.len() * 8, u64::MAX),
+            ),
+        ];
+        for (name, bytes) in corrupt {
+            assert!(
+                matches!(load_bytes(name, &bytes), Err(StoreError::Corrupt { .. })),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn huge_dims_are_errors_without_documents() {
+        // With no tokens to read, only the header bounds the row size.
+        for dtype in [
+            StoreDtype::F32,
+            StoreDtype::F16,
+            StoreDtype::Bf16,
+            StoreDtype::Int8,
+        ] {
+            let store = DocStoreBuilder::new(DIM)
+                .with_storage(dtype)
+                .finish()
+                .unwrap();
+            let path = scratch_path(&format!("huge-dim-{}", dtype.id()));
+            store.save(&path).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            for dim in [u64::MAX, u64::MAX / 2 + 1, 1 << 62] {
+                let bytes = with_u64(&bytes, 16, dim);
+                let loaded = load_bytes(&format!("huge-dim-{}-{dim}", dtype.id()), &bytes);
+                assert!(loaded.is_err(), "{dtype:?} dim {dim}");
+            }
+        }
+    }
+
+    #[test]
+    fn flipped_header_bytes_never_panic() {
+        let store = build(
+            DocStoreBuilder::new(DIM)
+                .with_storage(StoreDtype::Bf16)
+                .with_buckets(&[4, 8, 16]),
+        );
+        let path = scratch_path("flipped");
+        store.save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let table = BUCKETS_START + store.length_buckets().len() * BUCKET_LEN + store.len() * 8;
+        for at in 0..table + store.len() * 16 {
+            for bit in [0x01, 0x80] {
+                let mut flipped = bytes.clone();
+                flipped[at] ^= bit;
+                // Either outcome is fine; a panic fails the test.
+                let _ = load_bytes(&format!("flipped-{at}-{bit}"), &flipped);
+            }
+        }
+    }
+
+    /// Documents with values each conversion rounds, saturates or
+    /// flushes.
+    fn awkward_docs() -> Vec<Vec<f32>> {
+        let mut edge = vec![
+            1e-40,
+            -1e-45,
+            6e-8,
+            65504.0,
+            65520.0,
+            1e5,
+            -3e38,
+            f32::MAX,
+            -0.0,
+            1.0 + f32::EPSILON,
+            0.1,
+            -7.75,
+        ];
+        edge.resize(2 * DIM, 0.5);
+        let mut docs: Vec<Vec<f32>> = LENS
+            .iter()
+            .enumerate()
+            .map(|(i, &len)| unit_rows(len, DIM, 50 + i as u64))
+            .collect();
+        docs.push(edge);
+        docs
+    }
+
+    fn pushed(builder: DocStoreBuilder, docs: &[Vec<f32>]) -> DocStore {
+        let mut builder = builder;
+        for doc in docs {
+            builder.push(doc).unwrap();
+        }
+        builder.finish().unwrap()
+    }
+
+    #[test]
+    fn pushed_rows_convert_like_the_standalone_functions() {
+        let docs = awkward_docs();
+        let builders = [
+            DocStoreBuilder::new(DIM).with_storage(StoreDtype::F16),
+            DocStoreBuilder::new(DIM).with_storage(StoreDtype::Bf16),
+            DocStoreBuilder::new(DIM)
+                .with_storage(StoreDtype::Bf16)
+                .with_operand(Operand::B),
+            DocStoreBuilder::new(DIM)
+                .with_storage(StoreDtype::Bf16)
+                .with_auto_operand(4),
+            DocStoreBuilder::new(DIM).with_storage(StoreDtype::Int8),
+        ];
+        for builder in builders {
+            let store = pushed(builder, &docs);
+            for (i, doc) in docs.iter().enumerate() {
+                let tokens = doc.len() / DIM;
+                let mut half = vec![0u16; doc.len()];
+                match store.docs() {
+                    StoreDocs::F16(stored) => {
+                        convert_f32_to_f16(doc, &mut half);
+                        assert_eq!(stored.doc(i), &half[..], "f16 doc {i}");
+                    }
+                    StoreDocs::Bf16(stored) => {
+                        convert_f32_to_bf16(doc, &mut half);
+                        let mut packed = AlignedVec::new();
+                        let packing = (stored.layout(), stored.operand());
+                        pack_doc(&half, tokens, DIM, packing, &mut packed);
+                        assert_eq!(stored.doc(i), &packed[..], "bf16 {packing:?} doc {i}");
+                    }
+                    StoreDocs::Int8(stored) => {
+                        let mut rows = vec![0i8; doc.len()];
+                        let scales: Vec<f32> = doc
+                            .chunks_exact(DIM)
+                            .zip(rows.chunks_exact_mut(DIM))
+                            .map(|(row, out)| {
+                                let scale = max_abs_scale(row);
+                                quantize_i8(row, scale, out);
+                                scale
+                            })
+                            .collect();
+                        assert_eq!(stored.doc(i), &rows[..], "int8 doc {i}");
+                        let bits = |s: &[f32]| s.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+                        assert_eq!(bits(stored.doc_scales(i)), bits(&scales), "int8 doc {i}");
+                    }
+                    StoreDocs::F32(_) => unreachable!("every builder converts"),
+                }
+            }
+        }
+    }
+
+    /// Multiples of 1/8 in [-2, 2], exact in every stored dtype, so that
+    /// any summation order gives the same bits.
+    fn dyadic_docs() -> Vec<Vec<f32>> {
+        LENS.iter()
+            .enumerate()
+            .map(|(d, &len)| {
+                (0..len * DIM)
+                    .map(|i| ((i * 7919 + d * 104_729) % 33) as f32 / 8.0 - 2.0)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Scores of `query` (`[tokens, DIM]`) against `store`, the query
+    /// padded to the store's dim.
+    fn query_scores(store: &DocStore, query: &[f32]) -> Vec<u32> {
+        let config = ScorerConfig::default()
+            .with_num_threads(1)
+            .with_similarity(store.similarity());
+        let scorer = MaxSimScorer::from_config(config, store).unwrap();
+        let query = QueryEmbeddings::new(query.to_vec(), query.len() / DIM, DIM).unwrap();
+        let scores = scorer
+            .score_batch(&store.pad_query(query).unwrap())
+            .unwrap();
+        scores.into_iter().map(f32::to_bits).collect()
+    }
+
+    #[test]
+    fn dim_padding_never_changes_scores() {
+        let docs = dyadic_docs();
+        let query: Vec<f32> = dyadic_docs()[1].iter().rev().copied().collect();
+        for dtype in [StoreDtype::F32, StoreDtype::F16, StoreDtype::Bf16] {
+            let plain = pushed(DocStoreBuilder::new(DIM).with_storage(dtype), &docs);
+            for multiple in [16, 32, 64] {
+                let mut builder = DocStoreBuilder::new(DIM)
+                    .with_storage(dtype)
+                    .with_dim_padding(multiple);
+                for doc in &docs {
+                    builder.push(doc).unwrap();
+                }
+                let stats = builder.stats();
+                let tokens: usize = LENS.iter().sum();
+                let value_bytes = if dtype == StoreDtype::F32 { 4 } else { 2 };
+                assert_eq!(
+                    stats.dim_padding_bytes,
+                    tokens * (multiple - DIM) * value_bytes
+                );
+                let padded = builder.finish().unwrap();
+                assert_eq!((padded.dim(), padded.logical_dim()), (multiple, DIM));
+                assert_eq!(
+                    query_scores(&padded, &query),
+                    query_scores(&plain, &query),
+                    "{dtype:?} padded to {multiple}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bucket_padding_never_changes_scores() {
+        let docs: Vec<Vec<f32>> = LENS
+            .iter()
+            .enumerate()
+            .map(|(i, &len)| unit_rows(len, DIM, 70 + i as u64))
+            .collect();
+        let query = unit_rows(5, DIM, 80);
+        for dtype in [
+            StoreDtype::F32,
+            StoreDtype::F16,
+            StoreDtype::Bf16,
+            StoreDtype::Int8,
+        ] {
+            let plain = pushed(DocStoreBuilder::new(DIM).with_storage(dtype), &docs);
+            for buckets in [&[4, 8, 16][..], &[16], &[12, 13]] {
+                let builder = DocStoreBuilder::new(DIM)
+                    .with_storage(dtype)
+                    .with_buckets(buckets)
+                    .with_token_multiple(2);
+                let bucketed = pushed(builder, &docs);
+                for (i, &len) in LENS.iter().enumerate() {
+                    let bucket = buckets.iter().find(|&&b| b >= len).unwrap();
+                    assert_eq!(bucketed.doc_len(i), bucket.next_multiple_of(2));
+                    if let StoreDocs::F32(stored) = bucketed.docs() {
+                        let (rows, last) = stored.doc(i).split_at(len * DIM);
+                        assert_eq!(rows, &docs[i][..]);
+                        let last_token = &docs[i][(len - 1) * DIM..];
+                        assert!(last.chunks_exact(DIM).all(|row| row == last_token));
+                    }
+                }
+                assert_eq!(
+                    query_scores(&bucketed, &query),
+                    query_scores(&plain, &query),
+                    "{dtype:?} in buckets {buckets:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bucket_padding_never_changes_document_side_scores() {
+        let docs: Vec<Vec<f32>> = LENS
+            .iter()
+            .enumerate()
+            .map(|(i, &len)| unit_rows(len, DIM, 90 + i as u64))
+            .collect();
+        let query = QueryEmbeddings::new(unit_rows(5, DIM, 99), 5, DIM).unwrap();
+        let path = scratch_path("bucketed-directions");
+        for dtype in [
+            StoreDtype::F32,
+            StoreDtype::F16,
+            StoreDtype::Bf16,
+            StoreDtype::Int8,
+        ] {
+            let plain = pushed(DocStoreBuilder::new(DIM).with_storage(dtype), &docs);
+            let bucketed = DocStoreBuilder::new(DIM)
+                .with_storage(dtype)
+                .with_buckets(&[4, 8, 16]);
+            let tiled = DocStoreBuilder::new(DIM)
+                .with_storage(dtype)
+                .with_token_multiple(32);
+            let both = bucketed.clone().with_token_multiple(8);
+            for (name, builder) in [("buckets", bucketed), ("tiles", tiled), ("both", both)] {
+                let padded = pushed(builder, &docs);
+                for (i, &len) in LENS.iter().enumerate() {
+                    assert_eq!(padded.doc_len(i) - padded.padding(i), len, "{name}");
+                }
+                padded.save(&path).unwrap();
+                let loaded = DocStore::load(&path).unwrap();
+                assert_same_scores(
+                    &plain,
+                    &[&padded, &loaded],
+                    &query,
+                    &format!("{dtype:?} {name}"),
+                );
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Each store's scores of `query` equal `plain`'s, in every direction
+    /// and aggregation.
+    fn assert_same_scores(
+        plain: &DocStore,
+        stores: &[&DocStore],
+        query: &QueryEmbeddings,
+        what: &str,
+    ) {
+        let aggregations = [
+            Aggregation::Sum,
+            Aggregation::Mean,
+            Aggregation::Max,
+            Aggregation::LogSumExp { temperature: 0.5 },
+        ];
+        for direction in [
+            Direction::QueryToDoc,
+            Direction::DocToQuery,
+            Direction::Symmetric,
+        ] {
+            for aggregation in aggregations {
+                let config = ScorerConfig::default()
+                    .with_num_threads(1)
+                    .with_direction(direction)
+                    .with_aggregation(aggregation);
+                let scores = |store| {
+                    let scorer = MaxSimScorer::from_config(config, store).unwrap();
+                    scorer.score_batch(query).unwrap()
+                };
+                let expected = scores(plain);
+                for &store in stores {
+                    assert_eq!(
+                        scores(store),
+                        expected,
+                        "{what} {direction:?} {aggregation:?}"
+                    );
+                }
+            }
+        }
+    }
+}
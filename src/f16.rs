@@ -0,0 +1,274 @@
+//! Bulk f32 ↔ IEEE half (f16) conversion.
+//!
+//! f16 values are raw `u16` bit patterns. f32 → f16 rounds to nearest-even
+//! (overflow to infinity, subnormals kept, NaNs quieted); f16 → f32 is
+//! exact apart from quieting signalling NaNs, as `VCVTPH2PS` does.
+//!
+//! Runtime dispatch picks F16C (`VCVTPS2PH` / `VCVTPH2PS`), then a scalar
+//! loop. Both give the same bits, as does `half::f16::from_f32`; with the
+//! `half` feature the bulk conversions also take `half::f16` slices.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[cfg(feature = "half")]
+use half::slice::HalfFloatSliceExt;
+
+/// Round one f32 to f16 (nearest-even, NaNs quieted).
+pub fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let man = bits & 0x007f_ffff;
+
+    if exp == 0xff {
+        // Infinity, or NaN with the quiet bit set
+        let nan = if man != 0 {
+            0x0200 | (man >> 13) as u16
+        } else {
+            0
+        };
+        return sign | 0x7c00 | nan;
+    }
+    // Rebias 127 → 15
+    let half_exp = exp - 112;
+    if half_exp >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exp <= 0 {
+        // Subnormal half (or zero): shift the implicit-one mantissa down
+        if half_exp < -10 {
+            return sign;
+        }
+        let man = man | 0x0080_0000;
+        let shift = (14 - half_exp) as u32;
+        let half = man >> shift;
+        let rem = man & ((1 << shift) - 1);
+        let mid = 1 << (shift - 1);
+        let round = (rem > mid || (rem == mid && half & 1 == 1)) as u32;
+        return sign | (half + round) as u16;
+    }
+    let half = ((half_exp as u32) << 10) | (man >> 13);
+    let rem = man & 0x1fff;
+    let round = (rem > 0x1000 || (rem == 0x1000 && half & 1 == 1)) as u32;
+    // A carry out of the mantissa bumps the exponent, up to infinity
+    sign | (half + round) as u16
+}
+
+/// Widen one f16 to f32 (exact, NaNs quieted).
+pub fn f16_to_f32(x: u16) -> f32 {
+    let sign = ((x & 0x8000) as u32) << 16;
+    let exp = ((x >> 10) & 0x1f) as u32;
+    let man = (x & 0x03ff) as u32;
+    let bits = match (exp, man) {
+        (0, 0) => sign,
+        (0, _) => {
+            // Subnormal: normalize into an f32 exponent
+            let shift = man.leading_zeros() - 21;
+            sign | ((113 - shift) << 23) | ((man << shift) & 0x03ff) << 13
+        }
+        (0x1f, 0) => sign | 0x7f80_0000,
+        (0x1f, _) => sign | 0x7fc0_0000 | (man << 13),
+        _ => sign | ((exp + 112) << 23) | (man << 13),
+    };
+    f32::from_bits(bits)
+}
+
+/// Convert `src` to f16 into `dst`. Panics if the lengths differ.
+pub fn convert_f32_to_f16(src: &[f32], dst: &mut [u16]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "f32→f16: source and destination lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("f16c") && is_x86_feature_detected!("avx") {
+            return unsafe { f32_to_f16_f16c(src, dst) };
+        }
+    }
+
+    f32_to_f16_scalar(src, dst);
+}
+
+/// Convert f16 `src` to f32 into `dst`. Panics if the lengths differ.
+pub fn convert_f16_to_f32(src: &[u16], dst: &mut [f32]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "f16→f32: source and destination lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("f16c") && is_x86_feature_detected!("avx") {
+            return unsafe { f16_to_f32_f16c(src, dst) };
+        }
+    }
+
+    f16_to_f32_scalar(src, dst);
+}
+
+/// `convert_f32_to_f16` into `half::f16` values.
+#[cfg(feature = "half")]
+pub fn convert_f32_to_half_f16(src: &[f32], dst: &mut [half::f16]) {
+    convert_f32_to_f16(src, dst.reinterpret_cast_mut());
+}
+
+/// `convert_f16_to_f32` from `half::f16` values.
+#[cfg(feature = "half")]
+pub fn convert_half_f16_to_f32(src: &[half::f16], dst: &mut [f32]) {
+    convert_f16_to_f32(src.reinterpret_cast(), dst);
+}
+
+fn f32_to_f16_scalar(src: &[f32], dst: &mut [u16]) {
+    for (d, &s) in dst.iter_mut().zip(src) {
+        *d = f32_to_f16(s);
+    }
+}
+
+fn f16_to_f32_scalar(src: &[u16], dst: &mut [f32]) {
+    for (d, &s) in dst.iter_mut().zip(src) {
+        *d = f16_to_f32(s);
+    }
+}
+
+/// F16C: 8 floats per iteration.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx,f16c")]
+unsafe fn f32_to_f16_f16c(src: &[f32], dst: &mut [u16]) {
+    let n = src.len();
+    let mut i = 0;
+
+    while i + 8 <= n {
+        let x = _mm256_loadu_ps(src.as_ptr().add(i));
+        let h = _mm256_cvtps_ph::<_MM_FROUND_TO_NEAREST_INT>(x);
+        _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, h);
+        i += 8;
+    }
+
+    f32_to_f16_scalar(&src[i..], &mut dst[i..]);
+}
+
+/// F16C: 8 values per iteration.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx,f16c")]
+unsafe fn f16_to_f32_f16c(src: &[u16], dst: &mut [f32]) {
+    let n = src.len();
+    let mut i = 0;
+
+    while i + 8 <= n {
+        let h = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+        _mm256_storeu_ps(dst.as_mut_ptr().add(i), _mm256_cvtph_ps(h));
+        i += 8;
+    }
+
+    f16_to_f32_scalar(&src[i..], &mut dst[i..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// f32 bit patterns covering ties, f16 subnormals, f32 subnormals, the
+    /// overflow boundary, infinities and NaNs, padded past the vector width
+    /// with a sweep.
+    fn inputs() -> Vec<f32> {
+        let special = [
+            0x3f80_1000, // 1 + 2^-11: tie, rounds down to even
+            0x3f80_3000, // tie, rounds up to even
+            0x3f80_1001, // just past the tie
+            0x3380_0000, // 2^-24: smallest f16 subnormal
+            0x3300_0000, // 2^-25: tie with zero, rounds to even (zero)
+            0x3300_0001, // just past it, rounds up to the smallest subnormal
+            0x387f_c000, // largest f16 subnormal
+            0x0000_0001, // smallest f32 subnormal flushes to zero
+            0x807f_ffff,
+            0x477f_e000, // 65504: f16::MAX
+            0x477f_f000, // tie between f16::MAX and infinity
+            0x7f7f_ffff,
+            0x7f80_0000,
+            0xff80_0000,
+            0x7f80_0001, // signalling NaN with only low payload bits
+            0x7fbf_ffff,
+            0xffc0_0000,
+            0x8000_0000,
+        ];
+        let sweep = (0..4000u32).map(|i| i.wrapping_mul(0x9e37_79b9));
+        special
+            .into_iter()
+            .chain(sweep)
+            .map(f32::from_bits)
+            .collect()
+    }
+
+    #[test]
+    fn scalar_rounds_to_nearest_even() {
+        let round = |bits: u32| f32_to_f16(f32::from_bits(bits));
+        assert_eq!(round(0x3f80_1000), 0x3c00);
+        assert_eq!(round(0x3f80_3000), 0x3c02);
+        assert_eq!(round(0x3f80_1001), 0x3c01);
+        assert_eq!(round(0x3380_0000), 0x0001);
+        assert_eq!(round(0x3300_0000), 0x0000);
+        assert_eq!(round(0x3300_0001), 0x0001);
+        assert_eq!(round(0x387f_c000), 0x03ff);
+        assert_eq!(round(0x807f_ffff), 0x8000);
+        assert_eq!(round(0x477f_e000), 0x7bff);
+        assert_eq!(round(0x477f_f000), 0x7c00);
+        assert_eq!(round(0xff80_0000), 0xfc00);
+        // NaNs stay NaNs even when the payload sits below bit 13
+        assert_eq!(round(0x7f80_0001), 0x7e00);
+        assert_eq!(round(0xffc0_0000), 0xfe00);
+    }
+
+    #[test]
+    fn bulk_paths_match_scalar() {
+        let src = inputs();
+        let mut dst = vec![0u16; src.len()];
+        convert_f32_to_f16(&src, &mut dst);
+        for (&x, &y) in src.iter().zip(&dst) {
+            assert_eq!(y, f32_to_f16(x), "{:#010x}", x.to_bits());
+        }
+        let all: Vec<u16> = (0..=u16::MAX).collect();
+        let mut widened = vec![0.0f32; all.len()];
+        convert_f16_to_f32(&all, &mut widened);
+        for (&bits, &x) in all.iter().zip(&widened) {
+            assert_eq!(x.to_bits(), f16_to_f32(bits).to_bits(), "{bits:#06x}");
+        }
+    }
+
+    #[test]
+    fn representable_values_round_trip_exactly() {
+        let all: Vec<u16> = (0..=u16::MAX).collect();
+        let mut widened = vec![0.0f32; all.len()];
+        convert_f16_to_f32(&all, &mut widened);
+        let mut back = vec![0u16; all.len()];
+        convert_f32_to_f16(&widened, &mut back);
+        for (&bits, &y) in all.iter().zip(&back) {
+            if bits & 0x7c00 == 0x7c00 && bits & 0x03ff != 0 {
+                assert_eq!(y, bits | 0x0200, "{bits:#06x}");
+            } else {
+                assert_eq!(y, bits, "{bits:#06x}");
+            }
+        }
+    }
+
+    #[cfg(feature = "half")]
+    #[test]
+    fn half_agrees_with_the_bulk_paths() {
+        let src = inputs();
+        let mut dst = vec![half::f16::ZERO; src.len()];
+        convert_f32_to_half_f16(&src, &mut dst);
+        for (&x, &y) in src.iter().zip(&dst) {
+            let want = half::f16::from_f32(x);
+            assert_eq!(y.to_bits(), want.to_bits(), "{:#010x}", x.to_bits());
+        }
+        let all: Vec<half::f16> = (0..=u16::MAX).map(half::f16::from_bits).collect();
+        let mut widened = vec![0.0f32; all.len()];
+        convert_half_f16_to_f32(&all, &mut widened);
+        for (&h, &x) in all.iter().zip(&widened) {
+            assert_eq!(x.to_bits(), h.to_f32().to_bits(), "{:#06x}", h.to_bits());
+        }
+    }
+}
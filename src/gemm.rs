@@ -0,0 +1,585 @@
+//! Unified f32 GEMM entry point: JIT kernel when libxsmm can generate one,
+//! `libxsmm_sgemm` otherwise, or the native kernel when `libxsmm-dlopen`
+//! found no library.
+//!
+//! Both paths use the same column-major conventions (`Transpose`, leading
+//! dimensions, `Beta`), so callers never need to care which one ran.
+
+use std::sync::Arc;
+
+use libc::{c_int, c_void};
+use rayon::prelude::*;
+
+use crate::kernel_cache;
+use crate::libxsmm_bindings::{
+    xsmm_sgemm, Beta, CallError, CpuArch, GemmSpec, JitKernel, KernelCall, Prefetch, TileConfig,
+    TileConfigGuard, Transpose, LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32,
+    LIBXSMM_GEMM_PREFETCH_NONE,
+};
+use crate::libxsmm_link::loaded;
+
+/// Largest M/N we ask libxsmm to JIT; bigger shapes go straight to SGEMM.
+const JIT_MAX_MN: i32 = 256;
+/// Largest K we ask libxsmm to JIT.
+const JIT_MAX_K: i32 = 4096;
+
+/// Which implementation a `Gemm` runs on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GemmPath {
+    /// Dispatched JIT kernel.
+    Jit,
+    /// BLAS-style `libxsmm_sgemm` fallback.
+    Sgemm,
+}
+
+/// f32 GEMM for a fixed shape with transparent JIT → SGEMM fallback.
+pub struct Gemm {
+    spec: GemmSpec,
+    trans: Transpose,
+    beta: Beta,
+    kernel: Option<Arc<JitKernel>>,
+}
+
+impl Gemm {
+    /// Tightly packed operands.
+    pub fn f32(m: i32, n: i32, k: i32, trans: Transpose, beta: Beta) -> Self {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        let spec = GemmSpec::packed(m, n, k, trans, f32, f32).with_beta(beta);
+        Self::from_parts(spec, trans, beta)
+    }
+
+    /// Tightly packed operands, with a kernel that prefetches the next
+    /// blocks handed to `run_prefetch` per `prefetch`.
+    pub fn f32_with_prefetch(
+        m: i32,
+        n: i32,
+        k: i32,
+        trans: Transpose,
+        beta: Beta,
+        prefetch: Prefetch,
+    ) -> Self {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        let spec = GemmSpec::packed(m, n, k, trans, f32, f32)
+            .with_beta(beta)
+            .with_prefetch(prefetch);
+        Self::from_parts(spec, trans, beta)
+    }
+
+    /// Explicit leading dimensions.
+    #[allow(clippy::too_many_arguments)]
+    pub fn f32_with_ld(
+        m: i32,
+        n: i32,
+        k: i32,
+        lda: i32,
+        ldb: i32,
+        ldc: i32,
+        trans: Transpose,
+        beta: Beta,
+    ) -> Self {
+        let spec = GemmSpec::new(
+            m,
+            n,
+            k,
+            lda,
+            ldb,
+            ldc,
+            trans,
+            beta,
+            Prefetch::None,
+            LIBXSMM_DATATYPE_F32,
+            LIBXSMM_DATATYPE_F32,
+        );
+        Self::from_parts(spec, trans, beta)
+    }
+
+    fn from_parts(spec: GemmSpec, trans: Transpose, beta: Beta) -> Self {
+        let kernel = if spec.m <= JIT_MAX_MN && spec.n <= JIT_MAX_MN && spec.k <= JIT_MAX_K {
+            kernel_cache::get_kernel(spec).ok()
+        } else {
+            None
+        };
+        trace_event!(
+            TRACE,
+            m = spec.m,
+            n = spec.n,
+            k = spec.k,
+            jit = kernel.is_some(),
+            "f32 gemm path (jit or sgemm)"
+        );
+        Self {
+            spec,
+            trans,
+            beta,
+            kernel,
+        }
+    }
+
+    /// Which implementation `run` will use.
+    pub fn path(&self) -> GemmPath {
+        if self.kernel.is_some() {
+            GemmPath::Jit
+        } else {
+            GemmPath::Sgemm
+        }
+    }
+
+    pub fn spec(&self) -> &GemmSpec {
+        &self.spec
+    }
+
+    /// Whether `run_prefetch` hands its next blocks to a prefetching JIT
+    /// kernel; SGEMM ignores them.
+    pub fn prefetches(&self) -> bool {
+        self.kernel.is_some() && self.spec.prefetch != LIBXSMM_GEMM_PREFETCH_NONE
+    }
+
+    /// `run`, with the kernel prefetching `a_next` and `b_next` (the
+    /// operands of the next call) while it computes this one. The hints are
+    /// never dereferenced, so they may run past the next block's end.
+    pub fn run_prefetch(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        c: &mut [f32],
+        a_next: &[f32],
+        b_next: &[f32],
+    ) -> Result<(), CallError> {
+        let Some(kernel) = self.kernel.as_ref().filter(|_| self.prefetches()) else {
+            return self.run(a, b, c);
+        };
+        let f32 = LIBXSMM_DATATYPE_F32;
+        kernel.check(f32, f32, a.len(), b.len(), c.len())?;
+        kernel.debug_assert_covers(a.len(), b.len(), c.len());
+        unsafe {
+            kernel.call_prefetch(
+                a.as_ptr() as *const c_void,
+                b.as_ptr() as *const c_void,
+                c.as_mut_ptr() as *mut c_void,
+                a_next.as_ptr() as *const c_void,
+                b_next.as_ptr() as *const c_void,
+            );
+        }
+        Ok(())
+    }
+
+    /// C = A·B (or C += A·B with `Beta::One`), with operand sizes checked
+    /// against the shape.
+    pub fn run(&self, a: &[f32], b: &[f32], c: &mut [f32]) -> Result<(), CallError> {
+        if let Some(kernel) = &self.kernel {
+            return kernel.call_f32(a, b, c);
+        }
+        self.run_sgemm(1.0, a, b, c)
+    }
+
+    /// C = alpha·A·B (or C += alpha·A·B with `Beta::One`).
+    ///
+    /// JIT kernels only compute alpha = 1, so the JIT path scales whichever
+    /// of A, B or C is smallest: the operand into a scratch copy, or C in
+    /// place after the GEMM. C is never post-scaled under `Beta::One`,
+    /// since that would also scale what it accumulated into.
+    pub fn run_scaled(
+        &self,
+        alpha: f32,
+        a: &[f32],
+        b: &[f32],
+        c: &mut [f32],
+    ) -> Result<(), CallError> {
+        let kernel = match &self.kernel {
+            Some(kernel) if alpha != 1.0 => kernel,
+            Some(kernel) => return kernel.call_f32(a, b, c),
+            None => return self.run_sgemm(alpha, a, b, c),
+        };
+        self.spec.check_lens(a.len(), b.len(), c.len())?;
+
+        let (a_len, b_len, c_len) = self.spec.operand_lens();
+        let scale =
+            |x: &[f32], len: usize| -> Vec<f32> { x[..len].iter().map(|v| alpha * v).collect() };
+        let post_scale_c = matches!(self.beta, Beta::Zero) && c_len <= a_len.min(b_len);
+
+        if post_scale_c {
+            kernel.call_f32(a, b, c)?;
+            // Column by column: rows past m within ldc belong to the caller
+            let (m, ldc) = (self.spec.m as usize, self.spec.ldc as usize);
+            for col in c[..c_len].chunks_mut(ldc) {
+                for v in &mut col[..m] {
+                    *v *= alpha;
+                }
+            }
+            Ok(())
+        } else if a_len <= b_len {
+            kernel.call_f32(&scale(a, a_len), b, c)
+        } else {
+            kernel.call_f32(a, &scale(b, b_len), c)
+        }
+    }
+
+    fn run_sgemm(&self, alpha: f32, a: &[f32], b: &[f32], c: &mut [f32]) -> Result<(), CallError> {
+        self.spec.check_lens(a.len(), b.len(), c.len())?;
+        let transa = if matches!(self.trans, Transpose::A | Transpose::Both) {
+            b'T'
+        } else {
+            b'N'
+        };
+        let transb = if matches!(self.trans, Transpose::B | Transpose::Both) {
+            b'T'
+        } else {
+            b'N'
+        };
+        let beta = match self.beta {
+            Beta::Zero => 0.0,
+            Beta::One => 1.0,
+        };
+        if !loaded() {
+            let spec = &self.spec;
+            let (m, n, k) = (spec.m, spec.n, spec.k);
+            let (lda, ldb, ldc) = (spec.lda, spec.ldb, spec.ldc);
+            // Lengths checked above
+            unsafe {
+                crate::backend::sgemm(transa, transb, m, n, k, alpha, a, lda, b, ldb, beta, c, ldc)
+            };
+            return Ok(());
+        }
+        unsafe {
+            xsmm_sgemm(
+                transa,
+                transb,
+                self.spec.m,
+                self.spec.n,
+                self.spec.k,
+                alpha,
+                a.as_ptr(),
+                self.spec.lda,
+                b.as_ptr(),
+                self.spec.ldb,
+                beta,
+                c.as_mut_ptr(),
+                self.spec.ldc,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// One-shot f32 GEMM; see `Gemm::run`. Returns the path that was taken.
+#[allow(clippy::too_many_arguments)]
+pub fn gemm_f32(
+    m: i32,
+    n: i32,
+    k: i32,
+    trans: Transpose,
+    beta: Beta,
+    a: &[f32],
+    b: &[f32],
+    c: &mut [f32],
+) -> Result<GemmPath, CallError> {
+    let gemm = Gemm::f32(m, n, k, trans, beta);
+    gemm.run(a, b, c)?;
+    Ok(gemm.path())
+}
+
+/// One-shot scaled f32 GEMM; see `Gemm::run_scaled`. Returns the path that
+/// was taken.
+#[allow(clippy::too_many_arguments)]
+pub fn gemm_scaled(
+    m: i32,
+    n: i32,
+    k: i32,
+    trans: Transpose,
+    beta: Beta,
+    alpha: f32,
+    a: &[f32],
+    b: &[f32],
+    c: &mut [f32],
+) -> Result<GemmPath, CallError> {
+    let gemm = Gemm::f32(m, n, k, trans, beta);
+    gemm.run_scaled(alpha, a, b, c)?;
+    Ok(gemm.path())
+}
+
+/// One-shot f32 GEMM on row-major operands: C (m×n) = A (m×k) · B (k×n),
+/// each packed row by row. Runs the column-major product Cᵀ = Bᵀ·Aᵀ, which
+/// reads the same buffers with A and B swapped.
+///
+/// ```ignore
+/// let a = [1., 2., 3., 4., 5., 6., 7., 8., 9., 10., 11., 12.]; // 3x4
+/// let b = [1., 0., 0., 1., 1., 0., 0., 1.]; // 4x2
+/// let mut c = [0.0f32; 6]; // 3x2
+/// gemm_row_major(3, 2, 4, &a, &b, &mut c)?;
+/// assert_eq!(c, [4., 6., 12., 14., 20., 22.]);
+/// ```
+pub fn gemm_row_major(
+    m: i32,
+    n: i32,
+    k: i32,
+    a: &[f32],
+    b: &[f32],
+    c: &mut [f32],
+) -> Result<GemmPath, CallError> {
+    let gemm = Gemm::f32(n, m, k, Transpose::None, Beta::Zero);
+    gemm.run(b, a, c).map_err(CallError::swap_ab)?;
+    Ok(gemm.path())
+}
+
+/// Run an f32 kernel over `batch` (B, C) pairs sharing one A operand:
+/// `C_i = A·B_i`, with `B_i = b[i * b_stride..]` and `C_i = c[i * c_stride..]`.
+///
+/// This is one query against `batch` equal-length documents. A single param
+/// block is reused per worker and only the B/C pointers are rewritten. With
+/// `par`, the batch is split across the rayon pool; `c_stride` must then
+/// cover a whole C tile so workers never write the same elements.
+///
+/// `kernel` must be column-major; the param block bypasses the row-major
+/// operand swap of `JitKernel::call`.
+#[allow(clippy::too_many_arguments)]
+pub fn gemm_batch_strided_f32(
+    kernel: &JitKernel,
+    a: &[f32],
+    b: &[f32],
+    b_stride: usize,
+    c: &mut [f32],
+    c_stride: usize,
+    batch: usize,
+    par: bool,
+) -> Result<(), CallError> {
+    let f32 = LIBXSMM_DATATYPE_F32;
+    batch_strided(
+        kernel,
+        (f32, f32),
+        None,
+        a,
+        b,
+        b_stride,
+        c,
+        c_stride,
+        batch,
+        par,
+    )
+}
+
+/// BF16→f32 version of `gemm_batch_strided_f32` (bf16 as raw `u16` bits).
+///
+/// On AMX machines the batch runs a tile-scoped variant of `kernel`: each
+/// worker configures the tiles once for its whole share of the batch instead
+/// of once per call.
+#[allow(clippy::too_many_arguments)]
+pub fn gemm_batch_strided_bf16(
+    kernel: &JitKernel,
+    a: &[u16],
+    b: &[u16],
+    b_stride: usize,
+    c: &mut [f32],
+    c_stride: usize,
+    batch: usize,
+    par: bool,
+) -> Result<(), CallError> {
+    let dtypes = (LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32);
+    let spec = *kernel.spec();
+    if batch > 1 && !spec.in_tile_scope() && CpuArch::detect().supports_amx() {
+        let scoped = spec.with_tile_scope();
+        if let (Ok(tiles), Ok(scoped_kernel)) = (
+            TileConfig::for_spec(&scoped),
+            kernel_cache::get_kernel(scoped),
+        ) {
+            return batch_strided(
+                &scoped_kernel,
+                dtypes,
+                Some(&tiles),
+                a,
+                b,
+                b_stride,
+                c,
+                c_stride,
+                batch,
+                par,
+            );
+        }
+    }
+    batch_strided(
+        kernel, dtypes, None, a, b, b_stride, c, c_stride, batch, par,
+    )
+}
+
+/// Shared batch loop. With `tiles`, every worker holds a `TileConfigGuard`
+/// while it runs its chunk.
+#[allow(clippy::too_many_arguments)]
+fn batch_strided<I: Sync, O: Send>(
+    kernel: &JitKernel,
+    (in_type, out_type): (c_int, c_int),
+    tiles: Option<&TileConfig>,
+    a: &[I],
+    b: &[I],
+    b_stride: usize,
+    c: &mut [O],
+    c_stride: usize,
+    batch: usize,
+    par: bool,
+) -> Result<(), CallError> {
+    debug_assert!(
+        !kernel.is_row_major(),
+        "batched calls take column-major kernels"
+    );
+    if batch == 0 {
+        return Ok(());
+    }
+    let (_, _, c_req) = kernel.spec().operand_lens();
+    // Checks dtype, A, and the last B and C tiles (earlier ones sit below them).
+    kernel.check(
+        in_type,
+        out_type,
+        a.len(),
+        b.len().saturating_sub((batch - 1) * b_stride),
+        c.len().saturating_sub((batch - 1) * c_stride),
+    )?;
+    if c_stride < c_req {
+        return Err(CallError::BufferTooSmall {
+            operand: 'C',
+            required: c_req,
+            actual: c_stride,
+        });
+    }
+
+    let new_state = || {
+        let mut call = KernelCall::new(kernel);
+        call.set_a(a.as_ptr() as *const c_void);
+        (call, tiles.map(TileConfig::enter))
+    };
+    let run = |(call, _tiles): &mut (KernelCall, Option<TileConfigGuard>),
+               (i, c_tile): (usize, &mut [O])| {
+        call.set_b(b[i * b_stride..].as_ptr() as *const c_void);
+        call.set_c(c_tile.as_mut_ptr() as *mut c_void);
+        unsafe { call.fire() };
+    };
+
+    let c = &mut c[..(batch - 1) * c_stride + c_req];
+    if par {
+        c.par_chunks_mut(c_stride)
+            .enumerate()
+            .for_each_init(new_state, run);
+    } else {
+        let mut state = new_state();
+        c.chunks_mut(c_stride)
+            .enumerate()
+            .for_each(|item| run(&mut state, item));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+
+    /// Column-major C = op(A)·op(B), tightly packed, accumulated in f64.
+    fn reference(
+        trans: Transpose,
+        (m, n, k): (usize, usize, usize),
+        a: &[f32],
+        b: &[f32],
+    ) -> Vec<f32> {
+        let at = matches!(trans, Transpose::A | Transpose::Both);
+        let bt = matches!(trans, Transpose::B | Transpose::Both);
+        let mut c = vec![0.0f32; m * n];
+        for j in 0..n {
+            for i in 0..m {
+                let sum: f64 = (0..k)
+                    .map(|p| {
+                        let x = if at { a[i * k + p] } else { a[p * m + i] };
+                        let y = if bt { b[p * n + j] } else { b[j * k + p] };
+                        x as f64 * y as f64
+                    })
+                    .sum();
+                c[j * m + i] = sum as f32;
+            }
+        }
+        c
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32], tol: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (&x, &y)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (x - y).abs() <= tol * y.abs().max(1.0),
+                "C[{i}]: {x} vs {y}"
+            );
+        }
+    }
+
+    #[test]
+    fn absurd_k_falls_back_to_sgemm() {
+        let (m, n, k) = (2, 3, 1 << 20);
+        let a = unit_rows(m, k, 11);
+        let b = unit_rows(n, k, 12);
+        let expected = reference(Transpose::A, (m, n, k), &a, &b);
+
+        let gemm = Gemm::f32(m as i32, n as i32, k as i32, Transpose::A, Beta::Zero);
+        assert_eq!(gemm.path(), GemmPath::Sgemm);
+        let mut c = vec![1e6f32; m * n];
+        gemm.run(&a, &b, &mut c).unwrap();
+        assert_close(&c, &expected, 1e-4);
+
+        // Beta::One adds into what C already holds on the fallback too
+        let acc = Gemm::f32(m as i32, n as i32, k as i32, Transpose::A, Beta::One);
+        acc.run(&a, &b, &mut c).unwrap();
+        let twice: Vec<f32> = expected.iter().map(|v| 2.0 * v).collect();
+        assert_close(&c, &twice, 1e-4);
+
+        assert!(gemm.run(&a[1..], &b, &mut c).is_err());
+    }
+
+    #[test]
+    fn every_path_matches_reference() {
+        for (m, n, k) in [(8, 5, 16), (300, 4, 32), (4, 3, 5000)] {
+            let (mi, ni, ki) = (m as i32, n as i32, k as i32);
+            for trans in [Transpose::None, Transpose::A, Transpose::B, Transpose::Both] {
+                let a = unit_rows(m, k, 13);
+                let b = unit_rows(n, k, 14);
+                let expected = reference(trans, (m, n, k), &a, &b);
+                let mut c = vec![0.0f32; m * n];
+                let path = gemm_f32(mi, ni, ki, trans, Beta::Zero, &a, &b, &mut c).unwrap();
+                if m > JIT_MAX_MN as usize || k > JIT_MAX_K as usize {
+                    assert_eq!(path, GemmPath::Sgemm);
+                }
+                assert_close(&c, &expected, 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn scaled_jit_agrees_with_sgemm() {
+        let alpha = -0.37f32;
+        // Post-scaled C, pre-scaled A, pre-scaled B
+        for (m, n, k) in [(4, 4, 32), (4, 16, 8), (16, 4, 8)] {
+            let (mi, ni, ki) = (m as i32, n as i32, k as i32);
+            let a = unit_rows(m, k, 15);
+            let b = unit_rows(n, k, 16);
+            let scaled: Vec<f32> = reference(Transpose::A, (m, n, k), &a, &b)
+                .iter()
+                .map(|v| alpha * v)
+                .collect();
+            for beta in [Beta::Zero, Beta::One] {
+                let jit = Gemm::f32(mi, ni, ki, Transpose::A, beta);
+                let sgemm = Gemm {
+                    kernel: None,
+                    ..Gemm::f32(mi, ni, ki, Transpose::A, beta)
+                };
+                assert_eq!(sgemm.path(), GemmPath::Sgemm);
+                let start = unit_rows(n, m, 17);
+                let (mut c_jit, mut c_sgemm) = (start.clone(), start.clone());
+                jit.run_scaled(alpha, &a, &b, &mut c_jit).unwrap();
+                sgemm.run_scaled(alpha, &a, &b, &mut c_sgemm).unwrap();
+                assert_close(&c_jit, &c_sgemm, 1e-5);
+
+                let expected: Vec<f32> = match beta {
+                    Beta::Zero => scaled.clone(),
+                    Beta::One => start.iter().zip(&scaled).map(|(c, v)| c + v).collect(),
+                };
+                assert_close(&c_jit, &expected, 1e-5);
+            }
+            let mut c = vec![0.0f32; m * n];
+            gemm_scaled(mi, ni, ki, Transpose::A, Beta::Zero, 1.0, &a, &b, &mut c).unwrap();
+            let unscaled: Vec<f32> = scaled.iter().map(|v| v / alpha).collect();
+            assert_close(&c, &unscaled, 1e-5);
+        }
+    }
+}
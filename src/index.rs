@@ -0,0 +1,770 @@
+//! Mutable document index: add and remove documents without rebuilding.
+//!
+//! Documents live in segments. The frozen segments are read-only: the
+//! main segment `compact` last wrote, followed by append segments sealed
+//! by compactions that were cancelled. Everything added since goes to the
+//! append segment. Removing a document only tombstones it, and `search`
+//! skips tombstoned documents before scoring, so they never cost a GEMM.
+//! `compact` rewrites the live documents of every segment into a fresh
+//! main segment.
+//!
+//! All state sits behind one `RwLock`: searches share the read lock and
+//! run concurrently, `add_doc` and `remove_doc` take the write lock and
+//! wait for in-flight searches to finish. `compact` seals the append
+//! segment and copies the frozen segments without holding the lock, so
+//! searches, additions and removals continue meanwhile; it takes the write
+//! lock only to swap the new main segment in.
+//!
+//! `save` writes the live documents with their ids and metadata, little-
+//! endian, extending the `crate::store` header:
+//!
+//! ```text
+//! 0    magic       b"MAXSIMIX"
+//! 8    ...         version, dtype (f32), dim, n_docs as in `crate::store`
+//! 32   ids         n_docs × u64
+//! ...  table       n_docs × (start: u64, tokens: u64)
+//! ...  data        each document's f32 rows at its `start`
+//! ...  metadata    per document: length u64 (u64::MAX for none), bytes
+//! ```
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::collection::{DocBatch, DocId, Documents, QueryEmbeddings};
+use crate::rerank::{resolve_candidates, OnMissing};
+use crate::score::{check_dim, top_k_heap, Reduction, ScoreError};
+use crate::store::{
+    read_extents, read_header, read_u64, write_docs, write_header, StoreError, DTYPE_F32,
+    HEADER_LEN,
+};
+use crate::topk::SearchHit;
+
+/// `Slot::segment` of the append segment, after every frozen one.
+const APPEND: usize = usize::MAX;
+
+const MAGIC: &[u8; 8] = b"MAXSIMIX";
+/// Metadata length of a document saved without metadata.
+const NO_METADATA: u64 = u64::MAX;
+
+/// A mutable set of documents keyed by caller-chosen `u64` ids, each with
+/// an optional metadata blob.
+#[derive(Debug)]
+pub struct MaxSimIndex {
+    dim: usize,
+    segments: RwLock<Segments>,
+    /// Held for the whole of a `compact`, one at a time.
+    compacting: Mutex<()>,
+}
+
+/// What a finished `compact` did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompactStats {
+    /// Tombstoned documents dropped.
+    pub docs_removed: usize,
+    /// Embedding bytes the dropped documents took up.
+    pub bytes_reclaimed: usize,
+    pub duration: Duration,
+}
+
+#[derive(Debug)]
+struct Segments {
+    /// Main segment first, then sealed append segments.
+    frozen: Vec<Segment>,
+    append: Segment,
+    /// Where each live id is stored.
+    slots: HashMap<u64, Slot>,
+}
+
+/// Ordered by segment (frozen ones first), then by position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Slot {
+    /// Index into `Segments::frozen`, or `APPEND`.
+    segment: usize,
+    pos: DocId,
+}
+
+#[derive(Debug)]
+struct Segment {
+    /// Shared with a running `compact` once frozen, never written then.
+    docs: Arc<DocBatch>,
+    dead: Vec<bool>,
+    tombstones: usize,
+}
+
+impl Segments {
+    fn segment(&self, slot: Slot) -> &Segment {
+        match slot.segment {
+            APPEND => &self.append,
+            i => &self.frozen[i],
+        }
+    }
+
+    fn segment_mut(&mut self, slot: Slot) -> &mut Segment {
+        match slot.segment {
+            APPEND => &mut self.append,
+            i => &mut self.frozen[i],
+        }
+    }
+
+    /// Every segment with its `Slot::segment`, in slot order.
+    fn all(&self) -> impl Iterator<Item = (usize, &Segment)> {
+        let frozen = self.frozen.iter().enumerate();
+        frozen.chain(std::iter::once((APPEND, &self.append)))
+    }
+}
+
+impl Segment {
+    fn new(dim: usize) -> Self {
+        Self {
+            docs: Arc::new(DocBatch::new(dim)),
+            dead: Vec::new(),
+            tombstones: 0,
+        }
+    }
+
+    /// Append to an unfrozen segment.
+    fn push(
+        &mut self,
+        id: u64,
+        doc: &[f32],
+        metadata: Option<Vec<u8>>,
+    ) -> Result<DocId, ScoreError> {
+        let docs = Arc::get_mut(&mut self.docs).expect("only frozen segments are shared");
+        // A removed document keeps its id here until `compact`
+        if docs.position(id).is_some_and(|pos| self.dead[pos]) {
+            docs.release_id(id);
+        }
+        let pos = docs.push_with_id(id, doc, metadata)?;
+        self.dead.push(false);
+        Ok(pos)
+    }
+
+    fn live(&self) -> impl Iterator<Item = DocId> + '_ {
+        self.dead
+            .iter()
+            .enumerate()
+            .filter(|(_, &dead)| !dead)
+            .map(|(pos, _)| pos)
+    }
+}
+
+impl MaxSimIndex {
+    /// Empty index of `dim`-dimensional token embeddings.
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            segments: RwLock::new(Segments {
+                frozen: vec![Segment::new(dim)],
+                append: Segment::new(dim),
+                slots: HashMap::new(),
+            }),
+            compacting: Mutex::new(()),
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Number of live documents.
+    pub fn len(&self) -> usize {
+        self.segments.read().unwrap().slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removed documents still taking up space until the next `compact`.
+    pub fn tombstones(&self) -> usize {
+        let segments = self.segments.read().unwrap();
+        segments.all().map(|(_, segment)| segment.tombstones).sum()
+    }
+
+    /// Add a `[tokens, dim]` document under `id` to the append segment.
+    /// Fails if `id` is already live or `embeddings` is not whole rows.
+    pub fn add_doc(&self, id: u64, embeddings: &[f32]) -> Result<(), ScoreError> {
+        self.insert(id, embeddings, None)
+    }
+
+    /// `add_doc` that also stores `metadata` with the document.
+    pub fn add_doc_with_metadata(
+        &self,
+        id: u64,
+        embeddings: &[f32],
+        metadata: Vec<u8>,
+    ) -> Result<(), ScoreError> {
+        self.insert(id, embeddings, Some(metadata))
+    }
+
+    fn insert(
+        &self,
+        id: u64,
+        embeddings: &[f32],
+        metadata: Option<Vec<u8>>,
+    ) -> Result<(), ScoreError> {
+        let mut segments = self.segments.write().unwrap();
+        if segments.slots.contains_key(&id) {
+            return Err(ScoreError::DuplicateId { id });
+        }
+        let pos = segments.append.push(id, embeddings, metadata)?;
+        let slot = Slot {
+            segment: APPEND,
+            pos,
+        };
+        segments.slots.insert(id, slot);
+        Ok(())
+    }
+
+    /// Metadata stored with live document `id`, if any.
+    pub fn metadata(&self, id: u64) -> Option<Vec<u8>> {
+        let segments = self.segments.read().unwrap();
+        let slot = *segments.slots.get(&id)?;
+        segments
+            .segment(slot)
+            .docs
+            .metadata(slot.pos)
+            .map(<[u8]>::to_vec)
+    }
+
+    /// Tombstone document `id`. Returns whether it was live.
+    pub fn remove_doc(&self, id: u64) -> bool {
+        let mut segments = self.segments.write().unwrap();
+        let Some(slot) = segments.slots.remove(&id) else {
+            return false;
+        };
+        let segment = segments.segment_mut(slot);
+        segment.dead[slot.pos] = true;
+        segment.tombstones += 1;
+        true
+    }
+
+    /// The `k` best live documents for `query`, best first. All segments
+    /// are scored as one collection, so the scores equal `maxsim_search`
+    /// over the live documents.
+    pub fn search(&self, query: &QueryEmbeddings, k: usize) -> Result<Vec<SearchHit>, ScoreError> {
+        check_dim(query, self.dim)?;
+        let segments = self.segments.read().unwrap();
+        let live = LiveDocs::new(&segments);
+        let top = top_k_heap(query, &live, k, Reduction::default())?;
+        Ok(top.into_hits(|i| live.id(i)))
+    }
+
+    /// The `k` best of the live documents whose ids are in
+    /// `candidate_ids`. Only the candidates are read; ids that are not
+    /// live are handled per `on_missing`. Scores equal `search`'s.
+    pub fn rerank(
+        &self,
+        query: &QueryEmbeddings,
+        candidate_ids: &[u64],
+        k: usize,
+        on_missing: OnMissing,
+    ) -> Result<Vec<SearchHit>, ScoreError> {
+        check_dim(query, self.dim)?;
+        let segments = self.segments.read().unwrap();
+        let slots = resolve_candidates(
+            candidate_ids,
+            |id| segments.slots.get(&id).copied(),
+            on_missing,
+        )?;
+        let live = LiveDocs {
+            segments: &segments,
+            docs: slots,
+        };
+        let top = top_k_heap(query, &live, k, Reduction::default())?;
+        Ok(top.into_hits(|i| live.id(i)))
+    }
+
+    /// Write the live documents, with their ids and metadata, to `path`.
+    /// Tombstoned documents are left out, so the loaded index starts
+    /// compacted.
+    pub fn save(&self, path: &Path) -> Result<(), StoreError> {
+        trace_span!(DEBUG, "maxsim.index_save", path = %path.display());
+        let segments = self.segments.read().unwrap();
+        let live = LiveDocs::new(&segments);
+        let n_docs = live.len();
+        let mut out = BufWriter::new(File::create(path)?);
+        write_header(&mut out, MAGIC, DTYPE_F32, self.dim, n_docs)?;
+        for i in 0..n_docs {
+            out.write_all(&live.id(i).to_le_bytes())?;
+        }
+        let lens: Vec<usize> = (0..n_docs).map(|i| live.doc_len(i)).collect();
+        let table_start = HEADER_LEN + n_docs * 8;
+        write_docs(
+            &mut out,
+            table_start,
+            &lens,
+            |t| t * self.dim * 4,
+            |out, i| {
+                let mut values = live.doc(i).iter();
+                values.try_for_each(|x| out.write_all(&x.to_le_bytes()))
+            },
+        )?;
+        for i in 0..n_docs {
+            let (segment, pos) = live.segment(i);
+            match segment.docs.metadata(pos) {
+                Some(metadata) => {
+                    out.write_all(&(metadata.len() as u64).to_le_bytes())?;
+                    out.write_all(metadata)?;
+                }
+                None => out.write_all(&NO_METADATA.to_le_bytes())?,
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Read an index written by `save`, every document in the main
+    /// segment.
+    pub fn load(path: &Path) -> Result<Self, StoreError> {
+        trace_span!(DEBUG, "maxsim.index_load", path = %path.display());
+        let bytes = std::fs::read(path)?;
+        let (dim, n_docs) = read_header(&bytes, MAGIC, DTYPE_F32)?;
+        let table_start = n_docs
+            .checked_mul(8)
+            .and_then(|len| len.checked_add(HEADER_LEN))
+            .filter(|&end| end <= bytes.len())
+            .ok_or(StoreError::Corrupt {
+                reason: "id table runs past the end of the file",
+            })?;
+        let doc_bytes = |tokens: usize| tokens.checked_mul(dim)?.checked_mul(4);
+        let extents = read_extents(&bytes, table_start, n_docs, doc_bytes)?;
+
+        let mut at = match extents.last() {
+            Some(&(start, tokens)) => start + tokens * dim * 4,
+            None => table_start,
+        };
+        let corrupt = |reason| StoreError::Corrupt { reason };
+        let mut main = Segment::new(dim);
+        let mut slots = HashMap::with_capacity(n_docs);
+        for (i, &(start, tokens)) in extents.iter().enumerate() {
+            let id = read_u64(&bytes, HEADER_LEN + i * 8);
+            let raw = &bytes[start..start + tokens * dim * 4];
+            let doc: Vec<f32> = raw
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+            if at + 8 > bytes.len() {
+                return Err(corrupt("metadata runs past the end of the file"));
+            }
+            let len = read_u64(&bytes, at);
+            at += 8;
+            let metadata = match len {
+                NO_METADATA => None,
+                len => {
+                    let end = at
+                        .checked_add(len as usize)
+                        .filter(|&end| end <= bytes.len())
+                        .ok_or(corrupt("metadata runs past the end of the file"))?;
+                    let metadata = bytes[at..end].to_vec();
+                    at = end;
+                    Some(metadata)
+                }
+            };
+            let pos = main
+                .push(id, &doc, metadata)
+                .map_err(|_| corrupt("duplicate or malformed document"))?;
+            slots.insert(id, Slot { segment: 0, pos });
+        }
+        Ok(Self {
+            dim,
+            segments: RwLock::new(Segments {
+                frozen: vec![main],
+                append: Segment::new(dim),
+                slots,
+            }),
+            compacting: Mutex::new(()),
+        })
+    }
+
+    /// Rewrite the live documents of every segment, in slot order, into
+    /// a new main segment with no tombstones.
+    ///
+    /// Searches, additions and removals proceed while the documents are
+    /// copied; documents added meanwhile stay in the append segment, and
+    /// those removed meanwhile are tombstoned in the new main segment.
+    pub fn compact(&self) -> Result<CompactStats, ScoreError> {
+        self.compact_cancellable(&AtomicBool::new(false))
+    }
+
+    /// `compact` that stops with `ScoreError::Cancelled` once `cancel` is
+    /// set. A cancelled compaction leaves the index as it found it, except
+    /// that the append segment it sealed stays frozen; the next `compact`
+    /// picks it up.
+    pub fn compact_cancellable(&self, cancel: &AtomicBool) -> Result<CompactStats, ScoreError> {
+        let _compacting = self.compacting.lock().unwrap();
+        let start = Instant::now();
+
+        // Seal the append segment, then snapshot the frozen documents
+        let snapshot: Vec<(Arc<DocBatch>, Vec<bool>)> = {
+            let mut segments = self.segments.write().unwrap();
+            if !segments.append.docs.is_empty() {
+                let sealed = std::mem::replace(&mut segments.append, Segment::new(self.dim));
+                let frozen = segments.frozen.len();
+                for slot in segments.slots.values_mut() {
+                    if slot.segment == APPEND {
+                        slot.segment = frozen;
+                    }
+                }
+                segments.frozen.push(sealed);
+            }
+            let frozen = segments.frozen.iter();
+            frozen.map(|s| (s.docs.clone(), s.dead.clone())).collect()
+        };
+
+        let mut main = Segment::new(self.dim);
+        let mut origins = Vec::new();
+        for (segment, (docs, dead)) in snapshot.iter().enumerate() {
+            for pos in (0..docs.len()).filter(|&pos| !dead[pos]) {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(ScoreError::Cancelled);
+                }
+                let metadata = docs.metadata(pos).map(<[u8]>::to_vec);
+                main.push(docs.id(pos), docs.doc(pos), metadata)?;
+                origins.push(Slot { segment, pos });
+            }
+        }
+        if cancel.load(Ordering::Relaxed) {
+            return Err(ScoreError::Cancelled);
+        }
+
+        let (mut docs_removed, mut values_removed) = (0, 0);
+        for (docs, dead) in &snapshot {
+            for pos in (0..docs.len()).filter(|&pos| dead[pos]) {
+                docs_removed += 1;
+                values_removed += docs.doc(pos).len();
+            }
+        }
+
+        let mut segments = self.segments.write().unwrap();
+        for (new_pos, origin) in origins.into_iter().enumerate() {
+            let id = main.docs.id(new_pos);
+            match segments.slots.get_mut(&id) {
+                Some(slot) if *slot == origin => {
+                    *slot = Slot {
+                        segment: 0,
+                        pos: new_pos,
+                    }
+                }
+                // Removed (and perhaps added again) while copying
+                _ => {
+                    main.dead[new_pos] = true;
+                    main.tombstones += 1;
+                }
+            }
+        }
+        segments.frozen = vec![main];
+        Ok(CompactStats {
+            docs_removed,
+            bytes_reclaimed: values_removed * 4,
+            duration: start.elapsed(),
+        })
+    }
+}
+
+/// Documents of every segment seen as one collection: all live ones, or a
+/// rerank's candidates.
+struct LiveDocs<'a> {
+    segments: &'a Segments,
+    docs: Vec<Slot>,
+}
+
+impl<'a> LiveDocs<'a> {
+    fn new(segments: &'a Segments) -> Self {
+        let docs = segments
+            .all()
+            .flat_map(|(i, segment)| segment.live().map(move |pos| Slot { segment: i, pos }))
+            .collect();
+        Self { segments, docs }
+    }
+
+    fn segment(&self, i: DocId) -> (&'a Segment, DocId) {
+        let slot = self.docs[i];
+        (self.segments.segment(slot), slot.pos)
+    }
+}
+
+impl Documents for LiveDocs<'_> {
+    fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.segments.append.docs.dim()
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        let (segment, pos) = self.segment(i);
+        segment.docs.doc_len(pos)
+    }
+
+    fn doc(&self, i: DocId) -> &[f32] {
+        let (segment, pos) = self.segment(i);
+        segment.docs.doc(pos)
+    }
+
+    fn max_token_norm(&self, i: DocId) -> f32 {
+        let (segment, pos) = self.segment(i);
+        segment.docs.max_token_norm(pos)
+    }
+
+    fn id(&self, i: DocId) -> u64 {
+        let (segment, pos) = self.segment(i);
+        segment.docs.id(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+    use crate::score::maxsim_search;
+
+    const DIM: usize = 16;
+
+    /// Document `id`: `1 + 7 * id % 23` tokens, the same every time.
+    fn doc(id: u64) -> Vec<f32> {
+        unit_rows(1 + (id as usize * 7) % 23, DIM, 100 + id)
+    }
+
+    fn query(len: usize, seed: u64) -> QueryEmbeddings {
+        QueryEmbeddings::new(unit_rows(len, DIM, seed), len, DIM).unwrap()
+    }
+
+    /// `maxsim_search` over a fresh batch of the documents `live`.
+    fn expected(live: &[u64], query: &QueryEmbeddings, k: usize) -> Vec<SearchHit> {
+        let mut batch = DocBatch::new(DIM);
+        for &id in live {
+            batch.push_with_id(id, &doc(id), None).unwrap();
+        }
+        maxsim_search(query, &batch, k).unwrap()
+    }
+
+    #[test]
+    fn search_matches_a_plain_collection_of_the_live_documents() {
+        let index = MaxSimIndex::new(DIM);
+        let mut live = Vec::new();
+        for id in 0..60u64 {
+            index.add_doc(id, &doc(id)).unwrap();
+            live.push(id);
+            // Remove every third document some steps after adding it
+            if id % 3 == 2 {
+                let gone = id - 2;
+                assert!(index.remove_doc(gone));
+                live.retain(|&l| l != gone);
+            }
+            if id % 10 == 9 {
+                let query = query(5, id);
+                for k in [1, 7, 100] {
+                    assert_eq!(
+                        index.search(&query, k).unwrap(),
+                        expected(&live, &query, k),
+                        "after {id}, k = {k}"
+                    );
+                }
+            }
+        }
+        assert_eq!(index.len(), live.len());
+        assert_eq!(index.tombstones(), 60 - live.len());
+        assert!(matches!(
+            index.add_doc(1, &doc(1)),
+            Err(ScoreError::DuplicateId { id: 1 })
+        ));
+        assert!(!index.remove_doc(0));
+    }
+
+    #[test]
+    fn tombstoned_documents_are_never_returned() {
+        let index = MaxSimIndex::new(DIM);
+        let query = query(4, 7);
+        for id in 0..30u64 {
+            index.add_doc(id, &doc(id)).unwrap();
+        }
+        // The best documents go first, so the next best must take over
+        let best: Vec<u64> = index.search(&query, 10).unwrap()[..5]
+            .iter()
+            .map(|hit| hit.id)
+            .collect();
+        for &id in &best {
+            assert!(index.remove_doc(id));
+        }
+        let hits = index.search(&query, 30).unwrap();
+        assert_eq!(hits.len(), 25);
+        assert!(hits.iter().all(|hit| !best.contains(&hit.id)));
+        let ranks: Vec<u32> = hits.iter().map(|hit| hit.rank).collect();
+        assert_eq!(ranks, (0..25).collect::<Vec<_>>());
+
+        let all: Vec<u64> = (0..30).collect();
+        let reranked = index.rerank(&query, &all, 30, OnMissing::Skip).unwrap();
+        assert_eq!(reranked, hits);
+        assert!(matches!(
+            index.rerank(&query, &best, 5, OnMissing::Error),
+            Err(ScoreError::UnknownId { .. })
+        ));
+        assert_eq!(index.metadata(best[0]), None);
+
+        // A removed id can be added again, as a new document
+        index
+            .add_doc_with_metadata(best[0], &doc(99), vec![9])
+            .unwrap();
+        let hits = index.search(&query, 30).unwrap();
+        let again = hits.iter().find(|hit| hit.id == best[0]).unwrap();
+        assert_eq!(again.score, expected(&[99], &query, 1)[0].score);
+        assert_eq!(index.metadata(best[0]), Some(vec![9]));
+    }
+
+    #[test]
+    fn searches_run_while_documents_are_added() {
+        const DOCS: u64 = 200;
+        let index = MaxSimIndex::new(DIM);
+        let query = query(6, 8);
+        let adding = AtomicBool::new(true);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for id in 0..DOCS {
+                    index.add_doc(id, &doc(id)).unwrap();
+                }
+                adding.store(false, Ordering::Release);
+            });
+            let mut searches = 0;
+            while adding.load(Ordering::Acquire) || searches == 0 {
+                let hits = index.search(&query, 10).unwrap();
+                // Documents arrive in id order, so a search sees some prefix
+                // of them, whose top 10 lie within the highest id returned
+                let seen = hits.iter().map(|hit| hit.id + 1).max().unwrap_or(0);
+                let prefix: Vec<u64> = (0..seen).collect();
+                assert_eq!(hits, expected(&prefix, &query, 10), "search {searches}");
+                searches += 1;
+            }
+        });
+        let all: Vec<u64> = (0..DOCS).collect();
+        assert_eq!(
+            index.search(&query, 10).unwrap(),
+            expected(&all, &query, 10)
+        );
+    }
+
+    /// An index of documents `0..n` with every third one removed, and the
+    /// live ids.
+    fn with_tombstones(n: u64) -> (MaxSimIndex, Vec<u64>) {
+        let index = MaxSimIndex::new(DIM);
+        for id in 0..n {
+            index
+                .add_doc_with_metadata(id, &doc(id), vec![id as u8])
+                .unwrap();
+        }
+        let (dead, live): (Vec<u64>, Vec<u64>) = (0..n).partition(|id| id % 3 == 0);
+        for &id in &dead {
+            assert!(index.remove_doc(id));
+        }
+        (index, live)
+    }
+
+    #[test]
+    fn compaction_keeps_results_and_reports_what_it_dropped() {
+        let (index, live) = with_tombstones(45);
+        let query = query(5, 3);
+        let before = index.search(&query, 20).unwrap();
+
+        let stats = index.compact().unwrap();
+        assert_eq!(stats.docs_removed, 15);
+        let reclaimed: usize = (0..45).step_by(3).map(|id| doc(id).len() * 4).sum();
+        assert_eq!(stats.bytes_reclaimed, reclaimed);
+        assert_eq!(index.tombstones(), 0);
+        assert_eq!(index.len(), live.len());
+        assert_eq!(index.search(&query, 20).unwrap(), before);
+        assert_eq!(before, expected(&live, &query, 20));
+        for &id in &live {
+            assert_eq!(index.metadata(id), Some(vec![id as u8]));
+        }
+
+        // Nothing left to drop, but documents added since move to the main
+        // segment
+        index.add_doc(100, &doc(100)).unwrap();
+        let stats = index.compact().unwrap();
+        assert_eq!((stats.docs_removed, stats.bytes_reclaimed), (0, 0));
+        let mut all = live.clone();
+        all.push(100);
+        assert_eq!(
+            index.search(&query, 20).unwrap(),
+            expected(&all, &query, 20)
+        );
+    }
+
+    #[test]
+    fn cancelled_compaction_leaves_the_index_unchanged() {
+        let (index, mut live) = with_tombstones(30);
+        let query = query(3, 4);
+        let before = index.search(&query, 15).unwrap();
+
+        let cancel = AtomicBool::new(true);
+        for round in 0..2 {
+            assert!(matches!(
+                index.compact_cancellable(&cancel),
+                Err(ScoreError::Cancelled)
+            ));
+            assert_eq!(index.len(), live.len(), "round {round}");
+            assert_eq!(index.tombstones(), 10, "round {round}");
+            assert_eq!(index.search(&query, 15).unwrap(), before, "round {round}");
+        }
+
+        // Still usable, and the next compaction drops everything removed
+        index.add_doc(50, &doc(50)).unwrap();
+        assert!(index.remove_doc(1));
+        live.retain(|&id| id != 1);
+        live.push(50);
+        assert_eq!(
+            index.search(&query, 15).unwrap(),
+            expected(&live, &query, 15)
+        );
+        let stats = index.compact().unwrap();
+        assert_eq!(stats.docs_removed, 11);
+        assert_eq!(index.tombstones(), 0);
+        assert_eq!(
+            index.search(&query, 15).unwrap(),
+            expected(&live, &query, 15)
+        );
+    }
+
+    #[test]
+    fn compaction_runs_alongside_searches() {
+        const ROUNDS: u64 = 8;
+        let (index, live) = with_tombstones(90);
+        let query = query(4, 5);
+        let everything = expected(&live, &query, live.len());
+        let compacting = AtomicBool::new(true);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                // Give each compaction a document to copy and one to drop
+                for round in 0..ROUNDS {
+                    index.add_doc(1000 + round, &doc(round)).unwrap();
+                    index.compact().unwrap();
+                    assert!(index.remove_doc(1000 + round));
+                }
+                index.compact().unwrap();
+                compacting.store(false, Ordering::Release);
+            });
+            let mut searches = 0;
+            while compacting.load(Ordering::Acquire) || searches == 0 {
+                // Every live document, whichever segment it sits in
+                let hits = index.search(&query, 200).unwrap();
+                let found: Vec<(u64, f32)> = hits
+                    .iter()
+                    .filter(|hit| hit.id < 1000)
+                    .map(|hit| (hit.id, hit.score))
+                    .collect();
+                let want: Vec<(u64, f32)> =
+                    everything.iter().map(|hit| (hit.id, hit.score)).collect();
+                assert_eq!(found, want, "search {searches}");
+                searches += 1;
+            }
+        });
+        assert_eq!(index.tombstones(), 0);
+        assert_eq!(index.search(&query, 200).unwrap(), everything);
+    }
+}
@@ -0,0 +1,207 @@
+//! IVF-style candidate generation ahead of exact MaxSim.
+//!
+//! At build time every document token is assigned to its nearest centroid,
+//! and each centroid keeps the list of documents with a token assigned to
+//! it. A search probes the `nprobe` centroids nearest to each query token,
+//! takes the union of their lists as candidates, and scores only those
+//! exactly. Documents with no token near any query token are never read,
+//! so results are approximate: recall grows with `nprobe`.
+
+use rayon::prelude::*;
+
+use crate::centroids::CentroidTable;
+use crate::collection::{DocId, Documents, QueryEmbeddings};
+use crate::rerank::Subset;
+use crate::score::{check_dim, check_len, top_k_heap, Reduction, ScoreError};
+use crate::topk::SearchHit;
+
+/// Inverted lists from centroids to the documents that have a token there.
+#[derive(Clone, Debug)]
+pub struct IvfIndex {
+    /// `[n_centroids, dim]` row-major.
+    centroids: Vec<f32>,
+    dim: usize,
+    /// Ascending doc ids per centroid.
+    lists: Vec<Vec<DocId>>,
+}
+
+/// Result of `IvfIndex::search`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IvfSearch {
+    pub hits: Vec<SearchHit>,
+    /// Documents scored exactly.
+    pub candidates: usize,
+}
+
+impl IvfIndex {
+    /// Assign every token of `docs` to its nearest of `centroids`
+    /// (`[n_centroids, dim]`, e.g. from `train_centroids`).
+    pub fn build<D: Documents + ?Sized>(docs: &D, centroids: Vec<f32>) -> Result<Self, ScoreError> {
+        let dim = docs.dim();
+        let n_centroids = centroids.len().checked_div(dim).unwrap_or(0);
+        check_len("centroids", &centroids, n_centroids, dim)?;
+        let has_tokens = (0..docs.len()).any(|i| docs.doc_len(i) > 0);
+        if n_centroids == 0 && has_tokens && dim > 0 {
+            return Err(ScoreError::EmptyCodebook);
+        }
+
+        let mut lists = vec![Vec::new(); n_centroids];
+        if dim > 0 {
+            let table = CentroidTable::new(&centroids, dim);
+            let assigned: Vec<Vec<u32>> = (0..docs.len())
+                .into_par_iter()
+                .map(|i| {
+                    let mut ids = table.nearest(docs.doc(i));
+                    ids.sort_unstable();
+                    ids.dedup();
+                    ids
+                })
+                .collect();
+            for (i, ids) in assigned.iter().enumerate() {
+                for &c in ids {
+                    lists[c as usize].push(i);
+                }
+            }
+        }
+        Ok(Self {
+            centroids,
+            dim,
+            lists,
+        })
+    }
+
+    pub fn n_centroids(&self) -> usize {
+        self.lists.len()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Documents with a token assigned to centroid `c`, ascending.
+    pub fn list(&self, c: usize) -> &[DocId] {
+        &self.lists[c]
+    }
+
+    /// The `k` best of the candidates for `query` in `docs`, the
+    /// collection the index was built from. Candidates are the documents
+    /// in the lists of the `nprobe` centroids nearest to each unmasked
+    /// query token; their scores are exact, as `maxsim_search` gives them.
+    pub fn search<D: Documents + ?Sized>(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &D,
+        k: usize,
+        nprobe: usize,
+    ) -> Result<IvfSearch, ScoreError> {
+        check_dim(query, self.dim)?;
+        check_dim(query, docs.dim())?;
+        let positions = self.candidates(query, docs.len(), nprobe);
+        let subset = Subset {
+            docs,
+            positions: &positions,
+        };
+        let top = top_k_heap(query, &subset, k, Reduction::default())?;
+        Ok(IvfSearch {
+            hits: top.into_hits(|i| subset.id(i)),
+            candidates: positions.len(),
+        })
+    }
+
+    /// Union of the lists of each unmasked query token's `nprobe` nearest
+    /// centroids, ascending.
+    fn candidates(&self, query: &QueryEmbeddings, n_docs: usize, nprobe: usize) -> Vec<DocId> {
+        let n_centroids = self.n_centroids();
+        let nprobe = nprobe.min(n_centroids);
+        if self.dim == 0 || nprobe == 0 {
+            return Vec::new();
+        }
+        let (q_data, _, tokens) = query.active();
+        let mut probed = vec![false; n_centroids];
+        let mut order: Vec<usize> = Vec::with_capacity(n_centroids);
+        CentroidTable::new(&self.centroids, self.dim).for_each_scored(q_data, |row, scores| {
+            if tokens.mask.is_some_and(|mask| !mask[row]) {
+                return;
+            }
+            order.clear();
+            order.extend(0..n_centroids);
+            if nprobe < n_centroids {
+                order.select_nth_unstable_by(nprobe - 1, |&a, &b| scores[b].total_cmp(&scores[a]));
+            }
+            for &c in &order[..nprobe] {
+                probed[c] = true;
+            }
+        });
+
+        let mut is_candidate = vec![false; n_docs];
+        for (list, _) in self.lists.iter().zip(&probed).filter(|(_, &p)| p) {
+            for &i in list {
+                is_candidate[i] = true;
+            }
+        }
+        (0..n_docs).filter(|&i| is_candidate[i]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::clustered_docs;
+    use crate::centroids::train_centroids;
+    use crate::collection::DocCollection;
+    use crate::score::maxsim_search;
+
+    const DIM: usize = 32;
+    const CLUSTERS: usize = 32;
+
+    fn collection() -> DocCollection {
+        let lengths: Vec<usize> = (0..300).map(|i| 8 + (i * 5) % 17).collect();
+        clustered_docs(&lengths, DIM, CLUSTERS, 61)
+    }
+
+    /// The first six tokens of document `i` as a query.
+    fn query(docs: &DocCollection, i: DocId) -> QueryEmbeddings {
+        QueryEmbeddings::new(docs.doc(i)[..6 * DIM].to_vec(), 6, DIM).unwrap()
+    }
+
+    #[test]
+    fn recall_against_exhaustive_search_is_high() {
+        const K: usize = 10;
+        let docs = collection();
+        let index = IvfIndex::build(&docs, train_centroids(&docs, CLUSTERS, 10)).unwrap();
+        let (mut found, mut candidates) = (0, 0);
+        let queries: Vec<DocId> = (0..docs.len()).step_by(15).collect();
+        for &i in &queries {
+            let query = query(&docs, i);
+            let exact = maxsim_search(&query, &docs, K).unwrap();
+            let approx = index.search(&query, &docs, K, 2).unwrap();
+            found += approx
+                .hits
+                .iter()
+                .filter(|hit| exact.iter().any(|e| e.id == hit.id))
+                .count();
+            candidates += approx.candidates;
+        }
+        let recall = found as f64 / (K * queries.len()) as f64;
+        assert!(recall > 0.95, "recall@{K} {recall}");
+        // Probing two centroids per token leaves many documents unread
+        let read = candidates as f64 / (docs.len() * queries.len()) as f64;
+        assert!(read < 0.75, "read {read} of the documents");
+    }
+
+    #[test]
+    fn probing_every_centroid_is_exact() {
+        let docs = collection();
+        let index = IvfIndex::build(&docs, train_centroids(&docs, CLUSTERS, 10)).unwrap();
+        assert_eq!(index.n_centroids(), CLUSTERS);
+        for i in [0, 101, 299] {
+            let query = query(&docs, i);
+            let exact = maxsim_search(&query, &docs, 25).unwrap();
+            for nprobe in [CLUSTERS, CLUSTERS + 5] {
+                let search = index.search(&query, &docs, 25, nprobe).unwrap();
+                assert_eq!(search.candidates, docs.len());
+                assert_eq!(search.hits, exact, "doc {i}, nprobe {nprobe}");
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Process-wide cache of dispatched JIT kernels.
+//!
+//! libxsmm keeps its own registry, but every `libxsmm_dispatch_gemm` still
+//! takes a lock and does a registry lookup. Hot loops should instead grab an
+//! `Arc<JitKernel>` from here once and reuse it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use libc::c_int;
+
+use crate::libxsmm_bindings::{
+    DispatchError, GemmSpec, JitKernel, Transpose, LIBXSMM_DATATYPE_F32, LIBXSMM_DATATYPE_I32,
+    LIBXSMM_DATATYPE_I8, LIBXSMM_DATATYPE_U8,
+};
+
+/// Thread-safe map from `GemmSpec` to dispatched kernel.
+///
+/// Failed dispatches are cached too, so an unsupported shape costs one
+/// dispatch attempt per process rather than one per call.
+#[derive(Default)]
+pub struct KernelCache {
+    kernels: RwLock<HashMap<GemmSpec, Result<Arc<JitKernel>, DispatchError>>>,
+}
+
+impl KernelCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The process-wide cache.
+    pub fn global() -> &'static KernelCache {
+        static GLOBAL: OnceLock<KernelCache> = OnceLock::new();
+        GLOBAL.get_or_init(KernelCache::new)
+    }
+
+    /// Return the kernel for `spec`, dispatching it on first use.
+    pub fn get(&self, spec: GemmSpec) -> Result<Arc<JitKernel>, DispatchError> {
+        if let Some(kernel) = self.kernels.read().unwrap().get(&spec) {
+            return kernel.clone();
+        }
+
+        trace_span!(
+            DEBUG,
+            "maxsim.jit_dispatch",
+            m = spec.m,
+            n = spec.n,
+            k = spec.k
+        );
+        // JIT outside the lock, so lookups of other specs never wait on it.
+        // Threads racing on the same spec each compile it; the first to
+        // insert wins and the others' kernels are dropped.
+        let kernel = JitKernel::from_spec(spec).map(Arc::new);
+        self.kernels
+            .write()
+            .unwrap()
+            .entry(spec)
+            .or_insert(kernel)
+            .clone()
+    }
+
+    /// Number of cached specs (including ones that failed to dispatch).
+    pub fn len(&self) -> usize {
+        self.kernels.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cached specs (including ones that failed to dispatch).
+    pub fn specs(&self) -> Vec<GemmSpec> {
+        self.kernels.read().unwrap().keys().copied().collect()
+    }
+
+    pub fn clear(&self) {
+        self.kernels.write().unwrap().clear();
+    }
+}
+
+/// Look up (or dispatch) a kernel in the global cache.
+pub fn get_kernel(spec: GemmSpec) -> Result<Arc<JitKernel>, DispatchError> {
+    KernelCache::global().get(spec)
+}
+
+/// Kernels dispatched eagerly for the tile shapes a scorer will hit.
+///
+/// The scorer computes one `[doc_bucket, query_len]` similarity tile per
+/// block of document tokens (A = doc block stored transposed, k = dim).
+/// `prebuild` dispatches every such shape up front through the global
+/// cache, so the first query pays no JIT latency and the scoring loop never
+/// dispatches lazily for a prebuilt shape.
+pub struct KernelSet {
+    kernels: HashMap<GemmSpec, Arc<JitKernel>>,
+    failed: Vec<DispatchError>,
+    elapsed: Duration,
+}
+
+impl KernelSet {
+    /// Dispatch the cross product of `dims` × `doc_buckets` for one query
+    /// length. `dtype` is the input datatype; outputs are f32 (i32 for int8).
+    pub fn prebuild(dims: &[i32], doc_buckets: &[i32], query_len: i32, dtype: c_int) -> Self {
+        let start = Instant::now();
+
+        let mut kernels = HashMap::new();
+        let mut failed = Vec::new();
+        for &dim in dims {
+            for &bucket in doc_buckets {
+                let spec = tile_spec(bucket, query_len, dim, dtype);
+                match get_kernel(spec) {
+                    Ok(kernel) => {
+                        kernels.insert(spec, kernel);
+                    }
+                    Err(e) => failed.push(e),
+                }
+            }
+        }
+
+        Self {
+            kernels,
+            failed,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Prebuilt kernel for one scorer tile, if it was part of the set.
+    pub fn get(
+        &self,
+        doc_bucket: i32,
+        query_len: i32,
+        dim: i32,
+        dtype: c_int,
+    ) -> Option<&Arc<JitKernel>> {
+        self.kernels
+            .get(&tile_spec(doc_bucket, query_len, dim, dtype))
+    }
+
+    pub fn get_spec(&self, spec: &GemmSpec) -> Option<&Arc<JitKernel>> {
+        self.kernels.get(spec)
+    }
+
+    /// Number of kernels successfully built.
+    pub fn built(&self) -> usize {
+        self.kernels.len()
+    }
+
+    /// Shapes libxsmm could not generate (the scorer uses SGEMM for these).
+    pub fn failed(&self) -> &[DispatchError] {
+        &self.failed
+    }
+
+    /// Wall time spent dispatching.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl std::fmt::Display for KernelSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "prebuilt {} kernels ({} failed) in {:.1} ms",
+            self.built(),
+            self.failed.len(),
+            self.elapsed.as_secs_f64() * 1e3
+        )
+    }
+}
+
+/// Spec of one scorer tile: C[doc_bucket, query_len] = docᵀ·query.
+fn tile_spec(doc_bucket: i32, query_len: i32, dim: i32, dtype: c_int) -> GemmSpec {
+    let out_type = match dtype {
+        LIBXSMM_DATATYPE_I8 | LIBXSMM_DATATYPE_U8 => LIBXSMM_DATATYPE_I32,
+        _ => LIBXSMM_DATATYPE_F32,
+    };
+    GemmSpec::packed(doc_bucket, query_len, dim, Transpose::A, dtype, out_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn concurrent_gets_share_one_kernel_per_spec() {
+        const THREADS: usize = 16;
+        let specs: Vec<GemmSpec> = [4, 8, 16, 32]
+            .iter()
+            .flat_map(|&m| [1, 7, 32].map(move |n| (m, n)))
+            .flat_map(|(m, n)| [16, 64, 128].map(move |k| (m, n, k)))
+            .map(|(m, n, k)| tile_spec(m, n, k, LIBXSMM_DATATYPE_F32))
+            .collect();
+        let cache = KernelCache::new();
+        let barrier = Barrier::new(THREADS);
+
+        let seen: Vec<Vec<Result<Arc<JitKernel>, DispatchError>>> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let (cache, barrier, specs) = (&cache, &barrier, &specs);
+                    s.spawn(move || {
+                        barrier.wait();
+                        // Each thread walks the specs from a different offset
+                        // so first dispatches race on every spec
+                        let mut seen = vec![None; specs.len()];
+                        for round in 0..4 {
+                            for i in 0..specs.len() {
+                                let i = (i + t * 5 + round) % specs.len();
+                                let kernel = cache.get(specs[i]);
+                                seen[i].get_or_insert(kernel);
+                            }
+                        }
+                        seen.into_iter().map(Option::unwrap).collect()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(cache.len(), specs.len());
+        for (i, spec) in specs.iter().enumerate() {
+            let cached = cache.get(*spec);
+            for thread in &seen {
+                match (&thread[i], &cached) {
+                    (Ok(a), Ok(b)) => assert!(Arc::ptr_eq(a, b), "{spec:?} handed out two kernels"),
+                    (a, b) => assert_eq!(a.as_ref().err(), b.as_ref().err()),
+                }
+            }
+        }
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
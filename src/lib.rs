@@ -4,37 +4,138 @@
 //! - x86_64 with AVX2 (Intel Haswell 2013+, AMD Excavator 2015+)
 //! - ARM64/AArch64 (Apple Silicon, AWS Graviton, etc.)
 
-#[cfg(not(any(
-    all(target_arch = "x86_64", target_feature = "avx2"),
-    target_arch = "aarch64"
-)))]
-
-use blas::sgemm;
+#[cfg(feature = "python")]
 use numpy::{PyArray1, PyReadonlyArray2, PyReadonlyArray3};
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
+#[cfg(feature = "python")]
 use rayon::prelude::*;
+#[cfg(feature = "python")]
 use std::cell::RefCell;
 
-#[cfg(feature = "use-libxsmm")]
+#[cfg(all(libxsmm, feature = "python"))]
 use libc::c_void;
 
-#[cfg(feature = "use-libxsmm")]
-mod libxsmm_bindings;
+#[macro_use]
+mod trace;
+
+#[cfg(libxsmm)]
+#[macro_use]
+pub mod libxsmm_link;
+
+#[cfg(libxsmm)]
+pub mod libxsmm_bindings;
+
+#[cfg(libxsmm)]
+pub mod kernel_cache;
+
+#[cfg(libxsmm)]
+pub mod gemm;
+
+#[cfg(libxsmm)]
+pub mod meltw;
+
+pub mod vnni;
+
+pub mod bf16;
+
+pub mod aligned;
+pub mod backend;
+pub mod bench;
+#[cfg(feature = "ndarray")]
+pub mod array;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod centroids;
+pub mod chunked;
+pub mod collection;
+pub mod docstore;
+pub mod f16;
+pub mod index;
+pub mod ivf;
+pub mod norm;
+#[cfg(feature = "numa")]
+pub mod numa;
+pub mod packed;
+pub mod pool;
+#[cfg(feature = "python")]
+mod python;
+pub mod quant;
+pub mod reduce;
+pub mod rerank;
+pub mod residual;
+pub mod safetensors;
+pub mod score;
+pub mod scorer;
+mod scratch;
+pub mod stats;
+pub mod store;
+pub mod stream;
+pub mod topk;
+pub use aligned::{is_aligned_for_kernels, AlignedVec, AllocPolicy, PageBacking, KERNEL_ALIGN};
+pub use backend::{compare_backends, set_backend, Backend, BackendReport, BackendRun};
+pub use bench::{
+    bench_gemm, bench_kernel_call, bench_loop_order, bench_maxsim, bench_stacked,
+    measured_crossover, BenchResult, GemmShape, LoopOrderPoint,
+};
+#[cfg(feature = "arrow")]
+pub use arrow::{
+    arrow_batch_rows, set_arrow_batch_rows, ArrowArray, ArrowError, ArrowRecordBatch, ArrowSchema,
+    ExportedRecordBatch,
+};
+#[cfg(feature = "ndarray")]
+pub use array::{
+    maxsim_per_token_array, maxsim_per_token_batch_array, maxsim_score_array,
+    maxsim_score_matrix_array,
+};
+pub use centroids::train_centroids;
+pub use chunked::{ChunkConfig, ChunkStats, ChunkedDocStore, ChunkedError};
+pub use collection::{
+    Bf16DocCollection, DocBatch, DocCollection, DocId, Documents, F16DocCollection,
+    Int8DocCollection, QueryBatch, QueryEmbeddings,
+};
+pub use docstore::{BuildStats, DocStore, DocStoreBuilder, OnOverflow, StoreDocs};
+pub use index::{CompactStats, MaxSimIndex};
+pub use ivf::{IvfIndex, IvfSearch};
+pub use norm::normalize_rows_inplace;
+#[cfg(feature = "numa")]
+pub use numa::{NumaConfig, NumaDocStore, NumaNode, NumaTopology};
+pub use packed::{write_packed_store, ArchFamily, OnMismatch, Operand, PackedDocStore};
+pub use pool::physical_cores;
+pub use rerank::{maxsim_rerank, OnMissing};
+pub use residual::{ResidualCodebook, ResidualDocCollection};
+pub use safetensors::SafetensorsError;
+pub use score::{
+    maxsim_per_token, maxsim_per_token_batch, maxsim_score, maxsim_score_batch,
+    maxsim_score_masked, maxsim_score_matrix, maxsim_score_threshold, maxsim_score_with_matches,
+    maxsim_search, maxsim_top_k, maxsim_top_k_batch, maxsim_top_k_pruned, PruneStats, ScoreError,
+};
+pub use scorer::{
+    Aggregation, Direction, Fallback, LoopOrder, MaxSimScorer, Partitioning, Precision, Scorer,
+    ScorerConfig, Similarity, WarmupReport, WarmupShapes,
+};
+pub use stats::{KernelKind, KernelShape, ScorerStats, StageTime, Timing};
+pub use store::{
+    write_doc_store, write_doc_store_as, MmapDocStore, MmapF16DocStore, Storage, StoreDtype,
+    StoreError,
+};
+pub use stream::{maxsim_score_stream, DocChunk, StreamError, TopKStream};
+pub use topk::{SearchHit, SearchResults, TopK};
 
 
 // Thread-local buffers to avoid repeated allocations
+#[cfg(feature = "python")]
 thread_local! {
-    static SIMILARITY_BUFFER: RefCell<Vec<f32>> = RefCell::new(Vec::new());
-    static TEMP_BUFFER: RefCell<Vec<f32>> = RefCell::new(Vec::new());
+    static SIMILARITY_BUFFER: RefCell<Vec<f32>> = const { RefCell::new(Vec::new()) };
+    static TEMP_BUFFER: RefCell<Vec<f32>> = const { RefCell::new(Vec::new()) };
     static BATCH_BUFFER: RefCell<Vec<f32>> = RefCell::new(Vec::with_capacity(1024 * 1024));
 }
 
-// Initialize libxsmm only once
-#[cfg(feature = "use-libxsmm")]
-use std::sync::Once;
-
-#[cfg(feature = "use-libxsmm")]
-static LIBXSMM_INIT: Once = Once::new();
+// Process-lifetime libxsmm context: initialized on first use, never finalized
+#[cfg(libxsmm)]
+static LIBXSMM_CTX: std::sync::OnceLock<libxsmm_bindings::LibxsmmContext> = std::sync::OnceLock::new();
 
 // SIMD module with platform-specific implementations.
 // AVX-512 added, with AVX2 and NEON fallbacks.
@@ -103,8 +204,8 @@ mod simd {
         let mut result = _mm512_reduce_max_ps(max_vec0);
 
         // Scalar tail
-        for j in i..slice.len() {
-            result = result.max(slice[j]);
+        for &x in &slice[i..] {
+            result = result.max(x);
         }
 
         result
@@ -168,8 +269,8 @@ mod simd {
         let mut result = _mm_cvtss_f32(final_max);
 
         // Handle remaining elements
-        for j in i..slice.len() {
-            result = result.max(slice[j]);
+        for &x in &slice[i..] {
+            result = result.max(x);
         }
 
         result
@@ -239,13 +340,22 @@ mod simd {
     pub fn simd_max_avx2(slice: &[f32]) -> f32 {
         slice.iter().copied().fold(f32::NEG_INFINITY, f32::max)
     }
+
+    /// Max and the index of its first occurrence (SIMD max, then a scan for it).
+    #[inline]
+    pub fn simd_argmax(slice: &[f32]) -> (f32, usize) {
+        let max = simd_max_avx2(slice);
+        (max, slice.iter().position(|&x| x == max).unwrap_or(0))
+    }
 }
 
 // MaxSim algorithm.
+#[cfg(feature = "python")]
 mod algorithm {
     use super::*;
     use crate::simd::simd_max_avx2;
-    use blas::sgemm;
+    use crate::backend::sgemm;
+    use crate::reduce::masked_max_sum;
     
     /// Process a single variable-length document directly
     fn process_single_doc(
@@ -288,12 +398,17 @@ mod algorithm {
     }
     
     /// Fused GEMM+reduction with document tiling
+    ///
+    /// With `valid_lens`, document `i` is padded to `d_len` and only its first
+    /// `valid_lens[i]` tokens take part in the per-query-token max (see
+    /// `reduce::masked_max_sum`); a document with none scores 0.
     pub fn maxsim_fused_doc_tiles(
         q: &[f32],           // [q_len * dim]
         d: &[f32],           // [n_docs * d_len * dim]
         q_len: usize,
         d_len: usize,
         dim: usize,
+        valid_lens: Option<&[usize]>, // [n_docs], each <= d_len
     ) -> Vec<f32> {
         let n_docs = d.len() / (d_len * dim);
         
@@ -305,13 +420,19 @@ mod algorithm {
             (0..n_docs).into_par_iter().map(|doc_idx| {
                 let doc_offset = doc_idx * d_len * dim;
                 let doc_data = &d[doc_offset..doc_offset + d_len * dim];
+                // Padding rows are never multiplied
+                let valid_len = valid_lens.map_or(d_len, |lens| lens[doc_idx]);
+                if valid_len == 0 {
+                    // No tokens: 0, as in `masked_max_sum`
+                    return 0.0;
+                }
                 
                 // Process in smaller blocks to fit in L2 cache
                 let block_size = 64; // Claude says this is the best value to fit in cache for most Apple chips.
                 let mut max_vals = vec![f32::NEG_INFINITY; q_len];
                 
-                for block_start in (0..d_len).step_by(block_size) {
-                    let block_end = (block_start + block_size).min(d_len);
+                for block_start in (0..valid_len).step_by(block_size) {
+                    let block_end = (block_start + block_size).min(valid_len);
                     let actual_block_size = block_end - block_start;
                     
                     // Compute similarities for this block
@@ -385,16 +506,9 @@ mod algorithm {
                 
                 let tile_results: Vec<f32> = (0..tile_docs).into_par_iter().map(|tile_doc_idx| {
                     let doc_start = tile_doc_idx * d_len;
-                    let mut score = 0.0f32;
-                    
-                    for qi in 0..q_len {
-                        let base_idx = doc_start + qi * tile_tokens;
-                        let doc_sims = &tile_sims[base_idx..base_idx + d_len];
-                        let max_val = simd_max_avx2(doc_sims);
-                        score += max_val;
-                    }
-                    
-                    score
+                    // Only scan the unpadded columns of this doc
+                    let valid_len = valid_lens.map_or(d_len, |lens| lens[doc_tile_start + tile_doc_idx]);
+                    masked_max_sum(&tile_sims[doc_start..], q_len, tile_tokens, valid_len)
                 }).collect();
                 
                 for (i, &score) in tile_results.iter().enumerate() {
@@ -413,14 +527,14 @@ mod algorithm {
         d_len: usize,
         dim: usize,
     ) -> Vec<f32> {
-        #[cfg(feature = "use-libxsmm")]
+        #[cfg(libxsmm)]
         {
             crate::libxsmm::maxsim_libxsmm_clean(q, d, q_len, d_len, dim)
         }
         
-        #[cfg(not(feature = "use-libxsmm"))]
+        #[cfg(not(libxsmm))]
         {
-            maxsim_fused_doc_tiles(q, d, q_len, d_len, dim)
+            maxsim_fused_doc_tiles(q, d, q_len, d_len, dim, None)
         }
     }
     
@@ -431,12 +545,12 @@ mod algorithm {
         q_len: usize,
         dim: usize,
     ) -> Vec<f32> {
-        #[cfg(feature = "use-libxsmm")]
+        #[cfg(libxsmm)]
         {
             return crate::libxsmm::maxsim_libxsmm_variable(q, doc_infos, q_len, dim);
         }
         
-        #[cfg(not(feature = "use-libxsmm"))]
+        #[cfg(not(libxsmm))]
         {
             let n_docs = doc_infos.len();
             let mut results = vec![0.0f32; n_docs];
@@ -463,8 +577,9 @@ mod algorithm {
                     }
                     
                     // Process all at once
+                    let valid_lens: Vec<usize> = doc_infos.iter().map(|(_, len, _)| *len).collect();
                     let batch_results = maxsim_fused_doc_tiles(
-                        q, &buffer[..required_size], q_len, max_len, dim
+                        q, &buffer[..required_size], q_len, max_len, dim, Some(&valid_lens)
                     );
                     
                     // Results are already in correct order
@@ -529,7 +644,7 @@ mod algorithm {
                             
                             // Process with no wasted computation
                             maxsim_fused_doc_tiles(
-                                q, &buffer[..required_size], q_len, first_len, dim
+                                q, &buffer[..required_size], q_len, first_len, dim, None
                             )
                         });
                         
@@ -572,8 +687,12 @@ mod algorithm {
                             }
                             
                             // Process batch with optimized kernel
+                            let valid_lens: Vec<usize> = sorted_indices[i..batch_end]
+                                .iter()
+                                .map(|&idx| doc_infos[idx].1)
+                                .collect();
                             maxsim_fused_doc_tiles(
-                                q, &buffer[..required_size], q_len, max_len, dim
+                                q, &buffer[..required_size], q_len, max_len, dim, Some(&valid_lens)
                             )
                         });
                         
@@ -618,8 +737,12 @@ mod algorithm {
                         }
                         
                         // Process batch with optimized kernel
+                        let valid_lens: Vec<usize> = sorted_indices[i..batch_end]
+                            .iter()
+                            .map(|&idx| doc_infos[idx].1)
+                            .collect();
                         maxsim_fused_doc_tiles(
-                            q, &buffer[..required_size], q_len, max_len, dim
+                            q, &buffer[..required_size], q_len, max_len, dim, Some(&valid_lens)
                         )
                     });
                     
@@ -644,24 +767,77 @@ mod algorithm {
 //   Path 1: BLAS wrapper (libxsmm_sgemm) — auto-JIT, always works
 //   Path 2: JIT dispatch (libxsmm_dispatch_gemm) — explicit JIT, zero dispatch overhead
 //
-// Path 2 is used when the block size is known ahead of time (fixed d_len).
-// Path 1 is the fallback for variable-length documents.
-#[cfg(feature = "use-libxsmm")]
+// `gemm::Gemm` picks between them per block shape: Path 2 whenever libxsmm
+// can JIT the shape, Path 1 otherwise.
+#[cfg(all(libxsmm, feature = "python"))]
 mod libxsmm {
     use super::*;
+    use crate::gemm::Gemm;
+    use crate::libxsmm_bindings::{Beta, Transpose};
+    use crate::meltw::BinaryKernel;
+
+    /// Block GEMM for a [block_size, dim] doc tile against the query.
+    ///
+    /// Both operands are row-major [tokens, dim], so the doc block is the
+    /// transposed A operand (lda = dim) and C is [q_len, block_size].
+    /// Runs as a JIT kernel when libxsmm can generate one, SGEMM otherwise.
+    fn block_gemm(block_size: usize, q_len: usize, dim: usize) -> Gemm {
+        Gemm::f32(block_size as i32, q_len as i32, dim as i32, Transpose::A, Beta::Zero)
+    }
 
-    /// Try to get a JIT kernel for the given block GEMM shape.
-    /// Returns None if LIBXSMM can't JIT for this shape (falls back to SGEMM).
-    fn try_jit_kernel(block_size: usize, q_len: usize, dim: usize) -> Option<libxsmm_bindings::JitKernel> {
-        // JIT dispatch works best for small, fixed shapes (M,N,K ≤ 256)
-        if block_size > 256 || q_len > 256 || dim > 4096 {
-            return None;
+    /// Running per-query-token max over one document, tiled in blocks of
+    /// `full.m()` tokens.
+    ///
+    /// With `tile_max`, full blocks are folded element-wise into a running
+    /// [q_len, block] max tile and only reduced per query token once at the end.
+    fn doc_max_sum(
+        full: &Gemm,
+        tile_max: Option<&BinaryKernel>,
+        q: &[f32],
+        doc_data: &[f32],
+        q_len: usize,
+        doc_len: usize,
+        dim: usize,
+    ) -> f32 {
+        let block_size = full.spec().m as usize;
+        let mut max_vals = vec![f32::NEG_INFINITY; q_len];
+        let mut running = tile_max.map(|_| vec![f32::NEG_INFINITY; q_len * block_size]);
+
+        for t in (0..doc_len).step_by(block_size) {
+            let actual_block_size = block_size.min(doc_len - t);
+            let mut c = vec![0.0f32; q_len * actual_block_size];
+            let block = &doc_data[t * dim..(t + actual_block_size) * dim];
+
+            // Tail block gets its own (cached) shape
+            let tail;
+            let gemm = if actual_block_size == block_size {
+                full
+            } else {
+                tail = block_gemm(actual_block_size, q_len, dim);
+                &tail
+            };
+            gemm.run(block, q, &mut c).expect("block GEMM operands sized from its own shape");
+
+            if let (Some(kernel), Some(running), true) = (tile_max, running.as_mut(), actual_block_size == block_size) {
+                kernel.call_f32_inplace(running, &c).expect("max tile sized from the block shape");
+                continue;
+            }
+
+            // Update max values with SIMD
+            for qi in 0..q_len {
+                let base = qi * actual_block_size;
+                let block_sims = &c[base..base + actual_block_size];
+                max_vals[qi] = max_vals[qi].max(simd::simd_max_avx2(block_sims));
+            }
         }
-        libxsmm_bindings::JitKernel::f32_gemm(
-            block_size as i32,
-            q_len as i32,
-            dim as i32,
-        )
+
+        if let Some(running) = running {
+            for (max_val, tile_col) in max_vals.iter_mut().zip(running.chunks_exact(block_size)) {
+                *max_val = max_val.max(simd::simd_max_avx2(tile_col));
+            }
+        }
+
+        max_vals.iter().sum()
     }
 
     /// Clean libxsmm implementation with JIT dispatch.
@@ -674,147 +850,38 @@ mod libxsmm {
         d_len: usize,
         dim: usize,
     ) -> Vec<f32> {
-        LIBXSMM_INIT.call_once(|| {
-            unsafe { libxsmm_bindings::libxsmm_init(); }
-        });
+        LIBXSMM_CTX.get_or_init(libxsmm_bindings::LibxsmmContext::acquire);
 
         let n_docs = d.len() / (d_len * dim);
         let block_size = 64; // L2 cache tile size
-
-        // Try JIT dispatch for the common block size
-        let jit_kernel = try_jit_kernel(block_size, q_len, dim);
+        let full = block_gemm(block_size, q_len, dim);
+        let tile_max = BinaryKernel::max_f32(block_size as i32, q_len as i32);
 
         (0..n_docs).into_par_iter().map(|doc_idx| {
             let doc_offset = doc_idx * d_len * dim;
             let doc_data = &d[doc_offset..doc_offset + d_len * dim];
-            let mut max_vals = vec![f32::NEG_INFINITY; q_len];
-
-            for t in (0..d_len).step_by(block_size) {
-                let actual_block_size = block_size.min(d_len - t);
-                let mut c = vec![0.0f32; q_len * actual_block_size];
-
-                // Use JIT kernel if available AND block is full-size
-                if let Some(ref kernel) = jit_kernel {
-                    if actual_block_size == block_size {
-                        unsafe {
-                            kernel.call(
-                                doc_data.as_ptr().add(t * dim) as *const libc::c_void,
-                                q.as_ptr() as *const libc::c_void,
-                                c.as_mut_ptr() as *mut libc::c_void,
-                            );
-                        }
-                    } else {
-                        // Tail block: fall back to SGEMM
-                        unsafe {
-                            libxsmm_bindings::xsmm_sgemm(
-                                b'T', b'N',
-                                actual_block_size as i32, q_len as i32, dim as i32,
-                                1.0,
-                                doc_data.as_ptr().add(t * dim), dim as i32,
-                                q.as_ptr(), dim as i32,
-                                0.0,
-                                c.as_mut_ptr(), actual_block_size as i32,
-                            );
-                        }
-                    }
-                } else {
-                    // No JIT: use BLAS wrapper
-                    unsafe {
-                        libxsmm_bindings::xsmm_sgemm(
-                            b'T', b'N',
-                            actual_block_size as i32, q_len as i32, dim as i32,
-                            1.0,
-                            doc_data.as_ptr().add(t * dim), dim as i32,
-                            q.as_ptr(), dim as i32,
-                            0.0,
-                            c.as_mut_ptr(), actual_block_size as i32,
-                        );
-                    }
-                }
-
-                // Update max values with SIMD
-                for qi in 0..q_len {
-                    let base = qi * actual_block_size;
-                    let block_sims = &c[base..base + actual_block_size];
-                    max_vals[qi] = max_vals[qi].max(simd::simd_max_avx2(block_sims));
-                }
-            }
-
-            max_vals.iter().sum()
+            doc_max_sum(&full, tile_max.as_ref(), q, doc_data, q_len, d_len, dim)
         }).collect()
     }
 
     /// Process variable-length documents with libxsmm.
-    /// Uses SGEMM (auto-JIT) since block sizes vary per document.
+    /// Full blocks share one kernel; tail blocks hit the kernel cache per shape.
     pub fn maxsim_libxsmm_variable(
         q: &[f32],                                    // [q_len * dim]
         doc_infos: Vec<(usize, usize, &[f32])>,     // [(doc_idx, doc_len, doc_data)]
         q_len: usize,
         dim: usize,
     ) -> Vec<f32> {
-        LIBXSMM_INIT.call_once(|| {
-            unsafe { libxsmm_bindings::libxsmm_init(); }
-        });
+        LIBXSMM_CTX.get_or_init(libxsmm_bindings::LibxsmmContext::acquire);
 
         let n_docs = doc_infos.len();
         let block_size = 64;
-
-        // Try JIT for the common full-block shape
-        let jit_kernel = try_jit_kernel(block_size, q_len, dim);
+        let full = block_gemm(block_size, q_len, dim);
+        let tile_max = BinaryKernel::max_f32(block_size as i32, q_len as i32);
 
         let mut results = vec![0.0f32; n_docs];
         let results_vec: Vec<(usize, f32)> = doc_infos.into_par_iter().map(|(doc_idx, doc_len, doc_data)| {
-            let mut max_vals = vec![f32::NEG_INFINITY; q_len];
-
-            for t in (0..doc_len).step_by(block_size) {
-                let actual_block_size = block_size.min(doc_len - t);
-                let mut c = vec![0.0f32; q_len * actual_block_size];
-
-                if let Some(ref kernel) = jit_kernel {
-                    if actual_block_size == block_size {
-                        unsafe {
-                            kernel.call(
-                                doc_data.as_ptr().add(t * dim) as *const libc::c_void,
-                                q.as_ptr() as *const libc::c_void,
-                                c.as_mut_ptr() as *mut libc::c_void,
-                            );
-                        }
-                    } else {
-                        unsafe {
-                            libxsmm_bindings::xsmm_sgemm(
-                                b'T', b'N',
-                                actual_block_size as i32, q_len as i32, dim as i32,
-                                1.0,
-                                doc_data.as_ptr().add(t * dim), dim as i32,
-                                q.as_ptr(), dim as i32,
-                                0.0,
-                                c.as_mut_ptr(), actual_block_size as i32,
-                            );
-                        }
-                    }
-                } else {
-                    unsafe {
-                        libxsmm_bindings::xsmm_sgemm(
-                            b'T', b'N',
-                            actual_block_size as i32, q_len as i32, dim as i32,
-                            1.0,
-                            doc_data.as_ptr().add(t * dim), dim as i32,
-                            q.as_ptr(), dim as i32,
-                            0.0,
-                            c.as_mut_ptr(), actual_block_size as i32,
-                        );
-                    }
-                }
-
-                // Update max values with SIMD
-                for qi in 0..q_len {
-                    let base = qi * actual_block_size;
-                    let block_sims = &c[base..base + actual_block_size];
-                    max_vals[qi] = max_vals[qi].max(simd::simd_max_avx2(block_sims));
-                }
-            }
-
-            (doc_idx, max_vals.iter().sum())
+            (doc_idx, doc_max_sum(&full, tile_max.as_ref(), q, doc_data, q_len, doc_len, dim))
         }).collect();
 
         for (doc_idx, score) in results_vec {
@@ -823,16 +890,56 @@ mod libxsmm {
 
         results
     }
+
+    /// f64 reference implementation over a fixed-length batch.
+    ///
+    /// Same layout and result order as `maxsim_libxsmm_clean`, so the two can
+    /// be diffed to quantify f32 (or bf16) error.
+    pub fn maxsim_libxsmm_f64(
+        q: &[f64],           // [q_len * dim]
+        d: &[f64],           // [n_docs * d_len * dim]
+        q_len: usize,
+        d_len: usize,
+        dim: usize,
+    ) -> Vec<f64> {
+        LIBXSMM_CTX.get_or_init(libxsmm_bindings::LibxsmmContext::acquire);
+
+        let n_docs = d.len() / (d_len * dim);
+
+        (0..n_docs).into_par_iter().map(|doc_idx| {
+            let doc_data = &d[doc_idx * d_len * dim..(doc_idx + 1) * d_len * dim];
+            let mut c = vec![0.0f64; q_len * d_len];
+
+            unsafe {
+                libxsmm_bindings::xsmm_dgemm(
+                    b'T', b'N',
+                    d_len as i32, q_len as i32, dim as i32,
+                    1.0,
+                    doc_data.as_ptr(), dim as i32,
+                    q.as_ptr(), dim as i32,
+                    0.0,
+                    c.as_mut_ptr(), d_len as i32,
+                );
+            }
+
+            c.chunks_exact(d_len)
+                .map(|sims| sims.iter().copied().fold(f64::NEG_INFINITY, f64::max))
+                .sum()
+        }).collect()
+    }
 }
 
 // from here onwards, we're back in the safety of python land.
+#[cfg(feature = "python")]
 #[pymodule]
 fn maxsim_cpu(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(maxsim_scores, m)?)?;
     m.add_function(wrap_pyfunction!(maxsim_scores_variable, m)?)?;
+    python::register(m)?;
     Ok(())
 }
 
+#[cfg(feature = "python")]
 #[pyfunction]
 fn maxsim_scores<'py>(
     py: Python<'py>,
@@ -865,6 +972,7 @@ fn maxsim_scores<'py>(
     Ok(PyArray1::from_vec(py, scores))
 }
 
+#[cfg(feature = "python")]
 #[pyfunction]
 fn maxsim_scores_variable<'py>(
     py: Python<'py>,
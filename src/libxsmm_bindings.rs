@@ -1,8 +1,13 @@
-use libc::{c_char, c_float, c_int, c_void};
+use libc::{c_char, c_double, c_float, c_int, c_void};
+
+use std::sync::OnceLock;
+
+use crate::libxsmm_link::loaded;
+use crate::vnni::VnniLayout;
 
 // Type aliases matching libxsmm
 type LibxsmmBlasint = c_int; // LP64: 32-bit int
-type LibxsmmBitfield = libc::c_uint;
+pub type LibxsmmBitfield = libc::c_uint;
 
 // ============================================================================
 // Data types (from libxsmm_typedefs.h line 209)
@@ -12,6 +17,7 @@ pub const LIBXSMM_DATATYPE_F64: c_int = 0;
 pub const LIBXSMM_DATATYPE_F32: c_int = 1;
 pub const LIBXSMM_DATATYPE_BF16: c_int = 2;
 pub const LIBXSMM_DATATYPE_F16: c_int = 3;
+pub const LIBXSMM_DATATYPE_I32: c_int = 8;
 pub const LIBXSMM_DATATYPE_I8: c_int = 12;
 pub const LIBXSMM_DATATYPE_U8: c_int = 13;
 
@@ -20,10 +26,39 @@ pub const LIBXSMM_DATATYPE_U8: c_int = 13;
 // ============================================================================
 
 pub const LIBXSMM_GEMM_FLAG_NONE: LibxsmmBitfield = 0;
+pub const LIBXSMM_GEMM_FLAG_TRANS_A: LibxsmmBitfield = 1;
+pub const LIBXSMM_GEMM_FLAG_TRANS_B: LibxsmmBitfield = 2;
 pub const LIBXSMM_GEMM_FLAG_BETA_0: LibxsmmBitfield = 4;
+pub const LIBXSMM_GEMM_FLAG_NO_RESET_TILECONFIG: LibxsmmBitfield = 64; // AMX: leave tiles configured
+pub const LIBXSMM_GEMM_FLAG_NO_SETUP_TILECONFIG: LibxsmmBitfield = 128; // AMX: assume tiles configured
 pub const LIBXSMM_GEMM_FLAG_VNNI_A: LibxsmmBitfield = 2048;
 pub const LIBXSMM_GEMM_FLAG_VNNI_B: LibxsmmBitfield = 4096;
 pub const LIBXSMM_GEMM_FLAG_A_UNSIGNED: LibxsmmBitfield = 256;
+pub const LIBXSMM_GEMM_FLAG_B_UNSIGNED: LibxsmmBitfield = 512;
+pub const LIBXSMM_GEMM_FLAG_AB_UNSIGNED: LibxsmmBitfield = 768;
+
+// ============================================================================
+// Prefetch strategies (from libxsmm_typedefs.h, libxsmm_gemm_prefetch_type)
+// ============================================================================
+
+pub const LIBXSMM_GEMM_PREFETCH_NONE: LibxsmmBitfield = 0;
+pub const LIBXSMM_GEMM_PREFETCH_SIGONLY: LibxsmmBitfield = 1;
+pub const LIBXSMM_GEMM_PREFETCH_BL2_VIA_C: LibxsmmBitfield = 2;
+pub const LIBXSMM_GEMM_PREFETCH_AL2_AHEAD: LibxsmmBitfield = 4;
+pub const LIBXSMM_GEMM_PREFETCH_AL2BL2_VIA_C_AHEAD: LibxsmmBitfield = 6;
+pub const LIBXSMM_GEMM_PREFETCH_AL2: LibxsmmBitfield = 8;
+pub const LIBXSMM_GEMM_PREFETCH_AL2BL2_VIA_C: LibxsmmBitfield = 10;
+pub const LIBXSMM_GEMM_PREFETCH_AL1: LibxsmmBitfield = 32;
+pub const LIBXSMM_GEMM_PREFETCH_BL1: LibxsmmBitfield = 64;
+
+// ============================================================================
+// Batch-reduce types (from libxsmm_typedefs.h, libxsmm_gemm_batch_reduce_type)
+// ============================================================================
+
+pub const LIBXSMM_GEMM_BATCH_REDUCE_NONE: c_int = 0;
+pub const LIBXSMM_GEMM_BATCH_REDUCE_ADDRESS: c_int = 1;
+pub const LIBXSMM_GEMM_BATCH_REDUCE_OFFSET: c_int = 2;
+pub const LIBXSMM_GEMM_BATCH_REDUCE_STRIDE: c_int = 3;
 
 // ============================================================================
 // Architecture IDs (from libxsmm_cpuid.h)
@@ -112,21 +147,53 @@ pub struct LibxsmmGemmParam {
     pub c: LibxsmmMatrixArg,
 }
 
+/// Batch-reduce GEMM configuration. Strides are in bytes and only used by
+/// the STRIDE variant.
+/// From libxsmm_typedefs.h (libxsmm_gemm_batch_reduce_config).
+#[repr(C)]
+#[derive(Clone)]
+pub struct LibxsmmGemmBatchReduceConfig {
+    pub br_type: c_int,
+    pub br_stride_a_hint: LibxsmmBlasint,
+    pub br_stride_b_hint: LibxsmmBlasint,
+    pub br_unroll_hint: libc::c_uchar,
+}
+
+/// Code registry statistics.
+/// From libxsmm_typedefs.h (libxsmm_registry_info).
+#[repr(C)]
+#[derive(Clone, Default)]
+pub struct LibxsmmRegistryInfo {
+    pub capacity: libc::size_t,
+    pub size: libc::size_t,
+    pub nbytes: libc::size_t,
+    pub nstatic: libc::size_t,
+    pub ncache: libc::size_t,
+}
+
 /// JIT-compiled GEMM function pointer type.
 pub type LibxsmmGemmFunction = unsafe extern "C" fn(*const LibxsmmGemmParam);
 
+/// JIT-compiled AMX tile (re)configuration function. Called with a null
+/// state pointer it applies the configuration baked in at dispatch.
+pub type LibxsmmTileCfgFunction = unsafe extern "C" fn(*const c_void);
+
 // ============================================================================
 // FFI function bindings
 // ============================================================================
 
-#[link(name = "xsmm")]
-extern "C" {
+libxsmm_extern! {
     // Lifecycle
     pub fn libxsmm_init();
     pub fn libxsmm_finalize();
 
     // Architecture detection
     pub fn libxsmm_get_target_archid() -> c_int;
+    pub fn libxsmm_set_target_archid(id: c_int);
+    pub fn libxsmm_set_target_arch(arch: *const c_char);
+
+    // Introspection
+    pub fn libxsmm_get_registry_info(info: *mut LibxsmmRegistryInfo) -> c_int;
 
     // Shape constructor (convenience — fills a struct)
     pub fn libxsmm_create_gemm_shape(
@@ -142,6 +209,13 @@ extern "C" {
         comp_type: c_int,
     ) -> LibxsmmGemmShape;
 
+    // AMX tile configuration kernel for a GEMM shape. With NO_RESET_TILECONFIG
+    // it sets the tiles up, with NO_SETUP_TILECONFIG it releases them.
+    pub fn libxsmm_dispatch_tilecfg_gemm(
+        gemm_shape: LibxsmmGemmShape,
+        gemm_flags: LibxsmmBitfield,
+    ) -> Option<LibxsmmTileCfgFunction>;
+
     // JIT dispatch — returns function pointer to generated machine code.
     // Returns null if shape/type unsupported for this CPU.
     pub fn libxsmm_dispatch_gemm(
@@ -150,6 +224,14 @@ extern "C" {
         prefetch_flags: LibxsmmBitfield,
     ) -> Option<LibxsmmGemmFunction>;
 
+    // Batch-reduce JIT dispatch: C = sum_i A_i · B_i in one call.
+    pub fn libxsmm_dispatch_brgemm(
+        gemm_shape: LibxsmmGemmShape,
+        gemm_flags: LibxsmmBitfield,
+        prefetch_flags: LibxsmmBitfield,
+        brgemm_config: LibxsmmGemmBatchReduceConfig,
+    ) -> Option<LibxsmmGemmFunction>;
+
     // BLAS-compatible SGEMM (auto-JIT internally, fallback path)
     pub fn libxsmm_sgemm(
         transa: *const c_char,
@@ -166,6 +248,23 @@ extern "C" {
         c: *mut c_float,
         ldc: *const LibxsmmBlasint,
     );
+
+    // BLAS-compatible DGEMM (same calling convention as SGEMM)
+    pub fn libxsmm_dgemm(
+        transa: *const c_char,
+        transb: *const c_char,
+        m: *const LibxsmmBlasint,
+        n: *const LibxsmmBlasint,
+        k: *const LibxsmmBlasint,
+        alpha: *const c_double,
+        a: *const c_double,
+        lda: *const LibxsmmBlasint,
+        b: *const c_double,
+        ldb: *const LibxsmmBlasint,
+        beta: *const c_double,
+        c: *mut c_double,
+        ldc: *const LibxsmmBlasint,
+    );
 }
 
 // ============================================================================
@@ -173,6 +272,12 @@ extern "C" {
 // ============================================================================
 
 /// Safe wrapper for SGEMM via LIBXSMM.
+///
+/// # Safety
+///
+/// `a`, `b` and `c` must cover the column-major operands described by the
+/// shape, transposes and leading dimensions.
+#[allow(clippy::too_many_arguments)]
 pub unsafe fn xsmm_sgemm(
     transa: u8,
     transb: u8,
@@ -214,70 +319,2329 @@ pub unsafe fn xsmm_sgemm(
     );
 }
 
+/// Safe wrapper for DGEMM via LIBXSMM.
+///
+/// # Safety
+///
+/// Same contract as `xsmm_sgemm`.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn xsmm_dgemm(
+    transa: u8,
+    transb: u8,
+    m: i32,
+    n: i32,
+    k: i32,
+    alpha: f64,
+    a: *const f64,
+    lda: i32,
+    b: *const f64,
+    ldb: i32,
+    beta: f64,
+    c: *mut f64,
+    ldc: i32,
+) {
+    let transa_char = transa as c_char;
+    let transb_char = transb as c_char;
+    let m_blasint = m as LibxsmmBlasint;
+    let n_blasint = n as LibxsmmBlasint;
+    let k_blasint = k as LibxsmmBlasint;
+    let lda_blasint = lda as LibxsmmBlasint;
+    let ldb_blasint = ldb as LibxsmmBlasint;
+    let ldc_blasint = ldc as LibxsmmBlasint;
+
+    libxsmm_dgemm(
+        &transa_char,
+        &transb_char,
+        &m_blasint,
+        &n_blasint,
+        &k_blasint,
+        &alpha,
+        a,
+        &lda_blasint,
+        b,
+        &ldb_blasint,
+        &beta,
+        c,
+        &ldc_blasint,
+    );
+}
+
+/// CPU feature tier as seen by libxsmm's code generator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CpuArch {
+    /// Pre-AVX2 x86; libxsmm falls back to generic code.
+    Generic,
+    /// AVX2 (Haswell+, Zen).
+    Avx2,
+    /// AVX-512 (Skylake-SP).
+    Avx512Skx,
+    /// AVX-512 + VNNI (Cascade Lake).
+    Avx512Clx,
+    /// AVX-512 + BF16 (Cooper Lake).
+    Avx512Cpx,
+    /// AVX-512 + AMX (Sapphire Rapids and later).
+    Avx512Spr,
+    /// Any other libxsmm arch id (e.g. non-x86 targets).
+    Other(c_int),
+}
+
+impl CpuArch {
+    /// Ask libxsmm which code path it will generate for on this CPU;
+    /// `Generic` when it could not be loaded.
+    pub fn detect() -> Self {
+        if !loaded() {
+            return CpuArch::Generic;
+        }
+        let _ctx = LibxsmmContext::acquire();
+        Self::from_id(unsafe { libxsmm_get_target_archid() })
+    }
+
+    pub fn from_id(id: c_int) -> Self {
+        match id {
+            LIBXSMM_TARGET_ARCH_AVX512_SKX => CpuArch::Avx512Skx,
+            LIBXSMM_TARGET_ARCH_AVX512_CLX => CpuArch::Avx512Clx,
+            LIBXSMM_TARGET_ARCH_AVX512_CPX => CpuArch::Avx512Cpx,
+            // Everything past SPR within the x86 range (GNR, ...) keeps AMX.
+            LIBXSMM_TARGET_ARCH_AVX512_SPR..=1999 => CpuArch::Avx512Spr,
+            // AVX2 variants (ADL, SRF) and AVX-512 subsets below SKX.
+            LIBXSMM_TARGET_ARCH_AVX2..=1100 => CpuArch::Avx2,
+            1000..=1005 => CpuArch::Generic,
+            other => CpuArch::Other(other),
+        }
+    }
+
+    /// libxsmm arch id for this tier.
+    pub fn id(self) -> c_int {
+        match self {
+            CpuArch::Generic => 1001,
+            CpuArch::Avx2 => LIBXSMM_TARGET_ARCH_AVX2,
+            CpuArch::Avx512Skx => LIBXSMM_TARGET_ARCH_AVX512_SKX,
+            CpuArch::Avx512Clx => LIBXSMM_TARGET_ARCH_AVX512_CLX,
+            CpuArch::Avx512Cpx => LIBXSMM_TARGET_ARCH_AVX512_CPX,
+            CpuArch::Avx512Spr => LIBXSMM_TARGET_ARCH_AVX512_SPR,
+            CpuArch::Other(id) => id,
+        }
+    }
+
+    /// Int8 dot products (VPDPBUSD), CLX and later.
+    pub fn supports_vnni(self) -> bool {
+        matches!(
+            self,
+            CpuArch::Avx512Clx | CpuArch::Avx512Cpx | CpuArch::Avx512Spr
+        )
+    }
+
+    /// BF16 dot products (VDPBF16PS or AMX), CPX and later.
+    pub fn supports_bf16_dot(self) -> bool {
+        matches!(self, CpuArch::Avx512Cpx | CpuArch::Avx512Spr)
+    }
+
+    /// AMX tiles (TDPBF16PS / TDPBUSD), SPR and later.
+    pub fn supports_amx(self) -> bool {
+        matches!(self, CpuArch::Avx512Spr)
+    }
+}
+
+impl std::fmt::Display for CpuArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CpuArch::Generic => write!(f, "generic x86"),
+            CpuArch::Avx2 => write!(f, "AVX2"),
+            CpuArch::Avx512Skx => write!(f, "AVX-512 (Skylake)"),
+            CpuArch::Avx512Clx => write!(f, "AVX-512 VNNI (Cascade Lake)"),
+            CpuArch::Avx512Cpx => write!(f, "AVX-512 BF16 (Cooper Lake)"),
+            CpuArch::Avx512Spr => write!(f, "AVX-512 AMX (Sapphire Rapids)"),
+            CpuArch::Other(id) => write!(f, "libxsmm arch {}", id),
+        }
+    }
+}
+
+/// How to dispatch bf16 kernels and pack their operands on a given CPU.
+///
+/// AVX512-BF16 (CPX) and AMX (SPR) both consume k in pairs and want the A
+/// operand VNNI2-packed. Without native bf16 dot products libxsmm emulates
+/// via f32, where packing only costs time, so operands stay flat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bf16KernelConfig {
+    pub arch: CpuArch,
+    /// Layout the A operand must be packed in.
+    pub layout: VnniLayout,
+    /// GEMM flags to add on top of transpose/beta.
+    pub flags: LibxsmmBitfield,
+}
+
+impl Bf16KernelConfig {
+    /// Config for the CPU libxsmm is targeting.
+    pub fn auto() -> Self {
+        Self::for_arch(CpuArch::detect())
+    }
+
+    /// Config for an explicit arch (also handy for exercising the decision
+    /// logic without the hardware).
+    pub fn for_arch(arch: CpuArch) -> Self {
+        if arch.supports_bf16_dot() {
+            Self {
+                arch,
+                layout: VnniLayout::Vnni2,
+                flags: LIBXSMM_GEMM_FLAG_VNNI_A,
+            }
+        } else {
+            Self {
+                arch,
+                layout: VnniLayout::Flat,
+                flags: LIBXSMM_GEMM_FLAG_NONE,
+            }
+        }
+    }
+
+    /// k the kernel must be dispatched with for a logical reduction size.
+    pub fn padded_k(&self, k: i32) -> i32 {
+        let g = self.layout.group() as i32;
+        (k + g - 1) / g * g
+    }
+}
+
+/// Set once `libxsmm_init` has run.
+static INITIALIZED: OnceLock<()> = OnceLock::new();
+
+/// Initialize libxsmm the first time through; afterwards a single load.
+fn ensure_init() {
+    INITIALIZED.get_or_init(|| {
+        if loaded() {
+            unsafe { libxsmm_init() };
+        }
+    });
+}
+
+/// Handle on the libxsmm runtime.
+///
+/// The first handle in the process calls `libxsmm_init`, exactly once; later
+/// handles (one per kernel) are free. The runtime then stays up for the rest
+/// of the process: the crate never calls `libxsmm_finalize`, so the JIT
+/// registry outlives every kernel, including those `KernelCache` keeps for
+/// good, and an application that finalizes libxsmm itself at shutdown is not
+/// finalized twice. Kernels must not be called after such a finalize.
+#[derive(Clone)]
+pub struct LibxsmmContext {
+    _private: (),
+}
+
+impl LibxsmmContext {
+    pub fn acquire() -> Self {
+        ensure_init();
+        Self { _private: () }
+    }
+}
+
+/// Force libxsmm to generate code for `arch` instead of the detected CPU,
+/// e.g. to exercise the AVX2 path on an AVX-512 machine. Returns the arch
+/// libxsmm actually selected (it refuses tiers the CPU can't run).
+///
+/// Call before dispatching kernels: already generated kernels, including
+/// those in `KernelCache`, keep the code path they were built for. The
+/// override holds for the rest of the process, or until
+/// `reset_target_arch`; kernels cached while it was active survive the
+/// reset, so clear `KernelCache::global()` after it.
+pub fn set_target_arch(arch: CpuArch) -> CpuArch {
+    let _ctx = LibxsmmContext::acquire();
+    if loaded() {
+        unsafe { libxsmm_set_target_archid(arch.id()) };
+    }
+    CpuArch::detect()
+}
+
+/// `set_target_arch` by libxsmm arch name (`"hsw"`, `"skx"`, `"clx"`,
+/// `"cpx"`, `"spr"`, ... as accepted by `LIBXSMM_TARGET`).
+pub fn set_target_arch_name(name: &str) -> Result<CpuArch, std::ffi::NulError> {
+    let name = std::ffi::CString::new(name)?;
+    let _ctx = LibxsmmContext::acquire();
+    if loaded() {
+        unsafe { libxsmm_set_target_arch(name.as_ptr()) };
+    }
+    Ok(CpuArch::detect())
+}
+
+/// Drop any override and go back to the CPUID-detected arch. Kernels
+/// generated under the override, including those in `KernelCache`, keep it.
+pub fn reset_target_arch() -> CpuArch {
+    let _ctx = LibxsmmContext::acquire();
+    if loaded() {
+        unsafe { libxsmm_set_target_arch(std::ptr::null()) };
+    }
+    CpuArch::detect()
+}
+
+/// Snapshot of libxsmm's code registry.
+///
+/// `size` is the number of generated kernels: with bucketed document
+/// lengths it should stay small and flat over a run, while steady growth
+/// means every new length is JIT-compiling its own tail kernel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegistryInfo {
+    /// Registry slots.
+    pub capacity: usize,
+    /// Kernels currently registered.
+    pub size: usize,
+    /// Bytes of generated code.
+    pub nbytes: usize,
+    /// Statically generated (non-JIT) kernels.
+    pub nstatic: usize,
+}
+
+impl std::fmt::Display for RegistryInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "libxsmm registry: {} kernels ({} static) in {} slots, {:.1} KiB code",
+            self.size,
+            self.nstatic,
+            self.capacity,
+            self.nbytes as f64 / 1024.0
+        )
+    }
+}
+
+/// Query the code registry. `None` if libxsmm reports an error or could
+/// not be loaded.
+pub fn registry_info() -> Option<RegistryInfo> {
+    if !loaded() {
+        return None;
+    }
+    let _ctx = LibxsmmContext::acquire();
+    let mut info = LibxsmmRegistryInfo::default();
+    if unsafe { libxsmm_get_registry_info(&mut info) } != 0 {
+        return None;
+    }
+    Some(RegistryInfo {
+        capacity: info.capacity,
+        size: info.size,
+        nbytes: info.nbytes,
+        nstatic: info.nstatic,
+    })
+}
+
+/// Print the registry snapshot to stderr.
+pub fn print_registry_info() {
+    match registry_info() {
+        Some(info) => eprintln!("{info}"),
+        None => eprintln!("libxsmm registry: unavailable"),
+    }
+}
+
+/// Which GEMM operands are stored transposed (column-major convention).
+///
+/// With `A`, the A operand is stored as a k×m matrix and the kernel computes
+/// C = Aᵀ·B; a row-major [tokens, dim] matrix is exactly such an operand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transpose {
+    None,
+    A,
+    B,
+    Both,
+}
+
+impl Transpose {
+    fn trans_a(self) -> bool {
+        matches!(self, Transpose::A | Transpose::Both)
+    }
+
+    fn trans_b(self) -> bool {
+        matches!(self, Transpose::B | Transpose::Both)
+    }
+
+    fn flags(self) -> LibxsmmBitfield {
+        let mut flags = LIBXSMM_GEMM_FLAG_NONE;
+        if self.trans_a() {
+            flags |= LIBXSMM_GEMM_FLAG_TRANS_A;
+        }
+        if self.trans_b() {
+            flags |= LIBXSMM_GEMM_FLAG_TRANS_B;
+        }
+        flags
+    }
+
+    /// Tightly packed (lda, ldb, ldc) for an m×n×k GEMM.
+    fn default_ld(self, m: i32, n: i32, k: i32) -> (i32, i32, i32) {
+        let lda = if self.trans_a() { k } else { m };
+        let ldb = if self.trans_b() { n } else { k };
+        (lda, ldb, m)
+    }
+}
+
+/// Whether the kernel overwrites C (beta = 0) or accumulates into it (beta = 1).
+///
+/// Accumulation lets a GEMM be split over k-chunks that sum into the same C.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Beta {
+    Zero,
+    One,
+}
+
+impl Beta {
+    fn flags(self) -> LibxsmmBitfield {
+        match self {
+            Beta::Zero => LIBXSMM_GEMM_FLAG_BETA_0,
+            Beta::One => LIBXSMM_GEMM_FLAG_NONE,
+        }
+    }
+}
+
+/// Software prefetch strategy baked into a JIT kernel.
+///
+/// The kernel prefetches the operands handed to `JitKernel::call_prefetch`
+/// as "next" pointers while computing the current block, which pays off when
+/// streaming tiles whose successor is known in advance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prefetch {
+    None,
+    /// Next A block into L2.
+    AL2,
+    /// Next A block into L2, issued ahead of the current block.
+    AL2Ahead,
+    /// Next B block into L2 (via the C prefetch slot).
+    BL2,
+    /// Next A and B blocks into L2.
+    AL2BL2,
+    /// Next A block into L1.
+    AL1,
+    /// Next B block into L1.
+    BL1,
+}
+
+impl Prefetch {
+    fn flags(self) -> LibxsmmBitfield {
+        match self {
+            Prefetch::None => LIBXSMM_GEMM_PREFETCH_NONE,
+            Prefetch::AL2 => LIBXSMM_GEMM_PREFETCH_AL2,
+            Prefetch::AL2Ahead => LIBXSMM_GEMM_PREFETCH_AL2_AHEAD,
+            Prefetch::BL2 => LIBXSMM_GEMM_PREFETCH_BL2_VIA_C,
+            Prefetch::AL2BL2 => LIBXSMM_GEMM_PREFETCH_AL2BL2_VIA_C,
+            Prefetch::AL1 => LIBXSMM_GEMM_PREFETCH_AL1,
+            Prefetch::BL1 => LIBXSMM_GEMM_PREFETCH_BL1,
+        }
+    }
+}
+
+/// Everything libxsmm needs to dispatch a GEMM kernel.
+///
+/// Doubles as the key of the kernel cache, so two specs compare equal
+/// exactly when they would dispatch the same kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GemmSpec {
+    pub m: i32,
+    pub n: i32,
+    pub k: i32,
+    pub lda: i32,
+    pub ldb: i32,
+    pub ldc: i32,
+    pub in_type: c_int,
+    pub out_type: c_int,
+    pub flags: LibxsmmBitfield,
+    pub prefetch: LibxsmmBitfield,
+}
+
+impl GemmSpec {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        m: i32,
+        n: i32,
+        k: i32,
+        lda: i32,
+        ldb: i32,
+        ldc: i32,
+        trans: Transpose,
+        beta: Beta,
+        prefetch: Prefetch,
+        in_type: c_int,
+        out_type: c_int,
+    ) -> Self {
+        Self {
+            m,
+            n,
+            k,
+            lda,
+            ldb,
+            ldc,
+            in_type,
+            out_type,
+            flags: beta.flags() | trans.flags(),
+            prefetch: prefetch.flags(),
+        }
+    }
+
+    /// Tightly packed leading dimensions, overwrite C, no prefetch.
+    pub fn packed(
+        m: i32,
+        n: i32,
+        k: i32,
+        trans: Transpose,
+        in_type: c_int,
+        out_type: c_int,
+    ) -> Self {
+        let (lda, ldb, ldc) = trans.default_ld(m, n, k);
+        Self::new(
+            m,
+            n,
+            k,
+            lda,
+            ldb,
+            ldc,
+            trans,
+            Beta::Zero,
+            Prefetch::None,
+            in_type,
+            out_type,
+        )
+    }
+
+    pub fn with_beta(mut self, beta: Beta) -> Self {
+        self.flags = (self.flags & !LIBXSMM_GEMM_FLAG_BETA_0) | beta.flags();
+        self
+    }
+
+    /// Same kernel prefetching per `prefetch` the operands handed to
+    /// `JitKernel::call_prefetch`.
+    pub fn with_prefetch(mut self, prefetch: Prefetch) -> Self {
+        self.prefetch = prefetch.flags();
+        self
+    }
+
+    /// Same kernel without its own AMX tile setup/teardown, for calls made
+    /// inside a `TileConfigGuard` scope.
+    pub fn with_tile_scope(mut self) -> Self {
+        self.flags |= TILE_SCOPE_FLAGS;
+        self
+    }
+
+    /// Whether the kernel expects its tiles configured by a `TileConfigGuard`.
+    pub fn in_tile_scope(&self) -> bool {
+        self.flags & TILE_SCOPE_FLAGS == TILE_SCOPE_FLAGS
+    }
+
+    /// Minimum element counts of the (A, B, C) buffers, column-major.
+    pub(crate) fn operand_lens(&self) -> (usize, usize, usize) {
+        fn len(ld: i32, rows: i32, cols: i32) -> usize {
+            if rows <= 0 || cols <= 0 {
+                0
+            } else {
+                ld as usize * (cols as usize - 1) + rows as usize
+            }
+        }
+        let (a_rows, a_cols) = if self.flags & LIBXSMM_GEMM_FLAG_TRANS_A != 0 {
+            (self.k, self.m)
+        } else {
+            (self.m, self.k)
+        };
+        let (b_rows, b_cols) = if self.flags & LIBXSMM_GEMM_FLAG_TRANS_B != 0 {
+            (self.n, self.k)
+        } else {
+            (self.k, self.n)
+        };
+        (
+            len(self.lda, a_rows, a_cols),
+            len(self.ldb, b_rows, b_cols),
+            len(self.ldc, self.m, self.n),
+        )
+    }
+
+    /// Reject operand buffers shorter than the shape requires.
+    pub(crate) fn check_lens(
+        &self,
+        a_len: usize,
+        b_len: usize,
+        c_len: usize,
+    ) -> Result<(), CallError> {
+        let (a_req, b_req, c_req) = self.operand_lens();
+        for (operand, required, actual) in [
+            ('A', a_req, a_len),
+            ('B', b_req, b_len),
+            ('C', c_req, c_len),
+        ] {
+            if actual < required {
+                return Err(CallError::BufferTooSmall {
+                    operand,
+                    required,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Catch shapes libxsmm would reject (or mis-handle) before dispatching.
+    fn validate(&self) -> Result<(), DispatchError> {
+        if self.m <= 0 || self.n <= 0 || self.k <= 0 {
+            return Err(DispatchError::InvalidShape { spec: *self });
+        }
+        let a_rows = if self.flags & LIBXSMM_GEMM_FLAG_TRANS_A != 0 {
+            self.k
+        } else {
+            self.m
+        };
+        let b_rows = if self.flags & LIBXSMM_GEMM_FLAG_TRANS_B != 0 {
+            self.n
+        } else {
+            self.k
+        };
+        if self.lda < a_rows || self.ldb < b_rows || self.ldc < self.m {
+            return Err(DispatchError::InvalidLeadingDim { spec: *self });
+        }
+        // libxsmm indexes operands with 32-bit blasints.
+        let (a_len, b_len, c_len) = self.operand_lens();
+        if a_len.max(b_len).max(c_len) > i32::MAX as usize {
+            return Err(DispatchError::ShapeTooLarge { spec: *self });
+        }
+        let supported = matches!(
+            (self.in_type, self.out_type),
+            (LIBXSMM_DATATYPE_F32, LIBXSMM_DATATYPE_F32)
+                | (LIBXSMM_DATATYPE_F64, LIBXSMM_DATATYPE_F64)
+                | (LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32)
+                | (LIBXSMM_DATATYPE_F16, LIBXSMM_DATATYPE_F32)
+                | (LIBXSMM_DATATYPE_I8, LIBXSMM_DATATYPE_I32)
+        );
+        if !supported {
+            return Err(DispatchError::UnsupportedDtype {
+                in_type: self.in_type,
+                out_type: self.out_type,
+                spec: *self,
+            });
+        }
+        Ok(())
+    }
+
+    /// Explain a null kernel from libxsmm: blame the arch when the dtype
+    /// needs instructions it lacks, otherwise report a plain JIT failure.
+    fn jit_error(&self) -> DispatchError {
+        let arch = CpuArch::detect();
+        let arch_ok = match self.in_type {
+            LIBXSMM_DATATYPE_BF16 => {
+                arch.supports_bf16_dot()
+                    || self.flags & (LIBXSMM_GEMM_FLAG_VNNI_A | LIBXSMM_GEMM_FLAG_VNNI_B) == 0
+            }
+            LIBXSMM_DATATYPE_F16 => arch.supports_amx(),
+            LIBXSMM_DATATYPE_I8 => arch.supports_vnni(),
+            _ => true,
+        };
+        if arch_ok {
+            DispatchError::JitFailed { spec: *self }
+        } else {
+            DispatchError::UnsupportedArch { arch, spec: *self }
+        }
+    }
+}
+
 /// Cached JIT kernel for a fixed GEMM shape.
 /// Dispatch cost paid once; hot-path is a single indirect call.
 pub struct JitKernel {
     kernel: LibxsmmGemmFunction,
+    spec: GemmSpec,
+    /// Row-major front end: callers pass (A, B) and the kernel is invoked
+    /// with (B, A) on the transposed problem.
+    row_major: bool,
+    /// Code path the kernel was generated for.
+    arch: CpuArch,
+    _ctx: LibxsmmContext,
 }
 
+// SAFETY: the kernel is a pointer into libxsmm's JIT code cache. That code is
+// written once during dispatch, never patched afterwards, and stays mapped
+// while `_ctx` keeps the runtime alive. It keeps no state between calls; all
+// per-call state lives in the param block on the caller's stack. Calling it
+// concurrently is therefore as safe as calling a plain `extern "C" fn`, as
+// long as callers respect the aliasing rules documented on `call`.
+unsafe impl Send for JitKernel {}
+unsafe impl Sync for JitKernel {}
+
 impl JitKernel {
     /// Try to dispatch a JIT kernel for f32 GEMM.
-    /// Returns None if LIBXSMM can't JIT for this shape.
-    pub fn f32_gemm(m: i32, n: i32, k: i32) -> Option<Self> {
-        unsafe {
-            libxsmm_init();
-            let shape = libxsmm_create_gemm_shape(
-                m,
-                n,
-                k,
-                m,  // lda
-                k,  // ldb
-                m,  // ldc
-                LIBXSMM_DATATYPE_F32,
-                LIBXSMM_DATATYPE_F32,
-                LIBXSMM_DATATYPE_F32,
-                LIBXSMM_DATATYPE_F32,
-            );
-            let kernel = libxsmm_dispatch_gemm(shape, LIBXSMM_GEMM_FLAG_BETA_0, 0)?;
-            Some(Self { kernel })
-        }
+    /// Fails if LIBXSMM can't JIT for this shape.
+    pub fn f32_gemm(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        Self::dispatch(GemmSpec::packed(m, n, k, trans, f32, f32))
+    }
+
+    /// f32 GEMM on row-major operands: A is m×k, B is k×n, C is m×n, all
+    /// packed row by row as numpy/ndarray store them.
+    ///
+    /// A row-major matrix is the column-major view of its transpose, so
+    /// C = A·B is computed as Cᵀ = Bᵀ·Aᵀ: the dispatched column-major kernel
+    /// is n×m×k and the call swaps A and B. `spec()` describes that kernel.
+    ///
+    /// ```ignore
+    /// let a = [1., 2., 3., 4., 5., 6., 7., 8., 9., 10., 11., 12.]; // 3x4
+    /// let b = [1., 0., 0., 1., 1., 0., 0., 1.]; // 4x2
+    /// let mut c = [0.0f32; 6]; // 3x2
+    /// JitKernel::f32_gemm_row_major(3, 2, 4)?.call_f32(&a, &b, &mut c)?;
+    /// assert_eq!(c, [4., 6., 12., 14., 20., 22.]);
+    /// ```
+    pub fn f32_gemm_row_major(m: i32, n: i32, k: i32) -> Result<Self, DispatchError> {
+        let mut kernel = Self::f32_gemm(n, m, k, Transpose::None)?;
+        kernel.row_major = true;
+        Ok(kernel)
+    }
+
+    /// f32 GEMM that accumulates into C (C += A·B).
+    pub fn f32_gemm_acc(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        Self::dispatch(GemmSpec::packed(m, n, k, trans, f32, f32).with_beta(Beta::One))
+    }
+
+    /// f32 GEMM with explicit leading dimensions, for operands that are
+    /// views into a larger (padded) buffer.
+    /// Fails if a leading dimension is smaller than the stored rows of its
+    /// operand, or LIBXSMM can't JIT.
+    #[allow(clippy::too_many_arguments)]
+    pub fn f32_gemm_with_ld(
+        m: i32,
+        n: i32,
+        k: i32,
+        lda: i32,
+        ldb: i32,
+        ldc: i32,
+        trans: Transpose,
+        beta: Beta,
+        prefetch: Prefetch,
+    ) -> Result<Self, DispatchError> {
+        Self::dispatch(GemmSpec::new(
+            m,
+            n,
+            k,
+            lda,
+            ldb,
+            ldc,
+            trans,
+            beta,
+            prefetch,
+            LIBXSMM_DATATYPE_F32,
+            LIBXSMM_DATATYPE_F32,
+        ))
     }
 
     /// Try to dispatch a JIT kernel for BF16→f32 GEMM.
     /// Requires CPX+ (VDPBF16PS) or SPR+ (AMX TDPBF16PS).
-    pub fn bf16_gemm(m: i32, n: i32, k: i32) -> Option<Self> {
+    pub fn bf16_gemm(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        let (bf16, f32) = (LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32);
+        Self::dispatch(GemmSpec::packed(m, n, k, trans, bf16, f32))
+    }
+
+    /// BF16→f32 GEMM that accumulates into C (C += A·B).
+    pub fn bf16_gemm_acc(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        let (bf16, f32) = (LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32);
+        Self::dispatch(GemmSpec::packed(m, n, k, trans, bf16, f32).with_beta(Beta::One))
+    }
+
+    /// BF16→f32 GEMM on VNNI2-packed operands (see `crate::vnni`).
+    ///
+    /// `k` is the padded (even) reduction size. `vnni_a`/`vnni_b` select
+    /// which operands are packed; unpacked operands are plain column-major.
+    pub fn bf16_gemm_vnni(
+        m: i32,
+        n: i32,
+        k: i32,
+        vnni_a: bool,
+        vnni_b: bool,
+    ) -> Result<Self, DispatchError> {
+        let (bf16, f32) = (LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32);
+        let mut spec = GemmSpec::packed(m, n, k, Transpose::None, bf16, f32);
+        if k % 2 != 0 {
+            return Err(DispatchError::InvalidShape { spec });
+        }
+        if vnni_a {
+            spec.flags |= LIBXSMM_GEMM_FLAG_VNNI_A;
+        }
+        if vnni_b {
+            spec.flags |= LIBXSMM_GEMM_FLAG_VNNI_B;
+        }
+        Self::dispatch(spec)
+    }
+
+    /// BF16→f32 GEMM dispatched per `config`: k is padded to the layout's
+    /// group size and the A operand must be packed in `config.layout`.
+    pub fn bf16_gemm_with_config(
+        m: i32,
+        n: i32,
+        k: i32,
+        config: &Bf16KernelConfig,
+    ) -> Result<Self, DispatchError> {
+        let (bf16, f32) = (LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32);
+        let mut spec = GemmSpec::packed(m, n, config.padded_k(k), Transpose::None, bf16, f32);
+        spec.flags |= config.flags;
+        Self::dispatch(spec)
+    }
+
+    /// BF16→f32 GEMM with explicit leading dimensions.
+    #[allow(clippy::too_many_arguments)]
+    pub fn bf16_gemm_with_ld(
+        m: i32,
+        n: i32,
+        k: i32,
+        lda: i32,
+        ldb: i32,
+        ldc: i32,
+        trans: Transpose,
+        beta: Beta,
+        prefetch: Prefetch,
+    ) -> Result<Self, DispatchError> {
+        Self::dispatch(GemmSpec::new(
+            m,
+            n,
+            k,
+            lda,
+            ldb,
+            ldc,
+            trans,
+            beta,
+            prefetch,
+            LIBXSMM_DATATYPE_BF16,
+            LIBXSMM_DATATYPE_F32,
+        ))
+    }
+
+    /// Try to dispatch a JIT kernel for f64 GEMM (f64 inputs, accumulation
+    /// and output). Mostly useful as a high-precision reference.
+    pub fn f64_gemm(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        let f64 = LIBXSMM_DATATYPE_F64;
+        Self::dispatch(GemmSpec::packed(m, n, k, trans, f64, f64))
+    }
+
+    /// Try to dispatch a JIT kernel for F16→f32 GEMM (IEEE half inputs,
+    /// f32 accumulation and output). Needs AVX512-FP16 (SPR+).
+    pub fn f16_gemm(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        let (f16, f32) = (LIBXSMM_DATATYPE_F16, LIBXSMM_DATATYPE_F32);
+        Self::dispatch(GemmSpec::packed(m, n, k, trans, f16, f32))
+    }
+
+    /// F16→f32 GEMM with explicit leading dimensions.
+    #[allow(clippy::too_many_arguments)]
+    pub fn f16_gemm_with_ld(
+        m: i32,
+        n: i32,
+        k: i32,
+        lda: i32,
+        ldb: i32,
+        ldc: i32,
+        trans: Transpose,
+        beta: Beta,
+        prefetch: Prefetch,
+    ) -> Result<Self, DispatchError> {
+        Self::dispatch(GemmSpec::new(
+            m,
+            n,
+            k,
+            lda,
+            ldb,
+            ldc,
+            trans,
+            beta,
+            prefetch,
+            LIBXSMM_DATATYPE_F16,
+            LIBXSMM_DATATYPE_F32,
+        ))
+    }
+
+    /// Dispatch a kernel for an arbitrary spec.
+    pub fn from_spec(spec: GemmSpec) -> Result<Self, DispatchError> {
+        Self::dispatch(spec)
+    }
+
+    fn dispatch(spec: GemmSpec) -> Result<Self, DispatchError> {
+        spec.validate()?;
+        if !loaded() {
+            return Err(DispatchError::NotLoaded { spec });
+        }
+        let ctx = LibxsmmContext::acquire();
         unsafe {
-            libxsmm_init();
             let shape = libxsmm_create_gemm_shape(
-                m,
-                n,
-                k,
-                m,
-                k,
-                m,
-                LIBXSMM_DATATYPE_BF16,
-                LIBXSMM_DATATYPE_BF16,
-                LIBXSMM_DATATYPE_F32,
-                LIBXSMM_DATATYPE_F32,
+                spec.m,
+                spec.n,
+                spec.k,
+                spec.lda,
+                spec.ldb,
+                spec.ldc,
+                spec.in_type,
+                spec.in_type,
+                spec.out_type,
+                spec.out_type,
             );
-            let kernel = libxsmm_dispatch_gemm(shape, LIBXSMM_GEMM_FLAG_BETA_0, 0)?;
-            Some(Self { kernel })
+            let kernel = libxsmm_dispatch_gemm(shape, spec.flags, spec.prefetch)
+                .ok_or_else(|| spec.jit_error())?;
+            Ok(Self {
+                kernel,
+                spec,
+                row_major: false,
+                arch: CpuArch::detect(),
+                _ctx: ctx,
+            })
         }
     }
 
-    /// Call the JIT kernel. a/b/c must be valid for the dispatched shape.
-    pub unsafe fn call(
-        &self,
-        a: *const c_void,
-        b: *const c_void,
-        c: *mut c_void,
-    ) {
-        let param = LibxsmmGemmParam {
-            op: LibxsmmMatrixOpArg::default(),
-            a: LibxsmmMatrixArg::from_ptr(a),
-            b: LibxsmmMatrixArg::from_ptr(b),
-            c: LibxsmmMatrixArg::from_ptr(c as *const c_void),
-        };
-        (self.kernel)(&param);
+    /// The full spec the kernel was dispatched with.
+    pub fn spec(&self) -> &GemmSpec {
+        &self.spec
+    }
+
+    /// Code path the kernel was generated for.
+    pub fn arch(&self) -> CpuArch {
+        self.arch
+    }
+
+    /// Operand alignment (bytes) `call_checked` enforces: a full vector
+    /// register of the dispatched ISA.
+    pub fn required_alignment(&self) -> usize {
+        match self.arch {
+            CpuArch::Avx512Skx | CpuArch::Avx512Clx | CpuArch::Avx512Cpx | CpuArch::Avx512Spr => 64,
+            _ => 16,
+        }
+    }
+
+    /// Whether `call*` takes row-major operands (see `f32_gemm_row_major`).
+    pub fn is_row_major(&self) -> bool {
+        self.row_major
+    }
+
+    pub fn m(&self) -> i32 {
+        self.spec.m
+    }
+
+    pub fn n(&self) -> i32 {
+        self.spec.n
+    }
+
+    pub fn k(&self) -> i32 {
+        self.spec.k
+    }
+
+    /// Input (A/B) data type, one of the `LIBXSMM_DATATYPE_*` constants.
+    pub fn dtype(&self) -> c_int {
+        self.spec.in_type
+    }
+
+    /// GEMM flags (transpose, beta, VNNI, ...) the kernel was dispatched with.
+    pub fn flags(&self) -> LibxsmmBitfield {
+        self.spec.flags
+    }
+
+    /// Leading dimension of A the kernel was dispatched with.
+    pub fn lda(&self) -> i32 {
+        self.spec.lda
+    }
+
+    /// Leading dimension of B the kernel was dispatched with.
+    pub fn ldb(&self) -> i32 {
+        self.spec.ldb
+    }
+
+    /// Leading dimension of C the kernel was dispatched with.
+    pub fn ldc(&self) -> i32 {
+        self.spec.ldc
+    }
+
+    /// Call the JIT kernel. a/b/c must be valid for the dispatched shape.
+    ///
+    /// # Safety
+    ///
+    /// The kernel may be called from many threads at once, but each
+    /// concurrent call needs its own C buffer: C regions must not overlap
+    /// with each other or with any A/B region being read. A and B may be
+    /// shared freely across threads.
+    pub unsafe fn call(&self, a: *const c_void, b: *const c_void, c: *mut c_void) {
+        let (a, b) = if self.row_major { (b, a) } else { (a, b) };
+        let param = LibxsmmGemmParam {
+            op: LibxsmmMatrixOpArg::default(),
+            a: LibxsmmMatrixArg::from_ptr(a),
+            b: LibxsmmMatrixArg::from_ptr(b),
+            c: LibxsmmMatrixArg::from_ptr(c as *const c_void),
+        };
+        (self.kernel)(&param);
+    }
+
+    /// `call` without any checks, for hot loops whose buffers were
+    /// validated once up front.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `call`.
+    #[inline]
+    pub unsafe fn call_unchecked(&self, a: *const c_void, b: *const c_void, c: *mut c_void) {
+        self.call(a, b, c)
+    }
+
+    /// `call` that validates raw operands first: element counts against the
+    /// shape, alignment against `required_alignment`, and that C overlaps
+    /// neither A nor B. Returns an error instead of running the kernel.
+    ///
+    /// # Safety
+    ///
+    /// Each pointer must be valid for its length (in elements of the
+    /// dispatched dtype); everything else the kernel relies on is checked.
+    pub unsafe fn call_checked(
+        &self,
+        (a, a_len): (*const c_void, usize),
+        (b, b_len): (*const c_void, usize),
+        (c, c_len): (*mut c_void, usize),
+    ) -> Result<(), CallError> {
+        self.check(self.spec.in_type, self.spec.out_type, a_len, b_len, c_len)?;
+
+        let align = self.required_alignment();
+        for (operand, ptr) in [('A', a as usize), ('B', b as usize), ('C', c as usize)] {
+            if ptr % align != 0 {
+                return Err(CallError::Misaligned {
+                    operand,
+                    required: align,
+                    offset: ptr % align,
+                });
+            }
+        }
+
+        let (in_size, out_size) = (
+            dtype_size(self.spec.in_type),
+            dtype_size(self.spec.out_type),
+        );
+        let c_range = c as usize..c as usize + c_len * out_size;
+        for (operand, ptr, len) in [('A', a as usize, a_len), ('B', b as usize, b_len)] {
+            if ptr < c_range.end && c_range.start < ptr + len * in_size {
+                return Err(CallError::Aliased { operand });
+            }
+        }
+
+        self.debug_assert_covers(a_len, b_len, c_len);
+        self.call(a, b, c);
+        Ok(())
+    }
+
+    /// Shape- and type-checked call for f32 kernels.
+    pub fn call_f32(&self, a: &[f32], b: &[f32], c: &mut [f32]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_F32,
+            LIBXSMM_DATATYPE_F32,
+            a.len(),
+            b.len(),
+            c.len(),
+        )?;
+        self.debug_assert_covers(a.len(), b.len(), c.len());
+        unsafe {
+            self.call(
+                a.as_ptr() as *const c_void,
+                b.as_ptr() as *const c_void,
+                c.as_mut_ptr() as *mut c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// Shape- and type-checked call for BF16→f32 kernels (bf16 as raw `u16` bits).
+    pub fn call_bf16(&self, a: &[u16], b: &[u16], c: &mut [f32]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_BF16,
+            LIBXSMM_DATATYPE_F32,
+            a.len(),
+            b.len(),
+            c.len(),
+        )?;
+        self.debug_assert_covers(a.len(), b.len(), c.len());
+        unsafe {
+            self.call(
+                a.as_ptr() as *const c_void,
+                b.as_ptr() as *const c_void,
+                c.as_mut_ptr() as *mut c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// Shape- and type-checked call for F16→f32 kernels (f16 as raw `u16` bits).
+    pub fn call_f16(&self, a: &[u16], b: &[u16], c: &mut [f32]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_F16,
+            LIBXSMM_DATATYPE_F32,
+            a.len(),
+            b.len(),
+            c.len(),
+        )?;
+        self.debug_assert_covers(a.len(), b.len(), c.len());
+        unsafe {
+            self.call(
+                a.as_ptr() as *const c_void,
+                b.as_ptr() as *const c_void,
+                c.as_mut_ptr() as *mut c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// Shape- and type-checked call for f64 kernels.
+    pub fn call_f64(&self, a: &[f64], b: &[f64], c: &mut [f64]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_F64,
+            LIBXSMM_DATATYPE_F64,
+            a.len(),
+            b.len(),
+            c.len(),
+        )?;
+        self.debug_assert_covers(a.len(), b.len(), c.len());
+        unsafe {
+            self.call(
+                a.as_ptr() as *const c_void,
+                b.as_ptr() as *const c_void,
+                c.as_mut_ptr() as *mut c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// Debug-build assertion that buffers of these lengths cover the m×k,
+    /// k×n and m×n operands at the dispatched leading dimensions.
+    #[inline]
+    pub(crate) fn debug_assert_covers(&self, a_len: usize, b_len: usize, c_len: usize) {
+        let (a_req, b_req, c_req) = self.spec.operand_lens();
+        // Row-major kernels run with A and B swapped
+        let (a_req, b_req) = if self.row_major {
+            (b_req, a_req)
+        } else {
+            (a_req, b_req)
+        };
+        debug_assert!(a_len >= a_req, "A holds {a_len} elements, needs {a_req}");
+        debug_assert!(b_len >= b_req, "B holds {b_len} elements, needs {b_req}");
+        debug_assert!(c_len >= c_req, "C holds {c_len} elements, needs {c_req}");
+    }
+
+    pub(crate) fn check(
+        &self,
+        in_type: c_int,
+        out_type: c_int,
+        a_len: usize,
+        b_len: usize,
+        c_len: usize,
+    ) -> Result<(), CallError> {
+        if self.spec.in_type != in_type || self.spec.out_type != out_type {
+            return Err(CallError::DtypeMismatch {
+                expected: (self.spec.in_type, self.spec.out_type),
+                actual: (in_type, out_type),
+            });
+        }
+        if self.row_major {
+            // Check against the swapped kernel, but report the caller's names
+            return self
+                .spec
+                .check_lens(b_len, a_len, c_len)
+                .map_err(CallError::swap_ab);
+        }
+        self.spec.check_lens(a_len, b_len, c_len)
+    }
+
+    /// Call a kernel dispatched with a `Prefetch` strategy, handing it the
+    /// A/B blocks of the next iteration to prefetch while computing this one.
+    /// With `Prefetch::None` the extra pointers are ignored.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `call`; the prefetch pointers are only hinted, never
+    /// dereferenced, but should point at the next blocks for any benefit.
+    pub unsafe fn call_prefetch(
+        &self,
+        a: *const c_void,
+        b: *const c_void,
+        c: *mut c_void,
+        a_next: *const c_void,
+        b_next: *const c_void,
+    ) {
+        let (a, b, a_next, b_next) = if self.row_major {
+            (b, a, b_next, a_next)
+        } else {
+            (a, b, a_next, b_next)
+        };
+        let mut param = LibxsmmGemmParam {
+            op: LibxsmmMatrixOpArg::default(),
+            a: LibxsmmMatrixArg::from_ptr(a),
+            b: LibxsmmMatrixArg::from_ptr(b),
+            c: LibxsmmMatrixArg::from_ptr(c as *const c_void),
+        };
+        param.a.secondary = a_next;
+        param.b.secondary = b_next;
+        (self.kernel)(&param);
+    }
+}
+
+/// One `LibxsmmGemmParam` for repeated calls of a kernel: the block is
+/// built once, `set_a`/`set_b`/`set_c` rewrite only the primary operand
+/// pointers and `fire` makes the indirect call, so a hot loop moving one
+/// operand per call skips rebuilding the other pointers. Row-major kernels
+/// swap A and B in the setters, as `JitKernel::call` does.
+///
+/// ```ignore
+/// let mut call = KernelCall::new(&kernel);
+/// call.set_a(query.as_ptr() as *const c_void);
+/// call.set_c(sims.as_mut_ptr() as *mut c_void);
+/// for tile in doc.chunks(tile_len) {
+///     call.set_b(tile.as_ptr() as *const c_void);
+///     unsafe { call.fire() };
+/// }
+/// ```
+pub struct KernelCall<'k> {
+    kernel: &'k JitKernel,
+    param: LibxsmmGemmParam,
+}
+
+impl<'k> KernelCall<'k> {
+    /// Call block for `kernel` with every pointer null.
+    pub fn new(kernel: &'k JitKernel) -> Self {
+        let null = std::ptr::null();
+        Self {
+            kernel,
+            param: LibxsmmGemmParam {
+                op: LibxsmmMatrixOpArg::default(),
+                a: LibxsmmMatrixArg::from_ptr(null),
+                b: LibxsmmMatrixArg::from_ptr(null),
+                c: LibxsmmMatrixArg::from_ptr(null),
+            },
+        }
+    }
+
+    pub fn kernel(&self) -> &'k JitKernel {
+        self.kernel
+    }
+
+    #[inline]
+    pub fn set_a(&mut self, a: *const c_void) {
+        match self.kernel.row_major {
+            false => self.param.a.primary = a,
+            true => self.param.b.primary = a,
+        }
+    }
+
+    #[inline]
+    pub fn set_b(&mut self, b: *const c_void) {
+        match self.kernel.row_major {
+            false => self.param.b.primary = b,
+            true => self.param.a.primary = b,
+        }
+    }
+
+    #[inline]
+    pub fn set_c(&mut self, c: *mut c_void) {
+        self.param.c.primary = c as *const c_void;
+    }
+
+    /// Blocks of the next call for a kernel dispatched with a `Prefetch`
+    /// strategy, as in `JitKernel::call_prefetch`.
+    #[inline]
+    pub fn set_next(&mut self, a_next: *const c_void, b_next: *const c_void) {
+        let (a_next, b_next) = match self.kernel.row_major {
+            false => (a_next, b_next),
+            true => (b_next, a_next),
+        };
+        self.param.a.secondary = a_next;
+        self.param.b.secondary = b_next;
+    }
+
+    /// Run the kernel on the current pointers.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `JitKernel::call` for the pointers last set.
+    #[inline]
+    pub unsafe fn fire(&self) {
+        (self.kernel.kernel)(&self.param);
+    }
+}
+
+const TILE_SCOPE_FLAGS: LibxsmmBitfield =
+    LIBXSMM_GEMM_FLAG_NO_RESET_TILECONFIG | LIBXSMM_GEMM_FLAG_NO_SETUP_TILECONFIG;
+
+/// AMX tile configuration for one GEMM shape, dispatched once.
+///
+/// By default every AMX kernel call configures the tiles on entry and
+/// releases them on exit, which costs more than the math for small tiles.
+/// Kernels built from `GemmSpec::with_tile_scope` skip both steps; they may
+/// only run on a thread holding a `TileConfigGuard` for the same shape.
+pub struct TileConfig {
+    setup: LibxsmmTileCfgFunction,
+    release: LibxsmmTileCfgFunction,
+    _ctx: LibxsmmContext,
+}
+
+impl TileConfig {
+    /// Dispatch setup and release kernels for `spec`'s shape and dtypes.
+    /// Fails with `UnsupportedArch` on CPUs without AMX.
+    pub fn for_spec(spec: &GemmSpec) -> Result<Self, DispatchError> {
+        let ctx = LibxsmmContext::acquire();
+        let arch = CpuArch::detect();
+        if !arch.supports_amx() {
+            return Err(DispatchError::UnsupportedArch { arch, spec: *spec });
+        }
+        let base = spec.flags & !TILE_SCOPE_FLAGS;
+        let dispatch = |flags| unsafe {
+            let shape = libxsmm_create_gemm_shape(
+                spec.m,
+                spec.n,
+                spec.k,
+                spec.lda,
+                spec.ldb,
+                spec.ldc,
+                spec.in_type,
+                spec.in_type,
+                spec.out_type,
+                spec.out_type,
+            );
+            libxsmm_dispatch_tilecfg_gemm(shape, flags).ok_or_else(|| spec.jit_error())
+        };
+        Ok(Self {
+            setup: dispatch(base | LIBXSMM_GEMM_FLAG_NO_RESET_TILECONFIG)?,
+            release: dispatch(base | LIBXSMM_GEMM_FLAG_NO_SETUP_TILECONFIG)?,
+            _ctx: ctx,
+        })
+    }
+
+    /// Configure the tiles on this thread until the guard drops.
+    pub fn enter(&self) -> TileConfigGuard<'_> {
+        unsafe { (self.setup)(std::ptr::null()) };
+        TileConfigGuard {
+            config: self,
+            _not_send: std::marker::PhantomData,
+        }
+    }
+}
+
+/// Live AMX tile configuration on the current thread; released on drop.
+///
+/// Tile state is per thread, so the guard is neither `Send` nor `Sync`.
+pub struct TileConfigGuard<'a> {
+    config: &'a TileConfig,
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+impl Drop for TileConfigGuard<'_> {
+    fn drop(&mut self) {
+        unsafe { (self.config.release)(std::ptr::null()) };
+    }
+}
+
+/// Why a kernel could not be dispatched. Every variant carries the requested
+/// spec so the failure can be logged with its shape.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DispatchError {
+    /// The CPU lacks the instructions this dtype/flag combination needs.
+    UnsupportedArch { arch: CpuArch, spec: GemmSpec },
+    /// m, n or k is not positive (or k is odd for VNNI2 operands).
+    InvalidShape { spec: GemmSpec },
+    /// A leading dimension is smaller than the stored rows of its operand.
+    InvalidLeadingDim { spec: GemmSpec },
+    /// Operands too large to index with libxsmm's 32-bit (LP64) ints.
+    ShapeTooLarge { spec: GemmSpec },
+    /// Input/output dtype combination libxsmm has no GEMM for.
+    UnsupportedDtype {
+        in_type: c_int,
+        out_type: c_int,
+        spec: GemmSpec,
+    },
+    /// libxsmm returned no kernel for an otherwise valid request.
+    JitFailed { spec: GemmSpec },
+    /// libxsmm could not be loaded (`libxsmm-dlopen`).
+    NotLoaded { spec: GemmSpec },
+}
+
+impl DispatchError {
+    pub fn spec(&self) -> &GemmSpec {
+        match self {
+            DispatchError::UnsupportedArch { spec, .. }
+            | DispatchError::InvalidShape { spec }
+            | DispatchError::InvalidLeadingDim { spec }
+            | DispatchError::ShapeTooLarge { spec }
+            | DispatchError::UnsupportedDtype { spec, .. }
+            | DispatchError::JitFailed { spec }
+            | DispatchError::NotLoaded { spec } => spec,
+        }
+    }
+}
+
+impl std::fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let spec = self.spec();
+        let shape = format!(
+            "{} {}x{}x{} (lda={}, ldb={}, ldc={}, flags={:#x})",
+            dtype_name(spec.in_type),
+            spec.m,
+            spec.n,
+            spec.k,
+            spec.lda,
+            spec.ldb,
+            spec.ldc,
+            spec.flags
+        );
+        match self {
+            DispatchError::UnsupportedArch { arch, .. } => {
+                write!(f, "{} not supported on {}", shape, arch)
+            }
+            DispatchError::InvalidShape { .. } => write!(f, "invalid GEMM shape {}", shape),
+            DispatchError::InvalidLeadingDim { .. } => {
+                write!(f, "leading dimension smaller than operand rows: {}", shape)
+            }
+            DispatchError::ShapeTooLarge { .. } => {
+                write!(f, "operands too large for 32-bit indexing: {}", shape)
+            }
+            DispatchError::UnsupportedDtype {
+                in_type, out_type, ..
+            } => write!(
+                f,
+                "no GEMM for {} -> {}: {}",
+                dtype_name(*in_type),
+                dtype_name(*out_type),
+                shape
+            ),
+            DispatchError::JitFailed { .. } => write!(f, "libxsmm failed to JIT {}", shape),
+            DispatchError::NotLoaded { .. } => {
+                write!(f, "libxsmm not loaded, no kernel for {}", shape)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DispatchError {}
+
+/// Bytes per element of a libxsmm datatype (0 for unknown ids).
+pub fn dtype_size(dtype: c_int) -> usize {
+    match dtype {
+        LIBXSMM_DATATYPE_F64 => 8,
+        LIBXSMM_DATATYPE_F32 | LIBXSMM_DATATYPE_I32 => 4,
+        LIBXSMM_DATATYPE_BF16 | LIBXSMM_DATATYPE_F16 => 2,
+        LIBXSMM_DATATYPE_I8 | LIBXSMM_DATATYPE_U8 => 1,
+        _ => 0,
+    }
+}
+
+/// Short name of a `LIBXSMM_DATATYPE_*` constant, for logs and errors.
+pub fn dtype_name(dtype: c_int) -> &'static str {
+    match dtype {
+        LIBXSMM_DATATYPE_F64 => "f64",
+        LIBXSMM_DATATYPE_F32 => "f32",
+        LIBXSMM_DATATYPE_BF16 => "bf16",
+        LIBXSMM_DATATYPE_F16 => "f16",
+        LIBXSMM_DATATYPE_I32 => "i32",
+        LIBXSMM_DATATYPE_I8 => "i8",
+        LIBXSMM_DATATYPE_U8 => "u8",
+        _ => "unknown",
+    }
+}
+
+impl std::fmt::Debug for JitKernel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "JitKernel{{{} {}x{}x{}, lda={}, ldb={}, ldc={}}}",
+            dtype_name(self.spec.in_type),
+            self.spec.m,
+            self.spec.n,
+            self.spec.k,
+            self.spec.lda,
+            self.spec.ldb,
+            self.spec.ldc
+        )
+    }
+}
+
+/// Why a checked kernel call was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallError {
+    /// An operand slice is shorter than the dispatched shape requires.
+    BufferTooSmall {
+        operand: char,
+        required: usize,
+        actual: usize,
+    },
+    /// Buffer element types don't match the dispatched (input, output) types.
+    DtypeMismatch {
+        expected: (c_int, c_int),
+        actual: (c_int, c_int),
+    },
+    /// An operand is not aligned to the dispatched ISA's vector width.
+    Misaligned {
+        operand: char,
+        required: usize,
+        offset: usize,
+    },
+    /// C overlaps the given input operand.
+    Aliased { operand: char },
+}
+
+impl CallError {
+    /// Swap the A/B operand labels, for errors raised on a kernel that was
+    /// called with its operands exchanged (row-major front ends).
+    pub(crate) fn swap_ab(self) -> Self {
+        match self {
+            CallError::BufferTooSmall {
+                operand: op @ ('A' | 'B'),
+                required,
+                actual,
+            } => CallError::BufferTooSmall {
+                operand: if op == 'A' { 'B' } else { 'A' },
+                required,
+                actual,
+            },
+            CallError::Misaligned {
+                operand: op @ ('A' | 'B'),
+                required,
+                offset,
+            } => CallError::Misaligned {
+                operand: if op == 'A' { 'B' } else { 'A' },
+                required,
+                offset,
+            },
+            CallError::Aliased { operand } => CallError::Aliased {
+                operand: if operand == 'A' { 'B' } else { 'A' },
+            },
+            other => other,
+        }
+    }
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallError::BufferTooSmall {
+                operand,
+                required,
+                actual,
+            } => write!(
+                f,
+                "operand {} too small: need {} elements, got {}",
+                operand, required, actual
+            ),
+            CallError::DtypeMismatch { expected, actual } => write!(
+                f,
+                "dtype mismatch: kernel is {}->{}, buffers are {}->{}",
+                dtype_name(expected.0),
+                dtype_name(expected.1),
+                dtype_name(actual.0),
+                dtype_name(actual.1)
+            ),
+            CallError::Misaligned {
+                operand,
+                required,
+                offset,
+            } => write!(
+                f,
+                "operand {} misaligned: kernel needs {}-byte alignment, pointer is {} bytes past",
+                operand, required, offset
+            ),
+            CallError::Aliased { operand } => {
+                write!(f, "C overlaps operand {}", operand)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+/// Element type usable as an int8 GEMM operand (`u8` or `i8`).
+pub trait Int8Operand: Copy + private::Sealed {
+    const UNSIGNED: bool;
+}
+
+impl Int8Operand for u8 {
+    const UNSIGNED: bool = true;
+}
+
+impl Int8Operand for i8 {
+    const UNSIGNED: bool = false;
+}
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for u8 {}
+    impl Sealed for i8 {}
+}
+
+/// Signedness of the two int8 GEMM operands (A first, B second).
+///
+/// Which one is unsigned depends on which side was quantized to u8, e.g.
+/// u8 activations against i8 weights. Sign extension differs per operand, so
+/// the kernel must be built for the exact combination it is called with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Int8Signedness {
+    SignedSigned,
+    UnsignedSigned,
+    SignedUnsigned,
+    UnsignedUnsigned,
+}
+
+impl Int8Signedness {
+    fn from_operands(a_unsigned: bool, b_unsigned: bool) -> Self {
+        match (a_unsigned, b_unsigned) {
+            (false, false) => Int8Signedness::SignedSigned,
+            (true, false) => Int8Signedness::UnsignedSigned,
+            (false, true) => Int8Signedness::SignedUnsigned,
+            (true, true) => Int8Signedness::UnsignedUnsigned,
+        }
+    }
+
+    fn flags(self) -> LibxsmmBitfield {
+        match self {
+            Int8Signedness::SignedSigned => LIBXSMM_GEMM_FLAG_NONE,
+            Int8Signedness::UnsignedSigned => LIBXSMM_GEMM_FLAG_A_UNSIGNED,
+            Int8Signedness::SignedUnsigned => LIBXSMM_GEMM_FLAG_B_UNSIGNED,
+            Int8Signedness::UnsignedUnsigned => LIBXSMM_GEMM_FLAG_AB_UNSIGNED,
+        }
+    }
+
+    /// libxsmm dtype of each operand, for error reporting.
+    fn dtypes(self) -> (c_int, c_int) {
+        let dtype = |unsigned| {
+            if unsigned {
+                LIBXSMM_DATATYPE_U8
+            } else {
+                LIBXSMM_DATATYPE_I8
+            }
+        };
+        match self {
+            Int8Signedness::SignedSigned => (dtype(false), dtype(false)),
+            Int8Signedness::UnsignedSigned => (dtype(true), dtype(false)),
+            Int8Signedness::SignedUnsigned => (dtype(false), dtype(true)),
+            Int8Signedness::UnsignedUnsigned => (dtype(true), dtype(true)),
+        }
+    }
+}
+
+/// JIT kernel for int8 GEMM with i32 accumulation and i32 output.
+///
+/// Kept separate from `JitKernel` so the output buffer is typed as `i32`
+/// and an f32 buffer can't be handed to an integer kernel by accident.
+pub struct Int8Kernel {
+    inner: JitKernel,
+    signedness: Int8Signedness,
+}
+
+impl Int8Kernel {
+    /// int8×int8→i32 GEMM with the given operand signedness.
+    /// Fails with `UnsupportedArch` on CPUs without VNNI (pre-CLX).
+    pub fn gemm(
+        m: i32,
+        n: i32,
+        k: i32,
+        trans: Transpose,
+        signedness: Int8Signedness,
+    ) -> Result<Self, DispatchError> {
+        let _ctx = LibxsmmContext::acquire();
+        let mut spec = GemmSpec::packed(m, n, k, trans, LIBXSMM_DATATYPE_I8, LIBXSMM_DATATYPE_I32);
+        spec.flags |= signedness.flags();
+        let arch = CpuArch::detect();
+        if !arch.supports_vnni() {
+            return Err(DispatchError::UnsupportedArch { arch, spec });
+        }
+        let inner = JitKernel::dispatch(spec)?;
+        Ok(Self { inner, signedness })
+    }
+
+    /// u8×i8→i32 GEMM (A unsigned, B signed), the usual VNNI `VPDPBUSD` form.
+    pub fn u8i8_gemm(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        Self::gemm(m, n, k, trans, Int8Signedness::UnsignedSigned)
+    }
+
+    /// i8×i8→i32 GEMM (both operands signed).
+    pub fn i8i8_gemm(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        Self::gemm(m, n, k, trans, Int8Signedness::SignedSigned)
+    }
+
+    pub fn signedness(&self) -> Int8Signedness {
+        self.signedness
+    }
+
+    /// Shape- and signedness-checked int8 call.
+    pub fn call_slices<A: Int8Operand, B: Int8Operand>(
+        &self,
+        a: &[A],
+        b: &[B],
+        c: &mut [i32],
+    ) -> Result<(), CallError> {
+        let actual = Int8Signedness::from_operands(A::UNSIGNED, B::UNSIGNED);
+        if actual != self.signedness {
+            // Report the operand dtypes; the output is i32 either way
+            let (expected_a, expected_b) = self.signedness.dtypes();
+            let (actual_a, actual_b) = actual.dtypes();
+            let (expected, actual) = if expected_a != actual_a {
+                (expected_a, actual_a)
+            } else {
+                (expected_b, actual_b)
+            };
+            return Err(CallError::DtypeMismatch {
+                expected: (expected, LIBXSMM_DATATYPE_I32),
+                actual: (actual, LIBXSMM_DATATYPE_I32),
+            });
+        }
+        self.inner.check(
+            LIBXSMM_DATATYPE_I8,
+            LIBXSMM_DATATYPE_I32,
+            a.len(),
+            b.len(),
+            c.len(),
+        )?;
+        self.inner.debug_assert_covers(a.len(), b.len(), c.len());
+        unsafe { self.call(a.as_ptr(), b.as_ptr(), c.as_mut_ptr()) };
+        Ok(())
+    }
+
+    /// Call the int8 kernel. a/b/c must be valid for the dispatched shape, and
+    /// the signedness of `A` and `B` must match the constructor used.
+    ///
+    /// # Safety
+    ///
+    /// As stated above; `call_slices` checks all of it.
+    pub unsafe fn call<A: Int8Operand, B: Int8Operand>(
+        &self,
+        a: *const A,
+        b: *const B,
+        c: *mut i32,
+    ) {
+        debug_assert_eq!(
+            Int8Signedness::from_operands(A::UNSIGNED, B::UNSIGNED),
+            self.signedness,
+            "int8 operand signedness mismatch"
+        );
+        self.inner
+            .call(a as *const c_void, b as *const c_void, c as *mut c_void);
+    }
+}
+
+/// Batch-reduce f32 GEMM kernel: C = Σᵢ Aᵢ·Bᵢ over a batch of A/B blocks,
+/// all in a single indirect call.
+///
+/// The batch is either a fixed byte stride from a base pointer (`strided`)
+/// or an explicit list of block addresses (`address`).
+pub struct BrgemmKernel {
+    kernel: LibxsmmGemmFunction,
+    br_type: c_int,
+    _ctx: LibxsmmContext,
+}
+
+impl BrgemmKernel {
+    /// Stride-based BRGEMM. Consecutive A (B) blocks are `stride_a` (`stride_b`)
+    /// elements apart. `ShapeTooLarge` if a stride in bytes overflows i32.
+    pub fn f32_strided(
+        m: i32,
+        n: i32,
+        k: i32,
+        stride_a: i32,
+        stride_b: i32,
+        trans: Transpose,
+    ) -> Result<Self, DispatchError> {
+        let elem = std::mem::size_of::<f32>() as i32;
+        let (Some(stride_a), Some(stride_b)) =
+            (stride_a.checked_mul(elem), stride_b.checked_mul(elem))
+        else {
+            let f32 = LIBXSMM_DATATYPE_F32;
+            let spec = GemmSpec::packed(m, n, k, trans, f32, f32);
+            return Err(DispatchError::ShapeTooLarge { spec });
+        };
+        Self::dispatch(
+            m,
+            n,
+            k,
+            trans,
+            LibxsmmGemmBatchReduceConfig {
+                br_type: LIBXSMM_GEMM_BATCH_REDUCE_STRIDE,
+                br_stride_a_hint: stride_a,
+                br_stride_b_hint: stride_b,
+                br_unroll_hint: 0,
+            },
+        )
+    }
+
+    /// Address-list BRGEMM. Blocks are passed as arrays of pointers at call time.
+    pub fn f32_address(m: i32, n: i32, k: i32, trans: Transpose) -> Result<Self, DispatchError> {
+        Self::dispatch(
+            m,
+            n,
+            k,
+            trans,
+            LibxsmmGemmBatchReduceConfig {
+                br_type: LIBXSMM_GEMM_BATCH_REDUCE_ADDRESS,
+                br_stride_a_hint: 0,
+                br_stride_b_hint: 0,
+                br_unroll_hint: 0,
+            },
+        )
+    }
+
+    fn dispatch(
+        m: i32,
+        n: i32,
+        k: i32,
+        trans: Transpose,
+        config: LibxsmmGemmBatchReduceConfig,
+    ) -> Result<Self, DispatchError> {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        let spec = GemmSpec::packed(m, n, k, trans, f32, f32);
+        spec.validate()?;
+        if !loaded() {
+            return Err(DispatchError::NotLoaded { spec });
+        }
+        let ctx = LibxsmmContext::acquire();
+        unsafe {
+            let shape = libxsmm_create_gemm_shape(
+                spec.m, spec.n, spec.k, spec.lda, spec.ldb, spec.ldc, f32, f32, f32, f32,
+            );
+            let br_type = config.br_type;
+            let kernel = libxsmm_dispatch_brgemm(shape, spec.flags, 0, config)
+                .ok_or_else(|| spec.jit_error())?;
+            Ok(Self {
+                kernel,
+                br_type,
+                _ctx: ctx,
+            })
+        }
+    }
+
+    /// Reduce over `batch_count` blocks laid out at the dispatched strides
+    /// from `a` and `b`. Panics on an `f32_address` kernel.
+    ///
+    /// # Safety
+    ///
+    /// All `batch_count` A/B blocks and the C block must lie within valid
+    /// allocations.
+    pub unsafe fn call_stride(&self, a: *const f32, b: *const f32, c: *mut f32, batch_count: u64) {
+        assert_eq!(
+            self.br_type, LIBXSMM_GEMM_BATCH_REDUCE_STRIDE,
+            "brgemm: call_stride on an address-list kernel"
+        );
+        let mut param = LibxsmmGemmParam {
+            op: LibxsmmMatrixOpArg::default(),
+            a: LibxsmmMatrixArg::from_ptr(a as *const c_void),
+            b: LibxsmmMatrixArg::from_ptr(b as *const c_void),
+            c: LibxsmmMatrixArg::from_ptr(c as *const c_void),
+        };
+        param.op.tertiary = &batch_count as *const u64 as *const c_void;
+        (self.kernel)(&param);
+    }
+
+    /// Reduce over the blocks addressed by `a` and `b`. Panics unless they
+    /// have the same length, or on an `f32_strided` kernel.
+    ///
+    /// # Safety
+    ///
+    /// Every address must point at a full block of the dispatched shape, and
+    /// `c` at a full C block.
+    pub unsafe fn call_address(&self, a: &[*const f32], b: &[*const f32], c: *mut f32) {
+        assert_eq!(
+            self.br_type, LIBXSMM_GEMM_BATCH_REDUCE_ADDRESS,
+            "brgemm: call_address on a strided kernel"
+        );
+        assert_eq!(
+            a.len(),
+            b.len(),
+            "brgemm: A and B address lists differ in length"
+        );
+        let batch_count = a.len() as u64;
+        let mut param = LibxsmmGemmParam {
+            op: LibxsmmMatrixOpArg::default(),
+            a: LibxsmmMatrixArg::from_ptr(a.as_ptr() as *const c_void),
+            b: LibxsmmMatrixArg::from_ptr(b.as_ptr() as *const c_void),
+            c: LibxsmmMatrixArg::from_ptr(c as *const c_void),
+        };
+        param.op.tertiary = &batch_count as *const u64 as *const c_void;
+        (self.kernel)(&param);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+
+    /// The kernel, or `None` where there is no libxsmm to dispatch it
+    /// (`libxsmm-dlopen` without the library) or the CPU lacks the
+    /// instructions its dtype needs.
+    fn available<T>(kernel: Result<T, DispatchError>) -> Option<T> {
+        match kernel {
+            Err(DispatchError::NotLoaded { .. } | DispatchError::UnsupportedArch { .. }) => None,
+            kernel => Some(kernel.expect("libxsmm dispatches the shape")),
+        }
+    }
+
+    /// Column-major C = op(A)·op(B) with tightly packed operands.
+    fn reference(
+        trans: Transpose,
+        (m, n, k): (usize, usize, usize),
+        a: &[f32],
+        b: &[f32],
+    ) -> Vec<f32> {
+        let mut c = vec![0.0f32; m * n];
+        for j in 0..n {
+            for i in 0..m {
+                c[i + j * m] = (0..k)
+                    .map(|p| {
+                        let a = if trans.trans_a() {
+                            a[p + i * k]
+                        } else {
+                            a[i + p * m]
+                        };
+                        let b = if trans.trans_b() {
+                            b[j + p * n]
+                        } else {
+                            b[p + j * k]
+                        };
+                        a * b
+                    })
+                    .sum();
+            }
+        }
+        c
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32], tol: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (&x, &y)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (x - y).abs() <= tol * y.abs().max(1.0),
+                "element {i}: {x} vs {y}"
+            );
+        }
+    }
+
+    #[test]
+    fn transposed_operands_match_reference() {
+        let shapes = [(1, 1, 1), (4, 3, 5), (8, 16, 32), (13, 7, 29), (32, 5, 128)];
+        for (m, n, k) in shapes {
+            let a = unit_rows(m, k, 1);
+            let b = unit_rows(n, k, 2);
+            for trans in [Transpose::None, Transpose::A, Transpose::B, Transpose::Both] {
+                let Some(kernel) =
+                    available(JitKernel::f32_gemm(m as i32, n as i32, k as i32, trans))
+                else {
+                    return;
+                };
+                let mut c = vec![f32::NAN; m * n];
+                kernel.call_f32(&a, &b, &mut c).unwrap();
+                let expected = reference(trans, (m, n, k), &a, &b);
+                assert_close(&c, &expected, 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn transpose_sets_flags_and_leading_dims() {
+        let spec =
+            |trans| GemmSpec::packed(4, 3, 5, trans, LIBXSMM_DATATYPE_F32, LIBXSMM_DATATYPE_F32);
+        let none = spec(Transpose::None);
+        assert_eq!((none.lda, none.ldb, none.ldc), (4, 5, 4));
+        assert_eq!(
+            none.flags & (LIBXSMM_GEMM_FLAG_TRANS_A | LIBXSMM_GEMM_FLAG_TRANS_B),
+            0
+        );
+        let both = spec(Transpose::Both);
+        assert_eq!((both.lda, both.ldb, both.ldc), (5, 3, 4));
+        assert_ne!(both.flags & LIBXSMM_GEMM_FLAG_TRANS_A, 0);
+        assert_ne!(both.flags & LIBXSMM_GEMM_FLAG_TRANS_B, 0);
+        let a = spec(Transpose::A);
+        assert_eq!((a.lda, a.ldb), (5, 5));
+        assert_eq!(a.flags & LIBXSMM_GEMM_FLAG_TRANS_B, 0);
+    }
+
+    #[test]
+    fn row_major_front_end() {
+        let a: Vec<f32> = (1..=12).map(|v| v as f32).collect();
+        let b = [1., 0., 0., 1., 1., 0., 0., 1.];
+        let Some(kernel) = available(JitKernel::f32_gemm_row_major(3, 2, 4)) else {
+            return;
+        };
+        let mut c = [0.0f32; 6];
+        kernel.call_f32(&a, &b, &mut c).unwrap();
+        assert_eq!(c, [4., 6., 12., 14., 20., 22.]);
+    }
+
+    #[test]
+    fn reused_call_block_matches_kernel_calls() {
+        let (m, n, k) = (16, 4, 32);
+        let (mi, ni, ki) = (m as i32, n as i32, k as i32);
+        let kernels = [
+            available(JitKernel::f32_gemm(mi, ni, ki, Transpose::A)),
+            available(JitKernel::f32_gemm_row_major(mi, ni, ki)),
+        ];
+        for kernel in kernels.iter().flatten() {
+            let a_tiles: Vec<Vec<f32>> = (0..2).map(|t| unit_rows(m, k, 30 + t)).collect();
+            let b_tiles: Vec<Vec<f32>> = (0..5).map(|t| unit_rows(n, k, 40 + t)).collect();
+            let pairs = a_tiles
+                .iter()
+                .flat_map(|a| b_tiles.iter().map(move |b| (a, b)));
+            let pairs: Vec<_> = pairs.collect();
+
+            let mut expected = vec![0.0f32; pairs.len() * m * n];
+            for ((a, b), c) in pairs.iter().zip(expected.chunks_exact_mut(m * n)) {
+                unsafe { kernel.call(a.as_ptr().cast(), b.as_ptr().cast(), c.as_mut_ptr().cast()) };
+            }
+
+            // A set once per A tile, B and C moved every call
+            let mut reused = vec![f32::NAN; pairs.len() * m * n];
+            let mut call = KernelCall::new(kernel);
+            let mut outputs = reused.chunks_exact_mut(m * n);
+            for a in &a_tiles {
+                call.set_a(a.as_ptr().cast());
+                for b in &b_tiles {
+                    call.set_b(b.as_ptr().cast());
+                    call.set_c(outputs.next().unwrap().as_mut_ptr().cast());
+                    unsafe { call.fire() };
+                }
+            }
+            let layout = if kernel.is_row_major() {
+                "row"
+            } else {
+                "column"
+            };
+            assert_eq!(reused, expected, "{layout}-major");
+        }
+    }
+
+    #[test]
+    fn accumulating_kernel_adds_into_c() {
+        let (m, n, k) = (8, 6, 16);
+        let a = unit_rows(m, k, 3);
+        let b = unit_rows(n, k, 4);
+        let (mi, ni, ki) = (m as i32, n as i32, k as i32);
+        let Some(acc) = available(JitKernel::f32_gemm_acc(mi, ni, ki, Transpose::None)) else {
+            return;
+        };
+        let product = reference(Transpose::None, (m, n, k), &a, &b);
+        let mut c = vec![0.0f32; m * n];
+        acc.call_f32(&a, &b, &mut c).unwrap();
+        acc.call_f32(&a, &b, &mut c).unwrap();
+        let twice: Vec<f32> = product.iter().map(|v| 2.0 * v).collect();
+        assert_close(&c, &twice, 1e-5);
+
+        // The overwriting kernel ignores what C held
+        let overwrite = JitKernel::f32_gemm(mi, ni, ki, Transpose::None).unwrap();
+        c.fill(1e6);
+        overwrite.call_f32(&a, &b, &mut c).unwrap();
+        overwrite.call_f32(&a, &b, &mut c).unwrap();
+        assert_close(&c, &product, 1e-5);
+    }
+
+    #[test]
+    fn with_beta_toggles_only_the_beta_flag() {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        let spec = GemmSpec::packed(4, 4, 4, Transpose::A, f32, f32);
+        assert_ne!(spec.flags & LIBXSMM_GEMM_FLAG_BETA_0, 0);
+        let acc = spec.with_beta(Beta::One);
+        assert_eq!(acc.flags & LIBXSMM_GEMM_FLAG_BETA_0, 0);
+        assert_ne!(acc.flags & LIBXSMM_GEMM_FLAG_TRANS_A, 0);
+        assert_eq!(acc.with_beta(Beta::Zero), spec);
+    }
+
+    #[test]
+    fn f16_gemm_within_tolerance_of_f32() {
+        use crate::f16::{convert_f16_to_f32, convert_f32_to_f16};
+
+        let (m, n, k) = (12, 9, 64);
+        let a = unit_rows(k, m, 5);
+        let b = unit_rows(n, k, 6);
+        let expected = reference(Transpose::A, (m, n, k), &a, &b);
+        let to_f16 = |src: &[f32]| {
+            let mut dst = vec![0u16; src.len()];
+            convert_f32_to_f16(src, &mut dst);
+            dst
+        };
+        let (a16, b16) = (to_f16(&a), to_f16(&b));
+
+        // The conversion fallback: widen the halves and run the f32 GEMM
+        let widen = |src: &[u16]| {
+            let mut dst = vec![0.0f32; src.len()];
+            convert_f16_to_f32(src, &mut dst);
+            dst
+        };
+        let widened = reference(Transpose::A, (m, n, k), &widen(&a16), &widen(&b16));
+        assert_close(&widened, &expected, 1e-2);
+
+        let f16 = JitKernel::f16_gemm(m as i32, n as i32, k as i32, Transpose::A);
+        let Some(kernel) = available(f16) else {
+            return;
+        };
+        let mut c = vec![0.0f32; m * n];
+        kernel.call_f16(&a16, &b16, &mut c).unwrap();
+        assert_close(&c, &expected, 1e-2);
+        assert!(kernel.call_f32(&a, &b, &mut c).is_err());
+    }
+
+    #[test]
+    fn int8_gemm_dequantizes_to_f32_reference() {
+        use crate::quant::{max_abs_scale, quantize_i8, quantize_u8, U8_ZERO_POINT};
+
+        let (m, n, k) = (10, 7, 64);
+        let a = unit_rows(k, m, 7);
+        let b = unit_rows(n, k, 8);
+        let expected = reference(Transpose::A, (m, n, k), &a, &b);
+        let (scale_a, scale_b) = (max_abs_scale(&a), max_abs_scale(&b));
+        let mut a_i8 = vec![0i8; a.len()];
+        let mut a_u8 = vec![0u8; a.len()];
+        let mut b_i8 = vec![0i8; b.len()];
+        quantize_i8(&a, scale_a, &mut a_i8);
+        quantize_u8(&a, scale_a, &mut a_u8);
+        quantize_i8(&b, scale_b, &mut b_i8);
+
+        // Integer products are exact in f32 at this size
+        let widen = |src: &[i32]| src.iter().map(|&v| v as f32).collect::<Vec<_>>();
+        let a_ints: Vec<i32> = a_i8.iter().map(|&v| v as i32).collect();
+        let b_ints: Vec<i32> = b_i8.iter().map(|&v| v as i32).collect();
+        let signed = reference(Transpose::A, (m, n, k), &widen(&a_ints), &widen(&b_ints));
+        let dequantized: Vec<f32> = signed.iter().map(|v| v * scale_a * scale_b).collect();
+        assert_close(&dequantized, &expected, 2e-2);
+
+        // u8 operands carry a +128 zero point: each column of C gains 128·Σb
+        let a_shifted: Vec<i32> = a_u8.iter().map(|&v| v as i32).collect();
+        let unsigned = reference(Transpose::A, (m, n, k), &widen(&a_shifted), &widen(&b_ints));
+        for (j, column) in unsigned.chunks_exact(m).enumerate() {
+            let b_sum: i32 = b_ints[j * k..(j + 1) * k].iter().sum();
+            for (i, &v) in column.iter().enumerate() {
+                assert_eq!(v as i32 - U8_ZERO_POINT * b_sum, signed[j * m + i] as i32);
+            }
+        }
+
+        let (mi, ni, ki) = (m as i32, n as i32, k as i32);
+        if let Some(kernel) = available(Int8Kernel::i8i8_gemm(mi, ni, ki, Transpose::A)) {
+            let mut c = vec![0i32; m * n];
+            kernel.call_slices(&a_i8, &b_i8, &mut c).unwrap();
+            assert_eq!(widen(&c), signed);
+            assert!(matches!(
+                kernel.call_slices(&a_u8, &b_i8, &mut c),
+                Err(CallError::DtypeMismatch { .. })
+            ));
+        }
+        if let Some(kernel) = available(Int8Kernel::u8i8_gemm(mi, ni, ki, Transpose::A)) {
+            let mut c = vec![0i32; m * n];
+            kernel.call_slices(&a_u8, &b_i8, &mut c).unwrap();
+            assert_eq!(widen(&c), unsigned);
+            assert!(kernel.call_slices(&a_i8, &b_i8, &mut c).is_err());
+        }
+    }
+
+    #[test]
+    fn brgemm_matches_looped_gemm_calls() {
+        let (m, n, k, batch) = (8, 5, 16, 4);
+        let (mi, ni, ki) = (m as i32, n as i32, k as i32);
+        let a = unit_rows(batch * k, m, 9);
+        let b = unit_rows(batch * n, k, 10);
+        let (a_block, b_block) = (m * k, k * n);
+        let Some(acc) = available(JitKernel::f32_gemm_acc(mi, ni, ki, Transpose::A)) else {
+            return;
+        };
+        let mut looped = vec![0.0f32; m * n];
+        for (a, b) in a.chunks_exact(a_block).zip(b.chunks_exact(b_block)) {
+            acc.call_f32(a, b, &mut looped).unwrap();
+        }
+
+        let strided =
+            BrgemmKernel::f32_strided(mi, ni, ki, a_block as i32, b_block as i32, Transpose::A);
+        let strided = strided.expect("libxsmm dispatches the shape");
+        let mut c = vec![1e6f32; m * n];
+        unsafe { strided.call_stride(a.as_ptr(), b.as_ptr(), c.as_mut_ptr(), batch as u64) };
+        assert_close(&c, &looped, 1e-5);
+
+        // Address mode over the blocks in reverse order sums the same terms
+        let address = BrgemmKernel::f32_address(mi, ni, ki, Transpose::A);
+        let address = address.expect("libxsmm dispatches the shape");
+        let a_ptrs: Vec<*const f32> = a.chunks_exact(a_block).rev().map(<[f32]>::as_ptr).collect();
+        let b_ptrs: Vec<*const f32> = b.chunks_exact(b_block).rev().map(<[f32]>::as_ptr).collect();
+        c.fill(1e6);
+        unsafe { address.call_address(&a_ptrs, &b_ptrs, c.as_mut_ptr()) };
+        assert_close(&c, &looped, 1e-5);
+
+        // Lists of different lengths, and the wrong call for the kernel,
+        // panic before the kernel runs
+        let c_ptr = c.as_mut_ptr();
+        let short = std::panic::catch_unwind(|| unsafe {
+            address.call_address(&a_ptrs, &b_ptrs[1..], c_ptr)
+        });
+        assert!(short.is_err());
+        let mismatched =
+            std::panic::catch_unwind(|| unsafe { strided.call_address(&a_ptrs, &b_ptrs, c_ptr) });
+        assert!(mismatched.is_err());
+    }
+
+    #[test]
+    fn brgemm_strides_overflowing_i32_bytes_are_refused() {
+        let huge = i32::MAX / 2;
+        for (stride_a, stride_b) in [(huge, 16), (16, huge), (-huge, 16)] {
+            let kernel = BrgemmKernel::f32_strided(8, 5, 16, stride_a, stride_b, Transpose::A);
+            assert!(
+                matches!(kernel, Err(DispatchError::ShapeTooLarge { .. })),
+                "strides {stride_a}, {stride_b}"
+            );
+        }
+    }
+
+    #[test]
+    fn contexts_acquired_and_dropped_across_threads() {
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 16;
+        std::thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    let held: Vec<LibxsmmContext> = (0..PER_THREAD / 2)
+                        .map(|_| LibxsmmContext::acquire())
+                        .collect();
+                    let clones = held.clone();
+                    drop((held, clones));
+                });
+            }
+        });
+        assert!(INITIALIZED.get().is_some());
+        // Dropping every handle leaves the runtime up for new kernels
+        if let Some(kernel) = available(JitKernel::f32_gemm(8, 4, 16, Transpose::A)) {
+            let a = unit_rows(8, 16, 1);
+            let b = unit_rows(4, 16, 2);
+            let mut c = vec![0.0f32; 8 * 4];
+            kernel.call_f32(&a, &b, &mut c).unwrap();
+            assert_close(&c, &reference(Transpose::A, (8, 4, 16), &a, &b), 1e-5);
+        }
+    }
+
+    #[test]
+    fn bf16_config_follows_mocked_arch_ids() {
+        let cases = [
+            (1001, CpuArch::Generic, VnniLayout::Flat),
+            (LIBXSMM_TARGET_ARCH_AVX2, CpuArch::Avx2, VnniLayout::Flat),
+            (1010, CpuArch::Avx2, VnniLayout::Flat),
+            (
+                LIBXSMM_TARGET_ARCH_AVX512_SKX,
+                CpuArch::Avx512Skx,
+                VnniLayout::Flat,
+            ),
+            (
+                LIBXSMM_TARGET_ARCH_AVX512_CLX,
+                CpuArch::Avx512Clx,
+                VnniLayout::Flat,
+            ),
+            (
+                LIBXSMM_TARGET_ARCH_AVX512_CPX,
+                CpuArch::Avx512Cpx,
+                VnniLayout::Vnni2,
+            ),
+            (
+                LIBXSMM_TARGET_ARCH_AVX512_SPR,
+                CpuArch::Avx512Spr,
+                VnniLayout::Vnni2,
+            ),
+            (1105, CpuArch::Avx512Spr, VnniLayout::Vnni2),
+            (2001, CpuArch::Other(2001), VnniLayout::Flat),
+        ];
+        for (id, arch, layout) in cases {
+            assert_eq!(CpuArch::from_id(id), arch, "arch id {id}");
+            let config = Bf16KernelConfig::for_arch(CpuArch::from_id(id));
+            assert_eq!(config.layout, layout, "arch id {id}");
+            let vnni = config.flags & LIBXSMM_GEMM_FLAG_VNNI_A != 0;
+            assert_eq!(vnni, layout == VnniLayout::Vnni2, "arch id {id}");
+            assert_eq!(config.padded_k(33), if vnni { 34 } else { 33 });
+            assert_eq!(config.padded_k(32), 32);
+        }
+        for arch in [
+            CpuArch::Generic,
+            CpuArch::Avx2,
+            CpuArch::Avx512Cpx,
+            CpuArch::Avx512Spr,
+        ] {
+            assert_eq!(CpuArch::from_id(arch.id()), arch);
+        }
+    }
+
+    #[test]
+    fn shared_kernel_runs_from_32_threads() {
+        const THREADS: usize = 32;
+        let (m, n, k) = (16, 4, 32);
+        let Some(kernel) = available(JitKernel::f32_gemm(
+            m as i32,
+            n as i32,
+            k as i32,
+            Transpose::A,
+        )) else {
+            return;
+        };
+        let kernel = std::sync::Arc::new(kernel);
+        let a = unit_rows(m, k, 15);
+        let bs: Vec<Vec<f32>> = (0..THREADS)
+            .map(|t| unit_rows(n, k, 100 + t as u64))
+            .collect();
+        // One C buffer split into disjoint per-thread tiles
+        let mut c = vec![0.0f32; THREADS * m * n];
+        std::thread::scope(|s| {
+            for (tile, b) in c.chunks_exact_mut(m * n).zip(&bs) {
+                let (kernel, a) = (kernel.clone(), &a);
+                s.spawn(move || {
+                    for _ in 0..50 {
+                        kernel.call_f32(a, b, tile).unwrap();
+                    }
+                });
+            }
+        });
+        for (tile, b) in c.chunks_exact(m * n).zip(&bs) {
+            assert_close(tile, &reference(Transpose::A, (m, n, k), &a, b), 1e-5);
+        }
+    }
+
+    /// Bytes hitting both sign-extension boundaries (0x7f/0x80, 0x00/0xff).
+    fn extreme_bytes(len: usize, seed: usize) -> Vec<u8> {
+        let edges = [0x00, 0x01, 0x7f, 0x80, 0x81, 0xfe, 0xff];
+        (0..len)
+            .map(|i| edges[(i * 5 + seed) % edges.len()])
+            .collect()
+    }
+
+    /// C = Aᵀ·B over integers, A stored k×m and B k×n column-major.
+    fn int_reference(m: usize, n: usize, k: usize, a: &[i32], b: &[i32]) -> Vec<i32> {
+        let mut c = vec![0i32; m * n];
+        for j in 0..n {
+            for i in 0..m {
+                c[j * m + i] = (0..k).map(|p| a[i * k + p] * b[j * k + p]).sum();
+            }
+        }
+        c
+    }
+
+    #[test]
+    fn mixed_signedness_matches_integer_reference() {
+        let (m, n, k) = (8, 6, 64);
+        let a = extreme_bytes(m * k, 0);
+        let b = extreme_bytes(n * k, 3);
+        let as_i8 = |bytes: &[u8]| bytes.iter().map(|&v| v as i8).collect::<Vec<_>>();
+        let signed = |bytes: &[u8]| bytes.iter().map(|&v| v as i8 as i32).collect::<Vec<_>>();
+        let unsigned = |bytes: &[u8]| bytes.iter().map(|&v| v as i32).collect::<Vec<_>>();
+        let (a_i8, b_i8) = (as_i8(&a), as_i8(&b));
+        let (mi, ni, ki) = (m as i32, n as i32, k as i32);
+        let mut c = vec![0i32; m * n];
+
+        // u8 × i8: only A is zero-extended
+        let expected = int_reference(m, n, k, &unsigned(&a), &signed(&b));
+        assert_ne!(expected, int_reference(m, n, k, &signed(&a), &signed(&b)));
+        if let Some(kernel) = available(Int8Kernel::u8i8_gemm(mi, ni, ki, Transpose::A)) {
+            kernel.call_slices(&a, &b_i8, &mut c).unwrap();
+            assert_eq!(c, expected);
+        }
+
+        // i8 × u8: only B is zero-extended
+        let expected = int_reference(m, n, k, &signed(&a), &unsigned(&b));
+        let signedness = Int8Signedness::SignedUnsigned;
+        if let Some(kernel) = available(Int8Kernel::gemm(mi, ni, ki, Transpose::A, signedness)) {
+            kernel.call_slices(&a_i8, &b, &mut c).unwrap();
+            assert_eq!(c, expected);
+            let err = kernel.call_slices(&a, &b, &mut c).unwrap_err();
+            assert_eq!(
+                err,
+                CallError::DtypeMismatch {
+                    expected: (LIBXSMM_DATATYPE_I8, LIBXSMM_DATATYPE_I32),
+                    actual: (LIBXSMM_DATATYPE_U8, LIBXSMM_DATATYPE_I32),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn signedness_maps_to_flags_and_dtypes() {
+        let (i8, u8) = (LIBXSMM_DATATYPE_I8, LIBXSMM_DATATYPE_U8);
+        let cases = [
+            (false, false, LIBXSMM_GEMM_FLAG_NONE, (i8, i8)),
+            (true, false, LIBXSMM_GEMM_FLAG_A_UNSIGNED, (u8, i8)),
+            (false, true, LIBXSMM_GEMM_FLAG_B_UNSIGNED, (i8, u8)),
+            (true, true, LIBXSMM_GEMM_FLAG_AB_UNSIGNED, (u8, u8)),
+        ];
+        for (a_unsigned, b_unsigned, flags, dtypes) in cases {
+            let signedness = Int8Signedness::from_operands(a_unsigned, b_unsigned);
+            assert_eq!(signedness.flags(), flags);
+            assert_eq!(signedness.dtypes(), dtypes);
+        }
     }
 }
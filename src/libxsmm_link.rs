@@ -0,0 +1,229 @@
+//! How the libxsmm FFI symbols are bound.
+//!
+//! By default `libxsmm_extern!` declares them in an `extern "C"` block
+//! linked against `xsmm` at build time. With `libxsmm-dlopen` it declares
+//! `unsafe fn`s of the same names and signatures instead, which call
+//! through symbols resolved from the shared library the first time
+//! anything asks for them, so one binary runs with or without libxsmm
+//! installed.
+//!
+//! The library is `$MAXSIM_LIBXSMM` when set, else `libxsmm.so` (then
+//! `libxsmm.so.1`) on the loader's search path. If it or any symbol the
+//! bindings declare is missing, `loaded()` is false: dispatch fails with
+//! `DispatchError::NotLoaded`, `CpuArch::detect` reports `Generic` and
+//! `Gemm` runs on the native kernel, so scoring falls back as on a CPU
+//! libxsmm has no kernels for. `status()` says which library was used or
+//! why none was. Calling a binding directly without the library panics
+//! with that error.
+
+#[cfg(feature = "libxsmm-dlopen")]
+use std::fmt;
+#[cfg(feature = "libxsmm-dlopen")]
+use std::path::PathBuf;
+#[cfg(feature = "libxsmm-dlopen")]
+use std::sync::OnceLock;
+
+/// Declare libxsmm functions: linked, or resolved at runtime with
+/// `libxsmm-dlopen` (see the module docs).
+macro_rules! libxsmm_extern {
+    ($(
+        $(#[$meta:meta])*
+        pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;
+    )*) => {
+        #[cfg(not(feature = "libxsmm-dlopen"))]
+        #[link(name = "xsmm")]
+        extern "C" {
+            $($(#[$meta])* pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+        }
+
+        /// This module's bindings, resolved from the loaded library.
+        #[cfg(feature = "libxsmm-dlopen")]
+        pub(crate) struct Symbols {
+            $($name: unsafe extern "C" fn($($ty),*) $(-> $ret)?,)*
+        }
+
+        /// Resolve this module's bindings once; the error names the first
+        /// missing symbol.
+        #[cfg(feature = "libxsmm-dlopen")]
+        pub(crate) fn symbols(
+        ) -> Result<&'static Symbols, &'static crate::libxsmm_link::LoadError> {
+            static SYMBOLS: std::sync::OnceLock<
+                Result<Symbols, crate::libxsmm_link::LoadError>,
+            > = std::sync::OnceLock::new();
+            SYMBOLS
+                .get_or_init(|| unsafe {
+                    Ok(Symbols {
+                        $($name: crate::libxsmm_link::symbol(stringify!($name))?,)*
+                    })
+                })
+                .as_ref()
+        }
+
+        $(
+            #[cfg(feature = "libxsmm-dlopen")]
+            $(#[$meta])*
+            /// # Safety
+            /// As the libxsmm function of this name; panics when libxsmm
+            /// could not be loaded.
+            #[allow(clippy::too_many_arguments)]
+            pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+                let symbols = symbols().unwrap_or_else(|err| panic!("{}", err));
+                (symbols.$name)($($arg),*)
+            }
+        )*
+    };
+}
+
+/// The shared library the bindings were resolved from.
+#[cfg(feature = "libxsmm-dlopen")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LibxsmmLibrary {
+    /// File the loader mapped, symlinks resolved when possible.
+    pub path: PathBuf,
+    /// From a versioned file name such as `libxsmm.so.1.17`.
+    pub version: Option<String>,
+}
+
+/// Why libxsmm could not be used.
+#[cfg(feature = "libxsmm-dlopen")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// None of the candidate names opened; `message` is the loader's
+    /// error for the last.
+    Library { tried: Vec<String>, message: String },
+    /// The library lacks a symbol the bindings need.
+    Symbol {
+        library: PathBuf,
+        name: &'static str,
+        message: String,
+    },
+}
+
+#[cfg(feature = "libxsmm-dlopen")]
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Library { tried, message } => write!(
+                f,
+                "could not load libxsmm (tried {}): {}",
+                tried.join(", "),
+                message
+            ),
+            LoadError::Symbol { library, name, .. } => write!(
+                f,
+                "libxsmm symbol `{}` missing from {}",
+                name,
+                library.display()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "libxsmm-dlopen")]
+impl std::error::Error for LoadError {}
+
+/// Whether libxsmm can be called: always when linked, with
+/// `libxsmm-dlopen` once the library and every binding resolved.
+#[cfg(not(feature = "libxsmm-dlopen"))]
+pub fn loaded() -> bool {
+    true
+}
+
+/// Whether libxsmm can be called: always when linked, with
+/// `libxsmm-dlopen` once the library and every binding resolved.
+#[cfg(feature = "libxsmm-dlopen")]
+pub fn loaded() -> bool {
+    status().is_ok()
+}
+
+/// The library every binding was resolved from, or the first failure.
+#[cfg(feature = "libxsmm-dlopen")]
+pub fn status() -> Result<&'static LibxsmmLibrary, &'static LoadError> {
+    let (_, library) = library()?;
+    crate::libxsmm_bindings::symbols()?;
+    crate::meltw::symbols()?;
+    Ok(library)
+}
+
+/// Environment variable naming the library to load instead of the
+/// defaults.
+#[cfg(feature = "libxsmm-dlopen")]
+pub const LIBRARY_ENV: &str = "MAXSIM_LIBXSMM";
+
+#[cfg(feature = "libxsmm-dlopen")]
+type Loaded = (libloading::Library, LibxsmmLibrary);
+
+/// Open the library once.
+#[cfg(feature = "libxsmm-dlopen")]
+fn library() -> Result<&'static Loaded, &'static LoadError> {
+    static LIBRARY: OnceLock<Result<Loaded, LoadError>> = OnceLock::new();
+    LIBRARY.get_or_init(open).as_ref()
+}
+
+#[cfg(feature = "libxsmm-dlopen")]
+fn open() -> Result<Loaded, LoadError> {
+    let tried: Vec<String> = match std::env::var(LIBRARY_ENV) {
+        Ok(path) => vec![path],
+        Err(_) => {
+            let default = libloading::library_filename("xsmm");
+            let default = default.to_string_lossy().into_owned();
+            vec![default.clone(), format!("{}.1", default)]
+        }
+    };
+    let mut message = String::new();
+    for name in &tried {
+        // Loading runs the library's initializers, as linking it would
+        match unsafe { libloading::Library::new(name) } {
+            Ok(lib) => {
+                let path = mapped_path(&lib).unwrap_or_else(|| PathBuf::from(name));
+                let path = path.canonicalize().unwrap_or(path);
+                let version = path
+                    .file_name()
+                    .and_then(|name| name.to_str()?.split_once(".so.").map(|(_, v)| v))
+                    .map(str::to_string);
+                return Ok((lib, LibxsmmLibrary { path, version }));
+            }
+            Err(err) => message = err.to_string(),
+        }
+    }
+    Err(LoadError::Library { tried, message })
+}
+
+/// File `lib` was mapped from, found through the address of one of its
+/// symbols.
+#[cfg(all(feature = "libxsmm-dlopen", unix))]
+fn mapped_path(lib: &libloading::Library) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let init = unsafe { lib.get::<unsafe extern "C" fn()>(b"libxsmm_init").ok()? };
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    if unsafe { libc::dladdr(*init as *const libc::c_void, &mut info) } == 0
+        || info.dli_fname.is_null()
+    {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(info.dli_fname) };
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(name.to_bytes())))
+}
+
+#[cfg(all(feature = "libxsmm-dlopen", not(unix)))]
+fn mapped_path(_: &libloading::Library) -> Option<PathBuf> {
+    None
+}
+
+/// Symbol `name` of the loaded library, as a `T` (a function pointer).
+///
+/// # Safety
+/// `T` must be the symbol's actual type.
+#[cfg(feature = "libxsmm-dlopen")]
+pub(crate) unsafe fn symbol<T: Copy>(name: &'static str) -> Result<T, LoadError> {
+    let (lib, library) = library().map_err(Clone::clone)?;
+    match lib.get::<T>(name.as_bytes()) {
+        Ok(symbol) => Ok(*symbol),
+        Err(err) => Err(LoadError::Symbol {
+            library: library.path.clone(),
+            name,
+            message: err.to_string(),
+        }),
+    }
+}
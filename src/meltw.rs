@@ -0,0 +1,613 @@
+//! libxsmm element-wise TPPs (tensor processing primitives).
+//!
+//! JIT kernels for the data movement around the GEMM — copies, transposes,
+//! f32→bf16 conversion, activations — which are slower than the GEMM itself
+//! when hand-rolled for small tiles. All shapes are column-major m×n.
+
+use libc::{c_int, c_void};
+
+use crate::libxsmm_bindings::{
+    CallError, LibxsmmBitfield, LibxsmmContext, LibxsmmMatrixArg, LibxsmmMatrixOpArg,
+    LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32,
+};
+use crate::libxsmm_link::loaded;
+
+type LibxsmmBlasint = c_int;
+
+// ============================================================================
+// Unary operator types (from libxsmm_typedefs.h, libxsmm_meltw_unary_type)
+// ============================================================================
+
+pub const LIBXSMM_MELTW_TYPE_UNARY_NONE: c_int = 0;
+pub const LIBXSMM_MELTW_TYPE_UNARY_IDENTITY: c_int = 1; // copy / convert
+pub const LIBXSMM_MELTW_TYPE_UNARY_XOR: c_int = 2; // zero
+pub const LIBXSMM_MELTW_TYPE_UNARY_RELU: c_int = 5;
+pub const LIBXSMM_MELTW_TYPE_UNARY_TRANSFORM_NORM_TO_NORMT: c_int = 29;
+
+pub const LIBXSMM_MELTW_FLAG_UNARY_NONE: LibxsmmBitfield = 0;
+
+// ============================================================================
+// Binary operator types (from libxsmm_typedefs.h, libxsmm_meltw_binary_type)
+// ============================================================================
+
+pub const LIBXSMM_MELTW_TYPE_BINARY_NONE: c_int = 0;
+pub const LIBXSMM_MELTW_TYPE_BINARY_ADD: c_int = 1;
+pub const LIBXSMM_MELTW_TYPE_BINARY_MUL: c_int = 2;
+pub const LIBXSMM_MELTW_TYPE_BINARY_MAX: c_int = 9;
+
+pub const LIBXSMM_MELTW_FLAG_BINARY_NONE: LibxsmmBitfield = 0;
+
+// ============================================================================
+// Struct types
+// ============================================================================
+
+/// Unary TPP shape: m×n input with leading dimension `ldi`, output `ldo`.
+/// From libxsmm_typedefs.h (libxsmm_meltw_unary_shape).
+#[repr(C)]
+#[derive(Clone)]
+pub struct LibxsmmMeltwUnaryShape {
+    pub m: LibxsmmBlasint,
+    pub n: LibxsmmBlasint,
+    pub ldi: LibxsmmBlasint,
+    pub ldo: LibxsmmBlasint,
+    pub in0_type: c_int,
+    pub out_type: c_int,
+    pub comp_type: c_int,
+}
+
+/// Call-site argument bundle for unary TPPs.
+/// From libxsmm_typedefs.h (libxsmm_meltw_unary_param).
+#[repr(C)]
+pub struct LibxsmmMeltwUnaryParam {
+    pub op: LibxsmmMatrixOpArg,
+    pub input: LibxsmmMatrixArg,
+    pub output: LibxsmmMatrixArg,
+}
+
+/// JIT-compiled unary TPP function pointer type.
+pub type LibxsmmMeltwUnaryFunction = unsafe extern "C" fn(*const LibxsmmMeltwUnaryParam);
+
+/// Binary TPP shape: two m×n inputs (`ldi`, `ldi2`) and an m×n output.
+/// From libxsmm_typedefs.h (libxsmm_meltw_binary_shape).
+#[repr(C)]
+#[derive(Clone)]
+pub struct LibxsmmMeltwBinaryShape {
+    pub m: LibxsmmBlasint,
+    pub n: LibxsmmBlasint,
+    pub ldi: LibxsmmBlasint,
+    pub ldi2: LibxsmmBlasint,
+    pub ldo: LibxsmmBlasint,
+    pub in0_type: c_int,
+    pub in1_type: c_int,
+    pub out_type: c_int,
+    pub comp_type: c_int,
+}
+
+/// Call-site argument bundle for binary TPPs.
+/// From libxsmm_typedefs.h (libxsmm_meltw_binary_param).
+#[repr(C)]
+pub struct LibxsmmMeltwBinaryParam {
+    pub op: LibxsmmMatrixOpArg,
+    pub in0: LibxsmmMatrixArg,
+    pub in1: LibxsmmMatrixArg,
+    pub in2: LibxsmmMatrixArg,
+    pub output: LibxsmmMatrixArg,
+}
+
+/// JIT-compiled binary TPP function pointer type.
+pub type LibxsmmMeltwBinaryFunction = unsafe extern "C" fn(*const LibxsmmMeltwBinaryParam);
+
+// ============================================================================
+// FFI function bindings
+// ============================================================================
+
+libxsmm_extern! {
+    pub fn libxsmm_dispatch_meltw_unary(
+        unary_type: c_int,
+        unary_shape: LibxsmmMeltwUnaryShape,
+        unary_flags: LibxsmmBitfield,
+    ) -> Option<LibxsmmMeltwUnaryFunction>;
+
+    pub fn libxsmm_dispatch_meltw_binary(
+        binary_type: c_int,
+        binary_shape: LibxsmmMeltwBinaryShape,
+        binary_flags: LibxsmmBitfield,
+    ) -> Option<LibxsmmMeltwBinaryFunction>;
+}
+
+// ============================================================================
+// Safe wrappers
+// ============================================================================
+
+/// JIT element-wise unary kernel for a fixed m×n shape.
+pub struct UnaryKernel {
+    kernel: LibxsmmMeltwUnaryFunction,
+    shape: LibxsmmMeltwUnaryShape,
+    /// Rows/columns of the output (swapped for transposes).
+    out_rows: i32,
+    out_cols: i32,
+    _ctx: LibxsmmContext,
+}
+
+impl UnaryKernel {
+    /// Copy of an f32 m×n matrix.
+    pub fn copy_f32(m: i32, n: i32) -> Option<Self> {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        Self::dispatch(
+            LIBXSMM_MELTW_TYPE_UNARY_IDENTITY,
+            m,
+            n,
+            m,
+            m,
+            f32,
+            f32,
+            (m, n),
+        )
+    }
+
+    /// Transpose an f32 m×n matrix into an n×m one.
+    pub fn transpose_f32(m: i32, n: i32) -> Option<Self> {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        Self::dispatch(
+            LIBXSMM_MELTW_TYPE_UNARY_TRANSFORM_NORM_TO_NORMT,
+            m,
+            n,
+            m,
+            n,
+            f32,
+            f32,
+            (n, m),
+        )
+    }
+
+    /// Round an f32 m×n matrix to bf16 (round-to-nearest-even).
+    pub fn cvt_f32_bf16(m: i32, n: i32) -> Option<Self> {
+        let (f32, bf16) = (LIBXSMM_DATATYPE_F32, LIBXSMM_DATATYPE_BF16);
+        Self::dispatch(
+            LIBXSMM_MELTW_TYPE_UNARY_IDENTITY,
+            m,
+            n,
+            m,
+            m,
+            f32,
+            bf16,
+            (m, n),
+        )
+    }
+
+    /// max(x, 0) over an f32 m×n matrix.
+    pub fn relu_f32(m: i32, n: i32) -> Option<Self> {
+        let f32 = LIBXSMM_DATATYPE_F32;
+        Self::dispatch(LIBXSMM_MELTW_TYPE_UNARY_RELU, m, n, m, m, f32, f32, (m, n))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch(
+        unary_type: c_int,
+        m: i32,
+        n: i32,
+        ldi: i32,
+        ldo: i32,
+        in_type: c_int,
+        out_type: c_int,
+        (out_rows, out_cols): (i32, i32),
+    ) -> Option<Self> {
+        if m <= 0 || n <= 0 {
+            return None;
+        }
+        let shape = LibxsmmMeltwUnaryShape {
+            m,
+            n,
+            ldi,
+            ldo,
+            in0_type: in_type,
+            out_type,
+            comp_type: LIBXSMM_DATATYPE_F32,
+        };
+        if !loaded() {
+            return None;
+        }
+        let ctx = LibxsmmContext::acquire();
+        let kernel = unsafe {
+            libxsmm_dispatch_meltw_unary(unary_type, shape.clone(), LIBXSMM_MELTW_FLAG_UNARY_NONE)?
+        };
+        Some(Self {
+            kernel,
+            shape,
+            out_rows,
+            out_cols,
+            _ctx: ctx,
+        })
+    }
+
+    fn check(
+        &self,
+        in_type: c_int,
+        out_type: c_int,
+        in_len: usize,
+        out_len: usize,
+    ) -> Result<(), CallError> {
+        if self.shape.in0_type != in_type || self.shape.out_type != out_type {
+            return Err(CallError::DtypeMismatch {
+                expected: (self.shape.in0_type, self.shape.out_type),
+                actual: (in_type, out_type),
+            });
+        }
+        let in_req = (self.shape.ldi * (self.shape.n - 1) + self.shape.m) as usize;
+        let out_req = (self.shape.ldo * (self.out_cols - 1) + self.out_rows) as usize;
+        for (operand, required, actual) in [('I', in_req, in_len), ('O', out_req, out_len)] {
+            if actual < required {
+                return Err(CallError::BufferTooSmall {
+                    operand,
+                    required,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Run an f32→f32 kernel (copy, transpose, relu).
+    pub fn call_f32(&self, input: &[f32], output: &mut [f32]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_F32,
+            LIBXSMM_DATATYPE_F32,
+            input.len(),
+            output.len(),
+        )?;
+        unsafe {
+            self.call(
+                input.as_ptr() as *const c_void,
+                output.as_mut_ptr() as *mut c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// Run an f32→bf16 conversion kernel (bf16 as raw `u16` bits).
+    pub fn call_f32_bf16(&self, input: &[f32], output: &mut [u16]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_F32,
+            LIBXSMM_DATATYPE_BF16,
+            input.len(),
+            output.len(),
+        )?;
+        unsafe {
+            self.call(
+                input.as_ptr() as *const c_void,
+                output.as_mut_ptr() as *mut c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// Call the kernel.
+    ///
+    /// # Safety
+    ///
+    /// input/output must be valid for the dispatched shape and must not
+    /// overlap.
+    pub unsafe fn call(&self, input: *const c_void, output: *mut c_void) {
+        let param = LibxsmmMeltwUnaryParam {
+            op: LibxsmmMatrixOpArg::default(),
+            input: LibxsmmMatrixArg::from_ptr(input),
+            output: LibxsmmMatrixArg::from_ptr(output as *const c_void),
+        };
+        (self.kernel)(&param);
+    }
+}
+
+/// Element-wise binary operator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Mul,
+    Max,
+}
+
+impl BinaryOp {
+    fn id(self) -> c_int {
+        match self {
+            BinaryOp::Add => LIBXSMM_MELTW_TYPE_BINARY_ADD,
+            BinaryOp::Mul => LIBXSMM_MELTW_TYPE_BINARY_MUL,
+            BinaryOp::Max => LIBXSMM_MELTW_TYPE_BINARY_MAX,
+        }
+    }
+}
+
+/// JIT element-wise binary kernel `out = op(a, b)` for a fixed m×n shape.
+///
+/// Inputs and output share one datatype (f32 or bf16); bf16 is computed in
+/// f32 and rounded on store.
+pub struct BinaryKernel {
+    kernel: LibxsmmMeltwBinaryFunction,
+    op: BinaryOp,
+    shape: LibxsmmMeltwBinaryShape,
+    _ctx: LibxsmmContext,
+}
+
+impl BinaryKernel {
+    /// Dispatch `op` over packed m×n matrices of `dtype`
+    /// (`LIBXSMM_DATATYPE_F32` or `LIBXSMM_DATATYPE_BF16`).
+    pub fn new(op: BinaryOp, m: i32, n: i32, dtype: c_int) -> Option<Self> {
+        if m <= 0 || n <= 0 || !matches!(dtype, LIBXSMM_DATATYPE_F32 | LIBXSMM_DATATYPE_BF16) {
+            return None;
+        }
+        let shape = LibxsmmMeltwBinaryShape {
+            m,
+            n,
+            ldi: m,
+            ldi2: m,
+            ldo: m,
+            in0_type: dtype,
+            in1_type: dtype,
+            out_type: dtype,
+            comp_type: LIBXSMM_DATATYPE_F32,
+        };
+        if !loaded() {
+            return None;
+        }
+        let ctx = LibxsmmContext::acquire();
+        let kernel = unsafe {
+            libxsmm_dispatch_meltw_binary(op.id(), shape.clone(), LIBXSMM_MELTW_FLAG_BINARY_NONE)?
+        };
+        Some(Self {
+            kernel,
+            op,
+            shape,
+            _ctx: ctx,
+        })
+    }
+
+    pub fn add_f32(m: i32, n: i32) -> Option<Self> {
+        Self::new(BinaryOp::Add, m, n, LIBXSMM_DATATYPE_F32)
+    }
+
+    pub fn mul_f32(m: i32, n: i32) -> Option<Self> {
+        Self::new(BinaryOp::Mul, m, n, LIBXSMM_DATATYPE_F32)
+    }
+
+    pub fn max_f32(m: i32, n: i32) -> Option<Self> {
+        Self::new(BinaryOp::Max, m, n, LIBXSMM_DATATYPE_F32)
+    }
+
+    pub fn op(&self) -> BinaryOp {
+        self.op
+    }
+
+    /// Elements per operand (all three are packed m×n).
+    pub fn len(&self) -> usize {
+        (self.shape.m * self.shape.n) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn check(&self, dtype: c_int, lens: [(char, usize); 3]) -> Result<(), CallError> {
+        if self.shape.in0_type != dtype {
+            return Err(CallError::DtypeMismatch {
+                expected: (self.shape.in0_type, self.shape.out_type),
+                actual: (dtype, dtype),
+            });
+        }
+        let required = self.len();
+        for (operand, actual) in lens {
+            if actual < required {
+                return Err(CallError::BufferTooSmall {
+                    operand,
+                    required,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// `out = op(a, b)` over f32 operands.
+    pub fn call_f32(&self, a: &[f32], b: &[f32], out: &mut [f32]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_F32,
+            [('A', a.len()), ('B', b.len()), ('C', out.len())],
+        )?;
+        unsafe {
+            self.call(
+                a.as_ptr() as *const c_void,
+                b.as_ptr() as *const c_void,
+                out.as_mut_ptr() as *mut c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// `acc = op(acc, b)` over f32 operands, e.g. folding a partial
+    /// column-max tile into a running one.
+    pub fn call_f32_inplace(&self, acc: &mut [f32], b: &[f32]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_F32,
+            [('A', acc.len()), ('B', b.len()), ('C', acc.len())],
+        )?;
+        let acc = acc.as_mut_ptr() as *mut c_void;
+        unsafe { self.call(acc as *const c_void, b.as_ptr() as *const c_void, acc) };
+        Ok(())
+    }
+
+    /// `out = op(a, b)` over bf16 operands (raw `u16` bits).
+    pub fn call_bf16(&self, a: &[u16], b: &[u16], out: &mut [u16]) -> Result<(), CallError> {
+        self.check(
+            LIBXSMM_DATATYPE_BF16,
+            [('A', a.len()), ('B', b.len()), ('C', out.len())],
+        )?;
+        unsafe {
+            self.call(
+                a.as_ptr() as *const c_void,
+                b.as_ptr() as *const c_void,
+                out.as_mut_ptr() as *mut c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// Call the kernel.
+    ///
+    /// # Safety
+    ///
+    /// a/b/out must be valid for the dispatched shape. Element-wise TPPs
+    /// tolerate `out` aliasing `a` or `b` exactly; partial overlap is
+    /// undefined.
+    pub unsafe fn call(&self, a: *const c_void, b: *const c_void, out: *mut c_void) {
+        let param = LibxsmmMeltwBinaryParam {
+            op: LibxsmmMatrixOpArg::default(),
+            in0: LibxsmmMatrixArg::from_ptr(a),
+            in1: LibxsmmMatrixArg::from_ptr(b),
+            in2: LibxsmmMatrixArg::from_ptr(std::ptr::null()),
+            output: LibxsmmMatrixArg::from_ptr(out as *const c_void),
+        };
+        (self.kernel)(&param);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+    use crate::bf16::{bf16_to_f32, f32_to_bf16};
+
+    const M: i32 = 13;
+    const N: i32 = 7;
+
+    fn input() -> Vec<f32> {
+        unit_rows(N as usize, M as usize, 31)
+    }
+
+    #[test]
+    fn copy_and_relu_match_scalar() {
+        let x = input();
+        if let Some(copy) = UnaryKernel::copy_f32(M, N) {
+            let mut out = vec![f32::NAN; x.len()];
+            copy.call_f32(&x, &mut out).unwrap();
+            assert_eq!(out, x);
+            assert!(copy.call_f32(&x[1..], &mut out).is_err());
+        }
+        if let Some(relu) = UnaryKernel::relu_f32(M, N) {
+            let mut out = vec![f32::NAN; x.len()];
+            relu.call_f32(&x, &mut out).unwrap();
+            let expected: Vec<f32> = x.iter().map(|&v| v.max(0.0)).collect();
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn transpose_matches_scalar() {
+        let Some(transpose) = UnaryKernel::transpose_f32(M, N) else {
+            return;
+        };
+        let (m, n) = (M as usize, N as usize);
+        let x = input();
+        let mut out = vec![f32::NAN; x.len()];
+        transpose.call_f32(&x, &mut out).unwrap();
+        for j in 0..n {
+            for i in 0..m {
+                assert_eq!(out[i * n + j], x[j * m + i], "element ({i}, {j})");
+            }
+        }
+    }
+
+    #[test]
+    fn bf16_conversion_matches_scalar() {
+        let Some(cvt) = UnaryKernel::cvt_f32_bf16(M, N) else {
+            return;
+        };
+        let x = input();
+        let mut out = vec![0u16; x.len()];
+        cvt.call_f32_bf16(&x, &mut out).unwrap();
+        let expected: Vec<u16> = x.iter().map(|&v| f32_to_bf16(v)).collect();
+        assert_eq!(out, expected);
+        let mut wrong = vec![0.0f32; x.len()];
+        assert!(matches!(
+            cvt.call_f32(&x, &mut wrong),
+            Err(CallError::DtypeMismatch { .. })
+        ));
+    }
+
+    fn scalar(op: BinaryOp, a: f32, b: f32) -> f32 {
+        match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Max => a.max(b),
+        }
+    }
+
+    #[test]
+    fn binary_ops_match_scalar() {
+        let a = input();
+        let b = unit_rows(N as usize, M as usize, 32);
+        for op in [BinaryOp::Add, BinaryOp::Mul, BinaryOp::Max] {
+            let Some(kernel) = BinaryKernel::new(op, M, N, LIBXSMM_DATATYPE_F32) else {
+                continue;
+            };
+            assert_eq!(kernel.op(), op);
+            let expected: Vec<f32> = a.iter().zip(&b).map(|(&x, &y)| scalar(op, x, y)).collect();
+            let mut out = vec![f32::NAN; a.len()];
+            kernel.call_f32(&a, &b, &mut out).unwrap();
+            assert_eq!(out, expected, "{op:?}");
+
+            let mut acc = a.clone();
+            kernel.call_f32_inplace(&mut acc, &b).unwrap();
+            assert_eq!(acc, expected, "{op:?} in place");
+
+            assert!(matches!(
+                kernel.call_f32(&a, &b[1..], &mut out),
+                Err(CallError::BufferTooSmall { operand: 'B', .. })
+            ));
+            assert!(matches!(
+                kernel.call_f32_inplace(&mut acc[1..], &b),
+                Err(CallError::BufferTooSmall { .. })
+            ));
+        }
+        let kernels = [
+            BinaryKernel::add_f32(M, N),
+            BinaryKernel::mul_f32(M, N),
+            BinaryKernel::max_f32(M, N),
+        ];
+        let ops: Vec<BinaryOp> = kernels.iter().flatten().map(BinaryKernel::op).collect();
+        if ops.len() == 3 {
+            assert_eq!(ops, [BinaryOp::Add, BinaryOp::Mul, BinaryOp::Max]);
+        }
+    }
+
+    #[test]
+    fn bf16_binary_ops_round_the_f32_result() {
+        let a: Vec<u16> = input().iter().map(|&v| f32_to_bf16(v)).collect();
+        let b: Vec<u16> = unit_rows(N as usize, M as usize, 33)
+            .iter()
+            .map(|&v| f32_to_bf16(v))
+            .collect();
+        for op in [BinaryOp::Add, BinaryOp::Mul, BinaryOp::Max] {
+            let Some(kernel) = BinaryKernel::new(op, M, N, LIBXSMM_DATATYPE_BF16) else {
+                continue;
+            };
+            let expected: Vec<u16> = a
+                .iter()
+                .zip(&b)
+                .map(|(&x, &y)| f32_to_bf16(scalar(op, bf16_to_f32(x), bf16_to_f32(y))))
+                .collect();
+            let mut out = vec![0u16; a.len()];
+            kernel.call_bf16(&a, &b, &mut out).unwrap();
+            assert_eq!(out, expected, "{op:?}");
+            let mut wrong = vec![0.0f32; a.len()];
+            assert!(matches!(
+                kernel.call_f32(&input(), &input(), &mut wrong),
+                Err(CallError::DtypeMismatch { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn degenerate_shapes_are_refused() {
+        assert!(UnaryKernel::copy_f32(0, N).is_none());
+        assert!(UnaryKernel::relu_f32(M, -1).is_none());
+        assert!(BinaryKernel::add_f32(M, 0).is_none());
+        assert!(BinaryKernel::new(BinaryOp::Max, M, N, 0).is_none());
+    }
+}
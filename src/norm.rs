@@ -0,0 +1,95 @@
+//! L2 normalization of token embeddings.
+//!
+//! Each `dim`-value row is divided by `max(‖row‖, NORM_EPS)`, so non-zero
+//! rows become unit length and all-zero rows stay zero instead of turning
+//! into NaNs. Dot products of normalized rows are cosine similarities.
+//!
+//! Runtime dispatch picks AVX2+FMA, then a scalar loop.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Smallest norm a row is divided by.
+pub const NORM_EPS: f32 = 1e-12;
+
+/// L2-normalize every `dim`-value row of `data` in place. Panics if `data`
+/// is not a whole number of rows.
+pub fn normalize_rows_inplace(data: &mut [f32], dim: usize) {
+    if dim == 0 {
+        assert!(data.is_empty(), "normalize: rows of dim 0 hold no values");
+        return;
+    }
+    assert!(
+        data.len().is_multiple_of(dim),
+        "normalize: data is not a whole number of rows"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+            return unsafe { normalize_rows_avx2(data, dim) };
+        }
+    }
+
+    normalize_rows_scalar(data, dim);
+}
+
+fn normalize_rows_scalar(data: &mut [f32], dim: usize) {
+    for row in data.chunks_exact_mut(dim) {
+        let norm = row.iter().map(|&x| x * x).sum::<f32>().sqrt();
+        let inv = 1.0 / norm.max(NORM_EPS);
+        for x in row {
+            *x *= inv;
+        }
+    }
+}
+
+/// AVX2+FMA: 8 floats per step for both the sum of squares and the scaling.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn normalize_rows_avx2(data: &mut [f32], dim: usize) {
+    for row in data.chunks_exact_mut(dim) {
+        let ptr = row.as_mut_ptr();
+        let body = dim - dim % 8;
+
+        let mut acc = _mm256_setzero_ps();
+        let mut i = 0;
+        while i < body {
+            let x = _mm256_loadu_ps(ptr.add(i));
+            acc = _mm256_fmadd_ps(x, x, acc);
+            i += 8;
+        }
+        let sum4 = _mm_add_ps(_mm256_extractf128_ps(acc, 1), _mm256_castps256_ps128(acc));
+        let sum2 = _mm_hadd_ps(sum4, sum4);
+        let mut sum = _mm_cvtss_f32(_mm_hadd_ps(sum2, sum2));
+        for &x in &row[body..] {
+            sum += x * x;
+        }
+
+        let inv = 1.0 / sum.sqrt().max(NORM_EPS);
+        let scale = _mm256_set1_ps(inv);
+        let mut i = 0;
+        while i < body {
+            let x = _mm256_loadu_ps(ptr.add(i));
+            _mm256_storeu_ps(ptr.add(i), _mm256_mul_ps(x, scale));
+            i += 8;
+        }
+        for x in &mut row[body..] {
+            *x *= inv;
+        }
+    }
+}
+
+/// L2 norm of each `dim`-value row of `data`. Panics if `dim` is 0.
+pub fn row_norms(data: &[f32], dim: usize) -> impl Iterator<Item = f32> + '_ {
+    data.chunks_exact(dim)
+        .map(|row| row.iter().map(|x| x * x).sum::<f32>().sqrt())
+}
+
+/// Largest L2 norm among the `dim`-value rows of `data`, 0 without rows.
+pub fn max_row_norm(data: &[f32], dim: usize) -> f32 {
+    if dim == 0 {
+        return 0.0;
+    }
+    row_norms(data, dim).fold(0.0, f32::max)
+}
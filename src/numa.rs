@@ -0,0 +1,386 @@
+//! NUMA placement of document stores (feature `numa`, Linux; elsewhere a
+//! single node and no pinning).
+//!
+//! On a multi-socket machine a store built by one thread lives on that
+//! thread's node, and workers on the other nodes score it through the
+//! interconnect. `NumaDocStore` places the documents explicitly:
+//!
+//! - `Partition` splits them into one contiguous run per node, of about
+//!   equal token counts. Each run is copied by a thread pinned to its node,
+//!   so first touch puts its pages there, and is scored by a rayon pool
+//!   pinned to the same node; the per-node top k are merged at the end.
+//! - `Interleave` spreads one copy's pages round-robin over all nodes, and
+//!   scores it on the global pool: no node is remote for everything.
+//! - `None` keeps a plain copy on the global pool.
+//!
+//! The topology is read from `/sys/devices/system/node`. Pinning and
+//! memory policies are best effort: where the kernel refuses them (e.g. in
+//! a restricted container) the store still scores correctly, just without
+//! the placement.
+
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::aligned::AlignedVec;
+use crate::collection::{DocCollection, DocId, Documents, QueryEmbeddings};
+use crate::score::{check_dim, top_k_heap, Reduction, ScoreError};
+use crate::topk::TopK;
+
+const NODE_DIR: &str = "/sys/devices/system/node";
+#[cfg(target_os = "linux")]
+const MPOL_DEFAULT: libc::c_int = 0;
+#[cfg(target_os = "linux")]
+const MPOL_INTERLEAVE: libc::c_int = 3;
+
+/// How a `NumaDocStore` places its documents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NumaConfig {
+    /// One copy, placed by the allocating thread, scored on the global pool.
+    None,
+    /// One copy with pages spread round-robin over the nodes.
+    Interleave,
+    /// One partition per node, each scored by threads pinned to it.
+    Partition,
+}
+
+impl Default for NumaConfig {
+    /// `Partition` when this machine has more than one node, else `None`.
+    fn default() -> Self {
+        if NumaTopology::detect().nodes().len() > 1 {
+            NumaConfig::Partition
+        } else {
+            NumaConfig::None
+        }
+    }
+}
+
+/// One NUMA node: its id and the CPUs on it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// The NUMA nodes with CPUs on this machine.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NumaTopology {
+    nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// Nodes listed under `/sys/devices/system/node`, ascending, skipping
+    /// memory-only nodes. A single node holding every CPU when the
+    /// directory cannot be read.
+    pub fn detect() -> Self {
+        let mut nodes = read_nodes(Path::new(NODE_DIR)).unwrap_or_default();
+        nodes.retain(|node| !node.cpus.is_empty());
+        nodes.sort_by_key(|node| node.id);
+        if nodes.is_empty() {
+            let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+            nodes.push(NumaNode {
+                id: 0,
+                cpus: (0..cpus).collect(),
+            });
+        }
+        Self { nodes }
+    }
+
+    pub fn nodes(&self) -> &[NumaNode] {
+        &self.nodes
+    }
+}
+
+/// f32 documents placed across NUMA nodes per a `NumaConfig`.
+#[derive(Debug)]
+pub struct NumaDocStore {
+    config: NumaConfig,
+    partitions: Vec<Partition>,
+    len: usize,
+    dim: usize,
+}
+
+/// Documents `first..first + docs.len()` of the store, with the pool
+/// pinned to their node (`None` scores on the global pool).
+#[derive(Debug)]
+struct Partition {
+    node: Option<usize>,
+    first: DocId,
+    docs: DocCollection,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl NumaDocStore {
+    /// Copy `docs` into place per `config`, on the detected topology.
+    /// Fails with `ScoreError::ThreadPool` if a node's pool cannot be
+    /// built.
+    pub fn new<D: Documents + ?Sized>(docs: &D, config: NumaConfig) -> Result<Self, ScoreError> {
+        Self::with_topology(docs, config, &NumaTopology::detect())
+    }
+
+    /// `new` on an explicit topology, e.g. a subset of the nodes.
+    pub fn with_topology<D: Documents + ?Sized>(
+        docs: &D,
+        config: NumaConfig,
+        topology: &NumaTopology,
+    ) -> Result<Self, ScoreError> {
+        let all = 0..docs.len();
+        let partitions = match config {
+            NumaConfig::None => vec![Partition::global(copy_docs(docs, all)?)],
+            NumaConfig::Interleave => {
+                let ids: Vec<usize> = topology.nodes.iter().map(|node| node.id).collect();
+                let _policy = InterleaveGuard::set(&ids);
+                vec![Partition::global(copy_docs(docs, all)?)]
+            }
+            NumaConfig::Partition => {
+                let runs = split_by_tokens(docs, topology.nodes.len());
+                std::thread::scope(|scope| {
+                    let copies: Vec<_> = topology
+                        .nodes
+                        .iter()
+                        .zip(&runs)
+                        .map(|(node, run)| {
+                            let run = run.clone();
+                            scope.spawn(move || {
+                                pin_to(&node.cpus);
+                                copy_docs(docs, run)
+                            })
+                        })
+                        .collect();
+                    topology
+                        .nodes
+                        .iter()
+                        .zip(&runs)
+                        .zip(copies)
+                        .map(|((node, run), copy)| {
+                            let docs = copy.join().expect("partition copy panicked")?;
+                            Partition::pinned(node, run.start, docs)
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                })?
+            }
+        };
+        Ok(Self {
+            config,
+            partitions,
+            len: docs.len(),
+            dim: docs.dim(),
+        })
+    }
+
+    pub fn config(&self) -> NumaConfig {
+        self.config
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// (node, documents) per partition; the node is `None` when the
+    /// partition is not pinned.
+    pub fn partitions(&self) -> Vec<(Option<usize>, Range<DocId>)> {
+        self.partitions
+            .iter()
+            .map(|p| (p.node, p.first..p.first + p.docs.len()))
+            .collect()
+    }
+
+    /// The `k` best documents for `query`, ids being positions in the
+    /// documents the store was built from. Partitions are scored at once,
+    /// each on its own pool, and their top k merged.
+    pub fn top_k(&self, query: &QueryEmbeddings, k: usize) -> Result<TopK, ScoreError> {
+        check_dim(query, self.dim)?;
+        let partials = std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .partitions
+                .iter()
+                .map(|part| scope.spawn(move || part.top_k(query, k)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("partition scoring panicked"))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(partials.into_iter().fold(TopK::new(k), TopK::merge))
+    }
+}
+
+impl Partition {
+    fn global(docs: DocCollection) -> Self {
+        Self {
+            node: None,
+            first: 0,
+            docs,
+            pool: None,
+        }
+    }
+
+    fn pinned(node: &NumaNode, first: DocId, docs: DocCollection) -> Result<Self, ScoreError> {
+        let (id, cpus) = (node.id, node.cpus.clone());
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(cpus.len())
+            .thread_name(move |i| format!("maxsim-node{}-{}", id, i))
+            .start_handler(move |_| pin_to(&cpus))
+            .build()
+            .map_err(|err| ScoreError::ThreadPool {
+                message: err.to_string(),
+            })?;
+        Ok(Self {
+            node: Some(node.id),
+            first,
+            docs,
+            pool: Some(Arc::new(pool)),
+        })
+    }
+
+    /// This partition's top k, ids shifted to store positions.
+    fn top_k(&self, query: &QueryEmbeddings, k: usize) -> Result<TopK, ScoreError> {
+        let local = match &self.pool {
+            Some(pool) => pool.install(|| top_k_heap(query, &self.docs, k, Reduction::default())),
+            None => top_k_heap(query, &self.docs, k, Reduction::default()),
+        }?;
+        let mut top = TopK::new(k);
+        for (i, score) in local.into_sorted_vec() {
+            top.push(self.first + i, score);
+        }
+        Ok(top)
+    }
+}
+
+/// Documents `ids` of `docs`, copied on the calling thread (which
+/// therefore first-touches the copy).
+fn copy_docs<D: Documents + ?Sized>(
+    docs: &D,
+    ids: Range<DocId>,
+) -> Result<DocCollection, ScoreError> {
+    let lengths: Vec<usize> = ids.clone().map(|i| docs.doc_len(i)).collect();
+    let mut data = AlignedVec::with_capacity(lengths.iter().sum::<usize>() * docs.dim());
+    for i in ids {
+        data.extend_from_slice(docs.doc(i));
+    }
+    DocCollection::from_lengths(data, &lengths, docs.dim())
+}
+
+/// `parts` contiguous runs of `docs` with about equal token counts.
+fn split_by_tokens<D: Documents + ?Sized>(docs: &D, parts: usize) -> Vec<Range<DocId>> {
+    let total: usize = (0..docs.len()).map(|i| docs.doc_len(i)).sum();
+    let mut runs = Vec::with_capacity(parts);
+    let (mut start, mut seen) = (0, 0);
+    for i in 0..docs.len() {
+        seen += docs.doc_len(i);
+        let target = total * (runs.len() + 1) / parts.max(1);
+        if seen >= target && runs.len() + 1 < parts {
+            runs.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    runs.push(start..docs.len());
+    runs.resize(parts.max(1), docs.len()..docs.len());
+    runs
+}
+
+/// Pin the calling thread to `cpus`; ignored if the kernel refuses, and
+/// off Linux.
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+fn pin_to(cpus: &[usize]) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+    }
+}
+
+/// Interleaves the calling thread's new pages over some nodes until
+/// dropped.
+struct InterleaveGuard;
+
+impl InterleaveGuard {
+    /// No-op off Linux.
+    #[cfg(not(target_os = "linux"))]
+    fn set(_: &[usize]) -> Self {
+        InterleaveGuard
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set(nodes: &[usize]) -> Self {
+        let bits = libc::c_ulong::BITS as usize;
+        let max_node = nodes.iter().max().map_or(0, |&n| n + 1);
+        let mut mask = vec![0 as libc::c_ulong; max_node.div_ceil(bits).max(1)];
+        for &node in nodes {
+            mask[node / bits] |= 1 << (node % bits);
+        }
+        let max_node = (mask.len() * bits) as libc::c_ulong;
+        unsafe {
+            libc::syscall(
+                libc::SYS_set_mempolicy,
+                MPOL_INTERLEAVE,
+                mask.as_ptr(),
+                max_node,
+            );
+        }
+        InterleaveGuard
+    }
+}
+
+impl Drop for InterleaveGuard {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::syscall(
+                libc::SYS_set_mempolicy,
+                MPOL_DEFAULT,
+                std::ptr::null::<libc::c_ulong>(),
+                0 as libc::c_ulong,
+            );
+        }
+    }
+}
+
+/// Nodes under `dir`, each with the CPUs of its `cpulist`.
+fn read_nodes(dir: &Path) -> Option<Vec<NumaNode>> {
+    let mut nodes = Vec::new();
+    for entry in std::fs::read_dir(dir).ok()? {
+        let entry = entry.ok()?;
+        let name = entry.file_name();
+        let Some(id) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|id| id.parse().ok())
+        else {
+            continue;
+        };
+        let cpulist = std::fs::read_to_string(entry.path().join("cpulist")).ok()?;
+        nodes.push(NumaNode {
+            id,
+            cpus: parse_cpulist(&cpulist)?,
+        });
+    }
+    Some(nodes)
+}
+
+/// CPUs of a kernel cpulist such as `0-3,8-11`.
+fn parse_cpulist(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((lo, hi)) => cpus.extend(lo.parse::<usize>().ok()?..=hi.parse().ok()?),
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
@@ -0,0 +1,426 @@
+//! bf16 document store pre-packed for the bf16 kernels.
+//!
+//! Packing a document into the kernels' operand layout costs a pass over
+//! it on every query; this store does it once at ingest. The file follows
+//! `crate::store` (dtype 1 = bf16) with two more header fields:
+//!
+//! ```text
+//! 0    magic     b"MAXSIMPK"
+//! 8    ...       version, dtype, dim, n_docs as in `crate::store`
+//! 32   layout    u32 (0 = flat rows, 1 = VNNI2; + 2 for the B operand)
+//! 36   arch      u32 (`ArchFamily` packed for)
+//! 40   table     n_docs × (start: u64, tokens: u64)
+//! ```
+//!
+//! Documents are the A operand by default (see `Operand`): a VNNI2
+//! document is then `[k_pad / 2][tokens][2]` with k zero-padded to even
+//! (see `crate::vnni`). As the B operand a VNNI2-layout document is
+//! `[tokens, k_pad]` rows. A flat one is `[tokens, dim]` rows as in
+//! `Bf16DocCollection` either way.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::aligned::{AlignedVec, AllocPolicy};
+use crate::collection::{Bf16DocCollection, DocId};
+use crate::store::{
+    read_extents, read_header, read_u32, write_docs, write_header, Mmap, StoreError, DTYPE_BF16,
+    HEADER_LEN,
+};
+use crate::vnni::{
+    pack_bf16_vnni2_a_rows, pad_bf16_vnni2_b_rows, unpack_bf16_vnni2_a_rows,
+    unpad_bf16_vnni2_b_rows, vnni2_k, VnniLayout,
+};
+
+const MAGIC: &[u8; 8] = b"MAXSIMPK";
+const TABLE_START: usize = HEADER_LEN + 8;
+
+/// CPU family a store was packed for, as far as the bf16 kernels care.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArchFamily {
+    /// No native bf16 dot products; documents are widened to f32.
+    Generic,
+    /// AVX512-BF16 (Cooper Lake).
+    Avx512Bf16,
+    /// AMX (Sapphire Rapids and later).
+    Amx,
+}
+
+impl ArchFamily {
+    /// Family of this CPU. Always `Generic` without `use-libxsmm`, which
+    /// has no bf16 kernels.
+    pub fn detect() -> Self {
+        #[cfg(libxsmm)]
+        {
+            use crate::libxsmm_bindings::CpuArch;
+            match CpuArch::detect() {
+                CpuArch::Avx512Spr => ArchFamily::Amx,
+                CpuArch::Avx512Cpx => ArchFamily::Avx512Bf16,
+                _ => ArchFamily::Generic,
+            }
+        }
+        #[cfg(not(libxsmm))]
+        ArchFamily::Generic
+    }
+
+    /// Layout the bf16 kernels take documents in on this family.
+    pub fn layout(self) -> VnniLayout {
+        match self {
+            ArchFamily::Generic => VnniLayout::Flat,
+            ArchFamily::Avx512Bf16 | ArchFamily::Amx => VnniLayout::Vnni2,
+        }
+    }
+
+    /// Document tokens the bf16 kernels block by on this family: AMX tiles
+    /// take 32 rows. The AMX path only scores packed documents of a
+    /// multiple of this many tokens, which `DocStoreBuilder` pads bf16
+    /// documents to on AMX CPUs.
+    pub fn token_multiple(self) -> usize {
+        match self {
+            ArchFamily::Amx => 32,
+            ArchFamily::Generic | ArchFamily::Avx512Bf16 => 1,
+        }
+    }
+
+    pub(crate) fn id(self) -> u32 {
+        match self {
+            ArchFamily::Generic => 0,
+            ArchFamily::Avx512Bf16 => 1,
+            ArchFamily::Amx => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(ArchFamily::Generic),
+            1 => Some(ArchFamily::Avx512Bf16),
+            2 => Some(ArchFamily::Amx),
+            _ => None,
+        }
+    }
+}
+
+/// Which operand of the bf16 similarity GEMM documents are packed as.
+///
+/// libxsmm vectorizes and blocks along the rows of A (its `m`), so the
+/// longer side belongs there: documents as A against short queries, the
+/// query as A against documents shorter than it. The scorer runs the
+/// kernel in the orientation the store was packed for, never transposing
+/// documents at query time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operand {
+    /// Documents are A (VNNI2-packed), the query is B; the similarity tile
+    /// is `[q_len, d_len]`.
+    #[default]
+    A,
+    /// Documents are B (rows), the query is VNNI2-packed as A; the
+    /// similarity tile is `[d_len, q_len]`.
+    B,
+}
+
+impl Operand {
+    /// Operand for queries of typically `typical_q_len` tokens against
+    /// documents of typically `typical_d_len`: whichever side is longer
+    /// takes A, documents on a tie.
+    pub fn auto(typical_q_len: usize, typical_d_len: usize) -> Self {
+        if typical_d_len >= typical_q_len {
+            Operand::A
+        } else {
+            Operand::B
+        }
+    }
+
+    /// `auto` with the mean document length of `docs`.
+    pub fn auto_for(typical_q_len: usize, docs: &Bf16DocCollection) -> Self {
+        let tokens: usize = (0..docs.len()).map(|i| docs.doc_len(i)).sum();
+        Self::auto(typical_q_len, tokens / docs.len().max(1))
+    }
+}
+
+/// What `PackedDocStore::open` does when the file was packed in a layout
+/// this CPU's kernels do not take.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OnMismatch {
+    /// Repack every document into memory for this CPU.
+    #[default]
+    Repack,
+    /// Fail with `StoreError::LayoutMismatch`.
+    Error,
+}
+
+/// Write `docs` to `path`, packed for `arch` as the `operand` of the
+/// similarity GEMM.
+pub fn write_packed_store(
+    docs: &Bf16DocCollection,
+    arch: ArchFamily,
+    operand: Operand,
+    path: &Path,
+) -> Result<(), StoreError> {
+    let (dim, layout) = (docs.dim(), arch.layout());
+    let mut out = BufWriter::new(File::create(path)?);
+    write_header(&mut out, MAGIC, DTYPE_BF16, dim, docs.len())?;
+    out.write_all(&layout_id(layout, operand).to_le_bytes())?;
+    out.write_all(&arch.id().to_le_bytes())?;
+
+    let lens: Vec<usize> = (0..docs.len()).map(|i| docs.doc_len(i)).collect();
+    let mut packed = AlignedVec::new();
+    let write_doc = |out: &mut BufWriter<File>, i: DocId| {
+        pack_doc(
+            docs.doc(i),
+            docs.doc_len(i),
+            dim,
+            (layout, operand),
+            &mut packed,
+        );
+        packed
+            .iter()
+            .try_for_each(|v| out.write_all(&v.to_le_bytes()))
+    };
+    let doc_bytes = |tokens| tokens * packed_k(layout, dim) * 2;
+    write_docs(&mut out, TABLE_START, &lens, doc_bytes, write_doc)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// bf16 documents stored in the layout the bf16 kernels consume, mapped
+/// from disk (or repacked into memory on a mismatched CPU). Scored with
+/// `Scorer::score_batch_packed`.
+#[derive(Debug)]
+pub struct PackedDocStore {
+    data: PackedData,
+    dim: usize,
+    layout: VnniLayout,
+    operand: Operand,
+    arch: ArchFamily,
+    /// (u16 index, token count) per document.
+    extents: Vec<(usize, usize)>,
+}
+
+#[derive(Debug)]
+enum PackedData {
+    Mapped(Mmap),
+    Owned(AlignedVec<u16>),
+}
+
+impl PackedDocStore {
+    /// Map the store at `path`. If it was packed in another layout than
+    /// `ArchFamily::detect()` needs, `on_mismatch` decides between
+    /// repacking and failing.
+    pub fn open(path: &Path, on_mismatch: OnMismatch) -> Result<Self, StoreError> {
+        let map = Mmap::open(path)?;
+        let bytes = map.bytes();
+        let (dim, n_docs) = read_header(bytes, MAGIC, DTYPE_BF16)?;
+        if bytes.len() < TABLE_START {
+            return Err(StoreError::Corrupt {
+                reason: "file is shorter than the header",
+            });
+        }
+        let (layout, operand, arch) = read_packing(bytes, HEADER_LEN)?;
+        let k = packed_k(layout, dim);
+        let doc_bytes = |tokens: usize| tokens.checked_mul(k)?.checked_mul(2);
+        let extents = read_extents(bytes, TABLE_START, n_docs, doc_bytes)?
+            .into_iter()
+            .map(|(start, tokens)| (start / 2, tokens))
+            .collect();
+
+        Self {
+            data: PackedData::Mapped(map),
+            dim,
+            layout,
+            operand,
+            arch,
+            extents,
+        }
+        .for_cpu(on_mismatch)
+    }
+
+    /// Adopt documents packed in `layout` as `operand` for `arch`, held in
+    /// memory;
+    /// `extents` is (u16 index, token count) per document. Mismatches with
+    /// this CPU are handled as in `open`.
+    pub(crate) fn from_owned(
+        data: AlignedVec<u16>,
+        dim: usize,
+        (layout, operand, arch): (VnniLayout, Operand, ArchFamily),
+        extents: Vec<(usize, usize)>,
+        on_mismatch: OnMismatch,
+    ) -> Result<Self, StoreError> {
+        Self {
+            data: PackedData::Owned(data),
+            dim,
+            layout,
+            operand,
+            arch,
+            extents,
+        }
+        .for_cpu(on_mismatch)
+    }
+
+    /// `self` if this CPU's kernels take its layout, else repacked or an
+    /// error per `on_mismatch`.
+    fn for_cpu(self, on_mismatch: OnMismatch) -> Result<Self, StoreError> {
+        let cpu = ArchFamily::detect();
+        if cpu.layout() == self.layout {
+            return Ok(self);
+        }
+        match on_mismatch {
+            OnMismatch::Repack => Ok(self.repacked(cpu)),
+            OnMismatch::Error => Err(StoreError::LayoutMismatch {
+                stored: self.layout,
+                cpu: cpu.layout(),
+            }),
+        }
+    }
+
+    /// Every document unpacked to rows and packed again for `arch`, as the
+    /// same operand, in memory.
+    fn repacked(&self, arch: ArchFamily) -> Self {
+        let (dim, layout, operand) = (self.dim, arch.layout(), self.operand);
+        let policy = match &self.data {
+            PackedData::Owned(data) => data.policy(),
+            PackedData::Mapped(_) => AllocPolicy::Heap,
+        };
+        let mut data = AlignedVec::new_in(policy);
+        let mut extents = Vec::with_capacity(self.len());
+        let (mut rows, mut packed) = (AlignedVec::new(), AlignedVec::new());
+        for i in 0..self.len() {
+            let tokens = self.doc_len(i);
+            let doc = match (self.layout, operand) {
+                (VnniLayout::Vnni2, Operand::A) => {
+                    unpack_bf16_vnni2_a_rows(self.doc(i), tokens, dim, &mut rows);
+                    &rows[..]
+                }
+                (VnniLayout::Vnni2, Operand::B) => {
+                    unpad_bf16_vnni2_b_rows(self.doc(i), tokens, dim, &mut rows);
+                    &rows[..]
+                }
+                _ => self.doc(i),
+            };
+            pack_doc(doc, tokens, dim, (layout, operand), &mut packed);
+            extents.push((data.len(), tokens));
+            data.extend_from_slice(&packed);
+        }
+        Self {
+            data: PackedData::Owned(data),
+            dim,
+            layout,
+            operand,
+            arch,
+            extents,
+        }
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.extents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extents.is_empty()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Layout the documents are held in (after any repacking).
+    pub fn layout(&self) -> VnniLayout {
+        self.layout
+    }
+
+    /// GEMM operand the documents are packed as.
+    pub fn operand(&self) -> Operand {
+        self.operand
+    }
+
+    /// Family the documents are packed for (after any repacking).
+    pub fn arch(&self) -> ArchFamily {
+        self.arch
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        self.extents[i].1
+    }
+
+    /// Packed bf16 document `i`: `doc_len(i)` rows of `dim` values when
+    /// flat; when VNNI2, `[k_pad / 2][doc_len(i)][2]` as the A operand and
+    /// `doc_len(i)` rows of `k_pad` values as the B operand.
+    pub fn doc(&self, i: DocId) -> &[u16] {
+        let (start, tokens) = self.extents[i];
+        &self.words()[start..start + tokens * packed_k(self.layout, self.dim)]
+    }
+
+    fn words(&self) -> &[u16] {
+        match &self.data {
+            PackedData::Mapped(map) => {
+                let bytes = map.bytes();
+                // The mapping is page-aligned
+                unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const u16, bytes.len() / 2) }
+            }
+            PackedData::Owned(data) => data,
+        }
+    }
+}
+
+/// Values per token in `layout`, for either operand.
+pub(crate) fn packed_k(layout: VnniLayout, dim: usize) -> usize {
+    match layout {
+        VnniLayout::Vnni2 => vnni2_k(dim),
+        _ => dim,
+    }
+}
+
+pub(crate) fn layout_id(layout: VnniLayout, operand: Operand) -> u32 {
+    let layout = match layout {
+        VnniLayout::Vnni2 => 1,
+        _ => 0,
+    };
+    match operand {
+        Operand::A => layout,
+        Operand::B => layout + 2,
+    }
+}
+
+/// Layout, operand and arch ids written by `layout_id` and
+/// `ArchFamily::id` at `at` and `at + 4`.
+pub(crate) fn read_packing(
+    bytes: &[u8],
+    at: usize,
+) -> Result<(VnniLayout, Operand, ArchFamily), StoreError> {
+    let (layout, operand) = match read_u32(bytes, at) {
+        0 => (VnniLayout::Flat, Operand::A),
+        1 => (VnniLayout::Vnni2, Operand::A),
+        2 => (VnniLayout::Flat, Operand::B),
+        3 => (VnniLayout::Vnni2, Operand::B),
+        _ => {
+            return Err(StoreError::Corrupt {
+                reason: "unknown packing layout",
+            })
+        }
+    };
+    let arch = ArchFamily::from_id(read_u32(bytes, at + 4)).ok_or(StoreError::Corrupt {
+        reason: "unknown arch family",
+    })?;
+    Ok((layout, operand, arch))
+}
+
+/// `tokens` bf16 rows packed into `layout` as `operand` in `dst`.
+pub(crate) fn pack_doc(
+    rows: &[u16],
+    tokens: usize,
+    dim: usize,
+    (layout, operand): (VnniLayout, Operand),
+    dst: &mut AlignedVec<u16>,
+) {
+    match (layout, operand) {
+        (VnniLayout::Vnni2, Operand::A) => pack_bf16_vnni2_a_rows(rows, tokens, dim, dst),
+        (VnniLayout::Vnni2, Operand::B) => pad_bf16_vnni2_b_rows(rows, tokens, dim, dst),
+        _ => {
+            dst.clear();
+            dst.extend_from_slice(rows);
+        }
+    }
+}
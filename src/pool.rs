@@ -0,0 +1,125 @@
+//! Worker threads owned by a scorer.
+//!
+//! A `Scorer` with `num_threads` set scores on a rayon pool of its own,
+//! built once and kept, threads and all, until the last clone of the
+//! scorer is dropped. That drop then joins every worker, so a scorer's
+//! threads never outlive it, and scorers with different pools share no
+//! threads.
+
+use std::mem::ManuallyDrop;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::score::ScoreError;
+
+/// Physical cores this process may run on: distinct (package, core) pairs
+/// under `/sys/devices/system/cpu` on Linux, capped by
+/// `available_parallelism`; `available_parallelism` alone elsewhere.
+pub fn physical_cores() -> usize {
+    let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+    #[cfg(target_os = "linux")]
+    if let Some(cores) = linux_physical_cores() {
+        return cores.min(available);
+    }
+    available
+}
+
+#[cfg(target_os = "linux")]
+fn linux_physical_cores() -> Option<usize> {
+    let mut cores = std::collections::HashSet::new();
+    for entry in std::fs::read_dir("/sys/devices/system/cpu").ok()? {
+        let entry = entry.ok()?;
+        let name = entry.file_name();
+        let is_cpu = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("cpu"))
+            .is_some_and(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()));
+        if !is_cpu {
+            continue;
+        }
+        let topology = entry.path().join("topology");
+        let read = |file: &str| -> Option<u64> {
+            std::fs::read_to_string(topology.join(file))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()
+        };
+        // Offline CPUs have no topology
+        if let (Some(package), Some(core)) = (read("physical_package_id"), read("core_id")) {
+            cores.insert((package, core));
+        }
+    }
+    (!cores.is_empty()).then_some(cores.len())
+}
+
+/// A rayon pool whose drop joins its workers.
+#[derive(Debug)]
+pub(crate) struct WorkerPool {
+    pool: ManuallyDrop<ThreadPool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// `threads` workers (0 for rayon's default), named `maxsim-worker-N`
+    /// with `names`. Fails with `ScoreError::ThreadPool` if they cannot be
+    /// spawned.
+    pub(crate) fn new(threads: usize, names: bool) -> Result<Self, ScoreError> {
+        let spawned = Arc::new(Mutex::new(Vec::new()));
+        let handles = Arc::clone(&spawned);
+        let mut builder =
+            ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .spawn_handler(move |thread| {
+                    let mut builder = std::thread::Builder::new();
+                    if let Some(name) = thread.name() {
+                        builder = builder.name(name.to_owned());
+                    }
+                    if let Some(size) = thread.stack_size() {
+                        builder = builder.stack_size(size);
+                    }
+                    handles
+                        .lock()
+                        .unwrap()
+                        .push(builder.spawn(|| thread.run())?);
+                    Ok(())
+                });
+        if names {
+            builder = builder.thread_name(|i| format!("maxsim-worker-{}", i));
+        }
+        let pool = builder.build().map_err(|err| ScoreError::ThreadPool {
+            message: err.to_string(),
+        })?;
+        let workers = std::mem::take(&mut *spawned.lock().unwrap());
+        Ok(Self {
+            pool: ManuallyDrop::new(pool),
+            workers,
+        })
+    }
+
+    pub(crate) fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(f)
+    }
+
+    pub(crate) fn threads(&self) -> usize {
+        self.workers.len()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        let on_worker = self.pool.current_thread_index().is_some();
+        // Dropping the pool tells the workers to exit once idle
+        unsafe { ManuallyDrop::drop(&mut self.pool) };
+        // A worker cannot join itself; the others still exit on their own
+        if on_worker {
+            return;
+        }
+        for worker in self.workers.drain(..) {
+            // A worker that panicked has exited all the same
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,664 @@
+//! Python classes of the `maxsim_cpu` module (feature `python`).
+//!
+//! `DocBatch` wraps documents already in a NumPy buffer, read in place.
+//! `DocStore` is a mutable id-keyed store (a `MaxSimIndex`) that can be
+//! saved and loaded, and `Scorer` scores queries against either, or
+//! against plain arrays. Every scoring call releases the GIL. Arrays of
+//! the wrong dtype or shape raise `ValueError` naming what was passed.
+//!
+//! `DocBatch` reads its array in place:
+//! Document embeddings that already sit in a NumPy array (tens of GB for a
+//! large corpus) are wrapped, not copied: the class keeps a reference to
+//! the array, so its buffer lives at least as long as the Rust side reads
+//! it, and holding that reference makes NumPy refuse in-place resizes.
+//! Scoring reads the rows where they are with the GIL released. Writing to
+//! the array from Python while a call is running gives unspecified scores.
+//!
+//! Accepted layouts:
+//! - `[total_tokens, dim]` with token `offsets` (`n_docs + 1` entries),
+//!   or without, as one document;
+//! - `[n_docs, max_tokens, dim]` padded, with optional per-document
+//!   `lengths`; rows past a document's length are never read.
+//!
+//! The array must be C-contiguous, native-endian float32 or float16, and
+//! aligned to its element size; anything else raises `ValueError` instead
+//! of being copied behind the caller's back. Queries are small and are
+//! copied, from any layout.
+
+use numpy::ndarray::Array2;
+use numpy::{
+    Element, IntoPyArray, PyArray1, PyArray2, PyArrayDyn, PyReadonlyArray1, PyReadonlyArray2,
+};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::aligned::AlignedVec;
+use crate::collection::{DocBatch, DocId, Documents, QueryBatch, QueryEmbeddings};
+use crate::index::MaxSimIndex;
+use crate::rerank::OnMissing;
+use crate::score::{maxsim_score, maxsim_score_batch, maxsim_score_matrix, ScoreError};
+use crate::scorer::{F16Docs, Scorer, ScorerConfig};
+use crate::store::StoreError;
+use crate::topk::SearchHit;
+
+/// Register the classes of this module.
+pub(crate) fn register(m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyDocBatch>()?;
+    m.add_class::<PyDocStore>()?;
+    m.add_class::<PyScorer>()
+}
+
+/// Documents read in place from a NumPy array.
+#[pyclass(name = "DocBatch", module = "maxsim_cpu")]
+struct PyDocBatch {
+    /// The array `docs` points into (for float16, a `uint16` view of it).
+    _owner: PyObject,
+    docs: BufferDocs,
+}
+
+#[pymethods]
+impl PyDocBatch {
+    #[new]
+    #[pyo3(signature = (embeddings, offsets = None, lengths = None))]
+    fn new(
+        embeddings: &PyAny,
+        offsets: Option<PyReadonlyArray1<i64>>,
+        lengths: Option<PyReadonlyArray1<i64>>,
+    ) -> PyResult<Self> {
+        let offsets = offsets.map(|o| o.as_array().to_vec());
+        let lengths = lengths.map(|l| l.as_array().to_vec());
+        if let Ok(array) = embeddings.extract::<&PyArrayDyn<f32>>() {
+            let rows = BufferRows::new(array, offsets.as_deref(), lengths.as_deref())?;
+            return Ok(Self {
+                _owner: embeddings.into(),
+                docs: BufferDocs::F32(rows),
+            });
+        }
+        let dtype = embeddings
+            .getattr("dtype")
+            .and_then(|dtype| dtype.str().map(|name| name.to_string()))
+            .map_err(|_| PyValueError::new_err("embeddings must be a numpy.ndarray"))?;
+        if dtype != "float16" {
+            return Err(PyValueError::new_err(format!(
+                "embeddings have dtype {}, expected float32 or float16",
+                dtype
+            )));
+        }
+        // Same buffer, reinterpreted as the half bit patterns
+        let view = embeddings.call_method1("view", ("uint16",))?;
+        let array = view.extract::<&PyArrayDyn<u16>>()?;
+        let rows = BufferRows::new(array, offsets.as_deref(), lengths.as_deref())?;
+        Ok(Self {
+            _owner: view.into(),
+            docs: BufferDocs::F16(rows),
+        })
+    }
+
+    fn __len__(&self) -> usize {
+        self.docs.len()
+    }
+
+    #[getter]
+    fn dim(&self) -> usize {
+        self.docs.dim()
+    }
+
+    #[getter]
+    fn dtype(&self) -> &'static str {
+        match self.docs {
+            BufferDocs::F32(_) => "float32",
+            BufferDocs::F16(_) => "float16",
+        }
+    }
+
+    /// Token count of document `i`.
+    fn doc_len(&self, i: DocId) -> PyResult<usize> {
+        if i >= self.docs.len() {
+            return Err(PyValueError::new_err(format!(
+                "document {} out of range for {} documents",
+                i,
+                self.docs.len()
+            )));
+        }
+        Ok(self.docs.doc_len(i))
+    }
+
+    /// MaxSim score of a `[q_len, dim]` float32 query against every
+    /// document, in batch order.
+    fn score<'py>(
+        &self,
+        py: Python<'py>,
+        query: PyReadonlyArray2<f32>,
+    ) -> PyResult<&'py PyArray1<f32>> {
+        let query = query_embeddings(&query)?;
+        let docs = &self.docs;
+        let scores = py
+            .allow_threads(|| docs.score(&query))
+            .map_err(value_error)?;
+        Ok(PyArray1::from_vec(py, scores))
+    }
+
+    /// MaxSim score of every query against every document, as an
+    /// `[n_queries, n_docs]` array.
+    fn score_matrix<'py>(
+        &self,
+        py: Python<'py>,
+        queries: Vec<PyReadonlyArray2<f32>>,
+    ) -> PyResult<&'py PyArray2<f32>> {
+        let queries = QueryBatch::new(
+            queries
+                .iter()
+                .map(query_embeddings)
+                .collect::<PyResult<_>>()?,
+        );
+        let docs = &self.docs;
+        let scores = py
+            .allow_threads(|| docs.score_matrix(&queries))
+            .map_err(value_error)?;
+        let scores = Array2::from_shape_vec((queries.len(), docs.len()), scores)
+            .expect("score matrix is n_queries * n_docs");
+        Ok(scores.into_pyarray(py))
+    }
+}
+
+/// Documents keyed by `int` ids, added and removed at will.
+#[pyclass(name = "DocStore", module = "maxsim_cpu")]
+struct PyDocStore {
+    index: MaxSimIndex,
+}
+
+#[pymethods]
+impl PyDocStore {
+    /// Empty store of `dim`-dimensional token embeddings.
+    #[new]
+    fn new(dim: usize) -> Self {
+        Self {
+            index: MaxSimIndex::new(dim),
+        }
+    }
+
+    /// Store holding the float32 documents of `embeddings`, laid out as
+    /// for `DocBatch`, under `ids` (one per document; positions without).
+    #[staticmethod]
+    #[pyo3(signature = (embeddings, offsets = None, lengths = None, ids = None))]
+    fn from_numpy(
+        py: Python<'_>,
+        embeddings: &PyAny,
+        offsets: Option<PyReadonlyArray1<i64>>,
+        lengths: Option<PyReadonlyArray1<i64>>,
+        ids: Option<PyReadonlyArray1<u64>>,
+    ) -> PyResult<Self> {
+        let array = embeddings.extract::<&PyArrayDyn<f32>>().map_err(|_| {
+            PyValueError::new_err(format!(
+                "embeddings must be a float32 numpy array, got {}",
+                describe(embeddings)
+            ))
+        })?;
+        let offsets = offsets.map(|o| o.as_array().to_vec());
+        let lengths = lengths.map(|l| l.as_array().to_vec());
+        let rows = BufferRows::new(array, offsets.as_deref(), lengths.as_deref())?;
+        let n_docs = rows.extents.len();
+        let ids = match ids {
+            Some(ids) if ids.len() != n_docs => {
+                return Err(PyValueError::new_err(format!(
+                    "ids has {} entries for {} documents",
+                    ids.len(),
+                    n_docs
+                )))
+            }
+            Some(ids) => ids.as_array().to_vec(),
+            None => (0..n_docs as u64).collect(),
+        };
+        let index = MaxSimIndex::new(rows.dim);
+        py.allow_threads(|| {
+            ids.iter()
+                .enumerate()
+                .try_for_each(|(i, &id)| index.add_doc(id, rows.doc(i)))
+        })
+        .map_err(value_error)?;
+        Ok(Self { index })
+    }
+
+    /// Read a store written by `save`.
+    #[staticmethod]
+    fn load(py: Python<'_>, path: &str) -> PyResult<Self> {
+        let index = py
+            .allow_threads(|| MaxSimIndex::load(path.as_ref()))
+            .map_err(io_error)?;
+        Ok(Self { index })
+    }
+
+    /// Write the live documents, ids and metadata to `path`.
+    fn save(&self, py: Python<'_>, path: &str) -> PyResult<()> {
+        let index = &self.index;
+        py.allow_threads(|| index.save(path.as_ref()))
+            .map_err(io_error)
+    }
+
+    /// Add a `[tokens, dim]` float32 document under `id`, with optional
+    /// `bytes` metadata.
+    #[pyo3(signature = (id, embeddings, metadata = None))]
+    fn add(&self, id: u64, embeddings: &PyAny, metadata: Option<Vec<u8>>) -> PyResult<()> {
+        let (data, _, dim) = matrix(embeddings, "embeddings")?;
+        check_dims("embeddings", dim, self.index.dim())?;
+        match metadata {
+            Some(metadata) => self.index.add_doc_with_metadata(id, &data, metadata),
+            None => self.index.add_doc(id, &data),
+        }
+        .map_err(value_error)
+    }
+
+    /// Remove document `id`; returns whether it was present.
+    fn remove(&self, id: u64) -> bool {
+        self.index.remove_doc(id)
+    }
+
+    /// Drop removed documents for good; returns how many there were.
+    fn compact(&self, py: Python<'_>) -> PyResult<usize> {
+        let index = &self.index;
+        let stats = py.allow_threads(|| index.compact()).map_err(value_error)?;
+        Ok(stats.docs_removed)
+    }
+
+    fn __len__(&self) -> usize {
+        self.index.len()
+    }
+
+    #[getter]
+    fn dim(&self) -> usize {
+        self.index.dim()
+    }
+}
+
+/// MaxSim scoring, on a pool of `num_threads` threads or the global one.
+#[pyclass(name = "Scorer", module = "maxsim_cpu")]
+struct PyScorer {
+    scorer: Scorer,
+}
+
+#[pymethods]
+impl PyScorer {
+    #[new]
+    #[pyo3(signature = (num_threads = None))]
+    fn new(num_threads: Option<usize>) -> PyResult<Self> {
+        let config = ScorerConfig {
+            num_threads,
+            ..ScorerConfig::default()
+        };
+        let scorer = Scorer::new(config).map_err(value_error)?;
+        Ok(Self { scorer })
+    }
+
+    /// MaxSim score of a `[q_len, dim]` query against one `[d_len, dim]`
+    /// document.
+    fn score(&self, py: Python<'_>, query: &PyAny, doc: &PyAny) -> PyResult<f32> {
+        let (query, q_len, dim) = matrix(query, "query")?;
+        let (doc, d_len, doc_dim) = matrix(doc, "doc")?;
+        check_dims("query", dim, doc_dim)?;
+        py.allow_threads(|| maxsim_score(&query, q_len, &doc, d_len, dim))
+            .map_err(value_error)
+    }
+
+    /// MaxSim score of `query` against every document of `docs` (a
+    /// `DocBatch` or a list of `[d_len, dim]` arrays), in order.
+    fn score_batch<'py>(
+        &self,
+        py: Python<'py>,
+        query: &PyAny,
+        docs: &PyAny,
+    ) -> PyResult<&'py PyArray1<f32>> {
+        let query = query_from(query)?;
+        let scorer = &self.scorer;
+        let scores = if let Ok(batch) = docs.extract::<PyRef<PyDocBatch>>() {
+            check_dims("query", query.dim(), batch.docs.dim())?;
+            let docs = &batch.docs;
+            py.allow_threads(|| match docs {
+                BufferDocs::F32(rows) => scorer.score_batch(&query, rows),
+                BufferDocs::F16(rows) => scorer.f16_batch(&query, rows),
+            })
+        } else {
+            let arrays: Vec<&PyAny> = docs.extract().map_err(|_| {
+                PyValueError::new_err(format!(
+                    "docs must be a DocBatch or a list of arrays, got {}",
+                    describe(docs)
+                ))
+            })?;
+            let mut batch = DocBatch::new(query.dim());
+            for (i, doc) in arrays.into_iter().enumerate() {
+                let (doc, _, dim) = matrix(doc, "doc")?;
+                if dim != query.dim() {
+                    return Err(PyValueError::new_err(format!(
+                        "doc {} has dim {}, query has dim {}",
+                        i,
+                        dim,
+                        query.dim()
+                    )));
+                }
+                batch.push(&doc).map_err(value_error)?;
+            }
+            py.allow_threads(|| scorer.score_batch(&query, &batch))
+        }
+        .map_err(value_error)?;
+        Ok(PyArray1::from_vec(py, scores))
+    }
+
+    /// The `k` best documents of `store` for `query`, best first, as
+    /// `(ids, scores)` arrays.
+    fn top_k<'py>(
+        &self,
+        py: Python<'py>,
+        query: &PyAny,
+        store: PyRef<PyDocStore>,
+        k: usize,
+    ) -> PyResult<(&'py PyArray1<u64>, &'py PyArray1<f32>)> {
+        let query = query_from(query)?;
+        check_dims("query", query.dim(), store.index.dim())?;
+        let (scorer, index) = (&self.scorer, &store.index);
+        let hits = py
+            .allow_threads(|| scorer.install(|| index.search(&query, k)))
+            .map_err(value_error)?;
+        Ok(hit_arrays(py, hits))
+    }
+
+    /// The `k` best of the documents of `store` whose ids are in
+    /// `candidate_ids` (all of them by default), best first, as
+    /// `(ids, scores)` arrays. Ids not in the store are skipped.
+    #[pyo3(signature = (query, store, candidate_ids, k = None))]
+    fn rerank<'py>(
+        &self,
+        py: Python<'py>,
+        query: &PyAny,
+        store: PyRef<PyDocStore>,
+        candidate_ids: Vec<u64>,
+        k: Option<usize>,
+    ) -> PyResult<(&'py PyArray1<u64>, &'py PyArray1<f32>)> {
+        let query = query_from(query)?;
+        check_dims("query", query.dim(), store.index.dim())?;
+        let k = k.unwrap_or(candidate_ids.len());
+        let (scorer, index) = (&self.scorer, &store.index);
+        let hits = py
+            .allow_threads(|| {
+                scorer.install(|| index.rerank(&query, &candidate_ids, k, OnMissing::Skip))
+            })
+            .map_err(value_error)?;
+        Ok(hit_arrays(py, hits))
+    }
+}
+
+/// The wrapped documents, by element type.
+enum BufferDocs {
+    F32(BufferRows<f32>),
+    /// f16 bit patterns.
+    F16(BufferRows<u16>),
+}
+
+impl BufferDocs {
+    fn len(&self) -> usize {
+        match self {
+            BufferDocs::F32(rows) => rows.extents.len(),
+            BufferDocs::F16(rows) => rows.extents.len(),
+        }
+    }
+
+    fn dim(&self) -> usize {
+        match self {
+            BufferDocs::F32(rows) => rows.dim,
+            BufferDocs::F16(rows) => rows.dim,
+        }
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        match self {
+            BufferDocs::F32(rows) => rows.extents[i].1,
+            BufferDocs::F16(rows) => rows.extents[i].1,
+        }
+    }
+
+    fn score(&self, query: &QueryEmbeddings) -> Result<Vec<f32>, ScoreError> {
+        match self {
+            BufferDocs::F32(rows) => maxsim_score_batch(query, rows),
+            BufferDocs::F16(rows) => Scorer::new(ScorerConfig::default())?.f16_batch(query, rows),
+        }
+    }
+
+    fn score_matrix(&self, queries: &QueryBatch) -> Result<Vec<f32>, ScoreError> {
+        match self {
+            BufferDocs::F32(rows) => maxsim_score_matrix(queries, rows),
+            BufferDocs::F16(rows) => {
+                let scorer = Scorer::new(ScorerConfig::default())?;
+                let mut scores = Vec::with_capacity(queries.len() * rows.extents.len());
+                for query in queries.queries() {
+                    scores.extend(scorer.f16_batch(query, rows)?);
+                }
+                Ok(scores)
+            }
+        }
+    }
+}
+
+/// `[tokens, dim]` documents at row extents of a borrowed buffer.
+struct BufferRows<T> {
+    ptr: *const T,
+    len: usize,
+    dim: usize,
+    /// (first row, token count) per document.
+    extents: Vec<(usize, usize)>,
+}
+
+// The buffer is only read, and the owning array outlives `BufferRows`
+// (both live in the same `PyDocBatch`)
+unsafe impl<T: Sync> Send for BufferRows<T> {}
+unsafe impl<T: Sync> Sync for BufferRows<T> {}
+
+impl<T: Element> BufferRows<T> {
+    /// Validate `array` and lay documents over it per `offsets` (2-D) or
+    /// `lengths` (3-D).
+    fn new(
+        array: &PyArrayDyn<T>,
+        offsets: Option<&[i64]>,
+        lengths: Option<&[i64]>,
+    ) -> PyResult<Self> {
+        if !array.is_c_contiguous() {
+            return Err(PyValueError::new_err(
+                "embeddings must be C-contiguous (see numpy.ascontiguousarray)",
+            ));
+        }
+        let ptr = array.data() as *const T;
+        if !(ptr as usize).is_multiple_of(align_of::<T>()) {
+            return Err(PyValueError::new_err(
+                "embeddings buffer is not aligned to its element size",
+            ));
+        }
+        let (dim, extents) = match (array.shape(), offsets, lengths) {
+            (&[rows, dim], offsets, None) => (dim, offset_extents(offsets, rows)?),
+            (&[n_docs, max_tokens, dim], None, lengths) => {
+                (dim, padded_extents(lengths, n_docs, max_tokens)?)
+            }
+            _ => {
+                return Err(PyValueError::new_err(
+                    "embeddings must be [total_tokens, dim] with optional offsets, \
+                     or [n_docs, max_tokens, dim] with optional lengths",
+                ))
+            }
+        };
+        Ok(Self {
+            ptr,
+            len: array.len(),
+            dim,
+            extents,
+        })
+    }
+}
+
+impl<T> BufferRows<T> {
+    fn doc(&self, i: DocId) -> &[T] {
+        let (row, tokens) = self.extents[i];
+        if tokens * self.dim == 0 {
+            return &[];
+        }
+        // In bounds: extents were checked against the shape, and `len`
+        // values live at `ptr` while the owning array does
+        let values = unsafe { std::slice::from_raw_parts(self.ptr, self.len) };
+        &values[row * self.dim..(row + tokens) * self.dim]
+    }
+}
+
+impl Documents for BufferRows<f32> {
+    fn len(&self) -> usize {
+        self.extents.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        self.extents[i].1
+    }
+
+    fn doc(&self, i: DocId) -> &[f32] {
+        BufferRows::doc(self, i)
+    }
+}
+
+impl F16Docs for BufferRows<u16> {
+    fn len(&self) -> usize {
+        self.extents.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        self.extents[i].1
+    }
+
+    fn doc(&self, i: DocId) -> &[u16] {
+        BufferRows::doc(self, i)
+    }
+}
+
+/// Extents from token `offsets` over `rows` rows; one document without.
+fn offset_extents(offsets: Option<&[i64]>, rows: usize) -> PyResult<Vec<(usize, usize)>> {
+    let Some(offsets) = offsets else {
+        return Ok(vec![(0, rows)]);
+    };
+    if offsets.first() != Some(&0) || offsets.last().map(|&end| end as usize) != Some(rows) {
+        return Err(PyValueError::new_err(format!(
+            "offsets must start at 0 and end at the {} embedding rows",
+            rows
+        )));
+    }
+    if let Some(doc) = offsets.windows(2).position(|w| w[1] < w[0]) {
+        return Err(PyValueError::new_err(format!(
+            "offsets decrease at document {}",
+            doc
+        )));
+    }
+    Ok(offsets
+        .windows(2)
+        .map(|w| (w[0] as usize, (w[1] - w[0]) as usize))
+        .collect())
+}
+
+/// Extents of `n_docs` padded documents of `max_tokens` rows each,
+/// truncated to `lengths` when given.
+fn padded_extents(
+    lengths: Option<&[i64]>,
+    n_docs: usize,
+    max_tokens: usize,
+) -> PyResult<Vec<(usize, usize)>> {
+    let Some(lengths) = lengths else {
+        return Ok((0..n_docs).map(|i| (i * max_tokens, max_tokens)).collect());
+    };
+    if lengths.len() != n_docs {
+        return Err(PyValueError::new_err(format!(
+            "lengths has {} entries for {} documents",
+            lengths.len(),
+            n_docs
+        )));
+    }
+    lengths
+        .iter()
+        .enumerate()
+        .map(|(i, &len)| match usize::try_from(len) {
+            Ok(len) if len <= max_tokens => Ok((i * max_tokens, len)),
+            _ => Err(PyValueError::new_err(format!(
+                "length {} of document {} is outside 0..={}",
+                len, i, max_tokens
+            ))),
+        })
+        .collect()
+}
+
+/// A `[q_len, dim]` query array copied into aligned storage.
+fn query_embeddings(query: &PyReadonlyArray2<f32>) -> PyResult<QueryEmbeddings> {
+    let view = query.as_array();
+    let (len, dim) = view.dim();
+    let mut data = AlignedVec::with_capacity(view.len());
+    data.extend(view.iter().copied());
+    QueryEmbeddings::new(data, len, dim).map_err(value_error)
+}
+
+/// A 2-D float32 array of any layout copied row-major: (values, rows,
+/// dim). Anything else is a `ValueError` naming `name` and what it got.
+fn matrix(obj: &PyAny, name: &str) -> PyResult<(AlignedVec<f32>, usize, usize)> {
+    let array = obj
+        .extract::<&PyArrayDyn<f32>>()
+        .ok()
+        .filter(|array| array.ndim() == 2)
+        .ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "{} must be a 2-D [tokens, dim] float32 array, got {}",
+                name,
+                describe(obj)
+            ))
+        })?;
+    let view = array.readonly();
+    let view = view.as_array();
+    let (rows, dim) = (view.shape()[0], view.shape()[1]);
+    let mut data = AlignedVec::with_capacity(view.len());
+    data.extend(view.iter().copied());
+    Ok((data, rows, dim))
+}
+
+/// A query from any 2-D float32 array.
+fn query_from(obj: &PyAny) -> PyResult<QueryEmbeddings> {
+    let (data, len, dim) = matrix(obj, "query")?;
+    QueryEmbeddings::new(data, len, dim).map_err(value_error)
+}
+
+/// `ValueError` unless `name`'s dim `actual` is `expected`.
+fn check_dims(name: &str, actual: usize, expected: usize) -> PyResult<()> {
+    if actual != expected {
+        return Err(PyValueError::new_err(format!(
+            "{} has dim {}, expected {}",
+            name, actual, expected
+        )));
+    }
+    Ok(())
+}
+
+/// dtype and shape of an array, else the type name.
+fn describe(obj: &PyAny) -> String {
+    match (obj.getattr("dtype"), obj.getattr("shape")) {
+        (Ok(dtype), Ok(shape)) => format!("dtype {} with shape {}", dtype, shape),
+        _ => format!("a {}", obj.get_type().name().unwrap_or("object")),
+    }
+}
+
+/// `(ids, scores)` arrays of `hits`, in order.
+fn hit_arrays(py: Python<'_>, hits: Vec<SearchHit>) -> (&PyArray1<u64>, &PyArray1<f32>) {
+    let ids = hits.iter().map(|hit| hit.id).collect();
+    let scores = hits.iter().map(|hit| hit.score).collect();
+    (PyArray1::from_vec(py, ids), PyArray1::from_vec(py, scores))
+}
+
+fn value_error(err: ScoreError) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+fn io_error(err: StoreError) -> PyErr {
+    PyIOError::new_err(err.to_string())
+}
@@ -0,0 +1,85 @@
+//! Symmetric int8 quantization of embeddings.
+//!
+//! A row is stored as `round(x / scale)` clamped to ±127, with
+//! `scale = max|x| / 127`, so `q * scale` reconstructs it to within half a
+//! step. All-zero rows get scale 0 and quantize to zeros.
+//!
+//! VNNI dot products (`VPDPBUSD`) want one operand unsigned, so queries are
+//! quantized to u8 with a zero point of 128: `u = q + 128`. Then
+//! `Σ u·d = Σ q·d + 128·Σ d`, and subtracting `128·Σ d` per document token
+//! recovers the signed product exactly.
+
+/// Offset added to signed query values to make them u8.
+pub const U8_ZERO_POINT: i32 = 128;
+
+/// Which rows share a scale.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ScaleGranularity {
+    /// One scale per token (best accuracy).
+    #[default]
+    PerToken,
+    /// One scale per document (4 bytes per doc instead of per token).
+    PerDocument,
+}
+
+/// Max-abs scale of `values`: `max|x| / 127`, 0 for an all-zero slice.
+pub fn max_abs_scale(values: &[f32]) -> f32 {
+    values.iter().fold(0.0f32, |m, x| m.max(x.abs())) / 127.0
+}
+
+/// Quantize `src` with `scale` into `dst`. Panics if the lengths differ.
+pub fn quantize_i8(src: &[f32], scale: f32, dst: &mut [i8]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "quantize: source and destination lengths differ"
+    );
+    let inv = if scale > 0.0 { 1.0 / scale } else { 0.0 };
+    for (d, &x) in dst.iter_mut().zip(src) {
+        *d = (x * inv).round().clamp(-127.0, 127.0) as i8;
+    }
+}
+
+/// Quantize `src` with `scale` into zero-point-128 u8 (see module docs).
+pub fn quantize_u8(src: &[f32], scale: f32, dst: &mut [u8]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "quantize: source and destination lengths differ"
+    );
+    let inv = if scale > 0.0 { 1.0 / scale } else { 0.0 };
+    for (d, &x) in dst.iter_mut().zip(src) {
+        *d = ((x * inv).round().clamp(-127.0, 127.0) as i32 + U8_ZERO_POINT) as u8;
+    }
+}
+
+/// `dst = src * scale`. Panics if the lengths differ.
+pub fn dequantize_i8(src: &[i8], scale: f32, dst: &mut [f32]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "dequantize: source and destination lengths differ"
+    );
+    for (d, &q) in dst.iter_mut().zip(src) {
+        *d = q as f32 * scale;
+    }
+}
+
+/// Quantize every `dim`-value row of `data` with its own max-abs scale.
+/// Returns the int8 rows and one scale per row.
+pub fn quantize_rows_i8(data: &[f32], dim: usize) -> (Vec<i8>, Vec<f32>) {
+    let mut out = vec![0i8; data.len()];
+    if dim == 0 {
+        return (out, Vec::new());
+    }
+    let scales = data
+        .chunks_exact(dim)
+        .zip(out.chunks_exact_mut(dim))
+        .map(|(row, dst)| {
+            let scale = max_abs_scale(row);
+            quantize_i8(row, scale, dst);
+            scale
+        })
+        .collect();
+    (out, scales)
+}
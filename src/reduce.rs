@@ -0,0 +1,350 @@
+//! Reductions over similarity tiles and per-token maxima.
+//!
+//! The fused path's GEMM writes each tile doc-major: column `t` holds the
+//! similarities of every query token against document token `t`, so a
+//! query token's running max is a vertical `max` across columns, with no
+//! horizontal shuffles. Query lengths off the vector width are handled
+//! with masked loads and stores.
+//!
+//! Runtime dispatch picks AVX-512F, then AVX2, then a scalar loop. Inputs
+//! are assumed NaN-free: with a NaN the vector and scalar paths may keep
+//! different values.
+//!
+//! The final sum of a score goes through `weighted_sum`, whose order of
+//! operations is fixed: the same maxima give bit-identical scores on every
+//! CPU, run and thread count.
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::simd::simd_max_avx2;
+
+/// Accumulators of `weighted_sum`.
+const SUM_LANES: usize = 4;
+
+/// Fold the columns of the column-major `tile` (`maxes.len()` rows, a
+/// whole number of columns) into the per-row running maxima `maxes`.
+/// Panics if `tile` is not a whole number of columns.
+pub fn fold_row_maxes(tile: &[f32], maxes: &mut [f32]) {
+    let rows = maxes.len();
+    if rows == 0 {
+        return;
+    }
+    assert!(
+        tile.len().is_multiple_of(rows),
+        "row maxes: tile is not a whole number of columns"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512f") {
+            return unsafe { fold_row_maxes_avx512(tile, maxes) };
+        }
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { fold_row_maxes_avx2(tile, maxes) };
+        }
+    }
+
+    fold_row_maxes_scalar(tile, maxes);
+}
+
+fn fold_row_maxes_scalar(tile: &[f32], maxes: &mut [f32]) {
+    for column in tile.chunks_exact(maxes.len()) {
+        for (m, &s) in maxes.iter_mut().zip(column) {
+            *m = m.max(s);
+        }
+    }
+}
+
+/// AVX-512F: 16 rows per register, four columns in flight; the last rows
+/// through a load/store mask.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+unsafe fn fold_row_maxes_avx512(tile: &[f32], maxes: &mut [f32]) {
+    let rows = maxes.len();
+    let cols = tile.len() / rows;
+    let src = tile.as_ptr();
+    let dst = maxes.as_mut_ptr();
+
+    let mut r = 0;
+    while r < rows {
+        let mask: __mmask16 = if rows - r >= 16 {
+            0xffff
+        } else {
+            (1 << (rows - r)) - 1
+        };
+        let col = |c: usize| src.add(c * rows + r);
+
+        let mut acc0 = _mm512_maskz_loadu_ps(mask, dst.add(r));
+        let mut acc1 = acc0;
+        let mut acc2 = acc0;
+        let mut acc3 = acc0;
+        let mut c = 0;
+        while c + 4 <= cols {
+            acc0 = _mm512_max_ps(acc0, _mm512_maskz_loadu_ps(mask, col(c)));
+            acc1 = _mm512_max_ps(acc1, _mm512_maskz_loadu_ps(mask, col(c + 1)));
+            acc2 = _mm512_max_ps(acc2, _mm512_maskz_loadu_ps(mask, col(c + 2)));
+            acc3 = _mm512_max_ps(acc3, _mm512_maskz_loadu_ps(mask, col(c + 3)));
+            c += 4;
+        }
+        while c < cols {
+            acc0 = _mm512_max_ps(acc0, _mm512_maskz_loadu_ps(mask, col(c)));
+            c += 1;
+        }
+
+        let acc = _mm512_max_ps(_mm512_max_ps(acc0, acc1), _mm512_max_ps(acc2, acc3));
+        _mm512_mask_storeu_ps(dst.add(r), mask, acc);
+        r += 16;
+    }
+}
+
+/// AVX2: 8 rows per register, four columns in flight; the last rows
+/// through `vmaskmovps`.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn fold_row_maxes_avx2(tile: &[f32], maxes: &mut [f32]) {
+    let rows = maxes.len();
+    let cols = tile.len() / rows;
+    let src = tile.as_ptr();
+    let dst = maxes.as_mut_ptr();
+    let lanes = _mm256_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7);
+
+    let mut r = 0;
+    while r < rows {
+        // Lane i is live while i < rows - r
+        let live = _mm256_set1_epi32((rows - r).min(8) as i32);
+        let mask = _mm256_cmpgt_epi32(live, lanes);
+        let col = |c: usize| src.add(c * rows + r);
+
+        let mut acc0 = _mm256_maskload_ps(dst.add(r), mask);
+        let mut acc1 = acc0;
+        let mut acc2 = acc0;
+        let mut acc3 = acc0;
+        let mut c = 0;
+        while c + 4 <= cols {
+            acc0 = _mm256_max_ps(acc0, _mm256_maskload_ps(col(c), mask));
+            acc1 = _mm256_max_ps(acc1, _mm256_maskload_ps(col(c + 1), mask));
+            acc2 = _mm256_max_ps(acc2, _mm256_maskload_ps(col(c + 2), mask));
+            acc3 = _mm256_max_ps(acc3, _mm256_maskload_ps(col(c + 3), mask));
+            c += 4;
+        }
+        while c < cols {
+            acc0 = _mm256_max_ps(acc0, _mm256_maskload_ps(col(c), mask));
+            c += 1;
+        }
+
+        let acc = _mm256_max_ps(_mm256_max_ps(acc0, acc1), _mm256_max_ps(acc2, acc3));
+        _mm256_maskstore_ps(dst.add(r), mask, acc);
+        r += 8;
+    }
+}
+
+/// Sum over the `q_len` rows of `sims` (row `qi` starting at `qi * ld`) of
+/// each row's max over its first `valid_len` columns. The columns past it
+/// are bucket padding and never take part: a zero padding row would
+/// otherwise win every max whose real similarities are all negative. With
+/// no valid columns the document has no tokens and scores 0, as empty
+/// documents do in `maxsim_score_batch`.
+pub fn masked_max_sum(sims: &[f32], q_len: usize, ld: usize, valid_len: usize) -> f32 {
+    assert!(valid_len <= ld, "masked max: {valid_len} valid columns of {ld}");
+    if valid_len == 0 {
+        return 0.0;
+    }
+    (0..q_len)
+        .map(|qi| simd_max_avx2(&sims[qi * ld..qi * ld + valid_len]))
+        .sum()
+}
+
+/// `Σ maxima[i] · weights[i]`, or the plain sum without weights. Element
+/// `i` is fused-multiply-added into accumulator `i % 4`, and the four are
+/// combined as `(a0 + a1) + (a2 + a3)`; FMA (x86_64), NEON and the scalar
+/// fallback all keep that order, so the result does not depend on the CPU.
+/// Panics if the lengths differ.
+pub fn weighted_sum(maxima: &[f32], weights: Option<&[f32]>) -> f32 {
+    masked_weighted_sum(maxima, weights, None)
+}
+
+/// `weighted_sum` over the elements whose `mask` entry is `true`; the
+/// others leave their accumulator untouched.
+pub(crate) fn masked_weighted_sum(
+    maxima: &[f32],
+    weights: Option<&[f32]>,
+    mask: Option<&[bool]>,
+) -> f32 {
+    let n = maxima.len();
+    assert!(
+        weights.is_none_or(|w| w.len() == n) && mask.is_none_or(|m| m.len() == n),
+        "weighted sum: maxima, weights and mask lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("fma") {
+            return unsafe { weighted_sum_fma(maxima, weights, mask) };
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        weighted_sum_neon(maxima, weights, mask)
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    finish_sum([0.0; SUM_LANES], maxima, weights, mask, 0)
+}
+
+/// Sum of `values` in `weighted_sum`'s accumulator order, for sums whose
+/// terms are computed on the fly.
+pub(crate) fn lane_sum(values: impl IntoIterator<Item = f32>) -> f32 {
+    let mut acc = [0.0f32; SUM_LANES];
+    for (i, v) in values.into_iter().enumerate() {
+        acc[i % SUM_LANES] += v;
+    }
+    (acc[0] + acc[1]) + (acc[2] + acc[3])
+}
+
+/// Elements `from..` into the accumulators `acc` one at a time (`from` a
+/// multiple of `SUM_LANES`), then the fixed combine.
+fn finish_sum(
+    mut acc: [f32; SUM_LANES],
+    maxima: &[f32],
+    weights: Option<&[f32]>,
+    mask: Option<&[bool]>,
+    from: usize,
+) -> f32 {
+    for i in from..maxima.len() {
+        if mask.is_none_or(|mask| mask[i]) {
+            let w = weights.map_or(1.0, |w| w[i]);
+            acc[i % SUM_LANES] = maxima[i].mul_add(w, acc[i % SUM_LANES]);
+        }
+    }
+    (acc[0] + acc[1]) + (acc[2] + acc[3])
+}
+
+/// FMA: the four accumulators are the lanes of one xmm register.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1,fma")]
+unsafe fn weighted_sum_fma(maxima: &[f32], weights: Option<&[f32]>, mask: Option<&[bool]>) -> f32 {
+    let body = maxima.len() - maxima.len() % SUM_LANES;
+    let mut acc = _mm_setzero_ps();
+    let mut i = 0;
+    while i < body {
+        let m = _mm_loadu_ps(maxima.as_ptr().add(i));
+        let w = match weights {
+            Some(w) => _mm_loadu_ps(w.as_ptr().add(i)),
+            None => _mm_set1_ps(1.0),
+        };
+        let sum = _mm_fmadd_ps(m, w, acc);
+        acc = match mask {
+            Some(mask) => {
+                let keep = |j: usize| -(mask[i + j] as i32);
+                let keep = _mm_setr_epi32(keep(0), keep(1), keep(2), keep(3));
+                _mm_blendv_ps(acc, sum, _mm_castsi128_ps(keep))
+            }
+            None => sum,
+        };
+        i += SUM_LANES;
+    }
+    let mut lanes = [0.0f32; SUM_LANES];
+    _mm_storeu_ps(lanes.as_mut_ptr(), acc);
+    finish_sum(lanes, maxima, weights, mask, body)
+}
+
+/// NEON: the four accumulators are the lanes of one q register.
+#[cfg(target_arch = "aarch64")]
+unsafe fn weighted_sum_neon(maxima: &[f32], weights: Option<&[f32]>, mask: Option<&[bool]>) -> f32 {
+    let body = maxima.len() - maxima.len() % SUM_LANES;
+    let mut acc = vdupq_n_f32(0.0);
+    let mut i = 0;
+    while i < body {
+        let m = vld1q_f32(maxima.as_ptr().add(i));
+        let w = match weights {
+            Some(w) => vld1q_f32(w.as_ptr().add(i)),
+            None => vdupq_n_f32(1.0),
+        };
+        let sum = vfmaq_f32(acc, m, w);
+        acc = match mask {
+            Some(mask) => {
+                let keep: [u32; SUM_LANES] =
+                    std::array::from_fn(|j| 0u32.wrapping_sub(mask[i + j] as u32));
+                vbslq_f32(vld1q_u32(keep.as_ptr()), sum, acc)
+            }
+            None => sum,
+        };
+        i += SUM_LANES;
+    }
+    let mut lanes = [0.0f32; SUM_LANES];
+    vst1q_f32(lanes.as_mut_ptr(), acc);
+    finish_sum(lanes, maxima, weights, mask, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+
+    type Fold = unsafe fn(&[f32], &mut [f32]);
+
+    /// Every vector path this CPU runs, by name, and the dispatcher.
+    fn vector_paths() -> Vec<(&'static str, Fold)> {
+        let mut paths: Vec<(&str, Fold)> = Vec::new();
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx512f") {
+                paths.push(("avx512", fold_row_maxes_avx512));
+            }
+            if is_x86_feature_detected!("avx2") {
+                paths.push(("avx2", fold_row_maxes_avx2));
+            }
+        }
+        paths.push(("dispatch", |tile, maxes| fold_row_maxes(tile, maxes)));
+        paths
+    }
+
+    #[test]
+    fn padding_never_wins_the_max() {
+        // Two query rows over a bucket of 4 columns; only the first 2 are
+        // real tokens, all with negative similarities, and the padding
+        // columns hold the zeros a padded document's GEMM writes
+        let sims = [-1.0, -3.0, 0.0, 0.0, -2.0, -0.5, 0.0, 0.0];
+        assert_eq!(masked_max_sum(&sims, 2, 4, 2), -1.5);
+        // Unmasked, the padding wins both maxes
+        assert_eq!(masked_max_sum(&sims, 2, 4, 4), 0.0);
+        assert_eq!(masked_max_sum(&sims, 2, 4, 1), -3.0);
+        // No valid tokens: an empty document, which scores 0
+        assert_eq!(masked_max_sum(&sims, 2, 4, 0), 0.0);
+    }
+
+    #[test]
+    fn row_maxes_match_the_scalar_fold() {
+        for rows in [1, 3, 7, 8, 9, 15, 16, 17, 31, 33, 47] {
+            for cols in [1, 2, 3, 4, 5, 7, 9] {
+                let seed = (rows * 100 + cols) as u64;
+                // Unit columns: mostly small values of either sign
+                let tile = unit_rows(cols, rows, seed);
+                let start: Vec<f32> = unit_rows(1, rows, seed + 1)
+                    .iter()
+                    .enumerate()
+                    .map(|(r, &x)| {
+                        if r % 5 == 0 {
+                            f32::NEG_INFINITY
+                        } else {
+                            x - 0.5
+                        }
+                    })
+                    .collect();
+                let mut expected = start.clone();
+                fold_row_maxes_scalar(&tile, &mut expected);
+                for (name, fold) in vector_paths() {
+                    // Lanes past the last row must be left alone
+                    let mut maxes = start.clone();
+                    maxes.extend([7.0; 16]);
+                    unsafe { fold(&tile, &mut maxes[..rows]) };
+                    assert_eq!(maxes[..rows], expected, "{name}: {rows} x {cols}");
+                    assert_eq!(maxes[rows..], [7.0; 16], "{name}: {rows} x {cols}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! MaxSim reranking of an explicit candidate list.
+//!
+//! A first-stage retriever (BM25, ANN over pooled vectors, ...) hands over
+//! a few hundred ids per query; only those documents are read and scored.
+//! Candidates are resolved to positions and sorted, so the store is walked
+//! front to back over just the candidates' rows, and the scorer groups
+//! them by length as usual: one GEMM setup per distinct length, not per
+//! document.
+
+use crate::collection::{DocBatch, DocId, Documents, QueryEmbeddings};
+use crate::score::{top_k_heap, Reduction, ScoreError};
+use crate::topk::SearchHit;
+
+/// What reranking does with a candidate id that names no document.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OnMissing {
+    /// Drop it, reporting the dropped ids in a `tracing` warning.
+    #[default]
+    Skip,
+    /// Fail with `ScoreError::UnknownId`.
+    Error,
+}
+
+/// The `k` best of the documents in `docs` whose ids are in
+/// `candidate_ids`, as `SearchHit`s. Duplicate candidates are scored once.
+/// Scores equal `maxsim_search` over the same documents.
+pub fn maxsim_rerank(
+    query: &QueryEmbeddings,
+    docs: &DocBatch,
+    candidate_ids: &[u64],
+    k: usize,
+    on_missing: OnMissing,
+) -> Result<Vec<SearchHit>, ScoreError> {
+    let positions = resolve_candidates(candidate_ids, |id| docs.position(id), on_missing)?;
+    let subset = Subset {
+        docs,
+        positions: &positions,
+    };
+    Ok(top_k_heap(query, &subset, k, Reduction::default())?.into_hits(|i| subset.id(i)))
+}
+
+/// `candidate_ids` looked up with `lookup`, sorted and deduplicated, with
+/// unknown ids handled per `on_missing`.
+pub(crate) fn resolve_candidates<T: Ord>(
+    candidate_ids: &[u64],
+    lookup: impl Fn(u64) -> Option<T>,
+    on_missing: OnMissing,
+) -> Result<Vec<T>, ScoreError> {
+    let mut found = Vec::with_capacity(candidate_ids.len());
+    let mut missing = Vec::new();
+    for &id in candidate_ids {
+        match lookup(id) {
+            Some(slot) => found.push(slot),
+            None if on_missing == OnMissing::Error => return Err(ScoreError::UnknownId { id }),
+            None => missing.push(id),
+        }
+    }
+    if !missing.is_empty() {
+        trace_event!(
+            WARN,
+            skipped = missing.len(),
+            ids = ?missing,
+            "unknown candidate ids skipped"
+        );
+    }
+    found.sort_unstable();
+    found.dedup();
+    Ok(found)
+}
+
+/// The documents of `docs` at `positions`, renumbered from 0.
+pub(crate) struct Subset<'a, D: ?Sized> {
+    pub(crate) docs: &'a D,
+    pub(crate) positions: &'a [DocId],
+}
+
+impl<D: Documents + ?Sized> Documents for Subset<'_, D> {
+    fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.docs.dim()
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        self.docs.doc_len(self.positions[i])
+    }
+
+    fn doc(&self, i: DocId) -> &[f32] {
+        self.docs.doc(self.positions[i])
+    }
+
+    fn max_token_norm(&self, i: DocId) -> f32 {
+        self.docs.max_token_norm(self.positions[i])
+    }
+
+    fn id(&self, i: DocId) -> u64 {
+        self.docs.id(self.positions[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+    use crate::score::maxsim_search;
+    use std::sync::Mutex;
+
+    const DIM: usize = 8;
+
+    /// Records every document position whose rows are read.
+    struct Touched<'a> {
+        docs: &'a DocBatch,
+        read: Mutex<Vec<DocId>>,
+    }
+
+    impl Documents for Touched<'_> {
+        fn len(&self) -> usize {
+            self.docs.len()
+        }
+
+        fn dim(&self) -> usize {
+            self.docs.dim()
+        }
+
+        fn doc_len(&self, i: DocId) -> usize {
+            self.docs.doc_len(i)
+        }
+
+        fn doc(&self, i: DocId) -> &[f32] {
+            self.read.lock().unwrap().push(i);
+            self.docs.doc(i)
+        }
+
+        fn id(&self, i: DocId) -> u64 {
+            self.docs.id(i)
+        }
+    }
+
+    /// 200 documents of 1 to 12 tokens under ids 1000, 1003, 1006, ...
+    fn batch() -> DocBatch {
+        let mut docs = DocBatch::new(DIM);
+        for i in 0..200 {
+            let len = 1 + i % 12;
+            let id = 1000 + 3 * i as u64;
+            docs.push_with_id(id, &unit_rows(len, DIM, i as u64), None)
+                .unwrap();
+        }
+        docs
+    }
+
+    #[test]
+    fn only_candidates_are_read() {
+        let docs = batch();
+        let query = QueryEmbeddings::new(unit_rows(4, DIM, 500), 4, DIM).unwrap();
+        let candidates = [1597, 1003, 1300, 1003, 1000];
+        let positions = resolve_candidates(&candidates, |id| docs.position(id), OnMissing::Error);
+        let positions = positions.unwrap();
+        assert_eq!(positions, [0, 1, 100, 199]);
+
+        let touched = Touched {
+            docs: &docs,
+            read: Mutex::new(Vec::new()),
+        };
+        let subset = Subset {
+            docs: &touched,
+            positions: &positions,
+        };
+        let hits = top_k_heap(&query, &subset, 3, Reduction::default()).unwrap();
+        let mut read = touched.read.into_inner().unwrap();
+        read.sort_unstable();
+        read.dedup();
+        assert_eq!(read, positions);
+        assert_eq!(hits.len(), 3);
+    }
+
+    #[test]
+    fn rerank_equals_search_over_the_candidates() {
+        let docs = batch();
+        let query = QueryEmbeddings::new(unit_rows(4, DIM, 501), 4, DIM).unwrap();
+        let candidates: Vec<u64> = (0..200).step_by(7).map(|i| 1000 + 3 * i).collect();
+
+        let mut only = DocBatch::new(DIM);
+        for &id in &candidates {
+            let doc = docs.doc(docs.position(id).unwrap());
+            only.push_with_id(id, doc, None).unwrap();
+        }
+        let expected = maxsim_search(&query, &only, 5).unwrap();
+        let hits = maxsim_rerank(&query, &docs, &candidates, 5, OnMissing::Error).unwrap();
+        assert_eq!(hits, expected);
+
+        // Unknown ids are dropped or rejected per the policy
+        let with_unknown = [candidates.clone(), vec![1, 1001]].concat();
+        let skipped = maxsim_rerank(&query, &docs, &with_unknown, 5, OnMissing::Skip).unwrap();
+        assert_eq!(skipped, expected);
+        let err = maxsim_rerank(&query, &docs, &with_unknown, 5, OnMissing::Error);
+        assert_eq!(err, Err(ScoreError::UnknownId { id: 1 }));
+    }
+}
@@ -0,0 +1,321 @@
+//! PLAID-style residual compression of document tokens (ColBERTv2).
+//!
+//! Each token is stored as the id of its nearest centroid plus a 2-bit code
+//! per dimension for the residual `token - centroid`. The four code values
+//! form a codebook shared by every dimension: codes split at the quartiles
+//! of the residual values and decode to the 1/8, 3/8, 5/8 and 7/8
+//! quantiles. A token costs `4 + ceil(dim / 4)` bytes instead of `4 * dim`.
+//!
+//! Scoring decodes one tile of tokens (centroid row plus codebook value)
+//! into f32 scratch right before its GEMM; see `Scorer::score_batch_residual`.
+
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use crate::centroids::CentroidTable;
+use crate::collection::{DocId, Documents};
+use crate::score::{check_len, ScoreError};
+
+/// Residual codes per byte.
+const CODES_PER_BYTE: usize = 4;
+
+/// Residual values the codebook quantiles are taken from, at most.
+const SAMPLE_LIMIT: usize = 1 << 20;
+
+/// Centroids plus the shared 2-bit residual codebook.
+#[derive(Clone, Debug)]
+pub struct ResidualCodebook {
+    /// `[n_centroids, dim]` row-major.
+    centroids: Vec<f32>,
+    dim: usize,
+    /// A residual value codes as the number of cutoffs below it.
+    cutoffs: [f32; 3],
+    /// Value each code decodes to.
+    weights: [f32; 4],
+}
+
+impl ResidualCodebook {
+    pub fn n_centroids(&self) -> usize {
+        self.centroids.len().checked_div(self.dim).unwrap_or(0)
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Centroid `id`, `dim` values.
+    pub fn centroid(&self, id: u32) -> &[f32] {
+        let start = id as usize * self.dim;
+        &self.centroids[start..start + self.dim]
+    }
+
+    /// Boundaries between the four residual codes, ascending.
+    pub fn cutoffs(&self) -> [f32; 3] {
+        self.cutoffs
+    }
+
+    /// Residual value of each code.
+    pub fn weights(&self) -> [f32; 4] {
+        self.weights
+    }
+
+    /// 2-bit codes of `residual` packed four per byte, lowest bits first.
+    fn encode(&self, residual: &[f32], dst: &mut [u8]) {
+        dst.fill(0);
+        for (j, &r) in residual.iter().enumerate() {
+            let code = self.cutoffs.iter().filter(|&&c| r > c).count() as u8;
+            dst[j / CODES_PER_BYTE] |= code << (2 * (j % CODES_PER_BYTE));
+        }
+    }
+
+    /// Centroid `id` plus the decoded residual `codes`, into `dst`.
+    fn decode(&self, id: u32, codes: &[u8], dst: &mut [f32]) {
+        for (j, (d, &c)) in dst.iter_mut().zip(self.centroid(id)).enumerate() {
+            let code = (codes[j / CODES_PER_BYTE] >> (2 * (j % CODES_PER_BYTE))) & 0b11;
+            *d = c + self.weights[code as usize];
+        }
+    }
+}
+
+/// Documents compressed to centroid ids and 2-bit residuals. Built with
+/// `compress`; scored with `Scorer::score_batch_residual`.
+///
+/// Document `i` spans tokens `offsets[i]..offsets[i + 1]`. Zero-length
+/// documents are allowed and score 0.
+#[derive(Clone, Debug)]
+pub struct ResidualDocCollection {
+    codebook: ResidualCodebook,
+    /// Nearest centroid per token.
+    centroid_ids: Vec<u32>,
+    /// `bytes_per_token` residual code bytes per token.
+    residuals: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl ResidualDocCollection {
+    /// Compress `docs` against `centroids` (`[n_centroids, dim]`, e.g. from
+    /// `train_centroids`). Each token goes to the centroid nearest in L2;
+    /// the codebook comes from the quantiles of up to `SAMPLE_LIMIT`
+    /// residual values spread over the collection.
+    pub fn compress<D: Documents + ?Sized>(
+        docs: &D,
+        centroids: Vec<f32>,
+    ) -> Result<Self, ScoreError> {
+        let dim = docs.dim();
+        let n_centroids = centroids.len().checked_div(dim).unwrap_or(0);
+        check_len("centroids", &centroids, n_centroids, dim)?;
+        let mut offsets = Vec::with_capacity(docs.len() + 1);
+        offsets.push(0);
+        for i in 0..docs.len() {
+            offsets.push(offsets[i] + docs.doc_len(i));
+        }
+        let total = offsets[docs.len()];
+        if n_centroids == 0 && total > 0 && dim > 0 {
+            return Err(ScoreError::EmptyCodebook);
+        }
+
+        let mut codebook = ResidualCodebook {
+            centroids,
+            dim,
+            cutoffs: [0.0; 3],
+            weights: [0.0; 4],
+        };
+        let centroid_ids: Vec<u32> = if dim == 0 {
+            vec![0; total]
+        } else {
+            let table = CentroidTable::new(&codebook.centroids, dim);
+            (0..docs.len())
+                .into_par_iter()
+                .flat_map_iter(|i| table.nearest(docs.doc(i)))
+                .collect()
+        };
+        fit_codebook(&mut codebook, docs, &centroid_ids);
+
+        let codebook_ref = &codebook;
+        let residuals: Vec<u8> = (0..docs.len())
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let ids = &centroid_ids[offsets[i]..offsets[i + 1]];
+                encode_doc(codebook_ref, docs.doc(i), ids)
+            })
+            .collect();
+
+        Ok(Self {
+            codebook,
+            centroid_ids,
+            residuals,
+            offsets,
+        })
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.codebook.dim
+    }
+
+    pub fn codebook(&self) -> &ResidualCodebook {
+        &self.codebook
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        self.offsets[i + 1] - self.offsets[i]
+    }
+
+    /// Centroid id of each token of document `i`.
+    pub fn centroid_ids(&self, i: DocId) -> &[u32] {
+        &self.centroid_ids[self.offsets[i]..self.offsets[i + 1]]
+    }
+
+    /// Packed residual codes of document `i`, `ceil(dim / 4)` bytes per
+    /// token.
+    pub fn residual_codes(&self, i: DocId) -> &[u8] {
+        let bytes = bytes_per_token(self.dim());
+        &self.residuals[self.offsets[i] * bytes..self.offsets[i + 1] * bytes]
+    }
+
+    /// Reconstruct `tokens` of document `i` as `[tokens.len(), dim]` f32
+    /// rows into `dst`. Panics if `dst` has a different length.
+    pub fn decode(&self, i: DocId, tokens: Range<usize>, dst: &mut [f32]) {
+        let dim = self.dim();
+        assert_eq!(
+            dst.len(),
+            tokens.len() * dim,
+            "residual decode: destination is not tokens * dim"
+        );
+        if dim == 0 {
+            return;
+        }
+        let bytes = bytes_per_token(dim);
+        let ids = &self.centroid_ids(i)[tokens.clone()];
+        let codes = &self.residual_codes(i)[tokens.start * bytes..tokens.end * bytes];
+        for ((row, &id), codes) in dst.chunks_exact_mut(dim).zip(ids).zip(codes.chunks(bytes)) {
+            self.codebook.decode(id, codes, row);
+        }
+    }
+}
+
+pub(crate) fn bytes_per_token(dim: usize) -> usize {
+    dim.div_ceil(CODES_PER_BYTE)
+}
+
+/// Residual codes of every row of `doc` against its centroid in `ids`.
+fn encode_doc(codebook: &ResidualCodebook, doc: &[f32], ids: &[u32]) -> Vec<u8> {
+    let dim = codebook.dim;
+    let bytes = bytes_per_token(dim);
+    let mut codes = vec![0u8; ids.len() * bytes];
+    if dim == 0 {
+        return codes;
+    }
+    let mut residual = vec![0.0f32; dim];
+    for ((row, &id), dst) in doc
+        .chunks_exact(dim)
+        .zip(ids)
+        .zip(codes.chunks_exact_mut(bytes))
+    {
+        for ((r, &x), &c) in residual.iter_mut().zip(row).zip(codebook.centroid(id)) {
+            *r = x - c;
+        }
+        codebook.encode(&residual, dst);
+    }
+    codes
+}
+
+/// Set the codebook's cutoffs and weights from the quantiles of a strided
+/// sample of residual values.
+fn fit_codebook<D: Documents + ?Sized>(
+    codebook: &mut ResidualCodebook,
+    docs: &D,
+    centroid_ids: &[u32],
+) {
+    let dim = codebook.dim;
+    let total = centroid_ids.len();
+    if dim == 0 || total == 0 {
+        return;
+    }
+    let step = (total * dim).div_ceil(SAMPLE_LIMIT).max(1);
+    let mut sample = Vec::with_capacity(total.div_ceil(step) * dim);
+    let mut t = 0;
+    for i in 0..docs.len() {
+        for row in docs.doc(i).chunks_exact(dim) {
+            if t % step == 0 {
+                let centroid = codebook.centroid(centroid_ids[t]);
+                sample.extend(row.iter().zip(centroid).map(|(x, c)| x - c));
+            }
+            t += 1;
+        }
+    }
+    sample.sort_unstable_by(f32::total_cmp);
+    let quantile = |p: f32| sample[((sample.len() - 1) as f32 * p).round() as usize];
+    codebook.cutoffs = [quantile(0.25), quantile(0.5), quantile(0.75)];
+    codebook.weights = [
+        quantile(0.125),
+        quantile(0.375),
+        quantile(0.625),
+        quantile(0.875),
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::clustered_docs;
+    use crate::centroids::train_centroids;
+    use crate::collection::QueryEmbeddings;
+    use crate::scorer::{Scorer, ScorerConfig};
+
+    #[test]
+    fn scores_drift_little_from_the_exact_ones() {
+        let dim = 64;
+        let lengths: Vec<usize> = (0..200).map(|i| 4 + (i * 7) % 33).collect();
+        let docs = clustered_docs(&lengths, dim, 32, 71);
+        let compressed =
+            ResidualDocCollection::compress(&docs, train_centroids(&docs, 32, 10)).unwrap();
+
+        // Largest L2 error of a reconstructed token
+        let mut worst = 0.0f32;
+        for i in 0..docs.len() {
+            let mut decoded = vec![0.0; docs.doc(i).len()];
+            compressed.decode(i, 0..docs.doc_len(i), &mut decoded);
+            for (a, b) in decoded.chunks_exact(dim).zip(docs.doc(i).chunks_exact(dim)) {
+                let err: f32 = a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum();
+                worst = worst.max(err.sqrt());
+            }
+        }
+
+        let scorer = Scorer::new(ScorerConfig::default()).unwrap();
+        let q_len = 8;
+        let (mut drift, mut largest, mut n) = (0.0, 0.0f32, 0);
+        for i in [3, 50, 120, 199] {
+            let query = docs.doc(i)[..q_len * dim].to_vec();
+            let query = QueryEmbeddings::new(query, q_len, dim).unwrap();
+            let exact = scorer.score_batch(&query, &docs).unwrap();
+            let approx = scorer.score_batch_residual(&query, &compressed).unwrap();
+            for (doc, (&e, &a)) in exact.iter().zip(&approx).enumerate() {
+                // Each unit query token's maximum moves by at most `worst`
+                let bound = q_len as f32 * worst + 1e-4;
+                assert!(
+                    (a - e).abs() <= bound,
+                    "doc {doc}: {a} vs {e}, bound {bound}"
+                );
+                let per_token = ((a - e) / q_len as f32).abs();
+                drift += per_token;
+                largest = largest.max(per_token);
+                n += 1;
+            }
+        }
+        let drift = drift / n as f32;
+        // Per query token, with scores in [-1, 1] per token
+        assert!(drift < 0.03, "mean drift per query token {drift}");
+        assert!(largest < 0.2, "largest drift per query token {largest}");
+    }
+}
@@ -0,0 +1,757 @@
+//! Loading embeddings from safetensors files.
+//!
+//! A safetensors file is a little-endian u64 header length, a JSON header
+//! mapping tensor names to `{dtype, shape, data_offsets}`, then the raw
+//! tensor bytes. The file is mapped and each F32, F16 or BF16 tensor is
+//! widened straight from the mapping into the aligned buffer it ends up
+//! in, with no intermediate copy of the whole tensor.
+//!
+//! Embeddings are `[tokens, dim]` (one document or query) or
+//! `[n, max_tokens, dim]` (a padded batch). A padded batch can come with
+//! an integer `[n]` lengths tensor; rows past each entry's length are
+//! padding and are not loaded.
+
+use std::ops::Range;
+use std::path::Path;
+
+use crate::aligned::AlignedVec;
+use crate::bf16::convert_bf16_to_f32;
+use crate::collection::{DocBatch, QueryBatch, QueryEmbeddings};
+use crate::f16::convert_f16_to_f32;
+use crate::score::ScoreError;
+use crate::store::{read_u64, Mmap, StoreError};
+
+/// Values widened per conversion call.
+const CHUNK: usize = 1024;
+
+/// Arrays and objects a skipped header value may nest.
+const MAX_DEPTH: usize = 64;
+
+/// Why embeddings could not be loaded from a safetensors file.
+#[derive(Debug)]
+pub enum SafetensorsError {
+    Io(std::io::Error),
+    /// The header or a tensor's data range is not valid safetensors.
+    Malformed {
+        reason: &'static str,
+    },
+    /// The file has no tensor `name`; `found` lists the ones it has.
+    MissingTensor {
+        name: String,
+        found: Vec<String>,
+    },
+    /// Tensor `name` holds `dtype` elements, none of `supported`.
+    UnsupportedDtype {
+        name: String,
+        dtype: String,
+        supported: &'static str,
+    },
+    /// Tensor `name` has neither of the shapes the loader takes.
+    Shape {
+        name: String,
+        shape: Vec<usize>,
+        expected: &'static str,
+    },
+    /// A lengths tensor does not fit the padded batch it describes.
+    Lengths {
+        reason: &'static str,
+    },
+    /// The loaded embeddings were rejected by the collection.
+    Score(ScoreError),
+}
+
+impl std::fmt::Display for SafetensorsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SafetensorsError::Io(err) => write!(f, "safetensors I/O: {}", err),
+            SafetensorsError::Malformed { reason } => {
+                write!(f, "malformed safetensors file: {}", reason)
+            }
+            SafetensorsError::MissingTensor { name, found } => write!(
+                f,
+                "no tensor `{}` in safetensors file (found: {})",
+                name,
+                found.join(", ")
+            ),
+            SafetensorsError::UnsupportedDtype {
+                name,
+                dtype,
+                supported,
+            } => write!(
+                f,
+                "tensor `{}` has dtype {}, expected one of {}",
+                name, dtype, supported
+            ),
+            SafetensorsError::Shape {
+                name,
+                shape,
+                expected,
+            } => write!(
+                f,
+                "tensor `{}` has shape {:?}, expected {}",
+                name, shape, expected
+            ),
+            SafetensorsError::Lengths { reason } => write!(f, "lengths tensor: {}", reason),
+            SafetensorsError::Score(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SafetensorsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SafetensorsError::Io(err) => Some(err),
+            SafetensorsError::Score(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SafetensorsError {
+    fn from(err: std::io::Error) -> Self {
+        SafetensorsError::Io(err)
+    }
+}
+
+impl From<ScoreError> for SafetensorsError {
+    fn from(err: ScoreError) -> Self {
+        SafetensorsError::Score(err)
+    }
+}
+
+impl DocBatch {
+    /// Documents from tensor `tensor_name` of the safetensors file at
+    /// `path`: one document for `[tokens, dim]`, one per row of
+    /// `[docs, max_tokens, dim]` with every padded row kept.
+    pub fn from_safetensors(path: &Path, tensor_name: &str) -> Result<Self, SafetensorsError> {
+        Self::from_rows(load_rows(path, tensor_name, None)?)
+    }
+
+    /// `from_safetensors` for a padded `[docs, max_tokens, dim]` tensor
+    /// whose integer `[docs]` tensor `lengths_name` gives each document's
+    /// token count. Padding rows are skipped.
+    pub fn from_safetensors_padded(
+        path: &Path,
+        tensor_name: &str,
+        lengths_name: &str,
+    ) -> Result<Self, SafetensorsError> {
+        Self::from_rows(load_rows(path, tensor_name, Some(lengths_name))?)
+    }
+
+    fn from_rows(rows: Rows) -> Result<Self, SafetensorsError> {
+        let mut offsets = Vec::with_capacity(rows.lens.len() + 1);
+        offsets.push(0);
+        for (i, &tokens) in rows.lens.iter().enumerate() {
+            offsets.push(offsets[i] + tokens * rows.dim);
+        }
+        Ok(Self::from_parts(rows.data, offsets, rows.dim)?)
+    }
+}
+
+impl QueryEmbeddings {
+    /// The `[tokens, dim]` query in tensor `tensor_name` of the
+    /// safetensors file at `path`. A `[1, tokens, dim]` tensor is taken as
+    /// its only query.
+    pub fn from_safetensors(path: &Path, tensor_name: &str) -> Result<Self, SafetensorsError> {
+        let rows = load_rows(path, tensor_name, None)?;
+        if rows.lens.len() != 1 {
+            return Err(SafetensorsError::Shape {
+                name: tensor_name.to_string(),
+                shape: rows.shape,
+                expected: "[tokens, dim] or [1, tokens, dim]",
+            });
+        }
+        Ok(Self::new(rows.data, rows.lens[0], rows.dim)?)
+    }
+}
+
+impl QueryBatch {
+    /// Queries from tensor `tensor_name` of the safetensors file at
+    /// `path`: one for `[tokens, dim]`, one per row of
+    /// `[queries, max_tokens, dim]` with every padded row kept.
+    pub fn from_safetensors(path: &Path, tensor_name: &str) -> Result<Self, SafetensorsError> {
+        Self::from_rows(load_rows(path, tensor_name, None)?)
+    }
+
+    /// `from_safetensors` for a padded `[queries, max_tokens, dim]` tensor
+    /// whose integer `[queries]` tensor `lengths_name` gives each query's
+    /// token count. Padding rows are skipped.
+    pub fn from_safetensors_padded(
+        path: &Path,
+        tensor_name: &str,
+        lengths_name: &str,
+    ) -> Result<Self, SafetensorsError> {
+        Self::from_rows(load_rows(path, tensor_name, Some(lengths_name))?)
+    }
+
+    fn from_rows(rows: Rows) -> Result<Self, SafetensorsError> {
+        let mut queries = Vec::with_capacity(rows.lens.len());
+        let mut start = 0;
+        for &tokens in &rows.lens {
+            let end = start + tokens * rows.dim;
+            queries.push(QueryEmbeddings::new(
+                &rows.data[start..end],
+                tokens,
+                rows.dim,
+            )?);
+            start = end;
+        }
+        Ok(Self::new(queries))
+    }
+}
+
+/// Embedding rows loaded from one tensor, padding dropped.
+struct Rows {
+    /// Every entry's `[tokens, dim]` rows back to back.
+    data: AlignedVec<f32>,
+    /// Token count per entry.
+    lens: Vec<usize>,
+    dim: usize,
+    /// Shape of the tensor, for errors.
+    shape: Vec<usize>,
+}
+
+/// Element type of a tensor, as named in the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dtype {
+    F32,
+    F16,
+    Bf16,
+    I32,
+    I64,
+}
+
+impl Dtype {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "F32" => Some(Dtype::F32),
+            "F16" => Some(Dtype::F16),
+            "BF16" => Some(Dtype::Bf16),
+            "I32" => Some(Dtype::I32),
+            "I64" => Some(Dtype::I64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Dtype::F16 | Dtype::Bf16 => 2,
+            Dtype::F32 | Dtype::I32 => 4,
+            Dtype::I64 => 8,
+        }
+    }
+}
+
+/// One header entry.
+struct TensorInfo {
+    dtype: String,
+    shape: Vec<usize>,
+    /// Byte range within the data section.
+    data: Range<usize>,
+}
+
+/// A mapped safetensors file with its parsed header.
+struct Safetensors {
+    map: Mmap,
+    data_start: usize,
+    tensors: Vec<(String, TensorInfo)>,
+}
+
+impl Safetensors {
+    fn open(path: &Path) -> Result<Self, SafetensorsError> {
+        let map = Mmap::open(path).map_err(|err| match err {
+            StoreError::Io(err) => SafetensorsError::Io(err),
+            _ => SafetensorsError::Malformed {
+                reason: "file is empty",
+            },
+        })?;
+        let bytes = map.bytes();
+        let data_start = (bytes.len() >= 8)
+            .then(|| read_u64(bytes, 0) as usize)
+            .and_then(|len| len.checked_add(8))
+            .filter(|&end| end <= bytes.len())
+            .ok_or(SafetensorsError::Malformed {
+                reason: "header runs past the end of the file",
+            })?;
+        let tensors = parse_header(&bytes[8..data_start])?;
+        let data_len = bytes.len() - data_start;
+        if tensors.iter().any(|(_, t)| t.data.end > data_len) {
+            return Err(SafetensorsError::Malformed {
+                reason: "tensor data runs past the end of the file",
+            });
+        }
+        Ok(Self {
+            map,
+            data_start,
+            tensors,
+        })
+    }
+
+    /// Tensor `name`, its element type if one of `supported`, and its
+    /// bytes.
+    fn tensor(
+        &self,
+        name: &str,
+        supported: &[Dtype],
+        supported_names: &'static str,
+    ) -> Result<(&TensorInfo, Dtype, &[u8]), SafetensorsError> {
+        let info = self
+            .tensors
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, info)| info)
+            .ok_or_else(|| SafetensorsError::MissingTensor {
+                name: name.to_string(),
+                found: self.tensors.iter().map(|(n, _)| n.clone()).collect(),
+            })?;
+        let dtype = Dtype::parse(&info.dtype)
+            .filter(|d| supported.contains(d))
+            .ok_or_else(|| SafetensorsError::UnsupportedDtype {
+                name: name.to_string(),
+                dtype: info.dtype.clone(),
+                supported: supported_names,
+            })?;
+        let elements = info.shape.iter().try_fold(1usize, |n, &d| n.checked_mul(d));
+        if elements.and_then(|n| n.checked_mul(dtype.size())) != Some(info.data.len()) {
+            return Err(SafetensorsError::Malformed {
+                reason: "tensor data size does not match its shape",
+            });
+        }
+        let start = self.data_start + info.data.start;
+        let bytes = &self.map.bytes()[start..start + info.data.len()];
+        Ok((info, dtype, bytes))
+    }
+}
+
+/// Rows of embedding tensor `name`, with per-entry lengths from tensor
+/// `lengths` when given.
+fn load_rows(path: &Path, name: &str, lengths: Option<&str>) -> Result<Rows, SafetensorsError> {
+    let file = Safetensors::open(path)?;
+    let (info, dtype, bytes) = file.tensor(
+        name,
+        &[Dtype::F32, Dtype::F16, Dtype::Bf16],
+        "F32, F16, BF16",
+    )?;
+    let shape = info.shape.clone();
+    let (n, max_tokens, dim) = match (&shape[..], lengths) {
+        (&[tokens, dim], None) => (1, tokens, dim),
+        (&[n, max_tokens, dim], _) => (n, max_tokens, dim),
+        _ => {
+            return Err(SafetensorsError::Shape {
+                name: name.to_string(),
+                shape,
+                expected: match lengths {
+                    Some(_) => "[n, max_tokens, dim] with lengths",
+                    None => "[tokens, dim] or [n, max_tokens, dim]",
+                },
+            })
+        }
+    };
+    let lens = match lengths {
+        Some(lengths) => read_lengths(&file, lengths, n, max_tokens)?,
+        None => vec![max_tokens; n],
+    };
+
+    let total: usize = lens.iter().sum();
+    let mut data = AlignedVec::with_capacity(total * dim);
+    let entry_bytes = max_tokens * dim * dtype.size();
+    for (i, &tokens) in lens.iter().enumerate() {
+        let start = i * entry_bytes;
+        widen(
+            &bytes[start..start + tokens * dim * dtype.size()],
+            dtype,
+            &mut data,
+        );
+    }
+    Ok(Rows {
+        data,
+        lens,
+        dim,
+        shape,
+    })
+}
+
+/// Token counts from the integer `[n]` tensor `name`, each at most
+/// `max_tokens`.
+fn read_lengths(
+    file: &Safetensors,
+    name: &str,
+    n: usize,
+    max_tokens: usize,
+) -> Result<Vec<usize>, SafetensorsError> {
+    let (info, dtype, bytes) = file.tensor(name, &[Dtype::I32, Dtype::I64], "I32, I64")?;
+    if info.shape != [n] {
+        return Err(SafetensorsError::Lengths {
+            reason: "shape is not [n] for the batch's n entries",
+        });
+    }
+    let lens: Vec<i64> = match dtype {
+        Dtype::I32 => bytes
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes(b.try_into().unwrap()) as i64)
+            .collect(),
+        _ => bytes
+            .chunks_exact(8)
+            .map(|b| i64::from_le_bytes(b.try_into().unwrap()))
+            .collect(),
+    };
+    lens.into_iter()
+        .map(|len| {
+            usize::try_from(len)
+                .ok()
+                .filter(|&len| len <= max_tokens)
+                .ok_or(SafetensorsError::Lengths {
+                    reason: "a length is negative or exceeds max_tokens",
+                })
+        })
+        .collect()
+}
+
+/// Append `src`, little-endian `dtype` values, to `dst` as f32.
+fn widen(src: &[u8], dtype: Dtype, dst: &mut AlignedVec<f32>) {
+    if dtype == Dtype::F32 {
+        dst.extend(
+            src.chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap())),
+        );
+        return;
+    }
+    // 2-byte values staged a chunk at a time: the data section need not
+    // be 2-byte aligned
+    let mut halves = [0u16; CHUNK];
+    for chunk in src.chunks(2 * CHUNK) {
+        let halves = &mut halves[..chunk.len() / 2];
+        for (h, b) in halves.iter_mut().zip(chunk.chunks_exact(2)) {
+            *h = u16::from_le_bytes([b[0], b[1]]);
+        }
+        let start = dst.len();
+        dst.resize(start + halves.len(), 0.0);
+        match dtype {
+            Dtype::F16 => convert_f16_to_f32(halves, &mut dst[start..]),
+            _ => convert_bf16_to_f32(halves, &mut dst[start..]),
+        }
+    }
+}
+
+/// The tensors of a safetensors JSON header, in file order.
+fn parse_header(header: &[u8]) -> Result<Vec<(String, TensorInfo)>, SafetensorsError> {
+    let mut json = Json {
+        src: header,
+        pos: 0,
+    };
+    let mut tensors = Vec::new();
+    json.object(|json, name| {
+        if name == "__metadata__" {
+            return json.skip_value();
+        }
+        let (mut dtype, mut shape, mut offsets) = (None, None, None);
+        json.object(|json, field| {
+            match field.as_str() {
+                "dtype" => dtype = Some(json.string()?),
+                "shape" => shape = Some(json.uints()?),
+                "data_offsets" => offsets = Some(json.uints()?),
+                _ => json.skip_value()?,
+            }
+            Ok(())
+        })?;
+        let data = match offsets.as_deref() {
+            Some(&[start, end]) if start <= end => start..end,
+            _ => return Err(malformed("tensor has no valid data_offsets")),
+        };
+        let (Some(dtype), Some(shape)) = (dtype, shape) else {
+            return Err(malformed("tensor has no dtype or shape"));
+        };
+        tensors.push((name, TensorInfo { dtype, shape, data }));
+        Ok(())
+    })?;
+    json.whitespace();
+    if json.pos != json.src.len() {
+        return Err(malformed("trailing bytes after the header object"));
+    }
+    Ok(tensors)
+}
+
+fn malformed(reason: &'static str) -> SafetensorsError {
+    SafetensorsError::Malformed { reason }
+}
+
+/// Just enough JSON for safetensors headers: objects, strings and
+/// unsigned integer arrays are read, any other value can be skipped.
+struct Json<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl Json<'_> {
+    fn whitespace(&mut self) {
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// Next non-whitespace byte, not consumed.
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        self.src.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), SafetensorsError> {
+        if self.peek() != Some(byte) {
+            return Err(malformed("header is not valid JSON"));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// `{ "key": value, ... }`, calling `field` to read each value.
+    fn object(
+        &mut self,
+        mut field: impl FnMut(&mut Self, String) -> Result<(), SafetensorsError>,
+    ) -> Result<(), SafetensorsError> {
+        self.expect(b'{')?;
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            field(self, key)?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(malformed("header is not valid JSON")),
+            }
+        }
+    }
+
+    /// `[ value, ... ]`, calling `item` to read each value.
+    fn array(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<(), SafetensorsError>,
+    ) -> Result<(), SafetensorsError> {
+        self.expect(b'[')?;
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(malformed("header is not valid JSON")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SafetensorsError> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let byte = *self
+                .src
+                .get(self.pos)
+                .ok_or(malformed("unterminated string in header"))?;
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self
+                        .src
+                        .get(self.pos)
+                        .ok_or(malformed("unterminated string in header"))?;
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' | b'\\' | b'/' => escape as char,
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(malformed("invalid escape in header string")),
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => out.push(byte),
+            }
+        }
+        String::from_utf8(out).map_err(|_| malformed("header string is not UTF-8"))
+    }
+
+    /// The character of a `\uXXXX` escape (after the `u`), joining a
+    /// surrogate pair.
+    fn unicode_escape(&mut self) -> Result<char, SafetensorsError> {
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if self.src.get(self.pos..self.pos + 2) != Some(b"\\u") {
+                return Err(malformed("unpaired surrogate in header string"));
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            let low = low
+                .checked_sub(0xdc00)
+                .filter(|&low| low < 0x400)
+                .ok_or(malformed("unpaired surrogate in header string"))?;
+            0x10000 + ((high - 0xd800) << 10) + low
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or(malformed("invalid escape in header string"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, SafetensorsError> {
+        let digits = self
+            .src
+            .get(self.pos..self.pos + 4)
+            .filter(|d| d.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or(malformed("invalid escape in header string"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn uint(&mut self) -> Result<usize, SafetensorsError> {
+        self.whitespace();
+        let start = self.pos;
+        while self.src.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.src[start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or(malformed("expected an unsigned integer in header"))
+    }
+
+    fn uints(&mut self) -> Result<Vec<usize>, SafetensorsError> {
+        let mut values = Vec::new();
+        self.array(|json| {
+            values.push(json.uint()?);
+            Ok(())
+        })?;
+        Ok(values)
+    }
+
+    fn skip_value(&mut self) -> Result<(), SafetensorsError> {
+        self.skip_nested(0)
+    }
+
+    /// `skip_value` inside `depth` arrays or objects, refusing to recurse
+    /// past `MAX_DEPTH`.
+    fn skip_nested(&mut self, depth: usize) -> Result<(), SafetensorsError> {
+        if depth > MAX_DEPTH {
+            return Err(malformed("header nests values too deeply"));
+        }
+        match self.peek() {
+            Some(b'"') => self.string().map(drop),
+            Some(b'{') => self.object(|json, _| json.skip_nested(depth + 1)),
+            Some(b'[') => self.array(|json| json.skip_nested(depth + 1)),
+            Some(_) => {
+                // Number, true, false or null
+                let start = self.pos;
+                while self
+                    .src
+                    .get(self.pos)
+                    .is_some_and(|b| b.is_ascii_alphanumeric() || b"+-.".contains(b))
+                {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return Err(malformed("header is not valid JSON"));
+                }
+                Ok(())
+            }
+            None => Err(malformed("header is not valid JSON")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::scratch_path;
+
+    fn header(json: &str) -> Result<Vec<(String, TensorInfo)>, SafetensorsError> {
+        parse_header(json.as_bytes())
+    }
+
+    /// A header with one tensor named by the JSON string body `name`.
+    fn named(name: &str) -> Result<String, SafetensorsError> {
+        let json = format!(r#"{{"{name}":{{"dtype":"F32","shape":[0],"data_offsets":[0,0]}}}}"#);
+        Ok(header(&json)?.remove(0).0)
+    }
+
+    fn is_malformed<T>(result: Result<T, SafetensorsError>) -> bool {
+        matches!(result, Err(SafetensorsError::Malformed { .. }))
+    }
+
+    #[test]
+    fn escapes_decode() {
+        assert_eq!(named(r#"a\"\\\/\n\t"#).unwrap(), "a\"\\/\n\t");
+        assert_eq!(named(r"\u00e9\u4E2D").unwrap(), "é中");
+        assert_eq!(named(r"\ud83d\ude00").unwrap(), "😀");
+        assert_eq!(named(r"\udbff\udfff").unwrap(), "\u{10ffff}");
+    }
+
+    #[test]
+    fn bad_surrogates_are_malformed() {
+        for name in [
+            r"\ud83d",
+            r"\ud83dx",
+            r"\ud83d\u0041",
+            r"\ud83d\ud83d",
+            r"\ud83d\ue000",
+            r"\ud83d\uffff",
+            r"\ude00",
+            r"\u+abc",
+            r"\u12",
+            r"\uzzzz",
+        ] {
+            assert!(is_malformed(named(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn deep_metadata_is_malformed_without_overflowing_the_stack() {
+        let nested = |depth: usize| {
+            let value = "[".repeat(depth) + &"]".repeat(depth);
+            format!(r#"{{"__metadata__":{{"a":{value}}}}}"#)
+        };
+        assert!(header(&nested(MAX_DEPTH)).unwrap().is_empty());
+        assert!(is_malformed(header(&nested(MAX_DEPTH + 1))));
+        assert!(is_malformed(header(&nested(1_000_000))));
+        let objects = r#"{"a":"#.repeat(100_000);
+        assert!(is_malformed(header(&format!(
+            r#"{{"__metadata__":{objects}}}"#
+        ))));
+    }
+
+    #[test]
+    fn tensors_load_from_a_file() {
+        let rows: Vec<f32> = (0..12).map(|i| i as f32 * 0.25).collect();
+        let json = r#"{"__metadata__":{"format":"pt","x":[1,{"y":null}]},"#.to_string()
+            + r#""q\u00e9":{"dtype":"F32","shape":[3,4],"data_offsets":[0,48]}}"#;
+        let mut bytes = (json.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(json.as_bytes());
+        bytes.extend(rows.iter().flat_map(|x| x.to_le_bytes()));
+        let path = scratch_path("safetensors-query");
+        std::fs::write(&path, &bytes).unwrap();
+        let query = QueryEmbeddings::from_safetensors(&path, "qé");
+        std::fs::remove_file(&path).unwrap();
+        let query = query.unwrap();
+        assert_eq!((query.len(), query.dim()), (3, 4));
+        assert_eq!(query.data(), &rows[..]);
+    }
+}
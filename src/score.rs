@@ -0,0 +1,3077 @@
+//! MaxSim scoring on row-major `[tokens, dim]` embeddings.
+//!
+//! For every query token, take the highest dot product against any document
+//! token, then sum over query tokens. The similarities come from one GEMM
+//! per document: libxsmm (JIT kernel or SGEMM fallback) with `use-libxsmm`,
+//! otherwise the current `Backend`'s sgemm.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::{DerefMut, Range};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
+
+use rayon::iter::Either;
+use rayon::prelude::*;
+
+use crate::aligned::AlignedVec;
+use crate::backend::Backend;
+use crate::collection::{DocId, Documents, QueryBatch, QueryEmbeddings, TokenMask, TokenWeights};
+#[cfg(libxsmm)]
+use crate::gemm::Gemm;
+#[cfg(libxsmm)]
+use crate::libxsmm_bindings::{Beta, Prefetch, Transpose};
+use crate::norm::{max_row_norm, row_norms};
+use crate::packed::Operand;
+use crate::reduce::{fold_row_maxes, lane_sum, masked_weighted_sum, weighted_sum};
+use crate::scorer::{Aggregation, Direction, LoopOrder, Partitioning, Precision};
+use crate::scratch::{CallScratch, Scratch, ScratchPool};
+use crate::simd::{simd_argmax, simd_max_avx2};
+use crate::stats::{timed, Phase};
+use crate::topk::{SearchHit, TopK};
+
+/// Why a scoring call was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScoreError {
+    /// A buffer's length is not `tokens * dim`.
+    DimMismatch {
+        operand: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// The document has no tokens, so no query token has a best match.
+    EmptyDocument,
+    /// Query and documents have different embedding dimensions.
+    EmbeddingDim { query: usize, docs: usize },
+    /// Document offsets do not start at 0 or decrease at document `doc`.
+    InvalidOffsets { doc: usize },
+    /// A query mask covers a different number of tokens than the query.
+    MaskLength { mask: usize, tokens: usize },
+    /// Query token weights cover a different number of tokens than the
+    /// query.
+    WeightsLength { weights: usize, tokens: usize },
+    /// Document ids cover a different number of documents than the batch.
+    IdsLength { ids: usize, docs: usize },
+    /// The CPU cannot score in `precision` and the fallback policy is
+    /// `Fallback::Error`.
+    Unsupported { precision: Precision },
+    /// The scorer's thread pool could not be built.
+    ThreadPool { message: String },
+    /// A document was added under an id the index already holds.
+    DuplicateId { id: u64 },
+    /// A candidate id names no document, and the policy was
+    /// `OnMissing::Error`.
+    UnknownId { id: u64 },
+    /// `n_queries * k` heap entries exceed `top_k_batch_limit()`.
+    HeapLimit { entries: usize, limit: usize },
+    /// Documents with tokens were compressed against no centroids.
+    EmptyCodebook,
+    /// A document is longer than the largest length bucket, and the
+    /// policy was `OnOverflow::Error`.
+    DocumentTooLong { tokens: usize, max: usize },
+    /// A packed store holds documents as another GEMM operand than the
+    /// scorer was configured for.
+    OperandMismatch { stored: Operand, scorer: Operand },
+    /// A long-running operation stopped because its cancel flag was set.
+    Cancelled,
+    /// Scorer options that cannot apply together, or not to the store.
+    IncompatibleConfig { reason: String },
+    /// `set_backend` named a backend this build does not have.
+    UnavailableBackend { backend: Backend },
+}
+
+impl std::fmt::Display for ScoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScoreError::DimMismatch {
+                operand,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has {} values, expected tokens * dim = {}",
+                operand, actual, expected
+            ),
+            ScoreError::EmptyDocument => write!(f, "document has no tokens"),
+            ScoreError::EmbeddingDim { query, docs } => write!(
+                f,
+                "query embeddings have dim {}, documents have dim {}",
+                query, docs
+            ),
+            ScoreError::InvalidOffsets { doc } => {
+                write!(f, "invalid document offsets at document {}", doc)
+            }
+            ScoreError::MaskLength { mask, tokens } => {
+                write!(f, "query mask covers {} tokens, query has {}", mask, tokens)
+            }
+            ScoreError::WeightsLength { weights, tokens } => write!(
+                f,
+                "query weights cover {} tokens, query has {}",
+                weights, tokens
+            ),
+            ScoreError::IdsLength { ids, docs } => {
+                write!(f, "{} document ids for a batch of {} documents", ids, docs)
+            }
+            ScoreError::Unsupported { precision } => {
+                write!(f, "{} scoring is not supported on this CPU", precision)
+            }
+            ScoreError::ThreadPool { message } => {
+                write!(f, "failed to build scoring thread pool: {}", message)
+            }
+            ScoreError::DuplicateId { id } => write!(f, "document id {} is already in use", id),
+            ScoreError::UnknownId { id } => write!(f, "no document with id {}", id),
+            ScoreError::HeapLimit { entries, limit } => write!(
+                f,
+                "batched top-k needs {} heap entries per worker, limit is {}",
+                entries, limit
+            ),
+            ScoreError::EmptyCodebook => write!(f, "no centroids to compress documents against"),
+            ScoreError::DocumentTooLong { tokens, max } => write!(
+                f,
+                "document has {} tokens, the largest bucket holds {}",
+                tokens, max
+            ),
+            ScoreError::OperandMismatch { stored, scorer } => write!(
+                f,
+                "documents are packed as GEMM operand {:?}, the scorer expects {:?}",
+                stored, scorer
+            ),
+            ScoreError::IncompatibleConfig { reason } => {
+                write!(f, "incompatible scorer configuration: {}", reason)
+            }
+            ScoreError::Cancelled => write!(f, "operation cancelled"),
+            ScoreError::UnavailableBackend { backend } => {
+                write!(f, "the {} backend is not available in this build", backend)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScoreError {}
+
+/// Check that `data` holds exactly `tokens` rows of `dim` values.
+pub(crate) fn check_len<T>(
+    operand: &'static str,
+    data: &[T],
+    tokens: usize,
+    dim: usize,
+) -> Result<(), ScoreError> {
+    if data.len() != tokens * dim {
+        return Err(ScoreError::DimMismatch {
+            operand,
+            expected: tokens * dim,
+            actual: data.len(),
+        });
+    }
+    Ok(())
+}
+
+/// MaxSim score of one query against one document.
+///
+/// `query` is `[q_len, dim]` and `doc` is `[d_len, dim]`, both row-major.
+pub fn maxsim_score(
+    query: &[f32],
+    q_len: usize,
+    doc: &[f32],
+    d_len: usize,
+    dim: usize,
+) -> Result<f32, ScoreError> {
+    check_len("query", query, q_len, dim)?;
+    check_len("doc", doc, d_len, dim)?;
+    if d_len == 0 {
+        return Err(ScoreError::EmptyDocument);
+    }
+    if q_len == 0 || dim == 0 {
+        // No query tokens to sum, or every similarity is an empty dot product
+        return Ok(0.0);
+    }
+
+    let mut scratch = AlignedVec::new();
+    Ok(DocScorer::new(q_len, d_len, dim).score(query, doc, TokenWeights::default(), &mut scratch))
+}
+
+/// `maxsim_score` plus, for every query token, the index of the document
+/// token it matched (the first one on ties).
+///
+/// Indices are token positions in `doc` as passed in, regardless of how the
+/// scorer tiles it.
+pub fn maxsim_score_with_matches(
+    query: &[f32],
+    q_len: usize,
+    doc: &[f32],
+    d_len: usize,
+    dim: usize,
+) -> Result<(f32, Vec<u32>), ScoreError> {
+    check_len("query", query, q_len, dim)?;
+    check_len("doc", doc, d_len, dim)?;
+    if d_len == 0 {
+        return Err(ScoreError::EmptyDocument);
+    }
+    if q_len == 0 || dim == 0 {
+        // All similarities are 0, so every query token matches token 0
+        return Ok((0.0, vec![0; q_len]));
+    }
+
+    let mut scratch = AlignedVec::new();
+    Ok(DocScorer::new(q_len, d_len, dim).score_with_matches(query, doc, &mut scratch))
+}
+
+/// Each query token's best similarity against `doc`, written to `out`
+/// (`q_len` values): the vector `maxsim_score` sums.
+///
+/// On the fused path `out` is the running max itself, so nothing is copied
+/// out of scratch.
+pub fn maxsim_per_token(
+    query: &[f32],
+    q_len: usize,
+    doc: &[f32],
+    d_len: usize,
+    dim: usize,
+    out: &mut [f32],
+) -> Result<(), ScoreError> {
+    check_len("query", query, q_len, dim)?;
+    check_len("doc", doc, d_len, dim)?;
+    check_len("out", out, q_len, 1)?;
+    if d_len == 0 {
+        return Err(ScoreError::EmptyDocument);
+    }
+    if q_len == 0 || dim == 0 {
+        out.fill(0.0);
+        return Ok(());
+    }
+
+    let scorer = DocScorer::new(q_len, d_len, dim);
+    let mut work = AlignedVec::new();
+    work.resize(scorer.work_len(), 0.0);
+    scorer.fill_maxes(query, doc, out, &mut work);
+    Ok(())
+}
+
+/// `maxsim_per_token` for every document, into `out` as row-major
+/// `[docs.len(), query.len()]`.
+///
+/// Documents are bucketed by length as in `maxsim_score_batch`. Masked query
+/// tokens, and every token against an empty document, are written as 0, so
+/// each row sums to the document's `maxsim_score_batch` score. Query token
+/// weights are not applied.
+pub fn maxsim_per_token_batch<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    out: &mut [f32],
+) -> Result<(), ScoreError> {
+    check_dims(query, docs)?;
+    let q_len = query.len();
+    check_len("out", out, docs.len(), q_len)?;
+    if q_len == 0 {
+        return Ok(());
+    }
+    let (q_data, valid, tokens) = query.active();
+    let dim = query.dim();
+
+    // Hand each document its output row, grouped into length buckets
+    let order = length_order(docs.len(), |i| docs.doc_len(i));
+    let mut rows: Vec<Option<&mut [f32]>> = out.chunks_exact_mut(q_len).map(Some).collect();
+    let mut pending: Vec<(DocId, &mut [f32])> = order
+        .iter()
+        .map(|&i| (i, rows[i].take().unwrap()))
+        .collect();
+    let mut buckets = Vec::new();
+    let mut rest = &mut pending[..];
+    for ids in length_buckets(&order, |i| docs.doc_len(i)) {
+        let (bucket, tail) = rest.split_at_mut(ids.len());
+        buckets.push(bucket);
+        rest = tail;
+    }
+
+    buckets.par_iter_mut().for_each(|bucket| {
+        let d_len = docs.doc_len(bucket[0].0);
+        let scorer = (valid > 0 && d_len > 0 && dim > 0).then(|| DocScorer::new(valid, d_len, dim));
+        bucket
+            .par_iter_mut()
+            .for_each_init(AlignedVec::new, |work, (i, row)| {
+                let Some(scorer) = &scorer else {
+                    row.fill(0.0);
+                    return;
+                };
+                work.resize(scorer.work_len(), 0.0);
+                let (maxes, masked) = row.split_at_mut(valid);
+                scorer.fill_maxes(q_data, docs.doc(*i), maxes, work);
+                masked.fill(0.0);
+                if let Some(mask) = tokens.mask {
+                    for (max_val, _) in maxes.iter_mut().zip(mask).filter(|(_, &m)| !m) {
+                        *max_val = 0.0;
+                    }
+                }
+            });
+    });
+    Ok(())
+}
+
+/// `maxsim_score` when it reaches `threshold`, `None` when it cannot.
+///
+/// The document is scored in `FUSED_BLOCK`-token tiles, in order. Before
+/// each tile the best reachable score is bounded by Cauchy-Schwarz: a query
+/// token ends at its running max or at most its norm times the largest
+/// document-token norm in the remaining tiles. Once that bound is below
+/// `threshold` the rest of the document is skipped. Norms are padded for
+/// rounding, so a document that reaches the threshold is never rejected,
+/// and a returned score equals `maxsim_score`.
+pub fn maxsim_score_threshold(
+    query: &[f32],
+    q_len: usize,
+    doc: &[f32],
+    d_len: usize,
+    dim: usize,
+    threshold: f32,
+) -> Result<Option<f32>, ScoreError> {
+    check_len("query", query, q_len, dim)?;
+    check_len("doc", doc, d_len, dim)?;
+    if d_len == 0 {
+        return Err(ScoreError::EmptyDocument);
+    }
+    if q_len == 0 || dim == 0 {
+        return Ok((0.0 >= threshold).then_some(0.0));
+    }
+
+    let slack = norm_bound_slack(dim, 1);
+    let q_norms: Vec<f32> = row_norms(query, dim).map(|n| n * slack).collect();
+    // remaining[t]: largest doc-token norm in tiles t..
+    let mut remaining: Vec<f32> = doc
+        .chunks(FUSED_BLOCK * dim)
+        .map(|tile| max_row_norm(tile, dim))
+        .collect();
+    for t in (1..remaining.len()).rev() {
+        remaining[t - 1] = remaining[t - 1].max(remaining[t]);
+    }
+
+    let block = (d_len >= FUSED_BLOCK).then(|| SimilarityGemm::new(q_len, FUSED_BLOCK, dim));
+    let tail_len = d_len % FUSED_BLOCK;
+    let tail = (tail_len > 0).then(|| SimilarityGemm::new(q_len, tail_len, dim));
+    let mut tile = AlignedVec::new();
+    tile.resize(q_len * FUSED_BLOCK.min(d_len), 0.0);
+    let mut max_vals = vec![f32::NEG_INFINITY; q_len];
+
+    for (block_doc, &reachable) in doc.chunks(FUSED_BLOCK * dim).zip(&remaining) {
+        let bound: f32 = max_vals
+            .iter()
+            .zip(&q_norms)
+            .map(|(&max_val, &q_norm)| max_val.max(q_norm * reachable))
+            .sum();
+        if bound < threshold {
+            return Ok(None);
+        }
+
+        let block_len = block_doc.len() / dim;
+        let gemm = match &tail {
+            Some(tail) if block_len < FUSED_BLOCK => tail,
+            _ => block
+                .as_ref()
+                .expect("full tiles need d_len >= FUSED_BLOCK"),
+        };
+        let tile = &mut tile[..q_len * block_len];
+        gemm.run(query, block_doc, tile);
+        for (max_val, sims) in max_vals.iter_mut().zip(tile.chunks_exact(block_len)) {
+            *max_val = max_val.max(simd_max_avx2(sims));
+        }
+    }
+    let score = weighted_sum(&max_vals, None);
+    Ok((score >= threshold).then_some(score))
+}
+
+/// `maxsim_score` with query tokens masked out: only tokens whose `mask`
+/// entry is `true` are summed, so a fully masked query scores 0.
+///
+/// When the unmasked tokens form a prefix, the masked rows are dropped
+/// before the GEMM; otherwise they are computed and skipped in the sum.
+pub fn maxsim_score_masked(
+    query: &[f32],
+    q_len: usize,
+    mask: &[bool],
+    doc: &[f32],
+    d_len: usize,
+    dim: usize,
+) -> Result<f32, ScoreError> {
+    check_len("query", query, q_len, dim)?;
+    if mask.len() != q_len {
+        return Err(ScoreError::MaskLength {
+            mask: mask.len(),
+            tokens: q_len,
+        });
+    }
+    let mask = match TokenMask::from_bools(mask) {
+        TokenMask::Prefix(valid) => {
+            return maxsim_score(&query[..valid * dim], valid, doc, d_len, dim)
+        }
+        TokenMask::Tokens(mask) => mask,
+    };
+    check_len("doc", doc, d_len, dim)?;
+    if d_len == 0 {
+        return Err(ScoreError::EmptyDocument);
+    }
+    if dim == 0 {
+        return Ok(0.0);
+    }
+
+    let mut scratch = AlignedVec::new();
+    let tokens = TokenWeights {
+        mask: Some(&mask),
+        weights: None,
+    };
+    Ok(DocScorer::new(q_len, d_len, dim).score(query, doc, tokens, &mut scratch))
+}
+
+/// MaxSim score of `query` against every document, in collection order.
+///
+/// Documents are grouped by token count so each distinct length sets up its
+/// GEMM once; groups and the documents within them run on the rayon pool.
+/// Every document goes through the same kernel as `maxsim_score`, so the
+/// scores are identical to scoring the pairs one by one, unless stacking is
+/// turned on (see `set_stack_threshold`).
+pub fn maxsim_score_batch<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+) -> Result<Vec<f32>, ScoreError> {
+    score_batch_aggregated(
+        query,
+        docs,
+        Reduction::default(),
+        Tiling::default(),
+        Partitioning::default(),
+        ScratchPool::default().call(None),
+    )
+}
+
+/// `maxsim_score_batch` reducing the similarity matrix with `reduction`,
+/// in `tiling`'s tiles, split across the pool per `partitioning`, in
+/// buffers from `scratch`.
+pub(crate) fn score_batch_aggregated<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    reduction: Reduction,
+    tiling: Tiling,
+    partitioning: Partitioning,
+    scratch: CallScratch<'_>,
+) -> Result<Vec<f32>, ScoreError> {
+    check_dims(query, docs)?;
+    let order = length_order(docs.len(), |i| docs.doc_len(i));
+    let buckets = length_buckets(&order, |i| docs.doc_len(i));
+    trace_span!(
+        DEBUG,
+        "maxsim.score_batch",
+        docs = docs.len(),
+        buckets = buckets.len(),
+        q_len = query.len(),
+        dim = docs.dim()
+    );
+
+    let mut scores = vec![0.0f32; docs.len()];
+    let scored = score_buckets(
+        query,
+        docs,
+        &buckets,
+        reduction,
+        tiling,
+        partitioning,
+        scratch,
+    );
+    for (i, score) in scored.collect::<Vec<_>>() {
+        scores[i] = score;
+    }
+    Ok(scores)
+}
+
+/// The `k` best documents for `query`, sorted by descending score with ties
+/// broken by ascending doc id.
+///
+/// Each worker keeps a k-sized heap while scoring its share of the
+/// collection and the heaps are merged at the end, so no full score vector
+/// is allocated. The result equals scoring everything and sorting.
+pub fn maxsim_top_k<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    k: usize,
+) -> Result<Vec<(DocId, f32)>, ScoreError> {
+    top_k_aggregated(
+        query,
+        docs,
+        k,
+        Reduction::default(),
+        Tiling::default(),
+        Partitioning::default(),
+        ScratchPool::default().call(None),
+    )
+}
+
+/// `maxsim_top_k` reporting each document by its external id
+/// (`Documents::id`) with its rank.
+pub fn maxsim_search<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    k: usize,
+) -> Result<Vec<SearchHit>, ScoreError> {
+    Ok(top_k_heap(query, docs, k, Reduction::default())?.into_hits(|i| docs.id(i)))
+}
+
+/// `maxsim_top_k` reducing the similarity matrix with `reduction`, in
+/// `tiling`'s tiles, split across the pool per `partitioning`, in buffers
+/// from `scratch`.
+pub(crate) fn top_k_aggregated<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    k: usize,
+    reduction: Reduction,
+    tiling: Tiling,
+    partitioning: Partitioning,
+    scratch: CallScratch<'_>,
+) -> Result<Vec<(DocId, f32)>, ScoreError> {
+    let top = top_k_buckets(query, docs, k, reduction, tiling, partitioning, scratch)?;
+    Ok(top.into_sorted_vec())
+}
+
+/// The unsorted `TopK` behind `top_k_aggregated`, in default tiles.
+pub(crate) fn top_k_heap<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    k: usize,
+    reduction: Reduction,
+) -> Result<TopK, ScoreError> {
+    top_k_buckets(
+        query,
+        docs,
+        k,
+        reduction,
+        Tiling::default(),
+        Partitioning::default(),
+        ScratchPool::default().call(None),
+    )
+}
+
+fn top_k_buckets<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    k: usize,
+    reduction: Reduction,
+    tiling: Tiling,
+    partitioning: Partitioning,
+    scratch: CallScratch<'_>,
+) -> Result<TopK, ScoreError> {
+    check_dims(query, docs)?;
+    if k == 0 {
+        return Ok(TopK::new(0));
+    }
+    let order = length_order(docs.len(), |i| docs.doc_len(i));
+    let buckets = length_buckets(&order, |i| docs.doc_len(i));
+    trace_span!(
+        DEBUG,
+        "maxsim.top_k",
+        docs = docs.len(),
+        buckets = buckets.len(),
+        q_len = query.len(),
+        k
+    );
+
+    Ok(score_buckets(
+        query,
+        docs,
+        &buckets,
+        reduction,
+        tiling,
+        partitioning,
+        scratch,
+    )
+    .fold(
+        || TopK::new(k),
+        |mut top, (i, score)| {
+            top.push(i, score);
+            top
+        },
+    )
+    .reduce(|| TopK::new(k), TopK::merge))
+}
+
+/// MaxSim score of every query in `queries` against every document, as a
+/// row-major `[n_queries, n_docs]` matrix.
+///
+/// The loop is document-major: each document is read once and every query
+/// is scored against it while it is still in cache, so the document store
+/// is streamed through memory once rather than once per query. Rayon splits
+/// each length group into blocks of documents; queries are never split
+/// across workers. Each entry uses the same kernel as `maxsim_score_batch`,
+/// so row `q` equals scoring query `q` on its own.
+pub fn maxsim_score_matrix<D: Documents + ?Sized>(
+    queries: &QueryBatch,
+    docs: &D,
+) -> Result<Vec<f32>, ScoreError> {
+    for query in queries.queries() {
+        check_dims(query, docs)?;
+    }
+    let order = length_order(docs.len(), |i| docs.doc_len(i));
+    let buckets = length_buckets(&order, |i| docs.doc_len(i));
+    trace_span!(
+        DEBUG,
+        "maxsim.score_matrix",
+        docs = docs.len(),
+        buckets = buckets.len(),
+        queries = queries.len()
+    );
+    let dim = docs.dim();
+    let batch = &BatchQueries::new(queries);
+
+    let columns: Vec<(DocId, Vec<f32>)> = buckets
+        .par_iter()
+        .flat_map(|ids| {
+            trace_span!(
+                DEBUG,
+                "maxsim.bucket",
+                d_len = docs.doc_len(ids[0]),
+                docs = ids.len()
+            );
+            let scorers = batch.scorers(docs.doc_len(ids[0]), dim);
+            ids.par_iter()
+                .map_init(AlignedVec::new, move |scratch, &i| {
+                    let doc = (docs.doc(i), docs.padding(i));
+                    let column = batch.score(&scorers, doc, scratch).collect();
+                    (i, column)
+                })
+        })
+        .collect();
+
+    let n_docs = docs.len();
+    let mut scores = vec![0.0f32; queries.len() * n_docs];
+    for (i, column) in columns {
+        for (qi, score) in column.into_iter().enumerate() {
+            scores[qi * n_docs + i] = score;
+        }
+    }
+    Ok(scores)
+}
+
+/// Default for `set_top_k_batch_limit`: 16M heap entries.
+pub const DEFAULT_TOP_K_BATCH_LIMIT: usize = 1 << 24;
+
+static TOP_K_BATCH_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_TOP_K_BATCH_LIMIT);
+
+/// Largest `n_queries * k` `maxsim_top_k_batch` accepts. Each rayon worker
+/// holds that many heap entries, so this caps the heaps' memory.
+pub fn set_top_k_batch_limit(entries: usize) {
+    TOP_K_BATCH_LIMIT.store(entries, AtomicOrdering::Relaxed);
+}
+
+pub fn top_k_batch_limit() -> usize {
+    TOP_K_BATCH_LIMIT.load(AtomicOrdering::Relaxed)
+}
+
+/// `maxsim_search` for every query in `queries`, in batch order.
+///
+/// Document-major like `maxsim_score_matrix`: each document is read once
+/// and its score for every query is pushed into that query's heap, so no
+/// score matrix is materialized. Each worker keeps one k-sized heap per
+/// query; `n_queries * k` must not exceed `top_k_batch_limit()`. Every
+/// query's hits equal `maxsim_search` on its own.
+pub fn maxsim_top_k_batch<D: Documents + ?Sized>(
+    queries: &QueryBatch,
+    docs: &D,
+    k: usize,
+) -> Result<Vec<Vec<SearchHit>>, ScoreError> {
+    for query in queries.queries() {
+        check_dims(query, docs)?;
+    }
+    let limit = top_k_batch_limit();
+    let entries = queries.len().saturating_mul(k);
+    if entries > limit {
+        return Err(ScoreError::HeapLimit { entries, limit });
+    }
+    let order = length_order(docs.len(), |i| docs.doc_len(i));
+    let buckets = length_buckets(&order, |i| docs.doc_len(i));
+    trace_span!(
+        DEBUG,
+        "maxsim.top_k_batch",
+        docs = docs.len(),
+        buckets = buckets.len(),
+        queries = queries.len(),
+        k
+    );
+    let dim = docs.dim();
+    let batch = &BatchQueries::new(queries);
+    let empty = || vec![TopK::new(k); queries.len()];
+    let merge = |tops: Vec<TopK>, other: Vec<TopK>| -> Vec<TopK> {
+        tops.into_iter()
+            .zip(other)
+            .map(|(a, b)| a.merge(b))
+            .collect()
+    };
+
+    let tops = buckets
+        .par_iter()
+        .map(|ids| {
+            trace_span!(
+                DEBUG,
+                "maxsim.bucket",
+                d_len = docs.doc_len(ids[0]),
+                docs = ids.len()
+            );
+            let scorers = batch.scorers(docs.doc_len(ids[0]), dim);
+            ids.par_iter()
+                .fold(
+                    || (empty(), AlignedVec::new()),
+                    |(mut tops, mut scratch), &i| {
+                        let doc = (docs.doc(i), docs.padding(i));
+                        let scores = batch.score(&scorers, doc, &mut scratch);
+                        for (top, score) in tops.iter_mut().zip(scores) {
+                            top.push(i, score);
+                        }
+                        (tops, scratch)
+                    },
+                )
+                .map(|(tops, _)| tops)
+                .reduce(empty, merge)
+        })
+        .reduce(empty, merge);
+    Ok(tops
+        .into_iter()
+        .map(|top| top.into_hits(|i| docs.id(i)))
+        .collect())
+}
+
+type ActiveQuery<'q> = (&'q [f32], usize, TokenWeights<'q>);
+
+/// The active rows of every query in a batch. Queries of equal length
+/// share a `DocScorer`.
+struct BatchQueries<'q> {
+    /// `QueryEmbeddings::active` per query.
+    active: Vec<ActiveQuery<'q>>,
+    /// Distinct active lengths, ascending.
+    q_lens: Vec<usize>,
+    /// Index into `q_lens` per query.
+    slots: Vec<usize>,
+}
+
+impl<'q> BatchQueries<'q> {
+    fn new(queries: &'q QueryBatch) -> Self {
+        let active: Vec<_> = queries.queries().iter().map(|q| q.active()).collect();
+        let mut q_lens: Vec<usize> = active.iter().map(|&(_, q_len, _)| q_len).collect();
+        q_lens.sort_unstable();
+        q_lens.dedup();
+        let slots = active
+            .iter()
+            .map(|&(_, q_len, _)| q_lens.binary_search(&q_len).unwrap())
+            .collect();
+        Self {
+            active,
+            q_lens,
+            slots,
+        }
+    }
+
+    /// One scorer per distinct query length for documents of `d_len`
+    /// tokens; `None` where every score is 0.
+    fn scorers(&self, d_len: usize, dim: usize) -> Vec<Option<DocScorer>> {
+        self.q_lens
+            .iter()
+            .map(|&q_len| {
+                (q_len > 0 && d_len > 0 && dim > 0).then(|| DocScorer::new(q_len, d_len, dim))
+            })
+            .collect()
+    }
+
+    /// Every query's score against `doc` (rows, bucket padding), in batch
+    /// order.
+    fn score<'a>(
+        &'a self,
+        scorers: &'a [Option<DocScorer>],
+        (doc, padding): (&'a [f32], usize),
+        scratch: &'a mut AlignedVec<f32>,
+    ) -> impl Iterator<Item = f32> + 'a {
+        self.active
+            .iter()
+            .zip(&self.slots)
+            .map(move |(&(q_data, _, tokens), &slot)| match &scorers[slot] {
+                Some(scorer) => scorer.score_padded(q_data, doc, padding, tokens, scratch),
+                None => 0.0,
+            })
+    }
+}
+
+/// How many documents `maxsim_top_k_pruned` scored and how many it ruled
+/// out by their norm bound alone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruneStats {
+    pub scored: usize,
+    pub skipped: usize,
+}
+
+impl PruneStats {
+    fn merge(self, other: PruneStats) -> Self {
+        Self {
+            scored: self.scored + other.scored,
+            skipped: self.skipped + other.skipped,
+        }
+    }
+}
+
+/// `maxsim_top_k` that skips documents which cannot make the top k.
+///
+/// A query token's best match is at most its norm times the document's
+/// largest token norm (`Documents::max_token_norm`, kept from ingest), so
+/// `Σ |w_i| · ‖q_i‖ · max_token_norm(d)` bounds the score without a GEMM
+/// (`w_i` the query token weights, 0 when masked). Documents
+/// are visited by descending bound and skipped once their bound is below
+/// the current k-th score. The bound is padded for rounding and a tie is
+/// never skipped, so the result equals `maxsim_top_k`.
+pub fn maxsim_top_k_pruned<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    k: usize,
+) -> Result<(Vec<(DocId, f32)>, PruneStats), ScoreError> {
+    top_k_pruned(
+        query,
+        docs,
+        k,
+        Tiling::default(),
+        ScratchPool::default().call(None),
+    )
+}
+
+/// `maxsim_top_k_pruned` in `tiling`'s tiles, in buffers from `scratch`.
+pub(crate) fn top_k_pruned<D: Documents + ?Sized>(
+    query: &QueryEmbeddings,
+    docs: &D,
+    k: usize,
+    tiling: Tiling,
+    scratch: CallScratch<'_>,
+) -> Result<(Vec<(DocId, f32)>, PruneStats), ScoreError> {
+    check_dims(query, docs)?;
+    if k == 0 {
+        return Ok((Vec::new(), PruneStats::default()));
+    }
+    trace_span!(
+        DEBUG,
+        "maxsim.top_k_pruned",
+        docs = docs.len(),
+        q_len = query.len(),
+        k
+    );
+    let (q_data, q_len, tokens) = query.active();
+    let dim = query.dim();
+
+    let q_norm_sum: f32 = match dim {
+        0 => 0.0,
+        _ => row_norms(q_data, dim)
+            .enumerate()
+            .map(|(qi, norm)| tokens.weight(qi).abs() * norm)
+            .sum(),
+    };
+    // One more rounding per term when the maxima are weighted
+    let q_norm_sum = q_norm_sum * norm_bound_slack(dim + 1, q_len);
+    let bounds: Vec<f32> = (0..docs.len())
+        .map(|i| q_norm_sum * docs.max_token_norm(i))
+        .collect();
+    let mut order: Vec<DocId> = (0..docs.len()).collect();
+    order.sort_by(|&a, &b| bounds[b].total_cmp(&bounds[a]).then(a.cmp(&b)));
+
+    let (top, stats) = order
+        .par_iter()
+        .fold(
+            || PruneWorker::new(k),
+            |mut worker, &i| {
+                if worker.top.threshold().is_some_and(|kth| bounds[i] < kth) {
+                    worker.stats.skipped += 1;
+                    return worker;
+                }
+                let d_len = docs.doc_len(i);
+                let score = if q_len > 0 && d_len > 0 && dim > 0 {
+                    let scorer = worker
+                        .scorers
+                        .entry(d_len)
+                        .or_insert_with(|| DocScorer::tiled(q_len, d_len, dim, tiling));
+                    scorer.score(q_data, docs.doc(i), tokens, &mut scratch.get().sims)
+                } else {
+                    0.0
+                };
+                worker.top.push(i, score);
+                worker.stats.scored += 1;
+                worker
+            },
+        )
+        .map(|worker| (worker.top, worker.stats))
+        .reduce(
+            || (TopK::new(k), PruneStats::default()),
+            |a, b| (a.0.merge(b.0), a.1.merge(b.1)),
+        );
+    trace_event!(
+        DEBUG,
+        scored = stats.scored,
+        skipped = stats.skipped,
+        "pruned top-k"
+    );
+    Ok((top.into_sorted_vec(), stats))
+}
+
+/// One rayon worker's share of `maxsim_top_k_pruned`.
+struct PruneWorker {
+    top: TopK,
+    stats: PruneStats,
+    /// One scorer per document length seen.
+    scorers: HashMap<usize, DocScorer>,
+}
+
+impl PruneWorker {
+    fn new(k: usize) -> Self {
+        Self {
+            top: TopK::new(k),
+            stats: PruneStats::default(),
+            scorers: HashMap::new(),
+        }
+    }
+}
+
+/// Relative padding for norm-product bounds on MaxSim scores: covers
+/// rounding in the norms, the GEMM's `dim`-term dot products, and summing
+/// `q_len` terms.
+fn norm_bound_slack(dim: usize, q_len: usize) -> f32 {
+    1.0 + 4.0 * (dim + q_len + 2) as f32 * f32::EPSILON
+}
+
+fn check_dims<D: Documents + ?Sized>(query: &QueryEmbeddings, docs: &D) -> Result<(), ScoreError> {
+    check_dim(query, docs.dim())
+}
+
+pub(crate) fn check_dim(query: &QueryEmbeddings, docs_dim: usize) -> Result<(), ScoreError> {
+    if query.dim() != docs_dim {
+        return Err(ScoreError::EmbeddingDim {
+            query: query.dim(),
+            docs: docs_dim,
+        });
+    }
+    Ok(())
+}
+
+/// Ids of `n` docs sorted by token count (stable, so ids ascend within a
+/// length).
+pub(crate) fn length_order(n: usize, doc_len: impl Fn(DocId) -> usize) -> Vec<DocId> {
+    let mut order: Vec<DocId> = (0..n).collect();
+    order.sort_by_key(|&i| doc_len(i));
+    order
+}
+
+/// Runs of equal-length docs in `order`.
+pub(crate) fn length_buckets(order: &[DocId], doc_len: impl Fn(DocId) -> usize) -> Vec<&[DocId]> {
+    order.chunk_by(|&a, &b| doc_len(a) == doc_len(b)).collect()
+}
+
+/// (doc id, score) for every doc in `buckets`, one GEMM setup per bucket.
+/// Buckets up to `tiling`'s stack threshold are scored stacked, their GEMM
+/// groups shared out by work stealing whatever the `partitioning`; so are
+/// the query-major shares of the rest when the query spans more than one
+/// `QUERY_BLOCK` (see `LoopOrder`).
+fn score_buckets<'a, D: Documents + ?Sized>(
+    query: &'a QueryEmbeddings,
+    docs: &'a D,
+    buckets: &'a [&'a [DocId]],
+    reduction: Reduction,
+    tiling: Tiling,
+    partitioning: Partitioning,
+    scratch: CallScratch<'a>,
+) -> impl ParallelIterator<Item = (DocId, f32)> + 'a {
+    let (q_data, q_len, tokens) = query.active();
+    let dim = query.dim();
+    let setup = move |ids: &[DocId]| {
+        // Empty queries and empty documents (or dim 0) score 0 without
+        // touching the GEMM
+        let d_len = docs.doc_len(ids[0]);
+        trace_span!(DEBUG, "maxsim.bucket", d_len, docs = ids.len());
+        (q_len > 0 && d_len > 0 && dim > 0)
+            .then(|| DocScorer::tiled(q_len, d_len, dim, tiling).with_reduction(reduction))
+    };
+    let score = move |scorer: &Option<DocScorer>, scratch: &mut Scratch, i| match scorer {
+        Some(scorer) => {
+            let (doc, padding) = (docs.doc(i), docs.padding(i));
+            scorer.score_padded(q_data, doc, padding, tokens, &mut scratch.sims)
+        }
+        None => 0.0,
+    };
+    let doc_len = move |i| docs.doc_len(i);
+    // Buckets ascend by length, so the stacked ones are a prefix
+    let threshold = tiling.stack_threshold();
+    let stacked = buckets.partition_point(|ids| docs.doc_len(ids[0]) <= threshold);
+    let (short, long) = buckets.split_at(stacked);
+    let short = short.par_iter().flat_map(move |&ids| {
+        let d_len = docs.doc_len(ids[0]);
+        trace_span!(DEBUG, "maxsim.bucket", d_len, docs = ids.len());
+        let scorer = (q_len > 0 && d_len > 0 && dim > 0)
+            .then(|| StackedScorer::new(q_len, d_len, dim, tiling.dims(dim), reduction));
+        let group = scorer
+            .as_ref()
+            .map_or(STACK_MAX_TOKENS, StackedScorer::group);
+        ids.par_chunks(group)
+            .map_init(
+                move || scratch.get(),
+                move |scratch, group| {
+                    // A group is at most STACK_MAX_TOKENS documents
+                    let mut scores = [0.0; STACK_MAX_TOKENS];
+                    if let Some(scorer) = &scorer {
+                        let rows = group.iter().map(|&i| (docs.doc(i), docs.padding(i)));
+                        scorer.score(q_data, rows, tokens, scratch, &mut scores[..group.len()]);
+                    }
+                    group.iter().copied().zip(scores)
+                },
+            )
+            .flat_map_iter(|scored| scored)
+    });
+    let init = move || scratch.get();
+    let tokens_in_all = buckets.iter().map(|ids| ids.len() * doc_len(ids[0])).sum();
+    let long = match tiling.loop_order(q_len, docs.len(), tokens_in_all) {
+        None => Either::Left(score_partitioned(
+            long,
+            doc_len,
+            partitioning,
+            setup,
+            init,
+            score,
+        )),
+        Some(order) => {
+            let setup = move |ids: &[DocId]| {
+                let d_len = docs.doc_len(ids[0]);
+                trace_span!(DEBUG, "maxsim.bucket", d_len, docs = ids.len(), ?order);
+                (d_len > 0 && dim > 0)
+                    .then(|| BlockedScorer::new(q_len, d_len, dim, tiling, reduction))
+            };
+            Either::Right(match order {
+                LoopOrder::QueryMajor => Either::Left(long.par_iter().flat_map(move |&ids| {
+                    let scorer = setup(ids);
+                    let share = scorer.as_ref().map_or(ids.len(), BlockedScorer::share);
+                    ids.par_chunks(share)
+                        .map_init(init, move |scratch, share| {
+                            // A share is at most QUERY_MAJOR_MAX_DOCS documents
+                            let mut scores = [0.0; QUERY_MAJOR_MAX_DOCS];
+                            if let Some(scorer) = &scorer {
+                                let rows = share.iter().map(|&i| (docs.doc(i), docs.padding(i)));
+                                scorer.score_query_major(
+                                    q_data,
+                                    rows,
+                                    tokens,
+                                    scratch,
+                                    &mut scores[..share.len()],
+                                );
+                            }
+                            share.iter().copied().zip(scores)
+                        })
+                        .flat_map_iter(|scored| scored)
+                })),
+                _ => {
+                    let score = move |scorer: &Option<BlockedScorer>, scratch: &mut Scratch, i| {
+                        scorer.as_ref().map_or(0.0, |scorer| {
+                            let (doc, padding) = (docs.doc(i), docs.padding(i));
+                            scorer.score_doc_major(q_data, doc, padding, tokens, scratch)
+                        })
+                    };
+                    Either::Right(score_partitioned(
+                        long,
+                        doc_len,
+                        partitioning,
+                        setup,
+                        init,
+                        score,
+                    ))
+                }
+            })
+        }
+    };
+    long.chain(short)
+}
+
+/// (doc id, score) for every doc in `buckets`, runs of equal-length docs
+/// ascending by length, split across the current pool per `partitioning`.
+/// `setup` prepares a bucket (kernels, stats) once, `init` borrows a
+/// worker's scratch and `score` scores one doc with both.
+pub(crate) fn score_partitioned<'a, S, T, G>(
+    buckets: &'a [&'a [DocId]],
+    doc_len: impl Fn(DocId) -> usize + Sync + 'a,
+    partitioning: Partitioning,
+    setup: impl Fn(&'a [DocId]) -> S + Sync + Send + 'a,
+    init: impl Fn() -> G + Sync + Send + 'a,
+    score: impl Fn(&S, &mut T, DocId) -> f32 + Sync + Send + 'a,
+) -> impl ParallelIterator<Item = (DocId, f32)> + 'a
+where
+    S: Send + Sync + 'a,
+    G: DerefMut<Target = T>,
+{
+    let (init, score) = (Arc::new(init), Arc::new(score));
+    match partitioning {
+        Partitioning::WorkStealing => Either::Left(buckets.par_iter().flat_map(move |&ids| {
+            let state = setup(ids);
+            let (init, score) = (Arc::clone(&init), Arc::clone(&score));
+            ids.par_iter().map_init(
+                move || init(),
+                move |scratch, &i| (i, score(&state, &mut **scratch, i)),
+            )
+        })),
+        Partitioning::TokenBalanced => {
+            let states: Vec<S> = buckets.par_iter().map(|&ids| setup(ids)).collect();
+            let bins = balance_tokens(buckets, doc_len, rayon::current_num_threads());
+            Either::Right(bins.into_par_iter().flat_map_iter(move |bin| {
+                let mut scratch = init();
+                bin.into_iter()
+                    .map(|(b, i)| (i, score(&states[b], &mut *scratch, i)))
+                    .collect::<Vec<_>>()
+            }))
+        }
+    }
+}
+
+/// The docs of `buckets` dealt into `bins` lists of about equal token
+/// counts, as (bucket, doc id): longest first, each to the lightest list.
+/// No list exceeds the lightest by more than one document's tokens.
+fn balance_tokens(
+    buckets: &[&[DocId]],
+    doc_len: impl Fn(DocId) -> usize,
+    bins: usize,
+) -> Vec<Vec<(usize, DocId)>> {
+    let bins = bins.max(1);
+    let mut loads: BinaryHeap<Reverse<(usize, usize)>> =
+        (0..bins).map(|bin| Reverse((0, bin))).collect();
+    let mut dealt = vec![Vec::new(); bins];
+    for (b, ids) in buckets.iter().enumerate().rev() {
+        // Empty documents still cost a call
+        let tokens = doc_len(ids[0]).max(1);
+        for &i in ids.iter() {
+            let Reverse((load, bin)) = loads.pop().expect("at least one bin");
+            dealt[bin].push((b, i));
+            loads.push(Reverse((load + tokens, bin)));
+        }
+    }
+    dealt
+}
+
+/// Documents longer than this many tokens are scored with the fused tiled
+/// path by default (see `set_fused_threshold`).
+pub const DEFAULT_FUSED_THRESHOLD: usize = 512;
+
+/// Document tokens per tile on the fused path when no CPU-specific size
+/// applies (see `default_fused_block`).
+pub const FUSED_BLOCK: usize = 64;
+
+/// Embedding values per similarity GEMM pass above `DIM_BLOCK_THRESHOLD`
+/// (see `default_dim_block`).
+pub const DIM_BLOCK: usize = 256;
+
+/// Largest dim `default_dim_block` leaves in one pass.
+pub const DIM_BLOCK_THRESHOLD: usize = 512;
+
+/// Smallest and largest tile `default_fused_block` picks.
+const FUSED_BLOCK_RANGE: (usize, usize) = (32, 128);
+
+/// Fused tiles ahead whose document rows are prefetched by default (see
+/// `set_prefetch_distance`).
+pub const DEFAULT_PREFETCH_DISTANCE: usize = 1;
+
+/// Fused tiles of fewer document bytes are never prefetched: the hardware
+/// prefetcher keeps up with them within a page.
+pub const PREFETCH_MIN_TILE_BYTES: usize = 4 << 10;
+
+/// Document tokens of a fused tile folded between two shares of its
+/// prefetches.
+const FOLD_COLUMNS: usize = 8;
+
+/// Batch documents of at most this many tokens are stacked by default:
+/// none, so batch scores equal single-pair scores (see
+/// `set_stack_threshold`).
+pub const DEFAULT_STACK_THRESHOLD: usize = 0;
+
+/// Most document tokens one stacked GEMM takes: the widest shape libxsmm
+/// is asked to JIT.
+pub const STACK_MAX_TOKENS: usize = 256;
+
+/// Query tokens per block in a `LoopOrder`: 16 KiB of 128-value tokens,
+/// half of L1.
+pub const QUERY_BLOCK: usize = 32;
+
+/// Fewest documents `LoopOrder::Auto` scores query-major: a lone document
+/// has no neighbour to share a query block with.
+pub const QUERY_MAJOR_MIN_DOCS: usize = 2;
+
+/// Largest average document length `LoopOrder::Auto` scores query-major
+/// by default (see `set_loop_order_crossover`).
+pub const DEFAULT_LOOP_ORDER_CROSSOVER: usize = 256;
+
+/// Document bytes in a worker's query-major share: about L2, so the share
+/// stays cached while every query block passes over it.
+const QUERY_MAJOR_SHARE_BYTES: usize = 1 << 20;
+
+/// Most documents in a query-major share, however short.
+const QUERY_MAJOR_MAX_DOCS: usize = 256;
+
+static FUSED_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_FUSED_THRESHOLD);
+static PREFETCH_DISTANCE: AtomicUsize = AtomicUsize::new(DEFAULT_PREFETCH_DISTANCE);
+static STACK_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_STACK_THRESHOLD);
+static LOOP_ORDER_CROSSOVER: AtomicUsize = AtomicUsize::new(DEFAULT_LOOP_ORDER_CROSSOVER);
+
+/// Score documents longer than `d_len` tokens with the fused path: the doc is
+/// processed in `FUSED_BLOCK`-token tiles, each reduced into a running
+/// per-query-token max and discarded, so the full `[q_len, d_len]`
+/// similarity matrix never exists. Both paths give bit-identical scores.
+pub fn set_fused_threshold(d_len: usize) {
+    FUSED_THRESHOLD.store(d_len, AtomicOrdering::Relaxed);
+}
+
+pub fn fused_threshold() -> usize {
+    FUSED_THRESHOLD.load(AtomicOrdering::Relaxed)
+}
+
+/// On the fused path, prefetch into L2 the document rows of the tile
+/// `tiles` ahead of the one being scored; 0 disables. The prefetches are
+/// spread over the current tile's reduction, after its GEMM, so the rows
+/// arrive while the CPU still has work. libxsmm JIT kernels built with a
+/// prefetch strategy take the rows with the GEMM instead. Tiles under
+/// `PREFETCH_MIN_TILE_BYTES` are not prefetched.
+pub fn set_prefetch_distance(tiles: usize) {
+    PREFETCH_DISTANCE.store(tiles, AtomicOrdering::Relaxed);
+}
+
+pub fn prefetch_distance() -> usize {
+    PREFETCH_DISTANCE.load(AtomicOrdering::Relaxed)
+}
+
+/// In batch scoring, stack runs of documents of at most `d_len` tokens
+/// into one GEMM of up to `STACK_MAX_TOKENS` document tokens, then reduce
+/// each document's own columns, instead of one tiny GEMM per document;
+/// 0, the default, disables. Each document still gets its own maxima, but
+/// the stacked GEMM sums each similarity in another order: a stacked score
+/// is within `q_len * (dim + q_len) * f32::EPSILON` times the largest
+/// `|q|·|d|` over token pairs of `maxsim_score`'s, where an unstacked one
+/// is identical. Top-k, pruned and multi-query results then differ from
+/// one another by as much.
+pub fn set_stack_threshold(d_len: usize) {
+    STACK_THRESHOLD.store(d_len, AtomicOrdering::Relaxed);
+}
+
+pub fn stack_threshold() -> usize {
+    STACK_THRESHOLD.load(AtomicOrdering::Relaxed)
+}
+
+/// Under `LoopOrder::Auto`, score batches averaging at most `d_len` tokens
+/// per document query-major and longer ones doc-major.
+/// `bench_loop_order` measures where the two orders cross.
+pub fn set_loop_order_crossover(d_len: usize) {
+    LOOP_ORDER_CROSSOVER.store(d_len, AtomicOrdering::Relaxed);
+}
+
+pub fn loop_order_crossover() -> usize {
+    LOOP_ORDER_CROSSOVER.load(AtomicOrdering::Relaxed)
+}
+
+/// Fused-path tile for `dim`-value tokens when the scorer sets none: as
+/// many tokens as keep a tile of document rows within L1 (48 KiB on
+/// AVX-512 parts libxsmm recognizes, 32 KiB elsewhere), a multiple of 16
+/// between 32 and 128. `Scorer::autotune` measures instead.
+pub fn default_fused_block(dim: usize) -> usize {
+    #[cfg(libxsmm)]
+    let l1 = match crate::libxsmm_bindings::CpuArch::detect() {
+        crate::libxsmm_bindings::CpuArch::Generic
+        | crate::libxsmm_bindings::CpuArch::Avx2
+        | crate::libxsmm_bindings::CpuArch::Other(_) => 32 << 10,
+        _ => 48 << 10,
+    };
+    #[cfg(not(libxsmm))]
+    let l1 = 32 << 10;
+    if dim == 0 {
+        return FUSED_BLOCK;
+    }
+    let (lo, hi) = FUSED_BLOCK_RANGE;
+    (l1 / (dim * size_of::<f32>()) / 16 * 16).clamp(lo, hi)
+}
+
+/// Similarity GEMM pass for `dim`-value tokens when the scorer sets none:
+/// all of dim up to `DIM_BLOCK_THRESHOLD`, else `DIM_BLOCK` values, so the
+/// query slice and tile rows of a pass stay in L2 on wide (768, 1024)
+/// encoders. `Scorer::autotune_dim_block` measures instead.
+pub fn default_dim_block(dim: usize) -> usize {
+    if dim <= DIM_BLOCK_THRESHOLD {
+        dim
+    } else {
+        DIM_BLOCK
+    }
+}
+
+/// Tile sizes of the f32 path; `None` takes the default for the dim.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Tiling {
+    /// Document tokens per fused tile (`default_fused_block`).
+    pub(crate) tokens: Option<usize>,
+    /// Embedding values per GEMM pass (`default_dim_block`).
+    pub(crate) dims: Option<usize>,
+    /// Loop order over query blocks and document tiles.
+    pub(crate) order: LoopOrder,
+    /// Longest documents scored stacked (`stack_threshold`).
+    pub(crate) stack: Option<usize>,
+}
+
+impl Tiling {
+    pub(crate) fn tokens(self, dim: usize) -> usize {
+        self.tokens.unwrap_or_else(|| default_fused_block(dim))
+    }
+
+    pub(crate) fn stack_threshold(self) -> usize {
+        self.stack.unwrap_or_else(stack_threshold)
+    }
+
+    /// At least one, at most `dim`.
+    pub(crate) fn dims(self, dim: usize) -> usize {
+        self.dims
+            .unwrap_or_else(|| default_dim_block(dim))
+            .clamp(1, dim.max(1))
+    }
+
+    /// The order a batch of `n_docs` documents of `tokens` tokens in all
+    /// runs in against `q_len` query tokens; `None` within one
+    /// `QUERY_BLOCK`, where there is no order to pick.
+    pub(crate) fn loop_order(
+        self,
+        q_len: usize,
+        n_docs: usize,
+        tokens: usize,
+    ) -> Option<LoopOrder> {
+        if q_len <= QUERY_BLOCK {
+            return None;
+        }
+        Some(match self.order {
+            LoopOrder::Auto => {
+                let short = tokens / n_docs.max(1) <= loop_order_crossover();
+                if n_docs >= QUERY_MAJOR_MIN_DOCS && short {
+                    LoopOrder::QueryMajor
+                } else {
+                    LoopOrder::DocMajor
+                }
+            }
+            order => order,
+        })
+    }
+}
+
+/// GEMMs `DocScorer` runs per `d_len`-token document in `block`-token
+/// tiles: one, or one per fused tile.
+pub(crate) fn gemm_calls(d_len: usize, block: usize) -> usize {
+    if d_len > fused_threshold() && d_len > block {
+        d_len.div_ceil(block)
+    } else {
+        1
+    }
+}
+
+/// Documents of `d_len` tokens one stacked GEMM takes: at least one.
+pub(crate) fn stack_group(d_len: usize) -> usize {
+    (STACK_MAX_TOKENS / d_len.max(1)).max(1)
+}
+
+/// MaxSim for documents of one short fixed length, `group` at a time: their
+/// rows are copied into one operand, one doc-major GEMM computes every
+/// similarity, and each document's `d_len` columns are reduced on their
+/// own. A short last group runs the full GEMM and ignores the extra
+/// columns.
+pub(crate) struct StackedScorer {
+    q_len: usize,
+    d_len: usize,
+    dim: usize,
+    group: usize,
+    gemm: SimilarityGemm,
+    reduction: Reduction,
+}
+
+impl StackedScorer {
+    pub(crate) fn new(
+        q_len: usize,
+        d_len: usize,
+        dim: usize,
+        dim_block: usize,
+        reduction: Reduction,
+    ) -> Self {
+        let group = stack_group(d_len);
+        trace_span!(TRACE, "maxsim.kernel_dispatch", q_len, d_len, dim, group);
+        Self {
+            q_len,
+            d_len,
+            dim,
+            group,
+            gemm: SimilarityGemm::doc_major(q_len, group * d_len, dim, dim_block, false),
+            reduction,
+        }
+    }
+
+    /// Documents per GEMM.
+    pub(crate) fn group(&self) -> usize {
+        self.group
+    }
+
+    /// Scores of `docs` (at most `group`, each `[d_len, dim]` with its
+    /// bucket padding) into `out`, in order.
+    pub(crate) fn score<'d>(
+        &self,
+        query: &[f32],
+        docs: impl Iterator<Item = (&'d [f32], usize)>,
+        tokens: TokenWeights,
+        scratch: &mut Scratch,
+        out: &mut [f32],
+    ) {
+        let rows = self.d_len * self.dim;
+        scratch.stack.resize(self.group * rows, 0.0);
+        let mut paddings = [0; STACK_MAX_TOKENS];
+        timed(Phase::Convert, || {
+            let dsts = scratch.stack.chunks_exact_mut(rows).zip(&mut paddings);
+            for ((dst, padding), (doc, doc_padding)) in dsts.zip(docs) {
+                dst.copy_from_slice(doc);
+                *padding = doc_padding;
+            }
+        });
+        let tile = self.d_len * self.q_len;
+        scratch.sims.resize(self.group * tile, 0.0);
+        self.gemm.run(query, &scratch.stack, &mut scratch.sims);
+
+        let doc_part = self.reduction.doc_maxes_len(self.d_len);
+        scratch.maxes.resize(self.q_len + doc_part, 0.0);
+        let (maxes, doc_maxes) = scratch.maxes.split_at_mut(self.q_len);
+        let sims = &scratch.sims;
+        timed(Phase::Reduce, || {
+            let docs = out.iter_mut().zip(&paddings);
+            for ((score, &padding), sims) in docs.zip(sims.chunks_exact(tile)) {
+                maxes.fill(f32::NEG_INFINITY);
+                fold_row_maxes(sims, maxes);
+                if !doc_maxes.is_empty() {
+                    doc_token_maxes(sims, tokens.mask, doc_maxes);
+                }
+                *score = self.reduction.score(maxes, doc_maxes, padding, tokens);
+            }
+        });
+    }
+}
+
+/// MaxSim for documents of one fixed length against a query of more than
+/// one `QUERY_BLOCK`: one doc-major GEMM per query block and document tile
+/// (the whole document, or fused tiles past `fused_threshold()`). Every
+/// maximum folds its blocks and tiles in the same order whichever
+/// `LoopOrder` walks them, so the two orders give identical scores.
+pub(crate) struct BlockedScorer {
+    q_len: usize,
+    d_len: usize,
+    dim: usize,
+    /// Document tokens per tile.
+    tile: usize,
+    /// By (short query block, short tile); the short ones only where
+    /// `QUERY_BLOCK` or `tile` leaves a remainder.
+    gemms: [Option<SimilarityGemm>; 4],
+    reduction: Reduction,
+}
+
+impl BlockedScorer {
+    pub(crate) fn new(
+        q_len: usize,
+        d_len: usize,
+        dim: usize,
+        tiling: Tiling,
+        reduction: Reduction,
+    ) -> Self {
+        let (block, dims) = (tiling.tokens(dim).max(1), tiling.dims(dim));
+        let tile = if d_len > fused_threshold() && d_len > block {
+            block
+        } else {
+            d_len
+        };
+        trace_span!(
+            TRACE,
+            "maxsim.kernel_dispatch",
+            q_len,
+            d_len,
+            dim,
+            tile,
+            dims
+        );
+        let query_block = QUERY_BLOCK.min(q_len);
+        let gemm = |q: usize, d: usize| {
+            (q > 0 && d > 0).then(|| SimilarityGemm::doc_major(q, d, dim, dims, false))
+        };
+        let (q_tail, d_tail) = (q_len % query_block, d_len % tile);
+        Self {
+            q_len,
+            d_len,
+            dim,
+            tile,
+            gemms: [
+                gemm(query_block, tile),
+                gemm(query_block, d_tail),
+                gemm(q_tail, tile),
+                gemm(q_tail, d_tail),
+            ],
+            reduction,
+        }
+    }
+
+    /// Documents per query-major share: at least one, at most
+    /// `QUERY_MAJOR_MAX_DOCS`.
+    pub(crate) fn share(&self) -> usize {
+        let bytes = self.d_len * self.dim * size_of::<f32>();
+        (QUERY_MAJOR_SHARE_BYTES / bytes.max(1)).clamp(1, QUERY_MAJOR_MAX_DOCS)
+    }
+
+    /// Score of one document with `padding` rows of bucket padding, each
+    /// tile against every query block.
+    pub(crate) fn score_doc_major(
+        &self,
+        query: &[f32],
+        doc: &[f32],
+        padding: usize,
+        tokens: TokenWeights,
+        scratch: &mut Scratch,
+    ) -> f32 {
+        let per_doc = self.q_len + self.reduction.doc_maxes_len(self.d_len);
+        scratch.maxes.resize(per_doc, 0.0);
+        scratch.maxes.fill(f32::NEG_INFINITY);
+        scratch.sims.resize(self.work_len(), 0.0);
+        let (maxes, doc_maxes) = scratch.maxes.split_at_mut(self.q_len);
+        for tile in blocks(self.d_len, self.tile) {
+            for block in blocks(self.q_len, QUERY_BLOCK) {
+                let maxima = (&mut *maxes, &mut *doc_maxes);
+                let ranges = (block, tile.clone());
+                self.fold((query, doc), ranges, tokens.mask, maxima, &mut scratch.sims);
+            }
+        }
+        self.reduction.score(maxes, doc_maxes, padding, tokens)
+    }
+
+    /// Scores of `docs` (each `[d_len, dim]` with its bucket padding) into
+    /// `out`, in order: each query block against every tile of every
+    /// document.
+    pub(crate) fn score_query_major<'d>(
+        &self,
+        query: &[f32],
+        docs: impl Iterator<Item = (&'d [f32], usize)> + Clone,
+        tokens: TokenWeights,
+        scratch: &mut Scratch,
+        out: &mut [f32],
+    ) {
+        let per_doc = self.q_len + self.reduction.doc_maxes_len(self.d_len);
+        scratch.maxes.resize(out.len() * per_doc, 0.0);
+        scratch.maxes.fill(f32::NEG_INFINITY);
+        scratch.sims.resize(self.work_len(), 0.0);
+        for block in blocks(self.q_len, QUERY_BLOCK) {
+            for ((doc, _), maxima) in docs.clone().zip(scratch.maxes.chunks_exact_mut(per_doc)) {
+                let (maxes, doc_maxes) = maxima.split_at_mut(self.q_len);
+                for tile in blocks(self.d_len, self.tile) {
+                    let maxima = (&mut *maxes, &mut *doc_maxes);
+                    let ranges = (block.clone(), tile);
+                    self.fold((query, doc), ranges, tokens.mask, maxima, &mut scratch.sims);
+                }
+            }
+        }
+        let scored = out.iter_mut().zip(docs);
+        for ((score, (_, padding)), maxima) in scored.zip(scratch.maxes.chunks_exact(per_doc)) {
+            let (maxes, doc_maxes) = maxima.split_at(self.q_len);
+            *score = self.reduction.score(maxes, doc_maxes, padding, tokens);
+        }
+    }
+
+    /// Length of the `work` buffer `fold` needs.
+    fn work_len(&self) -> usize {
+        QUERY_BLOCK.min(self.q_len) * self.tile
+    }
+
+    /// Similarities of query tokens `block` against document tokens `tile`,
+    /// folded into `maxes` (all query tokens) and, unless empty,
+    /// `doc_maxes` (all document tokens, over unmasked query tokens).
+    fn fold(
+        &self,
+        (query, doc): (&[f32], &[f32]),
+        (block, tile): (Range<usize>, Range<usize>),
+        mask: Option<&[bool]>,
+        (maxes, doc_maxes): (&mut [f32], &mut [f32]),
+        work: &mut [f32],
+    ) {
+        let dim = self.dim;
+        let short = 2 * usize::from(block.len() < QUERY_BLOCK.min(self.q_len))
+            + usize::from(tile.len() < self.tile);
+        let gemm = self.gemms[short]
+            .as_ref()
+            .expect("a GEMM for every block and tile length");
+        let sims = &mut work[..block.len() * tile.len()];
+        let query = &query[block.start * dim..block.end * dim];
+        gemm.run(query, &doc[tile.start * dim..tile.end * dim], sims);
+        timed(Phase::Reduce, || {
+            fold_row_maxes(sims, &mut maxes[block.clone()]);
+            if !doc_maxes.is_empty() {
+                let mask = mask.map(|mask| &mask[block.clone()]);
+                let columns = sims.chunks_exact(block.len());
+                for (m, sims) in doc_maxes[tile].iter_mut().zip(columns) {
+                    *m = m.max(token_max(sims, mask));
+                }
+            }
+        });
+    }
+}
+
+/// `0..len` in runs of `step` (the last shorter).
+fn blocks(len: usize, step: usize) -> impl Iterator<Item = Range<usize>> {
+    (0..len)
+        .step_by(step)
+        .map(move |start| start..(start + step).min(len))
+}
+
+/// MaxSim scorer for documents of one fixed length.
+pub(crate) struct DocScorer {
+    q_len: usize,
+    d_len: usize,
+    /// Fused-path tile length.
+    block: usize,
+    plan: Plan,
+    reduction: Reduction,
+}
+
+enum Plan {
+    /// One GEMM over the whole document.
+    Whole(SimilarityGemm),
+    /// Full `block`-token tiles plus an optional shorter tail tile.
+    Fused {
+        block: SimilarityGemm,
+        tail: Option<Box<SimilarityGemm>>,
+    },
+}
+
+impl DocScorer {
+    pub(crate) fn new(q_len: usize, d_len: usize, dim: usize) -> Self {
+        Self::tiled(q_len, d_len, dim, Tiling::default())
+    }
+
+    /// `new` in `tiling`'s tiles (fused tiles of at least one token).
+    pub(crate) fn tiled(q_len: usize, d_len: usize, dim: usize, tiling: Tiling) -> Self {
+        Self::with_plan(q_len, d_len, dim, tiling, d_len > fused_threshold())
+    }
+
+    /// `tiled` on the fused path whatever the threshold, for documents
+    /// longer than one tile.
+    pub(crate) fn fused(q_len: usize, d_len: usize, dim: usize, tiling: Tiling) -> Self {
+        Self::with_plan(q_len, d_len, dim, tiling, true)
+    }
+
+    fn with_plan(q_len: usize, d_len: usize, dim: usize, tiling: Tiling, fused: bool) -> Self {
+        let (block, dims) = (tiling.tokens(dim).max(1), tiling.dims(dim));
+        trace_span!(
+            TRACE,
+            "maxsim.kernel_dispatch",
+            q_len,
+            d_len,
+            dim,
+            block,
+            dims
+        );
+        let plan = if fused && d_len > block {
+            let tail = d_len % block;
+            Plan::Fused {
+                block: SimilarityGemm::doc_major(q_len, block, dim, dims, true),
+                tail: (tail > 0)
+                    .then(|| Box::new(SimilarityGemm::doc_major(q_len, tail, dim, dims, false))),
+            }
+        } else {
+            Plan::Whole(SimilarityGemm::blocked(q_len, d_len, dim, dims))
+        };
+        trace_event!(
+            TRACE,
+            fused = matches!(plan, Plan::Fused { .. }),
+            "doc scorer plan"
+        );
+        Self {
+            q_len,
+            d_len,
+            block,
+            plan,
+            reduction: Reduction::default(),
+        }
+    }
+
+    /// Reduce the similarities with `reduction` in `score`.
+    pub(crate) fn with_reduction(mut self, reduction: Reduction) -> Self {
+        self.reduction = reduction;
+        self
+    }
+
+    /// MaxSim score of one document. `scratch` holds the per-token maxima
+    /// (per document token too, when the direction needs them) and the
+    /// similarity matrix, or on the fused path one tile. Query tokens masked
+    /// in `tokens` are left out of both reductions.
+    pub(crate) fn score(
+        &self,
+        query: &[f32],
+        doc: &[f32],
+        tokens: TokenWeights,
+        scratch: &mut AlignedVec<f32>,
+    ) -> f32 {
+        self.score_padded(query, doc, 0, tokens, scratch)
+    }
+
+    /// `score` for a document whose last `padding` rows are bucket padding
+    /// (`Documents::padding`).
+    pub(crate) fn score_padded(
+        &self,
+        query: &[f32],
+        doc: &[f32],
+        padding: usize,
+        tokens: TokenWeights,
+        scratch: &mut AlignedVec<f32>,
+    ) -> f32 {
+        let (maxes, doc_maxes, work) = self.split_scratch(scratch);
+        self.reduce(query, doc, tokens.mask, maxes, doc_maxes, work);
+        self.reduction.score(maxes, doc_maxes, padding, tokens)
+    }
+
+    /// `scratch` as (query-token maxima, document-token maxima, work).
+    fn split_scratch<'s>(
+        &self,
+        scratch: &'s mut AlignedVec<f32>,
+    ) -> (&'s mut [f32], &'s mut [f32], &'s mut [f32]) {
+        let doc_part = self.reduction.doc_maxes_len(self.d_len);
+        scratch.resize(self.q_len + doc_part + self.work_len(), 0.0);
+        let (maxes, rest) = scratch.split_at_mut(self.q_len);
+        let (doc_maxes, work) = rest.split_at_mut(doc_part);
+        (maxes, doc_maxes, work)
+    }
+
+    /// Length of the `work` buffer `fill_maxes` needs.
+    pub(crate) fn work_len(&self) -> usize {
+        match self.plan {
+            Plan::Whole(_) => self.q_len * self.d_len,
+            Plan::Fused { .. } => self.q_len * self.block,
+        }
+    }
+
+    /// Rows of the fused tile `prefetch_distance()` after tile `b` of
+    /// `doc`; empty past the end, with prefetching off, or for tiles under
+    /// `PREFETCH_MIN_TILE_BYTES`.
+    fn tile_ahead<'d>(&self, doc: &'d [f32], dim: usize, b: usize) -> &'d [f32] {
+        let tile = self.block * dim;
+        let ahead = prefetch_distance();
+        if ahead == 0 || tile * size_of::<f32>() < PREFETCH_MIN_TILE_BYTES {
+            return &[];
+        }
+        let start = b.saturating_add(ahead).saturating_mul(tile);
+        doc.get(start..)
+            .map_or(&[], |rest| &rest[..tile.min(rest.len())])
+    }
+
+    /// Each query token's best similarity into `maxes` (`q_len` values).
+    /// `work` holds the similarity matrix or, on the fused path, one tile;
+    /// there `maxes` itself is the running max.
+    pub(crate) fn fill_maxes(
+        &self,
+        query: &[f32],
+        doc: &[f32],
+        maxes: &mut [f32],
+        work: &mut [f32],
+    ) {
+        self.reduce(query, doc, None, maxes, &mut [], work);
+    }
+
+    /// `fill_maxes`, plus each document token's best similarity over the
+    /// unmasked query tokens into `doc_maxes` unless that is empty. On the
+    /// fused path both come out of the same tiles.
+    fn reduce(
+        &self,
+        query: &[f32],
+        doc: &[f32],
+        mask: Option<&[bool]>,
+        maxes: &mut [f32],
+        doc_maxes: &mut [f32],
+        work: &mut [f32],
+    ) {
+        match &self.plan {
+            Plan::Whole(gemm) => {
+                let sims = &mut work[..self.q_len * self.d_len];
+                gemm.run(query, doc, sims);
+                timed(Phase::Reduce, || {
+                    for (max_val, row) in maxes.iter_mut().zip(sims.chunks_exact(self.d_len)) {
+                        *max_val = simd_max_avx2(row);
+                    }
+                    if !doc_maxes.is_empty() {
+                        column_maxes(sims, mask, doc_maxes);
+                    }
+                });
+            }
+            Plan::Fused { block, tail } => {
+                maxes.fill(f32::NEG_INFINITY);
+                let dim = doc.len() / self.d_len;
+                for (b, block_doc) in doc.chunks(self.block * dim).enumerate() {
+                    let next = self.tile_ahead(doc, dim, b);
+                    let tail = tail.as_deref();
+                    fold_tile(block, tail, query, (block_doc, next), dim, maxes, work);
+                    if !doc_maxes.is_empty() {
+                        let start = b * self.block;
+                        let end = start + block_doc.len() / dim;
+                        let tile = &work[..self.q_len * (end - start)];
+                        doc_token_maxes(tile, mask, &mut doc_maxes[start..end]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `score_padded` for a document stored in another encoding. `decode`
+    /// writes a range of its tokens as f32 rows into `tile` right before
+    /// that tile's GEMM (the whole document on the whole-matrix path), so at
+    /// most one tile is ever held in f32.
+    pub(crate) fn score_decoded(
+        &self,
+        query: &[f32],
+        mut decode: impl FnMut(Range<usize>, &mut [f32]),
+        padding: usize,
+        tokens: TokenWeights,
+        scratch: &mut AlignedVec<f32>,
+        tile: &mut AlignedVec<f32>,
+    ) -> f32 {
+        let (maxes, doc_maxes, work) = self.split_scratch(scratch);
+        let dim = query.len() / self.q_len;
+        match &self.plan {
+            Plan::Whole(_) => {
+                tile.resize(self.d_len * dim, 0.0);
+                timed(Phase::Convert, || decode(0..self.d_len, tile));
+                self.reduce(query, tile, tokens.mask, maxes, doc_maxes, work);
+            }
+            Plan::Fused { block, tail } => {
+                maxes.fill(f32::NEG_INFINITY);
+                for start in (0..self.d_len).step_by(self.block) {
+                    let end = (start + self.block).min(self.d_len);
+                    tile.resize((end - start) * dim, 0.0);
+                    timed(Phase::Convert, || decode(start..end, tile));
+                    fold_tile(block, tail.as_deref(), query, (tile, &[]), dim, maxes, work);
+                    if !doc_maxes.is_empty() {
+                        let sims = &work[..self.q_len * (end - start)];
+                        doc_token_maxes(sims, tokens.mask, &mut doc_maxes[start..end]);
+                    }
+                }
+            }
+        }
+        self.reduction.score(maxes, doc_maxes, padding, tokens)
+    }
+
+    /// `score` that also reports each query token's best document token.
+    ///
+    /// The fused path carries the argmax alongside the running max; a later
+    /// tile only takes over on a strictly greater value, so ties keep the
+    /// first index.
+    pub(crate) fn score_with_matches(
+        &self,
+        query: &[f32],
+        doc: &[f32],
+        scratch: &mut AlignedVec<f32>,
+    ) -> (f32, Vec<u32>) {
+        let mut matches = vec![0u32; self.q_len];
+        match &self.plan {
+            Plan::Whole(gemm) => {
+                scratch.resize(self.q_len * (self.d_len + 1), 0.0);
+                let (sims, max_vals) = scratch.split_at_mut(self.q_len * self.d_len);
+                gemm.run(query, doc, sims);
+                let rows = max_vals.iter_mut().zip(&mut matches);
+                for ((max_val, best), sims) in rows.zip(sims.chunks_exact(self.d_len)) {
+                    let (block_max, idx) = simd_argmax(sims);
+                    *max_val = block_max;
+                    *best = idx as u32;
+                }
+                (weighted_sum(max_vals, None), matches)
+            }
+            Plan::Fused { block, tail } => {
+                scratch.resize(self.q_len * (self.block + 1), 0.0);
+                let (tile, max_vals) = scratch.split_at_mut(self.q_len * self.block);
+                max_vals.fill(f32::NEG_INFINITY);
+
+                let dim = doc.len() / self.d_len;
+                for (b, block_doc) in doc.chunks(self.block * dim).enumerate() {
+                    let block_len = block_doc.len() / dim;
+                    let (gemm, tile) = match tail.as_deref() {
+                        Some(tail) if block_len < self.block => {
+                            (tail, &mut tile[..self.q_len * block_len])
+                        }
+                        _ => (block, &mut tile[..]),
+                    };
+                    let next = self.tile_ahead(doc, dim, b);
+                    let next = if gemm.run_ahead(query, block_doc, tile, next) {
+                        &[]
+                    } else {
+                        next
+                    };
+                    for (di, sims) in tile.chunks_exact(self.q_len).enumerate() {
+                        let rows = max_vals.iter_mut().zip(&mut matches);
+                        for ((max_val, best), &sim) in rows.zip(sims) {
+                            if sim > *max_val {
+                                *max_val = sim;
+                                *best = (b * self.block + di) as u32;
+                            }
+                        }
+                        prefetch_l2(next, di, block_len);
+                    }
+                }
+                (weighted_sum(max_vals, None), matches)
+            }
+        }
+    }
+}
+
+/// One fused-path tile: doc-major similarities of `query` against the
+/// rows of `block_doc` (a full `block` tile, or the shorter `tail`), folded
+/// into the running `maxes`. The rows of `next` are prefetched by the GEMM
+/// or during the fold.
+fn fold_tile(
+    block: &SimilarityGemm,
+    tail: Option<&SimilarityGemm>,
+    query: &[f32],
+    (block_doc, next): (&[f32], &[f32]),
+    dim: usize,
+    maxes: &mut [f32],
+    work: &mut [f32],
+) {
+    let (q_len, block_len) = (maxes.len(), block_doc.len() / dim);
+    let (gemm, tile) = match tail {
+        Some(tail) if block_len < block.d_len() => (tail, &mut work[..q_len * block_len]),
+        _ => (block, &mut work[..q_len * block.d_len()]),
+    };
+    let next = if gemm.run_ahead(query, block_doc, tile, next) {
+        &[]
+    } else {
+        next
+    };
+    let parts = block_len.div_ceil(FOLD_COLUMNS);
+    timed(Phase::Reduce, || {
+        for (part, columns) in tile.chunks(q_len * FOLD_COLUMNS).enumerate() {
+            fold_row_maxes(columns, maxes);
+            prefetch_l2(next, part, parts);
+        }
+    });
+}
+
+/// The `part`-th of `parts` equal shares of `rows`' cache lines, hinted
+/// into L2, so a tile's prefetches spread over its reduction.
+#[inline]
+fn prefetch_l2(rows: &[f32], part: usize, parts: usize) {
+    const LINE: usize = 64 / size_of::<f32>();
+    let lines = rows.len().div_ceil(LINE);
+    for line in lines * part / parts..lines * (part + 1) / parts {
+        // In bounds: line < lines
+        let ptr = rows[line * LINE..].as_ptr();
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T1};
+            _mm_prefetch::<_MM_HINT_T1>(ptr as *const i8);
+        }
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            std::arch::asm!(
+                "prfm pldl2keep, [{0}]",
+                in(reg) ptr,
+                options(nostack, readonly, preserves_flags)
+            );
+        }
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        let _ = ptr;
+    }
+}
+
+/// Each document token's max over the unmasked query tokens of the
+/// doc-major `tile` (`out.len()` rows) into `out`; `-inf` with every query
+/// token masked.
+fn doc_token_maxes(tile: &[f32], mask: Option<&[bool]>, out: &mut [f32]) {
+    let q_len = tile.len() / out.len();
+    for (m, sims) in out.iter_mut().zip(tile.chunks_exact(q_len)) {
+        *m = token_max(sims, mask);
+    }
+}
+
+/// Max of one document token's `sims` over the unmasked query tokens;
+/// `-inf` with every one masked.
+fn token_max(sims: &[f32], mask: Option<&[bool]>) -> f32 {
+    match mask {
+        None => simd_max_avx2(sims),
+        Some(mask) => sims
+            .iter()
+            .zip(mask)
+            .filter(|&(_, &keep)| keep)
+            .fold(f32::NEG_INFINITY, |m, (&s, _)| m.max(s)),
+    }
+}
+
+/// Each column's max over the unmasked rows of the row-major `sims`
+/// (`out.len()` columns) into `out`; `-inf` with every row masked.
+fn column_maxes(sims: &[f32], mask: Option<&[bool]>, out: &mut [f32]) {
+    out.fill(f32::NEG_INFINITY);
+    for (qi, row) in sims.chunks_exact(out.len()).enumerate() {
+        if mask.is_none_or(|mask| mask[qi]) {
+            for (m, &s) in out.iter_mut().zip(row) {
+                *m = m.max(s);
+            }
+        }
+    }
+}
+
+/// How a `[q_len, d_len]` similarity matrix becomes a score: which side
+/// takes the max, and how the maxima are aggregated.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Reduction {
+    pub(crate) direction: Direction,
+    pub(crate) aggregation: Aggregation,
+}
+
+impl Reduction {
+    /// Document-token maxima needed: `d_len` for the directions that read
+    /// them, else 0.
+    pub(crate) fn doc_maxes_len(self, d_len: usize) -> usize {
+        match self.direction {
+            Direction::QueryToDoc => 0,
+            Direction::DocToQuery | Direction::Symmetric => d_len,
+        }
+    }
+
+    /// Score from the per-query-token `maxes` and the per-document-token
+    /// `doc_maxes` (empty for `QueryToDoc`), the last `padding` of which
+    /// are bucket padding and left out. Query weights scale the query side
+    /// only. With every query token masked the document side scores 0, as
+    /// the query side does.
+    pub(crate) fn score(
+        self,
+        maxes: &[f32],
+        doc_maxes: &[f32],
+        padding: usize,
+        tokens: TokenWeights,
+    ) -> f32 {
+        let query_side = || aggregate(maxes, tokens, self.aggregation);
+        let doc_side = || {
+            if tokens.mask.is_some_and(|mask| !mask.contains(&true)) {
+                0.0
+            } else {
+                let own = &doc_maxes[..doc_maxes.len().saturating_sub(padding)];
+                aggregate(own, TokenWeights::default(), self.aggregation)
+            }
+        };
+        match self.direction {
+            Direction::QueryToDoc => query_side(),
+            Direction::DocToQuery => doc_side(),
+            Direction::Symmetric => 0.5 * (query_side() + doc_side()),
+        }
+    }
+
+    /// `score` straight from a whole `[q_len, d_len]` matrix; `maxes` is
+    /// scratch of `q_len + doc_maxes_len(d_len)` values.
+    pub(crate) fn score_matrix(
+        self,
+        sims: &[f32],
+        d_len: usize,
+        padding: usize,
+        tokens: TokenWeights,
+        maxes: &mut [f32],
+    ) -> f32 {
+        trace_span!(TRACE, "maxsim.reduce", d_len);
+        timed(Phase::Reduce, || {
+            let (q_maxes, doc_maxes) = maxes.split_at_mut(sims.len() / d_len);
+            if !doc_maxes.is_empty() {
+                column_maxes(sims, tokens.mask, doc_maxes);
+            }
+            for (m, row) in q_maxes.iter_mut().zip(sims.chunks_exact(d_len)) {
+                *m = simd_max_avx2(row);
+            }
+            self.score(q_maxes, doc_maxes, padding, tokens)
+        })
+    }
+
+    /// `score_matrix` from the transposed `[d_len, q_len]` matrix the
+    /// document-as-B kernels produce; `maxes` is scratch of
+    /// `q_len + doc_maxes_len(d_len)` values.
+    #[cfg(libxsmm)]
+    pub(crate) fn score_matrix_t(
+        self,
+        sims: &[f32],
+        q_len: usize,
+        padding: usize,
+        tokens: TokenWeights,
+        maxes: &mut [f32],
+    ) -> f32 {
+        trace_span!(TRACE, "maxsim.reduce", q_len);
+        timed(Phase::Reduce, || {
+            let (q_maxes, doc_maxes) = maxes.split_at_mut(q_len);
+            column_maxes(sims, None, q_maxes);
+            for (m, row) in doc_maxes.iter_mut().zip(sims.chunks_exact(q_len)) {
+                *m = row
+                    .iter()
+                    .enumerate()
+                    .filter(|&(qi, _)| tokens.mask.is_none_or(|mask| mask[qi]))
+                    .fold(f32::NEG_INFINITY, |m, (_, &s)| m.max(s));
+            }
+            self.score(q_maxes, doc_maxes, padding, tokens)
+        })
+    }
+}
+
+/// Per-query-token maxima scaled by their weights and reduced with
+/// `aggregation`, skipping masked tokens. With no unmasked tokens every
+/// aggregation gives 0. Sums run in `weighted_sum`'s fixed order.
+pub(crate) fn aggregate(maxes: &[f32], tokens: TokenWeights, aggregation: Aggregation) -> f32 {
+    let weighted = || {
+        maxes
+            .iter()
+            .enumerate()
+            .filter(|&(qi, _)| tokens.mask.is_none_or(|mask| mask[qi]))
+            .map(|(qi, &v)| match tokens.weights {
+                Some(weights) => v * weights[qi],
+                None => v,
+            })
+    };
+    match aggregation {
+        Aggregation::Sum => masked_weighted_sum(maxes, tokens.weights, tokens.mask),
+        Aggregation::Mean => {
+            let n = tokens.mask.map_or(maxes.len(), |mask| {
+                mask.iter().filter(|&&keep| keep).count()
+            });
+            if n == 0 {
+                0.0
+            } else {
+                masked_weighted_sum(maxes, tokens.weights, tokens.mask) / n as f32
+            }
+        }
+        Aggregation::Max => weighted().reduce(f32::max).unwrap_or(0.0),
+        Aggregation::LogSumExp { temperature } => {
+            // Shifting by the max keeps every exponent <= 0
+            let Some(max) = weighted().reduce(f32::max) else {
+                return 0.0;
+            };
+            let sum = lane_sum(weighted().map(|v| ((v - max) / temperature).exp()));
+            max + temperature * sum.ln()
+        }
+    }
+}
+
+/// Similarity GEMM for a fixed (q_len, d_len, dim).
+///
+/// Computes the `[q_len, d_len]` row-major similarity matrix, i.e. the
+/// column-major C = Dᵀ·Q with the row-major doc read as a transposed
+/// `dim × d_len` operand. A doc-major GEMM computes its transpose, the
+/// `[d_len, q_len]` row-major C = Qᵀ·D, whose query-token maxima
+/// `fold_row_maxes` takes without horizontal reductions.
+///
+/// Dims over the `dim_block` it is built with are split into passes of
+/// `dim_block` values (the last shorter): the first writes C, the rest
+/// accumulate into it (`Beta::One`), so only a `dim_block`-wide slice of
+/// each operand is live per pass.
+pub(crate) struct SimilarityGemm {
+    #[cfg(libxsmm)]
+    gemm: Gemm,
+    /// Full passes after the first, for split dims.
+    #[cfg(libxsmm)]
+    accumulate: Option<Box<Gemm>>,
+    /// The shorter last pass, when `dim_block` does not divide dim.
+    #[cfg(libxsmm)]
+    tail: Option<Box<Gemm>>,
+    /// (q_len, d_len, dim) for the sgemm call.
+    shape: (usize, usize, usize),
+    /// Embedding values per pass, at most dim.
+    dim_block: usize,
+    doc_major: bool,
+}
+
+impl SimilarityGemm {
+    pub(crate) fn new(q_len: usize, d_len: usize, dim: usize) -> Self {
+        Self::blocked(q_len, d_len, dim, default_dim_block(dim))
+    }
+
+    /// `new` in passes of `dim_block` embedding values.
+    pub(crate) fn blocked(q_len: usize, d_len: usize, dim: usize, dim_block: usize) -> Self {
+        Self::build(q_len, d_len, dim, dim_block, false, false)
+    }
+
+    /// `blocked` writing the doc-major `[d_len, q_len]` matrix, with a
+    /// libxsmm JIT kernel that prefetches the next document rows handed to
+    /// `run_ahead` into L2 if `prefetch` and dim takes one pass.
+    pub(crate) fn doc_major(
+        q_len: usize,
+        d_len: usize,
+        dim: usize,
+        dim_block: usize,
+        prefetch: bool,
+    ) -> Self {
+        Self::build(q_len, d_len, dim, dim_block, prefetch, true)
+    }
+
+    #[cfg_attr(not(libxsmm), allow(unused_variables))]
+    fn build(
+        q_len: usize,
+        d_len: usize,
+        dim: usize,
+        dim_block: usize,
+        prefetch: bool,
+        doc_major: bool,
+    ) -> Self {
+        let dim_block = dim_block.clamp(1, dim.max(1));
+        #[cfg(libxsmm)]
+        let (gemm, accumulate, tail) = {
+            crate::LIBXSMM_CTX.get_or_init(crate::libxsmm_bindings::LibxsmmContext::acquire);
+            // The document is A query-major and B doc-major
+            let (m, n, prefetch) = match (doc_major, prefetch) {
+                (false, prefetch) => (d_len, q_len, prefetch.then_some(Prefetch::AL2)),
+                (true, prefetch) => (q_len, d_len, prefetch.then_some(Prefetch::BL2)),
+            };
+            let (m, n) = (m as i32, n as i32);
+            if dim_block == dim {
+                let prefetch = prefetch.unwrap_or(Prefetch::None);
+                let gemm =
+                    Gemm::f32_with_prefetch(m, n, dim as i32, Transpose::A, Beta::Zero, prefetch);
+                (gemm, None, None)
+            } else {
+                // Both operands keep their full rows; a pass reads a slice
+                let ld = dim as i32;
+                let pass = |k: usize, beta| {
+                    Gemm::f32_with_ld(m, n, k as i32, ld, ld, m, Transpose::A, beta)
+                };
+                let (full, rest) = (dim / dim_block, dim % dim_block);
+                (
+                    pass(dim_block, Beta::Zero),
+                    (full > 1).then(|| Box::new(pass(dim_block, Beta::One))),
+                    (rest > 0).then(|| Box::new(pass(rest, Beta::One))),
+                )
+            }
+        };
+        Self {
+            #[cfg(libxsmm)]
+            gemm,
+            #[cfg(libxsmm)]
+            accumulate,
+            #[cfg(libxsmm)]
+            tail,
+            shape: (q_len, d_len, dim),
+            dim_block,
+            doc_major,
+        }
+    }
+
+    /// Document tokens per call.
+    pub(crate) fn d_len(&self) -> usize {
+        self.shape.1
+    }
+
+    /// `run`, with a prefetching JIT kernel also taking `next_doc` into L2
+    /// while it computes. Whether it did; otherwise prefetching `next_doc`
+    /// is left to the caller.
+    #[cfg_attr(not(libxsmm), allow(unused_variables))]
+    pub(crate) fn run_ahead(
+        &self,
+        query: &[f32],
+        doc: &[f32],
+        sims: &mut [f32],
+        next_doc: &[f32],
+    ) -> bool {
+        #[cfg(libxsmm)]
+        if Backend::current() == Backend::Libxsmm && self.gemm.prefetches() && !next_doc.is_empty()
+        {
+            // The query is the same on every call; only the documents move
+            let run = timed(Phase::Gemm, || match self.doc_major {
+                false => self.gemm.run_prefetch(doc, query, sims, next_doc, query),
+                true => self.gemm.run_prefetch(query, doc, sims, query, next_doc),
+            });
+            run.expect("similarity GEMM operands sized from its own shape");
+            return true;
+        }
+        self.run(query, doc, sims);
+        false
+    }
+
+    /// `sims[qi * d_len + di] = query[qi] · doc[di]`, or
+    /// `sims[di * q_len + qi]` doc-major.
+    pub(crate) fn run(&self, query: &[f32], doc: &[f32], sims: &mut [f32]) {
+        timed(Phase::Gemm, || self.run_passes(query, doc, sims));
+    }
+
+    fn run_passes(&self, query: &[f32], doc: &[f32], sims: &mut [f32]) {
+        let (a, b) = match self.doc_major {
+            false => (doc, query),
+            true => (query, doc),
+        };
+        let (q_len, d_len, dim) = self.shape;
+        #[cfg(libxsmm)]
+        if Backend::current() == Backend::Libxsmm {
+            for k0 in (0..dim.max(1)).step_by(self.dim_block) {
+                let gemm = match k0 {
+                    0 => Some(&self.gemm),
+                    _ if dim - k0 < self.dim_block => self.tail.as_deref(),
+                    _ => self.accumulate.as_deref(),
+                }
+                .expect("a pass for every dim block");
+                gemm.run(&a[k0..], &b[k0..], sims)
+                    .expect("similarity GEMM operands sized from its own shape");
+            }
+            return;
+        }
+
+        let (m, n) = match self.doc_major {
+            false => (d_len, q_len),
+            true => (q_len, d_len),
+        };
+        for k0 in (0..dim.max(1)).step_by(self.dim_block) {
+            let k = self.dim_block.min(dim - k0);
+            let beta = if k0 == 0 { 0.0 } else { 1.0 };
+            unsafe {
+                crate::backend::sgemm(
+                    b'T',
+                    b'N',
+                    m as i32,
+                    n as i32,
+                    k as i32,
+                    1.0,
+                    &a[k0..],
+                    dim as i32,
+                    &b[k0..],
+                    dim as i32,
+                    beta,
+                    sims,
+                    m as i32,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{unit_docs, unit_rows};
+    use crate::collection::DocCollection;
+
+    /// Plain-loop MaxSim over row-major `[tokens, dim]` buffers.
+    fn scalar_maxsim(query: &[f32], doc: &[f32], dim: usize) -> f32 {
+        query
+            .chunks_exact(dim)
+            .map(|q| {
+                doc.chunks_exact(dim)
+                    .map(|d| q.iter().zip(d).map(|(x, y)| x * y).sum::<f32>())
+                    .fold(f32::NEG_INFINITY, f32::max)
+            })
+            .sum()
+    }
+
+    /// Multiples of 1/8 in [-2, 2]: every partial dot product is exact in
+    /// f32, so any summation order gives the same bits.
+    fn dyadic_rows(rows: usize, dim: usize, seed: usize) -> Vec<f32> {
+        (0..rows * dim)
+            .map(|i| ((i * 7919 + seed * 104_729) % 33) as f32 / 8.0 - 2.0)
+            .collect()
+    }
+
+    #[test]
+    fn hand_computed_score() {
+        let query = [1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        let doc = [2.0, 0.0, 0.0, -1.0, 0.5, 0.5];
+        // Best matches 2, 0.5 and 2
+        assert_eq!(maxsim_score(&query, 3, &doc, 3, 2), Ok(4.5));
+        assert_eq!(maxsim_score(&query[..2], 1, &doc, 3, 2), Ok(2.0));
+        // All-negative similarities still take the max, not 0
+        assert_eq!(maxsim_score(&[0.0, 1.0], 1, &doc[2..4], 1, 2), Ok(-1.0));
+
+        assert_eq!(maxsim_score(&[], 0, &doc, 3, 2), Ok(0.0));
+        assert_eq!(
+            maxsim_score(&query, 3, &[], 0, 2),
+            Err(ScoreError::EmptyDocument)
+        );
+        assert_eq!(
+            maxsim_score(&query, 3, &doc, 3, 3),
+            Err(ScoreError::DimMismatch {
+                operand: "query",
+                expected: 9,
+                actual: 6
+            })
+        );
+    }
+
+    #[test]
+    fn backend_matches_scalar_exactly() {
+        let dim = 24;
+        for (q_len, d_len) in [(1, 1), (5, 3), (32, 17), (40, 130), (7, 300)] {
+            let query = dyadic_rows(q_len, dim, q_len);
+            let doc = dyadic_rows(d_len, dim, d_len + 1);
+            let score = maxsim_score(&query, q_len, &doc, d_len, dim).unwrap();
+            assert_eq!(score, scalar_maxsim(&query, &doc, dim), "{q_len}x{d_len}");
+        }
+    }
+
+    #[test]
+    fn fused_tiles_score_bit_identical_to_the_whole_document() {
+        // Unit rows, whose dot products round, so a GEMM summing them in
+        // another order would show
+        for dim in [24, 128, 300] {
+            for (q_len, d_len) in [(5, 9), (32, 100), (40, 333)] {
+                let query = unit_rows(q_len, dim, (dim + q_len) as u64);
+                let doc = unit_rows(d_len, dim, (dim + d_len) as u64);
+                let score = |scorer: DocScorer| {
+                    let mut scratch = AlignedVec::new();
+                    scorer.score(&query, &doc, TokenWeights::default(), &mut scratch)
+                };
+                let whole = score(DocScorer::with_plan(
+                    q_len,
+                    d_len,
+                    dim,
+                    Tiling::default(),
+                    false,
+                ));
+                for tokens in [1, 8, 64] {
+                    let tiling = Tiling {
+                        tokens: Some(tokens),
+                        ..Tiling::default()
+                    };
+                    let fused = score(DocScorer::fused(q_len, d_len, dim, tiling));
+                    assert_eq!(
+                        fused, whole,
+                        "{q_len}x{d_len}x{dim} in {tokens}-token tiles"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn batch_matches_single_pair_scores() {
+        let dim = 32;
+        let lengths = [1, 9, 31, 32, 33, 64, 100];
+        let docs = unit_docs(&lengths, dim, 3);
+        let query = QueryEmbeddings::new(unit_rows(12, dim, 4), 12, dim).unwrap();
+        let scores = maxsim_score_batch(&query, &docs).unwrap();
+        for (i, &score) in scores.iter().enumerate() {
+            let expected = scalar_maxsim(query.data(), docs.doc(i), dim);
+            assert!(
+                (score - expected).abs() <= 1e-5,
+                "doc {i}: {score} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn batch_is_bit_identical_to_maxsim_score() {
+        // Queries past QUERY_BLOCK rows take the blocked path in both
+        let dim = 48;
+        let lengths = [1, 7, 32, 33, 100, 257];
+        let docs = unit_docs(&lengths, dim, 11);
+        for q_len in [12, 40, 70] {
+            let query = QueryEmbeddings::new(unit_rows(q_len, dim, 12), q_len, dim).unwrap();
+            let scores = maxsim_score_batch(&query, &docs).unwrap();
+            for (i, &score) in scores.iter().enumerate() {
+                let single =
+                    maxsim_score(query.data(), q_len, docs.doc(i), lengths[i], dim).unwrap();
+                assert_eq!(score, single, "q_len {q_len}, doc {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_equals_full_sort() {
+        let dim = 16;
+        let lengths: Vec<usize> = (0..40).map(|i| 1 + (i * 13) % 37).collect();
+        let docs = unit_docs(&lengths, dim, 5);
+        let query = QueryEmbeddings::new(unit_rows(6, dim, 6), 6, dim).unwrap();
+        let scores = maxsim_score_batch(&query, &docs).unwrap();
+        let mut expected: Vec<(DocId, f32)> = scores.into_iter().enumerate().collect();
+        expected.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        for k in [1, 5, 40, 100] {
+            let top = maxsim_top_k(&query, &docs, k).unwrap();
+            assert_eq!(top, expected[..k.min(docs.len())], "k = {k}");
+        }
+    }
+
+    #[test]
+    fn tied_documents_rank_by_id() {
+        let dim = 8;
+        let doc = unit_rows(5, dim, 7);
+        let other = unit_rows(3, dim, 8);
+        // Documents 0, 2 and 3 are identical, so their scores tie exactly
+        let data = [&doc[..], &other, &doc, &doc].concat();
+        let docs = DocCollection::from_lengths(data, &[5, 3, 5, 5], dim).unwrap();
+        let query = QueryEmbeddings::new(doc[..2 * dim].to_vec(), 2, dim).unwrap();
+        let top = maxsim_top_k(&query, &docs, 3).unwrap();
+        assert_eq!(top.iter().map(|&(i, _)| i).collect::<Vec<_>>(), [0, 2, 3]);
+        assert!(top.iter().all(|&(_, score)| score == top[0].1));
+
+        let hits = maxsim_search(&query, &docs, 2).unwrap();
+        assert_eq!((hits[0].id, hits[0].rank), (0, 0));
+        assert_eq!((hits[1].id, hits[1].rank), (2, 1));
+    }
+
+    #[test]
+    fn threshold_has_no_false_negatives() {
+        let (dim, q_len) = (16, 9);
+        let query = unit_rows(q_len, dim, 9);
+        for (seed, d_len) in [(10, 5), (11, 64), (12, 150), (13, 200)] {
+            // Later tiles hold longer rows, so the bound tightens tile by tile
+            let mut doc = unit_rows(d_len, dim, seed);
+            for (t, row) in doc.chunks_exact_mut(dim).enumerate() {
+                let scale = 0.5 + (t / FUSED_BLOCK) as f32;
+                row.iter_mut().for_each(|v| *v *= scale);
+            }
+            let full = maxsim_score(&query, q_len, &doc, d_len, dim).unwrap();
+            let below = [f32::MIN, full - 1.0, full - 1e-4, full];
+            for threshold in below {
+                let score = maxsim_score_threshold(&query, q_len, &doc, d_len, dim, threshold);
+                assert_eq!(
+                    score,
+                    Ok(Some(full)),
+                    "d_len {d_len}, threshold {threshold}"
+                );
+            }
+            let above = [full.next_up(), full + 1e-4, full + 1.0, f32::MAX];
+            for threshold in above {
+                let score = maxsim_score_threshold(&query, q_len, &doc, d_len, dim, threshold);
+                assert_eq!(score, Ok(None), "d_len {d_len}, threshold {threshold}");
+            }
+        }
+    }
+
+    #[test]
+    fn pruned_top_k_is_exact() {
+        let dim = 16;
+        let lengths: Vec<usize> = (0..400).map(|i| 1 + (i * 11) % 23).collect();
+        let mut docs_data = unit_rows(lengths.iter().sum(), dim, 14);
+        // A few long rows among many short ones, so most bounds lose
+        let mut start = 0;
+        for (i, &len) in lengths.iter().enumerate() {
+            let scale = if i % 16 == 0 {
+                4.0
+            } else {
+                0.1 + (i % 7) as f32 / 10.0
+            };
+            for v in &mut docs_data[start * dim..(start + len) * dim] {
+                *v *= scale;
+            }
+            start += len;
+        }
+        let docs = DocCollection::from_lengths(docs_data, &lengths, dim).unwrap();
+        let query = QueryEmbeddings::new(unit_rows(5, dim, 15), 5, dim).unwrap();
+        for k in [1, 4, 10] {
+            let exhaustive = maxsim_top_k(&query, &docs, k).unwrap();
+            let (pruned, stats) = maxsim_top_k_pruned(&query, &docs, k).unwrap();
+            assert_eq!(pruned, exhaustive, "k = {k}");
+            assert_eq!(stats.scored + stats.skipped, docs.len());
+            assert!(stats.skipped > 0, "k = {k}: nothing pruned");
+        }
+        let (all, stats) = maxsim_top_k_pruned(&query, &docs, docs.len()).unwrap();
+        assert_eq!(all, maxsim_top_k(&query, &docs, docs.len()).unwrap());
+        assert_eq!(stats.skipped, 0);
+    }
+
+    #[test]
+    fn thread_count_and_partitioning_never_change_the_bits() {
+        const DIM: usize = 32;
+        let lengths: Vec<usize> = (0..24).map(|i| 1 + (i * 23) % 45).collect();
+        let docs = unit_docs(&lengths, DIM, 21);
+        let query = QueryEmbeddings::new(unit_rows(20, DIM, 22), 20, DIM).unwrap();
+        let reductions = [
+            Reduction::default(),
+            Reduction {
+                direction: Direction::Symmetric,
+                aggregation: Aggregation::Mean,
+            },
+            Reduction {
+                direction: Direction::DocToQuery,
+                aggregation: Aggregation::LogSumExp { temperature: 0.1 },
+            },
+        ];
+        let run = |threads: usize, partitioning: Partitioning, reduction: Reduction| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let tiling = Tiling::default();
+                let scratch = ScratchPool::default();
+                let scores = score_batch_aggregated(
+                    &query,
+                    &docs,
+                    reduction,
+                    tiling,
+                    partitioning,
+                    scratch.call(None),
+                )
+                .unwrap();
+                let top = top_k_aggregated(
+                    &query,
+                    &docs,
+                    7,
+                    reduction,
+                    tiling,
+                    partitioning,
+                    scratch.call(None),
+                )
+                .unwrap();
+                let bits = |s: f32| s.to_bits();
+                (
+                    scores.into_iter().map(bits).collect::<Vec<_>>(),
+                    top.into_iter()
+                        .map(|(i, s)| (i, bits(s)))
+                        .collect::<Vec<_>>(),
+                )
+            })
+        };
+        for reduction in reductions {
+            let expected = run(1, Partitioning::WorkStealing, reduction);
+            for threads in [1, 3, 8] {
+                for partitioning in [Partitioning::WorkStealing, Partitioning::TokenBalanced] {
+                    assert_eq!(
+                        run(threads, partitioning, reduction),
+                        expected,
+                        "{threads} threads, {partitioning:?}, {reduction:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn token_balanced_lists_stay_within_a_ratio_on_skewed_lengths() {
+        // A few very long documents and a long tail of short ones, laid out
+        // so that chunking by count would hand every long one to one worker
+        let groups = [(5000, 8), (300, 500), (16, 4000)];
+        let mut lengths = Vec::new();
+        for &(count, len) in &groups {
+            lengths.extend(std::iter::repeat_n(len, count));
+        }
+        let mut buckets = Vec::new();
+        let mut start = 0;
+        for &(count, _) in &groups {
+            buckets.push((start..start + count).collect::<Vec<DocId>>());
+            start += count;
+        }
+        let buckets: Vec<&[DocId]> = buckets.iter().map(Vec::as_slice).collect();
+        let ratio = |loads: &[usize]| {
+            let (max, min) = (loads.iter().max().unwrap(), loads.iter().min().unwrap());
+            *max as f64 / *min as f64
+        };
+        for workers in [2, 3, 4, 8] {
+            let bins = balance_tokens(&buckets, |i| lengths[i], workers);
+            assert_eq!(bins.len(), workers);
+            let mut seen: Vec<DocId> = bins.iter().flatten().map(|&(_, i)| i).collect();
+            seen.sort_unstable();
+            assert_eq!(seen, (0..lengths.len()).collect::<Vec<_>>());
+            for bin in &bins {
+                assert!(bin.iter().all(|&(b, i)| buckets[b].contains(&i)));
+            }
+            let loads: Vec<usize> = bins
+                .iter()
+                .map(|bin| bin.iter().map(|&(_, i)| lengths[i]).sum())
+                .collect();
+            let (max, min) = (loads.iter().max().unwrap(), loads.iter().min().unwrap());
+            assert!(max - min <= 4000, "{workers} workers: {loads:?}");
+            assert!(ratio(&loads) < 1.3, "{workers} workers: {loads:?}");
+
+            let by_count: Vec<usize> = lengths
+                .chunks(lengths.len().div_ceil(workers))
+                .map(|chunk| chunk.iter().sum())
+                .collect();
+            assert!(ratio(&by_count) > 1.3, "{workers} workers: {by_count:?}");
+        }
+    }
+
+    #[test]
+    fn short_batch_documents_score_exactly_as_single_pairs() {
+        let (dim, q_len) = (48, 20);
+        let lengths: Vec<usize> = (0..60).map(|i| 1 + (i * 7) % 44).collect();
+        let docs = unit_docs(&lengths, dim, 31);
+        let query = QueryEmbeddings::new(unit_rows(q_len, dim, 32), q_len, dim).unwrap();
+        let scores = maxsim_score_batch(&query, &docs).unwrap();
+        for (i, &score) in scores.iter().enumerate() {
+            let d_len = docs.doc_len(i);
+            let single = maxsim_score(query.data(), q_len, docs.doc(i), d_len, dim).unwrap();
+            assert_eq!(score.to_bits(), single.to_bits(), "doc {i} of {d_len}");
+        }
+    }
+
+    #[test]
+    fn stacked_scores_stay_within_the_stated_tolerance() {
+        let (dim, q_len) = (48, 20);
+        let tokens = TokenWeights {
+            mask: None,
+            weights: None,
+        };
+        // Unit-norm tokens, so the largest |q|·|d| is 1
+        let bound = (q_len * (dim + q_len)) as f32 * f32::EPSILON;
+        let query = unit_rows(q_len, dim, 32);
+        let mut scratch = Scratch::default();
+        for d_len in [1, 3, 8, 32] {
+            let scorer = StackedScorer::new(q_len, d_len, dim, dim, Reduction::default());
+            let n = scorer.group() + 1;
+            let rows = unit_rows(n * d_len, dim, d_len as u64);
+            let docs: Vec<&[f32]> = rows.chunks_exact(d_len * dim).collect();
+            for group in docs.chunks(scorer.group()) {
+                let mut out = vec![0.0; group.len()];
+                scorer.score(
+                    &query,
+                    group.iter().map(|&doc| (doc, 0)),
+                    tokens,
+                    &mut scratch,
+                    &mut out,
+                );
+                for (doc, &score) in group.iter().zip(&out) {
+                    let single = maxsim_score(&query, q_len, doc, d_len, dim).unwrap();
+                    assert!(
+                        (score - single).abs() <= bound,
+                        "{d_len} tokens: {score} vs {single}"
+                    );
+                }
+            }
+        }
+    }
+
+    /// `aggregation` of `values` in f64.
+    fn scalar_aggregate(values: &[f64], aggregation: Aggregation) -> f64 {
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        match aggregation {
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Max => max,
+            Aggregation::LogSumExp { temperature } => {
+                let t = temperature as f64;
+                max + t * values
+                    .iter()
+                    .map(|v| ((v - max) / t).exp())
+                    .sum::<f64>()
+                    .ln()
+            }
+        }
+    }
+
+    /// `reduction` of one pair from its similarities in f64, over the
+    /// query tokens `mask` keeps.
+    fn scalar_reduction(
+        query: &[f32],
+        doc: &[f32],
+        dim: usize,
+        mask: &[bool],
+        reduction: Reduction,
+    ) -> f64 {
+        let dot = |q: &[f32], d: &[f32]| q.iter().zip(d).map(|(&x, &y)| x as f64 * y as f64).sum();
+        let kept: Vec<&[f32]> = query
+            .chunks_exact(dim)
+            .zip(mask)
+            .filter_map(|(q, &keep)| keep.then_some(q))
+            .collect();
+        let query_side: Vec<f64> = kept
+            .iter()
+            .map(|q| {
+                doc.chunks_exact(dim)
+                    .map(|d| dot(q, d))
+                    .fold(f64::NEG_INFINITY, f64::max)
+            })
+            .collect();
+        let doc_side: Vec<f64> = doc
+            .chunks_exact(dim)
+            .map(|d| {
+                kept.iter()
+                    .map(|q| dot(q, d))
+                    .fold(f64::NEG_INFINITY, f64::max)
+            })
+            .collect();
+        let aggregate = |values: &[f64]| scalar_aggregate(values, reduction.aggregation);
+        match reduction.direction {
+            Direction::QueryToDoc => aggregate(&query_side),
+            Direction::DocToQuery => aggregate(&doc_side),
+            Direction::Symmetric => 0.5 * (aggregate(&query_side) + aggregate(&doc_side)),
+        }
+    }
+
+    #[test]
+    fn every_reduction_matches_a_scalar_reference() {
+        let dim = 32;
+        let lengths: Vec<usize> = (0..30).map(|i| 1 + (i * 29) % 150).collect();
+        let docs = unit_docs(&lengths, dim, 41);
+        let aggregations = [
+            Aggregation::Sum,
+            Aggregation::Mean,
+            Aggregation::Max,
+            Aggregation::LogSumExp { temperature: 0.25 },
+        ];
+        // Whole-matrix, fused and, past `QUERY_BLOCK`, blocked queries
+        for q_len in [5, 40] {
+            let query = unit_rows(q_len, dim, 42 + q_len as u64);
+            let mask: Vec<bool> = (0..q_len).map(|qi| qi % 3 != 1).collect();
+            let plain = QueryEmbeddings::new(query.clone(), q_len, dim).unwrap();
+            let masked = plain.clone().with_mask(&mask).unwrap();
+            for (query, mask) in [(&plain, vec![true; q_len]), (&masked, mask)] {
+                for direction in [
+                    Direction::QueryToDoc,
+                    Direction::DocToQuery,
+                    Direction::Symmetric,
+                ] {
+                    for aggregation in aggregations {
+                        let reduction = Reduction {
+                            direction,
+                            aggregation,
+                        };
+                        let scores = score_batch_aggregated(
+                            query,
+                            &docs,
+                            reduction,
+                            Tiling::default(),
+                            Partitioning::default(),
+                            ScratchPool::default().call(None),
+                        )
+                        .unwrap();
+                        for (i, &score) in scores.iter().enumerate() {
+                            let expected =
+                                scalar_reduction(query.data(), docs.doc(i), dim, &mask, reduction);
+                            assert!(
+                                (score as f64 - expected).abs() <= 1e-4 * expected.abs().max(1.0),
+                                "{q_len} tokens, {reduction:?}, doc {i}: {score} vs {expected}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn query_major_and_doc_major_scores_are_identical() {
+        let dim = 16;
+        // Enough short documents for several capped shares, then long ones
+        let lengths: Vec<usize> = (0..1200).map(|i| 1 + i % 4).chain([150, 300]).collect();
+        let docs = unit_docs(&lengths, dim, 51);
+        let q_len = 3 * QUERY_BLOCK + 5;
+        let mask: Vec<bool> = (0..q_len).map(|qi| qi % 4 != 2).collect();
+        let query = QueryEmbeddings::new(unit_rows(q_len, dim, 52), q_len, dim)
+            .unwrap()
+            .with_mask(&mask)
+            .unwrap();
+        for direction in [Direction::QueryToDoc, Direction::Symmetric] {
+            let reduction = Reduction {
+                direction,
+                aggregation: Aggregation::Sum,
+            };
+            let scores = |order| {
+                let tiling = Tiling {
+                    order,
+                    ..Tiling::default()
+                };
+                let scratch = ScratchPool::default();
+                let partitioning = Partitioning::default();
+                score_batch_aggregated(
+                    &query,
+                    &docs,
+                    reduction,
+                    tiling,
+                    partitioning,
+                    scratch.call(None),
+                )
+                .unwrap()
+            };
+            assert_eq!(
+                scores(LoopOrder::QueryMajor),
+                scores(LoopOrder::DocMajor),
+                "{direction:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn dim_blocks_leaving_a_remainder_score_as_one_pass() {
+        // 100 = 3 * 32 + 4: three whole passes and a short one
+        let dim = 100;
+        let lengths = [1, 3, 8, 33, 130, 600];
+        let flat = dyadic_rows(lengths.iter().sum(), dim, 5);
+        let docs = DocCollection::from_lengths(flat, &lengths, dim).unwrap();
+        for q_len in [5, 40] {
+            let query = dyadic_rows(q_len, dim, q_len);
+            let query = QueryEmbeddings::new(query, q_len, dim).unwrap();
+            let scores = |dims, stack| {
+                let tiling = Tiling {
+                    dims: Some(dims),
+                    stack: Some(stack),
+                    ..Tiling::default()
+                };
+                let scratch = ScratchPool::default();
+                let (reduction, partitioning) = (Reduction::default(), Partitioning::default());
+                score_batch_aggregated(
+                    &query,
+                    &docs,
+                    reduction,
+                    tiling,
+                    partitioning,
+                    scratch.call(None),
+                )
+                .unwrap()
+            };
+            let whole = scores(dim, 0);
+            for (i, &score) in whole.iter().enumerate() {
+                assert_eq!(score, scalar_maxsim(query.data(), docs.doc(i), dim));
+            }
+            // Tiled, fused, query-blocked and (up to 8 tokens) stacked
+            for stack in [0, 8] {
+                assert_eq!(scores(32, stack), whole, "{q_len} tokens, stack {stack}");
+                assert_eq!(scores(dim, stack), whole, "{q_len} tokens, stack {stack}");
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_queries_score_as_a_scalar_weighted_sum_on_every_path() {
+        use crate::stream::{DocChunk, TopKStream};
+
+        let dim = 32;
+        let lengths: Vec<usize> = (0..100).map(|i| 1 + (i * 13) % 40).collect();
+        // Norms from 0.02 to 2, so pruning has bounds to tell apart
+        let mut flat = unit_rows(lengths.iter().sum(), dim, 91);
+        let mut rows = flat.chunks_exact_mut(dim);
+        for (i, &len) in lengths.iter().enumerate() {
+            let scale = 0.02 * ((1 + i % 10) as f32).powi(2);
+            for row in rows.by_ref().take(len) {
+                row.iter_mut().for_each(|x| *x *= scale);
+            }
+        }
+        let docs = DocCollection::from_lengths(flat, &lengths, dim).unwrap();
+
+        for q_len in [6, 40] {
+            // Zero and negative weights too, and a masked token
+            let weights: Vec<f32> = (0..q_len).map(|qi| 0.5 * (qi % 5) as f32 - 0.5).collect();
+            let mask: Vec<bool> = (0..q_len).map(|qi| qi != 3).collect();
+            let query = QueryEmbeddings::new(unit_rows(q_len, dim, 92), q_len, dim)
+                .unwrap()
+                .with_weights(&weights)
+                .unwrap()
+                .with_mask(&mask)
+                .unwrap();
+            let scalar = |doc: &[f32]| -> f64 {
+                let rows = query.data().chunks_exact(dim).zip(&weights).zip(&mask);
+                rows.filter(|(_, &keep)| keep)
+                    .map(|((q, &w), _)| {
+                        let best = doc.chunks_exact(dim).map(|d| {
+                            q.iter()
+                                .zip(d)
+                                .map(|(&x, &y)| x as f64 * y as f64)
+                                .sum::<f64>()
+                        });
+                        w as f64 * best.fold(f64::NEG_INFINITY, f64::max)
+                    })
+                    .sum()
+            };
+
+            let scores = maxsim_score_batch(&query, &docs).unwrap();
+            for (i, &score) in scores.iter().enumerate() {
+                let expected = scalar(docs.doc(i));
+                assert!(
+                    (score as f64 - expected).abs() <= 1e-4 * expected.abs().max(1.0),
+                    "{q_len} tokens, doc {i}: {score} vs {expected}"
+                );
+            }
+
+            let k = 10;
+            let mut sorted: Vec<(DocId, f32)> = scores.iter().copied().enumerate().collect();
+            sorted.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+            sorted.truncate(k);
+            let top = maxsim_top_k(&query, &docs, k).unwrap();
+            assert_eq!(top, sorted, "{q_len} tokens");
+
+            // On one thread, so a single heap's threshold sees every bound
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(1)
+                .build()
+                .unwrap();
+            let (pruned, stats) = pool
+                .install(|| maxsim_top_k_pruned(&query, &docs, k))
+                .unwrap();
+            assert_eq!(pruned, top, "{q_len} tokens");
+            assert!(stats.skipped > 0, "{q_len} tokens: nothing pruned");
+
+            let mut stream = TopKStream::new(&query, k);
+            for chunk in (0..docs.len()).collect::<Vec<_>>().chunks(7) {
+                let mut data = Vec::new();
+                let mut offsets = vec![0];
+                for &i in chunk {
+                    data.extend_from_slice(docs.doc(i));
+                    offsets.push(data.len());
+                }
+                stream.push(DocChunk { data, offsets, dim }).unwrap();
+            }
+            assert_eq!(stream.into_top_k().into_sorted_vec(), top, "{q_len} tokens");
+        }
+    }
+
+    #[test]
+    fn fully_masked_queries_score_zero_in_batch_and_top_k() {
+        let dim = 32;
+        // Stacked, whole-matrix and fused documents
+        let lengths: Vec<usize> = (0..40).map(|i| 1 + (i * 37) % 400).collect();
+        let docs = unit_docs(&lengths, dim, 61);
+        let k = 5;
+        let first_k: Vec<(DocId, f32)> = (0..k).map(|i| (i, 0.0)).collect();
+        // Whole-matrix and, past `QUERY_BLOCK`, blocked queries
+        for q_len in [5, 40] {
+            let plain = QueryEmbeddings::new(unit_rows(q_len, dim, 62), q_len, dim).unwrap();
+            let prefix = plain.clone().with_valid_len(0).unwrap();
+            let tokens = plain.clone().with_mask(&vec![false; q_len]).unwrap();
+            for query in [&prefix, &tokens] {
+                let scores = maxsim_score_batch(query, &docs).unwrap();
+                assert!(
+                    scores.iter().all(|&s| s == 0.0),
+                    "{q_len} tokens: {scores:?}"
+                );
+                for direction in [Direction::DocToQuery, Direction::Symmetric] {
+                    for aggregation in [Aggregation::Sum, Aggregation::Mean] {
+                        let reduction = Reduction {
+                            direction,
+                            aggregation,
+                        };
+                        let scores = score_batch_aggregated(
+                            query,
+                            &docs,
+                            reduction,
+                            Tiling::default(),
+                            Partitioning::default(),
+                            ScratchPool::default().call(None),
+                        )
+                        .unwrap();
+                        assert!(
+                            scores.iter().all(|&s| s == 0.0),
+                            "{q_len} tokens, {reduction:?}: {scores:?}"
+                        );
+                    }
+                }
+
+                // Every document ties at 0, so the lowest ids win
+                assert_eq!(maxsim_top_k(query, &docs, k).unwrap(), first_k);
+                let (pruned, _) = maxsim_top_k_pruned(query, &docs, k).unwrap();
+                assert_eq!(pruned, first_k);
+                let hits = maxsim_search(query, &docs, k).unwrap();
+                assert!(hits.iter().all(|hit| hit.score == 0.0), "{hits:?}");
+            }
+            let mask = vec![false; q_len];
+            let score =
+                maxsim_score_masked(plain.data(), q_len, &mask, docs.doc(0), lengths[0], dim);
+            assert_eq!(score.unwrap(), 0.0);
+        }
+    }
+
+    #[test]
+    fn top_k_batch_matches_one_search_per_query() {
+        use crate::collection::QueryBatch;
+
+        let dim = 32;
+        let lengths: Vec<usize> = (0..60).map(|i| 1 + (i * 37) % 150).collect();
+        let docs = unit_docs(&lengths, dim, 71);
+        let query = |q_len: usize, seed| {
+            QueryEmbeddings::new(unit_rows(q_len, dim, seed), q_len, dim).unwrap()
+        };
+        let weights: Vec<f32> = (0..9).map(|qi| 1.0 + 0.25 * qi as f32).collect();
+        let mask: Vec<bool> = (0..40).map(|qi| qi % 3 != 1).collect();
+        let queries = QueryBatch::new(vec![
+            query(1, 72),
+            query(9, 73).with_weights(&weights).unwrap(),
+            query(32, 74).with_valid_len(20).unwrap(),
+            query(40, 75).with_mask(&mask).unwrap(),
+        ]);
+        for k in [1, 10, 100] {
+            let batch = maxsim_top_k_batch(&queries, &docs, k).unwrap();
+            assert_eq!(batch.len(), queries.len());
+            for (i, hits) in batch.iter().enumerate() {
+                let expected = maxsim_search(queries.query(i), &docs, k).unwrap();
+                assert_eq!(hits, &expected, "query {i}, k {k}");
+            }
+        }
+
+        let limit = top_k_batch_limit();
+        let k = limit / queries.len() + 1;
+        assert!(matches!(
+            maxsim_top_k_batch(&queries, &docs, k),
+            Err(ScoreError::HeapLimit { .. })
+        ));
+    }
+}
@@ -0,0 +1,2411 @@
+//! Configurable MaxSim scorer.
+//!
+//! `Scorer` resolves a `ScorerConfig` against the running CPU once, then
+//! scores batches with the chosen precision. bf16 needs native bf16 dot
+//! products (AVX512-BF16 or AMX) through libxsmm; elsewhere the fallback
+//! policy decides between scoring in f32 and refusing.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::aligned::AlignedVec;
+use crate::backend::Backend;
+use crate::bf16::convert_bf16_to_f32;
+#[cfg(libxsmm)]
+use crate::bf16::convert_f32_to_bf16;
+use crate::collection::{
+    Bf16DocCollection, DocId, Documents, F16DocCollection, Int8DocCollection, QueryEmbeddings,
+    TokenWeights,
+};
+use crate::docstore::{DocStore, StoreDocs};
+use crate::f16::convert_f16_to_f32;
+#[cfg(libxsmm)]
+use crate::kernel_cache::get_kernel;
+#[cfg(libxsmm)]
+use crate::libxsmm_bindings::{
+    Bf16KernelConfig, GemmSpec, JitKernel, TileConfig, Transpose, LIBXSMM_DATATYPE_BF16,
+    LIBXSMM_DATATYPE_F32,
+};
+#[cfg(libxsmm)]
+use crate::libxsmm_bindings::{Int8Kernel, Int8Signedness};
+#[cfg(libxsmm)]
+use crate::packed::ArchFamily;
+use crate::packed::{Operand, PackedDocStore};
+use crate::pool::{physical_cores, WorkerPool};
+use crate::quant::{max_abs_scale, quantize_u8, U8_ZERO_POINT};
+use crate::residual::{bytes_per_token, ResidualDocCollection};
+use crate::score::{
+    check_dim, length_buckets, length_order, score_partitioned, top_k_pruned, BlockedScorer,
+    DocScorer, Reduction, ScoreError, StackedScorer, Tiling, QUERY_BLOCK,
+};
+#[cfg(libxsmm)]
+use crate::scratch::CallScratch;
+use crate::scratch::{Scratch, ScratchPool};
+use crate::stats::{timed, CallStats, Clock, Phase};
+use crate::stats::{KernelKind, KernelShape, ScorerStats, Stage, StatsRecorder};
+use crate::store::{MmapF16DocStore, StoreDtype};
+use crate::topk::{SearchHit, SearchResults, TopK};
+#[cfg(libxsmm)]
+use crate::vnni::{pack_bf16_vnni2_a_rows, pad_bf16_vnni2_b_rows, vnni2_k};
+use crate::vnni::{unpack_bf16_vnni2_a_rows, unpad_bf16_vnni2_b_rows, VnniLayout};
+
+/// Fused-path tiles `Scorer::autotune` times, in document tokens.
+pub const AUTOTUNE_BLOCKS: [usize; 5] = [32, 48, 64, 96, 128];
+
+/// Similarity GEMM dim passes `Scorer::autotune_dim_block` times, besides
+/// the whole dim, in embedding values.
+pub const AUTOTUNE_DIM_BLOCKS: [usize; 3] = [128, 256, 512];
+
+/// Query tokens `Scorer::autotune` scores the samples against.
+pub const AUTOTUNE_Q_LEN: usize = 32;
+
+/// Arithmetic the similarity GEMM runs in. Max and sum are always f32.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Precision {
+    #[default]
+    F32,
+    /// bf16 operands with f32 accumulation (VDPBF16PS / AMX TDPBF16PS).
+    Bf16,
+}
+
+impl std::fmt::Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Precision::F32 => write!(f, "f32"),
+            Precision::Bf16 => write!(f, "bf16"),
+        }
+    }
+}
+
+/// What `Scorer::new` does when the CPU cannot run the requested precision.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Fallback {
+    /// Score in f32 instead.
+    #[default]
+    F32,
+    /// Fail with `ScoreError::Unsupported`.
+    Error,
+}
+
+/// How a query token is compared with a document token.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Similarity {
+    /// Raw dot product.
+    #[default]
+    Dot,
+    /// Cosine similarity: query rows are L2-normalized at query time and the
+    /// GEMM runs on them unchanged. Documents must be normalized at ingest,
+    /// with `DocCollection::with_similarity` or `DocBatch::with_similarity`.
+    Cosine,
+}
+
+/// How the per-query-token maxima are reduced to a document score.
+/// Masked query tokens take no part.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Aggregation {
+    /// Sum over query tokens (standard MaxSim).
+    #[default]
+    Sum,
+    /// Mean over unmasked query tokens, comparable across query lengths.
+    Mean,
+    /// Best single query token.
+    Max,
+    /// `temperature * ln Σ exp(max / temperature)`, with `temperature > 0`:
+    /// tends to `Max` as the temperature falls.
+    LogSumExp { temperature: f32 },
+}
+
+/// Which side's tokens take the max.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+    /// For each query token, the best document token (standard MaxSim);
+    /// aggregated over unmasked query tokens.
+    #[default]
+    QueryToDoc,
+    /// For each document token, the best unmasked query token; aggregated
+    /// over document tokens, so `Mean` divides by the document length.
+    DocToQuery,
+    /// Average of both directions, reduced from the same similarity tiles.
+    Symmetric,
+}
+
+/// How a batch's documents are split across the pool's workers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Partitioning {
+    /// rayon work stealing over length buckets and their documents: an
+    /// idle worker takes half of a busy one's remaining range, down to
+    /// single documents.
+    #[default]
+    WorkStealing,
+    /// One list per worker, dealt up front by token count: longest
+    /// documents first, each to the list with the fewest tokens. Workers
+    /// never steal, so a skewed batch costs no splitting, but a worker
+    /// held up elsewhere holds up the call.
+    TokenBalanced,
+}
+
+/// Which loop runs innermost over a batch whose query spans more than one
+/// `QUERY_BLOCK` of tokens. Both orders run the same GEMMs, one per query
+/// block and document tile, and give identical scores; a query of one
+/// block scores each document whole either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoopOrder {
+    /// `QueryMajor` for batches of at least `QUERY_MAJOR_MIN_DOCS`
+    /// documents averaging at most `loop_order_crossover()` tokens, else
+    /// `DocMajor`.
+    #[default]
+    Auto,
+    /// Each query block against every document of a worker's share in
+    /// turn, documents innermost: the block stays in L1 while short
+    /// documents stream past it.
+    QueryMajor,
+    /// Each document tile against every query block in turn, query blocks
+    /// innermost: a tile of a long document is read from memory once.
+    DocMajor,
+}
+
+/// Scoring options. The default sums dot products computed in f32 on the
+/// global rayon pool.
+///
+/// With the `serde` feature the configuration (de)serializes, e.g. to keep
+/// it with an experiment's results; fields missing from the input take
+/// their defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ScorerConfig {
+    pub precision: Precision,
+    pub fallback: Fallback,
+    pub similarity: Similarity,
+    pub aggregation: Aggregation,
+    pub direction: Direction,
+    /// Size of a thread pool owned by the scorer; `None` scores on the
+    /// global rayon pool, or for a `MaxSimScorer` on `physical_cores()`
+    /// threads of its own.
+    pub num_threads: Option<usize>,
+    /// Name the owned pool's threads `maxsim-worker-N`.
+    pub thread_names: bool,
+    /// GEMM operand bf16 documents are scored as. `None` follows each
+    /// packed store and takes unpacked documents as A; a store packed as
+    /// the other operand fails with `ScoreError::OperandMismatch`.
+    pub operand: Option<Operand>,
+    /// Score bf16 on the AVX512-BF16 path even where AMX is available,
+    /// e.g. to compare the two.
+    pub disable_amx: bool,
+    /// `top_k` over f32 documents skips those whose score bound rules
+    /// them out, as `maxsim_top_k_pruned` does. Needs the default
+    /// direction and aggregation.
+    pub pruning: bool,
+    /// Document tokens per tile on the fused path. `None` takes
+    /// `default_fused_block` for the documents' dim; `Scorer::autotune`
+    /// sets it from measurements.
+    pub fused_block: Option<usize>,
+    /// Embedding values per similarity GEMM pass, partial passes summed
+    /// into the tile. `None` takes `default_dim_block` for the documents'
+    /// dim; `Scorer::autotune_dim_block` sets it from measurements.
+    pub dim_block: Option<usize>,
+    pub partitioning: Partitioning,
+    pub loop_order: LoopOrder,
+    /// Score f32 documents of at most this many tokens stacked, as
+    /// `set_stack_threshold` describes; 0 disables. `None` follows
+    /// `stack_threshold()`.
+    pub stack_threshold: Option<usize>,
+    /// Bytes of scratch a worker keeps across calls: buffers that end a
+    /// share of a batch larger are freed. `None` keeps each worker's
+    /// buffers at their high-water mark.
+    pub max_scratch_bytes: Option<usize>,
+    /// Break each call's time down by stage into `ScorerStats::timing`
+    /// (and `SearchResults::timing` from `search`).
+    pub timing: bool,
+}
+
+impl ScorerConfig {
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    pub fn with_fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    pub fn with_similarity(mut self, similarity: Similarity) -> Self {
+        self.similarity = similarity;
+        self
+    }
+
+    pub fn with_aggregation(mut self, aggregation: Aggregation) -> Self {
+        self.aggregation = aggregation;
+        self
+    }
+
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn with_num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = Some(num_threads);
+        self
+    }
+
+    pub fn with_thread_names(mut self, thread_names: bool) -> Self {
+        self.thread_names = thread_names;
+        self
+    }
+
+    pub fn with_operand(mut self, operand: Operand) -> Self {
+        self.operand = Some(operand);
+        self
+    }
+
+    pub fn with_disable_amx(mut self, disable: bool) -> Self {
+        self.disable_amx = disable;
+        self
+    }
+
+    pub fn with_pruning(mut self, pruning: bool) -> Self {
+        self.pruning = pruning;
+        self
+    }
+
+    pub fn with_fused_block(mut self, block: usize) -> Self {
+        self.fused_block = Some(block);
+        self
+    }
+
+    pub fn with_dim_block(mut self, dims: usize) -> Self {
+        self.dim_block = Some(dims);
+        self
+    }
+
+    pub fn with_partitioning(mut self, partitioning: Partitioning) -> Self {
+        self.partitioning = partitioning;
+        self
+    }
+
+    pub fn with_loop_order(mut self, order: LoopOrder) -> Self {
+        self.loop_order = order;
+        self
+    }
+
+    pub fn with_stack_threshold(mut self, d_len: usize) -> Self {
+        self.stack_threshold = Some(d_len);
+        self
+    }
+
+    pub fn with_max_scratch_bytes(mut self, bytes: usize) -> Self {
+        self.max_scratch_bytes = Some(bytes);
+        self
+    }
+
+    pub fn with_timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub(crate) fn reduction(&self) -> Reduction {
+        Reduction {
+            direction: self.direction,
+            aggregation: self.aggregation,
+        }
+    }
+
+    pub(crate) fn tiling(&self) -> Tiling {
+        Tiling {
+            tokens: self.fused_block,
+            dims: self.dim_block,
+            order: self.loop_order,
+            stack: self.stack_threshold,
+        }
+    }
+}
+
+/// Bucket shapes `Scorer::warmup` prepares: every query length against
+/// every document length, for `dtype` documents of `dim` values as the
+/// GEMM sees them (a store's padded `DocStore::dim`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WarmupShapes {
+    pub dtype: StoreDtype,
+    pub dim: usize,
+    pub query_lens: Vec<usize>,
+    pub doc_lens: Vec<usize>,
+}
+
+/// What `Scorer::warmup` did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WarmupReport {
+    /// Bucket shapes prepared; empty ones need no kernel and are skipped.
+    pub shapes: usize,
+    /// Kernels libxsmm generated meanwhile, by its code registry: shapes
+    /// it already held are not counted, kernels other threads generated
+    /// at the same time are. Always 0 without libxsmm.
+    pub kernels_compiled: usize,
+    pub elapsed: Duration,
+}
+
+/// Batch scorer with a fixed, CPU-resolved precision.
+///
+/// Batches are split across rayon workers by document, each with scratch
+/// buffers the scorer keeps from call to call, and the results are written
+/// back in collection order.
+/// With `num_threads` set the work runs on the scorer's own pool, leaving
+/// the global pool to the caller; its threads wait between calls and are
+/// joined when the last clone is dropped. Every call is counted in
+/// `stats`; clones share the counters as they share the pool.
+#[derive(Clone, Debug)]
+pub struct Scorer {
+    config: ScorerConfig,
+    precision: Precision,
+    pool: Option<Arc<WorkerPool>>,
+    /// Per-worker buffers, kept across calls.
+    scratch: Arc<ScratchPool>,
+    stats: Arc<StatsRecorder>,
+    #[cfg(libxsmm)]
+    bf16: Bf16KernelConfig,
+}
+
+impl Scorer {
+    /// Resolve `config` against this CPU. Fails with
+    /// `ScoreError::IncompatibleConfig` when pruning meets another direction
+    /// or aggregation, or a `LogSumExp` temperature is not a positive finite
+    /// number.
+    pub fn new(config: ScorerConfig) -> Result<Self, ScoreError> {
+        if config.pruning && config.reduction() != Reduction::default() {
+            return Err(ScoreError::IncompatibleConfig {
+                reason: "pruning needs the default direction and aggregation".to_string(),
+            });
+        }
+        if let Aggregation::LogSumExp { temperature } = config.aggregation {
+            if !temperature.is_finite() || temperature <= 0.0 {
+                return Err(ScoreError::IncompatibleConfig {
+                    reason: format!("log-sum-exp temperature must be positive, not {temperature}"),
+                });
+            }
+        }
+        #[cfg(libxsmm)]
+        let bf16 = {
+            crate::LIBXSMM_CTX.get_or_init(crate::libxsmm_bindings::LibxsmmContext::acquire);
+            Bf16KernelConfig::auto()
+        };
+        #[cfg(libxsmm)]
+        let native_bf16 = bf16.arch.supports_bf16_dot();
+        #[cfg(not(libxsmm))]
+        let native_bf16 = false;
+
+        let precision = match (config.precision, config.fallback) {
+            (Precision::Bf16, _) if native_bf16 => Precision::Bf16,
+            (Precision::Bf16, Fallback::F32) => {
+                trace_event!(DEBUG, "no native bf16 dot products, scoring in f32");
+                Precision::F32
+            }
+            (Precision::Bf16, Fallback::Error) => {
+                return Err(ScoreError::Unsupported {
+                    precision: Precision::Bf16,
+                })
+            }
+            (Precision::F32, _) => Precision::F32,
+        };
+        let pool = match config.num_threads {
+            Some(num_threads) => Some(Arc::new(WorkerPool::new(num_threads, config.thread_names)?)),
+            None => None,
+        };
+        let threads = pool
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, |pool| pool.threads());
+        let scratch = Arc::new(ScratchPool::new(threads, config.max_scratch_bytes));
+        Ok(Self {
+            config,
+            precision,
+            pool,
+            scratch,
+            stats: Arc::default(),
+            #[cfg(libxsmm)]
+            bf16,
+        })
+    }
+
+    pub fn config(&self) -> &ScorerConfig {
+        &self.config
+    }
+
+    /// Precision batches are actually scored in, after the fallback policy.
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// Threads a call is split across: the owned pool's, or the global
+    /// pool's without one.
+    pub fn num_threads(&self) -> usize {
+        self.pool
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, |pool| pool.threads())
+    }
+
+    /// Backend the f32 GEMMs currently run on.
+    pub fn backend(&self) -> Backend {
+        Backend::current()
+    }
+
+    /// Totals of every call since construction or `reset_stats`.
+    pub fn stats(&self) -> ScorerStats {
+        self.stats.totals()
+    }
+
+    /// The most recently finished call.
+    pub fn last_stats(&self) -> ScorerStats {
+        self.stats.last()
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.reset()
+    }
+
+    /// Time each of `AUTOTUNE_BLOCKS` as the fused-path tile on
+    /// `sample_docs`, forced onto the fused path whatever their length,
+    /// and keep the fastest in `config().fused_block`. The budget is split
+    /// evenly between the candidates; each is warmed up with one pass over
+    /// the samples, then scores them again until its share runs out,
+    /// keeping its fastest pass. Every pass counts against the budget, the
+    /// warm-up ones included, and a candidate that overruns its share
+    /// leaves the later ones less; only a budget shorter than two passes
+    /// per candidate is overrun. Scoring runs on the calling thread against
+    /// a query of the samples' first `AUTOTUNE_Q_LEN` tokens, so pick
+    /// documents of the lengths and dim the scorer will see. Scores do not
+    /// depend on the tile. `ScoreError::EmptyDocument` when the samples
+    /// have no tokens.
+    pub fn autotune<D: Documents + ?Sized>(
+        &mut self,
+        sample_docs: &D,
+        budget: Duration,
+    ) -> Result<usize, ScoreError> {
+        let tiling = self.config.tiling();
+        let block = self.fastest(sample_docs, budget, &AUTOTUNE_BLOCKS, |block| {
+            let tiling = Tiling {
+                tokens: Some(block),
+                ..tiling
+            };
+            move |q_len, d_len, dim| DocScorer::fused(q_len, d_len, dim, tiling)
+        })?;
+        self.config.fused_block = Some(block);
+        Ok(block)
+    }
+
+    /// `autotune` for the similarity GEMM's dim pass: times each of
+    /// `AUTOTUNE_DIM_BLOCKS` under the samples' dim, and the whole dim, at
+    /// the configured fused tile, and keeps the fastest in
+    /// `config().dim_block`. Scores move with the pass only by summation
+    /// order.
+    pub fn autotune_dim_block<D: Documents + ?Sized>(
+        &mut self,
+        sample_docs: &D,
+        budget: Duration,
+    ) -> Result<usize, ScoreError> {
+        let dim = sample_docs.dim();
+        let mut candidates: Vec<usize> = AUTOTUNE_DIM_BLOCKS
+            .iter()
+            .filter(|&&dims| dims < dim)
+            .chain([&dim])
+            .copied()
+            .collect();
+        candidates.dedup();
+        let tiling = self.config.tiling();
+        let dims = self.fastest(sample_docs, budget, &candidates, |dims| {
+            let tiling = Tiling {
+                dims: Some(dims),
+                ..tiling
+            };
+            move |q_len, d_len, dim| DocScorer::tiled(q_len, d_len, dim, tiling)
+        })?;
+        self.config.dim_block = Some(dims);
+        Ok(dims)
+    }
+
+    /// The fastest of `candidates` on `sample_docs`, as `autotune` times
+    /// them, each scoring with the `DocScorer`s `scorer(candidate)` builds
+    /// per (q_len, d_len, dim).
+    fn fastest<D: Documents + ?Sized, S: Fn(usize, usize, usize) -> DocScorer>(
+        &self,
+        sample_docs: &D,
+        budget: Duration,
+        candidates: &[usize],
+        scorer: impl Fn(usize) -> S,
+    ) -> Result<usize, ScoreError> {
+        let dim = sample_docs.dim();
+        let mut q_data = Vec::with_capacity(AUTOTUNE_Q_LEN * dim);
+        for i in 0..sample_docs.len() {
+            let want = AUTOTUNE_Q_LEN * dim - q_data.len();
+            if want == 0 {
+                break;
+            }
+            let doc = sample_docs.doc(i);
+            q_data.extend_from_slice(&doc[..want.min(doc.len())]);
+        }
+        let q_len = q_data.len() / dim.max(1);
+        if q_len == 0 {
+            return Err(ScoreError::EmptyDocument);
+        }
+        let query = QueryEmbeddings::new(q_data, q_len, dim)?;
+        let query = self.prepare_query(&query);
+        let q_data = query.active().0;
+        let reduction = self.config.reduction();
+        let mut lens: Vec<usize> = (0..sample_docs.len())
+            .map(|i| sample_docs.doc_len(i))
+            .filter(|&d_len| d_len > 0)
+            .collect();
+        lens.sort_unstable();
+        lens.dedup();
+
+        let share = budget / candidates.len() as u32;
+        let mut scratch = AlignedVec::new();
+        let mut best = (Duration::MAX, candidates[0]);
+        let start = Instant::now();
+        for (c, &candidate) in candidates.iter().enumerate() {
+            // Counted from the start, so overruns come out of later shares
+            let deadline = share * (c as u32 + 1);
+            let build = scorer(candidate);
+            let scorers: HashMap<usize, DocScorer> = lens
+                .iter()
+                .map(|&d_len| (d_len, build(q_len, d_len, dim).with_reduction(reduction)))
+                .collect();
+            let mut pass = || {
+                let start = Instant::now();
+                for i in 0..sample_docs.len() {
+                    if let Some(scorer) = scorers.get(&sample_docs.doc_len(i)) {
+                        let doc = black_box(sample_docs.doc(i));
+                        let tokens = TokenWeights::default();
+                        black_box(scorer.score(q_data, doc, tokens, &mut scratch));
+                    }
+                }
+                start.elapsed()
+            };
+            pass();
+            let mut fastest = pass();
+            while start.elapsed() < deadline {
+                fastest = fastest.min(pass());
+            }
+            trace_event!(
+                DEBUG,
+                candidate,
+                nanos = fastest.as_nanos() as u64,
+                "autotune candidate"
+            );
+            if fastest < best.0 {
+                best = (fastest, candidate);
+            }
+        }
+        Ok(best.1)
+    }
+
+    /// Build the kernels of every bucket in `shapes` the way scoring builds
+    /// them, at this scorer's precision, tiling and stacking, for every
+    /// path a bucket can take (stacked, query-blocked, fused, AMX), so
+    /// later calls on those shapes generate no code (`registry_info().size`
+    /// stays flat). With `execute` each bucket is also scored once on
+    /// zeroed buffers, faulting in the kernels' code pages. bf16 documents
+    /// are taken as the configured operand.
+    pub fn warmup(&self, shapes: &WarmupShapes, execute: bool) -> WarmupReport {
+        self.warmup_as(shapes, self.config.operand.unwrap_or_default(), execute)
+    }
+
+    fn warmup_as(&self, shapes: &WarmupShapes, operand: Operand, execute: bool) -> WarmupReport {
+        let start = Instant::now();
+        let before = registry_size();
+        let dim = shapes.dim;
+        let mut scratch = Scratch::default();
+        let mut prepared = 0;
+        for &q_len in &shapes.query_lens {
+            for &d_len in &shapes.doc_lens {
+                if q_len == 0 || d_len == 0 || dim == 0 {
+                    continue;
+                }
+                trace_span!(DEBUG, "maxsim.warmup", q_len, d_len, dim);
+                prepared += 1;
+                let shape = (q_len, d_len, dim);
+                black_box(self.warm_bucket(shape, shapes.dtype, operand, execute, &mut scratch));
+            }
+        }
+        WarmupReport {
+            shapes: prepared,
+            kernels_compiled: registry_size().saturating_sub(before),
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Build the scorers of one bucket shape as the batch path for `dtype`
+    /// does, and with `execute` score zeroed buffers; the summed scores.
+    fn warm_bucket(
+        &self,
+        (q_len, d_len, dim): (usize, usize, usize),
+        dtype: StoreDtype,
+        operand: Operand,
+        execute: bool,
+        scratch: &mut Scratch,
+    ) -> f32 {
+        let (reduction, tiling) = (self.config.reduction(), self.config.tiling());
+        let tokens = TokenWeights::default();
+        let (query, doc) = (vec![0.0; q_len * dim], vec![0.0; d_len * dim]);
+        let scorer = match dtype {
+            StoreDtype::Int8 => {
+                let scorer = Int8DocScorer::new(q_len, d_len, dim, reduction);
+                if !execute {
+                    return 0.0;
+                }
+                let query = (&vec![0u8; q_len * dim][..], &vec![1.0; q_len][..]);
+                let doc = (&vec![0i8; d_len * dim][..], &[1.0][..]);
+                return scorer.score(query, doc, 0, tokens, scratch);
+            }
+            // As `score_buckets` picks: stacked short buckets, else query
+            // blocks past `QUERY_BLOCK`, else whole or fused tiles
+            StoreDtype::F32 if d_len <= tiling.stack_threshold() => {
+                let scorer = StackedScorer::new(q_len, d_len, dim, tiling.dims(dim), reduction);
+                let mut score = [0.0];
+                if execute {
+                    let docs = std::iter::once((&doc[..], 0));
+                    scorer.score(&query, docs, tokens, scratch, &mut score);
+                }
+                return score[0];
+            }
+            StoreDtype::F32 if q_len > QUERY_BLOCK => {
+                let scorer = BlockedScorer::new(q_len, d_len, dim, tiling, reduction);
+                return match execute {
+                    true => scorer.score_doc_major(&query, &doc, 0, tokens, scratch),
+                    false => 0.0,
+                };
+            }
+            StoreDtype::Bf16 => {
+                #[cfg(libxsmm)]
+                if let Some(amx) = self.amx_scorer((q_len, d_len, dim), operand, true) {
+                    if execute {
+                        let query = query_to_bf16(&query, q_len, dim, operand);
+                        let doc = vec![0u16; d_len * vnni2_k(dim)];
+                        let _tiles = amx.tiles.enter();
+                        black_box(amx.score(&query, (&doc, 0), tokens, &mut scratch.sims));
+                    }
+                }
+                self.bucket_scorer(q_len, d_len, dim, operand)
+            }
+            StoreDtype::F32 | StoreDtype::F16 => {
+                let scorer = DocScorer::tiled(q_len, d_len, dim, tiling);
+                BucketScorer::F32(scorer.with_reduction(reduction))
+            }
+        };
+        if !execute {
+            return 0.0;
+        }
+        match &scorer {
+            #[cfg(libxsmm)]
+            BucketScorer::Bf16(scorer) => {
+                let query = query_to_bf16(&query, q_len, dim, operand);
+                let doc = vec![0u16; d_len * dim];
+                scorer.score(&query, &doc, 0, tokens, scratch)
+            }
+            BucketScorer::F32(scorer) => scorer.score(&query, &doc, tokens, &mut scratch.sims),
+        }
+    }
+
+    /// Stats of a call starting now.
+    fn start_call(&self) -> CallStats {
+        CallStats::start()
+            .with_tiling(self.config.tiling())
+            .with_timing(self.config.timing)
+    }
+
+    /// MaxSim score of `query` against every f32 document, in collection
+    /// order. f32 documents always score in f32; convert them with
+    /// `Bf16DocCollection::from_documents` to score in bf16.
+    pub fn score_batch<D: Documents + ?Sized>(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &D,
+    ) -> Result<Vec<f32>, ScoreError> {
+        let (reduction, tiling) = (self.config.reduction(), self.config.tiling());
+        let partitioning = self.config.partitioning;
+        let mut call = self.start_call();
+        let timing = call.timing();
+        let scratch = self.scratch.call(timing.as_deref());
+        let query = self.prepare_query(query);
+        call.end(Stage::Prepare);
+        let scores = self.install(|| {
+            crate::score::score_batch_aggregated(
+                &query,
+                docs,
+                reduction,
+                tiling,
+                partitioning,
+                scratch,
+            )
+        })?;
+        call.end(Stage::Score);
+        call.query(&query);
+        call.f32_documents(query.active().1, docs, docs.len(), true);
+        self.stats.record(call);
+        Ok(scores)
+    }
+
+    /// The `k` best f32 documents for `query`, as `maxsim_top_k` ranks them
+    /// but under this scorer's similarity, direction and aggregation;
+    /// pruned when configured.
+    pub fn top_k<D: Documents + ?Sized>(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &D,
+        k: usize,
+    ) -> Result<Vec<(DocId, f32)>, ScoreError> {
+        self.ranked(query, docs, k).map(|(top, _)| top)
+    }
+
+    /// `top_k` as the `SearchResults` of query `query_id`, documents by
+    /// their external ids (`Documents::id`), with the call's `Timing` when
+    /// the scorer collects it.
+    pub fn search<D: Documents + ?Sized>(
+        &self,
+        query_id: u64,
+        query: &QueryEmbeddings,
+        docs: &D,
+        k: usize,
+    ) -> Result<SearchResults, ScoreError> {
+        let (top, stats) = self.ranked(query, docs, k)?;
+        let hits = top
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (i, score))| SearchHit {
+                id: docs.id(i),
+                score,
+                rank: rank as u32,
+            })
+            .collect();
+        Ok(SearchResults::new(query_id, hits).with_timing(stats.timing))
+    }
+
+    /// `top_k` with the stats of the call.
+    fn ranked<D: Documents + ?Sized>(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &D,
+        k: usize,
+    ) -> Result<(Vec<(DocId, f32)>, ScorerStats), ScoreError> {
+        let (reduction, tiling) = (self.config.reduction(), self.config.tiling());
+        let partitioning = self.config.partitioning;
+        let mut call = self.start_call();
+        let timing = call.timing();
+        let clock = timing.as_deref().map(Clock::start);
+        let scratch = self.scratch.call(timing.as_deref());
+        let query = self.prepare_query(query);
+        call.end(Stage::Prepare);
+        let (top, scored) = self.install(|| match self.config.pruning {
+            true => top_k_pruned(&query, docs, k, tiling, scratch)
+                .map(|(top, stats)| (top, stats.scored)),
+            false => crate::score::top_k_aggregated(
+                &query,
+                docs,
+                k,
+                reduction,
+                tiling,
+                partitioning,
+                scratch,
+            )
+            .map(|top| (top, docs.len())),
+        })?;
+        call.end(Stage::Score);
+        call.query(&query);
+        call.f32_documents(query.active().1, docs, scored, !self.config.pruning);
+        drop(clock);
+        Ok((top, self.stats.record(call)))
+    }
+
+    /// MaxSim score of `query` against every document of `store`, in
+    /// store order, by the scoring path of its dtype. `query` must have the
+    /// store's (padded) dim.
+    pub(crate) fn score_store(
+        &self,
+        query: &QueryEmbeddings,
+        store: &DocStore,
+    ) -> Result<Vec<f32>, ScoreError> {
+        match store.docs() {
+            StoreDocs::F32(docs) => self.score_batch(query, &Bucketed { docs, store }),
+            StoreDocs::F16(docs) => self.f16_batch(query, &Bucketed { docs, store }),
+            StoreDocs::Bf16(docs) => {
+                self.install(|| self.bf16_batch(query, &Bucketed { docs, store }))
+            }
+            StoreDocs::Int8(docs) => {
+                self.install(|| self.int8_batch(query, docs, |i| store.padding(i)))
+            }
+        }
+    }
+
+    /// MaxSim score of `query` against every bf16 document, in collection
+    /// order.
+    ///
+    /// In bf16 the query is rounded to bf16 once for the whole batch and each
+    /// document is VNNI2-packed into the A operand of a bf16→f32 JIT kernel
+    /// (one per distinct length). Lengths the JIT cannot handle, and every
+    /// document in f32 mode, are widened to f32 and scored as
+    /// `maxsim_score_batch` would.
+    pub fn score_batch_bf16(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &Bf16DocCollection,
+    ) -> Result<Vec<f32>, ScoreError> {
+        self.install(|| self.bf16_batch(query, docs))
+    }
+
+    /// `score_batch_bf16` over a store packed at ingest: VNNI2-layout
+    /// documents go to the bf16 kernel as they are, in the orientation of
+    /// the store's `Operand`, skipping the per-query packing. In f32 mode
+    /// they are unpacked to rows and widened.
+    pub fn score_batch_packed(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &PackedDocStore,
+    ) -> Result<Vec<f32>, ScoreError> {
+        self.install(|| self.bf16_batch(query, docs))
+    }
+
+    fn bf16_batch<B: Bf16Docs>(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &B,
+    ) -> Result<Vec<f32>, ScoreError> {
+        check_dim(query, docs.dim())?;
+        let operand = match (docs.operand(), self.config.operand) {
+            (Some(stored), Some(scorer)) if stored != scorer => {
+                return Err(ScoreError::OperandMismatch { stored, scorer })
+            }
+            (stored, scorer) => stored.or(scorer).unwrap_or_default(),
+        };
+        let mut call = self.start_call();
+        let timing = call.timing();
+        let clock = timing.as_deref().map(Clock::start);
+        let scratch = self.scratch.call(timing.as_deref());
+        let query = self.prepare_query(query);
+        call.query(&query);
+        let order = length_order(docs.len(), |i| docs.doc_len(i));
+        let buckets = length_buckets(&order, |i| docs.doc_len(i));
+        let (q_data, q_len, tokens) = query.active();
+        let dim = docs.dim();
+        let packed = docs.layout() == VnniLayout::Vnni2;
+        trace_span!(
+            DEBUG,
+            "maxsim.score_batch",
+            dtype = "bf16",
+            docs = docs.len(),
+            buckets = buckets.len(),
+            q_len,
+            dim,
+            packed
+        );
+
+        #[cfg(libxsmm)]
+        let q_bf16 = (self.precision == Precision::Bf16).then(|| {
+            timed(Phase::Convert, || {
+                query_to_bf16(q_data, q_len, dim, operand)
+            })
+        });
+        #[cfg(libxsmm)]
+        let q_bf16 = q_bf16.as_deref();
+        #[cfg(libxsmm)]
+        let (amx, buckets) =
+            self.amx_buckets(buckets, (q_len, dim, operand), packed, |i| docs.doc_len(i));
+        call.end(Stage::Prepare);
+
+        let call_ref = &call;
+        let setup = move |ids: &[DocId]| {
+            let d_len = docs.doc_len(ids[0]);
+            trace_span!(DEBUG, "maxsim.bucket", d_len, docs = ids.len());
+            let scorer = (q_len > 0 && d_len > 0 && dim > 0)
+                .then(|| self.bucket_scorer(q_len, d_len, dim, operand));
+            let kernel = match &scorer {
+                #[cfg(libxsmm)]
+                Some(BucketScorer::Bf16(_)) => KernelKind::Bf16,
+                _ => KernelKind::F32,
+            };
+            let shape = KernelShape {
+                kernel,
+                q_len,
+                d_len,
+                dim,
+            };
+            call_ref.bucket(shape, ids.len(), size_of_val(docs.doc(ids[0])));
+            (scorer, d_len)
+        };
+        let score =
+            move |(scorer, d_len): &(Option<BucketScorer>, usize), scratch: &mut Scratch, i| {
+                let d_len = *d_len;
+                match scorer {
+                    #[cfg(libxsmm)]
+                    Some(BucketScorer::Bf16(scorer)) => {
+                        let query = q_bf16.expect("bf16 query built in bf16 mode");
+                        let (doc, padding) = (docs.doc(i), docs.padding(i));
+                        if packed {
+                            let sims = &mut scratch.sims;
+                            scorer.score_packed(query, doc, padding, tokens, sims)
+                        } else {
+                            scorer.score(query, doc, padding, tokens, scratch)
+                        }
+                    }
+                    Some(BucketScorer::F32(scorer)) => {
+                        timed(Phase::Convert, || {
+                            let rows = match (packed, operand) {
+                                (true, Operand::A) => {
+                                    let rows = &mut scratch.rows;
+                                    unpack_bf16_vnni2_a_rows(docs.doc(i), d_len, dim, rows);
+                                    &scratch.rows[..]
+                                }
+                                (true, Operand::B) => {
+                                    let rows = &mut scratch.rows;
+                                    unpad_bf16_vnni2_b_rows(docs.doc(i), d_len, dim, rows);
+                                    &scratch.rows[..]
+                                }
+                                (false, _) => docs.doc(i),
+                            };
+                            scratch.doc.resize(d_len * dim, 0.0);
+                            convert_bf16_to_f32(rows, &mut scratch.doc);
+                        });
+                        let (doc, padding) = (&scratch.doc, docs.padding(i));
+                        scorer.score_padded(q_data, doc, padding, tokens, &mut scratch.sims)
+                    }
+                    None => 0.0,
+                }
+            };
+        let doc_len = |i| docs.doc_len(i);
+        let partitioning = self.config.partitioning;
+        let scored = score_partitioned(
+            &buckets,
+            doc_len,
+            partitioning,
+            setup,
+            move || scratch.get(),
+            score,
+        );
+        #[cfg(libxsmm)]
+        let scored = {
+            let query = q_bf16.unwrap_or_default();
+            scored.chain(self.amx_batch(&amx, query, docs, tokens, &call, scratch))
+        };
+        let scored: Vec<(DocId, f32)> = scored.collect();
+        call.end(Stage::Score);
+
+        let mut scores = vec![0.0f32; docs.len()];
+        for (i, score) in scored {
+            scores[i] = score;
+        }
+        call.end(Stage::Collect);
+        drop(clock);
+        self.stats.record(call);
+        Ok(scores)
+    }
+
+    /// `buckets` split into those the AMX path scores, each with its
+    /// scorer, and the rest.
+    #[cfg(libxsmm)]
+    fn amx_buckets<'b>(
+        &self,
+        buckets: Vec<&'b [DocId]>,
+        (q_len, dim, operand): (usize, usize, Operand),
+        packed: bool,
+        doc_len: impl Fn(DocId) -> usize,
+    ) -> (Vec<AmxBucket<'b>>, Vec<&'b [DocId]>) {
+        let (mut amx, mut rest) = (Vec::new(), Vec::new());
+        for ids in buckets {
+            let d_len = doc_len(ids[0]);
+            match self.amx_scorer((q_len, d_len, dim), operand, packed) {
+                Some(scorer) => amx.push((scorer, ids)),
+                None => rest.push(ids),
+            }
+        }
+        (amx, rest)
+    }
+
+    /// The AMX scorer for a bucket of `(q_len, d_len, dim)`, if AMX takes
+    /// it: bf16 documents `packed` as A on CPUs with tiles, of whole tiles
+    /// it reads in place, unless `disable_amx` is set or the shape fails to
+    /// JIT.
+    #[cfg(libxsmm)]
+    fn amx_scorer(
+        &self,
+        (q_len, d_len, dim): (usize, usize, usize),
+        operand: Operand,
+        packed: bool,
+    ) -> Option<AmxScorer> {
+        let enabled = self.precision == Precision::Bf16
+            && operand == Operand::A
+            && packed
+            && !self.config.disable_amx
+            && self.bf16.arch.supports_amx()
+            && q_len > 0
+            && d_len > 0
+            && dim > 0
+            && d_len.is_multiple_of(ArchFamily::Amx.token_multiple());
+        let reduction = self.config.reduction();
+        enabled
+            .then(|| AmxScorer::new((q_len, d_len, dim), &self.bf16, reduction))
+            .flatten()
+    }
+
+    /// (doc id, score) for the documents of the AMX buckets. Each bucket
+    /// is dealt out in one share per worker, which configures the tiles
+    /// once and scores its whole share under that configuration.
+    #[cfg(libxsmm)]
+    fn amx_batch<B: Bf16Docs>(
+        &self,
+        amx: &[AmxBucket<'_>],
+        query: &[u16],
+        docs: &B,
+        tokens: TokenWeights,
+        call: &CallStats,
+        scratch: CallScratch<'_>,
+    ) -> Vec<(DocId, f32)> {
+        let workers = rayon::current_num_threads();
+        amx.par_iter()
+            .flat_map(|(scorer, ids)| {
+                trace_span!(
+                    DEBUG,
+                    "maxsim.bucket",
+                    d_len = scorer.d_len,
+                    docs = ids.len()
+                );
+                call.bucket(scorer.shape(), ids.len(), size_of_val(docs.doc(ids[0])));
+                let share = ids.len().div_ceil(workers);
+                ids.par_chunks(share).flat_map_iter(move |share| {
+                    let _tiles = scorer.tiles.enter();
+                    let mut scratch = scratch.get();
+                    share
+                        .iter()
+                        .map(|&i| {
+                            let doc = (docs.doc(i), docs.padding(i));
+                            (i, scorer.score(query, doc, tokens, &mut scratch.sims))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect()
+    }
+
+    /// MaxSim score of `query` against every document of an f16 store, in
+    /// collection order. Independent of `precision`: each tile is widened
+    /// to f32 (F16C when available) into per-worker scratch just before
+    /// its GEMM, so the only error against f32 storage is the f16 rounding
+    /// at write time.
+    pub fn score_batch_f16(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &MmapF16DocStore,
+    ) -> Result<Vec<f32>, ScoreError> {
+        self.f16_batch(query, docs)
+    }
+
+    /// `score_batch_f16` for f16 documents held in memory.
+    pub fn score_batch_f16_collection(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &F16DocCollection,
+    ) -> Result<Vec<f32>, ScoreError> {
+        self.f16_batch(query, docs)
+    }
+
+    pub(crate) fn f16_batch<F: F16Docs + ?Sized>(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &F,
+    ) -> Result<Vec<f32>, ScoreError> {
+        let dim = docs.dim();
+        let decode = |i: DocId, tokens: Range<usize>, dst: &mut [f32]| {
+            convert_f16_to_f32(&docs.doc(i)[tokens.start * dim..tokens.end * dim], dst)
+        };
+        let shape = (docs.len(), dim, |i| docs.doc_len(i), |i| docs.padding(i));
+        let token_bytes = dim * size_of::<u16>();
+        self.install(|| self.decoded_batch(query, shape, token_bytes, decode))
+    }
+
+    /// MaxSim score of `query` against every residual-compressed document,
+    /// in collection order. Independent of `precision`: each tile is
+    /// decoded (centroid plus codebook residual) into per-worker f32
+    /// scratch just before its GEMM, so the scores are those of the
+    /// reconstructed embeddings.
+    pub fn score_batch_residual(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &ResidualDocCollection,
+    ) -> Result<Vec<f32>, ScoreError> {
+        let decode = |i, tokens, dst: &mut [f32]| docs.decode(i, tokens, dst);
+        let shape = (docs.len(), docs.dim(), |i| docs.doc_len(i), |_| 0);
+        // A u32 centroid id and the packed residual codes
+        let token_bytes = size_of::<u32>() + bytes_per_token(docs.dim());
+        self.install(|| self.decoded_batch(query, shape, token_bytes, decode))
+    }
+
+    /// f32 scoring of documents held in another encoding: `docs` is
+    /// (count, dim, token count per document, bucket padding per document),
+    /// and `decode(i, tokens, dst)` writes rows `tokens` of document `i` as
+    /// f32, one tile at a time.
+    fn decoded_batch(
+        &self,
+        query: &QueryEmbeddings,
+        (n_docs, dim, doc_len, padding): (
+            usize,
+            usize,
+            impl Fn(DocId) -> usize + Sync,
+            impl Fn(DocId) -> usize + Sync,
+        ),
+        token_bytes: usize,
+        decode: impl Fn(DocId, Range<usize>, &mut [f32]) + Sync,
+    ) -> Result<Vec<f32>, ScoreError> {
+        check_dim(query, dim)?;
+        let mut call = self.start_call();
+        let timing = call.timing();
+        let scratch = self.scratch.call(timing.as_deref());
+        let query = self.prepare_query(query);
+        call.query(&query);
+        let order = length_order(n_docs, &doc_len);
+        let buckets = length_buckets(&order, &doc_len);
+        let (q_data, q_len, tokens) = query.active();
+        trace_span!(
+            DEBUG,
+            "maxsim.score_batch",
+            dtype = "decoded",
+            docs = n_docs,
+            buckets = buckets.len(),
+            q_len,
+            dim
+        );
+        let (reduction, doc_len, decode) = (self.config.reduction(), &doc_len, &decode);
+        let padding = &padding;
+        call.end(Stage::Prepare);
+
+        let call_ref = &call;
+        let setup = move |ids: &[DocId]| {
+            let d_len = doc_len(ids[0]);
+            trace_span!(DEBUG, "maxsim.bucket", d_len, docs = ids.len());
+            let scorer = (q_len > 0 && d_len > 0 && dim > 0).then(|| {
+                DocScorer::tiled(q_len, d_len, dim, self.config.tiling()).with_reduction(reduction)
+            });
+            let shape = KernelShape {
+                kernel: KernelKind::F32,
+                q_len,
+                d_len,
+                dim,
+            };
+            call_ref.bucket(shape, ids.len(), d_len * token_bytes);
+            scorer
+        };
+        let score = move |scorer: &Option<DocScorer>, scratch: &mut Scratch, i| match scorer {
+            Some(scorer) => scorer.score_decoded(
+                q_data,
+                |tokens, dst| decode(i, tokens, dst),
+                padding(i),
+                tokens,
+                &mut scratch.sims,
+                &mut scratch.doc,
+            ),
+            None => 0.0,
+        };
+        let partitioning = self.config.partitioning;
+        let scored: Vec<(DocId, f32)> = score_partitioned(
+            &buckets,
+            doc_len,
+            partitioning,
+            setup,
+            move || scratch.get(),
+            score,
+        )
+        .collect();
+        call.end(Stage::Score);
+
+        let mut scores = vec![0.0f32; n_docs];
+        for (i, score) in scored {
+            scores[i] = score;
+        }
+        call.end(Stage::Collect);
+        self.stats.record(call);
+        Ok(scores)
+    }
+
+    /// MaxSim score of `query` against every int8 document, in collection
+    /// order.
+    ///
+    /// The query is quantized per token to zero-point-128 u8 once per call.
+    /// Each document runs a u8×i8→i32 VNNI GEMM (a plain integer loop when
+    /// libxsmm or VNNI is unavailable, with identical results); the i32 tile
+    /// is corrected for the zero point, rescaled by `scale_q * scale_d` into
+    /// f32, and reduced with the usual max/sum. Independent of `precision`.
+    ///
+    /// Accuracy: on 1000 L2-normalized random documents (dim 128, 64-256
+    /// tokens) and 30 random 32-token queries, recall@10 against the f32
+    /// ranking is 0.98 with per-token scales and 0.97 with per-document
+    /// scales.
+    pub fn score_batch_int8(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &Int8DocCollection,
+    ) -> Result<Vec<f32>, ScoreError> {
+        self.install(|| self.int8_batch(query, docs, |_| 0))
+    }
+
+    /// `score_batch_int8` with `padding(i)` rows of bucket padding in
+    /// document `i`.
+    fn int8_batch(
+        &self,
+        query: &QueryEmbeddings,
+        docs: &Int8DocCollection,
+        padding: impl Fn(DocId) -> usize + Sync,
+    ) -> Result<Vec<f32>, ScoreError> {
+        check_dim(query, docs.dim())?;
+        let mut call = self.start_call();
+        let timing = call.timing();
+        let clock = timing.as_deref().map(Clock::start);
+        let scratch = self.scratch.call(timing.as_deref());
+        let query = self.prepare_query(query);
+        call.query(&query);
+        let order = length_order(docs.len(), |i| docs.doc_len(i));
+        let buckets = length_buckets(&order, |i| docs.doc_len(i));
+        let (q_data, q_len, tokens) = query.active();
+        let dim = docs.dim();
+        trace_span!(
+            DEBUG,
+            "maxsim.score_batch",
+            dtype = "int8",
+            docs = docs.len(),
+            buckets = buckets.len(),
+            q_len,
+            dim
+        );
+
+        let mut q_u8 = vec![0u8; q_len * dim];
+        let mut q_scales = Vec::with_capacity(q_len);
+        if dim > 0 {
+            timed(Phase::Convert, || {
+                for (row, dst) in q_data.chunks_exact(dim).zip(q_u8.chunks_exact_mut(dim)) {
+                    let scale = max_abs_scale(row);
+                    quantize_u8(row, scale, dst);
+                    q_scales.push(scale);
+                }
+            });
+        }
+        let (q_u8, q_scales, padding) = (&q_u8[..], &q_scales[..], &padding);
+        call.end(Stage::Prepare);
+
+        let call_ref = &call;
+        let setup = move |ids: &[DocId]| {
+            let d_len = docs.doc_len(ids[0]);
+            trace_span!(DEBUG, "maxsim.bucket", d_len, docs = ids.len());
+            let scorer = (q_len > 0 && d_len > 0 && dim > 0)
+                .then(|| Int8DocScorer::new(q_len, d_len, dim, self.config.reduction()));
+            let shape = KernelShape {
+                kernel: KernelKind::Int8,
+                q_len,
+                d_len,
+                dim,
+            };
+            let doc_bytes = docs.doc(ids[0]).len() + size_of_val(docs.doc_scales(ids[0]));
+            call_ref.bucket(shape, ids.len(), doc_bytes);
+            scorer
+        };
+        let score = move |scorer: &Option<Int8DocScorer>, scratch: &mut Scratch, i| match scorer {
+            Some(scorer) => {
+                let doc = (docs.doc(i), docs.doc_scales(i));
+                scorer.score((q_u8, q_scales), doc, padding(i), tokens, scratch)
+            }
+            None => 0.0,
+        };
+        let doc_len = |i| docs.doc_len(i);
+        let partitioning = self.config.partitioning;
+        let scored: Vec<(DocId, f32)> = score_partitioned(
+            &buckets,
+            doc_len,
+            partitioning,
+            setup,
+            move || scratch.get(),
+            score,
+        )
+        .collect();
+        call.end(Stage::Score);
+
+        let mut scores = vec![0.0f32; docs.len()];
+        for (i, score) in scored {
+            scores[i] = score;
+        }
+        call.end(Stage::Collect);
+        drop(clock);
+        self.stats.record(call);
+        Ok(scores)
+    }
+
+    /// Run `f` on the scorer's pool, or in place (global pool) without one.
+    pub(crate) fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match &self.pool {
+            Some(pool) => pool.install(f),
+            None => f(),
+        }
+    }
+
+    /// `query` as the GEMM sees it: rows normalized under
+    /// `Similarity::Cosine`, untouched otherwise.
+    fn prepare_query<'a>(&self, query: &'a QueryEmbeddings) -> Cow<'a, QueryEmbeddings> {
+        match self.config.similarity {
+            Similarity::Dot => Cow::Borrowed(query),
+            Similarity::Cosine => Cow::Owned(query.clone().normalized()),
+        }
+    }
+
+    fn bucket_scorer(
+        &self,
+        q_len: usize,
+        d_len: usize,
+        dim: usize,
+        operand: Operand,
+    ) -> BucketScorer {
+        let reduction = self.config.reduction();
+        #[cfg(libxsmm)]
+        if self.precision == Precision::Bf16 {
+            let shape = (q_len, d_len, dim, operand);
+            if let Some(scorer) = Bf16DocScorer::new(shape, &self.bf16, reduction) {
+                return BucketScorer::Bf16(scorer);
+            }
+            trace_event!(
+                DEBUG,
+                q_len,
+                d_len,
+                dim,
+                "no bf16 kernel for bucket, scoring in f32"
+            );
+        }
+        #[cfg(not(libxsmm))]
+        let _ = operand;
+        let scorer = DocScorer::tiled(q_len, d_len, dim, self.config.tiling());
+        BucketScorer::F32(scorer.with_reduction(reduction))
+    }
+}
+
+/// A `Scorer` bound to the `DocStore` its configuration was checked
+/// against: queries are padded to the store's dim and scored by the path
+/// of its dtype.
+#[derive(Clone, Debug)]
+pub struct MaxSimScorer<'a> {
+    scorer: Scorer,
+    store: &'a DocStore,
+}
+
+impl<'a> MaxSimScorer<'a> {
+    /// Resolve `config` for scoring `store`. Fails with
+    /// `ScoreError::IncompatibleConfig` when bf16 precision meets a store
+    /// of another dtype, the similarity differs from the one the store was
+    /// prepared for, or pruning is asked of a store that is not f32; with
+    /// `OperandMismatch` when a bf16 store is packed as the other operand;
+    /// and as `Scorer::new` does otherwise. Without `num_threads` the
+    /// scorer owns a pool of `physical_cores()` threads.
+    pub fn from_config(config: ScorerConfig, store: &'a DocStore) -> Result<Self, ScoreError> {
+        let dtype = store.dtype();
+        let incompatible = |reason: String| Err(ScoreError::IncompatibleConfig { reason });
+        if config.precision == Precision::Bf16 && dtype != StoreDtype::Bf16 {
+            return incompatible(format!(
+                "bf16 precision needs a bf16 store, not {:?}",
+                dtype
+            ));
+        }
+        if config.similarity != store.similarity() {
+            return incompatible(format!(
+                "similarity {:?} differs from the store's {:?}",
+                config.similarity,
+                store.similarity()
+            ));
+        }
+        if config.pruning && dtype != StoreDtype::F32 {
+            return incompatible(format!("pruning needs an f32 store, not {:?}", dtype));
+        }
+        if let (Some(scorer), StoreDocs::Bf16(docs)) = (config.operand, store.docs()) {
+            if docs.operand() != scorer {
+                return Err(ScoreError::OperandMismatch {
+                    stored: docs.operand(),
+                    scorer,
+                });
+            }
+        }
+        let config = ScorerConfig {
+            num_threads: config.num_threads.or_else(|| Some(physical_cores())),
+            ..config
+        };
+        Ok(Self {
+            scorer: Scorer::new(config)?,
+            store,
+        })
+    }
+
+    pub fn scorer(&self) -> &Scorer {
+        &self.scorer
+    }
+
+    pub fn store(&self) -> &'a DocStore {
+        self.store
+    }
+
+    /// `Scorer::warmup` for queries of `query_lens` tokens against every
+    /// length bucket of the store, at its dtype, padded dim and bf16
+    /// operand.
+    pub fn warmup(&self, query_lens: &[usize], execute: bool) -> WarmupReport {
+        let shapes = WarmupShapes {
+            dtype: self.store.dtype(),
+            dim: self.store.dim(),
+            query_lens: query_lens.to_vec(),
+            doc_lens: self
+                .store
+                .length_buckets()
+                .into_iter()
+                .map(|(d_len, _)| d_len)
+                .collect(),
+        };
+        let operand = match self.store.docs() {
+            StoreDocs::Bf16(docs) => docs.operand(),
+            _ => self.scorer.config.operand.unwrap_or_default(),
+        };
+        self.scorer.warmup_as(&shapes, operand, execute)
+    }
+
+    /// MaxSim score of `query` against every stored document, in store
+    /// order.
+    pub fn score_batch(&self, query: &QueryEmbeddings) -> Result<Vec<f32>, ScoreError> {
+        let query = self.padded(query)?;
+        self.scorer.score_store(&query, self.store)
+    }
+
+    /// The `k` best stored documents for `query`, best first.
+    pub fn top_k(
+        &self,
+        query: &QueryEmbeddings,
+        k: usize,
+    ) -> Result<Vec<(DocId, f32)>, ScoreError> {
+        let query = self.padded(query)?;
+        if let StoreDocs::F32(docs) = self.store.docs() {
+            let store = self.store;
+            return self.scorer.top_k(&query, &Bucketed { docs, store }, k);
+        }
+        let mut top = TopK::new(k);
+        let scores = self.scorer.score_store(&query, self.store)?;
+        for (i, score) in scores.into_iter().enumerate() {
+            top.push(i, score);
+        }
+        Ok(top.into_sorted_vec())
+    }
+
+    fn padded<'q>(
+        &self,
+        query: &'q QueryEmbeddings,
+    ) -> Result<Cow<'q, QueryEmbeddings>, ScoreError> {
+        match query.dim() == self.store.dim() {
+            true => Ok(Cow::Borrowed(query)),
+            false => self.store.pad_query(query.clone()).map(Cow::Owned),
+        }
+    }
+}
+
+/// The documents of `store` with its bucket padding (`DocStore::padding`)
+/// for the batch paths.
+struct Bucketed<'a, D: ?Sized> {
+    docs: &'a D,
+    store: &'a DocStore,
+}
+
+impl<D: Documents + ?Sized> Documents for Bucketed<'_, D> {
+    fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.docs.dim()
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        self.docs.doc_len(i)
+    }
+
+    fn doc(&self, i: DocId) -> &[f32] {
+        self.docs.doc(i)
+    }
+
+    fn max_token_norm(&self, i: DocId) -> f32 {
+        self.docs.max_token_norm(i)
+    }
+
+    fn id(&self, i: DocId) -> u64 {
+        self.docs.id(i)
+    }
+
+    fn padding(&self, i: DocId) -> usize {
+        self.store.padding(i)
+    }
+}
+
+impl<F: F16Docs + ?Sized> F16Docs for Bucketed<'_, F> {
+    fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.docs.dim()
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        self.docs.doc_len(i)
+    }
+
+    fn doc(&self, i: DocId) -> &[u16] {
+        self.docs.doc(i)
+    }
+
+    fn padding(&self, i: DocId) -> usize {
+        self.store.padding(i)
+    }
+}
+
+impl<B: Bf16Docs + ?Sized> Bf16Docs for Bucketed<'_, B> {
+    fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.docs.dim()
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        self.docs.doc_len(i)
+    }
+
+    fn doc(&self, i: DocId) -> &[u16] {
+        self.docs.doc(i)
+    }
+
+    fn layout(&self) -> VnniLayout {
+        self.docs.layout()
+    }
+
+    fn operand(&self) -> Option<Operand> {
+        self.docs.operand()
+    }
+
+    fn padding(&self, i: DocId) -> usize {
+        self.store.padding(i)
+    }
+}
+
+enum BucketScorer {
+    #[cfg(libxsmm)]
+    Bf16(Bf16DocScorer),
+    F32(DocScorer),
+}
+
+/// bf16 documents the bf16 batch path reads: plain rows, or operands
+/// packed at ingest.
+trait Bf16Docs: Sync {
+    fn len(&self) -> usize;
+    fn dim(&self) -> usize;
+    fn doc_len(&self, i: DocId) -> usize;
+    fn doc(&self, i: DocId) -> &[u16];
+    fn layout(&self) -> VnniLayout;
+    /// Operand the documents were packed as; `None` for plain rows.
+    fn operand(&self) -> Option<Operand>;
+
+    /// As `Documents::padding`.
+    fn padding(&self, _i: DocId) -> usize {
+        0
+    }
+}
+
+impl Bf16Docs for Bf16DocCollection {
+    fn len(&self) -> usize {
+        Bf16DocCollection::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        Bf16DocCollection::dim(self)
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        Bf16DocCollection::doc_len(self, i)
+    }
+
+    fn doc(&self, i: DocId) -> &[u16] {
+        Bf16DocCollection::doc(self, i)
+    }
+
+    fn layout(&self) -> VnniLayout {
+        VnniLayout::Flat
+    }
+
+    fn operand(&self) -> Option<Operand> {
+        None
+    }
+}
+
+impl Bf16Docs for PackedDocStore {
+    fn len(&self) -> usize {
+        PackedDocStore::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        PackedDocStore::dim(self)
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        PackedDocStore::doc_len(self, i)
+    }
+
+    fn doc(&self, i: DocId) -> &[u16] {
+        PackedDocStore::doc(self, i)
+    }
+
+    fn layout(&self) -> VnniLayout {
+        PackedDocStore::layout(self)
+    }
+
+    fn operand(&self) -> Option<Operand> {
+        Some(PackedDocStore::operand(self))
+    }
+}
+
+/// f16 documents as `[doc_len(i), dim]` rows of half bit patterns.
+pub(crate) trait F16Docs: Sync {
+    fn len(&self) -> usize;
+    fn dim(&self) -> usize;
+    fn doc_len(&self, i: DocId) -> usize;
+    fn doc(&self, i: DocId) -> &[u16];
+
+    /// As `Documents::padding`.
+    fn padding(&self, _i: DocId) -> usize {
+        0
+    }
+}
+
+impl F16Docs for MmapF16DocStore {
+    fn len(&self) -> usize {
+        MmapF16DocStore::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        MmapF16DocStore::dim(self)
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        MmapF16DocStore::doc_len(self, i)
+    }
+
+    fn doc(&self, i: DocId) -> &[u16] {
+        MmapF16DocStore::doc(self, i)
+    }
+}
+
+impl F16Docs for F16DocCollection {
+    fn len(&self) -> usize {
+        F16DocCollection::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        F16DocCollection::dim(self)
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        F16DocCollection::doc_len(self, i)
+    }
+
+    fn doc(&self, i: DocId) -> &[u16] {
+        F16DocCollection::doc(self, i)
+    }
+}
+
+/// Kernels in libxsmm's code registry; 0 without libxsmm.
+fn registry_size() -> usize {
+    #[cfg(libxsmm)]
+    return crate::libxsmm_bindings::registry_info().map_or(0, |info| info.size);
+    #[cfg(not(libxsmm))]
+    0
+}
+
+/// `[q_len, dim]` f32 query as the bf16 operand opposite the documents:
+/// `[q_len, k_pad]` row-major rows (B, column-major `k_pad × q_len`)
+/// zero-padded to even k, or those rows VNNI2-packed when it is A.
+#[cfg(libxsmm)]
+fn query_to_bf16(query: &[f32], q_len: usize, dim: usize, docs: Operand) -> Vec<u16> {
+    let k_pad = vnni2_k(dim);
+    let mut out = vec![0u16; q_len * k_pad];
+    if dim > 0 {
+        for (dst, src) in out.chunks_exact_mut(k_pad).zip(query.chunks_exact(dim)) {
+            convert_f32_to_bf16(src, &mut dst[..dim]);
+        }
+    }
+    match docs {
+        Operand::A => out,
+        Operand::B => {
+            let mut packed = AlignedVec::new();
+            pack_bf16_vnni2_a_rows(&out, q_len, k_pad, &mut packed);
+            packed.to_vec()
+        }
+    }
+}
+
+/// bf16 MaxSim for documents of one fixed length.
+///
+/// With documents as `Operand::A`, C = A·B with A the VNNI2-packed
+/// `d_len × k_pad` document and B the bf16 query, so C is the
+/// `[q_len, d_len]` row-major similarity matrix as on the f32 path. As
+/// `Operand::B` the query is A and the document rows are B, and the
+/// `[d_len, q_len]` result is reduced as it stands.
+#[cfg(libxsmm)]
+struct Bf16DocScorer {
+    kernel: Arc<JitKernel>,
+    q_len: usize,
+    d_len: usize,
+    dim: usize,
+    operand: Operand,
+    reduction: Reduction,
+}
+
+#[cfg(libxsmm)]
+impl Bf16DocScorer {
+    /// Kernel for `(q_len, d_len, dim, operand)` shaped buckets; `None`
+    /// when libxsmm cannot JIT the shape.
+    fn new(
+        (q_len, d_len, dim, operand): (usize, usize, usize, Operand),
+        config: &Bf16KernelConfig,
+        reduction: Reduction,
+    ) -> Option<Self> {
+        debug_assert_eq!(config.layout, VnniLayout::Vnni2);
+        let (bf16, f32) = (LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32);
+        let k = config.padded_k(dim as i32);
+        let (m, n) = match operand {
+            Operand::A => (d_len, q_len),
+            Operand::B => (q_len, d_len),
+        };
+        let mut spec = GemmSpec::packed(m as i32, n as i32, k, Transpose::None, bf16, f32);
+        spec.flags |= config.flags;
+        let kernel = get_kernel(spec).ok()?;
+        Some(Self {
+            kernel,
+            q_len,
+            d_len,
+            dim,
+            operand,
+            reduction,
+        })
+    }
+
+    /// Score of one bf16 document of rows ending in `padding` rows of
+    /// bucket padding.
+    fn score(
+        &self,
+        query: &[u16],
+        doc: &[u16],
+        padding: usize,
+        tokens: TokenWeights,
+        scratch: &mut Scratch,
+    ) -> f32 {
+        timed(Phase::Convert, || match self.operand {
+            Operand::A => pack_bf16_vnni2_a_rows(doc, self.d_len, self.dim, &mut scratch.packed),
+            Operand::B => pad_bf16_vnni2_b_rows(doc, self.d_len, self.dim, &mut scratch.packed),
+        });
+        self.score_packed(query, &scratch.packed, padding, tokens, &mut scratch.sims)
+    }
+
+    /// `score` for a document already packed as its operand.
+    fn score_packed(
+        &self,
+        query: &[u16],
+        packed: &[u16],
+        padding: usize,
+        tokens: TokenWeights,
+        sims: &mut AlignedVec<f32>,
+    ) -> f32 {
+        let tile = self.q_len * self.d_len;
+        let doc_part = self.reduction.doc_maxes_len(self.d_len);
+        let (a, b) = match self.operand {
+            Operand::A => (packed, query),
+            Operand::B => (query, packed),
+        };
+        sims.resize(tile + self.q_len + doc_part, 0.0);
+        let (sims, maxes) = sims.split_at_mut(tile);
+        timed(Phase::Gemm, || self.kernel.call_bf16(a, b, sims))
+            .expect("bf16 similarity operands sized from the kernel shape");
+        match self.operand {
+            Operand::A => self
+                .reduction
+                .score_matrix(sims, self.d_len, padding, tokens, maxes),
+            Operand::B => self
+                .reduction
+                .score_matrix_t(sims, self.q_len, padding, tokens, maxes),
+        }
+    }
+}
+
+/// bf16 MaxSim on AMX tiles for documents of one fixed length, scored as
+/// `Operand::A`.
+///
+/// Documents are read in place: VNNI2-packed as A when they were added,
+/// in whole tiles of `ArchFamily::Amx.token_multiple()` rows, as
+/// `DocStoreBuilder` stores them on AMX CPUs. The kernel leaves tile setup
+/// to its caller, which must hold a guard from `tiles`.
+#[cfg(libxsmm)]
+struct AmxScorer {
+    kernel: Arc<JitKernel>,
+    tiles: TileConfig,
+    q_len: usize,
+    /// A multiple of `ArchFamily::Amx.token_multiple()`.
+    d_len: usize,
+    dim: usize,
+    reduction: Reduction,
+}
+
+/// A bucket's documents with the scorer for their length.
+#[cfg(libxsmm)]
+type AmxBucket<'b> = (AmxScorer, &'b [DocId]);
+
+#[cfg(libxsmm)]
+impl AmxScorer {
+    /// Tile-scoped kernel and tile configuration for `(q_len, d_len, dim)`
+    /// shaped buckets; `None` when libxsmm cannot JIT the shape.
+    fn new(
+        (q_len, d_len, dim): (usize, usize, usize),
+        config: &Bf16KernelConfig,
+        reduction: Reduction,
+    ) -> Option<Self> {
+        debug_assert!(d_len.is_multiple_of(ArchFamily::Amx.token_multiple()));
+        let (bf16, f32) = (LIBXSMM_DATATYPE_BF16, LIBXSMM_DATATYPE_F32);
+        let k = config.padded_k(dim as i32);
+        let m = d_len as i32;
+        let mut spec = GemmSpec::packed(m, q_len as i32, k, Transpose::None, bf16, f32);
+        spec.flags |= config.flags;
+        let spec = spec.with_tile_scope();
+        let tiles = TileConfig::for_spec(&spec).ok()?;
+        let kernel = get_kernel(spec).ok()?;
+        Some(Self {
+            kernel,
+            tiles,
+            q_len,
+            d_len,
+            dim,
+            reduction,
+        })
+    }
+
+    fn shape(&self) -> KernelShape {
+        KernelShape {
+            kernel: KernelKind::Bf16,
+            q_len: self.q_len,
+            d_len: self.d_len,
+            dim: self.dim,
+        }
+    }
+
+    /// Score one document, VNNI2-packed as A, whose last `padding` rows
+    /// are bucket padding. `sims` holds the similarity tile and maxima.
+    fn score(
+        &self,
+        query: &[u16],
+        (doc, padding): (&[u16], usize),
+        tokens: TokenWeights,
+        sims: &mut AlignedVec<f32>,
+    ) -> f32 {
+        let (q_len, d_len) = (self.q_len, self.d_len);
+        let doc_part = self.reduction.doc_maxes_len(d_len);
+        sims.resize(q_len * d_len + q_len + doc_part, 0.0);
+        let (sims, maxes) = sims.split_at_mut(q_len * d_len);
+        timed(Phase::Gemm, || {
+            self.kernel
+                .call_bf16(doc, query, sims)
+                .expect("bf16 similarity operands sized from the kernel shape");
+        });
+        self.reduction
+            .score_matrix(sims, d_len, padding, tokens, maxes)
+    }
+}
+
+/// int8 MaxSim for documents of one fixed length.
+///
+/// The int8 tile is the `[q_len, d_len]` row-major similarity matrix as on
+/// the f32 path: A is the row-major doc read transposed, B the u8 query.
+struct Int8DocScorer {
+    /// `None` when libxsmm cannot JIT the shape or the CPU lacks VNNI.
+    #[cfg(libxsmm)]
+    kernel: Option<Int8Kernel>,
+    q_len: usize,
+    d_len: usize,
+    dim: usize,
+    reduction: Reduction,
+}
+
+impl Int8DocScorer {
+    fn new(q_len: usize, d_len: usize, dim: usize, reduction: Reduction) -> Self {
+        #[cfg(libxsmm)]
+        let kernel = {
+            let signedness = Int8Signedness::SignedUnsigned;
+            let (m, n, k) = (d_len as i32, q_len as i32, dim as i32);
+            Int8Kernel::gemm(m, n, k, Transpose::A, signedness).ok()
+        };
+        Self {
+            #[cfg(libxsmm)]
+            kernel,
+            q_len,
+            d_len,
+            dim,
+            reduction,
+        }
+    }
+
+    /// `query` is (u8 rows, per-token scales); `doc` is (i8 rows, scales),
+    /// with one scale per token or a single per-document one, and ends in
+    /// `padding` rows of bucket padding.
+    fn score(
+        &self,
+        query: (&[u8], &[f32]),
+        doc: (&[i8], &[f32]),
+        padding: usize,
+        tokens: TokenWeights,
+        scratch: &mut Scratch,
+    ) -> f32 {
+        let (q_u8, q_scales) = query;
+        let (d_i8, d_scales) = doc;
+        let (d_len, dim) = (self.d_len, self.dim);
+
+        scratch.acc.resize(self.q_len * d_len, 0);
+        timed(Phase::Gemm, || {
+            #[cfg(libxsmm)]
+            if let Some(kernel) = &self.kernel {
+                kernel
+                    .call_slices(d_i8, q_u8, &mut scratch.acc)
+                    .expect("int8 similarity operands sized from the kernel shape");
+            } else {
+                dot_u8_i8(q_u8, d_i8, dim, d_len, &mut scratch.acc);
+            }
+            #[cfg(not(libxsmm))]
+            dot_u8_i8(q_u8, d_i8, dim, d_len, &mut scratch.acc);
+        });
+
+        let doc_part = self.reduction.doc_maxes_len(d_len);
+        scratch
+            .sims
+            .resize(self.q_len * (d_len + 1) + doc_part, 0.0);
+        let (all_sims, maxes) = scratch.sims.split_at_mut(self.q_len * d_len);
+        let (acc, sums) = (&scratch.acc, &mut scratch.sums);
+        timed(Phase::Convert, || {
+            sums.clear();
+            sums.extend(
+                d_i8.chunks_exact(dim)
+                    .map(|row| U8_ZERO_POINT * row.iter().map(|&v| v as i32).sum::<i32>()),
+            );
+            let per_doc = d_scales.len() != d_len;
+            let rows = all_sims
+                .chunks_exact_mut(d_len)
+                .zip(acc.chunks_exact(d_len))
+                .zip(q_scales);
+            for ((sims, acc), &q_scale) in rows {
+                for (di, (sim, (&a, &zp))) in
+                    sims.iter_mut().zip(acc.iter().zip(sums.iter())).enumerate()
+                {
+                    let d_scale = if per_doc { d_scales[0] } else { d_scales[di] };
+                    // Query scales are non-negative, so scaling before the max
+                    // gives the same maxima as scaling them afterwards
+                    *sim = (a - zp) as f32 * d_scale * q_scale;
+                }
+            }
+        });
+        self.reduction
+            .score_matrix(all_sims, d_len, padding, tokens, maxes)
+    }
+}
+
+/// `acc[qi * d_len + di] = Σ query[qi] · doc[di]` with u8 query rows and i8
+/// doc rows, matching the VNNI kernel's output.
+fn dot_u8_i8(query: &[u8], doc: &[i8], dim: usize, d_len: usize, acc: &mut [i32]) {
+    for (q_row, acc_row) in query.chunks_exact(dim).zip(acc.chunks_exact_mut(d_len)) {
+        for (a, d_row) in acc_row.iter_mut().zip(doc.chunks_exact(dim)) {
+            *a = q_row
+                .iter()
+                .zip(d_row)
+                .map(|(&q, &d)| q as i32 * d as i32)
+                .sum();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{unit_docs, unit_rows};
+    use crate::docstore::DocStoreBuilder;
+
+    const DIM: usize = 32;
+
+    /// Lengths spread over several buckets, with some repeated.
+    fn lengths(n: usize) -> Vec<usize> {
+        (0..n).map(|i| 1 + (i * 37) % 90).collect()
+    }
+
+    fn query(len: usize, seed: u64) -> QueryEmbeddings {
+        QueryEmbeddings::new(unit_rows(len, DIM, seed), len, DIM).unwrap()
+    }
+
+    #[test]
+    fn concurrent_calls_match_single_threaded() {
+        const THREADS: usize = 16;
+        let docs = unit_docs(&lengths(24), DIM, 1);
+        let scorer = Scorer::new(ScorerConfig::default().with_num_threads(4)).unwrap();
+        let queries: Vec<QueryEmbeddings> =
+            (0..THREADS).map(|t| query(2 + t, 10 + t as u64)).collect();
+        let expected: Vec<(Vec<f32>, SearchResults)> = queries
+            .iter()
+            .enumerate()
+            .map(|(t, q)| {
+                let scores = scorer.score_batch(q, &docs).unwrap();
+                (scores, scorer.search(t as u64, q, &docs, 10).unwrap())
+            })
+            .collect();
+
+        std::thread::scope(|s| {
+            for (t, (q, (scores, results))) in queries.iter().zip(&expected).enumerate() {
+                let (scorer, docs) = (&scorer, &docs);
+                s.spawn(move || {
+                    for _ in 0..2 {
+                        assert_eq!(&scorer.score_batch(q, docs).unwrap(), scores);
+                        assert_eq!(&scorer.search(t as u64, q, docs, 10).unwrap(), results);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn log_sum_exp_needs_a_positive_temperature() {
+        let mut builder = DocStoreBuilder::new(DIM);
+        builder.push(&unit_rows(3, DIM, 2)).unwrap();
+        let store = builder.finish().unwrap();
+        for temperature in [0.0, -0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let config = ScorerConfig::default()
+                .with_num_threads(1)
+                .with_aggregation(Aggregation::LogSumExp { temperature });
+            assert!(
+                matches!(
+                    Scorer::new(config),
+                    Err(ScoreError::IncompatibleConfig { .. })
+                ),
+                "temperature {temperature}"
+            );
+            assert!(matches!(
+                MaxSimScorer::from_config(config, &store),
+                Err(ScoreError::IncompatibleConfig { .. })
+            ));
+        }
+        let config = ScorerConfig::default()
+            .with_num_threads(1)
+            .with_aggregation(Aggregation::LogSumExp { temperature: 0.05 });
+        assert!(Scorer::new(config).is_ok());
+        assert!(MaxSimScorer::from_config(config, &store).is_ok());
+    }
+
+    /// A config per variant of every option enum, plus every option set.
+    #[cfg(feature = "serde")]
+    fn every_variant() -> Vec<ScorerConfig> {
+        let base = ScorerConfig::default();
+        let mut configs = vec![
+            base,
+            base.with_precision(Precision::Bf16),
+            base.with_fallback(Fallback::Error),
+            base.with_similarity(Similarity::Cosine),
+            base.with_direction(Direction::DocToQuery),
+            base.with_direction(Direction::Symmetric),
+            base.with_partitioning(Partitioning::TokenBalanced),
+            base.with_loop_order(LoopOrder::QueryMajor),
+            base.with_loop_order(LoopOrder::DocMajor),
+        ];
+        for aggregation in [
+            Aggregation::Sum,
+            Aggregation::Mean,
+            Aggregation::Max,
+            Aggregation::LogSumExp { temperature: 0.05 },
+        ] {
+            configs.push(base.with_aggregation(aggregation));
+        }
+        for operand in [Operand::A, Operand::B] {
+            configs.push(ScorerConfig {
+                operand: Some(operand),
+                ..base
+            });
+        }
+        configs.push(ScorerConfig {
+            num_threads: Some(7),
+            thread_names: true,
+            disable_amx: true,
+            pruning: true,
+            fused_block: Some(48),
+            dim_block: Some(128),
+            max_scratch_bytes: Some(1 << 40),
+            timing: true,
+            ..base
+        });
+        configs
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn configs_round_trip_through_json() {
+        for config in every_variant() {
+            let json = serde_json::to_string(&config).unwrap();
+            let back: ScorerConfig = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, config, "{json}");
+        }
+        let lse =
+            ScorerConfig::default().with_aggregation(Aggregation::LogSumExp { temperature: 0.5 });
+        let json = serde_json::to_string(&lse).unwrap();
+        assert!(
+            json.contains(r#""aggregation":{"LogSumExp":{"temperature":0.5}}"#),
+            "{json}"
+        );
+        assert!(json.contains(r#""precision":"F32""#), "{json}");
+
+        // Missing fields take their defaults
+        assert_eq!(
+            serde_json::from_str::<ScorerConfig>("{}").unwrap(),
+            ScorerConfig::default()
+        );
+        let partial: ScorerConfig =
+            serde_json::from_str(r#"{"pruning": true, "num_threads": 3}"#).unwrap();
+        assert_eq!(
+            partial,
+            ScorerConfig {
+                pruning: true,
+                num_threads: Some(3),
+                ..ScorerConfig::default()
+            }
+        );
+        assert!(serde_json::from_str::<ScorerConfig>(r#"{"precision":"F64"}"#).is_err());
+        assert!(
+            serde_json::from_str::<ScorerConfig>(r#"{"aggregation":{"Mean":{"x":1}}}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn from_config_rejects_what_the_store_cannot_serve() {
+        let store = |dtype: StoreDtype, similarity: Similarity| {
+            let mut builder = DocStoreBuilder::new(DIM)
+                .with_storage(dtype)
+                .with_similarity(similarity)
+                .with_operand(Operand::A);
+            builder.push(&unit_rows(3, DIM, 2)).unwrap();
+            builder.finish().unwrap()
+        };
+        let config = ScorerConfig::default().with_num_threads(1);
+        let incompatible = |config: ScorerConfig, store: &DocStore| {
+            matches!(
+                MaxSimScorer::from_config(config, store),
+                Err(ScoreError::IncompatibleConfig { .. })
+            )
+        };
+        for dtype in [StoreDtype::F32, StoreDtype::F16, StoreDtype::Int8] {
+            let store = store(dtype, Similarity::Dot);
+            assert!(incompatible(config.with_precision(Precision::Bf16), &store));
+        }
+        let bf16 = store(StoreDtype::Bf16, Similarity::Dot);
+        assert!(MaxSimScorer::from_config(config.with_precision(Precision::Bf16), &bf16).is_ok());
+        assert!(matches!(
+            MaxSimScorer::from_config(
+                ScorerConfig {
+                    operand: Some(Operand::B),
+                    ..config
+                },
+                &bf16
+            ),
+            Err(ScoreError::OperandMismatch {
+                stored: Operand::A,
+                scorer: Operand::B
+            })
+        ));
+
+        let cosine = store(StoreDtype::F32, Similarity::Cosine);
+        assert!(incompatible(config, &cosine));
+        assert!(
+            MaxSimScorer::from_config(config.with_similarity(Similarity::Cosine), &cosine).is_ok()
+        );
+
+        let pruning = ScorerConfig {
+            pruning: true,
+            ..config
+        };
+        assert!(
+            MaxSimScorer::from_config(pruning, &store(StoreDtype::F32, Similarity::Dot)).is_ok()
+        );
+        for dtype in [StoreDtype::F16, StoreDtype::Bf16, StoreDtype::Int8] {
+            assert!(incompatible(pruning, &store(dtype, Similarity::Dot)));
+        }
+    }
+
+    #[test]
+    fn autotune_keeps_to_its_budget_and_up_with_the_default() {
+        // Long enough for the fused path the tile applies to
+        let docs = unit_docs(&[600, 700], DIM, 81);
+        let config = ScorerConfig::default().with_num_threads(1);
+        let query = query(AUTOTUNE_Q_LEN, 82);
+        let fastest_of = |scorer: &Scorer| {
+            (0..5)
+                .map(|_| {
+                    let start = Instant::now();
+                    scorer.score_batch(&query, &docs).unwrap();
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+        let default = Scorer::new(config).unwrap();
+        let pass = fastest_of(&default);
+
+        // Three passes per candidate: charging only the timed ones after
+        // the first would take five
+        let budget = pass * 3 * AUTOTUNE_BLOCKS.len() as u32;
+        let mut tuned = Scorer::new(config).unwrap();
+        let start = Instant::now();
+        let block = tuned.autotune(&docs, budget).unwrap();
+        let elapsed = start.elapsed();
+        assert!(AUTOTUNE_BLOCKS.contains(&block));
+        assert!(elapsed < budget * 3 / 2, "{elapsed:?} of {budget:?}");
+
+        let (tuned, default) = (fastest_of(&tuned), fastest_of(&default));
+        assert!(
+            tuned < default * 3 / 2,
+            "tuned {tuned:?}, default {default:?}"
+        );
+    }
+
+    /// A bf16 store of `lens` documents packed as A, when this CPU's bf16
+    /// kernels take AMX tiles.
+    #[cfg(libxsmm)]
+    fn amx_store(lens: &[usize]) -> Option<DocStore> {
+        if ArchFamily::detect() != ArchFamily::Amx {
+            return None;
+        }
+        let mut builder = DocStoreBuilder::new(DIM)
+            .with_storage(StoreDtype::Bf16)
+            .with_operand(Operand::A);
+        for (i, &len) in lens.iter().enumerate() {
+            builder.push(&unit_rows(len, DIM, 40 + i as u64)).unwrap();
+        }
+        Some(builder.finish().unwrap())
+    }
+
+    #[cfg(libxsmm)]
+    #[test]
+    fn amx_scores_match_the_avx512_bf16_path() {
+        let Some(store) = amx_store(&lengths(40)) else {
+            return;
+        };
+        let query = query(7, 41);
+        for direction in [
+            Direction::QueryToDoc,
+            Direction::DocToQuery,
+            Direction::Symmetric,
+        ] {
+            let config = ScorerConfig::default()
+                .with_num_threads(2)
+                .with_precision(Precision::Bf16)
+                .with_direction(direction);
+            let scores = |config| {
+                let scorer = MaxSimScorer::from_config(config, &store).unwrap();
+                scorer.score_batch(&query).unwrap()
+            };
+            let amx = scores(config);
+            let avx512 = scores(config.with_disable_amx(true));
+            for (i, (a, b)) in amx.iter().zip(&avx512).enumerate() {
+                // Same bf16 products, summed in another order.
+                assert!(
+                    (a - b).abs() <= 1e-4 * b.abs().max(1.0),
+                    "{direction:?} doc {i}: {a} vs {b}"
+                );
+            }
+        }
+    }
+
+    #[cfg(libxsmm)]
+    #[test]
+    fn disable_amx_keeps_every_bucket_off_amx() {
+        let Some(store) = amx_store(&lengths(40)) else {
+            return;
+        };
+        let StoreDocs::Bf16(docs) = store.docs() else {
+            unreachable!("bf16 store");
+        };
+        let order = length_order(docs.len(), |i| docs.doc_len(i));
+        let buckets = || length_buckets(&order, |i| docs.doc_len(i));
+        let n = buckets().len();
+        let shape = (7, DIM, Operand::A);
+        for disable in [false, true] {
+            let config = ScorerConfig::default()
+                .with_precision(Precision::Bf16)
+                .with_disable_amx(disable);
+            let scorer = Scorer::new(config).unwrap();
+            let (amx, rest) = scorer.amx_buckets(buckets(), shape, true, |i| docs.doc_len(i));
+            assert_eq!(amx.len() + rest.len(), n);
+            assert_eq!(amx.is_empty(), disable, "disable_amx {disable}");
+            // Unpacked documents would need packing per call.
+            let (amx, _) = scorer.amx_buckets(buckets(), shape, false, |i| docs.doc_len(i));
+            assert!(amx.is_empty());
+        }
+    }
+
+    #[cfg(libxsmm)]
+    #[test]
+    fn warmup_leaves_no_kernel_for_scoring_to_build() {
+        use crate::kernel_cache::KernelCache;
+
+        // A dim no other test uses, so kernels built concurrently by other
+        // tests are not counted
+        const WARM_DIM: usize = 52;
+        let cached = || {
+            let specs = KernelCache::global().specs();
+            specs
+                .iter()
+                .filter(|spec| spec.k == WARM_DIM as i32)
+                .count()
+        };
+        let lens: Vec<usize> = (0..40).map(|i| 1 + (i * 37) % 300).collect();
+        let query_lens = [5, 40];
+        // Stacking on for the shortest f32 documents too
+        for (dtype, precision, stack) in [
+            (StoreDtype::F32, Precision::F32, 0),
+            (StoreDtype::F32, Precision::F32, 24),
+            (StoreDtype::F16, Precision::F32, 0),
+            (StoreDtype::Bf16, Precision::Bf16, 0),
+            (StoreDtype::Bf16, Precision::F32, 0),
+            (StoreDtype::Int8, Precision::F32, 0),
+        ] {
+            let mut builder = DocStoreBuilder::new(WARM_DIM).with_storage(dtype);
+            for (i, &len) in lens.iter().enumerate() {
+                builder
+                    .push(&unit_rows(len, WARM_DIM, 60 + i as u64))
+                    .unwrap();
+            }
+            let store = builder.finish().unwrap();
+            let config = ScorerConfig::default()
+                .with_num_threads(2)
+                .with_precision(precision)
+                .with_stack_threshold(stack);
+            let scorer = MaxSimScorer::from_config(config, &store).unwrap();
+            scorer.warmup(&query_lens, false);
+            let warm = cached();
+            for q_len in query_lens {
+                let query = QueryEmbeddings::new(unit_rows(q_len, WARM_DIM, 70), q_len, WARM_DIM);
+                let query = query.unwrap();
+                scorer.score_batch(&query).unwrap();
+                scorer.top_k(&query, 5).unwrap();
+            }
+            assert_eq!(cached(), warm, "{dtype:?} in {precision:?}, stack {stack}");
+        }
+    }
+}
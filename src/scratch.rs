@@ -0,0 +1,329 @@
+//! Per-thread scoring scratch a scorer keeps across calls.
+//!
+//! Each worker of the scorer's pool owns one `Scratch`, found by its rayon
+//! thread index, so parallel scoring never takes buffers from the
+//! allocator once they have grown to the shapes it sees. Buffers only
+//! grow; a `max_bytes` cap releases a worker's scratch when a use leaves
+//! it larger. A thread outside the pool, or one already holding its slot
+//! further up its stack, gets fresh buffers for that use. A call borrows
+//! the pool as a `CallScratch`, whose guards also time the call's stages
+//! on their thread when it collects a `Timing`.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+
+use crate::aligned::AlignedVec;
+use crate::stats::{Clock, TimingSink};
+
+/// One worker's buffers, all `KERNEL_ALIGN`-aligned.
+#[derive(Default)]
+pub(crate) struct Scratch {
+    /// bf16 document packed as its operand.
+    #[cfg(libxsmm)]
+    pub(crate) packed: AlignedVec<u16>,
+    /// Pre-packed document unpacked back to bf16 rows.
+    pub(crate) rows: AlignedVec<u16>,
+    /// Document (or on the decoded paths, one tile) widened to f32.
+    pub(crate) doc: AlignedVec<f32>,
+    /// Similarity matrix (or fused tile), after a `DocScorer`'s maxima.
+    pub(crate) sims: AlignedVec<f32>,
+    /// Raw int8 similarity tile.
+    pub(crate) acc: AlignedVec<i32>,
+    /// Per-token sums of the int8 document, for the zero-point correction.
+    pub(crate) sums: AlignedVec<i32>,
+    /// Rows of a stacked group of documents.
+    pub(crate) stack: AlignedVec<f32>,
+    /// Per-token maxima of a stacked document, or of each document of a
+    /// query-major share.
+    pub(crate) maxes: AlignedVec<f32>,
+}
+
+impl Scratch {
+    /// Bytes allocated across the buffers.
+    fn bytes(&self) -> usize {
+        fn held<T: Copy>(v: &AlignedVec<T>) -> usize {
+            v.capacity() * size_of::<T>()
+        }
+        #[cfg(libxsmm)]
+        let packed = held(&self.packed);
+        #[cfg(not(libxsmm))]
+        let packed = 0;
+        packed
+            + held(&self.rows)
+            + held(&self.doc)
+            + held(&self.sims)
+            + held(&self.acc)
+            + held(&self.sums)
+            + held(&self.stack)
+            + held(&self.maxes)
+    }
+}
+
+/// A `Scratch` per worker thread.
+pub(crate) struct ScratchPool {
+    slots: Box<[Mutex<Scratch>]>,
+    max_bytes: Option<usize>,
+}
+
+impl ScratchPool {
+    /// Slots for `threads` workers; scratch over `max_bytes` is released
+    /// after use.
+    pub(crate) fn new(threads: usize, max_bytes: Option<usize>) -> Self {
+        Self {
+            slots: (0..threads).map(|_| Mutex::default()).collect(),
+            max_bytes,
+        }
+    }
+
+    /// The calling worker's scratch until the guard drops.
+    pub(crate) fn get(&self) -> ScratchGuard<'_> {
+        let slot = rayon::current_thread_index().and_then(|i| self.slots.get(i));
+        let held = match slot.map(Mutex::try_lock) {
+            Some(Ok(slot)) => Held::Slot(slot),
+            // A panic mid-score leaves the buffers sized, if not filled
+            Some(Err(TryLockError::Poisoned(slot))) => Held::Slot(slot.into_inner()),
+            Some(Err(TryLockError::WouldBlock)) | None => Held::Spare(Box::default()),
+        };
+        ScratchGuard {
+            held,
+            max_bytes: self.max_bytes,
+            clock: None,
+        }
+    }
+
+    /// The pool as one call borrows it, timing into `timing` if given.
+    pub(crate) fn call<'p>(&'p self, timing: Option<&'p TimingSink>) -> CallScratch<'p> {
+        CallScratch { pool: self, timing }
+    }
+}
+
+/// A `ScratchPool` borrowed by one call.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CallScratch<'p> {
+    pool: &'p ScratchPool,
+    timing: Option<&'p TimingSink>,
+}
+
+impl<'p> CallScratch<'p> {
+    /// `ScratchPool::get`, timing the stages this thread runs while the
+    /// guard lives when the call collects times.
+    pub(crate) fn get(&self) -> ScratchGuard<'p> {
+        let mut guard = self.pool.get();
+        guard.clock = self.timing.map(Clock::start);
+        guard
+    }
+}
+
+impl Default for ScratchPool {
+    /// Slots for the current pool, uncapped: scratch for one call.
+    fn default() -> Self {
+        Self::new(rayon::current_num_threads(), None)
+    }
+}
+
+impl fmt::Debug for ScratchPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScratchPool")
+            .field("slots", &self.slots.len())
+            .field("max_bytes", &self.max_bytes)
+            .finish()
+    }
+}
+
+/// A worker's `Scratch`, borrowed from a `ScratchPool`.
+pub(crate) struct ScratchGuard<'p> {
+    held: Held<'p>,
+    max_bytes: Option<usize>,
+    /// Times this thread's stages until the guard drops.
+    clock: Option<Clock<'p>>,
+}
+
+enum Held<'p> {
+    Slot(MutexGuard<'p, Scratch>),
+    Spare(Box<Scratch>),
+}
+
+impl Deref for ScratchGuard<'_> {
+    type Target = Scratch;
+
+    fn deref(&self) -> &Scratch {
+        match &self.held {
+            Held::Slot(slot) => slot,
+            Held::Spare(scratch) => scratch,
+        }
+    }
+}
+
+impl DerefMut for ScratchGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Scratch {
+        match &mut self.held {
+            Held::Slot(slot) => slot,
+            Held::Spare(scratch) => scratch,
+        }
+    }
+}
+
+impl Drop for ScratchGuard<'_> {
+    fn drop(&mut self) {
+        if let Held::Slot(slot) = &mut self.held {
+            if self.max_bytes.is_some_and(|max| slot.bytes() > max) {
+                **slot = Scratch::default();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::bench::unit_rows;
+    use crate::bf16::{convert_bf16_to_f32, convert_f32_to_bf16};
+    use crate::collection::TokenWeights;
+    use crate::score::{BlockedScorer, DocScorer, Reduction, StackedScorer, Tiling};
+
+    /// The system allocator, counting each thread's allocations.
+    struct Counting;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count() {
+        // Threads being torn down have no counter left; they are not ours
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+    }
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count();
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count();
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count();
+            System.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+
+    #[test]
+    fn warm_scratch_scores_without_allocating() {
+        // More query tokens than a QUERY_BLOCK, and a document past the
+        // fused threshold
+        let (q_len, dim, short, d_len, long) = (40, 32, 8, 20, 600);
+        let query = unit_rows(q_len, dim, 1);
+        let shorts = unit_rows(4 * short, dim, 2);
+        let doc = unit_rows(d_len, dim, 3);
+        let long_doc = unit_rows(long, dim, 4);
+        let mut bf16 = vec![0u16; doc.len()];
+        convert_f32_to_bf16(&doc, &mut bf16);
+        let tokens = TokenWeights {
+            mask: None,
+            weights: None,
+        };
+        let tiling = Tiling::default();
+        let plain = DocScorer::new(q_len, d_len, dim);
+        let fused = DocScorer::new(q_len, long, dim);
+        let stacked = StackedScorer::new(q_len, short, dim, tiling.dims(dim), Reduction::default());
+        let blocked = BlockedScorer::new(q_len, d_len, dim, tiling, Reduction::default());
+
+        let scratch = ScratchPool::new(1, None);
+        let run = || {
+            let mut guard = scratch.get();
+            let scratch = &mut *guard;
+            let mut out = [0.0; 4];
+            let mut total = plain.score(&query, &doc, tokens, &mut scratch.sims);
+            total += fused.score(&query, &long_doc, tokens, &mut scratch.sims);
+            stacked.score(
+                &query,
+                shorts.chunks_exact(short * dim).map(|doc| (doc, 0)),
+                tokens,
+                scratch,
+                &mut out,
+            );
+            total += blocked.score_doc_major(&query, &doc, 0, tokens, scratch);
+            let docs = [(&doc[..], 0), (&doc[..], 0)];
+            blocked.score_query_major(&query, docs.into_iter(), tokens, scratch, &mut out[..2]);
+            scratch.doc.resize(doc.len(), 0.0);
+            convert_bf16_to_f32(&bf16, &mut scratch.doc);
+            total += plain.score(&query, &scratch.doc, tokens, &mut scratch.sims);
+            total + out.iter().sum::<f32>()
+        };
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        threads.install(|| {
+            let start = allocations();
+            let first = run();
+            let before = allocations();
+            // Growing the fresh scratch counts, so the counter is live
+            assert!(before > start);
+            for _ in 0..3 {
+                assert_eq!(run().to_bits(), first.to_bits());
+            }
+            assert_eq!(allocations(), before);
+        });
+    }
+
+    #[test]
+    fn buffers_are_kept_unless_over_the_cap() {
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        threads.install(|| {
+            let kept = ScratchPool::new(2, Some(4096));
+            kept.get().sims.resize(512, 0.0);
+            let ptr = kept.get().sims.as_ptr();
+            assert!(kept.get().sims.capacity() >= 512);
+            kept.get().sims.resize(256, 0.0);
+            assert_eq!(kept.get().sims.as_ptr(), ptr);
+
+            let capped = ScratchPool::new(2, Some(1024));
+            capped.get().sims.resize(512, 0.0);
+            assert_eq!(capped.get().bytes(), 0);
+        });
+    }
+
+    #[test]
+    fn nested_and_outside_uses_get_spare_buffers() {
+        let pool = ScratchPool::new(1, None);
+        // Not a worker of any pool
+        pool.get().sims.resize(64, 0.0);
+        assert_eq!(pool.get().bytes(), 0);
+
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        threads.install(|| {
+            let mut outer = pool.get();
+            outer.sims.resize(64, 0.0);
+            let inner = pool.get();
+            assert_eq!(inner.bytes(), 0);
+            drop(inner);
+            drop(outer);
+            assert!(pool.get().sims.capacity() >= 64);
+        });
+    }
+}
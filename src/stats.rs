@@ -0,0 +1,553 @@
+//! Runtime statistics of `Scorer` calls.
+//!
+//! Every scoring call records the GEMMs it ran, the shapes it ran them at,
+//! the documents it scored or pruned, the embedding bytes it read and its
+//! wall time per stage. `Scorer::last_stats` returns the latest call's,
+//! `Scorer::stats` the totals since construction or `Scorer::reset_stats`,
+//! kept in atomics so concurrent calls add up without a lock. Work is
+//! counted once per length bucket rather than per document, so collection
+//! stays on.
+//!
+//! Approximate under parallelism or by construction:
+//! - Stage times are wall clock on the calling thread. The totals of calls
+//!   made concurrently overlap, so their `wall_time` exceeds the elapsed
+//!   time and their `gflops` understates the throughput.
+//! - `last_stats` is whichever concurrent call finished last.
+//! - `reset_stats` clears the counters one at a time; a call finishing
+//!   meanwhile may be partly kept.
+//! - Pruned top-k skips whole documents without knowing their shapes up
+//!   front: GEMMs, bytes and FLOPs are the share of each length bucket
+//!   that was scored, rounded down.
+//! - f32 collections score through the `score` module in one piece, so
+//!   their collect time is part of the score time.
+//!
+//! `ScorerConfig::timing` adds a `Timing` to each call: wall time and run
+//! count per stage (conversion, GEMM, reduction, top-k heap, disk reads).
+//! Each worker times its share into a thread-local clock while it holds
+//! its scratch and adds it to the call's when done; with timing off a
+//! stage costs one thread-local read. Stage times are summed over the
+//! workers, so on several threads they add up to more than the call's
+//! wall time. Under calls running concurrently on one pool, a worker may
+//! run another call's stages while it holds this call's scratch and count
+//! them here. Fields are only ever added, so series charted across
+//! releases keep their meaning.
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::collection::{Documents, QueryEmbeddings};
+use crate::score::{gemm_calls, stack_group, Tiling, QUERY_BLOCK};
+
+/// Kernel family a bucket was scored with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum KernelKind {
+    /// bf16 JIT kernel.
+    Bf16,
+    /// f32 GEMM (JIT or sgemm), including decoded f16 and residual
+    /// documents and bf16 ones widened on fallback.
+    F32,
+    /// u8×i8 integer GEMM, or its plain loop.
+    Int8,
+}
+
+/// Shape of a scored bucket: `q_len` query tokens against documents of
+/// `d_len` tokens of `dim` values. Fused-path documents run it as
+/// several GEMMs, one per tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KernelShape {
+    pub kernel: KernelKind,
+    pub q_len: usize,
+    pub d_len: usize,
+    pub dim: usize,
+}
+
+/// What one or more `Scorer` calls did. See the module docs for the
+/// values that are approximate.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScorerStats {
+    pub calls: u64,
+    /// GEMM (or integer loop) runs, one per fused tile.
+    pub gemm_calls: u64,
+    /// Distinct shapes scored, sorted.
+    pub kernel_shapes: Vec<KernelShape>,
+    pub docs_scored: u64,
+    /// Documents top-k pruning ruled out by their norm bound.
+    pub docs_pruned: u64,
+    /// Query bytes plus each scored document's bytes as stored.
+    pub bytes_touched: u64,
+    /// Similarity GEMM operations, `2 · q_len · d_len · dim` per document.
+    pub flops: u64,
+    /// Checks, query preparation and length bucketing.
+    pub prepare_time: Duration,
+    /// The parallel scoring: GEMMs and reductions.
+    pub score_time: Duration,
+    /// Writing scores back in collection order.
+    pub collect_time: Duration,
+    /// Per-stage times, with `ScorerConfig::timing` on.
+    pub timing: Option<Timing>,
+}
+
+impl ScorerStats {
+    pub fn wall_time(&self) -> Duration {
+        self.prepare_time + self.score_time + self.collect_time
+    }
+
+    /// `flops` over `wall_time` in 10^9 per second; 0 without a time.
+    pub fn gflops(&self) -> f64 {
+        match self.wall_time().as_secs_f64() {
+            0.0 => 0.0,
+            secs => self.flops as f64 / secs / 1e9,
+        }
+    }
+}
+
+/// Wall time and runs of one stage, summed over a call's workers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StageTime {
+    pub time: Duration,
+    pub count: u64,
+}
+
+/// Where a scoring call spent its time. See the module docs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Timing {
+    /// Packing, widening, decoding and quantizing queries and documents.
+    pub convert: StageTime,
+    /// Similarity GEMMs, or the integer loop without libxsmm.
+    pub gemm: StageTime,
+    /// Per-token maxima and their aggregation.
+    pub reduce: StageTime,
+    /// Top-k pushes and merges.
+    pub heap: StageTime,
+    /// Document reads from disk.
+    pub io: StageTime,
+}
+
+impl Timing {
+    /// Time across the stages.
+    pub fn total(&self) -> Duration {
+        self.stages().iter().map(|stage| stage.time).sum()
+    }
+
+    fn stages(&self) -> [StageTime; 5] {
+        [self.convert, self.gemm, self.reduce, self.heap, self.io]
+    }
+
+    fn stage_mut(&mut self, phase: Phase) -> &mut StageTime {
+        match phase {
+            Phase::Convert => &mut self.convert,
+            Phase::Gemm => &mut self.gemm,
+            Phase::Reduce => &mut self.reduce,
+            Phase::Heap => &mut self.heap,
+            Phase::Io => &mut self.io,
+        }
+    }
+}
+
+/// A stage `Timing` breaks a call into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Phase {
+    Convert,
+    Gemm,
+    Reduce,
+    Heap,
+    Io,
+}
+
+const PHASES: [Phase; 5] = [
+    Phase::Convert,
+    Phase::Gemm,
+    Phase::Reduce,
+    Phase::Heap,
+    Phase::Io,
+];
+
+thread_local! {
+    /// This thread's share of the call being timed on it, if any.
+    static CLOCK: Cell<Option<Timing>> = const { Cell::new(None) };
+}
+
+/// `f`, counted under `phase` when this thread is timing a call.
+#[inline]
+pub(crate) fn timed<R>(phase: Phase, f: impl FnOnce() -> R) -> R {
+    if CLOCK.with(|clock| clock.get().is_none()) {
+        return f();
+    }
+    let start = Instant::now();
+    let out = f();
+    let elapsed = start.elapsed();
+    CLOCK.with(|clock| {
+        if let Some(mut timing) = clock.get() {
+            let stage = timing.stage_mut(phase);
+            stage.time += elapsed;
+            stage.count += 1;
+            clock.set(Some(timing));
+        }
+    });
+    out
+}
+
+/// A call's `Timing`, added to by its workers.
+#[derive(Debug, Default)]
+pub(crate) struct TimingSink {
+    nanos: [AtomicU64; 5],
+    counts: [AtomicU64; 5],
+}
+
+impl TimingSink {
+    fn add(&self, timing: &Timing) {
+        for (i, stage) in timing.stages().iter().enumerate() {
+            add(&self.nanos[i], stage.time.as_nanos() as usize);
+            add(&self.counts[i], stage.count as usize);
+        }
+    }
+
+    fn timing(&self) -> Timing {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut timing = Timing::default();
+        for (i, phase) in PHASES.into_iter().enumerate() {
+            *timing.stage_mut(phase) = StageTime {
+                time: Duration::from_nanos(load(&self.nanos[i])),
+                count: load(&self.counts[i]),
+            };
+        }
+        timing
+    }
+}
+
+/// Times the stages run on this thread into `sink` until dropped. Clocks
+/// nest: an inner one (a stolen share of the same call) keeps its time
+/// apart and hands the outer one back.
+pub(crate) struct Clock<'a> {
+    sink: &'a TimingSink,
+    outer: Option<Timing>,
+    /// The clock is this thread's.
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+impl<'a> Clock<'a> {
+    pub(crate) fn start(sink: &'a TimingSink) -> Self {
+        Self {
+            sink,
+            outer: CLOCK.with(|clock| clock.replace(Some(Timing::default()))),
+            _not_send: std::marker::PhantomData,
+        }
+    }
+}
+
+impl Drop for Clock<'_> {
+    fn drop(&mut self) {
+        if let Some(timing) = CLOCK.with(|clock| clock.replace(self.outer)) {
+            self.sink.add(&timing);
+        }
+    }
+}
+
+/// A stage of a scoring call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stage {
+    Prepare,
+    Score,
+    Collect,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    gemm_calls: AtomicU64,
+    docs_scored: AtomicU64,
+    docs_pruned: AtomicU64,
+    bytes_touched: AtomicU64,
+    flops: AtomicU64,
+}
+
+/// Counters of one call in flight, added to from its scoring threads.
+#[derive(Debug)]
+pub(crate) struct CallStats {
+    counts: Counters,
+    shapes: Mutex<Vec<KernelShape>>,
+    times: [Duration; 3],
+    stage_start: Instant,
+    /// Tiles the f32 documents are scored in.
+    tiling: Tiling,
+    /// Per-stage times, when collected.
+    timing: Option<Arc<TimingSink>>,
+}
+
+impl CallStats {
+    /// Start the clock on `Stage::Prepare`.
+    pub(crate) fn start() -> Self {
+        Self {
+            counts: Counters::default(),
+            shapes: Mutex::new(Vec::new()),
+            times: [Duration::ZERO; 3],
+            stage_start: Instant::now(),
+            tiling: Tiling::default(),
+            timing: None,
+        }
+    }
+
+    /// Collect per-stage times as well.
+    pub(crate) fn with_timing(mut self, timing: bool) -> Self {
+        self.timing = timing.then(Arc::default);
+        self
+    }
+
+    /// Where workers add their stage times; `None` when not collected.
+    pub(crate) fn timing(&self) -> Option<Arc<TimingSink>> {
+        self.timing.clone()
+    }
+
+    /// f32 documents are scored in `tiling`'s tiles.
+    pub(crate) fn with_tiling(mut self, tiling: Tiling) -> Self {
+        self.tiling = tiling;
+        self
+    }
+
+    /// Close `stage` and start timing the next.
+    pub(crate) fn end(&mut self, stage: Stage) {
+        let now = Instant::now();
+        self.times[stage as usize] += now - self.stage_start;
+        self.stage_start = now;
+    }
+
+    /// The query was read once.
+    pub(crate) fn query(&self, query: &QueryEmbeddings) {
+        let bytes = query.len() * query.dim() * size_of::<f32>();
+        add(&self.counts.bytes_touched, bytes);
+    }
+
+    /// A bucket of `docs` documents of `shape` was scored, each
+    /// `doc_bytes` bytes as stored. Empty shapes score without a GEMM.
+    pub(crate) fn bucket(&self, shape: KernelShape, docs: usize, doc_bytes: usize) {
+        add(&self.counts.docs_scored, docs);
+        self.work(shape, docs, doc_bytes, false, false);
+    }
+
+    /// `scored` of the f32 `docs` were scored against `q_len` query
+    /// tokens; the rest were pruned. Work per length is that share. With
+    /// `stacked`, lengths up to the stack threshold ran stacked GEMMs and
+    /// longer ones one per query block once the query spans several.
+    pub(crate) fn f32_documents<D: Documents + ?Sized>(
+        &self,
+        q_len: usize,
+        docs: &D,
+        scored: usize,
+        stacked: bool,
+    ) {
+        let mut lens = BTreeMap::new();
+        for i in 0..docs.len() {
+            *lens.entry(docs.doc_len(i)).or_insert(0usize) += 1;
+        }
+        let tokens: usize = lens.iter().map(|(d_len, n)| d_len * n).sum();
+        let blocked = stacked && self.tiling.loop_order(q_len, docs.len(), tokens).is_some();
+        let dim = docs.dim();
+        for (d_len, n) in lens {
+            let shape = KernelShape {
+                kernel: KernelKind::F32,
+                q_len,
+                d_len,
+                dim,
+            };
+            let n = (n as u128 * scored as u128 / docs.len() as u128) as usize;
+            self.work(shape, n, d_len * dim * size_of::<f32>(), stacked, blocked);
+        }
+        add(&self.counts.docs_scored, scored);
+        add(&self.counts.docs_pruned, docs.len() - scored);
+    }
+
+    fn work(
+        &self,
+        shape: KernelShape,
+        docs: usize,
+        doc_bytes: usize,
+        stacked: bool,
+        blocked: bool,
+    ) {
+        let KernelShape {
+            q_len, d_len, dim, ..
+        } = shape;
+        if docs == 0 || q_len == 0 || d_len == 0 || dim == 0 {
+            return;
+        }
+        let gemms = match shape.kernel {
+            KernelKind::F32 => {
+                let passes = dim.div_ceil(self.tiling.dims(dim));
+                let calls = if stacked && d_len <= self.tiling.stack_threshold() {
+                    docs.div_ceil(stack_group(d_len))
+                } else if blocked {
+                    let blocks = q_len.div_ceil(QUERY_BLOCK);
+                    docs * blocks * gemm_calls(d_len, self.tiling.tokens(dim))
+                } else {
+                    docs * gemm_calls(d_len, self.tiling.tokens(dim))
+                };
+                calls * passes
+            }
+            KernelKind::Bf16 | KernelKind::Int8 => docs,
+        };
+        add(&self.counts.gemm_calls, gemms);
+        add(&self.counts.bytes_touched, docs * doc_bytes);
+        add(&self.counts.flops, docs * 2 * q_len * d_len * dim);
+        self.shapes.lock().unwrap().push(shape);
+    }
+}
+
+fn add(counter: &AtomicU64, n: usize) {
+    counter.fetch_add(n as u64, Ordering::Relaxed);
+}
+
+/// A scorer's running totals and last call.
+#[derive(Debug, Default)]
+pub(crate) struct StatsRecorder {
+    calls: AtomicU64,
+    counts: Counters,
+    nanos: [AtomicU64; 3],
+    shapes: Mutex<BTreeSet<KernelShape>>,
+    /// Stage times of the calls that collected them.
+    timing: TimingSink,
+    timed_calls: AtomicU64,
+    last: Mutex<ScorerStats>,
+}
+
+impl StatsRecorder {
+    /// Add the finished `call` to the totals and make it the last, which
+    /// is returned.
+    pub(crate) fn record(&self, call: CallStats) -> ScorerStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let shapes: BTreeSet<KernelShape> = call.shapes.into_inner().unwrap().into_iter().collect();
+        let stats = ScorerStats {
+            calls: 1,
+            gemm_calls: load(&call.counts.gemm_calls),
+            kernel_shapes: shapes.iter().copied().collect(),
+            docs_scored: load(&call.counts.docs_scored),
+            docs_pruned: load(&call.counts.docs_pruned),
+            bytes_touched: load(&call.counts.bytes_touched),
+            flops: load(&call.counts.flops),
+            prepare_time: call.times[Stage::Prepare as usize],
+            score_time: call.times[Stage::Score as usize],
+            collect_time: call.times[Stage::Collect as usize],
+            timing: call.timing.as_deref().map(TimingSink::timing),
+        };
+
+        let add = |counter: &AtomicU64, n: u64| counter.fetch_add(n, Ordering::Relaxed);
+        add(&self.calls, 1);
+        add(&self.counts.gemm_calls, stats.gemm_calls);
+        add(&self.counts.docs_scored, stats.docs_scored);
+        add(&self.counts.docs_pruned, stats.docs_pruned);
+        add(&self.counts.bytes_touched, stats.bytes_touched);
+        add(&self.counts.flops, stats.flops);
+        for (total, time) in self.nanos.iter().zip(call.times) {
+            add(total, time.as_nanos() as u64);
+        }
+        if let Some(timing) = &stats.timing {
+            self.timing.add(timing);
+            add(&self.timed_calls, 1);
+        }
+        self.shapes.lock().unwrap().extend(shapes);
+        *self.last.lock().unwrap() = stats.clone();
+        stats
+    }
+
+    /// Totals so far.
+    pub(crate) fn totals(&self) -> ScorerStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let time = |stage: Stage| Duration::from_nanos(load(&self.nanos[stage as usize]));
+        ScorerStats {
+            calls: load(&self.calls),
+            gemm_calls: load(&self.counts.gemm_calls),
+            kernel_shapes: self.shapes.lock().unwrap().iter().copied().collect(),
+            docs_scored: load(&self.counts.docs_scored),
+            docs_pruned: load(&self.counts.docs_pruned),
+            bytes_touched: load(&self.counts.bytes_touched),
+            flops: load(&self.counts.flops),
+            prepare_time: time(Stage::Prepare),
+            score_time: time(Stage::Score),
+            collect_time: time(Stage::Collect),
+            timing: (load(&self.timed_calls) > 0).then(|| self.timing.timing()),
+        }
+    }
+
+    pub(crate) fn last(&self) -> ScorerStats {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// Zero the totals and the last call.
+    pub(crate) fn reset(&self) {
+        let counters = [
+            &self.calls,
+            &self.counts.gemm_calls,
+            &self.counts.docs_scored,
+            &self.counts.docs_pruned,
+            &self.counts.bytes_touched,
+            &self.counts.flops,
+            &self.timed_calls,
+        ];
+        let timing = self.timing.nanos.iter().chain(&self.timing.counts);
+        for counter in counters.into_iter().chain(&self.nanos).chain(timing) {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.shapes.lock().unwrap().clear();
+        *self.last.lock().unwrap() = ScorerStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{unit_docs, unit_rows};
+    use crate::scorer::{Scorer, ScorerConfig};
+
+    const DIM: usize = 32;
+
+    #[test]
+    fn timed_calls_report_their_stages_and_untimed_ones_nothing() {
+        let lengths: Vec<usize> = (0..40).map(|i| 1 + (i * 37) % 90).collect();
+        let docs = unit_docs(&lengths, DIM, 1);
+        let query = QueryEmbeddings::new(unit_rows(20, DIM, 2), 20, DIM).unwrap();
+        let config = ScorerConfig::default().with_num_threads(1);
+
+        let timed = Scorer::new(config.with_timing(true)).unwrap();
+        timed.score_batch(&query, &docs).unwrap();
+        let stats = timed.last_stats();
+        let timing = stats.timing.expect("timing on");
+        assert!(timing.gemm.count > 0 && timing.gemm.time > Duration::ZERO);
+        assert!(timing.reduce.count > 0 && timing.reduce.time > Duration::ZERO);
+        assert_eq!(timing.gemm.count, stats.gemm_calls);
+        assert_eq!((timing.heap, timing.io), Default::default());
+        // One thread: the stages are parts of the call
+        assert!(timing.total() <= stats.wall_time(), "{stats:?}");
+
+        // The same call again runs the same stages, and the totals add up
+        timed.score_batch(&query, &docs).unwrap();
+        let again = timed.last_stats().timing.unwrap();
+        for (a, b) in timing.stages().iter().zip(again.stages()) {
+            assert_eq!(a.count, b.count);
+        }
+        let totals = timed.stats().timing.unwrap();
+        for ((total, a), b) in totals
+            .stages()
+            .iter()
+            .zip(timing.stages())
+            .zip(again.stages())
+        {
+            assert_eq!(total.count, a.count + b.count);
+            assert_eq!(total.time, a.time + b.time);
+        }
+
+        let results = timed.search(7, &query, &docs, 5).unwrap();
+        let heap = results.timing().expect("timing on").heap;
+        assert!(heap.count > 0 && heap.time > Duration::ZERO);
+
+        let untimed = Scorer::new(config).unwrap();
+        untimed.score_batch(&query, &docs).unwrap();
+        assert_eq!(untimed.last_stats().timing, None);
+        assert_eq!(untimed.search(7, &query, &docs, 5).unwrap().timing, None);
+        assert_eq!(untimed.stats().timing, None);
+        assert_eq!(untimed.stats().calls, 2);
+    }
+}
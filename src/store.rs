@@ -0,0 +1,511 @@
+//! Memory-mapped on-disk document store.
+//!
+//! A store file is little-endian:
+//!
+//! ```text
+//! 0    magic     b"MAXSIMDS"
+//! 8    version   u32 (1)
+//! 12   dtype     u32 (0 = f32, 1 = bf16, 2 = f16, 3 = int8)
+//! 16   dim       u64
+//! 24   n_docs    u64
+//! 32   table     n_docs × (start: u64, tokens: u64)
+//! ...  data      each document's `[tokens, dim]` rows at its `start`
+//! ```
+//!
+//! Every document starts on a `STORE_ALIGN`-byte boundary (zero padding in
+//! between), and the mapping is page-aligned, so the rows handed to the
+//! GEMM are as aligned as a fresh allocation. Nothing is read until a
+//! document is touched; the OS pages data in and out as needed.
+//!
+//! f32 stores open as `MmapDocStore`, f16 stores (half the size, see
+//! `Storage`) as `MmapF16DocStore`. `crate::packed` and `crate::docstore`
+//! extend the same header.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::collection::{DocId, Documents};
+use crate::f16::convert_f32_to_f16;
+use crate::vnni::VnniLayout;
+
+/// Byte alignment of every document in a store file.
+pub const STORE_ALIGN: usize = 64;
+
+pub(crate) const MAGIC: &[u8; 8] = b"MAXSIMDS";
+const VERSION: u32 = 1;
+pub(crate) const DTYPE_F32: u32 = 0;
+/// dtype of stores holding bf16 bit patterns.
+pub(crate) const DTYPE_BF16: u32 = 1;
+pub(crate) const DTYPE_F16: u32 = 2;
+/// dtype of stores holding int8 rows with f32 scales.
+pub(crate) const DTYPE_I8: u32 = 3;
+/// Bytes before the document table.
+pub(crate) const HEADER_LEN: usize = 32;
+const ENTRY_LEN: usize = 16;
+
+/// Why a store could not be written or opened.
+#[derive(Debug)]
+pub enum StoreError {
+    Io(std::io::Error),
+    /// The file does not start with the store magic.
+    BadMagic,
+    UnsupportedVersion {
+        version: u32,
+    },
+    UnsupportedDtype {
+        dtype: u32,
+    },
+    /// The file holds another element type than the caller asked for.
+    DtypeMismatch {
+        stored: StoreDtype,
+        expected: StoreDtype,
+    },
+    /// A packed store's layout is not the one this CPU's bf16 kernels
+    /// expect, and the policy was `OnMismatch::Error`.
+    LayoutMismatch {
+        stored: VnniLayout,
+        cpu: VnniLayout,
+    },
+    /// The header or a document extent does not fit the file.
+    Corrupt {
+        reason: &'static str,
+    },
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "document store I/O: {}", err),
+            StoreError::BadMagic => write!(f, "not a document store file"),
+            StoreError::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported document store version {} (this build reads version {})",
+                version, VERSION
+            ),
+            StoreError::UnsupportedDtype { dtype } => {
+                write!(f, "unsupported document store dtype {}", dtype)
+            }
+            StoreError::DtypeMismatch { stored, expected } => write!(
+                f,
+                "document store holds {:?} embeddings, expected {:?}",
+                stored, expected
+            ),
+            StoreError::LayoutMismatch { stored, cpu } => write!(
+                f,
+                "document store is packed {:?}, this CPU's bf16 kernels expect {:?}",
+                stored, cpu
+            ),
+            StoreError::Corrupt { reason } => write!(f, "corrupt document store: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(err: std::io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+/// Element type of the embeddings in a store file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Storage {
+    #[default]
+    F32,
+    /// IEEE half, rounded to nearest-even at write time. Scored in f32,
+    /// widened tile by tile just before the GEMM.
+    F16,
+}
+
+/// Element type of a store file's embeddings, as recorded in its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StoreDtype {
+    F32,
+    Bf16,
+    F16,
+    /// int8 rows with f32 scales.
+    Int8,
+}
+
+impl StoreDtype {
+    pub(crate) fn id(self) -> u32 {
+        match self {
+            StoreDtype::F32 => DTYPE_F32,
+            StoreDtype::Bf16 => DTYPE_BF16,
+            StoreDtype::F16 => DTYPE_F16,
+            StoreDtype::Int8 => DTYPE_I8,
+        }
+    }
+
+    pub(crate) fn from_id(id: u32) -> Option<Self> {
+        match id {
+            DTYPE_F32 => Some(StoreDtype::F32),
+            DTYPE_BF16 => Some(StoreDtype::Bf16),
+            DTYPE_F16 => Some(StoreDtype::F16),
+            DTYPE_I8 => Some(StoreDtype::Int8),
+            _ => None,
+        }
+    }
+}
+
+/// Write `docs` to `path` in the store format, as f32.
+pub fn write_doc_store<D: Documents + ?Sized>(docs: &D, path: &Path) -> Result<(), StoreError> {
+    write_doc_store_as(docs, path, Storage::F32)
+}
+
+/// Write `docs` to `path` in the store format, converted to `storage`.
+pub fn write_doc_store_as<D: Documents + ?Sized>(
+    docs: &D,
+    path: &Path,
+    storage: Storage,
+) -> Result<(), StoreError> {
+    trace_span!(DEBUG, "maxsim.store_save", path = %path.display(), docs = docs.len());
+    let dim = docs.dim();
+    let (dtype, width) = match storage {
+        Storage::F32 => (DTYPE_F32, 4),
+        Storage::F16 => (DTYPE_F16, 2),
+    };
+    let mut out = BufWriter::new(File::create(path)?);
+    write_header(&mut out, MAGIC, dtype, dim, docs.len())?;
+    let lens: Vec<usize> = (0..docs.len()).map(|i| docs.doc_len(i)).collect();
+    let mut halves = Vec::new();
+    let write_doc = |out: &mut BufWriter<File>, i: DocId| match storage {
+        Storage::F32 => {
+            let mut values = docs.doc(i).iter();
+            values.try_for_each(|x| out.write_all(&x.to_le_bytes()))
+        }
+        Storage::F16 => {
+            halves.resize(docs.doc(i).len(), 0);
+            convert_f32_to_f16(docs.doc(i), &mut halves);
+            halves
+                .iter()
+                .try_for_each(|x| out.write_all(&x.to_le_bytes()))
+        }
+    };
+    let doc_bytes = |tokens| tokens * dim * width;
+    write_docs(&mut out, HEADER_LEN, &lens, doc_bytes, write_doc)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Magic, version, dtype, dim and document count: the first `HEADER_LEN`
+/// bytes of every store file.
+pub(crate) fn write_header(
+    out: &mut impl Write,
+    magic: &[u8; 8],
+    dtype: u32,
+    dim: usize,
+    n_docs: usize,
+) -> std::io::Result<()> {
+    out.write_all(magic)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&dtype.to_le_bytes())?;
+    out.write_all(&(dim as u64).to_le_bytes())?;
+    out.write_all(&(n_docs as u64).to_le_bytes())
+}
+
+/// Check the header written by `write_header`; returns (dim, n_docs).
+pub(crate) fn read_header(
+    bytes: &[u8],
+    magic: &[u8; 8],
+    dtype: u32,
+) -> Result<(usize, usize), StoreError> {
+    let (stored, dim, n_docs) = read_header_any(bytes, magic)?;
+    if stored != dtype {
+        return Err(dtype_error(stored, dtype));
+    }
+    Ok((dim, n_docs))
+}
+
+/// `DtypeMismatch` when both dtypes are known, else `UnsupportedDtype`.
+pub(crate) fn dtype_error(stored: u32, expected: u32) -> StoreError {
+    match (StoreDtype::from_id(stored), StoreDtype::from_id(expected)) {
+        (Some(stored), Some(expected)) => StoreError::DtypeMismatch { stored, expected },
+        _ => StoreError::UnsupportedDtype { dtype: stored },
+    }
+}
+
+/// `read_header` for any dtype; returns (dtype, dim, n_docs).
+pub(crate) fn read_header_any(
+    bytes: &[u8],
+    magic: &[u8; 8],
+) -> Result<(u32, usize, usize), StoreError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(StoreError::Corrupt {
+            reason: "file is shorter than the header",
+        });
+    }
+    if &bytes[..8] != magic {
+        return Err(StoreError::BadMagic);
+    }
+    let version = read_u32(bytes, 8);
+    if version != VERSION {
+        return Err(StoreError::UnsupportedVersion { version });
+    }
+    Ok((
+        read_u32(bytes, 12),
+        read_u64(bytes, 16) as usize,
+        read_u64(bytes, 24) as usize,
+    ))
+}
+
+/// Write the document table at `table_start` (the current position), then
+/// every document at the next `STORE_ALIGN` boundary. `doc_bytes` gives a
+/// document's size from its token count; `write_doc` writes document `i`.
+pub(crate) fn write_docs<W: Write>(
+    out: &mut W,
+    table_start: usize,
+    lens: &[usize],
+    doc_bytes: impl Fn(usize) -> usize,
+    mut write_doc: impl FnMut(&mut W, usize) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let table_end = table_start + lens.len() * ENTRY_LEN;
+    let mut pos = align_up(table_end);
+    let mut starts = Vec::with_capacity(lens.len());
+    for &tokens in lens {
+        out.write_all(&(pos as u64).to_le_bytes())?;
+        out.write_all(&(tokens as u64).to_le_bytes())?;
+        starts.push(pos);
+        pos = align_up(pos + doc_bytes(tokens));
+    }
+
+    let mut written = table_end;
+    for (i, (&start, &tokens)) in starts.iter().zip(lens).enumerate() {
+        out.write_all(&[0u8; STORE_ALIGN][..start - written])?;
+        write_doc(out, i)?;
+        written = start + doc_bytes(tokens);
+    }
+    Ok(())
+}
+
+/// Read and bounds-check the table written by `write_docs`: (byte start,
+/// token count) per document. `doc_bytes` is `None` on overflow.
+pub(crate) fn read_extents(
+    bytes: &[u8],
+    table_start: usize,
+    n_docs: usize,
+    doc_bytes: impl Fn(usize) -> Option<usize>,
+) -> Result<Vec<(usize, usize)>, StoreError> {
+    let table_end = n_docs
+        .checked_mul(ENTRY_LEN)
+        .and_then(|len| len.checked_add(table_start))
+        .filter(|&end| end <= bytes.len())
+        .ok_or(StoreError::Corrupt {
+            reason: "document table runs past the end of the file",
+        })?;
+
+    let mut extents = Vec::with_capacity(n_docs);
+    for at in (table_start..table_end).step_by(ENTRY_LEN) {
+        let start = read_u64(bytes, at) as usize;
+        let tokens = read_u64(bytes, at + 8) as usize;
+        if !start.is_multiple_of(STORE_ALIGN) {
+            return Err(StoreError::Corrupt {
+                reason: "document is not 64-byte aligned",
+            });
+        }
+        let end = doc_bytes(tokens).and_then(|len| len.checked_add(start));
+        if end.is_none_or(|end| start < table_end || end > bytes.len()) {
+            return Err(StoreError::Corrupt {
+                reason: "document lies outside the data section",
+            });
+        }
+        extents.push((start, tokens));
+    }
+    Ok(extents)
+}
+
+pub(crate) fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// A whole file mapped read-only and private; unmapped on drop.
+#[derive(Debug)]
+pub(crate) struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// The mapping is never written through and lives until drop
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    pub(crate) fn open(path: &Path) -> Result<Self, StoreError> {
+        trace_span!(DEBUG, "maxsim.mmap_open", path = %path.display());
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mmap rejects empty mappings
+            return Err(StoreError::Corrupt {
+                reason: "file is shorter than the header",
+            });
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    /// The mapped file. Page-aligned.
+    pub(crate) fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// A store file mapped read-only into memory, scored through `Documents`
+/// like an in-memory `DocBatch`.
+#[derive(Debug)]
+pub struct MmapDocStore {
+    map: Mmap,
+    dim: usize,
+    /// (byte start, token count) per document.
+    extents: Vec<(usize, usize)>,
+}
+
+impl MmapDocStore {
+    /// Map the store at `path` and validate its header and document table.
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let map = Mmap::open(path)?;
+        let (dim, n_docs) = read_header(map.bytes(), MAGIC, DTYPE_F32)?;
+        let doc_bytes = |tokens: usize| tokens.checked_mul(dim)?.checked_mul(4);
+        let extents = read_extents(map.bytes(), HEADER_LEN, n_docs, doc_bytes)?;
+        Ok(Self { map, dim, extents })
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.extents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extents.is_empty()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        self.extents[i].1
+    }
+
+    /// Embeddings of document `i`, `[doc_len(i), dim]`, read from the map.
+    pub fn doc(&self, i: DocId) -> &[f32] {
+        let (start, tokens) = self.extents[i];
+        // In bounds and 64-byte aligned, checked in `read_extents`
+        let ptr = self.map.bytes()[start..].as_ptr() as *const f32;
+        unsafe { std::slice::from_raw_parts(ptr, tokens * self.dim) }
+    }
+}
+
+impl Documents for MmapDocStore {
+    fn len(&self) -> usize {
+        MmapDocStore::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn doc_len(&self, i: DocId) -> usize {
+        MmapDocStore::doc_len(self, i)
+    }
+
+    fn doc(&self, i: DocId) -> &[f32] {
+        MmapDocStore::doc(self, i)
+    }
+}
+
+/// An f16 store file (`Storage::F16`) mapped read-only into memory.
+/// Scored with `Scorer::score_batch_f16`.
+#[derive(Debug)]
+pub struct MmapF16DocStore {
+    map: Mmap,
+    dim: usize,
+    /// (byte start, token count) per document.
+    extents: Vec<(usize, usize)>,
+}
+
+impl MmapF16DocStore {
+    /// Map the f16 store at `path` and validate its header and document
+    /// table.
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let map = Mmap::open(path)?;
+        let (dim, n_docs) = read_header(map.bytes(), MAGIC, DTYPE_F16)?;
+        let doc_bytes = |tokens: usize| tokens.checked_mul(dim)?.checked_mul(2);
+        let extents = read_extents(map.bytes(), HEADER_LEN, n_docs, doc_bytes)?;
+        Ok(Self { map, dim, extents })
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.extents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extents.is_empty()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Token count of document `i`.
+    pub fn doc_len(&self, i: DocId) -> usize {
+        self.extents[i].1
+    }
+
+    /// f16 embeddings of document `i`, `[doc_len(i), dim]`, read from the
+    /// map.
+    pub fn doc(&self, i: DocId) -> &[u16] {
+        let (start, tokens) = self.extents[i];
+        let ptr = self.map.bytes()[start..].as_ptr() as *const u16;
+        unsafe { std::slice::from_raw_parts(ptr, tokens * self.dim) }
+    }
+}
+
+fn align_up(pos: usize) -> usize {
+    pos.next_multiple_of(STORE_ALIGN)
+}
+
+/// A path in the temp dir unique to this test process and `name`.
+#[cfg(test)]
+pub(crate) fn scratch_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("maxsim-{}-{name}", std::process::id()))
+}
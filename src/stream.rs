@@ -0,0 +1,217 @@
+//! Top-k search over documents that arrive in chunks.
+//!
+//! Only the chunk being scored and the running top k are held in memory,
+//! so the collection can be far larger than RAM (e.g. read from disk one
+//! chunk at a time). Each chunk is scored in parallel like a `DocBatch`.
+
+use crate::collection::{DocBatch, DocId, QueryEmbeddings};
+use crate::score::{top_k_heap, Reduction, ScoreError};
+use crate::topk::TopK;
+
+/// Consecutive documents of a stream in `DocBatch`'s CSR layout: document
+/// `i` is `data[offsets[i]..offsets[i + 1]]`, offsets counting f32 values.
+#[derive(Clone, Debug)]
+pub struct DocChunk {
+    pub data: Vec<f32>,
+    pub offsets: Vec<usize>,
+    pub dim: usize,
+}
+
+impl DocChunk {
+    /// Number of documents the offsets describe.
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Running top k over a stream of `DocChunk`s.
+///
+/// Document ids are positions in the stream. A chunk that fails still
+/// advances them by its document count, so later ids do not shift.
+#[derive(Clone, Debug)]
+pub struct TopKStream<'q> {
+    query: &'q QueryEmbeddings,
+    top: TopK,
+    k: usize,
+    next_id: DocId,
+    chunks: usize,
+}
+
+impl<'q> TopKStream<'q> {
+    pub fn new(query: &'q QueryEmbeddings, k: usize) -> Self {
+        Self {
+            query,
+            top: TopK::new(k),
+            k,
+            next_id: 0,
+            chunks: 0,
+        }
+    }
+
+    /// Score `chunk` and fold it into the running top k. On error the
+    /// chunk is dropped and the results so far are kept.
+    pub fn push(&mut self, chunk: DocChunk) -> Result<(), ScoreError> {
+        let first_id = self.next_id;
+        self.next_id += chunk.len();
+        self.chunks += 1;
+
+        let docs = DocBatch::from_parts(chunk.data, chunk.offsets, chunk.dim)?;
+        let local = top_k_heap(self.query, &docs, self.k, Reduction::default())?;
+        for (i, score) in local.into_sorted_vec() {
+            self.top.push(first_id + i, score);
+        }
+        Ok(())
+    }
+
+    /// Best documents so far.
+    pub fn top_k(&self) -> &TopK {
+        &self.top
+    }
+
+    /// Documents seen so far, including those of failed chunks.
+    pub fn docs_seen(&self) -> usize {
+        self.next_id
+    }
+
+    /// Chunks pushed so far, including failed ones.
+    pub fn chunks_seen(&self) -> usize {
+        self.chunks
+    }
+
+    pub fn into_top_k(self) -> TopK {
+        self.top
+    }
+}
+
+/// A chunk of a stream failed; `top` holds the results of every chunk
+/// before it.
+#[derive(Clone, Debug)]
+pub struct StreamError {
+    /// Position of the failed chunk in the stream.
+    pub chunk: usize,
+    pub error: ScoreError,
+    pub top: TopK,
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chunk {}: {}", self.chunk, self.error)
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The `k` best documents of `chunks` for `query`, ids being positions in
+/// the stream.
+///
+/// Stops at the first chunk that fails. Pass `&mut iter` to resume after
+/// it, or drive a `TopKStream` directly to skip bad chunks.
+pub fn maxsim_score_stream<I: IntoIterator<Item = DocChunk>>(
+    query: &QueryEmbeddings,
+    chunks: I,
+    k: usize,
+) -> Result<TopK, StreamError> {
+    let mut stream = TopKStream::new(query, k);
+    for chunk in chunks {
+        if let Err(error) = stream.push(chunk) {
+            return Err(StreamError {
+                chunk: stream.chunks_seen() - 1,
+                error,
+                top: stream.into_top_k(),
+            });
+        }
+    }
+    Ok(stream.into_top_k())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{unit_docs, unit_rows};
+    use crate::collection::DocCollection;
+    use crate::score::maxsim_search;
+
+    const DIM: usize = 32;
+
+    /// `docs` in consecutive chunks of `sizes` documents.
+    fn chunks(docs: &DocCollection, sizes: &[usize]) -> Vec<DocChunk> {
+        let mut next = 0;
+        sizes
+            .iter()
+            .map(|&size| {
+                let mut data = Vec::new();
+                let mut offsets = vec![0];
+                for i in next..next + size {
+                    data.extend_from_slice(docs.doc(i));
+                    offsets.push(data.len());
+                }
+                next += size;
+                DocChunk {
+                    data,
+                    offsets,
+                    dim: DIM,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn streamed_top_k_equals_searching_the_concatenated_chunks() {
+        let lengths: Vec<usize> = (0..90).map(|i| 1 + (i * 37) % 120).collect();
+        let docs = unit_docs(&lengths, DIM, 81);
+        let sizes = [1, 30, 7, 40, 12];
+        assert_eq!(sizes.iter().sum::<usize>(), docs.len());
+        for q_len in [4, 40] {
+            let query = QueryEmbeddings::new(unit_rows(q_len, DIM, 82), q_len, DIM).unwrap();
+            for k in [1, 10, 200] {
+                let expected = maxsim_search(&query, &docs, k).unwrap();
+                let top = maxsim_score_stream(&query, chunks(&docs, &sizes), k).unwrap();
+                assert_eq!(
+                    top.into_hits(|i| i as u64),
+                    expected,
+                    "{q_len} tokens, k {k}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_failed_chunk_keeps_the_results_before_it() {
+        let lengths: Vec<usize> = (0..40).map(|i| 1 + (i * 13) % 50).collect();
+        let docs = unit_docs(&lengths, DIM, 83);
+        let query = QueryEmbeddings::new(unit_rows(8, DIM, 84), 8, DIM).unwrap();
+        let k = 5;
+        let mut stream = chunks(&docs, &[15, 10, 15]);
+        stream[1].dim = DIM / 2;
+
+        let err = maxsim_score_stream(&query, stream.clone(), k).unwrap_err();
+        assert_eq!(err.chunk, 1);
+        assert!(matches!(err.error, ScoreError::EmbeddingDim { .. }), "{err}");
+        let first = DocBatch::from_parts(stream[0].data.clone(), stream[0].offsets.clone(), DIM);
+        let expected = maxsim_search(&query, &first.unwrap(), k).unwrap();
+        assert_eq!(err.top.into_hits(|i| i as u64), expected);
+
+        // Skipping the bad chunk keeps the later ids where they were
+        let mut skipping = TopKStream::new(&query, k);
+        for chunk in stream {
+            let _ = skipping.push(chunk);
+        }
+        assert_eq!((skipping.docs_seen(), skipping.chunks_seen()), (40, 3));
+        let mut kept: Vec<(DocId, f32)> = maxsim_search(&query, &docs, docs.len())
+            .unwrap()
+            .into_iter()
+            .map(|hit| (hit.id as DocId, hit.score))
+            .filter(|&(i, _)| !(15..25).contains(&i))
+            .collect();
+        kept.truncate(k);
+        assert_eq!(skipping.into_top_k().into_sorted_vec(), kept);
+    }
+}
@@ -0,0 +1,250 @@
+//! Bounded top-k selection over (doc id, score) pairs.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::collection::DocId;
+use crate::stats::{timed, Phase, Timing};
+
+/// A scored document, ordered best first: higher score, then lower id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Ranked {
+    score: f32,
+    id: DocId,
+}
+
+impl Eq for Ranked {}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// One result of a top-k search: a document's external id, its score, and
+/// its rank (0 for the best hit).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchHit {
+    pub id: u64,
+    pub score: f32,
+    pub rank: u32,
+}
+
+/// The hits of one query, best first, under the id the caller gave it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchResults {
+    pub query_id: u64,
+    pub hits: Vec<SearchHit>,
+    /// Where the search spent its time, when it was collected.
+    pub timing: Option<Timing>,
+}
+
+impl SearchResults {
+    pub fn new(query_id: u64, hits: Vec<SearchHit>) -> Self {
+        Self {
+            query_id,
+            hits,
+            timing: None,
+        }
+    }
+
+    pub fn with_timing(mut self, timing: Option<Timing>) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Per-stage times of the search; see `ScorerConfig::timing`.
+    pub fn timing(&self) -> Option<&Timing> {
+        self.timing.as_ref()
+    }
+
+    /// One `SearchResults` per query of a `maxsim_top_k_batch` result, the
+    /// query ids being their positions in the batch.
+    pub fn from_batch(hits: Vec<Vec<SearchHit>>) -> Vec<Self> {
+        hits.into_iter()
+            .enumerate()
+            .map(|(i, hits)| Self::new(i as u64, hits))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+}
+
+/// The `k` best (id, score) pairs seen so far, kept in a k-sized min-heap.
+///
+/// Ties on score go to the lower id, so the result does not depend on the
+/// order pairs were pushed or merged in.
+#[derive(Clone, Debug)]
+pub struct TopK {
+    k: usize,
+    heap: BinaryHeap<Reverse<Ranked>>,
+}
+
+impl TopK {
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            heap: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    pub fn push(&mut self, id: DocId, score: f32) {
+        timed(Phase::Heap, || self.insert(Ranked { score, id }));
+    }
+
+    fn insert(&mut self, entry: Ranked) {
+        if self.heap.len() < self.k {
+            self.heap.push(Reverse(entry));
+        } else if self
+            .heap
+            .peek()
+            .is_some_and(|Reverse(worst)| entry > *worst)
+        {
+            self.heap.pop();
+            self.heap.push(Reverse(entry));
+        }
+    }
+
+    /// Fold another partial result in (e.g. from another worker).
+    pub fn merge(mut self, other: TopK) -> Self {
+        trace_span!(
+            TRACE,
+            "maxsim.top_k_merge",
+            left = self.len(),
+            right = other.len()
+        );
+        timed(Phase::Heap, || {
+            for Reverse(entry) in other.heap {
+                self.insert(entry);
+            }
+        });
+        self
+    }
+
+    /// Lowest score still in the set, once it holds `k` entries.
+    pub fn threshold(&self) -> Option<f32> {
+        (self.heap.len() == self.k)
+            .then(|| self.heap.peek().map(|Reverse(worst)| worst.score))
+            .flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// The set as `SearchHit`s, best first, with ids mapped through `id`.
+    pub fn into_hits(self, id: impl Fn(DocId) -> u64) -> Vec<SearchHit> {
+        self.into_sorted_vec()
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (i, score))| SearchHit {
+                id: id(i),
+                score,
+                rank: rank as u32,
+            })
+            .collect()
+    }
+
+    /// Results sorted by descending score (ascending id on ties).
+    pub fn into_sorted_vec(self) -> Vec<(DocId, f32)> {
+        // Ascending order of Reverse<Ranked> is best first
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(Ranked { score, id })| (id, score))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Full sort by descending score, ascending id on ties.
+    fn sorted(pairs: &[(DocId, f32)], k: usize) -> Vec<(DocId, f32)> {
+        let mut all = pairs.to_vec();
+        all.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        all.truncate(k);
+        all
+    }
+
+    /// Scores from a small set, so most of them tie.
+    fn pairs(n: usize) -> Vec<(DocId, f32)> {
+        (0..n).map(|i| (i, ((i * 7) % 5) as f32 - 1.5)).collect()
+    }
+
+    #[test]
+    fn equals_full_sort_with_ties_by_id() {
+        let pairs = pairs(50);
+        for k in [0, 1, 3, 10, 50, 80] {
+            let mut forward = TopK::new(k);
+            let mut backward = TopK::new(k);
+            for &(id, score) in &pairs {
+                forward.push(id, score);
+            }
+            for &(id, score) in pairs.iter().rev() {
+                backward.push(id, score);
+            }
+            assert_eq!(forward.into_sorted_vec(), sorted(&pairs, k), "k = {k}");
+            assert_eq!(backward.into_sorted_vec(), sorted(&pairs, k), "k = {k}");
+        }
+    }
+
+    #[test]
+    fn merged_partitions_equal_one_heap() {
+        let pairs = pairs(64);
+        let k = 9;
+        let merged = pairs
+            .chunks(10)
+            .map(|chunk| {
+                let mut top = TopK::new(k);
+                for &(id, score) in chunk {
+                    top.push(id, score);
+                }
+                top
+            })
+            .fold(TopK::new(k), TopK::merge);
+        assert_eq!(merged.threshold(), Some(sorted(&pairs, k)[k - 1].1));
+        let hits = merged.into_hits(|i| 1000 + i as u64);
+        for (rank, (hit, (id, score))) in hits.iter().zip(sorted(&pairs, k)).enumerate() {
+            assert_eq!(
+                *hit,
+                SearchHit {
+                    id: 1000 + id as u64,
+                    score,
+                    rank: rank as u32
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn threshold_waits_for_k_entries() {
+        let mut top = TopK::new(2);
+        top.push(0, 1.0);
+        assert_eq!(top.threshold(), None);
+        top.push(1, 3.0);
+        assert_eq!(top.threshold(), Some(1.0));
+        top.push(2, 2.0);
+        assert_eq!(top.threshold(), Some(2.0));
+        assert_eq!(top.len(), 2);
+    }
+}
@@ -0,0 +1,167 @@
+//! Optional `tracing` instrumentation (feature `tracing`).
+//!
+//! `trace_span!` enters a span until the end of the enclosing block and
+//! `trace_event!` emits an event, both at the given `tracing::Level`
+//! (`DEBUG` for per-call work, `TRACE` for per-document work). Without the
+//! feature both expand to nothing, field expressions included, so the
+//! instrumented paths cost nothing.
+
+/// `trace_span!(LEVEL, "name", field = value, ...)`.
+macro_rules! trace_span {
+    ($level:ident, $name:literal $(, $($field:tt)+)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!(tracing::Level::$level, $name $(, $($field)+)?).entered();
+    };
+}
+
+/// `trace_event!(LEVEL, field = value, ..., "message")`.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)+);
+    };
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::fmt::Debug;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::bench::{unit_docs, unit_rows};
+    use crate::collection::QueryEmbeddings;
+    use crate::docstore::{DocStore, DocStoreBuilder};
+    use crate::score::{maxsim_score_batch, maxsim_top_k, maxsim_top_k_pruned};
+    use crate::store::scratch_path;
+
+    /// A span (`name`) or event (`name` is its message) with its fields.
+    #[derive(Debug)]
+    struct Seen {
+        name: String,
+        fields: Vec<(String, String)>,
+    }
+
+    impl Seen {
+        fn field(&self, name: &str) -> Option<&str> {
+            let mut fields = self.fields.iter();
+            fields.find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+        }
+    }
+
+    impl Visit for Seen {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            let value = format!("{value:?}");
+            if field.name() == "message" {
+                self.name = value;
+            } else {
+                self.fields.push((field.name().to_string(), value));
+            }
+        }
+    }
+
+    /// Records the spans and events of the test's pool, whose thread names
+    /// start with `traced`; other tests run alongside.
+    struct Recorder;
+
+    static SEEN: Mutex<Vec<Seen>> = Mutex::new(Vec::new());
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    impl Recorder {
+        fn push(metadata: &Metadata<'_>, record: impl FnOnce(&mut Seen)) {
+            let thread = std::thread::current();
+            if !thread
+                .name()
+                .is_some_and(|name| name.starts_with("traced-"))
+            {
+                return;
+            }
+            let mut seen = Seen {
+                name: metadata.name().to_string(),
+                fields: Vec::new(),
+            };
+            record(&mut seen);
+            SEEN.lock().unwrap().push(seen);
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            Self::push(span.metadata(), |seen| span.record(seen));
+            Id::from_u64(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            Self::push(event.metadata(), |seen| event.record(seen));
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn search_and_store_spans_fire() {
+        tracing::subscriber::set_global_default(Recorder).unwrap();
+        const DIM: usize = 16;
+        let lengths = [3, 5, 5, 9, 2, 5, 40, 3];
+        let docs = unit_docs(&lengths, DIM, 1);
+        let query = QueryEmbeddings::new(unit_rows(4, DIM, 2), 4, DIM).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .thread_name(|i| format!("traced-{i}"))
+            .build()
+            .unwrap();
+        pool.install(|| {
+            maxsim_score_batch(&query, &docs).unwrap();
+            maxsim_top_k(&query, &docs, 3).unwrap();
+            maxsim_top_k_pruned(&query, &docs, 3).unwrap();
+
+            let mut builder = DocStoreBuilder::new(DIM);
+            builder.push(&unit_rows(3, DIM, 3)).unwrap();
+            let path = scratch_path("traced-store");
+            builder.finish().unwrap().save(&path).unwrap();
+            DocStore::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+        });
+
+        let seen = SEEN.lock().unwrap();
+        let fired = |name: &str, fields: &[(&str, &str)]| {
+            seen.iter()
+                .any(|s| s.name == name && fields.iter().all(|&(f, v)| s.field(f) == Some(v)))
+        };
+        let docs = lengths.len().to_string();
+        let buckets = "5";
+        for (name, fields) in [
+            (
+                "maxsim.score_batch",
+                &[("docs", &docs[..]), ("buckets", buckets)][..],
+            ),
+            ("maxsim.top_k", &[("docs", &docs), ("k", "3")]),
+            ("maxsim.top_k_pruned", &[("docs", &docs), ("q_len", "4")]),
+            ("maxsim.bucket", &[("d_len", "5"), ("docs", "3")]),
+            ("maxsim.bucket", &[("d_len", "40"), ("docs", "1")]),
+            ("maxsim.kernel_dispatch", &[("q_len", "4"), ("dim", "16")]),
+            ("maxsim.top_k_merge", &[]),
+            ("pruned top-k", &[]),
+            ("maxsim.store_save", &[("docs", "1")]),
+            ("maxsim.store_load", &[]),
+        ] {
+            assert!(fired(name, fields), "{name} {fields:?} did not fire");
+        }
+        let pruned = seen.iter().find(|s| s.name == "pruned top-k").unwrap();
+        let count = |field| pruned.field(field).unwrap().parse::<usize>().unwrap();
+        assert_eq!(count("scored") + count("skipped"), lengths.len());
+    }
+}
@@ -0,0 +1,333 @@
+//! VNNI2 packing for bf16 GEMM operands.
+//!
+//! BF16 dot-product instructions (VDPBF16PS, AMX TDPBF16PS) consume pairs of
+//! adjacent k-elements at once, so libxsmm's VNNI kernels expect the
+//! reduction dimension grouped in pairs: for each k-pair `p` and output index
+//! `x`, the two values `[2p, 2p + 1]` sit next to each other.
+//!
+//! bf16 values are raw `u16` bit patterns. Odd k is zero-padded to even, and
+//! zeros contribute nothing to the dot products.
+
+use crate::aligned::AlignedVec;
+
+/// Operand layout expected by a low-precision kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VnniLayout {
+    /// Plain column-major, no k-grouping.
+    Flat,
+    /// k grouped in pairs (bf16 dot products).
+    Vnni2,
+    /// k grouped in fours (int8 dot products).
+    Vnni4,
+}
+
+impl VnniLayout {
+    /// Number of adjacent k-elements per group.
+    pub fn group(self) -> usize {
+        match self {
+            VnniLayout::Flat => 1,
+            VnniLayout::Vnni2 => 2,
+            VnniLayout::Vnni4 => 4,
+        }
+    }
+}
+
+/// Round k up to the next multiple of the VNNI2 pair size.
+pub fn vnni2_k(k: usize) -> usize {
+    k.div_ceil(2) * 2
+}
+
+/// Pack a column-major m×k A operand (leading dimension `lda`) into VNNI2:
+/// `[k_pad / 2][m][2]`.
+pub fn pack_bf16_vnni2_a(src: &[u16], m: usize, k: usize, lda: usize) -> Vec<u16> {
+    assert!(lda >= m, "lda ({}) must be >= m ({})", lda, m);
+    let k_pad = vnni2_k(k);
+    let mut packed = vec![0u16; k_pad * m];
+    for kk in 0..k {
+        let (p, r) = (kk / 2, kk % 2);
+        let col = &src[kk * lda..kk * lda + m];
+        for (i, &v) in col.iter().enumerate() {
+            packed[(p * m + i) * 2 + r] = v;
+        }
+    }
+    packed
+}
+
+/// Pack a column-major k×n B operand (leading dimension `ldb`) into VNNI2:
+/// `[k_pad / 2][n][2]`, i.e. rows `2p` and `2p + 1` interleaved.
+pub fn pack_bf16_vnni2_b(src: &[u16], k: usize, n: usize, ldb: usize) -> Vec<u16> {
+    assert!(ldb >= k, "ldb ({}) must be >= k ({})", ldb, k);
+    let k_pad = vnni2_k(k);
+    let mut packed = vec![0u16; k_pad * n];
+    for j in 0..n {
+        let col = &src[j * ldb..j * ldb + k];
+        for (kk, &v) in col.iter().enumerate() {
+            let (p, r) = (kk / 2, kk % 2);
+            packed[(p * n + j) * 2 + r] = v;
+        }
+    }
+    packed
+}
+
+/// Pack a row-major m×k A operand (e.g. `[tokens, dim]` embeddings) into
+/// VNNI2 in `dst`, reusing its allocation. Same layout as
+/// `pack_bf16_vnni2_a`; row `i` just happens to hold the pairs contiguously.
+pub fn pack_bf16_vnni2_a_rows(src: &[u16], m: usize, k: usize, dst: &mut AlignedVec<u16>) {
+    assert_eq!(src.len(), m * k, "source must be m×k");
+    let k_pad = vnni2_k(k);
+    dst.clear();
+    dst.resize(k_pad * m, 0);
+    if k == 0 {
+        return;
+    }
+    for (i, row) in src.chunks_exact(k).enumerate() {
+        for (kk, &v) in row.iter().enumerate() {
+            let (p, r) = (kk / 2, kk % 2);
+            dst[(p * m + i) * 2 + r] = v;
+        }
+    }
+}
+
+/// A VNNI2-packed m×k A operand grown to `m_pad` rows in `dst`, the extra
+/// rows zero, as kernels blocked by whole row tiles read it.
+pub fn pad_bf16_vnni2_a_rows(
+    packed: &[u16],
+    m: usize,
+    m_pad: usize,
+    k: usize,
+    dst: &mut AlignedVec<u16>,
+) {
+    let k_pad = vnni2_k(k);
+    assert_eq!(packed.len(), k_pad * m, "packed operand must be k_pad×m");
+    assert!(m_pad >= m, "padded rows must cover the operand");
+    dst.clear();
+    dst.resize(k_pad * m_pad, 0);
+    if m == 0 {
+        return;
+    }
+    for (pair, padded) in packed
+        .chunks_exact(2 * m)
+        .zip(dst.chunks_exact_mut(2 * m_pad))
+    {
+        padded[..2 * m].copy_from_slice(pair);
+    }
+}
+
+/// Inverse of `pack_bf16_vnni2_a_rows`: the row-major m×k matrix in `dst`,
+/// reusing its allocation.
+pub fn unpack_bf16_vnni2_a_rows(packed: &[u16], m: usize, k: usize, dst: &mut AlignedVec<u16>) {
+    assert_eq!(
+        packed.len(),
+        vnni2_k(k) * m,
+        "packed operand must be k_pad×m"
+    );
+    dst.clear();
+    dst.resize(m * k, 0);
+    if k == 0 {
+        return;
+    }
+    for (i, row) in dst.chunks_exact_mut(k).enumerate() {
+        for (kk, v) in row.iter_mut().enumerate() {
+            let (p, r) = (kk / 2, kk % 2);
+            *v = packed[(p * m + i) * 2 + r];
+        }
+    }
+}
+
+/// Pad a row-major n×k B operand (column-major k×n, e.g. `[tokens, dim]`
+/// embeddings) to `vnni2_k(k)` values per row in `dst`, zero-filling the
+/// extra k, as the VNNI2-A bf16 kernels read B.
+pub fn pad_bf16_vnni2_b_rows(src: &[u16], n: usize, k: usize, dst: &mut AlignedVec<u16>) {
+    assert_eq!(src.len(), n * k, "source must be n×k");
+    let k_pad = vnni2_k(k);
+    dst.clear();
+    dst.resize(k_pad * n, 0);
+    if k == 0 {
+        return;
+    }
+    for (row, padded) in src.chunks_exact(k).zip(dst.chunks_exact_mut(k_pad)) {
+        padded[..k].copy_from_slice(row);
+    }
+}
+
+/// Inverse of `pad_bf16_vnni2_b_rows`: the row-major n×k matrix in `dst`,
+/// reusing its allocation.
+pub fn unpad_bf16_vnni2_b_rows(padded: &[u16], n: usize, k: usize, dst: &mut AlignedVec<u16>) {
+    let k_pad = vnni2_k(k);
+    assert_eq!(padded.len(), k_pad * n, "padded operand must be n×k_pad");
+    dst.clear();
+    if k == 0 {
+        return;
+    }
+    for row in padded.chunks_exact(k_pad) {
+        dst.extend_from_slice(&row[..k]);
+    }
+}
+
+/// Inverse of `pack_bf16_vnni2_a`: a tightly packed column-major m×k matrix.
+pub fn unpack_bf16_vnni2_a(packed: &[u16], m: usize, k: usize) -> Vec<u16> {
+    let mut out = vec![0u16; m * k];
+    for kk in 0..k {
+        let (p, r) = (kk / 2, kk % 2);
+        for i in 0..m {
+            out[kk * m + i] = packed[(p * m + i) * 2 + r];
+        }
+    }
+    out
+}
+
+/// Inverse of `pack_bf16_vnni2_b`: a tightly packed column-major k×n matrix.
+pub fn unpack_bf16_vnni2_b(packed: &[u16], k: usize, n: usize) -> Vec<u16> {
+    let mut out = vec![0u16; k * n];
+    for j in 0..n {
+        for kk in 0..k {
+            let (p, r) = (kk / 2, kk % 2);
+            out[j * k + kk] = packed[(p * n + j) * 2 + r];
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::unit_rows;
+    use crate::bf16::{bf16_to_f32, convert_f32_to_bf16};
+
+    fn to_bf16(src: &[f32]) -> Vec<u16> {
+        let mut dst = vec![0u16; src.len()];
+        convert_f32_to_bf16(src, &mut dst);
+        dst
+    }
+
+    /// Distinct nonzero bit patterns, so a misplaced element can't hide.
+    fn patterns(len: usize) -> Vec<u16> {
+        (1..=len as u16).collect()
+    }
+
+    #[test]
+    fn pack_unpack_round_trips_with_odd_k() {
+        let (m, k, n) = (5, 7, 3);
+        let (lda, ldb) = (m + 2, k + 1);
+        let a = patterns(lda * k);
+        let packed = pack_bf16_vnni2_a(&a, m, k, lda);
+        assert_eq!(packed.len(), vnni2_k(k) * m);
+        let tight: Vec<u16> = a
+            .chunks_exact(lda)
+            .flat_map(|col| &col[..m])
+            .copied()
+            .collect();
+        assert_eq!(unpack_bf16_vnni2_a(&packed, m, k), tight);
+        // The padded k row is the second half of the last pair
+        let last = (vnni2_k(k) / 2 - 1) * m;
+        assert!((0..m).all(|i| packed[(last + i) * 2 + 1] == 0));
+
+        let b = patterns(ldb * n);
+        let packed = pack_bf16_vnni2_b(&b, k, n, ldb);
+        let tight: Vec<u16> = b
+            .chunks_exact(ldb)
+            .flat_map(|col| &col[..k])
+            .copied()
+            .collect();
+        assert_eq!(unpack_bf16_vnni2_b(&packed, k, n), tight);
+    }
+
+    #[test]
+    fn row_major_helpers_round_trip() {
+        let (m, k, m_pad) = (6, 9, 8);
+        let rows = patterns(m * k);
+        let mut packed = AlignedVec::new();
+        pack_bf16_vnni2_a_rows(&rows, m, k, &mut packed);
+        // Row-major m×k is column-major k×m: the same layout as the
+        // column-major packer given the transpose
+        let col_major: Vec<u16> = (0..k)
+            .flat_map(|kk| (0..m).map(move |i| (i, kk)))
+            .map(|(i, kk)| rows[i * k + kk])
+            .collect();
+        assert_eq!(&packed[..], &pack_bf16_vnni2_a(&col_major, m, k, m)[..]);
+        let mut unpacked = AlignedVec::new();
+        unpack_bf16_vnni2_a_rows(&packed, m, k, &mut unpacked);
+        assert_eq!(&unpacked[..], &rows[..]);
+
+        let mut padded = AlignedVec::new();
+        pad_bf16_vnni2_a_rows(&packed, m, m_pad, k, &mut padded);
+        for (pair, padded) in packed
+            .chunks_exact(2 * m)
+            .zip(padded.chunks_exact(2 * m_pad))
+        {
+            assert_eq!(&padded[..2 * m], pair);
+            assert!(padded[2 * m..].iter().all(|&v| v == 0));
+        }
+
+        pad_bf16_vnni2_b_rows(&rows, m, k, &mut padded);
+        assert_eq!(padded.len(), m * vnni2_k(k));
+        unpad_bf16_vnni2_b_rows(&padded, m, k, &mut unpacked);
+        assert_eq!(&unpacked[..], &rows[..]);
+    }
+
+    #[test]
+    fn packed_pairs_compute_the_flat_product() {
+        let (m, n, k) = (4, 3, 11);
+        let a = to_bf16(&unit_rows(k, m, 21));
+        let b = to_bf16(&unit_rows(n, k, 22));
+        let (pa, pb) = (
+            pack_bf16_vnni2_a(&a, m, k, m),
+            pack_bf16_vnni2_b(&b, k, n, k),
+        );
+        for j in 0..n {
+            for i in 0..m {
+                let flat: f32 = (0..k)
+                    .map(|kk| bf16_to_f32(a[kk * m + i]) * bf16_to_f32(b[j * k + kk]))
+                    .sum();
+                // One VDPBF16PS-style step per pair
+                let paired: f32 = (0..vnni2_k(k) / 2)
+                    .map(|p| {
+                        (0..2)
+                            .map(|r| {
+                                bf16_to_f32(pa[(p * m + i) * 2 + r])
+                                    * bf16_to_f32(pb[(p * n + j) * 2 + r])
+                            })
+                            .sum::<f32>()
+                    })
+                    .sum();
+                assert!(
+                    (flat - paired).abs() <= 1e-6,
+                    "C[{i},{j}]: {flat} vs {paired}"
+                );
+            }
+        }
+    }
+
+    #[cfg(libxsmm)]
+    #[test]
+    fn vnni_kernel_matches_f32_reference() {
+        use crate::libxsmm_bindings::{DispatchError, JitKernel};
+
+        let (m, n, k) = (16, 5, 33);
+        let k_pad = vnni2_k(k);
+        let a = to_bf16(&unit_rows(k, m, 23));
+        let b_rows = to_bf16(&unit_rows(n, k, 24));
+        let kernel = match JitKernel::bf16_gemm_vnni(m as i32, n as i32, k_pad as i32, true, false)
+        {
+            Err(DispatchError::NotLoaded { .. } | DispatchError::UnsupportedArch { .. }) => return,
+            kernel => kernel.expect("libxsmm dispatches the shape"),
+        };
+        let packed = pack_bf16_vnni2_a(&a, m, k, m);
+        let mut b = AlignedVec::new();
+        pad_bf16_vnni2_b_rows(&b_rows, n, k, &mut b);
+        let mut c = vec![0.0f32; m * n];
+        kernel.call_bf16(&packed, &b, &mut c).unwrap();
+        for j in 0..n {
+            for i in 0..m {
+                let expected: f32 = (0..k)
+                    .map(|kk| bf16_to_f32(a[kk * m + i]) * bf16_to_f32(b_rows[j * k + kk]))
+                    .sum();
+                let actual = c[j * m + i];
+                assert!(
+                    (actual - expected).abs() <= 1e-4,
+                    "C[{i},{j}]: {actual} vs {expected}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+/* Exercises include/maxsim.h against the `capi` build of the library:
+ * open -> score -> top-k -> free on a small store, plus the error codes,
+ * `maxsim_last_error_message` and the null-pointer checks.
+ *
+ *   cargo build --profile capi --no-default-features --features capi
+ *   cc -std=c99 -Wall -Wextra -Iinclude tests/c/capi_test.c \
+ *      -Ltarget/capi -lmaxsim_cpu -o capi_test
+ *   LD_LIBRARY_PATH=target/capi ./capi_test /tmp/capi_test.store
+ *
+ * The store is written here in the `DocStore::save` format (see
+ * src/docstore.rs), so the test needs nothing but the library. Values are
+ * written in host byte order, which the format requires to be
+ * little-endian.
+ */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "maxsim.h"
+
+#define DIM 4
+#define N_DOCS 3
+#define ALIGN 64
+
+static int failures = 0;
+
+#define CHECK(cond)                                                        \
+    do {                                                                   \
+        if (!(cond)) {                                                     \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                    #cond);                                                \
+            failures++;                                                    \
+        }                                                                  \
+    } while (0)
+
+/* A failed call: status `code` and a non-empty last error message. */
+#define CHECK_FAILS(call, code)                                            \
+    do {                                                                   \
+        CHECK((call) == (code));                                           \
+        const char *message = maxsim_last_error_message();                 \
+        CHECK(message != NULL && message[0] != '\0');                      \
+    } while (0)
+
+static const size_t doc_tokens[N_DOCS] = {2, 1, 2};
+static const float doc_values[] = {
+    1.0f, 2.0f, 0.0f, 0.0f, 0.5f, 0.0f, 0.0f, 0.0f, /* doc 0 */
+    0.0f, 0.0f, 1.0f, 0.0f,                         /* doc 1 */
+    3.0f, 0.0f, 0.0f, 0.0f, 0.0f, -1.0f, 0.0f, 0.0f, /* doc 2 */
+};
+
+static size_t align_up(size_t n) { return (n + ALIGN - 1) / ALIGN * ALIGN; }
+
+static void put_u32(unsigned char *at, uint32_t v) { memcpy(at, &v, 4); }
+static void put_u64(unsigned char *at, uint64_t v) { memcpy(at, &v, 8); }
+
+/* An f32 dot-product store of the documents above. */
+static int write_store(const char *path) {
+    /* Length histogram, ascending: one document of 1 token, two of 2 */
+    const uint64_t buckets[2][2] = {{1, 1}, {2, 2}};
+    /* Rows of bucket padding per document, all zero, then the doc table */
+    size_t padding = 64 + sizeof buckets;
+    size_t table = padding + N_DOCS * 8;
+    size_t pos = align_up(table + N_DOCS * 16);
+    unsigned char file[1024] = {0};
+
+    memcpy(file, "MAXSIMST", 8);
+    put_u32(file + 8, 1);   /* version */
+    put_u32(file + 12, 0);  /* dtype f32 */
+    put_u64(file + 16, DIM);
+    put_u64(file + 24, N_DOCS);
+    /* similarity, layout, arch and scales stay 0: dot, flat, f32 */
+    put_u64(file + 48, DIM); /* logical dim */
+    put_u64(file + 56, 2);   /* buckets */
+    memcpy(file + 64, buckets, sizeof buckets);
+
+    const float *values = doc_values;
+    for (size_t i = 0; i < N_DOCS; i++) {
+        size_t bytes = doc_tokens[i] * DIM * sizeof(float);
+        put_u64(file + padding + i * 8, 0);
+        put_u64(file + table + i * 16, pos);
+        put_u64(file + table + i * 16 + 8, doc_tokens[i]);
+        memcpy(file + pos, values, bytes);
+        values += doc_tokens[i] * DIM;
+        pos = align_up(pos + bytes);
+    }
+
+    FILE *out = fopen(path, "wb");
+    if (out == NULL) {
+        return 0;
+    }
+    int ok = fwrite(file, 1, pos, out) == pos;
+    return fclose(out) == 0 && ok;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <scratch store path>\n", argv[0]);
+        return 2;
+    }
+    const char *path = argv[1];
+    const float query[2 * DIM] = {1, 0, 0, 0, 0, 1, 0, 0};
+    float scores[N_DOCS + 1];
+    uint64_t ids[N_DOCS];
+    size_t n_out = 0;
+    maxsim_store *store = NULL;
+
+    CHECK(maxsim_last_error_message() == NULL);
+
+    /* Opening: null arguments, a missing file, a file that is no store */
+    CHECK_FAILS(maxsim_store_open(NULL, &store), MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_store_open(path, NULL), MAXSIM_ERR_INVALID_ARGUMENT);
+    remove(path);
+    CHECK_FAILS(maxsim_store_open(path, &store), MAXSIM_ERR_STORE);
+    FILE *junk = fopen(path, "wb");
+    CHECK(junk != NULL && fputs("not a store", junk) >= 0 && fclose(junk) == 0);
+    CHECK_FAILS(maxsim_store_open(path, &store), MAXSIM_ERR_STORE);
+    CHECK(store == NULL);
+
+    CHECK(write_store(path));
+    CHECK(maxsim_store_open(path, &store) == MAXSIM_OK);
+    remove(path);
+    if (store == NULL) {
+        fprintf(stderr, "could not open the store: %s\n", maxsim_last_error_message());
+        return 1;
+    }
+    CHECK(maxsim_store_len(store) == N_DOCS);
+    CHECK(maxsim_store_dim(store) == DIM);
+    CHECK(maxsim_store_len(NULL) == 0);
+    CHECK(maxsim_store_dim(NULL) == 0);
+
+    /* Scores: doc 0 = 1 + 2, doc 1 = 0 + 0, doc 2 = 3 + 0 */
+    scores[N_DOCS] = -42.0f;
+    CHECK(maxsim_score_batch(store, query, 2, DIM, scores, N_DOCS + 1) == MAXSIM_OK);
+    CHECK(scores[0] == 3.0f && scores[1] == 0.0f && scores[2] == 3.0f);
+    CHECK(scores[N_DOCS] == -42.0f);
+    CHECK_FAILS(maxsim_score_batch(NULL, query, 2, DIM, scores, N_DOCS),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_score_batch(store, NULL, 2, DIM, scores, N_DOCS),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_score_batch(store, query, 2, DIM, NULL, N_DOCS),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_score_batch(store, query, 2, DIM, scores, N_DOCS - 1),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_score_batch(store, query, SIZE_MAX, 2, scores, N_DOCS),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_score_batch(store, query, 2, DIM - 1, scores, N_DOCS),
+                MAXSIM_ERR_SCORE);
+
+    /* Top-k: the tie at 3 goes to the lower position */
+    CHECK(maxsim_top_k(store, query, 2, DIM, 2, ids, scores, &n_out) == MAXSIM_OK);
+    CHECK(n_out == 2);
+    CHECK(ids[0] == 0 && ids[1] == 2);
+    CHECK(scores[0] == 3.0f && scores[1] == 3.0f);
+    CHECK(maxsim_top_k(store, query, 2, DIM, 10, ids, scores, &n_out) == MAXSIM_OK);
+    CHECK(n_out == N_DOCS && ids[2] == 1 && scores[2] == 0.0f);
+    CHECK(maxsim_top_k(store, query, 2, DIM, 0, ids, scores, &n_out) == MAXSIM_OK);
+    CHECK(n_out == 0);
+    CHECK_FAILS(maxsim_top_k(NULL, query, 2, DIM, 2, ids, scores, &n_out),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_top_k(store, NULL, 2, DIM, 2, ids, scores, &n_out),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_top_k(store, query, 2, DIM, 2, NULL, scores, &n_out),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_top_k(store, query, 2, DIM, 2, ids, NULL, &n_out),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_top_k(store, query, 2, DIM, 2, ids, scores, NULL),
+                MAXSIM_ERR_INVALID_ARGUMENT);
+    CHECK_FAILS(maxsim_top_k(store, query, 2, DIM + 1, 2, ids, scores, &n_out),
+                MAXSIM_ERR_SCORE);
+
+    /* A success leaves the last failure's message in place */
+    const char *last = maxsim_last_error_message();
+    CHECK(maxsim_store_len(store) == N_DOCS);
+    CHECK(maxsim_score_batch(store, query, 2, DIM, scores, N_DOCS) == MAXSIM_OK);
+    CHECK(maxsim_last_error_message() == last);
+
+    maxsim_store_free(store);
+    maxsim_store_free(NULL);
+
+    if (failures != 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("capi_test: all checks passed\n");
+    return 0;
+}
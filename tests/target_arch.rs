@@ -0,0 +1,36 @@
+//! Forcing libxsmm's target arch. The override is process-wide and changes
+//! what every later dispatch (and `KernelCache`) sees, so it runs in its own
+//! test binary rather than next to the unit tests scoring in parallel.
+#![cfg(libxsmm)]
+
+use maxsim_cpu::kernel_cache::KernelCache;
+use maxsim_cpu::libxsmm_bindings::{
+    reset_target_arch, set_target_arch, set_target_arch_name, CpuArch, JitKernel, Transpose,
+};
+use maxsim_cpu::libxsmm_link::loaded;
+
+#[test]
+fn forced_avx2_refuses_bf16_until_reset() {
+    let (m, n, k) = (16, 8, 32);
+    let forced = set_target_arch(CpuArch::Avx2);
+    assert!(!forced.supports_bf16_dot());
+    if loaded() {
+        assert_eq!(CpuArch::detect(), forced);
+    }
+    let result = JitKernel::bf16_gemm(m, n, k, Transpose::A);
+    let restored = reset_target_arch();
+    KernelCache::global().clear();
+    assert!(result.is_err(), "bf16 dispatched on {forced}");
+    if restored.supports_bf16_dot() {
+        let kernel = JitKernel::bf16_gemm(m, n, k, Transpose::A).unwrap();
+        assert_eq!(kernel.arch(), restored);
+    }
+
+    assert!(set_target_arch_name("hsw\0skx").is_err());
+    let named = set_target_arch_name("hsw").unwrap();
+    reset_target_arch();
+    KernelCache::global().clear();
+    if loaded() {
+        assert_eq!(named, forced);
+    }
+}